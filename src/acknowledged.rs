@@ -0,0 +1,282 @@
+//! Duplicate groups acknowledged as intentional, such as template copies, which are left
+//! out of every later report. Managed with the `acknowledge` and `unacknowledge` commands
+//! and kept in a JSON file that can also be edited by hand.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::FileInfo;
+use crate::content_hash::{ContentHash, GroupId};
+use crate::units::Count;
+
+/// Content acknowledged wherever its copies are.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AcknowledgedHash {
+    pub hash: ContentHash,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// Files acknowledged as copies of each other, whatever their content.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AcknowledgedPaths {
+    #[serde(with = "crate::paths::serde_path::vec")]
+    pub paths: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// What the `acknowledge` and `unacknowledge` commands are given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AcknowledgeTarget {
+    /// A group's hash.
+    Hash(ContentHash),
+    /// Absolute paths of files.
+    Paths(Vec<PathBuf>),
+}
+
+impl AcknowledgeTarget {
+    /// Reads the targets given on the command line: a hash for a single target that is a
+    /// hash and not a file, otherwise paths, or `None` for no targets. With `must_exist`,
+    /// every path must be an existing file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a path must exist but can't be read.
+    pub fn parse(targets: &[PathBuf], must_exist: bool) -> Result<Option<Self>> {
+        if let [target] = targets
+            && !target.exists()
+            && let Ok(hash) = target.to_string_lossy().parse()
+        {
+            return Ok(Some(Self::Hash(hash)));
+        }
+        if targets.is_empty() {
+            return Ok(None);
+        }
+        let paths = targets
+            .iter()
+            .map(|target| match target.canonicalize() {
+                Ok(path) => Ok(path),
+                Err(_) if !must_exist => Ok(std::path::absolute(target)?),
+                Err(e) => Err(e).with_context(|| format!("Failed to read {}", target.display())),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Some(Self::Paths(paths)))
+    }
+}
+
+/// On-disk form of [`Acknowledged`].
+#[derive(Default, Serialize, Deserialize)]
+struct Stored {
+    #[serde(default)]
+    hashes: Vec<AcknowledgedHash>,
+    #[serde(default)]
+    paths: Vec<AcknowledgedPaths>,
+}
+
+/// The acknowledged duplicate groups. A group is left out of reports, and so never acted
+/// on, if its hash is acknowledged or all of its files are in one acknowledged set of
+/// paths. Paths are absolute, as scanned.
+pub struct Acknowledged {
+    file: PathBuf,
+    hashes: Vec<AcknowledgedHash>,
+    paths: Vec<AcknowledgedPaths>,
+}
+
+impl Acknowledged {
+    /// Returns the default file: `acknowledged.json` in the platform data directory (e.g.
+    /// `~/.local/share/check-file-dups`), falling back to the current directory.
+    pub fn default_file() -> PathBuf {
+        dirs::data_local_dir()
+            .map(|dir| dir.join(env!("CARGO_PKG_NAME")))
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("acknowledged.json")
+    }
+
+    /// Loads the acknowledged groups from `file`, or starts with none if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or parsed.
+    pub fn load(file: PathBuf) -> Result<Self> {
+        let stored = match fs::read(&file) {
+            Ok(json) => serde_json::from_slice(&json)
+                .with_context(|| format!("Failed to parse {}", file.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Stored::default(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", file.display()));
+            }
+        };
+        Ok(Self {
+            file,
+            hashes: stored.hashes,
+            paths: stored.paths,
+        })
+    }
+
+    /// Writes the acknowledged groups back to their file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let stored = Stored {
+            hashes: self.hashes.clone(),
+            paths: self.paths.clone(),
+        };
+        fs::write(&self.file, serde_json::to_vec_pretty(&stored)?)
+            .with_context(|| format!("Failed to write {}", self.file.display()))
+    }
+
+    /// Acknowledges the group with `hash`, returning `false` if it already was.
+    pub fn acknowledge_hash(&mut self, hash: ContentHash, note: Option<String>) -> bool {
+        if self.hashes.iter().any(|entry| entry.hash == hash) {
+            return false;
+        }
+        self.hashes.push(AcknowledgedHash { hash, note });
+        true
+    }
+
+    /// Acknowledges `paths` as copies of each other, returning `false` if they already
+    /// were.
+    pub fn acknowledge_paths(&mut self, mut paths: Vec<PathBuf>, note: Option<String>) -> bool {
+        paths.sort();
+        paths.dedup();
+        if self.covers(&paths) {
+            return false;
+        }
+        self.paths.push(AcknowledgedPaths { paths, note });
+        true
+    }
+
+    /// Removes the acknowledgement of `hash`, returning `false` if there was none.
+    pub fn unacknowledge_hash(&mut self, hash: &ContentHash) -> bool {
+        let count = self.hashes.len();
+        self.hashes.retain(|entry| entry.hash != *hash);
+        self.hashes.len() < count
+    }
+
+    /// Removes every acknowledged set of paths that includes one of `paths`, returning the
+    /// number removed.
+    pub fn unacknowledge_paths(&mut self, paths: &[PathBuf]) -> usize {
+        let count = self.paths.len();
+        self.paths
+            .retain(|entry| !entry.paths.iter().any(|path| paths.contains(path)));
+        count - self.paths.len()
+    }
+
+    /// Returns `true` if one acknowledged set of paths holds all of `paths`.
+    fn covers(&self, paths: &[PathBuf]) -> bool {
+        self.paths
+            .iter()
+            .any(|entry| paths.iter().all(|path| entry.paths.contains(path)))
+    }
+
+    /// Returns `true` if the group of `files` with `hash` is acknowledged.
+    pub fn is_acknowledged(&self, hash: &ContentHash, files: &[FileInfo]) -> bool {
+        if self.hashes.iter().any(|entry| entry.hash == *hash) {
+            return true;
+        }
+        if self.paths.is_empty() {
+            return false;
+        }
+        let paths: Vec<PathBuf> = files.iter().map(|file| file.path.to_path_buf()).collect();
+        self.covers(&paths)
+    }
+
+    /// Drops the acknowledged groups from `duplicates`, and those picked by `ignored`
+    /// (from `--ignore-group`).
+    pub fn retain_unacknowledged(
+        &self,
+        duplicates: &mut HashMap<ContentHash, Vec<FileInfo>>,
+        ignored: &[GroupId],
+    ) {
+        let count = duplicates.len();
+        duplicates.retain(|hash, group| {
+            !ignored.iter().any(|id| id.matches(hash)) && !self.is_acknowledged(hash, group)
+        });
+        if duplicates.len() < count {
+            info!(
+                "Ignoring {} acknowledged duplicate groups",
+                Count((count - duplicates.len()) as u64)
+            );
+        }
+    }
+
+    /// Prints the acknowledged groups.
+    pub fn print(&self) {
+        if self.hashes.is_empty() && self.paths.is_empty() {
+            println!(
+                "No acknowledged duplicate groups in {}",
+                self.file.display()
+            );
+            return;
+        }
+        let note = |note: &Option<String>| {
+            note.as_ref()
+                .map(|note| format!("  # {}", note))
+                .unwrap_or_default()
+        };
+        for entry in &self.hashes {
+            println!("{}{}", entry.hash, note(&entry.note));
+        }
+        for entry in &self.paths {
+            let paths: Vec<String> = entry
+                .paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            println!("{}{}", paths.join(" = "), note(&entry.note));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_out_acknowledged_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("acknowledged.json");
+        let mut acknowledged = Acknowledged::load(file_path.clone()).unwrap();
+        assert!(acknowledged.acknowledge_hash(ContentHash::of(b"template"), None));
+        assert!(acknowledged.acknowledge_paths(
+            vec!["/b/2".into(), "/a/1".into()],
+            Some("kept on purpose".into())
+        ));
+        assert!(!acknowledged.acknowledge_paths(vec!["/a/1".into(), "/b/2".into()], None));
+        acknowledged.save().unwrap();
+        let acknowledged = Acknowledged::load(file_path).unwrap();
+
+        let group = |content: &[u8], paths: &[&str]| {
+            let files: Vec<FileInfo> = paths
+                .iter()
+                .map(|path| FileInfo::with_contents(path, content))
+                .collect();
+            (ContentHash::of(content), files)
+        };
+        let mut duplicates = HashMap::from([
+            group(b"template", &["/x", "/y"]),
+            group(b"pair", &["/a/1", "/b/2"]),
+            group(b"third copy", &["/a/1", "/b/2", "/c/3"]),
+            group(b"ignored", &["/i", "/j"]),
+            group(b"other", &["/o", "/p"]),
+        ]);
+        acknowledged.retain_unacknowledged(&mut duplicates, &[ContentHash::of(b"ignored").into()]);
+
+        let mut kept: Vec<_> = duplicates.keys().copied().collect();
+        kept.sort();
+        let mut expected = [ContentHash::of(b"third copy"), ContentHash::of(b"other")];
+        expected.sort();
+        assert_eq!(kept, expected);
+    }
+}
@@ -0,0 +1,970 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+use crate::FileInfo;
+use crate::attributes::{FileAttributes, MetadataPolicy};
+use crate::content_hash::ContentHash;
+use crate::duplicates::distinct_copies;
+use crate::reflink;
+use crate::scanner::{file_id, hash_file};
+use crate::selection::KeepPolicy;
+use crate::trash;
+use crate::units::{Bytes, Count};
+
+/// Version of the journal format written by this build.
+pub const JOURNAL_VERSION: u32 = 1;
+
+/// What to do with the redundant members of each duplicate group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Delete duplicates, keeping one copy.
+    Delete,
+    /// Replace duplicates with hardlinks to the kept copy.
+    Hardlink,
+    /// Replace duplicates with reflinks of the kept copy, which share its data on disk
+    /// but stay independent files.
+    Reflink,
+    /// Move duplicates into a quarantine directory, preserving their relative paths.
+    MoveTo(PathBuf),
+}
+
+/// A single filesystem change made by an action.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Operation {
+    /// A duplicate was moved from `from` to `to`.
+    Move {
+        #[serde(with = "crate::paths::serde_path")]
+        from: PathBuf,
+        #[serde(with = "crate::paths::serde_path")]
+        to: PathBuf,
+        hash: String,
+    },
+    /// A duplicate at `path` was deleted; `kept` is the identical copy that was kept.
+    Delete {
+        #[serde(with = "crate::paths::serde_path")]
+        path: PathBuf,
+        #[serde(with = "crate::paths::serde_path")]
+        kept: PathBuf,
+        hash: String,
+        /// Where the file is in the trash, if it was moved there with `--use-trash` and
+        /// the location is known.
+        #[serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            with = "crate::paths::serde_path::option"
+        )]
+        trashed: Option<PathBuf>,
+        /// Metadata of the deleted file, to restore with it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        attributes: Option<FileAttributes>,
+    },
+    /// A duplicate at `path` was replaced by a hardlink to `target`.
+    Hardlink {
+        #[serde(with = "crate::paths::serde_path")]
+        path: PathBuf,
+        #[serde(with = "crate::paths::serde_path")]
+        target: PathBuf,
+        hash: String,
+        /// Metadata of the replaced file, which the hardlink shares with `target`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        attributes: Option<FileAttributes>,
+    },
+    /// A duplicate at `path` was replaced by a reflink of `target`.
+    Reflink {
+        #[serde(with = "crate::paths::serde_path")]
+        path: PathBuf,
+        #[serde(with = "crate::paths::serde_path")]
+        target: PathBuf,
+        hash: String,
+        /// Metadata of the replaced file.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        attributes: Option<FileAttributes>,
+    },
+}
+
+impl Operation {
+    /// Returns the path of the duplicate the operation acts on.
+    pub fn path(&self) -> &Path {
+        match self {
+            Operation::Move { from, .. } => from,
+            Operation::Delete { path, .. }
+            | Operation::Hardlink { path, .. }
+            | Operation::Reflink { path, .. } => path,
+        }
+    }
+
+    /// Returns the hash of the duplicate group the operation belongs to.
+    pub fn hash(&self) -> &str {
+        match self {
+            Operation::Move { hash, .. }
+            | Operation::Delete { hash, .. }
+            | Operation::Hardlink { hash, .. }
+            | Operation::Reflink { hash, .. } => hash,
+        }
+    }
+}
+
+/// How [`apply_action`] carries out an action.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ActionOptions {
+    /// Hash every member of a group again right before acting on it, rather than only
+    /// comparing its size and modification time with the scan.
+    pub verify_hash: bool,
+    /// Move files to the trash rather than deleting them.
+    pub use_trash: bool,
+    /// What to do about the metadata of the files deleted or replaced.
+    pub metadata: MetadataPolicy,
+}
+
+/// Permission to change the files found by scans, required by everything that deletes,
+/// moves or replaces them: [`apply_action`], [`apply_planned`], [`undo`],
+/// [`crate::manifest::apply_manifest`], [`crate::merge_dirs::apply_merge`] and
+/// [`crate::trash`]. It can't be copied or made any other way than with
+/// [`ActionPermission::allow_changes`], so code that wasn't handed one can only read.
+#[derive(Debug)]
+pub struct ActionPermission(());
+
+impl ActionPermission {
+    /// Allows changing files. Callers should only do so when changes were asked for.
+    pub fn allow_changes() -> Self {
+        Self(())
+    }
+}
+
+/// Outcome of [`undo`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UndoStats {
+    /// Operations that were reverted.
+    pub restored: usize,
+    /// Operations that could not be reverted.
+    pub failed: usize,
+}
+
+/// Record of the changes made by a single run, written as JSON so that they can be
+/// reviewed and undone later.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Journal {
+    /// Journal format version.
+    pub version: u32,
+    /// RFC 3339 timestamp of when the run started.
+    pub created_at: String,
+    /// Operations in the order they were performed.
+    pub operations: Vec<Operation>,
+}
+
+impl Journal {
+    /// Creates an empty journal stamped with the current time.
+    pub fn new() -> Self {
+        Self {
+            version: JOURNAL_VERSION,
+            created_at: OffsetDateTime::now_local()
+                .unwrap_or_else(|_| OffsetDateTime::now_utc())
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            operations: Vec::new(),
+        }
+    }
+
+    /// Writes the journal to `path` as pretty-printed JSON. It is written to a temporary
+    /// file renamed over `path`, so a crash while saving leaves the previous journal
+    /// rather than a truncated one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if serialization fails or the file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_vec_pretty(self)?;
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_file = path.with_file_name(tmp_name);
+        fs::File::create(&tmp_file)
+            .and_then(|mut file| {
+                file.write_all(&content)?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&tmp_file, path))
+            .with_context(|| format!("Failed to write journal to {}", path.display()))
+    }
+
+    /// Loads a journal previously written by [`Journal::save`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is not a valid journal.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read(path)
+            .with_context(|| format!("Failed to read journal from {}", path.display()))?;
+        serde_json::from_slice(&content)
+            .with_context(|| format!("Failed to parse journal {}", path.display()))
+    }
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the path of `path` relative to `root`, or its path without the root/prefix
+/// components if it lies outside `root`, so it can be recreated under another directory.
+fn relative_to(path: &Path, root: &Path) -> PathBuf {
+    if let Ok(relative) = path.strip_prefix(root) {
+        return relative.to_path_buf();
+    }
+    path.components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect()
+}
+
+/// Moves `from` to `to`, creating parent directories and falling back to copy + delete
+/// when the destination is on another filesystem.
+pub(crate) fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the default journal location for actions that don't have a natural place for
+/// one: the platform data directory (e.g. `~/.local/share/check-file-dups/journals`),
+/// falling back to the current directory.
+pub fn default_journal_dir() -> PathBuf {
+    dirs::data_local_dir()
+        .map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("journals"))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Replaces `path` with a hardlink to `target`.
+///
+/// The link is created under a temporary name next to `path` and renamed over it, so
+/// `path` is never missing if linking fails.
+fn replace_with_hardlink(path: &Path, target: &Path) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(".{}-link.tmp", env!("CARGO_PKG_NAME")));
+    let tmp = path.with_file_name(tmp_name);
+    fs::hard_link(target, &tmp)?;
+    fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+/// Replaces `path` with a reflink of `target`, keeping the attributes of `path`, such as
+/// its permissions and modification time.
+///
+/// The reflink is created under a temporary name next to `path` and renamed over it, so
+/// `path` is never missing if reflinking fails.
+fn replace_with_reflink(path: &Path, target: &Path) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(".{}-reflink.tmp", env!("CARGO_PKG_NAME")));
+    let tmp = path.with_file_name(tmp_name);
+    let attributes = FileAttributes::read(path)?;
+    reflink::clone_file(target, &tmp)?;
+    attributes
+        .apply(&tmp)
+        .and_then(|()| fs::rename(&tmp, path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })
+}
+
+/// Replaces `path`, a hardlink, with an independent copy of its contents.
+fn break_hardlink(path: &Path) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(".{}-copy.tmp", env!("CARGO_PKG_NAME")));
+    let tmp = path.with_file_name(tmp_name);
+    fs::copy(path, &tmp)?;
+    fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+/// Gives a file recreated by [`undo`] the metadata recorded in the journal, if any.
+/// Failing to is only a warning, since the contents were restored.
+fn restore_attributes(path: &Path, attributes: Option<&FileAttributes>) {
+    if let Some(attributes) = attributes
+        && let Err(e) = attributes.apply(path)
+    {
+        warn!(
+            "Restored '{}' without its original metadata: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+/// Returns `path` made absolute, resolving symlinks where possible.
+pub(crate) fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Returns the duplicate groups that actions can be applied to, sorted by hash: the
+/// files of each group that are on disk as hashed rather than inside an archive or
+/// compressed, for groups that still have more than one distinct copy. The parts of a
+/// group that `policy` keeps a copy of each (see [`KeepPolicy::split`]), such as the
+/// copies in each directory with `--within-dir-only`, are groups of their own, so a hash
+/// can appear more than once.
+pub(crate) fn actionable_groups<'a>(
+    duplicates: &'a HashMap<ContentHash, Vec<FileInfo>>,
+    policy: &KeepPolicy,
+) -> Vec<(&'a ContentHash, Vec<FileInfo>)> {
+    let mut groups: Vec<(&ContentHash, Vec<FileInfo>)> = duplicates
+        .iter()
+        .flat_map(|(hash, group)| {
+            let on_disk: Vec<FileInfo> = group
+                .iter()
+                .filter(|f| f.is_actionable())
+                .cloned()
+                .collect();
+            policy
+                .split(&on_disk)
+                .into_iter()
+                .map(|indices| {
+                    let part: Vec<FileInfo> = indices.iter().map(|&i| on_disk[i].clone()).collect();
+                    (hash, part)
+                })
+                .collect::<Vec<_>>()
+        })
+        .filter(|(_, group)| distinct_copies(group) > 1)
+        .collect();
+    groups.sort_by(|a, b| a.0.cmp(b.0));
+    groups
+}
+
+/// Works out the operations that applying `action` would perform, without touching any
+/// files, keeping the copy of each group chosen by `policy`.
+///
+/// Members that are hardlinks of the kept copy are left alone, since acting on them
+/// frees no space. With [`Action::MoveTo`], moved files keep their path relative to
+/// `scan_path` under the quarantine directory, and files whose destination already
+/// exists are skipped. Files protected by `policy` are never acted on, and neither are
+/// copies hardlinked outside the scan with [`KeepPolicy::with_hardlinks_counted_once`].
+/// Paths are made absolute. Returns each operation together with the size of the
+/// duplicate it acts on, in a stable order.
+///
+/// # Errors
+///
+/// Returns an error if any group consists only of protected files, since the action
+/// can't be applied to it.
+pub fn plan_operations(
+    duplicates: &HashMap<ContentHash, Vec<FileInfo>>,
+    scan_path: &Path,
+    action: &Action,
+    policy: &KeepPolicy,
+) -> Result<Vec<(Operation, u64)>> {
+    let scan_root = absolute(scan_path);
+    let quarantine_dir = match action {
+        Action::MoveTo(dir) => Some(absolute(dir)),
+        _ => None,
+    };
+
+    let mut operations = Vec::new();
+    let mut protected_groups = Vec::new();
+    // Files inside archives can't be acted on, so only the files on disk are considered
+    for (hash, group) in actionable_groups(duplicates, policy) {
+        let group = &group;
+        if group
+            .iter()
+            .all(|file| policy.is_protected(&file.path.to_path_buf(), scan_path))
+        {
+            protected_groups.push(group[0].path.clone());
+            continue;
+        }
+        let keeper = &group[policy.select(group, scan_path)];
+        let kept = absolute(&keeper.path.to_path_buf());
+        let mut members: Vec<_> = group
+            .iter()
+            .filter(|file| {
+                file.path != keeper.path
+                    && (file.file_id.is_none() || file.file_id != keeper.file_id)
+            })
+            .collect();
+        members.sort_by(|a, b| a.path.cmp(&b.path));
+        for file in members {
+            if policy.is_protected(&file.path.to_path_buf(), scan_path) {
+                debug!("Leaving protected '{}' alone", file.path.display());
+                continue;
+            }
+            if policy.keeps_linked(file, group) {
+                debug!(
+                    "Leaving '{}' alone, since it is also hardlinked outside the scan",
+                    file.path.display()
+                );
+                continue;
+            }
+            if policy.streams_differ(file, keeper) {
+                info!(
+                    "Leaving '{}' alone, since its extended attributes or alternate data streams differ from '{}'",
+                    file.path.display(),
+                    kept.display()
+                );
+                continue;
+            }
+            let path = absolute(&file.path.to_path_buf());
+            let operation = match &quarantine_dir {
+                Some(dir) => {
+                    if path.starts_with(dir) {
+                        continue;
+                    }
+                    let to = dir.join(relative_to(&path, &scan_root));
+                    if to.exists() {
+                        warn!(
+                            "Not moving '{}': '{}' already exists",
+                            path.display(),
+                            to.display()
+                        );
+                        continue;
+                    }
+                    Operation::Move {
+                        from: path,
+                        to,
+                        hash: hash.to_string(),
+                    }
+                }
+                None if matches!(action, Action::Hardlink | Action::Reflink)
+                    && matches!((file.device(), keeper.device()), (Some(a), Some(b)) if a != b) =>
+                {
+                    warn!(
+                        "Not {} '{}': it is on another device than '{}'",
+                        if *action == Action::Hardlink {
+                            "hardlinking"
+                        } else {
+                            "reflinking"
+                        },
+                        path.display(),
+                        kept.display()
+                    );
+                    continue;
+                }
+                None if *action == Action::Hardlink => Operation::Hardlink {
+                    path,
+                    target: kept.clone(),
+                    hash: hash.to_string(),
+                    attributes: None,
+                },
+                None if *action == Action::Reflink => Operation::Reflink {
+                    path,
+                    target: kept.clone(),
+                    hash: hash.to_string(),
+                    attributes: None,
+                },
+                None => Operation::Delete {
+                    path,
+                    kept: kept.clone(),
+                    hash: hash.to_string(),
+                    trashed: None,
+                    attributes: None,
+                },
+            };
+            operations.push((operation, file.size));
+        }
+    }
+    if let Some(example) = protected_groups.first() {
+        anyhow::bail!(
+            "{} duplicate groups consist only of protected files (e.g. the group of '{}'), so nothing in them may be changed; adjust the protect patterns or leave them out of the scan",
+            Count(protected_groups.len() as u64),
+            example.display()
+        );
+    }
+    Ok(operations)
+}
+
+/// Returns why `file` may have changed since it was scanned, if it may have: it is gone,
+/// or its size or modification time differ. With `rehash`, its contents are also hashed
+/// again and compared with the hash found by the scan.
+pub fn changed_since_scan(file: &FileInfo, rehash: bool) -> Option<String> {
+    let path = file.path.to_path_buf();
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(e) => return Some(e.to_string()),
+    };
+    if metadata.len() != file.size {
+        return Some(format!(
+            "size changed from {} to {}",
+            Bytes(file.size),
+            Bytes(metadata.len())
+        ));
+    }
+    if file.modified.is_some() && metadata.modified().ok() != file.modified {
+        return Some("modified since the scan".to_string());
+    }
+    if rehash {
+        match hash_file(&path) {
+            Ok(hash) if hash == file.hash => {}
+            Ok(_) => return Some("contents changed since the scan".to_string()),
+            Err(e) => return Some(format!("{:#}", e)),
+        }
+    }
+    None
+}
+
+/// Applies `action` to every duplicate, keeping the copy of each group chosen by `policy`.
+///
+/// The operations are those returned by [`plan_operations`], carried out as described
+/// by `options`. Right before acting on a group, every member is checked with
+/// [`changed_since_scan`], and the whole group is skipped if any changed. With
+/// [`Action::Reflink`], each group is also skipped if its filesystem turns out not to
+/// support reflinks, which is tried once per filesystem. Every change is recorded in a
+/// journal written to `journal_path` (saved periodically while running, and once more
+/// at the end), together with the metadata of the files deleted or replaced, which
+/// [`undo`] can use to revert the run.
+///
+/// # Errors
+///
+/// Returns an error if the quarantine directory or journal cannot be written. Failures
+/// of individual file operations are logged and skipped.
+pub fn apply_action(
+    duplicates: &HashMap<ContentHash, Vec<FileInfo>>,
+    scan_path: &Path,
+    action: &Action,
+    policy: &KeepPolicy,
+    options: &ActionOptions,
+    journal_path: &Path,
+    permission: &ActionPermission,
+) -> Result<Journal> {
+    let operations = plan_operations(duplicates, scan_path, action, policy)?;
+    apply_planned(
+        duplicates,
+        action,
+        operations,
+        options,
+        journal_path,
+        permission,
+    )
+}
+
+/// Carries out `operations` returned by [`plan_operations`] for `action`, like
+/// [`apply_action`], so the plan can be shown (see [`crate::plan`]) before any file is
+/// changed.
+///
+/// # Errors
+///
+/// Returns an error if the quarantine directory or journal cannot be written. Failures
+/// of individual file operations are logged and skipped.
+pub fn apply_planned(
+    duplicates: &HashMap<ContentHash, Vec<FileInfo>>,
+    action: &Action,
+    operations: Vec<(Operation, u64)>,
+    options: &ActionOptions,
+    journal_path: &Path,
+    permission: &ActionPermission,
+) -> Result<Journal> {
+    if let Action::MoveTo(dir) = action {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create quarantine directory {}", dir.display()))?;
+    }
+
+    let groups: HashMap<String, Vec<FileInfo>> =
+        actionable_groups(duplicates, &KeepPolicy::default())
+            .into_iter()
+            .map(|(hash, group)| (hash.to_string(), group))
+            .collect();
+    // Whether reflinks work, by the device of the kept copy
+    let reflinks: RefCell<HashMap<Option<u64>, Option<String>>> = RefCell::default();
+    let verify = |operation: &Operation| {
+        let group = groups.get(operation.hash())?;
+        if let Some(reason) = group.iter().find_map(|file| {
+            changed_since_scan(file, options.verify_hash)
+                .map(|reason| format!("'{}': {}", file.path.display(), reason))
+        }) {
+            return Some(reason);
+        }
+        let Operation::Reflink { path, target, .. } = operation else {
+            return None;
+        };
+        let device = fs::metadata(target)
+            .ok()
+            .and_then(|metadata| file_id(&metadata));
+        let unsupported = reflinks
+            .borrow_mut()
+            .entry(device.map(|(device, _)| device))
+            .or_insert_with(|| {
+                let dir = path.parent().unwrap_or(Path::new("."));
+                reflink::probe(target, dir).err().map(|e| {
+                    format!(
+                        "reflinks are not supported on the filesystem of '{}': {}",
+                        target.display(),
+                        e
+                    )
+                })
+            })
+            .clone();
+        if unsupported.is_none() {
+            info!("Reflinking is possible for group {}", operation.hash());
+        }
+        unsupported
+    };
+    execute_operations(operations, journal_path, options, &verify, permission)
+}
+
+/// Performs `operations`, each paired with the size of the file it acts on, recording
+/// every change in a journal written to `journal_path` (saved periodically while
+/// running, and once more at the end), as described by `options`. The metadata of each
+/// file deleted or replaced is recorded with its operation.
+///
+/// Operations are expected in groups of the same hash. `verify` is called with the first
+/// operation of each group, and the group is skipped with a warning if it returns a
+/// reason.
+///
+/// # Errors
+///
+/// Returns an error if the journal cannot be written. Failures of individual file
+/// operations are logged and skipped.
+pub(crate) fn execute_operations(
+    operations: Vec<(Operation, u64)>,
+    journal_path: &Path,
+    options: &ActionOptions,
+    verify: &dyn Fn(&Operation) -> Option<String>,
+    permission: &ActionPermission,
+) -> Result<Journal> {
+    if let Some(parent) = journal_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut journal = Journal::new();
+    let mut freed_bytes = 0;
+    let mut skipped_groups = 0;
+    let mut current_hash = None;
+    let mut skip_group = false;
+    for (mut operation, size) in operations {
+        if current_hash.as_deref() != Some(operation.hash()) {
+            current_hash = Some(operation.hash().to_string());
+            skip_group = match verify(&operation) {
+                Some(reason) => {
+                    warn!("Skipping group {}: {}", operation.hash(), reason);
+                    skipped_groups += 1;
+                    true
+                }
+                None => false,
+            };
+        }
+        if skip_group {
+            continue;
+        }
+        if let Operation::Delete {
+            path, attributes, ..
+        }
+        | Operation::Hardlink {
+            path, attributes, ..
+        }
+        | Operation::Reflink {
+            path, attributes, ..
+        } = &mut operation
+        {
+            match FileAttributes::read(path) {
+                Ok(read) => *attributes = Some(read),
+                Err(e) => {
+                    error!("Failed to process '{}': {}", path.display(), e);
+                    continue;
+                }
+            }
+        }
+        if options.metadata == MetadataPolicy::Preserve
+            && let Operation::Hardlink {
+                path,
+                target,
+                attributes: Some(attributes),
+                ..
+            } = &operation
+            && !FileAttributes::read(target).is_ok_and(|kept| attributes.shareable_with(&kept))
+        {
+            warn!(
+                "Not hardlinking '{}': its permissions, owner or extended attributes differ from '{}'",
+                path.display(),
+                target.display()
+            );
+            continue;
+        }
+        let result = match &mut operation {
+            Operation::Move { from, to, .. } => move_file(from, to)
+                .map(|()| info!("Moved '{}' to '{}'", from.display(), to.display())),
+            Operation::Hardlink { path, target, .. } => replace_with_hardlink(path, target)
+                .map(|()| info!("Linked '{}' to '{}'", path.display(), target.display())),
+            Operation::Reflink { path, target, .. } => replace_with_reflink(path, target)
+                .map(|()| info!("Reflinked '{}' to '{}'", path.display(), target.display())),
+            Operation::Delete { path, trashed, .. } if options.use_trash => {
+                trash::trash(path, permission).map(|location| {
+                    info!("Moved '{}' to the trash", path.display());
+                    *trashed = location;
+                })
+            }
+            Operation::Delete { path, .. } => {
+                fs::remove_file(&*path).map(|()| info!("Deleted '{}'", path.display()))
+            }
+        };
+        match result {
+            Ok(()) => {
+                freed_bytes += size;
+                journal.operations.push(operation);
+                if journal.operations.len().is_multiple_of(100) {
+                    journal.save(journal_path)?;
+                }
+            }
+            Err(e) => error!("Failed to process '{}': {}", operation.path().display(), e),
+        }
+    }
+
+    if skipped_groups > 0 {
+        warn!(
+            "Skipped {} duplicate groups, see the warnings above",
+            Count(skipped_groups)
+        );
+    }
+    journal.save(journal_path)?;
+    info!(
+        "Processed {} duplicates ({} freed), journal written to {}",
+        Count(journal.operations.len() as u64),
+        Bytes(freed_bytes),
+        journal_path.display()
+    );
+    Ok(journal)
+}
+
+/// Returns a journal file name stamped with the current time, in nanoseconds, and the
+/// process ID, so runs started within the same second don't overwrite each other's
+/// journal.
+pub fn journal_file_name() -> String {
+    format!(
+        "{}-journal-{}-{}.json",
+        env!("CARGO_PKG_NAME"),
+        OffsetDateTime::now_utc().unix_timestamp_nanos(),
+        std::process::id()
+    )
+}
+
+/// Reverts the operations recorded in a journal, most recent first.
+///
+/// - Moved files are moved back to their original location.
+/// - Deleted files are moved back from the trash if they were moved there, and otherwise
+///   recreated by copying the kept copy, if it still has the same content.
+/// - Hardlinks are replaced by independent copies of the data.
+/// - Reflinks are left alone, since they already are independent files.
+///
+/// Operations whose original path has been reused, or whose source is gone or changed,
+/// are logged and counted as failed.
+pub fn undo(journal: &Journal, permission: &ActionPermission) -> UndoStats {
+    let mut stats = UndoStats::default();
+    for operation in journal.operations.iter().rev() {
+        let result = match operation {
+            Operation::Move { from, to, .. } => {
+                if from.exists() {
+                    Err(anyhow::anyhow!("'{}' already exists", from.display()))
+                } else {
+                    move_file(to, from)
+                        .map(|()| info!("Restored '{}' from '{}'", from.display(), to.display()))
+                        .map_err(Into::into)
+                }
+            }
+            Operation::Delete {
+                path,
+                trashed: Some(trashed),
+                ..
+            } if trashed.exists() => {
+                if path.exists() {
+                    Err(anyhow::anyhow!("'{}' already exists", path.display()))
+                } else {
+                    trash::restore(trashed, path, permission)
+                        .map(|()| info!("Restored '{}' from the trash", path.display()))
+                        .map_err(Into::into)
+                }
+            }
+            Operation::Delete {
+                path,
+                kept,
+                hash,
+                attributes,
+                ..
+            } => {
+                if path.exists() {
+                    Err(anyhow::anyhow!("'{}' already exists", path.display()))
+                } else {
+                    match hash_file(kept) {
+                        Ok(current) if current.to_string() == *hash => {
+                            let restored = match path.parent() {
+                                Some(parent) => fs::create_dir_all(parent),
+                                None => Ok(()),
+                            }
+                            .and_then(|()| fs::copy(kept, path));
+                            restored
+                                .map(|_| {
+                                    restore_attributes(path, attributes.as_ref());
+                                    info!("Restored '{}' from '{}'", path.display(), kept.display())
+                                })
+                                .map_err(Into::into)
+                        }
+                        Ok(_) => Err(anyhow::anyhow!(
+                            "kept copy '{}' has changed",
+                            kept.display()
+                        )),
+                        Err(e) => Err(anyhow::Error::from(e)
+                            .context(format!("kept copy '{}'", kept.display()))),
+                    }
+                }
+            }
+            Operation::Hardlink {
+                path, attributes, ..
+            } => break_hardlink(path)
+                .map(|()| {
+                    restore_attributes(path, attributes.as_ref());
+                    info!("Replaced hardlink '{}' with a copy", path.display())
+                })
+                .map_err(Into::into),
+            Operation::Reflink { path, .. } => {
+                info!(
+                    "'{}' is a reflink, already an independent file",
+                    path.display()
+                );
+                Ok(())
+            }
+        };
+        match result {
+            Ok(()) => stats.restored += 1,
+            Err(e) => {
+                error!("Failed to undo {:?}: {}", operation, e);
+                stats.failed += 1;
+            }
+        }
+    }
+    info!(
+        "Undo complete: {} operations reverted, {} failed",
+        Count(stats.restored as u64),
+        Count(stats.failed as u64)
+    );
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scanned(path: &Path, contents: &str) -> FileInfo {
+        fs::write(path, contents).unwrap();
+        let metadata = fs::metadata(path).unwrap();
+        FileInfo {
+            file_id: file_id(&metadata),
+            modified: metadata.modified().ok(),
+            ..FileInfo::for_test(path, metadata.len(), hash_file(path).unwrap())
+        }
+    }
+
+    #[test]
+    fn skips_groups_changed_since_the_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let mut duplicates = HashMap::new();
+        for (group, contents) in [("a", "first"), ("b", "second")] {
+            let files: Vec<FileInfo> = (1..=2)
+                .map(|i| scanned(&root.join(format!("{}{}", group, i)), contents))
+                .collect();
+            duplicates.insert(files[0].hash, files);
+        }
+        // Same size and modification time, so only noticed when hashing again
+        let changed = root.join("b2");
+        let modified = fs::metadata(&changed).unwrap().modified().unwrap();
+        fs::write(&changed, "SECOND").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&changed)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        let journal = apply_action(
+            &duplicates,
+            root,
+            &Action::Delete,
+            &KeepPolicy::default(),
+            &ActionOptions {
+                verify_hash: true,
+                ..ActionOptions::default()
+            },
+            &root.join("journal.json"),
+            &ActionPermission::allow_changes(),
+        )
+        .unwrap();
+        assert_eq!(journal.operations.len(), 1);
+        assert!(!root.join("a2").exists());
+        assert!(root.join("b2").exists());
+
+        let kept = scanned(&root.join("a1"), "first");
+        assert_eq!(changed_since_scan(&kept, false), None);
+        fs::write(kept.path.to_path_buf(), "first, longer").unwrap();
+        assert!(
+            changed_since_scan(&kept, false)
+                .unwrap()
+                .starts_with("size changed")
+        );
+    }
+
+    #[test]
+    fn journals_get_their_own_file() {
+        let name = journal_file_name();
+        assert!(
+            name.ends_with(&format!("-{}.json", std::process::id())),
+            "{}",
+            name
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(journal_file_name());
+        Journal::new().save(&path).unwrap();
+        Journal::new().save(&path).unwrap();
+        assert!(Journal::load(&path).unwrap().operations.is_empty());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn undo_restores_recorded_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let mut files = vec![
+            scanned(&root.join("a1"), "data"),
+            scanned(&root.join("a2"), "data"),
+        ];
+        let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        crate::attributes::set_modified(&root.join("a2"), modified).unwrap();
+        let mut permissions = fs::metadata(root.join("a2")).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(root.join("a2"), permissions).unwrap();
+        files[1].modified = Some(modified);
+        let duplicates = HashMap::from([(files[0].hash, files)]);
+
+        let journal = apply_action(
+            &duplicates,
+            root,
+            &Action::Delete,
+            &KeepPolicy::default(),
+            &ActionOptions::default(),
+            &root.join("journal.json"),
+            &ActionPermission::allow_changes(),
+        )
+        .unwrap();
+        assert!(!root.join("a2").exists());
+        let stats = undo(
+            &Journal::load(&root.join("journal.json")).unwrap(),
+            &ActionPermission::allow_changes(),
+        );
+        assert_eq!(stats.restored, 1);
+        assert_eq!(journal.operations.len(), 1);
+        let restored = fs::metadata(root.join("a2")).unwrap();
+        assert_eq!(restored.modified().unwrap(), modified);
+        assert!(restored.permissions().readonly());
+    }
+}
@@ -0,0 +1,232 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use flate2::read::GzDecoder;
+use log::{info, warn};
+use rayon::prelude::*;
+
+use crate::FileInfo;
+use crate::content_hash::ContentHash;
+use crate::units::Count;
+
+/// Separates an archive's path from a member's path inside it, as in
+/// `backup.zip!photos/img001.jpg`.
+pub const MEMBER_SEPARATOR: char = '!';
+
+/// A file inside an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveMember {
+    /// Path of the member inside the archive, with `/` separators.
+    pub name: String,
+    /// Uncompressed size in bytes.
+    pub size: u64,
+    /// BLAKE3 hash of the uncompressed contents.
+    pub hash: ContentHash,
+}
+
+/// Archive formats whose members can be hashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+    if name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveKind::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveKind::TarGz)
+    } else {
+        None
+    }
+}
+
+/// Returns `true` if `path` has the extension of a supported archive format (zip, tar,
+/// tar.gz or tgz).
+pub fn is_archive(path: &Path) -> bool {
+    archive_kind(path).is_some()
+}
+
+/// Returns the path used to report `member` of `archive`, e.g. `backup.zip!a/b.jpg`.
+pub fn member_path(archive: &Path, member: &str) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push(MEMBER_SEPARATOR.to_string());
+    path.push(member);
+    PathBuf::from(path)
+}
+
+fn hash_reader(reader: &mut dyn Read) -> Result<ContentHash> {
+    let mut hasher = blake3::Hasher::new();
+    std::io::copy(reader, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+fn hash_tar_members(reader: impl Read) -> Result<Vec<ArchiveMember>> {
+    let mut members = Vec::new();
+    for entry in tar::Archive::new(reader).entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().replace('\\', "/");
+        let size = entry.size();
+        members.push(ArchiveMember {
+            hash: hash_reader(&mut entry)?,
+            name,
+            size,
+        });
+    }
+    Ok(members)
+}
+
+/// Hashes every regular file inside the archive at `path`, without extracting it.
+///
+/// # Errors
+///
+/// Returns an error if `path` is not a supported archive or cannot be read.
+pub fn hash_members(path: &Path) -> Result<Vec<ArchiveMember>> {
+    let Some(kind) = archive_kind(path) else {
+        anyhow::bail!("not a supported archive");
+    };
+    let reader = BufReader::new(File::open(path)?);
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(reader)?;
+            let mut members = Vec::new();
+            for i in 0..archive.len() {
+                let mut file = archive.by_index(i)?;
+                if !file.is_file() {
+                    continue;
+                }
+                let name = file.name()?.replace('\\', "/");
+                let size = file.size();
+                members.push(ArchiveMember {
+                    hash: hash_reader(&mut file)?,
+                    name,
+                    size,
+                });
+            }
+            Ok(members)
+        }
+        ArchiveKind::Tar => hash_tar_members(reader),
+        ArchiveKind::TarGz => hash_tar_members(GzDecoder::new(reader)),
+    }
+}
+
+/// Hashes the members of every archive among `files`, returning them as files whose
+/// paths have the form `archive!member` so they are grouped with the files they
+/// duplicate. Archives that cannot be read are logged and skipped.
+///
+/// Members are not cached, so every archive is read on each scan.
+pub fn scan_archives<'a>(files: impl IntoIterator<Item = &'a FileInfo>) -> Vec<FileInfo> {
+    let archives: Vec<&FileInfo> = files
+        .into_iter()
+        .filter(|f| is_archive(&f.path.to_path_buf()))
+        .collect();
+    if archives.is_empty() {
+        return Vec::new();
+    }
+    info!(
+        "Hashing the contents of {} archives...",
+        Count(archives.len() as u64)
+    );
+    let members: Vec<FileInfo> = archives
+        .par_iter()
+        .flat_map_iter(|archive| {
+            let archive_path = archive.path.to_path_buf();
+            let members = match hash_members(&archive_path) {
+                Ok(members) => members,
+                Err(e) => {
+                    warn!("Failed to read archive '{}': {}", archive.path.display(), e);
+                    Vec::new()
+                }
+            };
+            members.into_iter().map(move |member| FileInfo {
+                path: member_path(&archive_path, &member.name).into(),
+                size: member.size,
+                hash: member.hash,
+                file_id: None,
+                links: None,
+                allocated_size: None,
+                modified: None,
+                in_archive: true,
+                compressed: false,
+                owner: None,
+            })
+        })
+        .collect();
+    info!(
+        "Found {} files inside archives",
+        Count(members.len() as u64)
+    );
+    members
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn recognizes_archives() {
+        assert!(is_archive(Path::new("backup.ZIP")));
+        assert!(is_archive(Path::new("a/b.tar.gz")));
+        assert!(is_archive(Path::new("c.tgz")));
+        assert!(!is_archive(Path::new("d.gz")));
+        assert_eq!(
+            member_path(Path::new("x/backup.zip"), "photos/a.jpg"),
+            PathBuf::from("x/backup.zip!photos/a.jpg")
+        );
+    }
+
+    #[test]
+    fn hashes_zip_and_tar_members() {
+        let dir = tempfile::tempdir().unwrap();
+        let expected = ContentHash::of(b"hello");
+
+        let zip_path = dir.path().join("a.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        zip.add_directory("photos/", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.start_file("photos/a.txt", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"hello").unwrap();
+        zip.finish().unwrap();
+
+        let tar_path = dir.path().join("b.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(
+            File::create(&tar_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut tar = tar::Builder::new(encoder);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, "docs/b.txt", &b"hello"[..])
+            .unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+
+        assert_eq!(
+            hash_members(&zip_path).unwrap(),
+            [ArchiveMember {
+                name: "photos/a.txt".into(),
+                size: 5,
+                hash: expected,
+            }]
+        );
+        assert_eq!(
+            hash_members(&tar_path).unwrap(),
+            [ArchiveMember {
+                name: "docs/b.txt".into(),
+                size: 5,
+                hash: expected,
+            }]
+        );
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use clap::ValueEnum;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+/// What to do about the metadata of duplicates that are deleted or replaced.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataPolicy {
+    /// Record the metadata of every deleted or replaced file in the journal, so `undo`
+    /// can restore it
+    #[default]
+    Record,
+    /// Also leave files unlinked whose permissions, owner or extended attributes differ
+    /// from the kept copy, since a hardlink would take on those of the kept copy
+    Preserve,
+}
+
+/// Metadata of a file that is lost when it is deleted or replaced by a link: permissions,
+/// modification time, ownership on Unix and extended attributes on Linux and macOS.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileAttributes {
+    /// Unix permission bits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    #[serde(default)]
+    pub readonly: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<SystemTime>,
+    /// User and group ID on Unix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<(u32, u32)>,
+    /// Extended attributes on Linux and macOS: name -> hex-encoded value.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub xattrs: BTreeMap<String, String>,
+}
+
+impl FileAttributes {
+    /// Reads the attributes of the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file's metadata can't be read.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        #[cfg(unix)]
+        let (mode, owner) = {
+            use std::os::unix::fs::MetadataExt;
+            (
+                Some(metadata.mode() & 0o7777),
+                Some((metadata.uid(), metadata.gid())),
+            )
+        };
+        #[cfg(not(unix))]
+        let (mode, owner) = (None, None);
+        Ok(Self {
+            mode,
+            readonly: metadata.permissions().readonly(),
+            modified: metadata.modified().ok(),
+            owner,
+            xattrs: xattrs::read(path)?,
+        })
+    }
+
+    /// Returns `true` if a file with these attributes can be replaced by a hardlink to
+    /// a file with the `other` attributes without changing its permissions, owner or
+    /// extended attributes. Modification times are not compared.
+    pub fn shareable_with(&self, other: &Self) -> bool {
+        self.mode == other.mode
+            && self.readonly == other.readonly
+            && self.owner == other.owner
+            && self.xattrs == other.xattrs
+    }
+
+    /// Applies the attributes to the file at `path`. The owner is only changed where the
+    /// process is allowed to.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error setting an attribute.
+    pub fn apply(&self, path: &Path) -> io::Result<()> {
+        xattrs::write(path, &self.xattrs)?;
+        #[cfg(unix)]
+        if let Some((uid, gid)) = self.owner
+            && let Err(e) = std::os::unix::fs::chown(path, Some(uid), Some(gid))
+        {
+            debug!("Not changing the owner of '{}': {}", path.display(), e);
+        }
+        if let Some(modified) = self.modified {
+            set_modified(path, modified)?;
+        }
+        let mut permissions = fs::metadata(path)?.permissions();
+        #[cfg(unix)]
+        if let Some(mode) = self.mode {
+            use std::os::unix::fs::PermissionsExt;
+            permissions.set_mode(mode);
+        }
+        #[cfg(not(unix))]
+        permissions.set_readonly(self.readonly);
+        fs::set_permissions(path, permissions)
+    }
+}
+
+/// Sets the modification time of the file at `path`, without needing write access to
+/// its contents, so it also works for read-only files.
+pub fn set_modified(path: &Path, modified: SystemTime) -> io::Result<()> {
+    // Owners can set the times through a read-only handle on Unix, and the handle only
+    // needs the right to change attributes on Windows
+    #[cfg(unix)]
+    let file = fs::File::open(path)?;
+    #[cfg(windows)]
+    let file = {
+        use std::os::windows::fs::OpenOptionsExt;
+        use windows_sys::Win32::Storage::FileSystem::FILE_WRITE_ATTRIBUTES;
+        fs::File::options()
+            .access_mode(FILE_WRITE_ATTRIBUTES)
+            .open(path)?
+    };
+    #[cfg(not(any(unix, windows)))]
+    let file = fs::File::options().write(true).open(path)?;
+    file.set_modified(modified)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub(crate) mod xattrs {
+    use std::collections::BTreeMap;
+    use std::ffi::{CStr, CString, OsStr};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    use log::debug;
+
+    // macOS takes an offset into resource forks and flags on top of the Linux arguments
+
+    fn list(path: &CStr, buffer: &mut [u8]) -> isize {
+        // SAFETY: `path` is NUL-terminated and `buffer` is valid for its length
+        unsafe {
+            #[cfg(target_os = "linux")]
+            return libc::listxattr(path.as_ptr(), buffer.as_mut_ptr().cast(), buffer.len());
+            #[cfg(target_os = "macos")]
+            return libc::listxattr(path.as_ptr(), buffer.as_mut_ptr().cast(), buffer.len(), 0);
+        }
+    }
+
+    fn get(path: &CStr, name: &CStr, buffer: &mut [u8]) -> isize {
+        let (path, name, value) = (path.as_ptr(), name.as_ptr(), buffer.as_mut_ptr().cast());
+        // SAFETY: both strings are NUL-terminated and `buffer` is valid for its length
+        unsafe {
+            #[cfg(target_os = "linux")]
+            return libc::getxattr(path, name, value, buffer.len());
+            #[cfg(target_os = "macos")]
+            return libc::getxattr(path, name, value, buffer.len(), 0, 0);
+        }
+    }
+
+    fn set(path: &CStr, name: &CStr, value: &[u8]) -> i32 {
+        let (path, name, size) = (path.as_ptr(), name.as_ptr(), value.len());
+        // SAFETY: both strings are NUL-terminated and `value` is valid for its length
+        unsafe {
+            #[cfg(target_os = "linux")]
+            return libc::setxattr(path, name, value.as_ptr().cast(), size, 0);
+            #[cfg(target_os = "macos")]
+            return libc::setxattr(path, name, value.as_ptr().cast(), size, 0, 0);
+        }
+    }
+
+    fn c_string(bytes: &[u8]) -> io::Result<CString> {
+        CString::new(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    /// Calls `get` with a buffer large enough for its result: `get` is called with an
+    /// empty buffer first to learn the size needed, as listxattr and getxattr support.
+    fn read_sized(get: impl Fn(&mut [u8]) -> isize) -> io::Result<Vec<u8>> {
+        loop {
+            let size = get(&mut []);
+            if size < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut buffer = vec![0; size as usize];
+            let read = get(&mut buffer);
+            if read >= 0 {
+                buffer.truncate(read as usize);
+                return Ok(buffer);
+            }
+            // The value grew in between
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() != Some(libc::ERANGE) {
+                return Err(error);
+            }
+        }
+    }
+
+    pub fn read(path: &Path) -> io::Result<BTreeMap<String, String>> {
+        Ok(read_raw(path)?
+            .into_iter()
+            .map(|(name, value)| {
+                let hex = value.iter().map(|byte| format!("{:02x}", byte)).collect();
+                (name, hex)
+            })
+            .collect())
+    }
+
+    /// Reads the extended attributes of `path` with their values as they are.
+    pub fn read_raw(path: &Path) -> io::Result<BTreeMap<String, Vec<u8>>> {
+        let c_path = c_string(path.as_os_str().as_bytes())?;
+        let names = match read_sized(|buffer| list(&c_path, buffer)) {
+            Ok(names) => names,
+            Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(BTreeMap::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut xattrs = BTreeMap::new();
+        for name in names
+            .split(|&byte| byte == 0)
+            .filter(|name| !name.is_empty())
+        {
+            let c_name = c_string(name)?;
+            let value = read_sized(|buffer| get(&c_path, &c_name, buffer))?;
+            xattrs.insert(
+                OsStr::from_bytes(name).to_string_lossy().into_owned(),
+                value,
+            );
+        }
+        Ok(xattrs)
+    }
+
+    pub fn write(path: &Path, xattrs: &BTreeMap<String, String>) -> io::Result<()> {
+        let c_path = c_string(path.as_os_str().as_bytes())?;
+        for (name, hex) in xattrs {
+            let value = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(hex.get(i..i + 2).unwrap_or("?"), 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let c_name = c_string(name.as_bytes())?;
+            if set(&c_path, &c_name, &value) != 0 {
+                // Attributes in namespaces such as `security.` may not be settable here
+                let error = io::Error::last_os_error();
+                if !matches!(error.raw_os_error(), Some(libc::EPERM | libc::ENOTSUP)) {
+                    return Err(error);
+                }
+                debug!("Not setting '{}' on '{}': {}", name, path.display(), error);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub(crate) mod xattrs {
+    use std::collections::BTreeMap;
+    use std::io;
+    use std::path::Path;
+
+    pub fn read(_path: &Path) -> io::Result<BTreeMap<String, String>> {
+        Ok(BTreeMap::new())
+    }
+
+    #[allow(dead_code)]
+    pub fn read_raw(_path: &Path) -> io::Result<BTreeMap<String, Vec<u8>>> {
+        Ok(BTreeMap::new())
+    }
+
+    pub fn write(_path: &Path, _xattrs: &BTreeMap<String, String>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn restores_permissions_and_modification_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("original");
+        fs::write(&original, "data").unwrap();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        fs::File::options()
+            .write(true)
+            .open(&original)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let mut permissions = fs::metadata(&original).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&original, permissions).unwrap();
+
+        let attributes = FileAttributes::read(&original).unwrap();
+        let json = serde_json::to_string(&attributes).unwrap();
+        let attributes: FileAttributes = serde_json::from_str(&json).unwrap();
+        let copy = dir.path().join("copy");
+        fs::write(&copy, "data").unwrap();
+        assert!(!attributes.shareable_with(&FileAttributes::read(&copy).unwrap()));
+
+        attributes.apply(&copy).unwrap();
+        let restored = FileAttributes::read(&copy).unwrap();
+        assert_eq!(restored.modified, Some(modified));
+        assert!(restored.readonly);
+        assert!(attributes.shareable_with(&restored));
+    }
+}
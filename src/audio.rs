@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+use log::{debug, info, warn};
+use rayon::prelude::*;
+use rusty_chromaprint::{Configuration, Fingerprinter};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as DecodeError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::FileInfo;
+use crate::content_hash::ContentHash;
+use crate::scan_root::ScanRoot;
+use crate::units::{Bytes, Count};
+use crate::utils::cluster;
+
+/// Extensions of the audio formats that can be decoded for fingerprinting.
+pub const AUDIO_EXTENSIONS: &[&str] = &[
+    "aac", "aif", "aiff", "flac", "m4a", "mka", "mp3", "oga", "ogg", "wav",
+];
+
+/// Only the start of each recording is fingerprinted, which is enough to tell
+/// recordings apart and keeps long files from dominating the scan.
+const MAX_SECONDS: u64 = 120;
+
+/// Fingerprints are compared at a few offsets either side of alignment, since encoders
+/// add different amounts of padding at the start.
+const MAX_OFFSET: usize = 3;
+
+/// Returns `true` if `path` has the extension of a supported audio format.
+pub fn is_audio(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// A Chromaprint fingerprint of the start of a recording.
+#[derive(Debug, Clone)]
+pub struct AudioFingerprint {
+    /// Fingerprint items, each describing about 0.12 seconds of audio.
+    pub items: Vec<u32>,
+    /// Duration of the whole recording in seconds, if the container records it.
+    pub duration: Option<f64>,
+}
+
+impl AudioFingerprint {
+    /// Returns `true` if both fingerprints are likely of the same recording: their
+    /// durations (when known) agree to within a second and 2%, and at least
+    /// `min_similarity` of their fingerprint bits match.
+    pub fn matches(&self, other: &Self, min_similarity: f64) -> bool {
+        if let (Some(a), Some(b)) = (self.duration, other.duration)
+            && (a - b).abs() > 1.0 + a.max(b) * 0.02
+        {
+            return false;
+        }
+        similarity(&self.items, &other.items) >= min_similarity
+    }
+}
+
+/// Returns the fraction of matching bits between two fingerprints, at the best of a few
+/// small offsets. Returns 0 if the fingerprints don't overlap.
+pub fn similarity(a: &[u32], b: &[u32]) -> f64 {
+    let score = |a: &[u32], b: &[u32], offset: usize| {
+        let pairs = a.iter().skip(offset).zip(b);
+        let (count, differing) = pairs.fold((0u64, 0u64), |(count, bits), (x, y)| {
+            (count + 1, bits + u64::from((x ^ y).count_ones()))
+        });
+        if count == 0 {
+            0.0
+        } else {
+            1.0 - differing as f64 / (count * 32) as f64
+        }
+    };
+    (0..=MAX_OFFSET)
+        .flat_map(|offset| [score(a, b, offset), score(b, a, offset)])
+        .fold(0.0, f64::max)
+}
+
+/// Decodes the start of an audio file and computes its Chromaprint fingerprint.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or has no decodable audio track.
+pub fn fingerprint(path: &Path) -> Result<AudioFingerprint> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            source,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?
+        .format;
+    let track = format
+        .default_track()
+        .ok_or_else(|| anyhow!("no audio track"))?;
+    let track_id = track.id;
+    let params = &track.codec_params;
+    let sample_rate = params
+        .sample_rate
+        .ok_or_else(|| anyhow!("unknown sample rate"))?;
+    let channels = params
+        .channels
+        .ok_or_else(|| anyhow!("unknown channel layout"))?
+        .count();
+    let duration = params
+        .n_frames
+        .map(|frames| frames as f64 / f64::from(sample_rate));
+    let mut decoder = symphonia::default::get_codecs().make(params, &DecoderOptions::default())?;
+
+    let mut printer = Fingerprinter::new(&Configuration::preset_test2());
+    printer
+        .start(sample_rate, channels as u32)
+        .map_err(|e| anyhow!("cannot fingerprint audio: {:?}", e))?;
+    let max_samples = MAX_SECONDS * u64::from(sample_rate) * channels as u64;
+    let mut consumed = 0u64;
+    let mut buffer: Option<SampleBuffer<i16>> = None;
+    while consumed < max_samples {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(DecodeError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        match decoder.decode(&packet) {
+            Ok(audio) => {
+                let buffer = buffer.get_or_insert_with(|| {
+                    SampleBuffer::new(audio.capacity() as u64, *audio.spec())
+                });
+                buffer.copy_interleaved_ref(audio);
+                printer.consume(buffer.samples());
+                consumed += buffer.samples().len() as u64;
+            }
+            // Skip corrupt packets, as players do
+            Err(DecodeError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    printer.finish();
+    Ok(AudioFingerprint {
+        items: printer.fingerprint().to_vec(),
+        duration,
+    })
+}
+
+/// Finds clusters of audio files that are likely the same recording, e.g. ripped at
+/// different bitrates or in different formats. Only clusters with at least two different
+/// contents are returned; files with identical contents are exact duplicates and are
+/// reported as such. Members of each cluster are sorted by path.
+///
+/// `min_similarity` is the fraction of fingerprint bits that must match, between 0 and 1.
+/// Files that cannot be decoded are skipped.
+pub fn find_same_recordings<'a>(
+    files: impl IntoIterator<Item = &'a FileInfo>,
+    min_similarity: f64,
+) -> Vec<Vec<FileInfo>> {
+    // Decode each distinct content once
+    let mut by_hash: HashMap<ContentHash, Vec<&FileInfo>> = HashMap::new();
+    for file in files
+        .into_iter()
+        .filter(|file| !file.in_archive && is_audio(&file.path.to_path_buf()))
+    {
+        by_hash.entry(file.hash).or_default().push(file);
+    }
+    let mut contents: Vec<Vec<&FileInfo>> = by_hash.into_values().collect();
+    contents.sort_by(|a, b| a[0].path.cmp(&b[0].path));
+    info!(
+        "Computing audio fingerprints of {} files...",
+        Count(contents.len() as u64)
+    );
+    let printed: Vec<(Vec<&FileInfo>, AudioFingerprint)> = contents
+        .into_par_iter()
+        .filter_map(
+            |members| match fingerprint(&members[0].path.to_path_buf()) {
+                Ok(fingerprint) => Some((members, fingerprint)),
+                Err(e) => {
+                    debug!("Skipping '{}': {}", members[0].path.display(), e);
+                    None
+                }
+            },
+        )
+        .collect();
+
+    let linked = |i: usize, j: usize| printed[i].1.matches(&printed[j].1, min_similarity);
+    let mut clusters: Vec<Vec<FileInfo>> = cluster(printed.len(), linked)
+        .into_iter()
+        .map(|indices| {
+            let mut members: Vec<FileInfo> = indices
+                .into_iter()
+                .flat_map(|i| printed[i].0.iter().map(|file| (*file).clone()))
+                .collect();
+            members.sort_by(|a, b| a.path.cmp(&b.path));
+            members
+        })
+        .collect();
+    clusters.sort_by(|a, b| a[0].path.cmp(&b[0].path));
+    clusters
+}
+
+/// Prints clusters found by [`find_same_recordings`], as a section separate from the
+/// exact duplicates.
+pub fn print_same_recordings(clusters: &[Vec<FileInfo>], root: &ScanRoot) {
+    if clusters.is_empty() {
+        info!("No differently encoded copies of the same recording found");
+        return;
+    }
+    warn!(
+        "Found {} recordings with differently encoded copies",
+        Count(clusters.len() as u64)
+    );
+    for cluster in clusters {
+        warn!("Likely the same recording ({} files):", cluster.len());
+        for file in cluster {
+            warn!(
+                "  {} ({})",
+                root.display_file(&file.path).display(),
+                Bytes(file.size)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_audio_extensions() {
+        assert!(is_audio(Path::new("Music/01 Intro.FLAC")));
+        assert!(is_audio(Path::new("b.mp3")));
+        assert!(!is_audio(Path::new("c.jpg")));
+    }
+
+    #[test]
+    fn similarity_tolerates_offsets() {
+        let a: Vec<u32> = (0..50u32).map(|i| i.wrapping_mul(2_654_435_761)).collect();
+        assert_eq!(similarity(&a, &a), 1.0);
+        assert_eq!(similarity(&a[2..], &a), 1.0);
+        let inverted: Vec<u32> = a.iter().map(|x| !x).collect();
+        assert!(similarity(&a, &inverted) < 0.8);
+        assert_eq!(similarity(&a, &[]), 0.0);
+    }
+
+    #[test]
+    fn durations_must_agree() {
+        let items = vec![1, 2, 3];
+        let print = |duration| AudioFingerprint {
+            items: items.clone(),
+            duration,
+        };
+        assert!(print(Some(200.0)).matches(&print(Some(201.5)), 0.9));
+        assert!(!print(Some(200.0)).matches(&print(Some(210.0)), 0.9));
+        assert!(print(None).matches(&print(Some(210.0)), 0.9));
+    }
+
+    #[test]
+    fn fingerprints_wav() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tone.wav");
+        let rate = 11_025u32;
+        let samples: Vec<i16> = (0..rate * 5)
+            .map(|i| {
+                let t = i as f32 / rate as f32;
+                let freq = if (i / 2_000).is_multiple_of(2) {
+                    440.0
+                } else {
+                    660.0
+                };
+                ((t * freq * std::f32::consts::TAU).sin() * 10_000.0) as i16
+            })
+            .collect();
+        let mut wav = Vec::new();
+        let data_len = samples.len() as u32 * 2;
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+        wav.extend_from_slice(&rate.to_le_bytes());
+        wav.extend_from_slice(&(rate * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+        std::fs::write(&path, wav).unwrap();
+
+        let print = fingerprint(&path).unwrap();
+        assert!(!print.items.is_empty());
+        assert!((print.duration.unwrap() - 5.0).abs() < 0.01);
+        assert!(print.matches(&print, 1.0));
+    }
+}
@@ -0,0 +1,228 @@
+//! The `bench` command: hashes samples of the files under a path with 1, 2, 4, ... threads
+//! to find the `threads` setting that reads the disk fastest, instead of guessing.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use log::{info, warn};
+use rayon::prelude::*;
+
+use crate::scan_errors::ScanErrors;
+use crate::scan_root::ScanRoot;
+use crate::scanner::{
+    ModifiedRange, NameFilter, ScanLimit, auto_walk_threads, hash_file, hash_file_parallel,
+    walk_directory,
+};
+use crate::units::{Bytes, Count};
+use crate::utils::new_progress_bar;
+
+/// Fewest files to hash for each thread count for the timings to mean anything.
+const MIN_SAMPLE_FILES: usize = 4;
+
+/// A thread count is recommended over a higher one unless the higher one is at least this
+/// much faster, since extra threads cost memory and CPU for little gain.
+const RECOMMEND_TOLERANCE: f64 = 0.9;
+
+/// Time taken to hash one sample with a number of threads.
+#[derive(Debug, Clone, Copy)]
+pub struct BenchResult {
+    /// Value of `--threads`: files hashed at once, and threads hashing each file.
+    pub threads: usize,
+    /// Files hashed.
+    pub files: usize,
+    /// Total size of the files hashed.
+    pub bytes: u64,
+    /// Time taken to hash them.
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    /// Returns the bytes hashed per second.
+    pub fn throughput(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64().max(1e-6)
+    }
+}
+
+/// Returns the thread counts to try: the powers of two below `max_threads`, then
+/// `max_threads` itself.
+pub fn thread_counts(max_threads: usize) -> Vec<usize> {
+    let mut counts: Vec<usize> = std::iter::successors(Some(1), |count| Some(count * 2))
+        .take_while(|&count| count < max_threads)
+        .collect();
+    counts.push(max_threads.max(1));
+    counts
+}
+
+/// Hashes samples of the files under `root.scan_path()` with each of
+/// [`thread_counts`]`(max_threads)` threads, bypassing the cache, and returns the
+/// timings.
+///
+/// Each thread count hashes its own files, taken from across the tree, up to about
+/// `sample_bytes`, so files read by an earlier run and still in the operating system's
+/// page cache don't make later runs look faster.
+pub fn run_bench(
+    root: &ScanRoot,
+    skip_dirs: &[String],
+    min_size: u64,
+    max_threads: usize,
+    sample_bytes: u64,
+) -> Result<Vec<BenchResult>> {
+    let mut errors = ScanErrors::default();
+    let (file_paths, _) = walk_directory(
+        root,
+        skip_dirs,
+        &NameFilter::default(),
+        min_size,
+        &ModifiedRange::default(),
+        &ScanLimit::default(),
+        false,
+        false,
+        None,
+        auto_walk_threads(),
+        &mut errors,
+    )?;
+    let counts = thread_counts(max_threads);
+    let samples: Vec<Vec<(&PathBuf, u64)>> = (0..counts.len())
+        .map(|run| sample(&file_paths, run, counts.len(), sample_bytes))
+        .collect();
+    if samples.iter().any(|files| files.len() < MIN_SAMPLE_FILES) {
+        anyhow::bail!(
+            "Too few files under {} for a benchmark, try a larger directory",
+            root.scan_path().display()
+        );
+    }
+
+    let mut results = Vec::new();
+    for (threads, files) in counts.into_iter().zip(samples) {
+        results.push(hash_sample(&files, threads)?);
+    }
+    Ok(results)
+}
+
+/// Returns every `runs`th file of `file_paths` starting at `run`, with its size, until
+/// the files add up to `sample_bytes`.
+fn sample(
+    file_paths: &[PathBuf],
+    run: usize,
+    runs: usize,
+    sample_bytes: u64,
+) -> Vec<(&PathBuf, u64)> {
+    let mut total = 0;
+    file_paths
+        .iter()
+        .skip(run)
+        .step_by(runs)
+        .filter_map(|path| Some((path, fs::metadata(path).ok()?.len())))
+        .take_while(|(_, size)| {
+            let more = total < sample_bytes;
+            total += size;
+            more
+        })
+        .collect()
+}
+
+/// Hashes `files` like a scan with `--threads threads` and times it.
+fn hash_sample(files: &[(&PathBuf, u64)], threads: usize) -> Result<BenchResult> {
+    let io_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?;
+    let hash_pool = if threads > 1 {
+        Some(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()?,
+        )
+    } else {
+        None
+    };
+    let bytes: u64 = files.iter().map(|(_, size)| size).sum();
+    info!(
+        "Hashing {} files ({}) with {} threads...",
+        Count(files.len() as u64),
+        Bytes(bytes),
+        threads
+    );
+    let progress = new_progress_bar(bytes);
+    let start = Instant::now();
+    io_pool.install(|| {
+        files.par_iter().for_each(|(path, size)| {
+            let result = match &hash_pool {
+                Some(pool) => hash_file_parallel(path, pool),
+                None => hash_file(path),
+            };
+            if let Err(e) = result {
+                warn!("Failed to hash '{}': {}", path.display(), e);
+            }
+            progress.inc(*size);
+        })
+    });
+    let elapsed = start.elapsed();
+    progress.finish_and_clear();
+    Ok(BenchResult {
+        threads,
+        files: files.len(),
+        bytes,
+        elapsed,
+    })
+}
+
+/// Returns the thread count to recommend: the fewest threads within
+/// [`RECOMMEND_TOLERANCE`] of the fastest result.
+pub fn recommend(results: &[BenchResult]) -> Option<usize> {
+    let best = results
+        .iter()
+        .map(BenchResult::throughput)
+        .fold(0.0, f64::max);
+    results
+        .iter()
+        .find(|result| result.throughput() >= best * RECOMMEND_TOLERANCE)
+        .map(|result| result.threads)
+}
+
+/// Prints the throughput of each thread count and the recommended setting.
+pub fn print_bench(results: &[BenchResult]) {
+    info!("Threads    Throughput    Time");
+    for result in results {
+        info!(
+            "{:>7}  {:>12}  {:>5.2}s",
+            result.threads,
+            format!("{}/s", Bytes(result.throughput() as u64)),
+            result.elapsed.as_secs_f64()
+        );
+    }
+    if let Some(threads) = recommend(results) {
+        info!(
+            "Recommended: --threads {}, or `threads = {}` in the config file",
+            threads, threads
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recommends_fewest_threads_near_the_fastest() {
+        assert_eq!(thread_counts(1), [1]);
+        assert_eq!(thread_counts(6), [1, 2, 4, 6]);
+        assert_eq!(thread_counts(8), [1, 2, 4, 8]);
+
+        let result = |threads, millis| BenchResult {
+            threads,
+            files: 10,
+            bytes: 1000,
+            elapsed: Duration::from_millis(millis),
+        };
+        let results = [
+            result(1, 400),
+            result(2, 210),
+            result(4, 200),
+            result(8, 250),
+        ];
+        assert_eq!(recommend(&results), Some(2));
+        assert_eq!(recommend(&[]), None);
+    }
+}
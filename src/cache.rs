@@ -1,29 +1,485 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
-
-use anyhow::Result;
-use indicatif::{HumanBytes, HumanCount, ProgressBar};
-use log::{info, warn};
-use serde_json;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use indicatif::ProgressBar;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
 use zstd::stream::{Encoder, decode_all};
 
+use crate::binary::{BinaryReader, put_str, put_u64};
+use crate::error::{CheckFileDupsError, IoResultExt, Result};
+use crate::paths::{escape_path, unescape_path};
+use crate::scan_root::ScanRoot;
+use crate::scanner::file_id;
+use crate::shutdown::hold_exit;
+use crate::units::{Bytes, Count};
+use crate::utils::new_spinner;
+
+/// Statistics returned by [`HashCache::prune`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PruneStats {
+    /// Number of entries in the cache before pruning.
+    pub total_entries: usize,
+    /// Number of entries removed.
+    pub removed_entries: usize,
+    /// Total size of the files the removed entries referred to, in bytes.
+    pub removed_bytes: u64,
+}
+
+impl PruneStats {
+    /// Percentage of entries removed, or 0 if the cache was empty.
+    pub fn removed_percent(&self) -> f64 {
+        if self.total_entries == 0 {
+            0.0
+        } else {
+            (self.removed_entries as f64 / self.total_entries as f64) * 100.0
+        }
+    }
+}
+
+/// A single cache entry, as returned by [`HashCache::entries`] and [`HashCache::get_entry`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    /// Normalized path relative to the base path.
+    pub path: String,
+    /// Modification time of the file when it was hashed (Unix timestamp).
+    pub mtime: u64,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// BLAKE3 hash of the file contents.
+    pub hash: String,
+    /// When the entry was last set or found valid in a scan (Unix timestamp), to a day.
+    pub last_seen: u64,
+}
+
+/// Summary statistics returned by [`HashCache::stats`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of entries in the cache.
+    pub entries: usize,
+    /// Total size of the files represented by the cache, in bytes.
+    pub total_size: u64,
+    /// Entry with the oldest recorded modification time: (path, mtime).
+    pub oldest: Option<(String, u64)>,
+    /// Entry with the newest recorded modification time: (path, mtime).
+    pub newest: Option<(String, u64)>,
+}
+
+/// What [`HashCache::inspect`] found in a cache file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheFileInfo {
+    /// Number of entries in the cache.
+    pub entries: usize,
+    /// Format the cache is stored in.
+    pub format: CacheFormat,
+    /// `true` if the cache is in an older format version, rewritten on the next save.
+    pub outdated: bool,
+}
+
+/// When to automatically save the cache during a long scan.
+///
+/// Parsed from a comma-separated list of limits, e.g. `10m`, `5000` or `10m,5000`:
+/// a number with an `s`, `m` or `h` suffix sets the time interval between checkpoints,
+/// and a plain number sets how many new hashes trigger a checkpoint. Whichever limit
+/// is reached first triggers the save.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CheckpointPolicy {
+    /// Save after this much time has passed since the last checkpoint.
+    pub interval: Option<Duration>,
+    /// Save after this many new hashes have been added since the last checkpoint.
+    pub every_entries: Option<usize>,
+}
+
+impl std::str::FromStr for CheckpointPolicy {
+    type Err = CheckFileDupsError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut policy = CheckpointPolicy::default();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (number, unit_secs) = match part.char_indices().last() {
+                Some((i, 's')) => (&part[..i], Some(1)),
+                Some((i, 'm')) => (&part[..i], Some(60)),
+                Some((i, 'h')) => (&part[..i], Some(60 * 60)),
+                _ => (part, None),
+            };
+            let value: u64 = number.parse().map_err(|_| {
+                CheckFileDupsError::InvalidOptions(format!("invalid checkpoint limit '{}'", part))
+            })?;
+            if value == 0 {
+                return Err(CheckFileDupsError::InvalidOptions(format!(
+                    "checkpoint limit '{}' must be greater than 0",
+                    part
+                )));
+            }
+            match unit_secs {
+                Some(unit) => policy.interval = Some(Duration::from_secs(value * unit)),
+                None => policy.every_entries = Some(value as usize),
+            }
+        }
+        if policy == CheckpointPolicy::default() {
+            return Err(CheckFileDupsError::InvalidOptions(
+                "expected a time interval (e.g. 10m) and/or entry count".to_string(),
+            ));
+        }
+        Ok(policy)
+    }
+}
+
+/// In-memory cache map: normalized relative path -> (mtime, size, hash, last_seen), where
+/// `last_seen` is when the entry was last set or found valid, in seconds since the epoch.
+type CacheMap = HashMap<String, (u64, u64, String, u64)>;
+
+/// How old `last_seen` gets before a valid entry refreshes it. Refreshing every hit would
+/// mean saving the whole cache after every scan.
+const LAST_SEEN_RESOLUTION: u64 = 24 * 60 * 60;
+
+/// Cache key of the file last hashed with each (device, inode), to find the entries of
+/// renamed files. Only filled on platforms with stable inode numbers.
+type FileIdMap = HashMap<(u64, u64), String>;
+
+/// Version of the on-disk cache format written by this build.
+///
+/// Version 1 was a bare JSON map without a header; version 2 wraps the entries in a
+/// [`CacheFile`] with an explicit version number; version 3 escapes the bytes of paths
+/// that aren't valid UTF-8, and `%`, in the keys (see [`crate::paths::escape_path`]);
+/// version 4 adds when each entry was last seen, for [`CacheLimits`]; version 5 records
+/// the base path the keys are relative to, see [`HashCache::with_base`].
+pub const CACHE_FORMAT_VERSION: u32 = 5;
+
+/// One step of upgrading a JSON cache from format version `from` to the next, which
+/// rewrites the parsed file in place. Older caches go through each step from their
+/// version up, so a format change only needs a step from the version before it, and the
+/// hashes of caches several versions old are kept rather than computed again.
+struct Migration {
+    from: u32,
+    /// What the step changes, for the log.
+    change: &'static str,
+    migrate: fn(&mut serde_json::Value) -> DecodeResult<()>,
+}
+
+/// Result of decoding a cache file, whose error becomes the reason of a
+/// [`CheckFileDupsError::CacheCorrupted`] error, or a
+/// [`CheckFileDupsError::CacheVersionUnsupported`] one for an [`UnsupportedVersion`].
+type DecodeResult<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Format version of a cache file written by a newer version of the program.
+#[derive(Debug)]
+struct UnsupportedVersion(u32);
+
+impl std::fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "cache format version {} is newer than supported version {}",
+            self.0, CACHE_FORMAT_VERSION
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedVersion {}
+
+/// The steps from version 1 up to [`CACHE_FORMAT_VERSION`], in order.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 1,
+        change: "add a version header",
+        migrate: |file| {
+            let entries = file.take();
+            *file = serde_json::json!({ "version": 1, "entries": entries });
+            Ok(())
+        },
+    },
+    Migration {
+        from: 2,
+        change: "escape paths in keys",
+        // Keys were lossily converted paths, so only `%` needs escaping
+        migrate: |file| {
+            for key in ["entries", "file_ids"] {
+                let escape = |key: &str| key.replace('%', "%25");
+                match file.get_mut(key) {
+                    Some(serde_json::Value::Object(entries)) => {
+                        *entries = std::mem::take(entries)
+                            .into_iter()
+                            .map(|(key, entry)| (escape(&key), entry))
+                            .collect();
+                    }
+                    Some(serde_json::Value::Array(ids)) => {
+                        for id in ids {
+                            if let Some(key) = id.get_mut(2)
+                                && let Some(escaped) = key.as_str().map(escape)
+                            {
+                                *key = escaped.into();
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Ok(())
+        },
+    },
+    Migration {
+        from: 3,
+        change: "record when entries were last seen",
+        // As last seen now, since they were valid when last saved
+        migrate: |file| {
+            let now = now();
+            let entries = file
+                .get_mut("entries")
+                .and_then(serde_json::Value::as_object_mut)
+                .ok_or("no entries")?;
+            for entry in entries.values_mut() {
+                entry
+                    .as_array_mut()
+                    .ok_or("malformed entry")?
+                    .push(now.into());
+            }
+            Ok(())
+        },
+    },
+    Migration {
+        from: 4,
+        change: "record the base path",
+        // Unknown for older caches, which are then taken to be for any base path
+        migrate: |_| Ok(()),
+    },
+];
+
+/// First bytes of a cache saved in [`CacheFormat::Binary`], followed by a little-endian
+/// `u32` format version and the zstd-compressed entries. JSON caches start with the zstd
+/// frame magic instead, so the format of a cache file is told from its content.
+pub const BINARY_CACHE_MAGIC: &[u8] = b"CFDCACHE";
+
+/// How the hash cache is stored on disk. Either is read regardless of this setting, and
+/// a cache in the other format is converted on the next save.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheFormat {
+    /// Zstandard-compressed JSON, which can be inspected with standard tools
+    #[default]
+    Json,
+    /// A compact binary encoding, much faster to load and save for large caches
+    Binary,
+}
+
+impl std::fmt::Display for CacheFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CacheFormat::Json => "JSON",
+            CacheFormat::Binary => "binary",
+        })
+    }
+}
+
+/// A decoded cache snapshot.
+struct Snapshot {
+    entries: CacheMap,
+    file_ids: FileIdMap,
+    format: CacheFormat,
+    /// Escaped base path the keys are relative to, if recorded.
+    base: Option<String>,
+    /// `true` if the snapshot is in an older format version and should be rewritten.
+    outdated: bool,
+}
+
+/// Just the version header of a [`CacheFile`], to tell how to parse the rest.
+#[derive(Deserialize)]
+struct CacheHeader {
+    version: u32,
+}
+
+/// On-disk representation of the cache: a version header followed by the base path the
+/// keys are relative to, the entries, and the (device, inode) of the files they were
+/// computed for. Caches written before file IDs or base paths were recorded simply have
+/// none.
+#[derive(Serialize, Deserialize)]
+struct CacheFile<M> {
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    base: Option<String>,
+    entries: M,
+    #[serde(default)]
+    file_ids: Vec<(u64, u64, String)>,
+}
+
+/// Limits on the size of the hash cache, enforced on [`HashCache::save`] by evicting the
+/// entries that were least recently seen in a scan.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheLimits {
+    /// Maximum number of entries.
+    pub max_entries: Option<usize>,
+    /// Maximum size of the compressed cache file in bytes.
+    pub max_size: Option<u64>,
+}
+
+/// How the hash cache is compressed on [`HashCache::save`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheCompression {
+    /// zstd level, from 1 (fastest) to 22 (smallest). Defaults to 9 for JSON caches and
+    /// 3 for binary ones, which are compact already.
+    pub level: Option<i32>,
+    /// Threads to compress with, all cores by default.
+    pub threads: Option<u32>,
+}
+
+/// How cache entries whose size matches the file but whose modification time doesn't
+/// are treated. On FAT, exFAT and some network filesystems, times are stored in local
+/// time or rounded to 2 seconds, so a change of time zone or daylight saving time shifts
+/// every file's modification time without the files changing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MtimePolicy {
+    /// Largest difference from the recorded modification time still taken as unchanged.
+    pub tolerance: Duration,
+    /// Use the recorded hash of entries whose modification time differs by more, and note
+    /// their files as suspect, to be hashed again once the other files are done, rather
+    /// than hashing them again right away.
+    pub rehash_suspect: bool,
+}
+
+/// Modification time, size and file ID of a cache file. Saves replace the file, so where
+/// file IDs are available, every save changes the stamp.
+type FileStamp = (SystemTime, u64, Option<(u64, u64)>);
+
+/// Flag of a binary cache entry whose hash is stored as 32 raw bytes.
+const BINARY_RAW_HASH: u8 = 1;
+/// Flag of a binary cache entry followed by the (device, inode) of its file.
+const BINARY_FILE_ID: u8 = 2;
+
+/// Infix of the name of a journal set aside by a save, followed by the time it was set
+/// aside, e.g. `check-file-dups-cache.json.zst.journal.merging-<nanoseconds>`.
+const SET_ASIDE_JOURNAL: &str = ".merging-";
+
+/// A line of the journal: `[path, mtime, size, hash]`, followed by `[device, inode]` when
+/// the file ID is known.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JournalEntry {
+    WithId(String, u64, u64, String, (u64, u64)),
+    Plain(String, u64, u64, String),
+}
+
 /// A thread-safe cache for storing file hash information.
 ///
 /// `HashCache` maintains a mapping from file paths to a tuple containing:
 /// - modification time (`mtime`: `u64`)
 /// - file size (`size`: `u64`)
 /// - hash (`hash`: `String`)
+/// - when the entry was last set or found valid (`last_seen`: `u64`)
 ///
 /// The cache is protected by a mutex for safe concurrent access, and can be
 /// serialized/deserialized to a compressed JSON file on disk.
+///
+/// Where inode numbers are stable, the (device, inode) of each file is recorded too, so a
+/// renamed or moved file whose size and modification time are unchanged keeps its hash
+/// instead of being read again.
+///
+/// Newly computed hashes are also appended to a journal file next to the cache file as
+/// soon as they are set, so that work is not lost if the process is killed before the
+/// next [`HashCache::save`]. The journal is replayed on load and truncated once its
+/// entries have been saved into the compressed snapshot.
+///
+/// Several processes can share a cache file. Saves hold an advisory lock on a `.lock`
+/// file next to it, and merge in the entries other processes saved since this one loaded
+/// the cache, so neither clobbers the other's work.
 pub struct HashCache {
     /// Path to the cache file on disk.
     pub cache_file: PathBuf,
-    /// The actual cache: path -> (mtime, size, hash).
-    cache: Arc<Mutex<HashMap<String, (u64, u64, String)>>>,
+    /// Path to the append-only journal of entries not yet saved to `cache_file`.
+    pub journal_file: PathBuf,
+    /// Path to the file locked while the cache is saved.
+    pub lock_file: PathBuf,
+    /// The actual cache: path -> (mtime, size, hash, last_seen).
+    cache: Arc<Mutex<CacheMap>>,
+    /// Cache key by (device, inode). Locked after `cache` when both are needed.
+    file_ids: Mutex<FileIdMap>,
+    /// Journal writer, opened lazily on the first new entry.
+    journal: Mutex<Option<LineWriter<fs::File>>>,
+    /// Set when the in-memory cache differs from the snapshot on disk.
+    dirty: AtomicBool,
+    /// Automatic checkpointing policy, if enabled.
+    checkpoint: Option<CheckpointPolicy>,
+    /// Size limits enforced on save.
+    limits: CacheLimits,
+    /// Format the cache is saved in.
+    format: CacheFormat,
+    /// Compression level and threads used on save.
+    compression: CacheCompression,
+    /// How entries with a changed modification time but the same size are treated.
+    mtime_policy: MtimePolicy,
+    /// Files whose hash was taken from an entry with a changed modification time, see
+    /// [`MtimePolicy::rehash_suspect`].
+    suspects: Mutex<HashSet<PathBuf>>,
+    /// Format of the cache file when it was loaded, if there was one.
+    loaded_format: Option<CacheFormat>,
+    /// Escaped base path the keys are relative to, see [`HashCache::with_base`].
+    base: Option<String>,
+    /// Stamp of the cache file as last loaded or saved, to notice saves by other processes.
+    saved_stamp: Mutex<Option<FileStamp>>,
+    /// Keys removed since the cache was last loaded or saved, which merging the saves of
+    /// other processes must not bring back.
+    removed: Mutex<HashSet<String>>,
+    /// Wait for other processes to finish saving, instead of failing.
+    wait_for_lock: bool,
+    /// Number of entries added since the last checkpoint.
+    pending_entries: AtomicUsize,
+    /// Time of the last checkpoint (or of loading the cache).
+    last_checkpoint: Mutex<Instant>,
+    /// Set while a checkpoint is being written, so only one worker saves at a time.
+    checkpointing: AtomicBool,
+    /// Set when the cache file was written by a newer version, so it is neither saved
+    /// over nor journaled next to.
+    read_only: bool,
+}
+
+/// A [`HashCache`] being loaded on another thread by [`HashCache::load_in_background`].
+///
+/// As a [`crate::hash_store::HashStore`], it waits for the load on the first lookup, so
+/// a scan lists its files while the cache is read and decoded, and only waits for what
+/// is left of the load when it starts hashing.
+pub struct LoadingCache {
+    thread: Mutex<Option<JoinHandle<(HashCache, Duration)>>>,
+    loaded: OnceLock<(Arc<HashCache>, Duration)>,
+}
+
+impl LoadingCache {
+    /// Waits for the cache to be loaded and returns it.
+    pub fn wait(&self) -> &Arc<HashCache> {
+        &self.loaded().0
+    }
+
+    /// Waits for the cache to be loaded and returns how long loading it took, most of
+    /// which the caller spent on other work.
+    pub fn load_time(&self) -> Duration {
+        self.loaded().1
+    }
+
+    fn loaded(&self) -> &(Arc<HashCache>, Duration) {
+        self.loaded.get_or_init(|| {
+            let thread = self
+                .thread
+                .lock()
+                .ok()
+                .and_then(|mut thread| thread.take())
+                .expect("the cache is loaded once");
+            let (cache, load_time) = thread
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            (Arc::new(cache), load_time)
+        })
+    }
+}
+
+impl Default for HashCache {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HashCache {
@@ -49,37 +505,758 @@ impl HashCache {
         let cache_file = std::env::current_dir()
             .expect("Failed to get current directory")
             .join(format!("{}-cache.json.zst", env!("CARGO_PKG_NAME")));
-        let mut cache = HashMap::new();
+        Self::load(cache_file)
+    }
 
-        if let Ok(compressed) = fs::read(&cache_file) {
-            let cache_size = fs::metadata(&cache_file).map(|m| m.len()).unwrap_or(0);
-            info!(
-                "Loading hash cache from: {} ({})",
-                cache_file.display(),
-                HumanBytes(cache_size)
-            );
-            let spinner = ProgressBar::new_spinner();
-            spinner.set_message("Loading hash cache...");
-            spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+    /// Returns the platform default directory for cache files, e.g.
+    /// `~/.cache/check-file-dups` on Linux, `~/Library/Caches/check-file-dups` on macOS
+    /// and `%LOCALAPPDATA%\check-file-dups` on Windows.
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")))
+    }
 
-            if let Ok(decoded_bytes) = decode_all(&compressed[..]) {
-                if let Ok(parsed) =
-                    serde_json::from_slice::<HashMap<String, (u64, u64, String)>>(&decoded_bytes)
-                {
-                    spinner.finish_and_clear();
-                    info!("Hash cache has {} entries", HumanCount(parsed.len() as u64));
-                    cache = parsed;
-                } else {
-                    warn!("Failed to parse decompressed hash cache, falling back");
+    /// Returns the cache file to use in `cache_dir` for scans with the given root.
+    ///
+    /// Cache keys are relative to the base path, so each base path gets its own cache file,
+    /// named after a short hash of the canonicalized base path. This lets scans of several
+    /// different roots share one cache directory without mixing up their entries.
+    pub fn file_for_base(cache_dir: &Path, root: &ScanRoot) -> PathBuf {
+        let digest = blake3::hash(root.base().to_string_lossy().as_bytes()).to_hex();
+        cache_dir.join(format!(
+            "{}-cache-{}.json.zst",
+            env!("CARGO_PKG_NAME"),
+            &digest[..16]
+        ))
+    }
+
+    /// Returns the file next to `cache_file` recording the contents of duplicates deleted
+    /// in scans using it (see [`crate::tombstones`]).
+    pub fn tombstones_file(cache_file: &Path) -> PathBuf {
+        Self::sibling_path(cache_file, ".tombstones")
+    }
+
+    /// Creates a `HashCache` backed by the given cache file.
+    ///
+    /// Behaves like [`HashCache::new`], but reads from and saves to `cache_file`
+    /// instead of the default location in the current working directory.
+    ///
+    /// A cache written by a newer version of the program is left as it is: the scan runs
+    /// without it, and nothing is saved over it.
+    pub fn load(cache_file: PathBuf) -> Self {
+        Self::load_showing(cache_file, true)
+    }
+
+    /// Loads the cache like [`HashCache::load`] on a new thread, so the caller can go on,
+    /// e.g. listing the files to scan, while a large cache is read and decoded. No
+    /// spinner is shown, since it would be drawn over the caller's output. `configure`
+    /// is applied to the loaded cache on that thread, e.g. to set its limits, and the
+    /// returned [`LoadingCache`] waits for it on first use.
+    pub fn load_in_background(
+        cache_file: PathBuf,
+        configure: impl FnOnce(Self) -> Self + Send + 'static,
+    ) -> LoadingCache {
+        let thread = std::thread::spawn(move || {
+            let start = Instant::now();
+            let cache = configure(Self::load_showing(cache_file, false));
+            (cache, start.elapsed())
+        });
+        LoadingCache {
+            thread: Mutex::new(Some(thread)),
+            loaded: OnceLock::new(),
+        }
+    }
+
+    /// Loads the cache like [`HashCache::load`], showing a spinner while it is decoded
+    /// with `spinner`.
+    fn load_showing(cache_file: PathBuf, spinner: bool) -> Self {
+        let saved_stamp = Self::stamp(&cache_file);
+        let mut read_only = false;
+        let snapshot = Self::read_snapshot(&cache_file, spinner).unwrap_or_else(|e| {
+            match e {
+                CheckFileDupsError::CacheCorrupted { .. } => {
+                    Self::backup_unreadable(&cache_file, &e)
+                }
+                CheckFileDupsError::CacheVersionUnsupported { .. } => {
+                    warn!("{}; running without it, and without saving over it", e);
+                    read_only = true;
                 }
+                _ => warn!("Failed to read hash cache ({:#}), starting fresh", e),
             }
-            spinner.finish_and_clear();
+            None
+        });
+        let mut cache = Self::from_snapshot(cache_file, snapshot, saved_stamp);
+        cache.read_only = read_only;
+        cache
+    }
+
+    /// Creates a `HashCache` backed by `cache_file` like [`HashCache::load`], but fails
+    /// instead of starting fresh if the file exists and cannot be read or decoded. The
+    /// file is left where it is.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CheckFileDupsError::CacheCorrupted`] if the file cannot be decoded,
+    /// [`CheckFileDupsError::CacheVersionUnsupported`] if it was written by a newer
+    /// version, and [`CheckFileDupsError::Io`] if it cannot be read.
+    pub fn try_load(cache_file: PathBuf) -> Result<Self> {
+        let saved_stamp = Self::stamp(&cache_file);
+        let snapshot = Self::read_snapshot(&cache_file, true)?;
+        Ok(Self::from_snapshot(cache_file, snapshot, saved_stamp))
+    }
+
+    /// Reads and decodes `cache_file`, or returns `None` if it doesn't exist, showing a
+    /// spinner while it is decoded with `spinner`.
+    fn read_snapshot(cache_file: &Path, spinner: bool) -> Result<Option<Snapshot>> {
+        let compressed = match fs::read(cache_file) {
+            Ok(compressed) => compressed,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!("No hash cache file found, starting fresh");
+                return Ok(None);
+            }
+            Err(e) => return Err(CheckFileDupsError::io(cache_file, e)),
+        };
+        info!(
+            "Loading hash cache from: {} ({})",
+            cache_file.display(),
+            Bytes(compressed.len() as u64)
+        );
+        let spinner = if spinner {
+            new_spinner("Loading hash cache...")
         } else {
-            warn!("No hash cache file found, starting fresh");
+            ProgressBar::hidden()
+        };
+        let snapshot = Self::decode_snapshot(cache_file, &compressed);
+        spinner.finish_and_clear();
+        let snapshot = snapshot?;
+        info!(
+            "Hash cache has {} entries",
+            Count(snapshot.entries.len() as u64)
+        );
+        Ok(Some(snapshot))
+    }
+
+    /// Creates a `HashCache` backed by `cache_file` with the entries of `snapshot`, read
+    /// from the file when it had `saved_stamp`, and those left in its journal.
+    fn from_snapshot(
+        cache_file: PathBuf,
+        snapshot: Option<Snapshot>,
+        saved_stamp: Option<FileStamp>,
+    ) -> Self {
+        let needs_upgrade = snapshot.as_ref().is_some_and(|snapshot| snapshot.outdated);
+        let loaded_format = snapshot.as_ref().map(|snapshot| snapshot.format);
+        let (mut cache, mut file_ids, base) = match snapshot {
+            Some(snapshot) => (snapshot.entries, snapshot.file_ids, snapshot.base),
+            None => (HashMap::new(), HashMap::new(), None),
+        };
+
+        let journal_file = Self::sibling_path(&cache_file, ".journal");
+        let lock_file = Self::sibling_path(&cache_file, ".lock");
+        let replayed = Self::replay_journals(&journal_file, &mut cache, &mut file_ids);
+        if replayed > 0 {
+            info!(
+                "Recovered {} unsaved entries from hash cache journal {}",
+                Count(replayed as u64),
+                journal_file.display()
+            );
         }
+
         Self {
             cache_file,
+            journal_file,
+            lock_file,
             cache: Arc::new(Mutex::new(cache)),
+            file_ids: Mutex::new(file_ids),
+            journal: Mutex::new(None),
+            dirty: AtomicBool::new(replayed > 0 || needs_upgrade),
+            checkpoint: None,
+            limits: CacheLimits::default(),
+            format: CacheFormat::default(),
+            compression: CacheCompression::default(),
+            mtime_policy: MtimePolicy::default(),
+            suspects: Mutex::new(HashSet::new()),
+            loaded_format,
+            base,
+            saved_stamp: Mutex::new(saved_stamp),
+            removed: Mutex::new(HashSet::new()),
+            wait_for_lock: true,
+            pending_entries: AtomicUsize::new(0),
+            last_checkpoint: Mutex::new(Instant::now()),
+            checkpointing: AtomicBool::new(false),
+            read_only: false,
+        }
+    }
+
+    /// Reads `cache_file` without loading it for use, to tell whether it can be read and
+    /// in which format. Unlike [`HashCache::load`], an unreadable file is left where it is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or decoded, e.g. since it is corrupt or
+    /// was written by a newer version.
+    pub fn inspect(cache_file: &Path) -> Result<CacheFileInfo> {
+        let compressed = fs::read(cache_file).at(cache_file)?;
+        let snapshot = Self::decode_snapshot(cache_file, &compressed)?;
+        Ok(CacheFileInfo {
+            entries: snapshot.entries.len(),
+            format: snapshot.format,
+            outdated: snapshot.outdated,
+        })
+    }
+
+    /// Enables automatic checkpointing: the cache is saved from within [`HashCache::set_hash`]
+    /// whenever the given policy's time interval or new-entry count is reached.
+    pub fn with_checkpoint(mut self, policy: CheckpointPolicy) -> Self {
+        self.checkpoint = Some(policy);
+        self
+    }
+
+    /// Sets the limits enforced on every [`HashCache::save`]. A cache already over them is
+    /// saved, and so trimmed, even if nothing else changed.
+    pub fn with_limits(mut self, limits: CacheLimits) -> Self {
+        self.limits = limits;
+        let file_size = fs::metadata(&self.cache_file).map_or(0, |m| m.len());
+        if limits.max_entries.is_some_and(|max| self.len() > max)
+            || limits.max_size.is_some_and(|max| file_size > max)
+        {
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+        self
+    }
+
+    /// Sets the format the cache is saved in. A cache file in the other format is
+    /// converted on the next [`HashCache::save`], even if nothing else changed.
+    pub fn with_format(mut self, format: CacheFormat) -> Self {
+        self.format = format;
+        if let Some(loaded) = self.loaded_format
+            && loaded != format
+        {
+            info!(
+                "Hash cache will be converted from {} to {} format when saved",
+                loaded, format
+            );
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+        self
+    }
+
+    /// Sets the zstd level and number of threads the cache is compressed with on
+    /// [`HashCache::save`]. Lower levels save large caches much faster, for a larger file.
+    pub fn with_compression(mut self, compression: CacheCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets how entries whose modification time changed but whose size didn't are
+    /// treated by [`HashCache::get_hash`].
+    pub fn with_mtime_policy(mut self, policy: MtimePolicy) -> Self {
+        self.mtime_policy = policy;
+        self
+    }
+
+    /// Returns the files whose hash was taken from a suspect entry since the last call,
+    /// sorted, so they can be hashed again.
+    pub fn take_suspects(&self) -> Vec<PathBuf> {
+        let Ok(mut suspects) = self.suspects.lock() else {
+            return Vec::new();
+        };
+        let mut suspects: Vec<PathBuf> = suspects.drain().collect();
+        suspects.sort();
+        suspects
+    }
+
+    /// Ties the cache to the base path of `root`, which its keys are relative to. Cache
+    /// files are named after their base path already, but two datasets can still hold the
+    /// same relative paths, e.g. `photos/img1.jpg`, so a cache recorded for another base
+    /// path (such as one copied or renamed by hand) is not trusted: its entries are
+    /// dropped, and the cache is saved for `root` from then on. Caches written before the
+    /// base path was recorded are taken as they are.
+    pub fn with_base(mut self, root: &ScanRoot) -> Self {
+        let base = escape_path(root.base());
+        if let Some(recorded) = self.base.as_deref()
+            && recorded != base
+        {
+            warn!(
+                "Hash cache {} was recorded for base path {}, not {}, ignoring its entries",
+                self.cache_file.display(),
+                unescape_path(recorded).display(),
+                root.base().display()
+            );
+            if let (Ok(mut cache), Ok(mut file_ids)) = (self.cache.lock(), self.file_ids.lock()) {
+                cache.clear();
+                file_ids.clear();
+            }
+            self.dirty.store(true, Ordering::SeqCst);
+        } else if self.base.is_none() && !self.is_empty() {
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+        self.base = Some(base);
+        self
+    }
+
+    /// Saves the cache if the checkpoint policy says it is due.
+    fn maybe_checkpoint(&self) {
+        let Some(policy) = self.checkpoint else {
+            return;
+        };
+        let pending = self.pending_entries.fetch_add(1, Ordering::SeqCst) + 1;
+        let due = policy.every_entries.is_some_and(|n| pending >= n)
+            || policy.interval.is_some_and(|interval| {
+                self.last_checkpoint
+                    .lock()
+                    .is_ok_and(|last| last.elapsed() >= interval)
+            });
+        if !due
+            || self
+                .checkpointing
+                .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                .is_err()
+        {
+            return;
+        }
+
+        info!(
+            "Checkpointing hash cache ({} new entries)",
+            Count(pending as u64)
+        );
+        if let Err(e) = self.save() {
+            warn!("Failed to checkpoint hash cache: {:#}", e);
+        }
+        self.pending_entries.store(0, Ordering::SeqCst);
+        if let Ok(mut last) = self.last_checkpoint.lock() {
+            *last = Instant::now();
+        }
+        self.checkpointing.store(false, Ordering::SeqCst);
+    }
+
+    /// Decodes the contents of `cache_file`, `compressed`, as [`HashCache::parse_snapshot`]
+    /// does.
+    fn decode_snapshot(cache_file: &Path, compressed: &[u8]) -> Result<Snapshot> {
+        Self::parse_snapshot(compressed).map_err(|e| match e.downcast::<UnsupportedVersion>() {
+            Ok(unsupported) => CheckFileDupsError::CacheVersionUnsupported {
+                path: cache_file.to_path_buf(),
+                version: unsupported.0,
+            },
+            Err(e) => CheckFileDupsError::CacheCorrupted {
+                path: cache_file.to_path_buf(),
+                reason: e.to_string(),
+            },
+        })
+    }
+
+    /// Decompresses and parses a cache snapshot in either [`CacheFormat`].
+    ///
+    /// JSON snapshots in older format versions are accepted too, including the legacy
+    /// format (a bare JSON map written before the version header was introduced), and
+    /// upgraded through [`MIGRATIONS`].
+    fn parse_snapshot(compressed: &[u8]) -> DecodeResult<Snapshot> {
+        if let Some(binary) = compressed.strip_prefix(BINARY_CACHE_MAGIC) {
+            return Self::decode_binary(binary);
+        }
+        let decoded_bytes = decode_all(compressed)?;
+        // Version 1 was a bare map, without a header
+        let version = serde_json::from_slice::<CacheHeader>(&decoded_bytes)
+            .map_or(1, |header| header.version);
+        if version > CACHE_FORMAT_VERSION {
+            return Err(UnsupportedVersion(version).into());
+        }
+        let file_ids = |ids: Vec<(u64, u64, String)>| {
+            ids.into_iter()
+                .map(|(device, inode, key)| ((device, inode), key))
+                .collect()
+        };
+        let file = if version < CACHE_FORMAT_VERSION {
+            info!("Upgrading hash cache from format version {version} to {CACHE_FORMAT_VERSION}");
+            let mut file = serde_json::from_slice(&decoded_bytes)?;
+            Self::migrate(&mut file, version)?;
+            serde_json::from_value::<CacheFile<CacheMap>>(file)?
+        } else {
+            serde_json::from_slice::<CacheFile<CacheMap>>(&decoded_bytes)?
+        };
+        Ok(Snapshot {
+            entries: file.entries,
+            file_ids: file_ids(file.file_ids),
+            format: CacheFormat::Json,
+            base: file.base,
+            outdated: version < CACHE_FORMAT_VERSION,
+        })
+    }
+
+    /// Parses a binary snapshot after [`BINARY_CACHE_MAGIC`]: see
+    /// [`HashCache::encode_binary`] for the layout.
+    fn decode_binary(data: &[u8]) -> DecodeResult<Snapshot> {
+        let mut header = BinaryReader::new(data, "Hash cache");
+        let version = header.u32()?;
+        if version > CACHE_FORMAT_VERSION {
+            return Err(UnsupportedVersion(version).into());
+        }
+        let body = decode_all(&data[4..])?;
+        let mut body = BinaryReader::new(&body, "Hash cache");
+        let base = if version >= 5 {
+            Some(body.string()?).filter(|base| !base.is_empty())
+        } else {
+            None
+        };
+        let count = body.u64()? as usize;
+        let mut entries = HashMap::with_capacity(count);
+        let mut file_ids = HashMap::new();
+        for _ in 0..count {
+            let key = body.string()?;
+            let mtime = body.u64()?;
+            let size = body.u64()?;
+            let last_seen = body.u64()?;
+            let flags = body.u8()?;
+            let hash = if flags & BINARY_RAW_HASH != 0 {
+                let mut raw = [0; 32];
+                raw.copy_from_slice(body.bytes(32)?);
+                blake3::Hash::from_bytes(raw).to_hex().to_string()
+            } else {
+                body.string()?
+            };
+            if flags & BINARY_FILE_ID != 0 {
+                file_ids.insert((body.u64()?, body.u64()?), key.clone());
+            }
+            entries.insert(key, (mtime, size, hash, last_seen));
+        }
+        Ok(Snapshot {
+            entries,
+            file_ids,
+            format: CacheFormat::Binary,
+            base,
+            outdated: version < CACHE_FORMAT_VERSION,
+        })
+    }
+
+    /// Upgrades a parsed JSON cache `file` of format `version` to the current one, through
+    /// each of [`MIGRATIONS`] from `version` up.
+    fn migrate(file: &mut serde_json::Value, version: u32) -> DecodeResult<()> {
+        for migration in MIGRATIONS.iter().skip_while(|step| step.from < version) {
+            debug!(
+                "Migrating hash cache from format version {}: {}",
+                migration.from, migration.change
+            );
+            (migration.migrate)(file).map_err(|e| {
+                format!(
+                    "failed to {} of format version {}: {}",
+                    migration.change, migration.from, e
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Moves an unreadable cache file aside so it can be inspected, instead of
+    /// silently overwriting it on the next save.
+    fn backup_unreadable(cache_file: &Path, error: &CheckFileDupsError) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut backup_name = cache_file.file_name().unwrap_or_default().to_os_string();
+        backup_name.push(format!(".corrupt-{}", timestamp));
+        let backup_file = cache_file.with_file_name(backup_name);
+        match fs::rename(cache_file, &backup_file) {
+            Ok(()) => warn!(
+                "Failed to read hash cache ({}), moved it to {} and starting fresh",
+                error,
+                backup_file.display()
+            ),
+            Err(e) => warn!(
+                "Failed to read hash cache ({}) and failed to back it up ({}), starting fresh",
+                error, e
+            ),
+        }
+    }
+
+    /// Returns the path of the file next to `cache_file` with `suffix` appended to its name,
+    /// such as its journal.
+    fn sibling_path(cache_file: &Path, suffix: &str) -> PathBuf {
+        let mut name = cache_file.file_name().unwrap_or_default().to_os_string();
+        name.push(suffix);
+        cache_file.with_file_name(name)
+    }
+
+    /// Returns the stamp of `cache_file`, if it exists.
+    fn stamp(cache_file: &Path) -> Option<FileStamp> {
+        let metadata = fs::metadata(cache_file).ok()?;
+        Some((
+            metadata.modified().ok()?,
+            metadata.len(),
+            file_id(&metadata),
+        ))
+    }
+
+    /// Makes [`HashCache::save`] fail if another process is saving the same cache, instead
+    /// of waiting for it to finish.
+    pub fn with_lock_wait(mut self, wait: bool) -> Self {
+        self.wait_for_lock = wait;
+        self
+    }
+
+    /// Takes the exclusive lock on [`HashCache::lock_file`], released when the returned
+    /// file is dropped.
+    fn lock(&self) -> Result<fs::File> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.lock_file)
+            .at(&self.lock_file)?;
+        match file.try_lock() {
+            Ok(()) => Ok(file),
+            Err(fs::TryLockError::WouldBlock) if self.wait_for_lock => {
+                info!("Waiting for another process to finish saving the hash cache...");
+                file.lock().at(&self.lock_file)?;
+                Ok(file)
+            }
+            Err(fs::TryLockError::WouldBlock) => Err(CheckFileDupsError::CacheLocked {
+                path: self.cache_file.clone(),
+            }),
+            Err(fs::TryLockError::Error(e)) => Err(CheckFileDupsError::io(&self.lock_file, e)),
+        }
+    }
+
+    /// Adds to `cache` the entries other processes saved to the cache file since this one
+    /// loaded or last saved it, and those in the journal that they haven't saved yet.
+    /// Entries removed here stay removed, and of two entries for the same file the one
+    /// seen more recently wins.
+    fn merge_others(&self, cache: &mut CacheMap, journals: &[PathBuf]) {
+        let Ok(removed) = self.removed.lock() else {
+            return;
+        };
+        let Ok(mut file_ids) = self.file_ids.lock() else {
+            return;
+        };
+        // The journal is shared and removed once saved, so save what other processes
+        // journaled too, for files this one hasn't hashed
+        let mut journaled = HashMap::new();
+        let mut journaled_ids = HashMap::new();
+        for journal in journals {
+            Self::replay_journal(journal, &mut journaled, &mut journaled_ids);
+        }
+        journaled.retain(|key, _| !cache.contains_key(key));
+        let mut merge = |entries: CacheMap, ids: FileIdMap| {
+            let mut merged = 0;
+            for (key, entry) in entries {
+                let ours_newer = cache.get(&key).is_some_and(|ours| ours.3 >= entry.3);
+                if ours_newer || removed.contains(&key) {
+                    continue;
+                }
+                cache.insert(key, entry);
+                merged += 1;
+            }
+            for (id, key) in ids {
+                file_ids.entry(id).or_insert(key);
+            }
+            merged
+        };
+
+        let stamp = Self::stamp(&self.cache_file);
+        if stamp.is_some() && self.saved_stamp.lock().is_ok_and(|saved| *saved != stamp) {
+            match fs::read(&self.cache_file)
+                .at(&self.cache_file)
+                .and_then(|compressed| Self::decode_snapshot(&self.cache_file, &compressed))
+            {
+                // Saved for another base path, see `with_base`
+                Ok(snapshot)
+                    if snapshot.base.is_some()
+                        && self.base.is_some()
+                        && snapshot.base != self.base => {}
+                Ok(snapshot) => {
+                    let merged = merge(snapshot.entries, snapshot.file_ids);
+                    if merged > 0 {
+                        info!(
+                            "Merged {} hash cache entries saved by another process",
+                            Count(merged as u64)
+                        );
+                    }
+                }
+                Err(e) => warn!(
+                    "Failed to read the hash cache saved by another process: {}",
+                    e
+                ),
+            }
+        }
+
+        merge(journaled, journaled_ids);
+    }
+
+    /// Applies the entries of the journal `journal_file`, and of those set aside by saves
+    /// that didn't finish, to `cache` and `file_ids`, returning the number of entries read.
+    fn replay_journals(
+        journal_file: &Path,
+        cache: &mut CacheMap,
+        file_ids: &mut FileIdMap,
+    ) -> usize {
+        let mut journals = Self::set_aside_journals(journal_file);
+        journals.push(journal_file.to_path_buf());
+        journals
+            .iter()
+            .map(|journal| Self::replay_journal(journal, cache, file_ids))
+            .sum()
+    }
+
+    /// Returns the journals set aside next to `journal_file` by [`HashCache::set_aside_journal`]
+    /// and not yet removed, oldest first.
+    fn set_aside_journals(journal_file: &Path) -> Vec<PathBuf> {
+        let mut prefix = journal_file.file_name().unwrap_or_default().to_os_string();
+        prefix.push(SET_ASIDE_JOURNAL);
+        let prefix = prefix.to_string_lossy().into_owned();
+        let dir = journal_file
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        let mut journals: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+            .map(|entry| journal_file.with_file_name(entry.file_name()))
+            .collect();
+        // Named after the time they were set aside, in nanoseconds of the same width
+        journals.sort();
+        journals
+    }
+
+    /// Sets the journal aside for a save, so that processes appending to it go on in a new
+    /// one, and returns the journals to save into the snapshot: it, and any set aside by
+    /// saves that didn't finish. Called while holding the lock file, so only one process
+    /// sets the journal aside at a time.
+    ///
+    /// Appends are written under a shared lock on the journal, after checking that it is
+    /// still the one at [`HashCache::journal_file`] (see [`HashCache::append_to_journal`]).
+    /// Once the journal is renamed, taking an exclusive lock on it waits for the appends in
+    /// progress, and later ones find it was set aside and reopen the journal, so no entry
+    /// is written to it after it is read.
+    fn set_aside_journal(&self) -> Vec<PathBuf> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        let set_aside = Self::sibling_path(
+            &self.journal_file,
+            &format!("{}{:020}", SET_ASIDE_JOURNAL, nanos),
+        );
+        match fs::rename(&self.journal_file, &set_aside) {
+            Ok(()) => {
+                if let Err(e) = fs::File::open(&set_aside).and_then(|file| file.lock()) {
+                    warn!("Failed to lock the hash cache journal set aside: {}", e);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to set the hash cache journal aside: {}", e),
+        }
+        Self::set_aside_journals(&self.journal_file)
+    }
+
+    /// Returns `true` if `file` is still the journal at `journal_file`, rather than one a
+    /// save set aside.
+    fn is_current_journal(file: &fs::File, journal_file: &Path) -> bool {
+        let (Ok(open), Ok(current)) = (file.metadata(), fs::metadata(journal_file)) else {
+            return false;
+        };
+        match (file_id(&open), file_id(&current)) {
+            (Some(open), Some(current)) => open == current,
+            // Without file IDs, a journal set aside is told apart by its length
+            _ => open.len() == current.len(),
+        }
+    }
+
+    /// Applies the entries of a journal file to `cache` and `file_ids`, returning the number
+    /// of entries read.
+    ///
+    /// Each journal line is a [`JournalEntry`]. A truncated or malformed line (e.g. from a
+    /// crash mid-write) ends the replay.
+    fn replay_journal(
+        journal_file: &Path,
+        cache: &mut CacheMap,
+        file_ids: &mut FileIdMap,
+    ) -> usize {
+        let Ok(file) = fs::File::open(journal_file) else {
+            return 0;
+        };
+        let mut replayed = 0;
+        let now = now();
+        for line in BufReader::new(file).lines() {
+            let Ok(line) = line else { break };
+            match serde_json::from_str::<JournalEntry>(&line) {
+                Ok(JournalEntry::WithId(path, mtime, size, hash, id)) => {
+                    file_ids.insert(id, path.clone());
+                    cache.insert(path, (mtime, size, hash, now));
+                    replayed += 1;
+                }
+                Ok(JournalEntry::Plain(path, mtime, size, hash)) => {
+                    cache.insert(path, (mtime, size, hash, now));
+                    replayed += 1;
+                }
+                Err(_) => {
+                    warn!("Ignoring truncated hash cache journal entry");
+                    break;
+                }
+            }
+        }
+        replayed
+    }
+
+    /// Appends an entry to the journal, opening it on first use, or again once a save set
+    /// it aside (see [`HashCache::set_aside_journal`]). Called after the entry is inserted,
+    /// without holding the cache lock.
+    fn append_to_journal(
+        &self,
+        path_str: &str,
+        mtime: u64,
+        size: u64,
+        hash: &str,
+        id: Option<(u64, u64)>,
+    ) {
+        if self.read_only {
+            return;
+        }
+        let Ok(mut journal) = self.journal.lock() else {
+            return;
+        };
+        let line = match id {
+            Some(id) => serde_json::to_string(&(path_str, mtime, size, hash, id)),
+            None => serde_json::to_string(&(path_str, mtime, size, hash)),
+        };
+        let Ok(line) = line else {
+            return;
+        };
+        for attempt in 0..3 {
+            if journal.is_none() {
+                match fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.journal_file)
+                {
+                    Ok(file) => *journal = Some(LineWriter::new(file)),
+                    Err(e) => {
+                        warn!(
+                            "Failed to open hash cache journal {}: {}",
+                            self.journal_file.display(),
+                            e
+                        );
+                        return;
+                    }
+                }
+            }
+            let Some(writer) = journal.as_mut() else {
+                return;
+            };
+            if let Err(e) = writer.get_ref().lock_shared() {
+                warn!("Failed to lock hash cache journal: {}", e);
+                return;
+            }
+            // Set aside by a save since it was opened: append to the new one instead
+            if attempt < 2 && !Self::is_current_journal(writer.get_ref(), &self.journal_file) {
+                let _ = writer.get_ref().unlock();
+                *journal = None;
+                continue;
+            }
+            if let Err(e) = writeln!(writer, "{}", line) {
+                warn!("Failed to write to hash cache journal: {}", e);
+            }
+            let _ = writer.get_ref().unlock();
+            return;
         }
     }
 
@@ -88,43 +1265,80 @@ impl HashCache {
     /// This method normalizes the file path for cross-platform compatibility,
     /// retrieves the file's current metadata (modification time and size),
     /// and checks if there is a cached entry for the file. If a cached entry
-    /// exists and both the modification time and file size match the current
-    /// file metadata, the cached hash is returned. Otherwise, returns `None`.
+    /// exists and both the modification time (within the tolerance of the
+    /// [`MtimePolicy`]) and file size match the current file metadata, the cached hash is
+    /// returned. With [`MtimePolicy::rehash_suspect`], it is also returned when only the
+    /// size matches, and the file is noted for [`HashCache::take_suspects`]. Otherwise, if the file was renamed
+    /// since it was hashed, the entry of its (device, inode) is copied to the new path
+    /// and returned when its modification time and size match. Otherwise, returns `None`.
     ///
     /// # Arguments
     ///
     /// * `file_path` - The path to the file whose hash is being queried.
-    /// * `base_path` - The base path to strip from the file path before caching.
+    /// * `root` - The scan root the cache key is derived from.
     ///
     /// # Returns
     ///
     /// * `Ok(Some(String))` containing the cached hash if valid.
     /// * `Ok(None)` if no valid cache entry exists.
     /// * `Err` if file metadata cannot be accessed.
-    pub fn get_hash(&self, file_path: &PathBuf, base_path: &PathBuf) -> Result<Option<String>> {
-        // Strip base path and normalize to use forward slashes for cross-platform compatibility
-        let relative_path = file_path.strip_prefix(base_path).unwrap_or(file_path);
-        let path_str = relative_path
-            .to_string_lossy()
-            .replace('\\', "/")
-            .trim_start_matches('/')
-            .to_string();
-        let metadata = file_path.metadata()?;
-        let current_mtime = metadata
-            .modified()?
-            .duration_since(std::time::UNIX_EPOCH)?
-            .as_secs();
+    pub fn get_hash(&self, file_path: &Path, root: &ScanRoot) -> Result<Option<String>> {
+        let path_str = root.key(file_path);
+        let metadata = file_path.metadata().at(file_path)?;
+        let current_mtime = mtime_secs(file_path, &metadata)?;
         let current_size = metadata.len();
 
-        if let Ok(cache) = self.cache.lock() {
-            if let Some((cached_mtime, cached_size, cached_hash)) = cache.get(&path_str) {
-                // Cache is valid if both mtime and size match
-                if *cached_mtime == current_mtime && *cached_size == current_size {
-                    return Ok(Some(cached_hash.clone()));
+        let Ok(mut cache) = self.cache.lock() else {
+            return Ok(None);
+        };
+        // Cache is valid if the size matches and the mtime is within the tolerance
+        let tolerance = self.mtime_policy.tolerance.as_secs();
+        let valid = |(mtime, size, _, _): &(u64, u64, String, u64)| {
+            mtime.abs_diff(current_mtime) <= tolerance && *size == current_size
+        };
+        let now = now();
+        if let Some(entry) = cache.get_mut(&path_str)
+            && entry.1 == current_size
+            && (valid(entry) || self.mtime_policy.rehash_suspect)
+        {
+            if !valid(entry) {
+                debug!(
+                    "Suspect cache entry, to hash again: {}",
+                    file_path.display()
+                );
+                if let Ok(mut suspects) = self.suspects.lock() {
+                    suspects.insert(file_path.to_path_buf());
                 }
             }
+            if now.saturating_sub(entry.3) >= LAST_SEEN_RESOLUTION {
+                entry.3 = now;
+                self.dirty.store(true, Ordering::SeqCst);
+            }
+            return Ok(Some(entry.2.clone()));
         }
-        Ok(None)
+
+        let Some(id) = file_id(&metadata) else {
+            return Ok(None);
+        };
+        let Ok(mut file_ids) = self.file_ids.lock() else {
+            return Ok(None);
+        };
+        let Some((mtime, size, hash, _)) = file_ids
+            .get(&id)
+            .and_then(|key| cache.get(key))
+            .filter(|entry| valid(entry))
+            .cloned()
+        else {
+            return Ok(None);
+        };
+        // Renamed since it was hashed: keep the old entry, in case the old path is a
+        // hardlink to the same file, and add one for the new path
+        file_ids.insert(id, path_str.clone());
+        cache.insert(path_str.clone(), (mtime, size, hash.clone(), now));
+        self.dirty.store(true, Ordering::SeqCst);
+        drop((cache, file_ids));
+        self.append_to_journal(&path_str, mtime, size, &hash, Some(id));
+        Ok(Some(hash))
     }
 
     /// Updates or inserts the hash for a given file in the cache.
@@ -137,31 +1351,33 @@ impl HashCache {
     /// # Arguments
     ///
     /// * `file_path` - The path to the file whose hash is being set.
-    /// * `base_path` - The base path to strip from the file path before caching.
+    /// * `root` - The scan root the cache key is derived from.
     /// * `hash` - The hash string to associate with the file.
     ///
     /// # Errors
     ///
     /// Returns an error if file metadata cannot be accessed.
-    pub fn set_hash(&self, file_path: &PathBuf, base_path: &PathBuf, hash: String) -> Result<()> {
-        // Strip base path and normalize to use forward slashes for cross-platform compatibility
-        let path_str = file_path
-            .strip_prefix(base_path)
-            .unwrap_or(file_path)
-            .to_string_lossy()
-            .replace('\\', "/")
-            .trim_start_matches('/')
-            .to_string();
-        let metadata = file_path.metadata()?;
-        let mtime = metadata
-            .modified()?
-            .duration_since(std::time::UNIX_EPOCH)?
-            .as_secs();
+    pub fn set_hash(&self, file_path: &Path, root: &ScanRoot, hash: String) -> Result<()> {
+        let path_str = root.key(file_path);
+        let metadata = file_path.metadata().at(file_path)?;
+        let mtime = mtime_secs(file_path, &metadata)?;
         let size = metadata.len();
+        let id = file_id(&metadata);
 
+        // Inserted before it is journaled, so a save in between either has the entry in
+        // its snapshot or leaves it to the journal, and journaled outside the cache lock,
+        // so other workers don't wait on the disk
         if let Ok(mut cache) = self.cache.lock() {
-            cache.insert(path_str, (mtime, size, hash));
+            if let Some(id) = id
+                && let Ok(mut file_ids) = self.file_ids.lock()
+            {
+                file_ids.insert(id, path_str.clone());
+            }
+            cache.insert(path_str.clone(), (mtime, size, hash.clone(), now()));
+            self.dirty.store(true, Ordering::SeqCst);
         }
+        self.append_to_journal(&path_str, mtime, size, &hash, id);
+        self.maybe_checkpoint();
         Ok(())
     }
 
@@ -170,99 +1386,1005 @@ impl HashCache {
     /// This method serializes the in-memory hash cache to JSON, compresses it using zstd,
     /// and writes it to the cache file. It displays a spinner while saving and logs the
     /// compressed file size. Use multiple threads for compression if multiple cores are available.
+    /// Once the snapshot is written the journal is truncated. If nothing changed since the
+    /// cache was loaded or last saved, the snapshot is left untouched.
     ///
     /// # Errors
     ///
     /// Returns an error if serialization, file creation, or compression fails.
     pub fn save(&self) -> Result<()> {
+        self.save_snapshot(true)
+    }
+
+    /// Saves the cache like [`HashCache::save`] on a new thread, so the caller can go on,
+    /// e.g. printing the report, while it is compressed. No spinner is shown, since it
+    /// would be drawn over the caller's output. Entries added meanwhile may miss the
+    /// snapshot, but stay in the journal. Join the returned thread before exiting.
+    pub fn save_in_background(self: &Arc<Self>) -> std::thread::JoinHandle<Result<()>> {
+        let cache = Arc::clone(self);
+        std::thread::spawn(move || cache.save_snapshot(false))
+    }
+
+    /// Saves the cache, showing a spinner while it is compressed with `spinner`.
+    fn save_snapshot(&self, spinner: bool) -> Result<()> {
+        if self.read_only {
+            info!("Hash cache was written by a newer version, skipping save");
+            return Ok(());
+        }
+        if !self.dirty.load(Ordering::SeqCst) && self.cache_file.exists() {
+            info!("Hash cache unchanged, skipping save");
+            return Ok(());
+        }
         let cache_path = &self.cache_file;
         let cache_size = fs::metadata(cache_path).map(|m| m.len()).unwrap_or(0);
         info!(
             "Saving hash cache to {} ({})",
             cache_path.display(),
-            HumanBytes(cache_size)
+            Bytes(cache_size)
         );
-        let spinner = ProgressBar::new_spinner();
-        spinner.set_message("Saving hash cache...");
-        spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+        let _lock = self.lock()?;
+        // Taken after the lock, so Ctrl+C doesn't wait for another process's save
+        let _exit = hold_exit();
+        let spinner = if spinner {
+            new_spinner("Saving hash cache...")
+        } else {
+            ProgressBar::hidden()
+        };
 
-        if let Ok(cache) = self.cache.lock() {
-            let content = serde_json::to_vec(&*cache)?;
-            let file = fs::File::create(&self.cache_file)?;
-            let mut encoder = Encoder::new(file, 9)?;
-            let threads = std::thread::available_parallelism()
-                .map(|n| n.get())
-                .unwrap_or(1);
-            if threads > 1 {
-                if let Err(err) = encoder.multithread(threads as u32) {
-                    info!(
-                        "Failed to enable multi-threaded compression ({}), using single thread",
-                        err
-                    );
+        if let Ok(mut cache) = self.cache.lock() {
+            let journals = self.set_aside_journal();
+            self.merge_others(&mut cache, &journals);
+            let mut evicted = Self::evict_oldest(&mut cache, self.limits.max_entries);
+            let mut compressed = self.encode(&cache).at(&self.cache_file)?;
+            if let Some(max_size) = self.limits.max_size {
+                while compressed.len() as u64 > max_size && !cache.is_empty() {
+                    // The compressed size is roughly proportional to the number of entries
+                    let keep = cache.len() as f64 * max_size as f64 / compressed.len() as f64;
+                    evicted += Self::evict_oldest(&mut cache, Some((keep * 0.9) as usize));
+                    compressed = self.encode(&cache).at(&self.cache_file)?;
                 }
             }
-            encoder.write_all(&content)?;
-            encoder.finish()?;
+            if evicted > 0 {
+                info!(
+                    "Evicted {} least recently seen hash cache entries to stay within the cache limits",
+                    Count(evicted as u64)
+                );
+            }
+            // Write to a temporary file and rename it over the cache file, so that an
+            // interrupted save never leaves a partially written cache behind
+            let mut tmp_name = self
+                .cache_file
+                .file_name()
+                .unwrap_or_default()
+                .to_os_string();
+            tmp_name.push(".tmp");
+            let tmp_file = self.cache_file.with_file_name(tmp_name);
+            let mut file = fs::File::create(&tmp_file).at(&tmp_file)?;
+            file.write_all(&compressed).at(&tmp_file)?;
+            file.sync_all().at(&tmp_file)?;
+            fs::rename(&tmp_file, &self.cache_file).at(&self.cache_file)?;
             let new_size = fs::metadata(&self.cache_file).map(|m| m.len()).unwrap_or(0);
             spinner.finish_and_clear();
-            info!("Hash cache compressed size: {}", HumanBytes(new_size));
+            info!("Hash cache compressed size: {}", Bytes(new_size));
+
+            // Everything in the journals set aside is now part of the snapshot
+            for journal in &journals {
+                if let Err(e) = fs::remove_file(journal)
+                    && e.kind() != std::io::ErrorKind::NotFound
+                {
+                    warn!("Failed to remove hash cache journal: {}", e);
+                }
+            }
+            if let Ok(mut saved_stamp) = self.saved_stamp.lock() {
+                *saved_stamp = Self::stamp(&self.cache_file);
+            }
+            if let Ok(mut removed) = self.removed.lock() {
+                removed.clear();
+            }
+            self.dirty.store(false, Ordering::SeqCst);
         }
         spinner.finish_and_clear();
         Ok(())
     }
 
+    /// Serializes and compresses `cache` into a snapshot in the configured format.
+    fn encode(&self, cache: &CacheMap) -> std::io::Result<Vec<u8>> {
+        // IDs whose entry has been pruned, removed or evicted are dropped
+        let file_ids: Vec<(u64, u64, String)> = self
+            .file_ids
+            .lock()
+            .map(|ids| {
+                ids.iter()
+                    .filter(|(_, key)| cache.contains_key(*key))
+                    .map(|(&(device, inode), key)| (device, inode, key.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        match self.format {
+            CacheFormat::Json => {
+                let content = serde_json::to_vec(&CacheFile {
+                    version: CACHE_FORMAT_VERSION,
+                    base: self.base.clone(),
+                    entries: cache,
+                    file_ids,
+                })?;
+                let level = self.compression.level.unwrap_or(9);
+                Self::compress(&content, level, self.compression.threads, Vec::new())
+            }
+            CacheFormat::Binary => {
+                Self::encode_binary(self.base.as_deref(), cache, &file_ids, self.compression)
+            }
+        }
+    }
+
+    /// Encodes `cache` in [`CacheFormat::Binary`]: [`BINARY_CACHE_MAGIC`] and the format
+    /// version, then a zstd-compressed body with the base path (empty if unknown), the
+    /// number of entries and, for each, its
+    /// key, mtime, size and last seen time, a flags byte, the hash (32 raw bytes for a
+    /// BLAKE3 hash, otherwise a string) and the (device, inode) if known. Strings are
+    /// UTF-8 prefixed with their length.
+    fn encode_binary(
+        base: Option<&str>,
+        cache: &CacheMap,
+        file_ids: &[(u64, u64, String)],
+        compression: CacheCompression,
+    ) -> std::io::Result<Vec<u8>> {
+        let ids: HashMap<&str, (u64, u64)> = file_ids
+            .iter()
+            .map(|(device, inode, key)| (key.as_str(), (*device, *inode)))
+            .collect();
+        let mut body = Vec::new();
+        put_str(&mut body, base.unwrap_or_default());
+        put_u64(&mut body, cache.len() as u64);
+        for (key, (mtime, size, hash, last_seen)) in cache {
+            put_str(&mut body, key);
+            put_u64(&mut body, *mtime);
+            put_u64(&mut body, *size);
+            put_u64(&mut body, *last_seen);
+            // Only hashes that read back identically are stored raw
+            let raw_hash = blake3::Hash::from_hex(hash)
+                .ok()
+                .filter(|raw| raw.to_hex().as_str() == hash);
+            let id = ids.get(key.as_str());
+            let mut flags = 0;
+            if raw_hash.is_some() {
+                flags |= BINARY_RAW_HASH;
+            }
+            if id.is_some() {
+                flags |= BINARY_FILE_ID;
+            }
+            body.push(flags);
+            match raw_hash {
+                Some(raw_hash) => body.extend_from_slice(raw_hash.as_bytes()),
+                None => put_str(&mut body, hash),
+            }
+            if let Some((device, inode)) = id {
+                put_u64(&mut body, *device);
+                put_u64(&mut body, *inode);
+            }
+        }
+        let mut snapshot = BINARY_CACHE_MAGIC.to_vec();
+        snapshot.extend_from_slice(&CACHE_FORMAT_VERSION.to_le_bytes());
+        // The binary form is compact already, so favor speed over ratio
+        let level = compression.level.unwrap_or(3);
+        Self::compress(&body, level, compression.threads, snapshot)
+    }
+
+    /// Compresses `content` at `level` using `threads` threads, or all cores, appending
+    /// it to `output`.
+    fn compress(
+        content: &[u8],
+        level: i32,
+        threads: Option<u32>,
+        output: Vec<u8>,
+    ) -> std::io::Result<Vec<u8>> {
+        let mut encoder = Encoder::new(output, level)?;
+        let threads = threads.map_or_else(
+            || {
+                std::thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(1)
+            },
+            |threads| threads as usize,
+        );
+        if threads > 1
+            && let Err(err) = encoder.multithread(threads as u32)
+        {
+            info!(
+                "Failed to enable multi-threaded compression ({}), using single thread",
+                err
+            );
+        }
+        encoder.write_all(content)?;
+        encoder.finish()
+    }
+
+    /// Removes the least recently seen entries of `cache` until at most `keep` are left,
+    /// returning the number removed.
+    fn evict_oldest(cache: &mut CacheMap, keep: Option<usize>) -> usize {
+        let Some(excess) = keep.and_then(|keep| cache.len().checked_sub(keep)) else {
+            return 0;
+        };
+        let mut by_age: Vec<(u64, String)> = cache
+            .iter()
+            .map(|(key, entry)| (entry.3, key.clone()))
+            .collect();
+        by_age.sort_unstable();
+        for (_, key) in by_age.into_iter().take(excess) {
+            cache.remove(&key);
+        }
+        excess
+    }
+
     /// Prunes the cache by removing entries for files that no longer exist on disk.
     ///
     /// This method iterates through all cached entries and checks if the corresponding
     /// file still exists. If a file is missing, its entry is removed from the cache.
+    /// When `older_than` is given, only missing entries whose recorded modification time
+    /// is older than that duration are removed, so that files on a temporarily unavailable
+    /// volume or recently moved files keep their entries for a while.
     /// Progress is displayed via a spinner, and statistics about pruned entries are logged.
     ///
     /// # Arguments
     ///
-    /// * `base_path` - The base path to resolve relative file paths against.
+    /// * `root` - The scan root to resolve cache keys against.
+    /// * `older_than` - Only prune entries whose recorded mtime is older than this.
+    ///
+    /// # Returns
+    ///
+    /// A [`PruneStats`] describing how many entries (and how many bytes of file data
+    /// they represented) were dropped.
     ///
     /// # Errors
     ///
     /// Returns an error if the cache lock cannot be acquired.
-    pub fn prune(&self, base_path: &PathBuf) -> Result<()> {
+    pub fn prune(&self, root: &ScanRoot, older_than: Option<Duration>) -> Result<PruneStats> {
         info!("Pruning cache entries for non-existent files...");
-        let spinner = ProgressBar::new_spinner();
-        spinner.set_message("Pruning hash cache...");
-        spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+        let spinner = new_spinner("Pruning hash cache...");
 
-        let mut removed_count = 0;
-        let total_count;
+        // Entries with a recorded mtime after this cutoff are kept even if missing
+        let cutoff = older_than.map(|age| {
+            SystemTime::now()
+                .checked_sub(age)
+                .unwrap_or(UNIX_EPOCH)
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
 
-        if let Ok(mut cache) = self.cache.lock() {
-            total_count = cache.len();
-            let mut to_remove = Vec::new();
-
-            // Collect keys to remove (can't modify while iterating)
-            for (path_str, _) in cache.iter() {
-                let file_path = base_path.join(path_str);
-                if !file_path.exists() {
-                    to_remove.push(path_str.clone());
+        let mut stats = PruneStats::default();
+        if let Ok(mut cache) = self.cache.lock()
+            && let Ok(mut removed) = self.removed.lock()
+        {
+            stats.total_entries = cache.len();
+            cache.retain(|path_str, (mtime, size, _, _)| {
+                if cutoff.is_some_and(|cutoff| *mtime >= cutoff) {
+                    return true;
                 }
-            }
-
-            // Remove the collected keys
-            for path_str in to_remove {
-                cache.remove(&path_str);
-                removed_count += 1;
+                if root.resolve(path_str).exists() {
+                    return true;
+                }
+                stats.removed_entries += 1;
+                stats.removed_bytes += *size;
+                removed.insert(path_str.clone());
+                false
+            });
+            if stats.removed_entries > 0 {
+                self.dirty.store(true, Ordering::SeqCst);
             }
         } else {
             spinner.finish_and_clear();
-            return Err(anyhow::anyhow!("Failed to acquire cache lock for pruning"));
+            return Err(CheckFileDupsError::CacheLocked {
+                path: self.cache_file.clone(),
+            });
         }
 
         spinner.finish_and_clear();
         info!(
-            "Pruned {} of {} cache entries ({:.1}% removed)",
-            HumanCount(removed_count as u64),
-            HumanCount(total_count as u64),
-            (removed_count as f64 / total_count as f64) * 100.0
+            "Pruned {} of {} cache entries ({:.1}% removed), representing {} of file data",
+            Count(stats.removed_entries as u64),
+            Count(stats.total_entries as u64),
+            stats.removed_percent(),
+            Bytes(stats.removed_bytes)
         );
 
-        Ok(())
+        Ok(stats)
+    }
+
+    /// Returns summary statistics about the cache contents.
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = CacheStats::default();
+        if let Ok(cache) = self.cache.lock() {
+            for (path, (mtime, size, _, _)) in cache.iter() {
+                stats.entries += 1;
+                stats.total_size += size;
+                if stats
+                    .oldest
+                    .as_ref()
+                    .is_none_or(|(_, oldest)| mtime < oldest)
+                {
+                    stats.oldest = Some((path.clone(), *mtime));
+                }
+                if stats
+                    .newest
+                    .as_ref()
+                    .is_none_or(|(_, newest)| mtime > newest)
+                {
+                    stats.newest = Some((path.clone(), *mtime));
+                }
+            }
+        }
+        stats
+    }
+
+    /// Returns all cache entries, sorted by path.
+    pub fn entries(&self) -> Vec<CacheEntry> {
+        let mut entries: Vec<CacheEntry> = self
+            .cache
+            .lock()
+            .map(|cache| {
+                cache
+                    .iter()
+                    .map(|(path, (mtime, size, hash, last_seen))| CacheEntry {
+                        path: path.clone(),
+                        mtime: *mtime,
+                        size: *size,
+                        hash: hash.clone(),
+                        last_seen: *last_seen,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        entries
+    }
+
+    /// Returns the raw cache entry for a file, without checking it against the file on disk.
+    pub fn get_entry(&self, file_path: &Path, root: &ScanRoot) -> Option<CacheEntry> {
+        let path = root.key(file_path);
+        let cache = self.cache.lock().ok()?;
+        let (mtime, size, hash, last_seen) = cache.get(&path)?;
+        Some(CacheEntry {
+            mtime: *mtime,
+            size: *size,
+            hash: hash.clone(),
+            last_seen: *last_seen,
+            path,
+        })
+    }
+
+    /// Removes the cache entry for a file, returning `true` if an entry was removed.
+    pub fn remove(&self, file_path: &Path, root: &ScanRoot) -> bool {
+        let path = root.key(file_path);
+        let removed = self
+            .cache
+            .lock()
+            .is_ok_and(|mut cache| cache.remove(&path).is_some());
+        if removed {
+            self.dirty.store(true, Ordering::SeqCst);
+            if let Ok(mut keys) = self.removed.lock() {
+                keys.insert(path);
+            }
+        }
+        removed
+    }
+
+    /// Merges the entries saved in another cache file, such as one built on another machine
+    /// against the same files, into this cache, including any left in its journal. Of two
+    /// entries for the same key, the one with the newer modification time wins, or the one
+    /// seen more recently if both have the same. File IDs are left out, since they only mean
+    /// something on the machine that recorded them. Returns the number of entries added
+    /// or replaced.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `file` cannot be read or is not a hash cache.
+    pub fn merge_file(&self, file: &Path) -> Result<usize> {
+        let compressed = fs::read(file).at(file)?;
+        let mut entries = Self::decode_snapshot(file, &compressed)?.entries;
+        Self::replay_journals(
+            &Self::sibling_path(file, ".journal"),
+            &mut entries,
+            &mut HashMap::new(),
+        );
+
+        let mut merged = 0;
+        if let Ok(mut cache) = self.cache.lock() {
+            for (key, entry) in entries {
+                let newer = cache
+                    .get(&key)
+                    .is_none_or(|ours| (entry.0, entry.3) > (ours.0, ours.3));
+                if newer {
+                    cache.insert(key, entry);
+                    merged += 1;
+                }
+            }
+        }
+        if merged > 0 {
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+        Ok(merged)
+    }
+
+    /// Returns the number of entries currently in the cache.
+    pub fn len(&self) -> usize {
+        self.cache.lock().map(|cache| cache.len()).unwrap_or(0)
+    }
+
+    /// Returns `true` if the cache has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Returns the modification time of `file_path`, with `metadata`, in seconds since the
+/// epoch.
+fn mtime_secs(file_path: &Path, metadata: &fs::Metadata) -> Result<u64> {
+    let modified = metadata.modified().at(file_path)?;
+    let since_epoch = modified
+        .duration_since(UNIX_EPOCH)
+        .map_err(std::io::Error::other)
+        .at(file_path)?;
+    Ok(since_epoch.as_secs())
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root(dir: &Path) -> ScanRoot {
+        ScanRoot::for_base(dir)
+    }
+
+    fn write_file(dir: &Path, name: &str, content: &[u8], age: Duration) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+        path
+    }
+
+    #[test]
+    fn prune_removes_missing_entries_and_counts_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = HashCache::load(dir.path().join("cache.json.zst"));
+        let kept = write_file(dir.path(), "kept", b"keep", Duration::ZERO);
+        let gone = write_file(dir.path(), "gone", b"gone!", Duration::ZERO);
+        cache
+            .set_hash(&kept, &root(dir.path()), "a".into())
+            .unwrap();
+        cache
+            .set_hash(&gone, &root(dir.path()), "b".into())
+            .unwrap();
+        fs::remove_file(&gone).unwrap();
+
+        let stats = cache.prune(&root(dir.path()), None).unwrap();
+
+        assert_eq!(
+            stats,
+            PruneStats {
+                total_entries: 2,
+                removed_entries: 1,
+                removed_bytes: 5,
+            }
+        );
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get_hash(&kept, &root(dir.path())).unwrap().is_some());
+    }
+
+    #[test]
+    fn prune_older_than_keeps_recent_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = HashCache::load(dir.path().join("cache.json.zst"));
+        let day = Duration::from_secs(24 * 60 * 60);
+        let recent = write_file(dir.path(), "recent", b"1", day);
+        let old = write_file(dir.path(), "old", b"22", day * 30);
+        cache
+            .set_hash(&recent, &root(dir.path()), "a".into())
+            .unwrap();
+        cache.set_hash(&old, &root(dir.path()), "b".into()).unwrap();
+        fs::remove_file(&recent).unwrap();
+        fs::remove_file(&old).unwrap();
+
+        let stats = cache.prune(&root(dir.path()), Some(day * 7)).unwrap();
+
+        assert_eq!(stats.removed_entries, 1);
+        assert_eq!(stats.removed_bytes, 2);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn prune_empty_cache_reports_zero_percent() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = HashCache::load(dir.path().join("cache.json.zst"));
+
+        let stats = cache.prune(&root(dir.path()), None).unwrap();
+
+        assert_eq!(stats, PruneStats::default());
+        assert_eq!(stats.removed_percent(), 0.0);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache.json.zst");
+        let file = write_file(dir.path(), "file", b"data", Duration::ZERO);
+        let cache = HashCache::load(cache_file.clone());
+        cache
+            .set_hash(&file, &root(dir.path()), "abc".into())
+            .unwrap();
+        cache.save().unwrap();
+
+        let reloaded = HashCache::load(cache_file);
+
+        assert_eq!(
+            reloaded
+                .get_hash(&file, &root(dir.path()))
+                .unwrap()
+                .as_deref(),
+            Some("abc")
+        );
+        assert!(!reloaded.journal_file.exists());
+    }
+
+    #[test]
+    fn loads_in_background() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache.json.zst");
+        let file = write_file(dir.path(), "file", b"data", Duration::ZERO);
+        let cache = HashCache::load(cache_file.clone());
+        cache
+            .set_hash(&file, &root(dir.path()), "abc".into())
+            .unwrap();
+        cache.save().unwrap();
+
+        let loading = HashCache::load_in_background(cache_file, |cache| {
+            cache.with_format(CacheFormat::Binary)
+        });
+
+        let reloaded = loading.wait();
+        assert_eq!(
+            reloaded
+                .get_hash(&file, &root(dir.path()))
+                .unwrap()
+                .as_deref(),
+            Some("abc")
+        );
+        assert_eq!(reloaded.format, CacheFormat::Binary);
+        assert!(Arc::ptr_eq(reloaded, loading.wait()));
+    }
+
+    #[test]
+    fn saves_in_background_at_the_configured_level() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache.json.zst");
+        let file = write_file(dir.path(), "file", b"data", Duration::ZERO);
+        let cache = Arc::new(HashCache::load(cache_file.clone()).with_compression(
+            CacheCompression {
+                level: Some(1),
+                threads: Some(1),
+            },
+        ));
+        cache
+            .set_hash(&file, &root(dir.path()), "abc".into())
+            .unwrap();
+        cache.save_in_background().join().unwrap().unwrap();
+
+        let reloaded = HashCache::load(cache_file);
+        assert_eq!(
+            reloaded
+                .get_hash(&file, &root(dir.path()))
+                .unwrap()
+                .as_deref(),
+            Some("abc")
+        );
+        assert!(!reloaded.journal_file.exists());
+    }
+
+    #[test]
+    fn checkpoint_policy_parsing() {
+        assert_eq!(
+            "10m,5000".parse::<CheckpointPolicy>().ok(),
+            Some(CheckpointPolicy {
+                interval: Some(Duration::from_secs(600)),
+                every_entries: Some(5000),
+            })
+        );
+        assert_eq!(
+            "30s".parse::<CheckpointPolicy>().unwrap().interval,
+            Some(Duration::from_secs(30))
+        );
+        assert!(matches!(
+            "".parse::<CheckpointPolicy>(),
+            Err(CheckFileDupsError::InvalidOptions(_))
+        ));
+        assert!("0".parse::<CheckpointPolicy>().is_err());
+        assert!("10x".parse::<CheckpointPolicy>().is_err());
+    }
+
+    #[test]
+    fn checkpoint_saves_after_entry_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache.json.zst");
+        let cache = HashCache::load(cache_file.clone()).with_checkpoint(CheckpointPolicy {
+            interval: None,
+            every_entries: Some(2),
+        });
+        let a = write_file(dir.path(), "a", b"a", Duration::ZERO);
+        let b = write_file(dir.path(), "b", b"b", Duration::ZERO);
+
+        cache.set_hash(&a, &root(dir.path()), "a".into()).unwrap();
+        assert!(!cache_file.exists());
+        cache.set_hash(&b, &root(dir.path()), "b".into()).unwrap();
+        assert!(cache_file.exists());
+        assert!(!cache.journal_file.exists());
+    }
+
+    #[test]
+    fn legacy_cache_format_is_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache.json.zst");
+        let file = write_file(dir.path(), "file", b"data", Duration::ZERO);
+        let mtime = file
+            .metadata()
+            .unwrap()
+            .modified()
+            .unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let legacy = serde_json::json!({ "file": [mtime, 4, "abc"] });
+        let compressed = zstd::encode_all(&serde_json::to_vec(&legacy).unwrap()[..], 3).unwrap();
+        fs::write(&cache_file, compressed).unwrap();
+
+        let cache = HashCache::load(cache_file);
+
+        assert_eq!(
+            cache.get_hash(&file, &root(dir.path())).unwrap().as_deref(),
+            Some("abc")
+        );
+    }
+
+    #[test]
+    fn old_cache_versions_are_migrated_step_by_step() {
+        let steps: Vec<u32> = MIGRATIONS.iter().map(|step| step.from).collect();
+        assert_eq!(steps, (1..CACHE_FORMAT_VERSION).collect::<Vec<_>>());
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache.json.zst");
+        let file = write_file(dir.path(), "100%.txt", b"data", Duration::ZERO);
+        let mtime = file
+            .metadata()
+            .unwrap()
+            .modified()
+            .unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let v2 = serde_json::json!({
+            "version": 2,
+            "entries": { "100%.txt": [mtime, 4, "abc"] },
+            "file_ids": [[1, 2, "100%.txt"]],
+        });
+        let compressed = zstd::encode_all(&serde_json::to_vec(&v2).unwrap()[..], 3).unwrap();
+        fs::write(&cache_file, compressed).unwrap();
+        assert!(HashCache::inspect(&cache_file).unwrap().outdated);
+
+        let cache = HashCache::load(cache_file.clone());
+        assert_eq!(
+            cache.get_hash(&file, &root(dir.path())).unwrap().as_deref(),
+            Some("abc")
+        );
+        assert!(cache.get_entry(&file, &root(dir.path())).unwrap().last_seen > 0);
+        cache.save().unwrap();
+        let info = HashCache::inspect(&cache_file).unwrap();
+        assert_eq!((info.entries, info.outdated), (1, false));
+    }
+
+    #[test]
+    fn save_evicts_least_recently_seen_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache.json.zst");
+        let cache = HashCache::load(cache_file.clone());
+        for name in ["old", "older", "new"] {
+            let file = write_file(dir.path(), name, name.as_bytes(), Duration::ZERO);
+            cache
+                .set_hash(&file, &root(dir.path()), name.into())
+                .unwrap();
+        }
+        if let Ok(mut entries) = cache.cache.lock() {
+            entries.get_mut("old").unwrap().3 -= 2 * LAST_SEEN_RESOLUTION;
+            entries.get_mut("older").unwrap().3 -= 3 * LAST_SEEN_RESOLUTION;
+        }
+        // Seeing an entry again refreshes it
+        let old = dir.path().join("old");
+        assert!(cache.get_hash(&old, &root(dir.path())).unwrap().is_some());
+
+        let cache = cache.with_limits(CacheLimits {
+            max_entries: Some(2),
+            max_size: None,
+        });
+        cache.save().unwrap();
+
+        let reloaded = HashCache::load(cache_file);
+        let keys: Vec<String> = reloaded.entries().into_iter().map(|e| e.path).collect();
+        assert_eq!(keys, ["new", "old"]);
+    }
+
+    #[test]
+    fn caches_of_other_base_paths_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache.json.zst");
+        let (photos, backup) = (dir.path().join("photos"), dir.path().join("backup"));
+        for base in [&photos, &backup] {
+            fs::create_dir(base).unwrap();
+        }
+        let file = write_file(&photos, "img1.jpg", b"photo", Duration::ZERO);
+        write_file(&backup, "img1.jpg", b"other", Duration::ZERO);
+        for format in [CacheFormat::Json, CacheFormat::Binary] {
+            let cache = HashCache::load(cache_file.clone())
+                .with_base(&root(&photos))
+                .with_format(format);
+            cache.set_hash(&file, &root(&photos), "abc".into()).unwrap();
+            cache.save().unwrap();
+
+            let reloaded = HashCache::load(cache_file.clone()).with_base(&root(&photos));
+            assert_eq!(reloaded.len(), 1);
+            let other = HashCache::load(cache_file.clone()).with_base(&root(&backup));
+            assert!(other.is_empty());
+            // Nothing is left for the next format
+            other.save().unwrap();
+        }
+    }
+
+    #[test]
+    fn binary_format_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache.json.zst");
+        let json = HashCache::load(cache_file.clone());
+        let hash = blake3::hash(b"data").to_hex().to_string();
+        for (name, hash) in [("file", hash.as_str()), ("other", "not-blake3")] {
+            let file = write_file(dir.path(), name, b"data", Duration::ZERO);
+            json.set_hash(&file, &root(dir.path()), hash.into())
+                .unwrap();
+        }
+        json.save().unwrap();
+
+        // A JSON cache is read and converted to binary
+        let binary = HashCache::load(cache_file.clone()).with_format(CacheFormat::Binary);
+        binary.save().unwrap();
+        assert!(
+            fs::read(&cache_file)
+                .unwrap()
+                .starts_with(BINARY_CACHE_MAGIC)
+        );
+
+        let reloaded = HashCache::load(cache_file);
+        assert_eq!(reloaded.entries(), json.entries());
+        assert_eq!(
+            reloaded.file_ids.lock().unwrap().len(),
+            if cfg!(unix) { 2 } else { 0 }
+        );
+    }
+
+    #[test]
+    fn concurrent_saves_are_merged() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache.json.zst");
+        let root = root(dir.path());
+        let setup = HashCache::load(cache_file.clone());
+        let removed = write_file(dir.path(), "removed", b"1", Duration::ZERO);
+        setup.set_hash(&removed, &root, "1".into()).unwrap();
+        setup.save().unwrap();
+
+        let first = HashCache::load(cache_file.clone());
+        let second = HashCache::load(cache_file.clone());
+        let a = write_file(dir.path(), "a", b"a", Duration::ZERO);
+        let b = write_file(dir.path(), "b", b"b", Duration::ZERO);
+        second.set_hash(&b, &root, "b".into()).unwrap();
+        second.save().unwrap();
+        first.set_hash(&a, &root, "a".into()).unwrap();
+        first.remove(&removed, &root);
+        first.save().unwrap();
+
+        let keys: Vec<String> = HashCache::load(cache_file.clone())
+            .entries()
+            .into_iter()
+            .map(|e| e.path)
+            .collect();
+        assert_eq!(keys, ["a", "b"]);
+
+        let locked = fs::File::create(dir.path().join("cache.json.zst.lock")).unwrap();
+        locked.lock().unwrap();
+        let waiting = HashCache::load(cache_file).with_lock_wait(false);
+        waiting.set_hash(&a, &root, "a2".into()).unwrap();
+        assert!(waiting.save().is_err());
+    }
+
+    #[test]
+    fn merge_keeps_newest_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let entry = |mtime, hash: &str| (mtime, 1, hash.to_string(), 0);
+        let write = |name: &str, entries: CacheMap| {
+            let cache = HashCache::load(dir.path().join(name));
+            *cache.cache.lock().unwrap() = entries;
+            cache.dirty.store(true, Ordering::SeqCst);
+            cache.save().unwrap();
+        };
+        write(
+            "nas.json.zst",
+            HashMap::from([("a".into(), entry(2, "new")), ("b".into(), entry(1, "b"))]),
+        );
+        write(
+            "laptop.json.zst",
+            HashMap::from([("a".into(), entry(1, "old")), ("c".into(), entry(1, "c"))]),
+        );
+
+        let merged = HashCache::load(dir.path().join("merged.json.zst"));
+        assert_eq!(
+            merged.merge_file(&dir.path().join("nas.json.zst")).unwrap(),
+            2
+        );
+        assert_eq!(
+            merged
+                .merge_file(&dir.path().join("laptop.json.zst"))
+                .unwrap(),
+            1
+        );
+        let hashes: Vec<String> = merged.entries().into_iter().map(|e| e.hash).collect();
+        assert_eq!(hashes, ["new", "b", "c"]);
+        assert!(merged.merge_file(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn corrupt_cache_is_backed_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache.json.zst");
+        fs::write(&cache_file, b"not a cache").unwrap();
+
+        let error = HashCache::try_load(cache_file.clone()).err().unwrap();
+        assert!(
+            matches!(&error, CheckFileDupsError::CacheCorrupted { path, .. } if *path == cache_file)
+        );
+        assert_eq!(fs::read(&cache_file).unwrap(), b"not a cache");
+        let cache = HashCache::load(cache_file.clone());
+
+        assert!(cache.is_empty());
+        assert!(!cache_file.exists());
+        let backups: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().contains(".corrupt-"))
+            .collect();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(fs::read(backups[0].path()).unwrap(), b"not a cache");
+    }
+
+    #[test]
+    fn cache_of_newer_version_is_left_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache.json.zst");
+        let contents = zstd::encode_all(&br#"{"version":99,"entries":{}}"#[..], 0).unwrap();
+        fs::write(&cache_file, &contents).unwrap();
+        let file = write_file(dir.path(), "file", b"data", Duration::ZERO);
+
+        let error = HashCache::try_load(cache_file.clone()).err().unwrap();
+        assert!(matches!(
+            error,
+            CheckFileDupsError::CacheVersionUnsupported { version: 99, .. }
+        ));
+        let cache = HashCache::load(cache_file.clone());
+        assert!(cache.is_empty());
+        cache
+            .set_hash(&file, &root(dir.path()), "abc".into())
+            .unwrap();
+        cache.save().unwrap();
+
+        assert_eq!(fs::read(&cache_file).unwrap(), contents);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn renamed_files_keep_their_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache.json.zst");
+        let file = write_file(dir.path(), "file", b"data", Duration::ZERO);
+        let cache = HashCache::load(cache_file.clone());
+        cache
+            .set_hash(&file, &root(dir.path()), "abc".into())
+            .unwrap();
+        cache.save().unwrap();
+        let renamed = dir.path().join("renamed");
+        fs::rename(&file, &renamed).unwrap();
+
+        let reloaded = HashCache::load(cache_file);
+
+        assert_eq!(
+            reloaded
+                .get_hash(&renamed, &root(dir.path()))
+                .unwrap()
+                .as_deref(),
+            Some("abc")
+        );
+        assert!(reloaded.get_entry(&renamed, &root(dir.path())).is_some());
+        // A changed file isn't matched by its inode
+        write_file(dir.path(), "renamed", b"other", Duration::ZERO);
+        fs::rename(&renamed, dir.path().join("moved")).unwrap();
+        assert_eq!(
+            reloaded
+                .get_hash(&dir.path().join("moved"), &root(dir.path()))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn unsaved_entries_are_recovered_from_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache.json.zst");
+        let file = write_file(dir.path(), "file", b"data", Duration::ZERO);
+        let cache = HashCache::load(cache_file.clone());
+        cache
+            .set_hash(&file, &root(dir.path()), "abc".into())
+            .unwrap();
+        // Simulate a crash: drop the cache without saving
+        drop(cache);
+
+        let reloaded = HashCache::load(cache_file);
+
+        assert_eq!(
+            reloaded
+                .get_hash(&file, &root(dir.path()))
+                .unwrap()
+                .as_deref(),
+            Some("abc")
+        );
+    }
+
+    #[test]
+    fn appends_after_another_process_saved_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache.json.zst");
+        let files: Vec<PathBuf> = ["a", "b", "c"]
+            .iter()
+            .map(|name| write_file(dir.path(), name, name.as_bytes(), Duration::ZERO))
+            .collect();
+        // Two processes sharing the cache file and its journal
+        let saving = HashCache::load(cache_file.clone());
+        let other = HashCache::load(cache_file.clone());
+        saving
+            .set_hash(&files[0], &root(dir.path()), "a".into())
+            .unwrap();
+        other
+            .set_hash(&files[1], &root(dir.path()), "b".into())
+            .unwrap();
+        saving.save().unwrap();
+        // The other process still has the journal set aside by the save open
+        other
+            .set_hash(&files[2], &root(dir.path()), "c".into())
+            .unwrap();
+        drop(other);
+
+        let reloaded = HashCache::load(cache_file);
+
+        for (file, hash) in files.iter().zip(["a", "b", "c"]) {
+            assert_eq!(
+                reloaded
+                    .get_hash(file, &root(dir.path()))
+                    .unwrap()
+                    .as_deref(),
+                Some(hash)
+            );
+        }
+        assert!(HashCache::set_aside_journals(&reloaded.journal_file).is_empty());
     }
 }
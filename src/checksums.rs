@@ -0,0 +1,325 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+
+use crate::cache::HashCache;
+use crate::paths::unescape_path;
+use crate::scan_root::ScanRoot;
+use crate::scanner::read_buffer_size;
+use crate::units::Count;
+
+/// Checksum manifest formats supported by `cache export`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ChecksumFormat {
+    /// BLAKE3 hashes in `b3sum` format, taken directly from the cache
+    Blake3,
+    /// SHA-256 hashes in `sha256sum` format. The cache only stores BLAKE3 hashes,
+    /// so every file is read again to compute its SHA-256 hash
+    Sha256sum,
+}
+
+/// Formats a manifest line in the `<hash>  <path>` format used by `b3sum` and `sha256sum`.
+///
+/// Like GNU coreutils, paths containing a backslash or newline are escaped and the line
+/// is prefixed with a backslash.
+pub fn format_line(hash: &str, path: &str) -> String {
+    if path.contains(['\\', '\n', '\r']) {
+        let escaped = path
+            .replace('\\', "\\\\")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r");
+        format!("\\{}  {}", hash, escaped)
+    } else {
+        format!("{}  {}", hash, path)
+    }
+}
+
+/// Parses a manifest line written by `b3sum`, `sha256sum` or [`format_line`].
+///
+/// Returns `(hash, path)`, or `None` for blank, comment or malformed lines. Both text
+/// (`hash  path`) and binary (`hash *path`) mode lines are accepted.
+pub fn parse_line(line: &str) -> Option<(String, String)> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    if line.trim().is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (hash, rest) = line.split_once(' ')?;
+    let path = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*'))?;
+    if hash.is_empty() || !hash.chars().all(|c| c.is_ascii_hexdigit()) || path.is_empty() {
+        return None;
+    }
+    let path = if escaped {
+        unescape(path)
+    } else {
+        path.to_string()
+    };
+    Some((hash.to_ascii_lowercase(), path))
+}
+
+/// Parses a line of an SFV file, `path CRC32` with the CRC-32 in 8 hexadecimal digits.
+///
+/// Returns `(crc32, path)`, with the CRC-32 in lowercase, or `None` for blank, comment
+/// (`;`) or malformed lines.
+pub fn parse_sfv_line(line: &str) -> Option<(String, String)> {
+    if line.trim().is_empty() || line.starts_with(';') {
+        return None;
+    }
+    let (path, crc) = line.trim_end().rsplit_once([' ', '\t'])?;
+    let path = path.trim_end();
+    if crc.len() != 8 || !crc.chars().all(|c| c.is_ascii_hexdigit()) || path.is_empty() {
+        return None;
+    }
+    Some((crc.to_ascii_lowercase(), path.to_string()))
+}
+
+fn unescape(path: &str) -> String {
+    let mut result = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('r') => result.push('\r'),
+                Some(other) => result.push(other),
+                None => result.push('\\'),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Computes the SHA-256 hash of a file as a lowercase hexadecimal string.
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let size = file.metadata().map_or(0, |metadata| metadata.len());
+    let mut buffer = vec![0; read_buffer_size(size)];
+    loop {
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// CRC-32 (the IEEE 802.3 one SFV files use) of each byte value, for [`crc32_update`].
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut crc = byte as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[byte] = crc;
+        byte += 1;
+    }
+    table
+};
+
+/// Returns the CRC-32 `crc` of some data, starting from 0, updated with `data`.
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let crc = data.iter().fold(!crc, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    });
+    !crc
+}
+
+/// Reads a file once to compute its CRC-32 and BLAKE3 hash, and returns the BLAKE3 hash
+/// as a lowercase hexadecimal string if the CRC-32 is `expected_crc`, or `None` if not.
+fn blake3_if_crc32_matches(path: &Path, expected_crc: &str) -> Result<Option<String>> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut crc = 0;
+    let size = file.metadata().map_or(0, |metadata| metadata.len());
+    let mut buffer = vec![0; read_buffer_size(size)];
+    loop {
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+        crc = crc32_update(crc, &buffer[..bytes_read]);
+    }
+    Ok((format!("{:08x}", crc) == expected_crc).then(|| hasher.finalize().to_hex().to_string()))
+}
+
+/// Writes the cache contents to `writer` as a checksum manifest.
+///
+/// Paths are written relative to the base path, so the manifest can be verified with
+/// `b3sum --check` / `sha256sum --check` from the base directory. Returns the number
+/// of lines written.
+///
+/// # Errors
+///
+/// Returns an error if writing fails. Files that cannot be read for SHA-256 hashing
+/// are logged and skipped.
+pub fn export_cache(
+    cache: &HashCache,
+    root: &ScanRoot,
+    format: ChecksumFormat,
+    writer: &mut dyn Write,
+) -> Result<usize> {
+    let mut written = 0;
+    for entry in cache.entries() {
+        // Cache keys escape some bytes, manifests hold the paths as they are
+        let path = unescape_path(&entry.path);
+        let hash = match format {
+            ChecksumFormat::Blake3 => entry.hash,
+            ChecksumFormat::Sha256sum => match sha256_file(&root.resolve(&entry.path)) {
+                Ok(hash) => hash,
+                Err(e) => {
+                    warn!("Skipping '{}': {}", path.display(), e);
+                    continue;
+                }
+            },
+        };
+        writeln!(writer, "{}", format_line(&hash, &path.to_string_lossy()))?;
+        written += 1;
+    }
+    writer.flush()?;
+    info!("Exported {} checksums", Count(written as u64));
+    Ok(written)
+}
+
+/// Seeds the cache from a `b3sum`-format manifest, or an SFV file if its name ends in
+/// `.sfv`.
+///
+/// Paths in the manifest are resolved relative to the base path of `root`. Each file must exist,
+/// since its current size and modification time are recorded alongside the imported hash.
+/// A BLAKE3 hash is trusted as-is. The CRC-32 of an SFV file can't be used as a hash, so
+/// each file is read to check it and compute its BLAKE3 hash, which saves hashing it
+/// again only when verifying the files with the SFV file anyway. Returns the number of
+/// entries imported.
+///
+/// # Errors
+///
+/// Returns an error if the manifest cannot be read. Malformed lines, non-BLAKE3 hashes,
+/// CRC-32 mismatches and missing files are logged and skipped.
+pub fn import_manifest(cache: &HashCache, root: &ScanRoot, manifest: &Path) -> Result<usize> {
+    let file = fs::File::open(manifest)
+        .with_context(|| format!("Failed to open manifest {}", manifest.display()))?;
+    let sfv = manifest
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("sfv"));
+    let mut imported = 0;
+    let mut skipped = 0;
+    for (line_number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let parsed = if sfv {
+            parse_sfv_line(&line)
+        } else {
+            parse_line(&line)
+        };
+        let Some((hash, path)) = parsed else {
+            if !line.trim().is_empty() && !line.starts_with(if sfv { ';' } else { '#' }) {
+                warn!("Skipping malformed line {}", line_number + 1);
+                skipped += 1;
+            }
+            continue;
+        };
+        let file_path = root.base().join(&path);
+        let hash = if sfv {
+            match blake3_if_crc32_matches(&file_path, &hash) {
+                Ok(Some(blake3)) => blake3,
+                Ok(None) => {
+                    warn!("Skipping '{}': its CRC-32 is not {}", path, hash);
+                    skipped += 1;
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Skipping '{}': {}", path, e);
+                    skipped += 1;
+                    continue;
+                }
+            }
+        } else if hash.len() != blake3::OUT_LEN * 2 {
+            warn!(
+                "Skipping line {}: not a BLAKE3 hash ({} hex digits)",
+                line_number + 1,
+                hash.len()
+            );
+            skipped += 1;
+            continue;
+        } else {
+            hash
+        };
+        match cache.set_hash(&file_path, root, hash) {
+            Ok(()) => imported += 1,
+            Err(e) => {
+                warn!("Skipping '{}': {}", path, e);
+                skipped += 1;
+            }
+        }
+    }
+    info!(
+        "Imported {} checksums ({} skipped)",
+        Count(imported as u64),
+        Count(skipped as u64)
+    );
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ContentHash;
+
+    #[test]
+    fn imports_sfv_files_whose_crc32_matches() {
+        assert_eq!(crc32_update(0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(
+            crc32_update(crc32_update(0, b"1234"), b"56789"),
+            0xcbf4_3926
+        );
+        assert_eq!(
+            parse_sfv_line("My Album/01 track.flac\tCBF43926"),
+            Some(("cbf43926".to_string(), "My Album/01 track.flac".to_string()))
+        );
+        assert_eq!(parse_sfv_line("; generated by foo"), None);
+        assert_eq!(parse_sfv_line("track.flac 1234"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a file"), b"123456789").unwrap();
+        fs::write(dir.path().join("changed"), b"12345678").unwrap();
+        let manifest = dir.path().join("album.SFV");
+        fs::write(
+            &manifest,
+            "; comment\na file CBF43926\nchanged cbf43926\nmissing cbf43926\n",
+        )
+        .unwrap();
+        let root = ScanRoot::for_base(dir.path());
+        let cache = HashCache::load(dir.path().join("cache"));
+
+        assert_eq!(import_manifest(&cache, &root, &manifest).unwrap(), 1);
+        assert_eq!(
+            cache
+                .get_hash(&dir.path().join("a file"), &root)
+                .unwrap()
+                .as_deref(),
+            Some(ContentHash::of(b"123456789").to_string().as_str())
+        );
+        assert_eq!(
+            cache.get_hash(&dir.path().join("changed"), &root).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,297 @@
+//! `--partial-duplicates`: large files that share most of their content without being
+//! identical, such as re-muxed videos or log archives that had more appended, which
+//! exact hashing misses. Files are split into chunks at boundaries chosen by their
+//! content (a gear rolling hash), so an insertion only changes the chunks around it, and
+//! files sharing a large part of their chunks are reported in pairs.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use log::{debug, info, warn};
+use rayon::prelude::*;
+
+use crate::FileInfo;
+use crate::content_hash::ContentHash;
+use crate::scan_root::ScanRoot;
+use crate::units::{Bytes, Count};
+
+/// Chunks are never cut shorter than this, except at the end of a file.
+const MIN_CHUNK: usize = 16 * 1024;
+
+/// Chunks are always cut at this length.
+const MAX_CHUNK: usize = 256 * 1024;
+
+/// Cutting where the low 16 bits of the rolling hash are zero gives chunks of about 64K
+/// past the minimum.
+const CUT_MASK: u64 = (1 << 16) - 1;
+
+/// Chunks found in more contents than this (such as runs of zeros) say little about
+/// which files are related, and would pair up every file holding them.
+const MAX_CHUNK_SHARERS: usize = 64;
+
+/// Random values for each byte, added to the rolling hash.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    // SplitMix64, so the table is the same in every build
+    let mut table = [0; 256];
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// The chunks of a file: the size of each distinct chunk, by the first 8 bytes of its
+/// BLAKE3 hash.
+pub type Chunks = HashMap<u64, u64>;
+
+/// Splits what `reader` reads into content-defined chunks.
+///
+/// # Errors
+///
+/// Returns an error if reading fails.
+pub fn chunk(mut reader: impl Read) -> io::Result<Chunks> {
+    let mut chunks = Chunks::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    let mut hasher = blake3::Hasher::new();
+    let mut length = 0;
+    let mut rolling: u64 = 0;
+    let mut cut = |hasher: &mut blake3::Hasher, length: usize| {
+        let hash = hasher.finalize();
+        let id = u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap());
+        chunks.insert(id, length as u64);
+        hasher.reset();
+    };
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let mut start = 0;
+        for (i, &byte) in buffer[..read].iter().enumerate() {
+            rolling = (rolling << 1).wrapping_add(GEAR[usize::from(byte)]);
+            length += 1;
+            if (length >= MIN_CHUNK && rolling & CUT_MASK == 0) || length >= MAX_CHUNK {
+                hasher.update(&buffer[start..=i]);
+                cut(&mut hasher, length);
+                start = i + 1;
+                length = 0;
+                rolling = 0;
+            }
+        }
+        hasher.update(&buffer[start..read]);
+    }
+    if length > 0 {
+        cut(&mut hasher, length);
+    }
+    Ok(chunks)
+}
+
+/// Splits the file at `path` into content-defined chunks.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be read.
+pub fn chunk_file(path: &Path) -> io::Result<Chunks> {
+    chunk(File::open(path)?)
+}
+
+/// Two files sharing much of their content.
+#[derive(Debug, Clone)]
+pub struct PartialDuplicate {
+    /// The smaller of the two files.
+    pub smaller: FileInfo,
+    /// The larger of the two files.
+    pub larger: FileInfo,
+    /// Bytes of the smaller file also found in the larger one.
+    pub shared: u64,
+}
+
+impl PartialDuplicate {
+    /// Fraction of the larger file that is shared, between 0 and 1.
+    pub fn similarity(&self) -> f64 {
+        self.shared as f64 / self.larger.size.max(1) as f64
+    }
+}
+
+/// Finds pairs of files of at least `min_size` bytes whose shared chunks make up at
+/// least `min_similarity` (between 0 and 1) of the larger file. Files with identical
+/// contents are exact duplicates and are reported as such, so each content is chunked
+/// once and compared by its first file. Pairs are sorted by shared bytes, most first.
+///
+/// Files that can't be read are skipped.
+pub fn find_partial_duplicates<'a>(
+    files: impl IntoIterator<Item = &'a FileInfo>,
+    min_size: u64,
+    min_similarity: f64,
+) -> Vec<PartialDuplicate> {
+    let mut by_hash: HashMap<ContentHash, &FileInfo> = HashMap::new();
+    for file in files
+        .into_iter()
+        .filter(|file| !file.in_archive && file.size >= min_size.max(1))
+    {
+        by_hash
+            .entry(file.hash)
+            .and_modify(|first| {
+                if file.path < first.path {
+                    *first = file;
+                }
+            })
+            .or_insert(file);
+    }
+    let mut contents: Vec<&FileInfo> = by_hash.into_values().collect();
+    contents.sort_by(|a, b| a.path.cmp(&b.path));
+    info!(
+        "Splitting {} files into chunks to find partial duplicates...",
+        Count(contents.len() as u64)
+    );
+    let chunked: Vec<(&FileInfo, Chunks)> = contents
+        .into_par_iter()
+        .filter_map(|file| match chunk_file(&file.path.to_path_buf()) {
+            Ok(chunks) => Some((file, chunks)),
+            Err(e) => {
+                debug!("Skipping '{}': {}", file.path.display(), e);
+                None
+            }
+        })
+        .collect();
+
+    let mut sharers: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (index, (_, chunks)) in chunked.iter().enumerate() {
+        for id in chunks.keys() {
+            sharers.entry(*id).or_default().push(index);
+        }
+    }
+    let mut shared: HashMap<(usize, usize), u64> = HashMap::new();
+    for (id, indices) in &sharers {
+        if indices.len() < 2 || indices.len() > MAX_CHUNK_SHARERS {
+            continue;
+        }
+        let size = chunked[indices[0]].1[id];
+        for (n, &i) in indices.iter().enumerate() {
+            for &j in &indices[n + 1..] {
+                *shared.entry((i, j)).or_insert(0) += size;
+            }
+        }
+    }
+
+    let mut pairs: Vec<PartialDuplicate> = shared
+        .into_iter()
+        .map(|((i, j), shared)| {
+            let (a, b) = (chunked[i].0, chunked[j].0);
+            let (smaller, larger) = if (a.size, &a.path) <= (b.size, &b.path) {
+                (a, b)
+            } else {
+                (b, a)
+            };
+            PartialDuplicate {
+                smaller: smaller.clone(),
+                larger: larger.clone(),
+                shared,
+            }
+        })
+        .filter(|pair| pair.similarity() >= min_similarity)
+        .collect();
+    pairs.sort_by(|a, b| {
+        b.shared
+            .cmp(&a.shared)
+            .then_with(|| a.smaller.path.cmp(&b.smaller.path))
+    });
+    pairs
+}
+
+/// Prints pairs found by [`find_partial_duplicates`], as a section separate from the
+/// exact duplicates.
+pub fn print_partial_duplicates(pairs: &[PartialDuplicate], root: &ScanRoot) {
+    if pairs.is_empty() {
+        info!("No partial duplicates found");
+        return;
+    }
+    warn!(
+        "Found {} pairs of files sharing most of their content",
+        Count(pairs.len() as u64)
+    );
+    for pair in pairs {
+        warn!(
+            "{:.0}% shared ({}):",
+            pair.similarity() * 100.0,
+            Bytes(pair.shared)
+        );
+        for file in [&pair.smaller, &pair.larger] {
+            warn!(
+                "  {} ({})",
+                root.display_file(&file.path).display(),
+                Bytes(file.size)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn finds_files_with_appended_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rng = fastrand::Rng::with_seed(7);
+        let mut random = |len: usize| -> Vec<u8> { (0..len).map(|_| rng.u8(..)).collect() };
+        let log = random(2 * 1024 * 1024);
+        let mut appended = log.clone();
+        appended.extend(random(200 * 1024));
+        let mut edited = log.clone();
+        edited[1024 * 1024..1024 * 1024 + 10].copy_from_slice(b"0123456789");
+        let other = random(2 * 1024 * 1024);
+
+        let files: Vec<FileInfo> = [
+            ("log", &log),
+            ("log.appended", &appended),
+            ("log.edited", &edited),
+            ("other", &other),
+        ]
+        .into_iter()
+        .map(|(name, content)| {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            FileInfo::with_contents(path, content)
+        })
+        .collect();
+
+        let pairs = find_partial_duplicates(&files, 1024 * 1024, 0.7);
+        let names: Vec<(String, String)> = pairs
+            .iter()
+            .map(|pair| {
+                let name = |file: &FileInfo| {
+                    file.path
+                        .to_path_buf()
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned()
+                };
+                (name(&pair.smaller), name(&pair.larger))
+            })
+            .collect();
+        assert_eq!(names.len(), 3);
+        for pair in [
+            ("log", "log.appended"),
+            ("log", "log.edited"),
+            ("log.edited", "log.appended"),
+        ] {
+            assert!(names.contains(&(pair.0.into(), pair.1.into())), "{pair:?}");
+        }
+        assert!(pairs.iter().all(|pair| pair.similarity() < 1.0));
+    }
+}
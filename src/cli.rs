@@ -1,27 +1,1487 @@
-use clap::Parser;
+use clap::{ArgAction, ArgGroup, ArgMatches, Args, FromArgMatches, Parser, Subcommand};
+use globset::GlobMatcher;
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::attributes::MetadataPolicy;
+use crate::cache::{CacheCompression, CacheFormat, CacheLimits, CheckpointPolicy, MtimePolicy};
+use crate::checksums::ChecksumFormat;
+use crate::completions::Shell;
+use crate::content_hash::GroupId;
+use crate::duplicates::{MatchMode, OutputFormat, SortBy};
+use crate::file_type::FileType;
+use crate::json_log::LogFormat;
+use crate::potential::{HashMode, PotentialMatch};
+use crate::progress::ProgressFormat;
+use crate::scan_root::DisplayPaths;
+use crate::scanner::{ModifiedRange, NameFilter, RetryPolicy, ScanLimit, Threads};
+use crate::script::ScriptFormat;
+use crate::selection::{KeepPolicy, Rule, path_glob};
+use crate::stats::StatsFormat;
+use crate::throttle::Throttle;
+use crate::top_dirs::GroupBy;
+use crate::utils::{parse_duration, parse_size, parse_time};
 
 #[derive(Parser)]
 #[command(name = "check-file-dups")]
 #[command(about = "A CLI tool to find duplicate files in a directory")]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    /// Only print warnings, errors and the duplicate report, without progress bars
+    /// [default: false]
+    #[arg(
+        short,
+        long,
+        default_value = "false",
+        global = true,
+        conflicts_with = "verbose"
+    )]
+    pub quiet: bool,
+
+    /// Print debug-level messages, including cache hits and misses for every file
+    /// [default: false]
+    #[arg(short, long, default_value = "false", global = true)]
+    pub verbose: bool,
+
+    /// Print without colors. Also set by the NO_COLOR environment variable
+    /// [default: false]
+    #[arg(long, default_value = "false", global = true)]
+    pub no_color: bool,
+
+    /// Refuse to change any files, failing instead, even if --delete, --move-to,
+    /// --hardlink, --reflink, `apply`, `undo` or `merge-dirs` asks to. Without any of
+    /// those, runs only read files anyway [default: false]
+    #[arg(long, default_value = "false", global = true)]
+    pub read_only: bool,
+
+    /// How to show progress. `json` writes one JSON object per line to stderr (or
+    /// --progress-fd) instead of drawing bars: the phase (`walk` or `hash`), files and
+    /// bytes done, the rate, and whether the phase is done, about once a second
+    #[arg(long, value_name = "FORMAT", default_value = "bar", global = true)]
+    pub progress: ProgressFormat,
+
+    /// File descriptor to write `--progress json` events to, such as the write end of a
+    /// pipe opened by a wrapper, instead of stderr (Unix only)
+    #[arg(long, value_name = "FD", global = true)]
+    pub progress_fd: Option<i32>,
+
+    /// Show sizes in decimal units (kB, MB, GB: powers of 1000), like disk makers and
+    /// some file managers, instead of binary units (KiB, MiB, GiB: powers of 1024)
+    /// [default: false]
+    #[arg(
+        long,
+        default_value = "false",
+        global = true,
+        overrides_with = "binary"
+    )]
+    pub si: bool,
+
+    /// Show sizes in binary units (KiB, MiB, GiB), as by default, overriding an earlier
+    /// --si or `si = true` in the config file [default: false]
+    #[arg(long, default_value = "false", global = true, overrides_with = "si")]
+    pub binary: bool,
+
+    /// How to write the paths of files in reports: `auto` for relative to the scanned
+    /// directory in the console and absolute in JSON, fdupes and rmlint output and
+    /// scripts, `absolute` for absolute everywhere, or `relative-to=DIR` for relative to
+    /// DIR everywhere, e.g. to compare the results of scans of different directories
+    #[arg(long, value_name = "MODE", default_value = "auto", global = true)]
+    pub display_paths: DisplayPaths,
+
+    /// Config file to use [default: check-file-dups.toml in the current directory, then in
+    /// the platform config directory, e.g. ~/.config/check-file-dups]
+    #[arg(long, value_name = "FILE", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Use the settings of the `[profile.NAME]` table of the config file, such as the
+    /// paths to scan, directories to skip, size filters and keep rules, in place of those
+    /// set outside it
+    #[arg(long, value_name = "NAME", global = true)]
+    pub profile: Option<String>,
+
+    /// File to append the log to
+    /// [default: platform data directory, e.g. ~/.local/share/check-file-dups/check-file-dups.log]
+    #[arg(long, value_name = "FILE", global = true)]
+    pub log_file: Option<PathBuf>,
+
+    /// Don't write a log file [default: false]
+    #[arg(
+        long,
+        default_value = "false",
+        global = true,
+        conflicts_with = "log_file"
+    )]
+    pub no_log_file: bool,
+
+    /// Format of the log file. `json` writes one object per line with the timestamp,
+    /// level, message and fields such as paths, sizes and durations, for log pipelines
+    #[arg(long, value_name = "FORMAT", default_value = "text", global = true)]
+    pub log_format: LogFormat,
+
+    /// Read files no faster than this many bytes per second in total (e.g. 20M), so a
+    /// background scan leaves disk bandwidth for other programs
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, global = true)]
+    pub throttle_rate: Option<u64>,
+
+    /// Pause for this many milliseconds after reading each file that needs hashing
+    #[arg(long, value_name = "MS", default_value = "0", global = true)]
+    pub throttle_delay_ms: u64,
+
+    /// Run with the lowest CPU and disk priority (nice 19 and the idle IO class on Linux,
+    /// background mode on Windows) [default: false]
+    #[arg(long, default_value = "false", global = true)]
+    pub low_priority: bool,
+
+    /// Give up reading a file when no data arrives for this many seconds, e.g. on a hung
+    /// network share. Such files are listed as unreadable. 0 waits forever [default: 0]
+    #[arg(long, value_name = "SECS", global = true)]
+    pub read_timeout: Option<u64>,
+
+    /// Size of the buffer files are read with (e.g. 1M). By default it follows the size
+    /// of each file, so small files are read at once and large ones in 4 MiB reads
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, global = true)]
+    pub read_buffer: Option<u64>,
+
+    /// Tell the kernel that files won't be read again once hashed, so a scan doesn't
+    /// evict what other programs have in the page cache. Only has an effect on Linux
+    /// [default: false]
+    #[arg(long, default_value = "false", global = true)]
+    pub drop_page_cache: bool,
+
+    /// Size from which a file is hashed on several threads at once (e.g. 512M), so a
+    /// single huge file uses all cores. The threads are those hashing or reading other
+    /// files, which help with it once they are idle
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "1G", global = true)]
+    pub large_file_threshold: u64,
+
+    /// Number of times to retry a file whose read failed or timed out, for flaky network
+    /// filesystems. Missing files and files without read permission are not retried
+    #[arg(long, value_name = "N", default_value = "0", global = true)]
+    pub retries: u32,
+
+    /// Directory to store the hash cache in. Each base path gets its own cache file, so
+    /// one directory can be shared by scans of different locations
+    /// [default: platform cache directory, e.g. ~/.cache/check-file-dups]
+    #[arg(long, value_name = "DIR", global = true)]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Skip hidden files and directories: those whose name starts with a dot, and on
+    /// Windows also those with the hidden attribute [default: false]
+    #[arg(long, default_value = "false", global = true)]
+    pub skip_hidden: bool,
+
+    /// Scan the metadata files operating systems leave behind (.DS_Store, Thumbs.db,
+    /// desktop.ini, .Spotlight-V100 and the like), which are skipped by default since
+    /// their copies would crowd the report [default: false]
+    #[arg(long, default_value = "false", global = true)]
+    pub include_junk: bool,
+
+    /// Don't descend into directories on other filesystems than the scanned directory,
+    /// such as network mounts or snapshots [default: false]
+    #[arg(long, default_value = "false", global = true)]
+    pub one_file_system: bool,
+
+    /// Follow directory junctions on Windows. They are skipped by default, since they
+    /// often point back into the scanned tree and would be scanned twice [default: false]
+    #[arg(long, default_value = "false", global = true)]
+    pub follow_junctions: bool,
+
+    /// File listing the duplicate groups acknowledged with `acknowledge` [default:
+    /// acknowledged.json in the platform data directory, e.g.
+    /// ~/.local/share/check-file-dups]
+    #[arg(long, value_name = "FILE", global = true)]
+    pub acknowledged_file: Option<PathBuf>,
+
+    /// File listing the known good originals registered with `register-originals`
+    /// [default: originals.json.zst in the platform data directory]
+    #[arg(long, value_name = "FILE", global = true)]
+    pub originals_file: Option<PathBuf>,
+
+    /// Treat paths differing only in case (`Photo.JPG` and `photo.jpg`) as the same file,
+    /// as the case-insensitive filesystems macOS and Windows use by default do: scan paths
+    /// overlap, cache keys are lowercased, and --report-same-name compares names ignoring
+    /// case [default: true on macOS and Windows, false elsewhere]
+    #[arg(
+        long,
+        value_name = "BOOL",
+        default_value_t = cfg!(any(windows, target_os = "macos")),
+        action = ArgAction::Set,
+        global = true,
+        hide_default_value = true
+    )]
+    pub case_insensitive_paths: bool,
+
+    /// Never delete, move or replace files whose path relative to the scanned directory
+    /// matches this glob (e.g. 'Originals/**'). Can be given several times. One protected
+    /// copy of each group is always kept, and acting on a group of only protected files
+    /// is an error
+    #[arg(long, value_name = "GLOB", value_parser = path_glob, global = true)]
+    pub protect: Vec<GlobMatcher>,
+}
+
+/// Options of a scan, taken directly by `check-file-dups PATH` or by `scan`.
+#[derive(Args)]
+#[command(group(ArgGroup::new("action").args(["move_to", "delete", "delete_group", "hardlink", "reflink"])))]
+pub struct ScanArgs {
     /// Directory to scan for duplicates
     #[arg(default_value = ".")]
     pub path: PathBuf,
 
-    /// Number of parallel threads for hashing.
-    /// Use multiple threads if the images are on NVMe SSD (e.g. CPU is the bottleneck).
-    /// Otherwise a single thread (default) is typically faster.
-    #[arg(short, long, default_value = "1")]
-    pub threads: Option<usize>,
+    /// More directories to scan along with the first one, for duplicates across all of
+    /// them. Directories given twice or inside another one are skipped with a warning, so
+    /// each file is scanned once
+    #[arg(value_name = "PATH", conflicts_with = "files_from")]
+    pub more_paths: Vec<PathBuf>,
+
+    /// Don't page the duplicate report through $PAGER (or less) when it doesn't fit on
+    /// the terminal [default: false]
+    #[arg(long, default_value = "false")]
+    pub no_pager: bool,
+
+    /// Shorthand for setting both --io-threads and --hash-threads. Run `bench` to measure
+    /// the best value for a disk
+    #[arg(short, long, value_name = "N|auto")]
+    pub threads: Option<Threads>,
+
+    /// Number of directories to list at once. Listing mostly waits for the filesystem,
+    /// so more threads than cores help on network shares with many small files. `auto`
+    /// uses twice the number of cores [default: auto]
+    #[arg(long, value_name = "N|auto")]
+    pub walk_threads: Option<Threads>,
+
+    /// Number of files to read at once. Use 1 for HDDs, where parallel reads cause
+    /// seeking, and more for SSDs. `auto` times sequential against parallel reads of a
+    /// few files at startup and picks accordingly [default: auto]
+    #[arg(long, value_name = "N|auto")]
+    pub io_threads: Option<Threads>,
+
+    /// Number of threads hashing each file. Helps with large files when the disk is
+    /// faster than a single core can hash. `auto` uses the cores not used for reading
+    /// [default: auto]
+    #[arg(long, value_name = "N|auto")]
+    pub hash_threads: Option<Threads>,
+
+    /// Find duplicate groups within about this much memory (e.g. 2G) for the list of
+    /// files, spilling files to sorted temporary files and merging them. Slower, for
+    /// scans of tens of millions of files on machines with little memory
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub memory_budget: Option<u64>,
 
     /// Skip using hash cache and compute all hashes fresh.
-    /// For performance testing / benchmarking optimal number of threads to use [default: false]
+    /// For performance testing / benchmarking optimal numbers of threads to use [default: false]
     #[arg(short, long, default_value = "false")]
     pub no_cache: bool,
 
+    /// Save the list of files and the hashes computed so far when the scan is
+    /// interrupted, and continue an interrupted scan of the same directory with the same
+    /// options instead of starting over. Works with --no-cache too [default: false]
+    #[arg(long, default_value = "false")]
+    pub resume: bool,
+
+    /// Remember the entries of each directory next to the hash cache, and only read the
+    /// directories changed since the last scan again. Speeds up rescans of network
+    /// shares. Ignored with --no-cache [default: false]
+    #[arg(long, default_value = "false")]
+    pub incremental: bool,
+
+    /// Expect some directories to be unreadable, such as the home directories of other
+    /// users: files and directories denied are only counted at the end, not listed as
+    /// scan errors [default: false]
+    #[arg(long, default_value = "false")]
+    pub skip_unreadable: bool,
+
+    /// Exit with an error status after reporting if any file or directory couldn't be
+    /// read for lack of permission, so scripts can tell the scan didn't cover the whole
+    /// tree [default: false]
+    #[arg(long, default_value = "false", conflicts_with = "skip_unreadable")]
+    pub fail_on_unreadable: bool,
+
     /// Remove cache entries for files that no longer exist on disk.
     /// Useful for cleaning up the cache after files have been deleted or moved [default: false]
     #[arg(short, long, default_value = "false")]
     pub prune_cache: bool,
+
+    /// Only prune cache entries whose recorded modification time is older than the given
+    /// number of days, keeping entries for recently changed files that may be temporarily
+    /// unavailable (e.g. on an unmounted drive)
+    #[arg(long, value_name = "DAYS", requires = "prune_cache")]
+    pub prune_older_than: Option<u64>,
+
+    /// Periodically save the hash cache during long scans, so a crash loses little work.
+    /// Takes a time interval (e.g. 10m, 1h), a number of new hashes (e.g. 5000), or both
+    /// separated by a comma (e.g. 10m,5000)
+    #[arg(long, value_name = "LIMITS")]
+    pub cache_checkpoint: Option<CheckpointPolicy>,
+
+    /// Keep at most this many entries in the hash cache, dropping those least recently
+    /// seen in a scan when it is saved
+    #[arg(long, value_name = "N")]
+    pub cache_max_entries: Option<usize>,
+
+    /// Keep the hash cache file below this size (e.g. 50M), dropping the entries least
+    /// recently seen in a scan when it is saved
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub cache_max_size: Option<u64>,
+
+    /// Format to save the hash cache in. Both are read, and a cache in the other format
+    /// is converted on the next save
+    #[arg(long, value_enum, default_value_t = CacheFormat::Json)]
+    pub cache_format: CacheFormat,
+
+    /// Fail instead of waiting when another process is saving the same hash cache
+    /// [default: false]
+    #[arg(long, default_value = "false")]
+    pub no_cache_wait: bool,
+
+    /// Take cached hashes as valid when the file's modification time differs from the
+    /// recorded one by at most this much (e.g. 2s for FAT's 2-second resolution, 1h for
+    /// daylight saving time shifts on FAT, exFAT and network filesystems) [default: 0s]
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub mtime_tolerance: Option<Duration>,
+
+    /// Trust cached hashes of files whose size is unchanged even if their modification
+    /// time changed by more than --mtime-tolerance, and hash those files again after
+    /// all others, correcting the results if their contents did change [default: false]
+    #[arg(long, default_value = "false")]
+    pub rehash_suspect: bool,
+
+    /// zstd level to compress the hash cache with, from 1 (fastest) to 22 (smallest)
+    /// [default: 9, or 3 with --cache-format binary]
+    #[arg(long, value_name = "LEVEL", value_parser = clap::value_parser!(i32).range(1..=22))]
+    pub cache_compression_level: Option<i32>,
+
+    /// Number of threads to compress the hash cache with [default: all cores]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub cache_compression_threads: Option<u32>,
+
+    /// Save the hash cache on a background thread while the results are reported,
+    /// instead of before exiting [default: false]
+    #[arg(long, default_value = "false")]
+    pub background_cache_save: bool,
+
+    /// Compute wasted space from the space allocated on disk (accounting for sparse,
+    /// compressed and small files) instead of the logical file size, so the estimate
+    /// matches what deleting the duplicates would actually free [default: false]
+    #[arg(long, default_value = "false")]
+    pub allocated_size: bool,
+
+    /// Take hardlinks outside the scanned files into account: a copy whose data is also
+    /// linked from elsewhere stays on disk whatever is done to it, so it isn't counted as
+    /// wasted space, is preferred as the copy to keep, and is otherwise left alone by
+    /// actions [default: false]
+    #[arg(long, default_value = "false")]
+    pub count_hardlinks_once: bool,
+
+    /// Read the extended attributes (including macOS resource forks) or Windows alternate
+    /// data streams of duplicates, point out copies whose streams differ from the kept
+    /// copy, and leave those alone when acting [default: false]
+    #[arg(long, default_value = "false")]
+    pub check_streams: bool,
+
+    /// Recognize files that already share their data through reflinks (clones) on btrfs
+    /// and XFS: read them only once, and leave copies sharing data with each other out of
+    /// the wasted space. Linux only [default: false]
+    #[arg(long, default_value = "false")]
+    pub detect_reflinks: bool,
+
+    /// Skip empty files, which are all identical, and only count them in a summary line.
+    /// Use `--ignore-empty=false` to report them as a duplicate group
+    #[arg(
+        long,
+        value_name = "BOOL",
+        default_value_t = true,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        action = ArgAction::Set
+    )]
+    pub ignore_empty: bool,
+
+    /// Skip non-empty files smaller than this (e.g. 4K), without hashing them. Empty files
+    /// are governed by `--ignore-empty`
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "0")]
+    pub min_size: u64,
+
+    /// Only scan files of these kinds, told from their first bytes where they are
+    /// distinctive and from their extension otherwise, so photos without an extension
+    /// are still found. Can be repeated or comma-separated
+    #[arg(long = "type", value_name = "TYPE", value_delimiter = ',')]
+    pub file_types: Vec<FileType>,
+
+    /// Only scan files modified at or after this time: a date (2024-01-31), a date and
+    /// time (2024-01-31T18:30:00) or an age such as 12h, 30d or 2w
+    #[arg(long, value_name = "WHEN", value_parser = parse_time)]
+    pub newer_than: Option<SystemTime>,
+
+    /// Only scan files modified before this time, given like --newer-than
+    #[arg(long, value_name = "WHEN", value_parser = parse_time)]
+    pub older_than: Option<SystemTime>,
+
+    /// Scan the files listed in this file, or on standard input with '-', instead of
+    /// walking the scanned directory, e.g. to pre-select them with `find -print0`. Paths
+    /// are separated by NUL bytes if there are any, otherwise one per line, and relative
+    /// paths are taken from the current directory
+    #[arg(long, value_name = "FILE")]
+    pub files_from: Option<PathBuf>,
+
+    /// Also scan the files inside this raw disk image, such as a backup made with `dd`,
+    /// by attaching it read-only and mounting its filesystems for the scan. Needs
+    /// `udisksctl` on Linux, and only takes ISO, VHD and VHDX images on Windows. Can be
+    /// given several times
+    #[arg(long, value_name = "IMAGE", conflicts_with_all = ["files_from", "action"])]
+    pub disk_image: Vec<PathBuf>,
+
+    /// Stop collecting files once this many are found, for a quick estimate of the
+    /// duplication in a large tree before a full scan
+    #[arg(long, value_name = "N")]
+    pub limit_files: Option<u64>,
+
+    /// Stop collecting files once they add up to this size (e.g. 100G), like
+    /// --limit-files
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub limit_bytes: Option<u64>,
+
+    /// With --limit-files or --limit-bytes, list the whole tree and pick the files at
+    /// random, so the sample represents all of it rather than the directories listed
+    /// first [default: false]
+    #[arg(long, default_value = "false")]
+    pub random_sample: bool,
+
+    /// Instead of a full scan, hash the files of a random sample of the groups of files
+    /// sharing a size, and estimate the duplicates and wasted space of the whole tree
+    /// with 95% confidence bounds [default: false]
+    #[arg(long, default_value = "false")]
+    pub estimate: bool,
+
+    /// Number of groups of files sharing a size to hash for --estimate. Larger samples
+    /// take longer but narrow the bounds
+    #[arg(long, value_name = "N", default_value = "1000")]
+    pub estimate_groups: usize,
+
+    /// Instead of a full scan, report the groups of files sharing a size as potential
+    /// duplicates, without reading any file [default: false]
+    #[arg(long, default_value = "false", conflicts_with = "estimate")]
+    pub size_only: bool,
+
+    /// How to match duplicates. With content+name, only copies with the same file name
+    /// are duplicates, and with content+name+mtime only those also modified at the same
+    /// time. With name-size, files with the same name and size are reported as potential
+    /// duplicates without reading them, each group with an ID for --confirm-group
+    #[arg(
+        long = "match",
+        value_name = "MODE",
+        value_enum,
+        default_value_t = MatchMode::Content,
+        conflicts_with_all = ["estimate", "size_only"]
+    )]
+    pub match_mode: MatchMode,
+
+    /// Whether to read the files. With none, for media where reading every file is
+    /// impractical, such as tape or optical discs, files with the same name, size and
+    /// modification time are reported as unverified but likely duplicates, each group
+    /// with an ID for --confirm-group
+    #[arg(
+        long = "hash",
+        value_name = "MODE",
+        value_enum,
+        default_value_t = HashMode::Content,
+        conflicts_with_all = ["estimate", "size_only", "match_mode"]
+    )]
+    pub hash_mode: HashMode,
+
+    /// With --match name-size or --hash none, hash only the files of the group with this
+    /// ID and report their duplicates as usual. Can be given several times
+    #[arg(
+        long = "confirm-group",
+        value_name = "ID",
+        conflicts_with = "files_from"
+    )]
+    pub confirm_groups: Vec<GroupId>,
+
+    /// Read files locked by other programs, such as Outlook's PST files, from a snapshot
+    /// of their volume taken for the scan, on Windows. Needs an administrator
+    /// [default: false]
+    #[arg(long, default_value = "false")]
+    pub snapshot: bool,
+
+    /// Only show the N duplicate groups wasting the most space
+    #[arg(long, value_name = "N")]
+    pub top: Option<usize>,
+
+    /// Only show duplicate groups wasting at least this much space (e.g. 10M, 1.5GiB, 500KB)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "0")]
+    pub min_group_waste: u64,
+
+    /// Only show duplicate groups with at least K distinct copies
+    #[arg(long, value_name = "K", default_value = "2")]
+    pub min_copies: usize,
+
+    /// Only show duplicate groups with copies in both directory A and directory B, both
+    /// inside the scanned directory, ignoring duplicates that are all within one of them
+    /// (e.g. when merging two photo libraries)
+    #[arg(long, num_args = 2, value_names = ["A", "B"])]
+    pub between: Option<Vec<PathBuf>>,
+
+    /// Only delete, move or replace files inside this directory, which must be inside the
+    /// scanned directory. Can be given several times. Files elsewhere are still hashed
+    /// and grouped, but only serve as references to match against: they are kept like
+    /// protected files, and groups without a copy inside the directories are ignored
+    #[arg(long, value_name = "DIR")]
+    pub dedupe_within: Vec<PathBuf>,
+
+    /// Only report and act on copies with another copy in the same directory, such as
+    /// `IMG_001.jpg` and `IMG_001 (1).jpg`. A copy is kept in each directory, and copies
+    /// in different directories are left alone [default: false]
+    #[arg(long, default_value = "false", conflicts_with = "between")]
+    pub within_dir_only: bool,
+
+    /// Leave out the duplicate group with this ID (shown in brackets next to each group)
+    /// or hash, as if acknowledged with `acknowledge`, for this run only. Can be given
+    /// several times
+    #[arg(long, value_name = "ID")]
+    pub ignore_group: Vec<GroupId>,
+
+    /// Only report and act on the duplicate group with this ID (shown in brackets next to
+    /// each group) or hash. IDs come from the contents, so they stay the same from run to
+    /// run. Can be given several times
+    #[arg(long = "group", value_name = "ID")]
+    pub groups: Vec<GroupId>,
+
+    /// Show each member of the groups picked with --group in the file manager (Explorer,
+    /// Finder, or the one of the desktop on Linux), selected, to look at the copies
+    /// before choosing what to keep
+    #[arg(long, default_value = "false", requires = "groups")]
+    pub reveal: bool,
+
+    /// Also hash the files inside zip and tar archives (.zip, .tar, .tar.gz, .tgz), so
+    /// archived copies of files are reported as duplicates, with paths like
+    /// `backup.zip!photos/img001.jpg`. Files inside archives are never moved, deleted or
+    /// hardlinked [default: false]
+    #[arg(long, default_value = "false")]
+    pub look_inside_archives: bool,
+
+    /// Hash gzip (.gz) and Zstandard (.zst) files by their decompressed contents, so
+    /// `notes.txt` and `notes.txt.gz` are reported as copies. Compressed files are marked
+    /// in the report and never moved, deleted or hardlinked, and their hashes aren't
+    /// cached [default: false]
+    #[arg(long, default_value = "false")]
+    pub decompress_before_hash: bool,
+
+    /// Also report JPEG and PNG images whose image data is identical but whose metadata
+    /// (EXIF, XMP, IPTC or text tags) differs, e.g. after editing tags [default: false]
+    #[arg(long, default_value = "false")]
+    pub ignore_image_metadata: bool,
+
+    /// Also report MP3 files whose audio is identical but whose ID3 tags (title, artist,
+    /// cover art) differ, e.g. after retagging a music library [default: false]
+    #[arg(long, default_value = "false")]
+    pub ignore_audio_tags: bool,
+
+    /// Also report files with the same name but different contents in different places,
+    /// which often are diverged copies to reconcile by hand [default: false]
+    #[arg(long, default_value = "false")]
+    pub report_same_name: bool,
+
+    /// Also list the N largest files scanned, duplicates or not, for a quick look at
+    /// where the space goes
+    #[arg(long, value_name = "N")]
+    pub report_largest: Option<usize>,
+
+    /// Also show how many files, and how much space, fall in each size range, from empty
+    /// files to 16 GiB and up [default: false]
+    #[arg(long, default_value = "false")]
+    pub size_histogram: bool,
+
+    /// Also report large files sharing most of their content without being identical,
+    /// such as re-muxed videos or log archives with more appended, by splitting them into
+    /// content-defined chunks. Reads every file of at least --partial-min-size again
+    /// [default: false]
+    #[arg(long, default_value = "false")]
+    pub partial_duplicates: bool,
+
+    /// Only look for partial duplicates among files at least this large
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        default_value = "16M",
+        requires = "partial_duplicates"
+    )]
+    pub partial_min_size: u64,
+
+    /// Percentage of the larger file that must be shared for two files to be reported as
+    /// partial duplicates
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        default_value = "50",
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        requires = "partial_duplicates"
+    )]
+    pub partial_similarity: u8,
+
+    /// Instead of duplicates, report the files that have no copy anywhere in the scanned
+    /// directory, e.g. to confirm before wiping a drive that nothing on it is the only
+    /// copy. Written in the chosen --output-format [default: false]
+    #[arg(
+        long,
+        default_value = "false",
+        conflicts_with_all = ["action", "write_manifest", "exec"]
+    )]
+    pub report_unique: bool,
+
+    /// Also report clusters of visually similar images (resized, re-encoded or lightly
+    /// edited copies), comparing perceptual hashes. Requires the `perceptual` build
+    /// feature [default: false]
+    #[arg(long, default_value = "false")]
+    pub perceptual: bool,
+
+    /// Maximum number of differing bits (out of 64) between the perceptual hashes of two
+    /// images for them to be considered similar
+    #[arg(
+        long,
+        value_name = "BITS",
+        default_value = "10",
+        requires = "perceptual"
+    )]
+    pub perceptual_distance: u32,
+
+    /// Also report audio files that are likely the same recording encoded differently
+    /// (e.g. at another bitrate or in another format), comparing audio fingerprints.
+    /// Requires the `audio` build feature [default: false]
+    #[arg(long, default_value = "false")]
+    pub audio: bool,
+
+    /// Percentage of audio fingerprint bits that must match for two files to be
+    /// considered the same recording
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        default_value = "80",
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        requires = "audio"
+    )]
+    pub audio_similarity: u8,
+
+    /// Format of the duplicate results. Formats other than text are written to standard
+    /// output, or to the file given with --output
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output_format: OutputFormat,
+
+    /// File to write the results to, for --output-format other than text
+    #[arg(long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Write the paths of each duplicate group like --output-format fdupes, but ending
+    /// each path with a NUL byte instead of a newline, for `xargs -0` and paths containing
+    /// newlines [default: false]
+    #[arg(short = 'z', long, default_value = "false")]
+    pub print0: bool,
+
+    /// Text written after each group with --print0, also ended by a NUL byte. By default
+    /// an empty entry separates groups, like the blank line of the fdupes format
+    #[arg(long, value_name = "TEXT", default_value = "")]
+    pub group_separator: String,
+
+    /// Order in which duplicate groups are shown
+    #[arg(long, value_enum, default_value_t = SortBy::Waste)]
+    pub sort_by: SortBy,
+
+    /// Summarize the duplicates rather than listing each group: with top-dir, the
+    /// redundant copies and the space they waste in each top-level folder of the scanned
+    /// directory, to tell where to start cleaning up. Text output only
+    #[arg(long, value_enum, value_name = "VIEW")]
+    pub group_by: Option<GroupBy>,
+
+    /// Only report the duplicate groups with a copy inside this directory, which must be
+    /// inside the scanned directory. With --group-by top-dir, summarizes its subfolders
+    /// instead of the top-level folders
+    #[arg(long, value_name = "DIR")]
+    pub drill_into: Option<PathBuf>,
+
+    /// Move duplicates into this quarantine directory instead of leaving them in place,
+    /// keeping one copy of each group. Moved files keep their path relative to the scanned
+    /// directory, and a JSON journal of the moves is written to the quarantine directory
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["delete", "hardlink", "reflink"])]
+    pub move_to: Option<PathBuf>,
+
+    /// Delete duplicates, keeping one copy of each group. A journal is written so that
+    /// deleted files can be recreated from the kept copy with `undo` [default: false]
+    #[arg(long, default_value = "false", conflicts_with_all = ["hardlink", "reflink"])]
+    pub delete: bool,
+
+    /// Delete the duplicates of the group with this ID, keeping one copy, like
+    /// `--delete --group ID`. Can be given several times
+    #[arg(
+        long,
+        value_name = "ID",
+        conflicts_with_all = ["groups", "move_to", "hardlink", "reflink"]
+    )]
+    pub delete_group: Vec<GroupId>,
+
+    /// Move duplicates to the trash (the Recycle Bin on Windows) instead of deleting
+    /// them, so they can also be recovered from there [default: false]
+    #[arg(
+        long,
+        default_value = "false",
+        requires = "delete",
+        conflicts_with = "emit_script"
+    )]
+    pub use_trash: bool,
+
+    /// Replace duplicates with hardlinks to the kept copy of each group, freeing their
+    /// space while keeping every path. A journal is written for `undo` [default: false]
+    #[arg(long, default_value = "false", conflicts_with = "reflink")]
+    pub hardlink: bool,
+
+    /// Replace duplicates with reflinks of the kept copy of each group on copy-on-write
+    /// filesystems (btrfs, XFS, APFS), freeing their space while keeping them independent
+    /// files. Groups on filesystems without reflink support are skipped. A journal is
+    /// written [default: false]
+    #[arg(long, default_value = "false")]
+    pub reflink: bool,
+
+    /// Hash every file of a group again right before acting on it, rather than only
+    /// checking that its size and modification time are unchanged since the scan. Slower,
+    /// but also catches changes that kept the modification time [default: false]
+    #[arg(long, default_value = "false", requires = "action")]
+    pub verify_hash: bool,
+
+    /// What to do about the permissions, modification time, owner and extended
+    /// attributes of the duplicates deleted or replaced. Both record them in the journal
+    /// so `undo` restores them; `preserve` also leaves duplicates unlinked with
+    /// --hardlink whose metadata differs from the kept copy
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POLICY",
+        default_value = "record",
+        requires = "action"
+    )]
+    pub metadata_policy: MetadataPolicy,
+
+    /// Only show the plan of what --move-to, --delete, --hardlink or --reflink would do:
+    /// the files and bytes in each directory, and the disk usage of each filesystem
+    /// before and after. No file is changed [default: false]
+    #[arg(
+        long,
+        default_value = "false",
+        requires = "action",
+        conflicts_with_all = ["emit_script", "write_manifest"]
+    )]
+    pub dry_run: bool,
+
+    /// Ask for confirmation on the terminal after showing the plan, before changing any
+    /// file. Without a terminal to ask on, nothing is changed [default: false]
+    #[arg(
+        long,
+        default_value = "false",
+        requires = "action",
+        conflicts_with = "dry_run"
+    )]
+    pub confirm: bool,
+
+    /// Instead of acting on duplicates, write a script of the commands that --move-to,
+    /// --delete, --hardlink or --reflink would run to standard output, for review
+    #[arg(long, value_enum, value_name = "SHELL", requires = "action")]
+    pub emit_script: Option<ScriptFormat>,
+
+    /// Run this command for each duplicate group, instead of a built-in action. It is run
+    /// by the shell, with {keeper} replaced by the copy that would be kept, {dupes...} by
+    /// the duplicates --delete would remove and {id} by the group ID, each path quoted,
+    /// e.g. 'review-tool {keeper} {dupes...}'
+    #[arg(long, value_name = "CMD", conflicts_with_all = ["action", "write_manifest"])]
+    pub exec: Option<String>,
+
+    /// Instead of acting on duplicates, write a manifest to this file marking the copy of
+    /// each group to keep and what to do with the others: what --move-to, --delete,
+    /// --hardlink or --reflink would do, deleting by default. Edit it by hand if needed, then carry it
+    /// out with `apply`
+    #[arg(long, value_name = "FILE", conflicts_with = "emit_script")]
+    pub write_manifest: Option<PathBuf>,
+
+    /// Prefer keeping files whose path relative to the scanned directory matches this glob
+    /// (e.g. 'Photos/**'). Can be given several times. Rules are applied in the order
+    /// given; if every rule ties, the file with the smallest path is kept
+    #[arg(long, value_name = "GLOB", value_parser = Rule::prefer_path)]
+    pub prefer_path: Vec<Rule>,
+
+    /// Prefer keeping the most recently modified file [default: false]
+    #[arg(long, default_value = "false")]
+    pub prefer_newest: bool,
+
+    /// Prefer keeping the file closest to the scanned directory [default: false]
+    #[arg(long, default_value = "false")]
+    pub prefer_shallowest: bool,
+
+    /// Prefer keeping files whose names don't look like copies, such as 'IMG_001 (1).jpg',
+    /// 'file - Copy.docx' or 'photo_2.jpg' next to 'photo.jpg' [default: false]
+    #[arg(long, default_value = "false")]
+    pub prefer_original_name: bool,
+
+    /// After the report, suggest the copy of each group to keep, with the reasons: the
+    /// copy closest to the scanned directory, not in a backup or copy directory, and
+    /// modified first, as set by `suggestion_signals` in the config file [default: false]
+    #[arg(long, default_value = "false")]
+    pub suggest: bool,
+
+    /// Keep the suggested copy of each group, ahead of the --prefer-* rules, which then
+    /// only break ties. Implies --suggest [default: false]
+    #[arg(long, default_value = "false")]
+    pub accept_suggestions: bool,
+
+    /// Where to write the journal of changes made by --move-to, --delete, --hardlink or
+    /// --reflink
+    /// [default: the quarantine directory for --move-to, otherwise the platform data
+    /// directory, e.g. ~/.local/share/check-file-dups/journals]
+    #[arg(long, value_name = "FILE")]
+    pub journal: Option<PathBuf>,
+
+    /// Save the duplicate report as JSON to the given file, for later comparison
+    /// with --diff-against
+    #[arg(long, value_name = "FILE")]
+    pub save_report: Option<PathBuf>,
+
+    /// Compare the results against a report previously saved with --save-report,
+    /// showing new, resolved and changed duplicate groups
+    #[arg(long, value_name = "FILE")]
+    pub diff_against: Option<PathBuf>,
+
+    /// Write the members of each duplicate group to FILE as a BLAKE3 checksum manifest
+    /// in b3sum format, to check with `b3sum --check FILE` that they really are
+    /// identical before acting on them
+    #[arg(long, value_name = "FILE")]
+    pub emit_checksums: Option<PathBuf>,
+
+    /// Write every scanned file's path (relative to the scanned directory), size,
+    /// modification time and hash to FILE, as NDJSON if it ends in .ndjson, .jsonl or
+    /// .json and as CSV otherwise
+    #[arg(long, value_name = "FILE")]
+    pub export_inventory: Option<PathBuf>,
+
+    /// Post a JSON summary (duplicate count, wasted space, largest groups) to this http://
+    /// or https:// URL when the scan finishes or fails, for unattended scans. https URLs
+    /// are posted with curl
+    #[arg(long, value_name = "URL")]
+    pub notify_webhook: Option<String>,
+
+    /// Print how long each phase took (cache load, walk, hash, group, report, cache
+    /// save) and the hashing throughput at the end of the run. `json` writes them as one
+    /// JSON object to standard error, to track performance across versions
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "text"
+    )]
+    pub stats: Option<StatsFormat>,
+}
+
+impl Cli {
+    /// Builds the options from the parsed command line. `scan PATH` is the same as
+    /// `PATH`, so the options of `scan` take the place of those before it, and its
+    /// matches, which hold the global options too, are returned for telling what was set
+    /// on the command line.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the matches don't fit the options.
+    pub fn from_matches(matches: ArgMatches) -> Result<(Self, ArgMatches), clap::Error> {
+        let mut cli = Self::from_arg_matches(&matches)?;
+        match cli
+            .command
+            .take_if(|command| matches!(command, Command::Scan(_)))
+        {
+            Some(Command::Scan(scan)) => {
+                cli.scan = *scan;
+                let matches = matches
+                    .subcommand_matches("scan")
+                    .cloned()
+                    .unwrap_or_default();
+                Ok((cli, matches))
+            }
+            _ => Ok((cli, matches)),
+        }
+    }
+
+    /// Builds the filter of files and directories to leave out from `--skip-hidden` and
+    /// `--include-junk`.
+    pub fn name_filter(&self) -> NameFilter {
+        NameFilter {
+            skip_hidden: self.skip_hidden,
+            skip_junk: !self.include_junk,
+        }
+    }
+
+    /// Builds the retry policy from `--read-timeout` and `--retries`.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            read_timeout: self
+                .read_timeout
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs),
+            retries: self.retries,
+        }
+    }
+
+    /// Builds the read throttle from the `--throttle-*` options.
+    pub fn throttle(&self) -> Throttle {
+        Throttle::new(
+            self.throttle_rate,
+            Duration::from_millis(self.throttle_delay_ms),
+        )
+    }
+}
+
+impl ScanArgs {
+    /// Builds the modification time limits from `--newer-than` and `--older-than`.
+    pub fn modified_range(&self) -> ModifiedRange {
+        ModifiedRange {
+            newer_than: self.newer_than,
+            older_than: self.older_than,
+        }
+    }
+
+    /// Returns the directories to scan: the first path and any more paths after it.
+    pub fn scan_paths(&self) -> Vec<PathBuf> {
+        std::iter::once(&self.path)
+            .chain(&self.more_paths)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns what files must share to be reported as potential duplicates without
+    /// reading them, with --size-only, --match name-size or --hash none.
+    pub fn potential_match(&self) -> Option<PotentialMatch> {
+        if self.size_only {
+            Some(PotentialMatch::Size)
+        } else if self.hash_mode == HashMode::None {
+            Some(PotentialMatch::NameSizeMtime)
+        } else if self.match_mode == MatchMode::NameSize {
+            Some(PotentialMatch::NameSize)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if an action on duplicates is given: --move-to, --delete,
+    /// --delete-group, --hardlink or --reflink.
+    pub fn has_action(&self) -> bool {
+        self.move_to.is_some()
+            || self.delete
+            || !self.delete_group.is_empty()
+            || self.hardlink
+            || self.reflink
+    }
+
+    /// Returns `true` if the run will change files: an action is given and neither
+    /// previewed with --dry-run, nor written out with --emit-script or --write-manifest.
+    pub fn changes_files(&self) -> bool {
+        self.has_action()
+            && !self.dry_run
+            && self.emit_script.is_none()
+            && self.write_manifest.is_none()
+    }
+
+    /// Builds the hash cache limits from `--cache-max-entries` and `--cache-max-size`.
+    pub fn cache_limits(&self) -> CacheLimits {
+        CacheLimits {
+            max_entries: self.cache_max_entries,
+            max_size: self.cache_max_size,
+        }
+    }
+
+    /// Builds the hash cache's handling of changed modification times from
+    /// `--mtime-tolerance` and `--rehash-suspect`.
+    pub fn mtime_policy(&self) -> MtimePolicy {
+        MtimePolicy {
+            tolerance: self.mtime_tolerance.unwrap_or_default(),
+            rehash_suspect: self.rehash_suspect,
+        }
+    }
+
+    /// Builds the hash cache compression from `--cache-compression-level` and
+    /// `--cache-compression-threads`.
+    pub fn cache_compression(&self) -> CacheCompression {
+        CacheCompression {
+            level: self.cache_compression_level,
+            threads: self.cache_compression_threads,
+        }
+    }
+
+    /// Builds the scan limits from `--limit-files`, `--limit-bytes` and `--random-sample`.
+    pub fn scan_limit(&self) -> ScanLimit {
+        ScanLimit {
+            files: self.limit_files,
+            bytes: self.limit_bytes,
+            random: self.random_sample,
+        }
+    }
+
+    /// Number of directories to list at once, from `--walk-threads`.
+    pub fn walk_threads(&self) -> Threads {
+        self.walk_threads.unwrap_or_default()
+    }
+
+    /// Number of files to read at once, from `--io-threads` or `--threads`.
+    pub fn io_threads(&self) -> Threads {
+        self.io_threads.or(self.threads).unwrap_or_default()
+    }
+
+    /// Number of threads hashing each file, from `--hash-threads` or `--threads`.
+    pub fn hash_threads(&self) -> Threads {
+        self.hash_threads.or(self.threads).unwrap_or_default()
+    }
+
+    /// Builds the keep policy from the `--prefer-*` options, in the order they were
+    /// given on the command line. Returns `None` if no rules were given.
+    pub fn keep_policy(&self, matches: &ArgMatches) -> Option<KeepPolicy> {
+        let mut rules: Vec<(usize, Rule)> = Vec::new();
+        if let Some(indices) = matches.indices_of("prefer_path") {
+            rules.extend(indices.zip(self.prefer_path.iter().cloned()));
+        }
+        for (id, rule) in [
+            ("prefer_newest", Rule::PreferNewest),
+            ("prefer_shallowest", Rule::PreferShallowest),
+            ("prefer_original_name", Rule::PreferOriginalName),
+        ] {
+            if matches.get_flag(id)
+                && let Some(index) = matches.index_of(id)
+            {
+                rules.push((index, rule));
+            }
+        }
+        if rules.is_empty() {
+            return None;
+        }
+        rules.sort_by_key(|(index, _)| *index);
+        Some(KeepPolicy::new(
+            rules.into_iter().map(|(_, rule)| rule).collect(),
+        ))
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Scan directories for duplicates, the same as giving them without a command, e.g.
+    /// to scan a directory named like a command
+    Scan(Box<ScanArgs>),
+    /// Show a report saved by --save-report, or the results of the last scan, without
+    /// scanning again: the groups wasting the most space and the breakdown by file type
+    /// and extension
+    Report {
+        /// Report file to show
+        #[arg(required_unless_present = "last")]
+        file: Option<PathBuf>,
+        /// Show the results of the last scan of the base path, saved next to its hash
+        /// cache [default: false]
+        #[arg(long, default_value = "false", conflicts_with = "file")]
+        last: bool,
+        /// Order in which duplicate groups are shown
+        #[arg(long, value_enum, default_value_t = SortBy::Waste)]
+        sort_by: SortBy,
+        /// Only list the first N groups
+        #[arg(long, value_name = "N")]
+        top: Option<usize>,
+        /// Only list groups wasting at least this much space (e.g. 10M, 1.5GiB, 500KB)
+        #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "0")]
+        min_group_waste: u64,
+        /// Only list groups with at least K distinct copies
+        #[arg(long, value_name = "K", default_value = "2")]
+        min_copies: usize,
+        /// Also compare the report against an earlier one, listing new, resolved and
+        /// changed groups
+        #[arg(long, value_name = "FILE")]
+        diff_against: Option<PathBuf>,
+    },
+    /// Show the trend of duplication over the scans of a directory, recorded next to the
+    /// hash cache of the base path: files, groups, duplicates and wasted space per scan
+    History {
+        /// Directory whose scans to show [default: the directory of the last scan]
+        path: Option<PathBuf>,
+        /// Only show the last N scans
+        #[arg(long, value_name = "N", default_value = "20")]
+        limit: usize,
+    },
+    /// Inspect and maintain the hash cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommand,
+    },
+    /// Manage the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Revert the changes recorded in a journal written by --move-to, --delete or
+    /// --hardlink: moved files are moved back, deleted files are recreated from the kept
+    /// copy, and hardlinks are replaced by independent copies
+    Undo {
+        /// Journal file to undo
+        journal: PathBuf,
+    },
+    /// Carry out a manifest written by --write-manifest, possibly edited by hand. Every
+    /// file is hashed again first, and files that changed since are skipped. A journal
+    /// is written for `undo`
+    Apply {
+        /// Manifest file to carry out
+        manifest: PathBuf,
+        /// Where to write the journal of changes [default: the platform data directory,
+        /// e.g. ~/.local/share/check-file-dups/journals]
+        #[arg(long, value_name = "FILE")]
+        journal: Option<PathBuf>,
+        /// Move files to delete to the trash (the Recycle Bin on Windows) instead of
+        /// deleting them
+        #[arg(long)]
+        use_trash: bool,
+        /// What to do about the metadata of the files deleted or replaced
+        #[arg(long, value_enum, value_name = "POLICY", default_value = "record")]
+        metadata_policy: MetadataPolicy,
+    },
+    /// Merge the directory FROM into INTO, e.g. a backup folder into the folder it was
+    /// copied from: files identical to the file at the same path in INTO are deleted,
+    /// files missing from INTO are moved there, and files that differ are reported and
+    /// left in place. Emptied directories are removed. A journal is written for `undo`
+    MergeDirs {
+        /// Directory to merge into
+        into: PathBuf,
+        /// Directory to merge, emptied as far as possible
+        from: PathBuf,
+        /// Where to write the journal of changes [default: the platform data directory,
+        /// e.g. ~/.local/share/check-file-dups/journals]
+        #[arg(long, value_name = "FILE")]
+        journal: Option<PathBuf>,
+        /// Move identical files to the trash (the Recycle Bin on Windows) instead of
+        /// deleting them
+        #[arg(long)]
+        use_trash: bool,
+        /// Only show what would be moved and deleted, and the conflicts
+        #[arg(long)]
+        dry_run: bool,
+        /// Ask for confirmation on the terminal after showing the plan
+        #[arg(long, conflicts_with = "dry_run")]
+        confirm: bool,
+    },
+    /// Acknowledge duplicates as intentional, such as template copies, so later scans
+    /// leave them out: a group by its hash, wherever its copies are, or a set of files
+    /// as copies of each other, whatever their content. Lists the acknowledged groups
+    /// when given nothing
+    Acknowledge {
+        /// A group's hash, or two or more files
+        targets: Vec<PathBuf>,
+        /// Why the duplicates are intentional, shown when listing
+        #[arg(long, value_name = "TEXT")]
+        note: Option<String>,
+    },
+    /// Take back acknowledgements: of a group's hash, or every acknowledged set of files
+    /// that includes one of the given files
+    Unacknowledge {
+        /// A group's hash, or files
+        #[arg(required = true)]
+        targets: Vec<PathBuf>,
+    },
+    /// Register a directory of known good originals, such as a canonical photo library.
+    /// Its files are hashed, and later scans report files with the same contents anywhere
+    /// as redundant copies, and never delete or link the originals. Registering a
+    /// directory again replaces its files. Lists the registered directories when given
+    /// nothing
+    RegisterOriginals {
+        /// Directory of originals
+        path: Option<PathBuf>,
+    },
+    /// Hash the given files and list the identical copies found in the hash cache for
+    /// the base path
+    Lookup {
+        /// Files to look up
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Build or update a persistent index of the files in a reference location, such as
+    /// an archive on a NAS, for `check-against-index`. Indexing a location again replaces
+    /// its files; other locations in the index are kept
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    Index {
+        #[command(subcommand)]
+        action: Option<IndexCommand>,
+        /// Directory to index
+        #[arg(required = true)]
+        path: Option<PathBuf>,
+        /// Index file to use [default: check-file-dups-index.json.zst in the cache
+        /// directory]
+        #[arg(long, value_name = "FILE", global = true)]
+        index_file: Option<PathBuf>,
+    },
+    /// Hash the files in a directory and report those that already exist in the index
+    /// built by `index`, without walking the indexed locations again
+    CheckAgainstIndex {
+        /// Directory to check
+        #[arg(default_value = ".")]
+        path: PathBuf,
+        /// Index file to check against [default: check-file-dups-index.json.zst in the
+        /// cache directory]
+        #[arg(long, value_name = "FILE")]
+        index_file: Option<PathBuf>,
+        /// List the files not found in the index instead, e.g. to see what still needs
+        /// archiving
+        #[arg(long)]
+        missing: bool,
+    },
+    /// Copy the files of SRC into DEST, such as photos off a camera card into a library,
+    /// skipping those whose contents DEST already has, wherever they are in it. DEST is
+    /// scanned with its hash cache, and the duplicates skipped are listed. Files are not
+    /// copied over other files at the same path
+    Import {
+        /// Directory to import from
+        src: PathBuf,
+        /// Directory to import into
+        dest: PathBuf,
+        /// Move the files imported instead of copying them. Duplicates are left in SRC
+        #[arg(long = "move")]
+        move_files: bool,
+        /// Only show what would be copied and skipped
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Hash the files in a directory again and compare them with an inventory written by
+    /// --export-inventory, listing corrupted, modified, missing and new files. Exits with
+    /// an error if any inventoried file is corrupted, modified or missing
+    Verify {
+        /// Inventory to check against
+        inventory: PathBuf,
+        /// Directory the inventory's paths are relative to
+        #[arg(default_value = ".")]
+        path: PathBuf,
+        /// Trust the hash cache for files whose size and modification time are unchanged.
+        /// Much faster, but silent corruption goes unnoticed
+        #[arg(long)]
+        cached: bool,
+    },
+    /// Copy one instance of each distinct content in a directory into a store named by
+    /// hash, and write a manifest mapping each path to its store entry. Contents already
+    /// in the store are reused, so archiving again only adds what changed
+    Archive {
+        /// Directory to archive
+        #[arg(default_value = ".")]
+        path: PathBuf,
+        /// Directory of the store, created if missing
+        #[arg(long, value_name = "DIR")]
+        store: PathBuf,
+        /// Hardlink files into the store instead of copying them where possible. Takes no
+        /// space, but the store then changes along with the files
+        #[arg(long)]
+        link: bool,
+        /// Write the manifest here, as CSV, or as NDJSON if its name ends in .ndjson,
+        /// .jsonl or .json [default: manifests/NAME-TIMESTAMP.csv in the store]
+        #[arg(long, value_name = "FILE")]
+        manifest: Option<PathBuf>,
+    },
+    /// Watch a directory and report files whose content already exists elsewhere under
+    /// the base path as they appear. The directory is scanned first to warm the cache
+    Watch {
+        /// Directory to watch
+        #[arg(default_value = ".")]
+        path: PathBuf,
+        /// Milliseconds a file must go unchanged before it is hashed
+        #[arg(long, value_name = "MS", default_value_t = 500)]
+        settle_ms: u64,
+    },
+    /// Watch a directory like `watch`, and answer queries about the hash index over HTTP:
+    /// GET /stats, /duplicates and /lookup?hash=HASH return JSON, and /metrics returns
+    /// Prometheus metrics
+    Daemon {
+        /// Directory to watch
+        #[arg(default_value = ".")]
+        path: PathBuf,
+        /// Address to listen on. Use a loopback address unless the index may be read by
+        /// other machines
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:7878")]
+        listen: String,
+        /// Milliseconds a file must go unchanged before it is hashed
+        #[arg(long, value_name = "MS", default_value_t = 500)]
+        settle_ms: u64,
+    },
+    /// Hash samples of the files in a directory with 1, 2, 4, ... threads, bypassing the
+    /// cache, print the throughput of each, and recommend a --threads value for the disk
+    /// it is on
+    Bench {
+        /// Directory on the disk to benchmark
+        #[arg(default_value = ".")]
+        path: PathBuf,
+        /// Most threads to try [default: the number of CPU cores]
+        #[arg(long, value_name = "N")]
+        max_threads: Option<usize>,
+        /// Size of the files to hash with each number of threads
+        #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "256M")]
+        sample_size: u64,
+    },
+    /// Check the config file, the hash cache, the log and cache directories, free disk
+    /// space and hashing speed, and print what to do about any problem found
+    Doctor,
+    /// Developer tool: write a reproducible tree of files with a known duplicate
+    /// structure, for testing, demos and benchmarks
+    GenTestdata {
+        /// Directory to write the tree to. Must be empty or not exist yet
+        dir: PathBuf,
+        /// Number of files to write
+        #[arg(long, value_name = "N", default_value_t = 1000)]
+        files: usize,
+        /// Share of the files that are copies of another file, from 0 up to but not
+        /// including 1
+        #[arg(long, value_name = "R", default_value_t = 0.3)]
+        dup_ratio: f64,
+        /// Seed of the random choices; the same options always write the same tree
+        #[arg(long, value_name = "S", default_value_t = 0)]
+        seed: u64,
+        /// Largest file size
+        #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "1M")]
+        max_size: u64,
+    },
+    /// Write a shell completion script, to standard output or into a directory
+    Completions {
+        /// Shell to complete in
+        shell: Shell,
+        /// Directory to write the script to, named as the shell expects
+        #[arg(long, value_name = "DIR")]
+        dir: Option<PathBuf>,
+    },
+    /// Write the man page in roff, to standard output or into a directory
+    Man {
+        /// Directory to write check-file-dups.1 to
+        #[arg(long, value_name = "DIR")]
+        dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// Show the number of entries, the total size they represent, and the oldest and
+    /// newest entries
+    Stats,
+    /// List all cache entries (path, size, modification time and hash)
+    List,
+    /// Show the cached entry for a file, and whether it is still valid
+    Get {
+        /// File to look up
+        path: PathBuf,
+    },
+    /// Remove the cached entry for a file, forcing it to be rehashed on the next scan
+    Remove {
+        /// File whose entry to remove
+        path: PathBuf,
+    },
+    /// Export the cache as a checksum manifest, with paths relative to the base path
+    Export {
+        /// Manifest format
+        #[arg(long, value_enum, default_value_t = ChecksumFormat::Blake3)]
+        format: ChecksumFormat,
+        /// File to write the manifest to [default: standard output]
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Merge cache files, such as caches built on other machines against the same files
+    /// mounted elsewhere, into one. Entries are matched by their path relative to the
+    /// base path; where two differ, the one for the newer modification time is kept
+    Merge {
+        /// Cache files to merge
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        /// Cache file to write, merged into if it exists
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+    },
+    /// Seed the cache from a b3sum-compatible manifest of BLAKE3 hashes, or from an SFV
+    /// file (ending in .sfv), whose files are read to check their CRC-32 and hashed.
+    /// Paths are resolved relative to the base path and the listed files must exist
+    Import {
+        /// Manifest file to import
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum IndexCommand {
+    /// Write the index as a compact binary manifest of hashes, sizes and relative paths,
+    /// to copy to another machine and import there
+    Export {
+        /// Manifest file to write
+        file: PathBuf,
+    },
+    /// Add the locations in a manifest written by `index export` on another machine to
+    /// the index, replacing those imported under the same name before. Use
+    /// `check-against-index` to find local files that exist on the other machine
+    Import {
+        /// Manifest file to read
+        file: PathBuf,
+        /// Name to prefix the imported locations with, e.g. the other machine's name
+        /// [default: the manifest's file name without extension]
+        #[arg(long, value_name = "NAME")]
+        name: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Write a commented config template
+    Init {
+        /// File to write [default: check-file-dups.toml in the platform config directory,
+        /// e.g. ~/.config/check-file-dups]
+        path: Option<PathBuf>,
+        /// Overwrite the file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        let matches = Cli::command().try_get_matches_from(args)?;
+        Cli::from_matches(matches).map(|(cli, _)| cli)
+    }
+
+    #[test]
+    fn delete_group_conflicts_with_other_actions() {
+        let cli = parse(&["check-file-dups", "/data", "--delete-group", "3f9a0c51d2e7"]).unwrap();
+        assert_eq!(cli.scan.delete_group.len(), 1);
+        for other in [
+            &["--move-to", "/quarantine"][..],
+            &["--hardlink"],
+            &["--reflink"],
+        ] {
+            let args = [
+                &["check-file-dups", "/data", "--delete-group", "3f9a0c51d2e7"],
+                other,
+            ]
+            .concat();
+            let error = parse(&args).err().unwrap();
+            assert_eq!(
+                error.kind(),
+                clap::error::ErrorKind::ArgumentConflict,
+                "{:?}",
+                other
+            );
+        }
+    }
+
+    #[test]
+    fn ignore_empty_takes_an_optional_value() {
+        let ignore_empty = |args: &[&str]| {
+            let args = [&["check-file-dups"], args].concat();
+            parse(&args).unwrap().scan.ignore_empty
+        };
+        assert!(ignore_empty(&["/data"]));
+        assert!(ignore_empty(&["--ignore-empty", "/data"]));
+        assert!(ignore_empty(&["/data", "--ignore-empty"]));
+        assert!(!ignore_empty(&["/data", "--ignore-empty=false"]));
+    }
 }
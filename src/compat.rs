@@ -0,0 +1,318 @@
+//! Output in the formats of other duplicate finders and of `b3sum`, so scripts and tools
+//! built around them can consume the results directly.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::Result;
+use log::warn;
+use serde_json::json;
+
+use crate::FileInfo;
+use crate::checksums::format_line;
+use crate::content_hash::ContentHash;
+use crate::duplicates::{ReportOptions, distinct_copies, wasted_space};
+use crate::paths::{FilePath, path_bytes};
+use crate::scan_root::exported_path;
+
+/// Returns the groups to write, largest waste first, each with its members in path
+/// order. Groups whose members are all hardlinks of one file are left out, and the
+/// `top`, `min_group_waste` and `min_copies` options apply.
+fn shown_groups<'a>(
+    duplicates: &'a HashMap<ContentHash, Vec<FileInfo>>,
+    options: &ReportOptions,
+) -> Vec<(&'a ContentHash, Vec<&'a FileInfo>)> {
+    let mut groups: Vec<(u64, &ContentHash, Vec<&FileInfo>)> = duplicates
+        .iter()
+        .filter(|(_, files)| distinct_copies(files) > 1 && options.shows(files))
+        .map(|(hash, files)| {
+            let mut members: Vec<&FileInfo> = files.iter().collect();
+            members.sort_by(|a, b| a.path.cmp(&b.path));
+            (wasted_space(files, options), hash, members)
+        })
+        .collect();
+    groups.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    groups.truncate(options.top.unwrap_or(usize::MAX));
+    groups
+        .into_iter()
+        .map(|(_, hash, members)| (hash, members))
+        .collect()
+}
+
+/// Writes the duplicate groups like `fdupes -r`: the paths of each group one per line,
+/// with a blank line after each group. Returns the number of groups written.
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_fdupes(
+    duplicates: &HashMap<ContentHash, Vec<FileInfo>>,
+    options: &ReportOptions,
+    writer: &mut dyn Write,
+) -> Result<usize> {
+    let groups = shown_groups(duplicates, options);
+    for (_, members) in &groups {
+        for file in members {
+            writeln!(
+                writer,
+                "{}",
+                exported_path(&file.path.to_path_buf()).display()
+            )?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(groups.len())
+}
+
+/// Writes the duplicate groups like `write_fdupes`, but with each path followed by a NUL
+/// byte instead of a newline, and `group_separator` followed by a NUL byte after each
+/// group, so the paths can be read safely whatever characters they contain (e.g. with
+/// `xargs -0`). Paths are written as the bytes the system uses. Returns the number of
+/// groups written.
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_print0(
+    duplicates: &HashMap<ContentHash, Vec<FileInfo>>,
+    options: &ReportOptions,
+    group_separator: &str,
+    writer: &mut dyn Write,
+) -> Result<usize> {
+    let groups = shown_groups(duplicates, options);
+    for (_, members) in &groups {
+        for file in members {
+            writer.write_all(&path_bytes(&exported_path(&file.path.to_path_buf())))?;
+            writer.write_all(b"\0")?;
+        }
+        writer.write_all(group_separator.as_bytes())?;
+        writer.write_all(b"\0")?;
+    }
+    writer.flush()?;
+    Ok(groups.len())
+}
+
+/// Writes the duplicate groups as a BLAKE3 checksum manifest in `b3sum` format, one
+/// `<hash>  <path>` line per member, group after group, so `b3sum --check` can confirm
+/// independently that every member really has the contents of its group before acting
+/// on them. Returns the number of groups written.
+///
+/// # Errors
+///
+/// Returns an error if writing fails. Members whose path is not valid UTF-8 are logged
+/// and left out, since `b3sum` could not find them.
+pub fn write_checksums(
+    duplicates: &HashMap<ContentHash, Vec<FileInfo>>,
+    options: &ReportOptions,
+    writer: &mut dyn Write,
+) -> Result<usize> {
+    let groups = shown_groups(duplicates, options);
+    for (hash, members) in &groups {
+        let hash = hash.to_string();
+        for file in members {
+            let path = exported_path(&file.path.to_path_buf()).into_owned();
+            match path.to_str() {
+                Some(path) => writeln!(writer, "{}", format_line(&hash, path))?,
+                None => warn!(
+                    "Leaving '{}' out of the checksums: path is not valid UTF-8",
+                    path.display()
+                ),
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(groups.len())
+}
+
+/// Returns the header object that starts rmlint's `json` output.
+fn rmlint_header() -> serde_json::Value {
+    json!({
+        "description": "rmlint json-dump of lint files",
+        "cwd": std::env::current_dir().unwrap_or_default(),
+        "args": std::env::args().collect::<Vec<_>>().join(" "),
+        "version": env!("CARGO_PKG_VERSION"),
+        "progress": 0,
+        "checksum_type": "blake3",
+    })
+}
+
+/// Returns the modification time of `file` in seconds, as rmlint writes it.
+fn rmlint_mtime(file: &FileInfo) -> f64 {
+    std::fs::metadata(file.path.to_path_buf())
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0.0, |mtime| mtime.as_secs_f64())
+}
+
+/// Writes the duplicate groups like rmlint's `json` formatter: an array holding a
+/// header object, one object per file of type `duplicate_file`, and a footer object
+/// with totals. The first file of each group in path order is marked as the original.
+/// Returns the number of groups written.
+///
+/// # Errors
+///
+/// Returns an error if serialization or writing fails.
+pub fn write_rmlint(
+    duplicates: &HashMap<ContentHash, Vec<FileInfo>>,
+    options: &ReportOptions,
+    writer: &mut dyn Write,
+) -> Result<usize> {
+    let groups = shown_groups(duplicates, options);
+    let total_files: usize = groups.iter().map(|(_, members)| members.len()).sum();
+
+    let mut objects = vec![rmlint_header()];
+    let (mut duplicates_count, mut lint_size) = (0usize, 0u64);
+    for (hash, members) in &groups {
+        for (i, file) in members.iter().enumerate() {
+            let is_original = i == 0;
+            if !is_original {
+                duplicates_count += 1;
+                lint_size += file.size;
+            }
+            objects.push(json!({
+                "id": objects.len(),
+                "type": "duplicate_file",
+                "progress": objects.len() * 100 / total_files.max(1),
+                "checksum": hash,
+                "path": FilePath::from(&*exported_path(&file.path.to_path_buf())),
+                "size": file.size,
+                "depth": file.path.components().count(),
+                "inode": file.file_id.map_or(0, |(_, inode)| inode),
+                "disk_id": file.file_id.map_or(0, |(device, _)| device),
+                "is_original": is_original,
+                "mtime": rmlint_mtime(file),
+            }));
+        }
+    }
+    objects.push(json!({
+        "aborted": false,
+        "progress": 100,
+        "total_files": total_files,
+        "ignored_files": 0,
+        "ignored_folders": 0,
+        "duplicates": duplicates_count,
+        "duplicate_sets": groups.len(),
+        "total_lint_size": lint_size,
+    }));
+
+    serde_json::to_writer_pretty(&mut *writer, &objects)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(groups.len())
+}
+
+/// Writes files without duplicates (see [`crate::unique`]) like rmlint's `json`
+/// formatter writes unique files: one object of type `unique_file` per file between the
+/// header and the footer. Returns the number of files written.
+///
+/// # Errors
+///
+/// Returns an error if serialization or writing fails.
+pub fn write_rmlint_unique(unique: &[FileInfo], writer: &mut dyn Write) -> Result<usize> {
+    let mut objects = vec![rmlint_header()];
+    for file in unique {
+        objects.push(json!({
+            "id": objects.len(),
+            "type": "unique_file",
+            "progress": objects.len() * 100 / unique.len().max(1),
+            "checksum": file.hash,
+            "path": FilePath::from(&*exported_path(&file.path.to_path_buf())),
+            "size": file.size,
+            "depth": file.path.components().count(),
+            "inode": file.file_id.map_or(0, |(_, inode)| inode),
+            "disk_id": file.file_id.map_or(0, |(device, _)| device),
+            "is_original": true,
+            "mtime": rmlint_mtime(file),
+        }));
+    }
+    objects.push(json!({
+        "aborted": false,
+        "progress": 100,
+        "total_files": unique.len(),
+        "ignored_files": 0,
+        "ignored_folders": 0,
+        "duplicates": 0,
+        "duplicate_sets": 0,
+        "total_lint_size": 0,
+    }));
+
+    serde_json::to_writer_pretty(&mut *writer, &objects)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(unique.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn duplicates() -> HashMap<ContentHash, Vec<FileInfo>> {
+        let file = |path: &str, size: u64| FileInfo::for_test(path, size, ContentHash::of(b""));
+        HashMap::from([
+            (
+                ContentHash::of(b"small"),
+                vec![file("b/1", 1), file("a/1", 1)],
+            ),
+            (
+                ContentHash::of(b"large"),
+                vec![file("c/2", 5), file("a/2", 5), file("b/2", 5)],
+            ),
+            (ContentHash::of(b"single"), vec![file("d", 9)]),
+        ])
+    }
+
+    #[test]
+    fn writes_fdupes_groups() {
+        let mut output = Vec::new();
+        let written = write_fdupes(&duplicates(), &ReportOptions::default(), &mut output).unwrap();
+        assert_eq!(written, 2);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a/2\nb/2\nc/2\n\na/1\nb/1\n\n"
+        );
+    }
+
+    #[test]
+    fn writes_nul_delimited_groups() {
+        let mut output = Vec::new();
+        let options = ReportOptions::default();
+        assert_eq!(
+            write_print0(&duplicates(), &options, "", &mut output).unwrap(),
+            2
+        );
+        assert_eq!(output, b"a/2\0b/2\0c/2\0\0a/1\0b/1\0\0");
+
+        let mut output = Vec::new();
+        write_print0(&duplicates(), &options, "--", &mut output).unwrap();
+        assert_eq!(output, b"a/2\0b/2\0c/2\0--\0a/1\0b/1\0--\0");
+    }
+
+    #[test]
+    fn writes_b3sum_checksums() {
+        let mut output = Vec::new();
+        let written =
+            write_checksums(&duplicates(), &ReportOptions::default(), &mut output).unwrap();
+        assert_eq!(written, 2);
+        let (large, small) = (ContentHash::of(b"large"), ContentHash::of(b"small"));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("{large}  a/2\n{large}  b/2\n{large}  c/2\n{small}  a/1\n{small}  b/1\n")
+        );
+    }
+
+    #[test]
+    fn writes_rmlint_json() {
+        let mut output = Vec::new();
+        write_rmlint(&duplicates(), &ReportOptions::default(), &mut output).unwrap();
+        let objects: Vec<serde_json::Value> = serde_json::from_slice(&output).unwrap();
+        assert_eq!(objects.len(), 7);
+        assert_eq!(objects[1]["path"], "a/2");
+        assert_eq!(objects[1]["is_original"], true);
+        assert_eq!(objects[2]["is_original"], false);
+        assert_eq!(objects[6]["duplicates"], 3);
+        assert_eq!(objects[6]["duplicate_sets"], 2);
+        assert_eq!(objects[6]["total_lint_size"], 11);
+    }
+}
@@ -0,0 +1,514 @@
+//! Shell completion scripts and the man page, generated from the command-line
+//! definition in [`crate::cli`] so they never fall behind it.
+
+use std::io::{self, Write};
+
+use clap::builder::PossibleValue;
+use clap::{Arg, Command, ValueEnum};
+
+/// Shells that `completions` writes scripts for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    /// Bash, sourced from bash-completion's completions directory
+    Bash,
+    /// Zsh, through its bash completion compatibility
+    Zsh,
+    /// Fish
+    Fish,
+    /// PowerShell, dot-sourced from the profile
+    Powershell,
+}
+
+impl Shell {
+    /// Returns the name of the completion file for `bin`, as each shell looks for it.
+    pub fn file_name(self, bin: &str) -> String {
+        match self {
+            Self::Bash => bin.to_string(),
+            Self::Zsh => format!("_{}", bin),
+            Self::Fish => format!("{}.fish", bin),
+            Self::Powershell => format!("_{}.ps1", bin),
+        }
+    }
+}
+
+/// A command or subcommand, with the subcommand names leading to it.
+struct Level<'a> {
+    path: Vec<&'a str>,
+    command: &'a Command,
+}
+
+impl Level<'_> {
+    /// Returns the path as `/cache/stats`, or an empty string for the top level.
+    fn key(&self) -> String {
+        self.path.iter().map(|name| format!("/{}", name)).collect()
+    }
+
+    fn subcommands(&self) -> Vec<&str> {
+        self.command
+            .get_subcommands()
+            .filter(|sub| !sub.is_hide_set())
+            .map(Command::get_name)
+            .collect()
+    }
+
+    fn options(&self) -> impl Iterator<Item = &Arg> {
+        self.command
+            .get_arguments()
+            .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+    }
+}
+
+/// Returns `command` and all its subcommands, parents first. `command` must be built.
+fn levels(command: &Command) -> Vec<Level<'_>> {
+    let mut levels = vec![Level {
+        path: Vec::new(),
+        command,
+    }];
+    let mut i = 0;
+    while i < levels.len() {
+        let children: Vec<Level> = levels[i]
+            .command
+            .get_subcommands()
+            .filter(|sub| !sub.is_hide_set())
+            .map(|sub| {
+                let mut path = levels[i].path.clone();
+                path.push(sub.get_name());
+                Level { path, command: sub }
+            })
+            .collect();
+        levels.extend(children);
+        i += 1;
+    }
+    levels
+}
+
+/// Returns the `--long` and `-s` spellings of an option.
+fn flags(arg: &Arg) -> Vec<String> {
+    let mut flags = Vec::new();
+    if let Some(long) = arg.get_long() {
+        flags.push(format!("--{}", long));
+    }
+    if let Some(short) = arg.get_short() {
+        flags.push(format!("-{}", short));
+    }
+    flags
+}
+
+/// Returns the values an option accepts, if it takes a value from a fixed set.
+fn values(arg: &Arg) -> Vec<String> {
+    if !arg.get_action().takes_values() {
+        return Vec::new();
+    }
+    arg.get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect()
+}
+
+/// Returns the first line of an option's or subcommand's help.
+fn summary(help: Option<&clap::builder::StyledStr>) -> String {
+    help.map(|help| help.to_string())
+        .and_then(|help| help.lines().next().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Writes the completion script for `shell`.
+pub fn write_completions(
+    command: &mut Command,
+    shell: Shell,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    command.build();
+    let bin = command.get_name().to_string();
+    let levels = levels(command);
+    match shell {
+        Shell::Bash => write_bash(&bin, &levels, writer),
+        Shell::Zsh => {
+            writeln!(writer, "#compdef {}", bin)?;
+            writeln!(writer, "autoload -U +X bashcompinit && bashcompinit")?;
+            write_bash(&bin, &levels, writer)
+        }
+        Shell::Fish => write_fish(&bin, &levels, writer),
+        Shell::Powershell => write_powershell(&bin, &levels, writer),
+    }
+}
+
+fn write_bash(bin: &str, levels: &[Level], writer: &mut dyn Write) -> io::Result<()> {
+    let function = format!("_{}", bin.replace('-', "_"));
+    let paths: Vec<String> = levels[1..].iter().map(Level::key).collect();
+    writeln!(writer, "{}() {{", function)?;
+    writeln!(writer, "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"")?;
+    writeln!(writer, "    local prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"")?;
+    writeln!(writer, "    local path=\"\" word words")?;
+    writeln!(
+        writer,
+        "    for word in \"${{COMP_WORDS[@]:1:COMP_CWORD-1}}\"; do"
+    )?;
+    writeln!(writer, "        case \"${{path}}/${{word}}\" in")?;
+    if !paths.is_empty() {
+        writeln!(
+            writer,
+            "            {}) path=\"${{path}}/${{word}}\" ;;",
+            paths.join("|")
+        )?;
+    }
+    writeln!(writer, "        esac")?;
+    writeln!(writer, "    done")?;
+
+    writeln!(writer, "    case \"${{path}} ${{prev}}\" in")?;
+    for level in levels {
+        for arg in level.options() {
+            let values = values(arg);
+            if values.is_empty() {
+                continue;
+            }
+            for flag in flags(arg) {
+                writeln!(
+                    writer,
+                    "        \"{} {}\") COMPREPLY=($(compgen -W \"{}\" -- \"${{cur}}\")); return ;;",
+                    level.key(),
+                    flag,
+                    values.join(" ")
+                )?;
+            }
+        }
+    }
+    writeln!(writer, "    esac")?;
+
+    writeln!(writer, "    case \"${{path}}\" in")?;
+    for level in levels {
+        let options: Vec<String> = level.options().flat_map(flags).collect();
+        writeln!(writer, "        \"{}\")", level.key())?;
+        writeln!(writer, "            if [[ ${{cur}} == -* ]]; then")?;
+        writeln!(writer, "                words=\"{}\"", options.join(" "))?;
+        writeln!(writer, "            else")?;
+        writeln!(
+            writer,
+            "                words=\"{}\"",
+            level.subcommands().join(" ")
+        )?;
+        writeln!(writer, "            fi ;;")?;
+    }
+    writeln!(writer, "    esac")?;
+    writeln!(
+        writer,
+        "    COMPREPLY=($(compgen -W \"${{words}}\" -- \"${{cur}}\"))"
+    )?;
+    writeln!(writer, "}}")?;
+    writeln!(writer, "complete -o default -F {} {}", function, bin)
+}
+
+/// Quotes `s` for fish between single quotes.
+fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn write_fish(bin: &str, levels: &[Level], writer: &mut dyn Write) -> io::Result<()> {
+    for level in levels {
+        // Within a level, its own subcommands must not have been given yet
+        let mut conditions: Vec<String> = level
+            .path
+            .iter()
+            .map(|name| format!("__fish_seen_subcommand_from {}", name))
+            .collect();
+        let subcommands = level.subcommands();
+        if !subcommands.is_empty() {
+            conditions.push(format!(
+                "not __fish_seen_subcommand_from {}",
+                subcommands.join(" ")
+            ));
+        }
+        let condition = fish_quote(&conditions.join("; and "));
+        for sub in level
+            .command
+            .get_subcommands()
+            .filter(|sub| !sub.is_hide_set())
+        {
+            writeln!(
+                writer,
+                "complete -c {} -n {} -f -a {} -d {}",
+                bin,
+                condition,
+                sub.get_name(),
+                fish_quote(&summary(sub.get_about()))
+            )?;
+        }
+        for arg in level.options() {
+            let mut line = format!("complete -c {} -n {}", bin, condition);
+            if let Some(long) = arg.get_long() {
+                line.push_str(&format!(" -l {}", long));
+            }
+            if let Some(short) = arg.get_short() {
+                line.push_str(&format!(" -s {}", short));
+            }
+            let values = values(arg);
+            if !values.is_empty() {
+                line.push_str(&format!(" -x -a {}", fish_quote(&values.join(" "))));
+            } else if arg.get_action().takes_values() {
+                line.push_str(" -r");
+            }
+            line.push_str(&format!(" -d {}", fish_quote(&summary(arg.get_help()))));
+            writeln!(writer, "{}", line)?;
+        }
+    }
+    Ok(())
+}
+
+/// Quotes `s` for PowerShell between single quotes.
+fn powershell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn write_powershell(bin: &str, levels: &[Level], writer: &mut dyn Write) -> io::Result<()> {
+    let list = |words: Vec<String>| {
+        words
+            .iter()
+            .map(|word| powershell_quote(word))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    writeln!(
+        writer,
+        "Register-ArgumentCompleter -Native -CommandName {} -ScriptBlock {{",
+        powershell_quote(bin)
+    )?;
+    writeln!(
+        writer,
+        "    param($wordToComplete, $commandAst, $cursorPosition)"
+    )?;
+    writeln!(
+        writer,
+        "    $subcommands = @({})",
+        list(levels[1..].iter().map(Level::key).collect())
+    )?;
+    writeln!(writer, "    $path = ''")?;
+    writeln!(writer, "    $prev = ''")?;
+    writeln!(
+        writer,
+        "    foreach ($element in $commandAst.CommandElements | Select-Object -Skip 1) {{"
+    )?;
+    writeln!(
+        writer,
+        "        if ($element.Extent.EndOffset -ge $cursorPosition) {{ break }}"
+    )?;
+    writeln!(writer, "        $prev = $element.ToString()")?;
+    writeln!(
+        writer,
+        "        if ($subcommands -contains \"$path/$prev\") {{ $path = \"$path/$prev\" }}"
+    )?;
+    writeln!(writer, "    }}")?;
+    writeln!(writer, "    $completions = switch (\"$path $prev\") {{")?;
+    for level in levels {
+        for arg in level.options() {
+            let values = values(arg);
+            if values.is_empty() {
+                continue;
+            }
+            for flag in flags(arg) {
+                writeln!(
+                    writer,
+                    "        {} {{ @({}); break }}",
+                    powershell_quote(&format!("{} {}", level.key(), flag)),
+                    list(values.clone())
+                )?;
+            }
+        }
+    }
+    writeln!(writer, "        default {{")?;
+    writeln!(writer, "            switch ($path) {{")?;
+    for level in levels {
+        let mut words: Vec<String> = level.subcommands().iter().map(|s| s.to_string()).collect();
+        words.extend(level.options().flat_map(flags));
+        writeln!(
+            writer,
+            "                {} {{ @({}) }}",
+            powershell_quote(&level.key()),
+            list(words)
+        )?;
+    }
+    writeln!(writer, "            }}")?;
+    writeln!(writer, "        }}")?;
+    writeln!(writer, "    }}")?;
+    writeln!(
+        writer,
+        "    $completions | Where-Object {{ $_ -like \"$wordToComplete*\" }} | ForEach-Object {{"
+    )?;
+    writeln!(
+        writer,
+        "        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)"
+    )?;
+    writeln!(writer, "    }}")?;
+    writeln!(writer, "}}")
+}
+
+/// Escapes `s` for roff, so backslashes, hyphens and leading dots print as written.
+fn roff_escape(s: &str) -> String {
+    s.lines()
+        .map(|line| {
+            let line = line.replace('\\', "\\e").replace('-', "\\-");
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{}", line)
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Returns the placeholder for an option's value, e.g. `<N|auto>`.
+fn value_placeholder(arg: &Arg) -> Option<String> {
+    if !arg.get_action().takes_values() {
+        return None;
+    }
+    let names = arg
+        .get_value_names()
+        .map(|names| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_else(|| vec![arg.get_id().as_str().to_uppercase()]);
+    Some(
+        names
+            .iter()
+            .map(|name| format!("<{}>", name))
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Writes the man page of `command` and its subcommands, in roff for section 1.
+pub fn write_man_page(command: &mut Command, writer: &mut dyn Write) -> io::Result<()> {
+    command.build();
+    let bin = command.get_name().to_string();
+    let version = env!("CARGO_PKG_VERSION");
+    writeln!(
+        writer,
+        ".TH {} 1 \"\" \"{} {}\"",
+        roff_escape(&bin.to_uppercase()),
+        roff_escape(&bin),
+        version
+    )?;
+    writeln!(writer, ".SH NAME")?;
+    writeln!(
+        writer,
+        "{} \\- {}",
+        roff_escape(&bin),
+        roff_escape(&summary(command.get_about()))
+    )?;
+    writeln!(writer, ".SH SYNOPSIS")?;
+    writeln!(
+        writer,
+        "\\fB{}\\fR [\\fIOPTIONS\\fR] [\\fIPATH\\fR]",
+        roff_escape(&bin)
+    )?;
+    writeln!(writer, ".br")?;
+    writeln!(
+        writer,
+        "\\fB{}\\fR \\fICOMMAND\\fR [\\fIOPTIONS\\fR]",
+        roff_escape(&bin)
+    )?;
+
+    let levels = levels(command);
+    for level in &levels {
+        if level.command.is_hide_set() {
+            continue;
+        }
+        if level.path.is_empty() {
+            writeln!(writer, ".SH OPTIONS")?;
+        } else {
+            writeln!(
+                writer,
+                ".SH \"{}\"",
+                roff_escape(&level.path.join(" ").to_uppercase())
+            )?;
+            if let Some(about) = level.command.get_long_about().or(level.command.get_about()) {
+                writeln!(writer, "{}", roff_escape(&about.to_string()))?;
+            }
+        }
+        // Global options are only described at the top level
+        for arg in level
+            .command
+            .get_arguments()
+            .filter(|arg| !arg.is_hide_set() && (level.path.is_empty() || !arg.is_global_set()))
+        {
+            writeln!(writer, ".TP")?;
+            let name = if arg.is_positional() {
+                format!(
+                    "[\\fI{}\\fR]",
+                    roff_escape(&arg.get_id().as_str().to_uppercase())
+                )
+            } else {
+                let flags = flags(arg)
+                    .iter()
+                    .rev()
+                    .map(|flag| format!("\\fB{}\\fR", roff_escape(flag)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                match value_placeholder(arg) {
+                    Some(value) => format!("{} \\fI{}\\fR", flags, roff_escape(&value)),
+                    None => flags,
+                }
+            };
+            writeln!(writer, "{}", name)?;
+            let help = arg
+                .get_long_help()
+                .or(arg.get_help())
+                .map(|help| help.to_string())
+                .unwrap_or_default();
+            writeln!(writer, "{}", roff_escape(&help))?;
+            let values: Vec<PossibleValue> = if arg.get_action().takes_values() {
+                arg.get_possible_values()
+                    .into_iter()
+                    .filter(|value| !value.is_hide_set())
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            if !values.is_empty() {
+                let values: Vec<&str> = values.iter().map(PossibleValue::get_name).collect();
+                writeln!(writer, ".br")?;
+                writeln!(
+                    writer,
+                    "Possible values: {}",
+                    roff_escape(&values.join(", "))
+                )?;
+            }
+        }
+    }
+    writeln!(writer, ".SH VERSION")?;
+    writeln!(writer, "v{}", version)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+    use crate::cli::Cli;
+
+    #[test]
+    fn generates_from_the_command_line_definition() {
+        let mut bash = Vec::new();
+        write_completions(&mut Cli::command(), Shell::Bash, &mut bash).unwrap();
+        let bash = String::from_utf8(bash).unwrap();
+        assert!(bash.contains("complete -o default -F _check_file_dups check-file-dups"));
+        assert!(bash.contains("/cache/stats"));
+        assert!(bash.contains("\" --output-format\") COMPREPLY=($(compgen -W \"text html"));
+
+        let mut fish = Vec::new();
+        write_completions(&mut Cli::command(), Shell::Fish, &mut fish).unwrap();
+        let fish = String::from_utf8(fish).unwrap();
+        assert!(fish.contains("-l min-size -r"));
+
+        let mut man = Vec::new();
+        write_man_page(&mut Cli::command(), &mut man).unwrap();
+        let man = String::from_utf8(man).unwrap();
+        assert!(man.starts_with(".TH CHECK\\-FILE\\-DUPS 1"));
+        assert!(man.contains("\\fB\\-\\-min\\-size\\fR \\fI<SIZE>\\fR"));
+        assert!(man.contains(".SH \"CACHE STATS\""));
+    }
+}
@@ -0,0 +1,971 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::ArgMatches;
+use clap::parser::ValueSource;
+use serde::{Deserialize, Serialize};
+
+use crate::cache::CacheFormat;
+use crate::cli::Cli;
+use crate::duplicates::{OutputFormat, SortBy};
+use crate::file_type::FileType;
+use crate::json_log::LogFormat;
+use crate::notify::SmtpConfig;
+use crate::progress::ProgressFormat;
+use crate::scanner::Threads;
+use crate::stats::StatsFormat;
+use crate::utils::{parse_duration, parse_size, parse_time};
+
+/// Commented template written by `config init`.
+pub const CONFIG_TEMPLATE: &str = include_str!("../check-file-dups.example.toml");
+
+/// Configuration structure for storing base path, skip directories and cache location.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default = "default_base_path")]
+    pub base_path: String,
+    /// Directories to scan when none are given on the command line.
+    #[serde(default)]
+    pub paths: Vec<PathBuf>,
+    #[serde(default)]
+    pub skip_dirs: Vec<String>,
+    /// Directory to store hash cache files in (defaults to the platform cache directory).
+    #[serde(default)]
+    pub cache_dir: Option<PathBuf>,
+    /// File listing acknowledged duplicate groups (defaults to the platform data directory).
+    #[serde(default)]
+    pub acknowledged_file: Option<PathBuf>,
+    /// File listing the registered originals (defaults to the platform data directory).
+    #[serde(default)]
+    pub originals_file: Option<PathBuf>,
+    /// Rules for choosing which duplicate to keep, used when no `--prefer-*` options are given.
+    #[serde(default)]
+    pub prefer: Vec<String>,
+    /// Directories whose copies are kept first, in order of priority, ahead of the
+    /// `prefer` rules.
+    #[serde(default)]
+    pub priority_dirs: Vec<PathBuf>,
+    /// Signals weighed to suggest the copy to keep, all of them if empty.
+    #[serde(default)]
+    pub suggestion_signals: Vec<String>,
+    /// Globs of files that are never deleted, moved or replaced, added to `--protect`.
+    #[serde(default)]
+    pub protect: Vec<String>,
+    /// File to append the log to (defaults to the platform data directory).
+    #[serde(default)]
+    pub log_file: Option<PathBuf>,
+    /// Disable the log file.
+    #[serde(default)]
+    pub no_log_file: bool,
+    /// Mail server to email a summary through when a scan finishes or fails.
+    #[serde(default)]
+    pub smtp: Option<SmtpConfig>,
+    /// Named sets of keys, selected with `--profile`, that replace the keys above.
+    #[serde(default)]
+    pub profile: BTreeMap<String, toml::Table>,
+
+    // The remaining keys mirror the command-line options of the same name. Options given
+    // on the command line take precedence; see [`Config::apply_to`].
+    /// Number of threads for both reading and hashing, a number or `"auto"`.
+    pub threads: Option<Threads>,
+    /// Number of directories to list at once, a number or `"auto"`.
+    pub walk_threads: Option<Threads>,
+    /// Number of files to read at once, a number or `"auto"`.
+    pub io_threads: Option<Threads>,
+    /// Number of threads hashing each file, a number or `"auto"`.
+    pub hash_threads: Option<Threads>,
+    /// Memory for finding duplicate groups, e.g. `"2G"`.
+    pub memory_budget: Option<String>,
+    /// Maximum read rate in bytes per second, e.g. `"20M"`.
+    pub throttle_rate: Option<String>,
+    /// Pause after reading each file, in milliseconds.
+    pub throttle_delay_ms: Option<u64>,
+    /// Run with the lowest CPU and disk priority.
+    pub low_priority: Option<bool>,
+    /// Give up reading a file after this many seconds without data.
+    pub read_timeout: Option<u64>,
+    /// Number of times to retry files that could not be read.
+    pub retries: Option<u32>,
+    /// Size of the buffer files are read with, e.g. `"1M"`.
+    pub read_buffer: Option<String>,
+    /// Drop files from the page cache once hashed.
+    pub drop_page_cache: Option<bool>,
+    /// Size from which a file is hashed on several threads at once, e.g. `"1G"`.
+    pub large_file_threshold: Option<String>,
+    /// Skip using the hash cache.
+    pub no_cache: Option<bool>,
+    /// Save and continue interrupted scans.
+    pub resume: Option<bool>,
+    /// Only read directories changed since the last scan.
+    pub incremental: Option<bool>,
+    /// Only count the files and directories denied, rather than listing them.
+    pub skip_unreadable: Option<bool>,
+    /// Exit with an error status if any file or directory was denied.
+    pub fail_on_unreadable: Option<bool>,
+    /// Remove cache entries for files that no longer exist.
+    pub prune_cache: Option<bool>,
+    /// Only prune entries older than this many days.
+    pub prune_older_than: Option<u64>,
+    /// Periodic cache save limits, e.g. `"10m,5000"`.
+    pub cache_checkpoint: Option<String>,
+    /// Maximum number of hash cache entries.
+    pub cache_max_entries: Option<usize>,
+    /// Maximum size of the hash cache file, e.g. `"50M"`.
+    pub cache_max_size: Option<String>,
+    /// Format to save the hash cache in.
+    pub cache_format: Option<CacheFormat>,
+    /// Fail instead of waiting for other processes saving the hash cache.
+    pub no_cache_wait: Option<bool>,
+    /// Modification time difference still taken as unchanged, e.g. `"2s"` or `"1h"`.
+    pub mtime_tolerance: Option<String>,
+    /// Trust cached hashes of files with a changed modification time but the same size,
+    /// and hash them again last.
+    pub rehash_suspect: Option<bool>,
+    /// zstd level to compress the hash cache with, from 1 to 22.
+    pub cache_compression_level: Option<i32>,
+    /// Number of threads to compress the hash cache with.
+    pub cache_compression_threads: Option<u32>,
+    /// Save the hash cache on a background thread while reporting.
+    pub background_cache_save: Option<bool>,
+    /// Compute wasted space from allocated disk space.
+    pub allocated_size: Option<bool>,
+    /// Account for hardlinks outside the scan in wasted space and actions.
+    pub count_hardlinks_once: Option<bool>,
+    /// Leave duplicates alone whose extended attributes or data streams differ.
+    pub check_streams: Option<bool>,
+    /// Recognize reflinked copies, which free no space.
+    pub detect_reflinks: Option<bool>,
+    /// Skip empty files instead of reporting them as a duplicate group.
+    pub ignore_empty: Option<bool>,
+    /// Skip non-empty files smaller than this, e.g. `"4K"`.
+    pub min_size: Option<String>,
+    /// Skip hidden files and directories.
+    pub skip_hidden: Option<bool>,
+    /// Scan operating system metadata files such as `.DS_Store`.
+    pub include_junk: Option<bool>,
+    /// Only scan files of these kinds, e.g. `["image", "video"]`.
+    pub file_types: Option<Vec<FileType>>,
+    /// Only scan files modified at or after this time, e.g. `"2024-01-31"` or `"30d"`.
+    pub newer_than: Option<String>,
+    /// Only scan files modified before this time, e.g. `"365d"`.
+    pub older_than: Option<String>,
+    /// Don't descend into other filesystems.
+    pub one_file_system: Option<bool>,
+    /// Follow directory junctions on Windows.
+    pub follow_junctions: Option<bool>,
+    /// Read locked files from a volume snapshot on Windows.
+    pub snapshot: Option<bool>,
+    /// Treat paths differing only in case as the same file.
+    pub case_insensitive_paths: Option<bool>,
+    /// Only show the N groups wasting the most space.
+    pub top: Option<usize>,
+    /// Only show groups wasting at least this much space, e.g. `"10M"`.
+    pub min_group_waste: Option<String>,
+    /// Only show groups with at least this many distinct copies.
+    pub min_copies: Option<usize>,
+    /// Order in which groups are shown.
+    pub sort_by: Option<SortBy>,
+    /// Also hash the files inside zip and tar archives.
+    pub look_inside_archives: Option<bool>,
+    /// Hash gzip and Zstandard files by their decompressed contents.
+    pub decompress_before_hash: Option<bool>,
+    /// Also report images differing only in metadata.
+    pub ignore_image_metadata: Option<bool>,
+    /// Also report MP3 files differing only in ID3 tags.
+    pub ignore_audio_tags: Option<bool>,
+    /// Also report files with the same name but different contents.
+    pub report_same_name: Option<bool>,
+    /// Also report large files sharing most of their content.
+    pub partial_duplicates: Option<bool>,
+    /// Only look for partial duplicates among files at least this large, e.g. `"100M"`.
+    pub partial_min_size: Option<String>,
+    /// Percentage of the larger file that partial duplicates must share.
+    pub partial_similarity: Option<u8>,
+    /// Report files without copies instead of duplicates.
+    pub report_unique: Option<bool>,
+    /// Print phase timings and throughput at the end of the run, `"text"` or `"json"`.
+    pub stats: Option<StatsFormat>,
+    /// Also report clusters of visually similar images.
+    pub perceptual: Option<bool>,
+    /// Maximum perceptual hash distance for images to be considered similar.
+    pub perceptual_distance: Option<u32>,
+    /// Also report differently encoded copies of the same recording.
+    pub audio: Option<bool>,
+    /// Percentage of audio fingerprint bits that must match.
+    pub audio_similarity: Option<u8>,
+    /// Format of the duplicate results.
+    pub output_format: Option<OutputFormat>,
+    /// Only print warnings, errors and the duplicate report.
+    pub quiet: Option<bool>,
+    /// Print debug-level messages.
+    pub verbose: Option<bool>,
+    /// Print without colors.
+    pub no_color: Option<bool>,
+    /// Refuse to change any files.
+    pub read_only: Option<bool>,
+    /// Don't page long reports.
+    pub no_pager: Option<bool>,
+    /// How to show progress.
+    pub progress: Option<ProgressFormat>,
+    /// Show sizes in decimal units (kB, MB) instead of binary units (KiB, MiB).
+    pub si: Option<bool>,
+    /// How to write the paths of files in reports: `"auto"`, `"absolute"` or
+    /// `"relative-to=DIR"`.
+    pub display_paths: Option<String>,
+    /// Format of the log file, `"text"` or `"json"`.
+    pub log_format: Option<LogFormat>,
+    /// URL to post a JSON summary to when a scan finishes or fails.
+    pub notify_webhook: Option<String>,
+}
+
+fn default_base_path() -> String {
+    ".".to_string()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            base_path: default_base_path(),
+            paths: Vec::new(),
+            skip_dirs: Vec::new(),
+            cache_dir: None,
+            acknowledged_file: None,
+            originals_file: None,
+            prefer: Vec::new(),
+            priority_dirs: Vec::new(),
+            suggestion_signals: Vec::new(),
+            protect: Vec::new(),
+            log_file: None,
+            no_log_file: false,
+            smtp: None,
+            profile: BTreeMap::new(),
+            threads: None,
+            walk_threads: None,
+            io_threads: None,
+            hash_threads: None,
+            memory_budget: None,
+            throttle_rate: None,
+            throttle_delay_ms: None,
+            low_priority: None,
+            read_timeout: None,
+            retries: None,
+            read_buffer: None,
+            drop_page_cache: None,
+            large_file_threshold: None,
+            no_cache: None,
+            resume: None,
+            incremental: None,
+            skip_unreadable: None,
+            fail_on_unreadable: None,
+            prune_cache: None,
+            prune_older_than: None,
+            cache_checkpoint: None,
+            cache_max_entries: None,
+            cache_max_size: None,
+            cache_format: None,
+            no_cache_wait: None,
+            mtime_tolerance: None,
+            rehash_suspect: None,
+            cache_compression_level: None,
+            cache_compression_threads: None,
+            background_cache_save: None,
+            allocated_size: None,
+            count_hardlinks_once: None,
+            check_streams: None,
+            detect_reflinks: None,
+            ignore_empty: None,
+            min_size: None,
+            skip_hidden: None,
+            include_junk: None,
+            file_types: None,
+            newer_than: None,
+            older_than: None,
+            one_file_system: None,
+            follow_junctions: None,
+            snapshot: None,
+            case_insensitive_paths: None,
+            top: None,
+            min_group_waste: None,
+            min_copies: None,
+            sort_by: None,
+            look_inside_archives: None,
+            decompress_before_hash: None,
+            ignore_image_metadata: None,
+            ignore_audio_tags: None,
+            report_same_name: None,
+            partial_duplicates: None,
+            partial_min_size: None,
+            partial_similarity: None,
+            report_unique: None,
+            stats: None,
+            perceptual: None,
+            perceptual_distance: None,
+            audio: None,
+            audio_similarity: None,
+            output_format: None,
+            quiet: None,
+            verbose: None,
+            no_color: None,
+            read_only: None,
+            no_pager: None,
+            progress: None,
+            si: None,
+            display_paths: None,
+            log_format: None,
+            notify_webhook: None,
+        }
+    }
+}
+
+impl Config {
+    /// Returns the config file location in the platform config directory
+    /// (e.g. `~/.config/check-file-dups/check-file-dups.toml` on Linux).
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| {
+            dir.join(env!("CARGO_PKG_NAME"))
+                .join(format!("{}.toml", env!("CARGO_PKG_NAME")))
+        })
+    }
+
+    /// Finds the config file to use.
+    ///
+    /// An explicitly given file must exist. Otherwise `check-file-dups.toml` is looked up
+    /// in the current directory, then in the platform config directory. Returns `None` if
+    /// no config file exists.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `explicit` is given but does not exist.
+    pub fn find(explicit: Option<&Path>) -> Result<Option<PathBuf>> {
+        if let Some(path) = explicit {
+            if !path.is_file() {
+                anyhow::bail!("Config file {} does not exist", path.display());
+            }
+            return Ok(Some(path.to_path_buf()));
+        }
+        let local = std::env::current_dir()?.join(format!("{}.toml", env!("CARGO_PKG_NAME")));
+        Ok([Some(local), Self::default_path()]
+            .into_iter()
+            .flatten()
+            .find(|path| path.is_file()))
+    }
+
+    /// Loads and parses a config file, with the keys of `[profile.NAME]` replacing those
+    /// at the top level if a `profile` is given.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, or if it is not valid TOML or contains
+    /// unknown keys, also in any profile; parse errors include the line and column of the
+    /// problem. Also returns an error if `profile` isn't defined.
+    pub fn load(path: &Path, profile: Option<&str>) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let config: Self = toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Invalid config file {}:\n{}", path.display(), e))?;
+        let table: toml::Table = toml::from_str(&content)?;
+        let mut selected = None;
+        for name in config.profile.keys() {
+            let merged = Self::with_profile(&table, name).map_err(|e| {
+                anyhow::anyhow!(
+                    "Invalid profile '{}' in config file {}:\n{}",
+                    name,
+                    path.display(),
+                    e
+                )
+            })?;
+            if profile == Some(name.as_str()) {
+                selected = Some(merged);
+            }
+        }
+        match (profile, selected) {
+            (None, _) => Ok(config),
+            (Some(_), Some(selected)) => Ok(selected),
+            (Some(name), None) => {
+                let names: Vec<&str> = config.profile.keys().map(String::as_str).collect();
+                anyhow::bail!(
+                    "No profile '{}' in config file {} (profiles: {})",
+                    name,
+                    path.display(),
+                    if names.is_empty() {
+                        "none".to_string()
+                    } else {
+                        names.join(", ")
+                    }
+                )
+            }
+        }
+    }
+
+    /// Parses `table`, the whole config file, with the keys of its `[profile.NAME]`
+    /// table in place of those at the top level.
+    fn with_profile(table: &toml::Table, name: &str) -> Result<Self, toml::de::Error> {
+        let mut merged = table.clone();
+        if let Some(toml::Value::Table(profiles)) = merged.remove("profile")
+            && let Some(toml::Value::Table(profile)) = profiles.get(name)
+        {
+            merged.extend(profile.clone());
+        }
+        toml::Value::Table(merged).try_into()
+    }
+
+    /// Fills in options that were not given on the command line from the config file.
+    ///
+    /// `matches` must be the matches `cli` was parsed from; they tell apart options given
+    /// on the command line from defaults. Actions that change files (`--move-to`,
+    /// `--delete`, `--hardlink`) deliberately have no config equivalent.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `cache_checkpoint`, `cache_max_size`, `memory_budget`,
+    /// `mtime_tolerance`, `throttle_rate`,
+    /// `read_buffer`, `large_file_threshold`, `min_size`, `newer_than`, `older_than`,
+    /// `min_group_waste` or `partial_min_size` cannot be parsed, or `partial_similarity`,
+    /// `cache_compression_level` or `cache_compression_threads` is out of range.
+    pub fn apply_to(&self, cli: &mut Cli, matches: &ArgMatches) -> Result<()> {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        fn set<T: Clone>(target: &mut T, value: &Option<T>, from_cli: bool) {
+            if let Some(value) = value
+                && !from_cli
+            {
+                *target = value.clone();
+            }
+        }
+
+        if !from_cli("path")
+            && let Some((first, more)) = self.paths.split_first()
+        {
+            cli.scan.path = first.clone();
+            cli.scan.more_paths = more.to_vec();
+        }
+        if !from_cli("threads") && self.threads.is_some() {
+            cli.scan.threads = self.threads;
+        }
+        if !from_cli("walk_threads") && self.walk_threads.is_some() {
+            cli.scan.walk_threads = self.walk_threads;
+        }
+        if !from_cli("io_threads") && self.io_threads.is_some() {
+            cli.scan.io_threads = self.io_threads;
+        }
+        if !from_cli("hash_threads") && self.hash_threads.is_some() {
+            cli.scan.hash_threads = self.hash_threads;
+        }
+        if !from_cli("memory_budget")
+            && let Some(size) = &self.memory_budget
+        {
+            cli.scan.memory_budget = Some(
+                parse_size(size)
+                    .map_err(|e| anyhow::anyhow!("Invalid memory_budget in config: {}", e))?,
+            );
+        }
+        if !from_cli("throttle_rate")
+            && let Some(rate) = &self.throttle_rate
+        {
+            cli.throttle_rate = Some(
+                parse_size(rate)
+                    .map_err(|e| anyhow::anyhow!("Invalid throttle_rate in config: {}", e))?,
+            );
+        }
+        set(
+            &mut cli.throttle_delay_ms,
+            &self.throttle_delay_ms,
+            from_cli("throttle_delay_ms"),
+        );
+        set(
+            &mut cli.low_priority,
+            &self.low_priority,
+            from_cli("low_priority"),
+        );
+        if !from_cli("read_timeout") && self.read_timeout.is_some() {
+            cli.read_timeout = self.read_timeout;
+        }
+        set(&mut cli.retries, &self.retries, from_cli("retries"));
+        if !from_cli("read_buffer")
+            && let Some(size) = &self.read_buffer
+        {
+            cli.read_buffer = Some(
+                parse_size(size)
+                    .map_err(|e| anyhow::anyhow!("Invalid read_buffer in config: {}", e))?,
+            );
+        }
+        set(
+            &mut cli.drop_page_cache,
+            &self.drop_page_cache,
+            from_cli("drop_page_cache"),
+        );
+        if !from_cli("large_file_threshold")
+            && let Some(size) = &self.large_file_threshold
+        {
+            cli.large_file_threshold = parse_size(size)
+                .map_err(|e| anyhow::anyhow!("Invalid large_file_threshold in config: {}", e))?;
+        }
+        set(&mut cli.scan.no_cache, &self.no_cache, from_cli("no_cache"));
+        set(&mut cli.scan.resume, &self.resume, from_cli("resume"));
+        set(
+            &mut cli.scan.incremental,
+            &self.incremental,
+            from_cli("incremental"),
+        );
+        set(
+            &mut cli.scan.skip_unreadable,
+            &self.skip_unreadable,
+            from_cli("skip_unreadable"),
+        );
+        set(
+            &mut cli.scan.fail_on_unreadable,
+            &self.fail_on_unreadable,
+            from_cli("fail_on_unreadable"),
+        );
+        set(
+            &mut cli.scan.prune_cache,
+            &self.prune_cache,
+            from_cli("prune_cache"),
+        );
+        if !from_cli("prune_older_than") && self.prune_older_than.is_some() {
+            cli.scan.prune_older_than = self.prune_older_than;
+        }
+        if !from_cli("cache_checkpoint")
+            && let Some(policy) = &self.cache_checkpoint
+        {
+            cli.scan.cache_checkpoint = Some(
+                policy
+                    .parse()
+                    .map_err(|e| anyhow::anyhow!("Invalid cache_checkpoint in config: {}", e))?,
+            );
+        }
+        if !from_cli("cache_max_entries") && self.cache_max_entries.is_some() {
+            cli.scan.cache_max_entries = self.cache_max_entries;
+        }
+        if !from_cli("cache_max_size")
+            && let Some(size) = &self.cache_max_size
+        {
+            cli.scan.cache_max_size = Some(
+                parse_size(size)
+                    .map_err(|e| anyhow::anyhow!("Invalid cache_max_size in config: {}", e))?,
+            );
+        }
+        set(
+            &mut cli.scan.cache_format,
+            &self.cache_format,
+            from_cli("cache_format"),
+        );
+        set(
+            &mut cli.scan.no_cache_wait,
+            &self.no_cache_wait,
+            from_cli("no_cache_wait"),
+        );
+        if !from_cli("mtime_tolerance")
+            && let Some(tolerance) = &self.mtime_tolerance
+        {
+            cli.scan.mtime_tolerance = Some(
+                parse_duration(tolerance)
+                    .map_err(|e| anyhow::anyhow!("Invalid mtime_tolerance in config: {}", e))?,
+            );
+        }
+        set(
+            &mut cli.scan.rehash_suspect,
+            &self.rehash_suspect,
+            from_cli("rehash_suspect"),
+        );
+        if !from_cli("cache_compression_level")
+            && let Some(level) = self.cache_compression_level
+        {
+            if !(1..=22).contains(&level) {
+                anyhow::bail!(
+                    "Invalid cache_compression_level in config: {} is not between 1 and 22",
+                    level
+                );
+            }
+            cli.scan.cache_compression_level = Some(level);
+        }
+        if !from_cli("cache_compression_threads")
+            && let Some(threads) = self.cache_compression_threads
+        {
+            if threads == 0 {
+                anyhow::bail!("Invalid cache_compression_threads in config: must be at least 1");
+            }
+            cli.scan.cache_compression_threads = Some(threads);
+        }
+        set(
+            &mut cli.scan.background_cache_save,
+            &self.background_cache_save,
+            from_cli("background_cache_save"),
+        );
+        if !from_cli("cache_dir") && self.cache_dir.is_some() {
+            cli.cache_dir = self.cache_dir.clone();
+        }
+        if !from_cli("acknowledged_file") && self.acknowledged_file.is_some() {
+            cli.acknowledged_file = self.acknowledged_file.clone();
+        }
+        if !from_cli("originals_file") && self.originals_file.is_some() {
+            cli.originals_file = self.originals_file.clone();
+        }
+        set(
+            &mut cli.case_insensitive_paths,
+            &self.case_insensitive_paths,
+            from_cli("case_insensitive_paths"),
+        );
+        set(
+            &mut cli.scan.allocated_size,
+            &self.allocated_size,
+            from_cli("allocated_size"),
+        );
+        set(
+            &mut cli.scan.count_hardlinks_once,
+            &self.count_hardlinks_once,
+            from_cli("count_hardlinks_once"),
+        );
+        set(
+            &mut cli.scan.check_streams,
+            &self.check_streams,
+            from_cli("check_streams"),
+        );
+        set(
+            &mut cli.scan.detect_reflinks,
+            &self.detect_reflinks,
+            from_cli("detect_reflinks"),
+        );
+        set(
+            &mut cli.scan.ignore_empty,
+            &self.ignore_empty,
+            from_cli("ignore_empty"),
+        );
+        if !from_cli("min_size")
+            && let Some(size) = &self.min_size
+        {
+            cli.scan.min_size = parse_size(size)
+                .map_err(|e| anyhow::anyhow!("Invalid min_size in config: {}", e))?;
+        }
+        set(
+            &mut cli.skip_hidden,
+            &self.skip_hidden,
+            from_cli("skip_hidden"),
+        );
+        set(
+            &mut cli.include_junk,
+            &self.include_junk,
+            from_cli("include_junk"),
+        );
+        set(
+            &mut cli.scan.file_types,
+            &self.file_types,
+            from_cli("file_types"),
+        );
+        if !from_cli("newer_than")
+            && let Some(when) = &self.newer_than
+        {
+            cli.scan.newer_than = Some(
+                parse_time(when)
+                    .map_err(|e| anyhow::anyhow!("Invalid newer_than in config: {}", e))?,
+            );
+        }
+        if !from_cli("older_than")
+            && let Some(when) = &self.older_than
+        {
+            cli.scan.older_than = Some(
+                parse_time(when)
+                    .map_err(|e| anyhow::anyhow!("Invalid older_than in config: {}", e))?,
+            );
+        }
+        set(
+            &mut cli.one_file_system,
+            &self.one_file_system,
+            from_cli("one_file_system"),
+        );
+        set(
+            &mut cli.follow_junctions,
+            &self.follow_junctions,
+            from_cli("follow_junctions"),
+        );
+        set(&mut cli.scan.snapshot, &self.snapshot, from_cli("snapshot"));
+        if !from_cli("top") && self.top.is_some() {
+            cli.scan.top = self.top;
+        }
+        if !from_cli("min_group_waste")
+            && let Some(size) = &self.min_group_waste
+        {
+            cli.scan.min_group_waste = parse_size(size)
+                .map_err(|e| anyhow::anyhow!("Invalid min_group_waste in config: {}", e))?;
+        }
+        set(
+            &mut cli.scan.min_copies,
+            &self.min_copies,
+            from_cli("min_copies"),
+        );
+        set(&mut cli.scan.sort_by, &self.sort_by, from_cli("sort_by"));
+        set(
+            &mut cli.scan.look_inside_archives,
+            &self.look_inside_archives,
+            from_cli("look_inside_archives"),
+        );
+        set(
+            &mut cli.scan.decompress_before_hash,
+            &self.decompress_before_hash,
+            from_cli("decompress_before_hash"),
+        );
+        set(
+            &mut cli.scan.ignore_image_metadata,
+            &self.ignore_image_metadata,
+            from_cli("ignore_image_metadata"),
+        );
+        set(
+            &mut cli.scan.ignore_audio_tags,
+            &self.ignore_audio_tags,
+            from_cli("ignore_audio_tags"),
+        );
+        set(
+            &mut cli.scan.report_same_name,
+            &self.report_same_name,
+            from_cli("report_same_name"),
+        );
+        set(
+            &mut cli.scan.partial_duplicates,
+            &self.partial_duplicates,
+            from_cli("partial_duplicates"),
+        );
+        if !from_cli("partial_min_size")
+            && let Some(size) = &self.partial_min_size
+        {
+            cli.scan.partial_min_size = parse_size(size)
+                .map_err(|e| anyhow::anyhow!("Invalid partial_min_size in config: {}", e))?;
+        }
+        if !from_cli("partial_similarity")
+            && let Some(percent) = self.partial_similarity
+        {
+            if !(1..=100).contains(&percent) {
+                anyhow::bail!("Invalid partial_similarity in config: must be between 1 and 100");
+            }
+            cli.scan.partial_similarity = percent;
+        }
+        set(
+            &mut cli.scan.report_unique,
+            &self.report_unique,
+            from_cli("report_unique"),
+        );
+        if !from_cli("stats") && self.stats.is_some() {
+            cli.scan.stats = self.stats;
+        }
+        set(
+            &mut cli.scan.perceptual,
+            &self.perceptual,
+            from_cli("perceptual"),
+        );
+        set(
+            &mut cli.scan.perceptual_distance,
+            &self.perceptual_distance,
+            from_cli("perceptual_distance"),
+        );
+        set(&mut cli.scan.audio, &self.audio, from_cli("audio"));
+        if !from_cli("audio_similarity")
+            && let Some(percent) = self.audio_similarity
+        {
+            if !(1..=100).contains(&percent) {
+                anyhow::bail!("Invalid audio_similarity in config: must be between 1 and 100");
+            }
+            cli.scan.audio_similarity = percent;
+        }
+        set(
+            &mut cli.scan.output_format,
+            &self.output_format,
+            from_cli("output_format"),
+        );
+        // --quiet and --verbose conflict, so either one on the command line overrides both
+        let verbosity_from_cli = from_cli("quiet") || from_cli("verbose");
+        set(&mut cli.quiet, &self.quiet, verbosity_from_cli);
+        set(&mut cli.verbose, &self.verbose, verbosity_from_cli);
+        if cli.quiet && cli.verbose {
+            anyhow::bail!("quiet and verbose cannot both be set in the config file");
+        }
+        set(&mut cli.no_color, &self.no_color, from_cli("no_color"));
+        set(&mut cli.read_only, &self.read_only, from_cli("read_only"));
+        set(&mut cli.scan.no_pager, &self.no_pager, from_cli("no_pager"));
+        set(&mut cli.progress, &self.progress, from_cli("progress"));
+        // --binary on the command line overrides si = true
+        set(&mut cli.si, &self.si, from_cli("si") || from_cli("binary"));
+        if !from_cli("display_paths")
+            && let Some(mode) = &self.display_paths
+        {
+            cli.display_paths = mode
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid display_paths in config: {}", e))?;
+        }
+        set(
+            &mut cli.log_format,
+            &self.log_format,
+            from_cli("log_format"),
+        );
+        if !from_cli("notify_webhook") && self.notify_webhook.is_some() {
+            cli.scan.notify_webhook = self.notify_webhook.clone();
+        }
+        // --log-file and --no-log-file conflict in the same way
+        if !from_cli("log_file") && !from_cli("no_log_file") {
+            if self.log_file.is_some() {
+                cli.log_file = self.log_file.clone();
+            }
+            cli.no_log_file = self.no_log_file;
+        }
+        Ok(())
+    }
+
+    /// Writes the commented config template to `path`, creating parent directories.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `path` already exists and `force` is not set, or if the file
+    /// cannot be written.
+    pub fn write_template(path: &Path, force: bool) -> Result<()> {
+        if path.exists() && !force {
+            anyhow::bail!(
+                "{} already exists, use --force to overwrite it",
+                path.display()
+            );
+        }
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, CONFIG_TEMPLATE)
+            .with_context(|| format!("Failed to write config file {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    #[test]
+    fn template_parses() {
+        let config: Config = toml::from_str(CONFIG_TEMPLATE).unwrap();
+        assert!(config.skip_dirs.is_empty());
+    }
+
+    #[test]
+    fn parse_errors_include_line_numbers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bad.toml");
+        fs::write(&path, "base_path = \".\"\nskip_dirs = [\"a\"\n").unwrap();
+        let message = format!("{:#}", Config::load(&path, None).err().unwrap());
+        assert!(message.contains("line 2"), "{}", message);
+
+        fs::write(&path, "base_pth = \".\"\n").unwrap();
+        let message = format!("{:#}", Config::load(&path, None).err().unwrap());
+        assert!(message.contains("unknown field"), "{}", message);
+    }
+
+    fn apply(config: &str, args: &[&str]) -> Cli {
+        let config: Config = toml::from_str(config).unwrap();
+        let matches = Cli::command()
+            .try_get_matches_from(std::iter::once("check-file-dups").chain(args.iter().copied()))
+            .unwrap();
+        let (mut cli, matches) = Cli::from_matches(matches).unwrap();
+        config.apply_to(&mut cli, &matches).unwrap();
+        cli
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        let config = "threads = 4\nhash_threads = \"auto\"\nsort_by = \"path\"\nmin_group_waste = \"1k\"\nquiet = true\n\
+                      ignore_empty = false\nmin_size = \"2k\"\n";
+
+        let cli = apply(config, &[]);
+        assert_eq!(cli.scan.io_threads(), Threads::Count(4));
+        assert_eq!(cli.scan.hash_threads(), Threads::Auto);
+        assert_eq!(cli.scan.sort_by, SortBy::Path);
+        assert_eq!(cli.scan.min_group_waste, 1024);
+        assert!(!cli.scan.ignore_empty);
+        assert_eq!(cli.scan.min_size, 2048);
+        assert!(cli.quiet);
+
+        let cli = apply(config, &["-t", "2", "--sort-by", "count", "-v"]);
+        assert_eq!(cli.scan.io_threads(), Threads::Count(2));
+        assert_eq!(cli.scan.sort_by, SortBy::Count);
+        assert!(!cli.quiet && cli.verbose);
+    }
+
+    #[test]
+    fn scan_command_takes_the_same_options() {
+        let config = "sort_by = \"path\"\nmin_size = \"2k\"\n";
+        let cli = apply(config, &["scan", "photos", "--sort-by", "count", "--quiet"]);
+        assert!(cli.command.is_none());
+        assert_eq!(cli.scan.path, PathBuf::from("photos"));
+        assert_eq!(cli.scan.sort_by, SortBy::Count);
+        assert_eq!(cli.scan.min_size, 2048);
+        assert!(cli.quiet);
+    }
+
+    #[test]
+    fn read_only_from_config_covers_actions() {
+        let cli = apply("read_only = true", &["--delete"]);
+        assert!(cli.read_only);
+        assert!(cli.scan.changes_files());
+        assert!(!apply("", &["--delete", "--dry-run"]).scan.changes_files());
+        assert!(!apply("", &[]).scan.changes_files());
+    }
+
+    #[test]
+    fn config_overrides_defaults_of_global_options() {
+        let cli = apply("cache_dir = \"/from/config\"", &["cache", "stats"]);
+        assert_eq!(cli.cache_dir, Some(PathBuf::from("/from/config")));
+
+        let cli = apply(
+            "cache_dir = \"/from/config\"",
+            &["cache", "stats", "--cache-dir", "/from/cli"],
+        );
+        assert_eq!(cli.cache_dir, Some(PathBuf::from("/from/cli")));
+    }
+
+    #[test]
+    fn profiles_replace_top_level_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "skip_dirs = [\"@eaDir\"]\nmin_size = \"1k\"\n\n\
+             [profile.photos]\npaths = [\"/data/photos\", \"/backup/photos\"]\n\
+             skip_dirs = [\"Thumbnails\"]\nprefer = [\"newest\"]\n",
+        )
+        .unwrap();
+
+        let config = Config::load(&path, None).unwrap();
+        assert_eq!(config.skip_dirs, ["@eaDir"]);
+        assert!(config.paths.is_empty());
+        let photos = Config::load(&path, Some("photos")).unwrap();
+        assert_eq!(photos.skip_dirs, ["Thumbnails"]);
+        assert_eq!(photos.min_size.as_deref(), Some("1k"));
+        assert_eq!(photos.prefer, ["newest"]);
+
+        let matches = Cli::command()
+            .try_get_matches_from(["check-file-dups"])
+            .unwrap();
+        let mut cli = Cli::from_arg_matches(&matches).unwrap();
+        photos.apply_to(&mut cli, &matches).unwrap();
+        assert_eq!(
+            cli.scan.scan_paths(),
+            [
+                PathBuf::from("/data/photos"),
+                PathBuf::from("/backup/photos")
+            ]
+        );
+
+        let message = format!("{:#}", Config::load(&path, Some("music")).err().unwrap());
+        assert!(message.contains("(profiles: photos)"), "{}", message);
+        fs::write(&path, "[profile.typo]\nmin_sise = \"1k\"\n").unwrap();
+        let message = format!("{:#}", Config::load(&path, None).err().unwrap());
+        assert!(message.contains("profile 'typo'"), "{}", message);
+    }
+
+    #[test]
+    fn write_template_refuses_to_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub").join("config.toml");
+        Config::write_template(&path, false).unwrap();
+        assert!(Config::write_template(&path, false).is_err());
+        Config::write_template(&path, true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), CONFIG_TEMPLATE);
+    }
+}
@@ -0,0 +1,153 @@
+//! The BLAKE3 hash identifying the contents of a file, and the IDs of duplicate groups
+//! derived from it.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A 32-byte BLAKE3 hash of a file's contents. Written as 64 lowercase hex digits, as in
+/// the cache, reports and journals.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ContentHash([u8; 32]);
+
+impl ContentHash {
+    /// Returns the hash of `data`.
+    pub fn of(data: &[u8]) -> Self {
+        blake3::hash(data).into()
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Returns the ID of the duplicate group of files with this hash: its first
+    /// [`GROUP_ID_LEN`] hex digits, which stay the same from run to run.
+    pub fn group_id(&self) -> String {
+        let mut id = self.to_string();
+        id.truncate(GROUP_ID_LEN);
+        id
+    }
+}
+
+/// Number of hex digits of a hash making up a group ID, enough to tell apart the groups
+/// of any realistic scan.
+pub const GROUP_ID_LEN: usize = 12;
+
+/// A duplicate group picked on the command line by its ID (see [`ContentHash::group_id`]),
+/// or by a longer prefix of its hash up to the whole hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupId(String);
+
+impl GroupId {
+    /// Returns `true` if this picks the group of files with `hash`.
+    pub fn matches(&self, hash: &ContentHash) -> bool {
+        hash.to_string().starts_with(&self.0)
+    }
+}
+
+impl From<ContentHash> for GroupId {
+    fn from(hash: ContentHash) -> Self {
+        Self(hash.to_string())
+    }
+}
+
+impl fmt::Display for GroupId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for GroupId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let id = s.trim().to_ascii_lowercase();
+        if (GROUP_ID_LEN..=64).contains(&id.len()) && id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            Ok(Self(id))
+        } else {
+            Err(format!(
+                "invalid group ID '{}', expected {} to 64 hex digits",
+                s, GROUP_ID_LEN
+            ))
+        }
+    }
+}
+
+impl From<blake3::Hash> for ContentHash {
+    fn from(hash: blake3::Hash) -> Self {
+        Self(*hash.as_bytes())
+    }
+}
+
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ContentHash({})", self)
+    }
+}
+
+impl FromStr for ContentHash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        blake3::Hash::from_hex(s)
+            .map(Self::from)
+            .map_err(|_| format!("invalid hash '{}', expected 64 hex digits", s))
+    }
+}
+
+impl Serialize for ContentHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ContentHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_as_hex() {
+        let hash = ContentHash::of(b"hello");
+        let hex = blake3::hash(b"hello").to_hex().to_string();
+        assert_eq!(hash.to_string(), hex);
+        assert_eq!(hex.parse::<ContentHash>(), Ok(hash));
+        assert_eq!(
+            serde_json::from_str::<ContentHash>(&serde_json::to_string(&hash).unwrap()).unwrap(),
+            hash
+        );
+        assert!("abc".parse::<ContentHash>().is_err());
+    }
+
+    #[test]
+    fn picks_groups_by_id_or_hash_prefix() {
+        let hash = ContentHash::of(b"hello");
+        let id = hash.group_id();
+        assert_eq!(id.len(), GROUP_ID_LEN);
+        assert!(hash.to_string().starts_with(&id));
+
+        let picked: GroupId = id.to_uppercase().parse().unwrap();
+        assert!(picked.matches(&hash));
+        assert!(!picked.matches(&ContentHash::of(b"world")));
+        assert!(GroupId::from(hash).matches(&hash));
+        assert!(id[..8].parse::<GroupId>().is_err());
+        assert!("not a group id".parse::<GroupId>().is_err());
+    }
+}
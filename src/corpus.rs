@@ -0,0 +1,392 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::FileInfo;
+use crate::binary::{BinaryReader, put_str, put_u32, put_u64};
+use crate::units::{Bytes, Count};
+use crate::utils::format_timestamp;
+
+/// A file recorded in a [`CorpusIndex`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CorpusEntry {
+    #[serde(with = "crate::paths::serde_path")]
+    pub path: PathBuf,
+    pub size: u64,
+    pub hash: String,
+}
+
+/// A location recorded in a [`CorpusIndex`], and when it was last indexed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CorpusRoot {
+    #[serde(with = "crate::paths::serde_path")]
+    pub path: PathBuf,
+    /// Unix timestamp of the last `index` run for this location, in seconds.
+    pub indexed_at: u64,
+}
+
+/// First bytes of an index manifest written by [`CorpusIndex::export`].
+pub const MANIFEST_MAGIC: &[u8] = b"CFDINDEX";
+
+/// Version of the manifest format. Bumped on incompatible changes; older versions are
+/// still read.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// On-disk form of a [`CorpusIndex`].
+#[derive(Default, Serialize, Deserialize)]
+struct Stored {
+    roots: Vec<CorpusRoot>,
+    files: Vec<CorpusEntry>,
+}
+
+/// A persistent index of the contents of reference locations, such as an archive on a
+/// NAS, built by the `index` command. New directories can be checked against it without
+/// walking or even mounting the indexed locations again.
+///
+/// Stored as zstd-compressed JSON. One index can hold several locations; indexing a
+/// location again replaces its files.
+pub struct CorpusIndex {
+    file: PathBuf,
+    roots: Vec<CorpusRoot>,
+    files: BTreeMap<PathBuf, CorpusEntry>,
+    by_hash: HashMap<String, Vec<PathBuf>>,
+}
+
+impl CorpusIndex {
+    /// Returns the path of the index kept in `cache_dir`.
+    pub fn file_in(cache_dir: &Path) -> PathBuf {
+        cache_dir.join(format!("{}-index.json.zst", env!("CARGO_PKG_NAME")))
+    }
+
+    /// Loads the index from `file`, or starts an empty one if it doesn't exist yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or parsed.
+    pub fn load(file: PathBuf) -> Result<Self> {
+        let stored = match fs::read(&file) {
+            Ok(compressed) => {
+                let json = zstd::decode_all(&compressed[..])
+                    .with_context(|| format!("Failed to decompress {}", file.display()))?;
+                serde_json::from_slice(&json)
+                    .with_context(|| format!("Failed to parse {}", file.display()))?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Stored::default(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", file.display()));
+            }
+        };
+        let mut index = Self {
+            file,
+            roots: stored.roots,
+            files: BTreeMap::new(),
+            by_hash: HashMap::new(),
+        };
+        for entry in stored.files {
+            index.files.insert(entry.path.clone(), entry);
+        }
+        index.rebuild();
+        Ok(index)
+    }
+
+    fn rebuild(&mut self) {
+        self.by_hash.clear();
+        for entry in self.files.values() {
+            self.by_hash
+                .entry(entry.hash.clone())
+                .or_default()
+                .push(entry.path.clone());
+        }
+    }
+
+    /// Replaces the files recorded for the location `root` with `files`, found by
+    /// scanning it at `indexed_at` (a Unix timestamp).
+    pub fn replace(&mut self, root: &Path, files: &[FileInfo], indexed_at: u64) {
+        let entries = files.iter().map(|file| CorpusEntry {
+            path: file.path.to_path_buf(),
+            size: file.size,
+            hash: file.hash.to_string(),
+        });
+        self.replace_entries(root, entries, indexed_at);
+    }
+
+    fn replace_entries(
+        &mut self,
+        root: &Path,
+        entries: impl IntoIterator<Item = CorpusEntry>,
+        indexed_at: u64,
+    ) {
+        self.files.retain(|path, _| !path.starts_with(root));
+        for entry in entries {
+            self.files.insert(entry.path.clone(), entry);
+        }
+        self.roots.retain(|r| r.path != root);
+        self.roots.push(CorpusRoot {
+            path: root.to_path_buf(),
+            indexed_at,
+        });
+        self.roots.sort_by(|a, b| a.path.cmp(&b.path));
+        self.rebuild();
+    }
+
+    /// Writes the index to `file` as a manifest for [`CorpusIndex::import`] on another
+    /// machine, returning the number of files written.
+    ///
+    /// The manifest starts with [`MANIFEST_MAGIC`] and a little-endian `u32` format
+    /// version, followed by a zstd-compressed body: the locations (path and time indexed),
+    /// then for each file its raw 32-byte BLAKE3 hash, size, location and path relative
+    /// to the location with `/` separators. Strings are UTF-8 prefixed with their length.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a hash is not a BLAKE3 hash or writing fails.
+    pub fn export(&self, file: &Path) -> Result<usize> {
+        let mut body = Vec::new();
+        put_u32(&mut body, self.roots.len() as u32);
+        for root in &self.roots {
+            put_str(&mut body, &root.path.to_string_lossy());
+            put_u64(&mut body, root.indexed_at);
+        }
+        put_u64(&mut body, self.files.len() as u64);
+        for entry in self.files.values() {
+            // Files are always under a location; the longest match is the one indexed
+            let (root_index, root) = self
+                .roots
+                .iter()
+                .enumerate()
+                .filter(|(_, root)| entry.path.starts_with(&root.path))
+                .max_by_key(|(_, root)| root.path.as_os_str().len())
+                .with_context(|| format!("{} is in no indexed location", entry.path.display()))?;
+            let hash = blake3::Hash::from_hex(&entry.hash)
+                .with_context(|| format!("Invalid hash for {}", entry.path.display()))?;
+            body.extend_from_slice(hash.as_bytes());
+            put_u64(&mut body, entry.size);
+            put_u32(&mut body, root_index as u32);
+            let relative = entry.path.strip_prefix(&root.path).unwrap_or(&entry.path);
+            let components: Vec<_> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect();
+            put_str(&mut body, &components.join("/"));
+        }
+
+        let mut manifest = MANIFEST_MAGIC.to_vec();
+        manifest.extend_from_slice(&MANIFEST_VERSION.to_le_bytes());
+        manifest.extend_from_slice(&zstd::encode_all(&body[..], 19)?);
+        fs::write(file, &manifest)
+            .with_context(|| format!("Failed to write {}", file.display()))?;
+        info!(
+            "Exported {} files in {} locations to {} ({})",
+            Count(self.files.len() as u64),
+            Count(self.roots.len() as u64),
+            file.display(),
+            Bytes(manifest.len() as u64)
+        );
+        Ok(self.files.len())
+    }
+
+    /// Adds the locations in a manifest written by [`CorpusIndex::export`] to the index,
+    /// as `name:location`, replacing any locations imported under `name` before. Returns
+    /// the number of files imported.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is not a valid manifest.
+    pub fn import(&mut self, file: &Path, name: &str) -> Result<usize> {
+        let manifest =
+            fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+        let header = MANIFEST_MAGIC.len() + 4;
+        if manifest.len() < header || !manifest.starts_with(MANIFEST_MAGIC) {
+            anyhow::bail!("{} is not an index manifest", file.display());
+        }
+        let version = u32::from_le_bytes(manifest[MANIFEST_MAGIC.len()..header].try_into()?);
+        if version > MANIFEST_VERSION {
+            anyhow::bail!(
+                "{} was written by a newer version (manifest format {}, this version reads up to {})",
+                file.display(),
+                version,
+                MANIFEST_VERSION
+            );
+        }
+        let body = zstd::decode_all(&manifest[header..])
+            .with_context(|| format!("Failed to decompress {}", file.display()))?;
+        let mut body = BinaryReader::new(&body, "Index manifest");
+
+        let prefix = |root: &str| PathBuf::from(format!("{}:{}", name, root));
+        let mut roots = Vec::new();
+        for _ in 0..body.u32()? {
+            roots.push((prefix(&body.string()?), body.u64()?));
+        }
+        let mut entries: Vec<Vec<CorpusEntry>> = vec![Vec::new(); roots.len()];
+        for _ in 0..body.u64()? {
+            let hash = blake3::Hash::from_bytes(body.bytes(32)?.try_into()?);
+            let size = body.u64()?;
+            let root_index = body.u32()? as usize;
+            let relative = body.string()?;
+            let (root, _) = roots
+                .get(root_index)
+                .context("Invalid location in index manifest")?;
+            entries[root_index].push(CorpusEntry {
+                path: root.join(relative),
+                size,
+                hash: hash.to_hex().to_string(),
+            });
+        }
+
+        // Drop locations imported under this name before, which may have been removed
+        let stale: Vec<PathBuf> = self
+            .roots
+            .iter()
+            .map(|root| root.path.clone())
+            .filter(|path| path.to_string_lossy().starts_with(&format!("{}:", name)))
+            .collect();
+        for path in stale {
+            self.files.retain(|file, _| !file.starts_with(&path));
+            self.roots.retain(|root| root.path != path);
+        }
+        let count = entries.iter().map(Vec::len).sum();
+        for ((root, indexed_at), entries) in roots.into_iter().zip(entries) {
+            self.replace_entries(&root, entries, indexed_at);
+        }
+        info!(
+            "Imported {} files from {}",
+            Count(count as u64),
+            file.display()
+        );
+        Ok(count)
+    }
+
+    /// Returns the indexed files with content `hash`, sorted by path.
+    pub fn paths(&self, hash: &str) -> &[PathBuf] {
+        self.by_hash.get(hash).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the indexed files, sorted by path.
+    pub fn entries(&self) -> impl Iterator<Item = &CorpusEntry> {
+        self.files.values()
+    }
+
+    /// Returns the file the index is kept in.
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    /// Returns the indexed locations.
+    pub fn roots(&self) -> &[CorpusRoot] {
+        &self.roots
+    }
+
+    /// Returns the number of files in the index.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Returns `true` if the index has no files.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Writes the index to its file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if serialization or writing fails.
+    pub fn save(&self) -> Result<()> {
+        let stored = Stored {
+            roots: self.roots.clone(),
+            files: self.files.values().cloned().collect(),
+        };
+        let compressed = zstd::encode_all(&serde_json::to_vec(&stored)?[..], 3)?;
+        fs::write(&self.file, &compressed)
+            .with_context(|| format!("Failed to write {}", self.file.display()))?;
+        info!(
+            "Saved index of {} files ({}) to {}",
+            Count(self.files.len() as u64),
+            Bytes(compressed.len() as u64),
+            self.file.display()
+        );
+        Ok(())
+    }
+
+    /// Logs the indexed locations and when each was indexed.
+    pub fn print_roots(&self) {
+        info!(
+            "Index of {} files in {} locations:",
+            Count(self.files.len() as u64),
+            Count(self.roots.len() as u64)
+        );
+        for root in &self.roots {
+            info!(
+                "  {} (indexed {})",
+                root.path.display(),
+                format_timestamp(root.indexed_at)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content_hash::ContentHash;
+
+    /// Returns a file with the contents `data`.
+    fn file(path: &str, data: &str) -> FileInfo {
+        FileInfo::for_test(path, 1, ContentHash::of(data.as_bytes()))
+    }
+
+    #[test]
+    fn reindexing_replaces_location() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut index = CorpusIndex::load(CorpusIndex::file_in(dir.path())).unwrap();
+        assert!(index.is_empty());
+        index.replace(
+            Path::new("/nas"),
+            &[file("/nas/a", "h1"), file("/nas/b", "h2")],
+            1,
+        );
+        index.replace(Path::new("/old"), &[file("/old/c", "h1")], 2);
+        index.replace(Path::new("/nas"), &[file("/nas/b", "h2")], 3);
+        index.save().unwrap();
+
+        let index = CorpusIndex::load(CorpusIndex::file_in(dir.path())).unwrap();
+        assert_eq!(index.len(), 2);
+        let hash = |data: &str| ContentHash::of(data.as_bytes()).to_string();
+        assert_eq!(index.paths(&hash("h1")), [PathBuf::from("/old/c")]);
+        assert_eq!(index.paths(&hash("h3")), [] as [PathBuf; 0]);
+        assert_eq!(index.roots().len(), 2);
+        assert_eq!(index.roots()[0].indexed_at, 3);
+    }
+
+    #[test]
+    fn exports_and_imports_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let hash = |data: &str| blake3::hash(data.as_bytes()).to_hex().to_string();
+        let mut server = CorpusIndex::load(dir.path().join("server.json.zst")).unwrap();
+        server.replace(
+            Path::new("/srv/media"),
+            &[file("/srv/media/2024/a.jpg", "a")],
+            7,
+        );
+        let manifest = dir.path().join("server.cfdindex");
+        assert_eq!(server.export(&manifest).unwrap(), 1);
+
+        let mut laptop = CorpusIndex::load(dir.path().join("laptop.json.zst")).unwrap();
+        laptop.replace(Path::new("/home/me"), &[file("/home/me/b", "b")], 8);
+        assert_eq!(laptop.import(&manifest, "server").unwrap(), 1);
+        assert_eq!(laptop.import(&manifest, "server").unwrap(), 1);
+        assert_eq!(laptop.len(), 2);
+        assert_eq!(
+            laptop.paths(&hash("a")),
+            [PathBuf::from("server:/srv/media/2024/a.jpg")]
+        );
+        assert_eq!(laptop.roots()[1].indexed_at, 7);
+
+        fs::write(&manifest, b"CFDINDEX").unwrap();
+        assert!(laptop.import(&manifest, "server").is_err());
+    }
+}
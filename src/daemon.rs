@@ -0,0 +1,162 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Result, anyhow};
+use log::{debug, info, warn};
+use serde_json::{Value, json};
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::cache::HashCache;
+use crate::index::HashIndex;
+use crate::metrics::{self, WatchMetrics};
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Returns the value of query parameter `name` in `query`, e.g. `hash=abc&x=1`.
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Answers a GET request for `url` from `index`, returning the HTTP status code and
+/// JSON body.
+///
+/// - `/stats`: number of files, distinct hashes, duplicate groups and duplicate files
+/// - `/duplicates`: every hash shared by more than one file, with its files
+/// - `/lookup?hash=HASH`: the files with the given BLAKE3 hash
+pub fn respond(index: &HashIndex, url: &str) -> (u16, Value) {
+    let (route, query) = url.split_once('?').unwrap_or((url, ""));
+    match route {
+        "/stats" => {
+            let (groups, files) = index
+                .duplicates()
+                .fold((0, 0), |(groups, files), (_, paths)| {
+                    (groups + 1, files + paths.len())
+                });
+            (
+                200,
+                json!({
+                    "files": index.len(),
+                    "hashes": index.hash_count(),
+                    "duplicate_groups": groups,
+                    "duplicate_files": files,
+                }),
+            )
+        }
+        "/duplicates" => {
+            let mut groups: Vec<(&str, Vec<&Path>)> = index.duplicates().collect();
+            groups.sort_by(|a, b| a.1.cmp(&b.1));
+            let groups: Vec<Value> = groups
+                .into_iter()
+                .map(|(hash, paths)| {
+                    json!({
+                        "hash": hash,
+                        "paths": paths.into_iter().map(path_string).collect::<Vec<_>>(),
+                    })
+                })
+                .collect();
+            (200, json!({ "groups": groups }))
+        }
+        "/lookup" => match query_param(query, "hash") {
+            Some(hash) => {
+                let hash = hash.to_ascii_lowercase();
+                let paths: Vec<String> = index.paths(&hash).map(path_string).collect();
+                (200, json!({ "hash": hash, "paths": paths }))
+            }
+            None => (400, json!({ "error": "missing hash parameter" })),
+        },
+        _ => (
+            404,
+            json!({ "error": format!("unknown endpoint {}", route) }),
+        ),
+    }
+}
+
+/// Starts listening for HTTP requests on `addr`, e.g. `127.0.0.1:7878`.
+///
+/// # Errors
+///
+/// Returns an error if the address cannot be bound.
+pub fn listen(addr: &str) -> Result<Server> {
+    let server = Server::http(addr).map_err(|e| anyhow!("Failed to listen on {}: {}", addr, e))?;
+    info!("Listening on http://{}", server.server_addr());
+    Ok(server)
+}
+
+/// Answers requests on `server` from `index` until the server is shut down. Only GET
+/// requests are accepted; see [`respond`] for the JSON endpoints. `/metrics` returns
+/// the index, `cache` and `metrics` in the Prometheus text format.
+pub fn serve(
+    server: Server,
+    index: Arc<Mutex<HashIndex>>,
+    cache: Arc<HashCache>,
+    metrics: Arc<WatchMetrics>,
+) {
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let metrics_type =
+        Header::from_bytes("Content-Type", "text/plain; version=0.0.4; charset=utf-8").unwrap();
+    for request in server.incoming_requests() {
+        debug!("{} {}", request.method(), request.url());
+        let response = if *request.method() != Method::Get {
+            let body = json!({ "error": "only GET requests are supported" });
+            Response::from_string(body.to_string())
+                .with_status_code(405)
+                .with_header(content_type.clone())
+        } else if request.url() == "/metrics" {
+            let text = metrics::render(&index.lock().unwrap(), cache.len(), &metrics);
+            Response::from_string(text).with_header(metrics_type.clone())
+        } else {
+            let (status, body) = respond(&index.lock().unwrap(), request.url());
+            Response::from_string(body.to_string())
+                .with_status_code(status)
+                .with_header(content_type.clone())
+        };
+        if let Err(e) = request.respond(response) {
+            warn!("Failed to send response: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn index() -> HashIndex {
+        let mut index = HashIndex::new();
+        index.insert(PathBuf::from("/d/b"), "ab12".into());
+        index.insert(PathBuf::from("/d/a"), "ab12".into());
+        index.insert(PathBuf::from("/d/c"), "cd34".into());
+        index
+    }
+
+    #[test]
+    fn answers_endpoints() {
+        let index = index();
+        assert_eq!(
+            respond(&index, "/stats"),
+            (
+                200,
+                json!({ "files": 3, "hashes": 2, "duplicate_groups": 1, "duplicate_files": 2 })
+            )
+        );
+        assert_eq!(
+            respond(&index, "/duplicates"),
+            (
+                200,
+                json!({ "groups": [{ "hash": "ab12", "paths": ["/d/a", "/d/b"] }] })
+            )
+        );
+        assert_eq!(
+            respond(&index, "/lookup?hash=CD34"),
+            (200, json!({ "hash": "cd34", "paths": ["/d/c"] }))
+        );
+        assert_eq!(respond(&index, "/lookup").0, 400);
+        assert_eq!(respond(&index, "/nope").0, 404);
+    }
+}
@@ -0,0 +1,125 @@
+//! `--decompress-before-hash`: gzip and Zstandard files are hashed by their decompressed
+//! contents, so `notes.txt` and `notes.txt.gz` holding the same text are reported as
+//! copies. Compressed files are recognized by their extension and confirmed by their
+//! first bytes.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use flate2::read::MultiGzDecoder;
+
+use crate::content_hash::ContentHash;
+use crate::error::{CheckFileDupsError, IoResultExt, Result};
+
+/// A compression format whose files can be hashed by their decompressed contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Returns the compression of the file at `path`, or `None` if it has no compressed
+    /// extension, doesn't start with the matching magic number, or can't be read.
+    pub fn of(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        let (compression, magic): (Self, &[u8]) = match ext.as_str() {
+            "gz" | "gzip" | "tgz" => (Self::Gzip, b"\x1F\x8B"),
+            "zst" | "zstd" | "tzst" => (Self::Zstd, b"\x28\xB5\x2F\xFD"),
+            _ => return None,
+        };
+        let mut header = [0; 4];
+        let mut file = File::open(path).ok()?;
+        let read = file.read(&mut header).ok()?;
+        header[..read].starts_with(magic).then_some(compression)
+    }
+
+    /// Wraps `reader` to decompress what it reads.
+    fn decoder<'a>(self, reader: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            // Concatenated gzip members decompress to the concatenation, like `gunzip`
+            Self::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            Self::Zstd => Box::new(zstd::Decoder::new(reader)?),
+        })
+    }
+}
+
+/// Passes the length of everything read through it to a callback, so compressed bytes
+/// count towards progress and throttling.
+struct Counted<R, F> {
+    inner: R,
+    on_read: F,
+}
+
+impl<R: Read, F: FnMut(usize)> Read for Counted<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        (self.on_read)(read);
+        Ok(read)
+    }
+}
+
+/// Computes the BLAKE3 hash of the decompressed contents of the file at `path`,
+/// calling `on_read` with the number of compressed bytes read as it goes.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be read or isn't valid `compression` data.
+pub fn hash_decompressed(
+    path: &Path,
+    compression: Compression,
+    on_read: impl FnMut(usize),
+) -> Result<ContentHash> {
+    let file = Counted {
+        inner: File::open(path).at(path)?,
+        on_read,
+    };
+    let mut decoder = compression.decoder(file).at(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = match decoder.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(CheckFileDupsError::io(path, e)),
+        };
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+
+    #[test]
+    fn hashes_compressed_files_by_their_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = b"the same text, compressed two ways".repeat(100);
+        let gz = dir.path().join("notes.txt.gz");
+        let mut encoder = flate2::write::GzEncoder::new(
+            fs::File::create(&gz).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(&text).unwrap();
+        encoder.finish().unwrap();
+        let zst = dir.path().join("notes.txt.zst");
+        fs::write(&zst, zstd::encode_all(&text[..], 3).unwrap()).unwrap();
+        let fake = dir.path().join("fake.gz");
+        fs::write(&fake, b"not compressed").unwrap();
+
+        assert_eq!(Compression::of(&gz), Some(Compression::Gzip));
+        assert_eq!(Compression::of(&zst), Some(Compression::Zstd));
+        assert_eq!(Compression::of(&fake), None);
+        let mut read = 0;
+        let hash = hash_decompressed(&gz, Compression::Gzip, |n| read += n).unwrap();
+        assert_eq!(hash, ContentHash::of(&text));
+        assert_eq!(read as u64, fs::metadata(&gz).unwrap().len());
+        let hash = hash_decompressed(&zst, Compression::Zstd, |_| ()).unwrap();
+        assert_eq!(hash, ContentHash::of(&text));
+    }
+}
@@ -0,0 +1,219 @@
+//! `--incremental`: remembering the entries of each directory walked, next to the hash
+//! cache, so later scans only list the directories that changed. Adding, removing or
+//! renaming an entry updates the modification time of its directory, so a directory
+//! whose modification time is the same as when it was listed still holds the same
+//! entries, and the walk takes them from the index instead of reading the directory.
+//! Changes to the files themselves don't touch their directory, so every file is still
+//! checked as usual, and subdirectories are still visited, since changes deeper down
+//! don't touch their parents either. This mostly saves time on network shares, where
+//! each directory read waits for the server.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::shutdown::hold_exit;
+use crate::units::Count;
+
+/// How long before the walk a directory must have last changed for its listing to be
+/// recorded. A directory changed while or just before it was read could change again
+/// without its modification time moving on, on filesystems with coarse timestamps.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// An entry of a directory, as the walk needs it before reading its metadata.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListedEntry {
+    /// File name of the entry.
+    #[serde(with = "crate::paths::serde_path")]
+    pub name: PathBuf,
+    /// Whether the entry is a symbolic link (or junction) rather than what it points to.
+    pub symlink: bool,
+    /// Whether the entry is hidden, see [`crate::scanner::NameFilter::skip_hidden`].
+    pub hidden: bool,
+}
+
+/// The entries of a directory when it was listed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Listing {
+    /// Modification time of the directory, in nanoseconds since the Unix epoch.
+    modified: u128,
+    entries: Vec<ListedEntry>,
+}
+
+/// Returns the modification time of a directory in nanoseconds, if known.
+fn modified(metadata: &fs::Metadata) -> Option<u128> {
+    let modified = metadata.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos())
+}
+
+/// The directory listings of the last scan, and those of the scan running now.
+pub struct DirIndex {
+    file: PathBuf,
+    previous: HashMap<PathBuf, Listing>,
+    current: Mutex<HashMap<PathBuf, Listing>>,
+    /// Directories listed from the index rather than read, to report.
+    reused: AtomicU64,
+}
+
+impl DirIndex {
+    /// Returns the path of the directory index kept next to `cache_file`.
+    pub fn file_for_cache(cache_file: &Path) -> PathBuf {
+        let mut name = cache_file.file_name().unwrap_or_default().to_os_string();
+        name.push(".dirs");
+        cache_file.with_file_name(name)
+    }
+
+    /// Loads the directory listings saved in `file`, or starts with none if it doesn't
+    /// exist or can't be read.
+    pub fn load(file: PathBuf) -> Self {
+        let previous = fs::read(&file)
+            .ok()
+            .and_then(|compressed| zstd::decode_all(&compressed[..]).ok())
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default();
+        Self {
+            file,
+            previous,
+            current: Mutex::new(HashMap::new()),
+            reused: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the entries of `dir` as last listed, if its modification time in
+    /// `metadata` shows it hasn't changed since. They are kept for the next scan too.
+    pub fn get(&self, dir: &Path, metadata: &fs::Metadata) -> Option<Vec<ListedEntry>> {
+        let listing = self.previous.get(dir)?;
+        if modified(metadata) != Some(listing.modified) {
+            return None;
+        }
+        self.reused.fetch_add(1, Ordering::Relaxed);
+        self.current
+            .lock()
+            .unwrap()
+            .insert(dir.to_path_buf(), listing.clone());
+        Some(listing.entries.clone())
+    }
+
+    /// Records the `entries` of `dir`, just read, whose `metadata` was read before them.
+    /// Directories changed too recently to tell later changes apart are not recorded.
+    pub fn record(&self, dir: &Path, metadata: &fs::Metadata, entries: Vec<ListedEntry>) {
+        let Some(modified) = modified(metadata) else {
+            return;
+        };
+        let settled = SystemTime::now()
+            .checked_sub(SETTLE_TIME)
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .is_some_and(|settled| modified < settled.as_nanos());
+        if !settled {
+            debug!("Not indexing recently changed directory {}", dir.display());
+            return;
+        }
+        self.current
+            .lock()
+            .unwrap()
+            .insert(dir.to_path_buf(), Listing { modified, entries });
+    }
+
+    /// Saves the listings for the next scan: those of the directories walked under
+    /// `roots`, and the earlier ones of directories elsewhere, e.g. scanned in other runs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if serialization or writing fails.
+    pub fn save(&self, roots: &[PathBuf]) -> Result<()> {
+        let reused = self.reused.load(Ordering::Relaxed);
+        if reused > 0 {
+            info!(
+                "Listed {} unchanged directories from the directory index",
+                Count(reused)
+            );
+        }
+        let _exit = hold_exit();
+        let mut listings = self.current.lock().unwrap().clone();
+        // Nothing was walked, e.g. with --file-list or a resumed file list
+        if listings.is_empty() {
+            return Ok(());
+        }
+        for (dir, listing) in &self.previous {
+            if !roots.iter().any(|root| dir.starts_with(root)) {
+                listings
+                    .entry(dir.clone())
+                    .or_insert_with(|| listing.clone());
+            }
+        }
+        let compressed = zstd::encode_all(&serde_json::to_vec(&listings)?[..], 3)?;
+        fs::write(&self.file, compressed)?;
+        debug!(
+            "Saved {} directory listings to {}",
+            Count(listings.len() as u64),
+            self.file.display()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes::set_modified;
+    use crate::scan_errors::ScanErrors;
+    use crate::scan_root::ScanRoot;
+    use crate::scanner::{ModifiedRange, NameFilter, ScanLimit, walk_directory};
+
+    fn walk(root: &ScanRoot, dir_index: &DirIndex) -> Vec<PathBuf> {
+        let (files, _) = walk_directory(
+            root,
+            &[],
+            &NameFilter::default(),
+            0,
+            &ModifiedRange::default(),
+            &ScanLimit::default(),
+            false,
+            false,
+            Some(dir_index),
+            2,
+            &mut ScanErrors::default(),
+        )
+        .unwrap();
+        files
+    }
+
+    // Directories can only be opened to set their time like this on Unix
+    #[cfg(unix)]
+    #[test]
+    fn only_reads_changed_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("tree/a")).unwrap();
+        let root = ScanRoot::for_base(&dir.path().join("tree"));
+        let base = root.scan_path().to_path_buf();
+        fs::write(base.join("top"), "top").unwrap();
+        fs::write(base.join("a/one"), "one").unwrap();
+        let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        for sub in ["", "a"] {
+            set_modified(&base.join(sub), hour_ago).unwrap();
+        }
+        let file = DirIndex::file_for_cache(&dir.path().join("cache.bin"));
+        let dir_index = DirIndex::load(file.clone());
+        assert_eq!(walk(&root, &dir_index).len(), 2);
+        dir_index.save(root.roots()).unwrap();
+
+        // A file added to a directory whose time is put back is not seen, showing its
+        // listing came from the index, while the changed directory is read again
+        fs::write(base.join("unseen"), "unseen").unwrap();
+        set_modified(&base, hour_ago).unwrap();
+        fs::write(base.join("a/two"), "two").unwrap();
+        let dir_index = DirIndex::load(file);
+        assert_eq!(
+            walk(&root, &dir_index),
+            [base.join("a/one"), base.join("a/two"), base.join("top")]
+        );
+        assert_eq!(dir_index.reused.load(Ordering::Relaxed), 1);
+    }
+}
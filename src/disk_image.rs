@@ -0,0 +1,300 @@
+//! `--disk-image`: scanning the files inside a raw disk image, such as a backup made
+//! with `dd`, along with live files. The image is attached read-only with the tools of
+//! the operating system, without needing root: `udisksctl` on Linux, `hdiutil` on macOS
+//! and `Mount-DiskImage` on Windows (ISO, VHD and VHDX images only). Each filesystem on
+//! it is mounted and scanned as another scan path, and the image is detached again when
+//! the scan is done.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+
+/// A disk image attached read-only, with its filesystems mounted. Dropping it unmounts
+/// and detaches the image.
+pub struct MountedImage {
+    image: PathBuf,
+    /// What to pass to the detach command: the loop or disk device, or the image itself
+    /// on Windows.
+    device: String,
+    /// Devices of the mounted filesystems, unmounted before detaching on Linux.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    filesystems: Vec<String>,
+    mount_points: Vec<PathBuf>,
+}
+
+impl MountedImage {
+    /// Attaches the disk image at `image` read-only and mounts the filesystems on it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image can't be attached or none of its filesystems can
+    /// be mounted, e.g. since the tools to do so aren't installed.
+    pub fn attach(image: &Path) -> Result<Self> {
+        let image = image
+            .canonicalize()
+            .with_context(|| format!("Failed to read disk image {}", image.display()))?;
+        let mounted = attach_image(&image)
+            .with_context(|| format!("Failed to attach disk image {}", image.display()))?;
+        if mounted.mount_points.is_empty() {
+            drop(mounted);
+            anyhow::bail!(
+                "Disk image {} holds no filesystem that could be mounted",
+                image.display()
+            );
+        }
+        for mount_point in &mounted.mount_points {
+            info!(
+                "Mounted disk image {} read-only at {}",
+                image.display(),
+                mount_point.display()
+            );
+        }
+        Ok(mounted)
+    }
+
+    /// Returns the directories the filesystems of the image are mounted on.
+    pub fn mount_points(&self) -> &[PathBuf] {
+        &self.mount_points
+    }
+}
+
+impl Drop for MountedImage {
+    fn drop(&mut self) {
+        match detach_image(self) {
+            Ok(()) => info!("Detached disk image {}", self.image.display()),
+            Err(e) => warn!(
+                "Failed to detach disk image {} ({}): {:#}",
+                self.image.display(),
+                self.device,
+                e
+            ),
+        }
+    }
+}
+
+/// Runs `program` with `args`, returning its standard output.
+pub(crate) fn run(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("{} could not be started", program))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Returns the device in `udisksctl loop-setup` output, such as `Mapped file disk.img as
+/// /dev/loop0.`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_loop_device(output: &str) -> Option<String> {
+    let device = output.split(" as ").nth(1)?.trim().trim_end_matches('.');
+    device.starts_with("/dev/").then(|| device.to_string())
+}
+
+/// Returns the directory in `udisksctl mount` output, such as `Mounted /dev/loop0p1 at
+/// /media/me/BACKUP`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_udisks_mount_point(output: &str) -> Option<PathBuf> {
+    let (_, mount_point) = output.trim().split_once(" at ")?;
+    Some(PathBuf::from(mount_point.trim_end_matches('.')))
+}
+
+/// Returns the whole-disk device and the mount points in `hdiutil attach` output, whose
+/// lines hold a device, a partition type and, for mounted filesystems, a mount point,
+/// separated by tabs.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_hdiutil_attach(output: &str) -> Option<(String, Vec<PathBuf>)> {
+    let mut lines = output.lines().filter(|line| line.starts_with("/dev/"));
+    let device = lines.next()?.split('\t').next()?.trim().to_string();
+    let mount_points = output
+        .lines()
+        .filter_map(|line| line.split('\t').nth(2))
+        .map(str::trim)
+        .filter(|mount_point| !mount_point.is_empty())
+        .map(PathBuf::from)
+        .collect();
+    Some((device, mount_points))
+}
+
+#[cfg(target_os = "linux")]
+fn attach_image(image: &Path) -> Result<MountedImage> {
+    let image_arg = image.to_string_lossy();
+    let output = run(
+        "udisksctl",
+        &[
+            "loop-setup",
+            "--read-only",
+            "--no-user-interaction",
+            "--file",
+            &image_arg,
+        ],
+    )?;
+    let device = parse_loop_device(&output)
+        .with_context(|| format!("Unexpected udisksctl output: {}", output.trim()))?;
+    let mut mounted = MountedImage {
+        image: image.to_path_buf(),
+        device: device.clone(),
+        filesystems: Vec::new(),
+        mount_points: Vec::new(),
+    };
+    // Partitioned images get a device per partition, e.g. /dev/loop0p1
+    let name = device.trim_start_matches("/dev/");
+    let mut partitions: Vec<String> = std::fs::read_dir(Path::new("/sys/block").join(name))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|entry| entry.starts_with(&format!("{}p", name)))
+        .map(|partition| format!("/dev/{}", partition))
+        .collect();
+    partitions.sort();
+    if partitions.is_empty() {
+        partitions.push(device);
+    }
+    for partition in partitions {
+        let output = run(
+            "udisksctl",
+            &[
+                "mount",
+                "--no-user-interaction",
+                "--options",
+                "ro",
+                "--block-device",
+                &partition,
+            ],
+        );
+        match output.map(|output| parse_udisks_mount_point(&output)) {
+            Ok(Some(mount_point)) => {
+                mounted.filesystems.push(partition);
+                mounted.mount_points.push(mount_point);
+            }
+            Ok(None) => warn!("Could not tell where {} was mounted", partition),
+            // Such as swap or extended partitions
+            Err(e) => warn!("Not scanning {}: {:#}", partition, e),
+        }
+    }
+    Ok(mounted)
+}
+
+#[cfg(target_os = "linux")]
+fn detach_image(mounted: &MountedImage) -> Result<()> {
+    for filesystem in &mounted.filesystems {
+        run(
+            "udisksctl",
+            &[
+                "unmount",
+                "--no-user-interaction",
+                "--block-device",
+                filesystem,
+            ],
+        )?;
+    }
+    run(
+        "udisksctl",
+        &[
+            "loop-delete",
+            "--no-user-interaction",
+            "--block-device",
+            &mounted.device,
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn attach_image(image: &Path) -> Result<MountedImage> {
+    let image_arg = image.to_string_lossy();
+    let output = run(
+        "hdiutil",
+        &["attach", "-readonly", "-nobrowse", "-noverify", &image_arg],
+    )?;
+    let (device, mount_points) = parse_hdiutil_attach(&output)
+        .with_context(|| format!("Unexpected hdiutil output: {}", output.trim()))?;
+    Ok(MountedImage {
+        image: image.to_path_buf(),
+        device,
+        filesystems: Vec::new(),
+        mount_points,
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn detach_image(mounted: &MountedImage) -> Result<()> {
+    run("hdiutil", &["detach", &mounted.device])?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn attach_image(image: &Path) -> Result<MountedImage> {
+    let path = image.to_string_lossy().replace('\'', "''");
+    let script = format!(
+        "Mount-DiskImage -ImagePath '{}' -Access ReadOnly -PassThru | Get-Volume | \
+         Where-Object DriveLetter | ForEach-Object {{ $_.DriveLetter }}",
+        path
+    );
+    let output = run("powershell", &["-NoProfile", "-Command", &script])?;
+    let mount_points = output
+        .lines()
+        .map(str::trim)
+        .filter(|letter| !letter.is_empty())
+        .map(|letter| PathBuf::from(format!("{}:\\", letter)))
+        .collect();
+    Ok(MountedImage {
+        image: image.to_path_buf(),
+        device: image.to_string_lossy().into_owned(),
+        filesystems: Vec::new(),
+        mount_points,
+    })
+}
+
+#[cfg(windows)]
+fn detach_image(mounted: &MountedImage) -> Result<()> {
+    let script = format!(
+        "Dismount-DiskImage -ImagePath '{}' | Out-Null",
+        mounted.device.replace('\'', "''")
+    );
+    run("powershell", &["-NoProfile", "-Command", &script])?;
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn attach_image(_image: &Path) -> Result<MountedImage> {
+    anyhow::bail!("disk images can't be attached on this platform")
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn detach_image(_mounted: &MountedImage) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_where_images_were_attached() {
+        assert_eq!(
+            parse_loop_device("Mapped file /backups/disk.img as /dev/loop7.\n").as_deref(),
+            Some("/dev/loop7")
+        );
+        assert_eq!(parse_loop_device("Error setting up loop device"), None);
+        assert_eq!(
+            parse_udisks_mount_point("Mounted /dev/loop7p1 at /media/me/BACKUP\n"),
+            Some(PathBuf::from("/media/me/BACKUP"))
+        );
+
+        let hdiutil = "/dev/disk4          \tGUID_partition_scheme          \t\n\
+                       /dev/disk4s1        \tEFI                            \t\n\
+                       /dev/disk4s2        \tApple_HFS                      \t/Volumes/Backup\n";
+        let (device, mount_points) = parse_hdiutil_attach(hdiutil).unwrap();
+        assert_eq!(device, "/dev/disk4");
+        assert_eq!(mount_points, [PathBuf::from("/Volumes/Backup")]);
+    }
+}
@@ -0,0 +1,277 @@
+//! The `doctor` command: checks the things a scan depends on, the config file, the base
+//! path, the hash cache, the log and cache directories, free disk space and hashing
+//! speed, and says what to do about any problem found. Useful when a scan seems to do
+//! nothing, or to attach to a bug report.
+
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::Result;
+use log::{error, info, warn};
+
+use crate::cache::HashCache;
+use crate::content_hash::ContentHash;
+use crate::units::{Bytes, Count};
+use crate::utils::disk_space;
+
+/// Data hashed in memory to measure hashing speed.
+const HASH_SAMPLE_SIZE: usize = 64 << 20;
+
+/// Hashing slower than this, in bytes per second, is likely to hold scans back.
+const SLOW_HASHING: f64 = 200.0 * 1024.0 * 1024.0;
+
+/// How a check went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Ok(String),
+    /// Not in the way of scanning, but worth knowing, such as a cache to be upgraded.
+    Warning(String),
+    /// In the way of scanning, with what to do about it.
+    Problem(String),
+}
+
+/// The outcome of one check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnosis {
+    /// What was checked, such as `Config`.
+    pub check: &'static str,
+    pub outcome: Outcome,
+}
+
+impl Diagnosis {
+    fn ok(check: &'static str, message: String) -> Self {
+        Self {
+            check,
+            outcome: Outcome::Ok(message),
+        }
+    }
+
+    fn warning(check: &'static str, message: String) -> Self {
+        Self {
+            check,
+            outcome: Outcome::Warning(message),
+        }
+    }
+
+    fn problem(check: &'static str, message: String) -> Self {
+        Self {
+            check,
+            outcome: Outcome::Problem(message),
+        }
+    }
+}
+
+/// Checks the config file found, if any, given the error loading or applying it.
+pub fn check_config(file: Option<&Path>, error: Option<&anyhow::Error>) -> Diagnosis {
+    match (file, error) {
+        (_, Some(e)) => Diagnosis::problem(
+            "Config",
+            format!(
+                "{:#}. Fix the file, or replace it with a fresh one with \
+                 `config init --force`",
+                e
+            ),
+        ),
+        (Some(file), None) => Diagnosis::ok("Config", format!("Loaded {}", file.display())),
+        (None, None) => Diagnosis::ok(
+            "Config",
+            "No config file found, using the defaults. Create one with `config init`".to_string(),
+        ),
+    }
+}
+
+/// Checks that the base path, which scans default to, is a readable directory.
+pub fn check_base_path(base_path: &Path) -> Diagnosis {
+    match fs::read_dir(base_path) {
+        Ok(_) => Diagnosis::ok("Base path", base_path.display().to_string()),
+        Err(e) => Diagnosis::problem(
+            "Base path",
+            format!(
+                "Cannot read {}: {}. Set `base_path` in the config file",
+                base_path.display(),
+                e
+            ),
+        ),
+    }
+}
+
+/// Checks that files can be created in `dir`, creating it if needed, by writing and
+/// removing a probe file.
+pub fn check_writable(check: &'static str, dir: &Path, option: &str) -> Diagnosis {
+    let probe = dir.join(format!(
+        ".{}-doctor-{}",
+        env!("CARGO_PKG_NAME"),
+        std::process::id()
+    ));
+    match fs::create_dir_all(dir).and_then(|()| fs::write(&probe, b"")) {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+            Diagnosis::ok(check, format!("{} is writable", dir.display()))
+        }
+        Err(e) => Diagnosis::problem(
+            check,
+            format!(
+                "Cannot write to {}: {}. Fix its permissions, or choose another with {}",
+                dir.display(),
+                e,
+                option
+            ),
+        ),
+    }
+}
+
+/// Checks that the hash cache for the base path can be read, and is in a format this
+/// version writes.
+pub fn check_cache(cache_file: &Path) -> Diagnosis {
+    if !cache_file.exists() {
+        return Diagnosis::ok(
+            "Hash cache",
+            format!(
+                "None yet at {}, so the next scan hashes every file",
+                cache_file.display()
+            ),
+        );
+    }
+    match HashCache::inspect(cache_file) {
+        Ok(info) if info.outdated => Diagnosis::warning(
+            "Hash cache",
+            format!(
+                "{} has {} entries in an older format, upgraded on the next scan",
+                cache_file.display(),
+                Count(info.entries as u64)
+            ),
+        ),
+        Ok(info) => Diagnosis::ok(
+            "Hash cache",
+            format!(
+                "{} has {} entries ({}, {})",
+                cache_file.display(),
+                Count(info.entries as u64),
+                info.format,
+                Bytes(fs::metadata(cache_file).map_or(0, |m| m.len()))
+            ),
+        ),
+        Err(e) => Diagnosis::problem(
+            "Hash cache",
+            format!(
+                "Cannot read {}: {:#}. The next scan moves it aside and hashes every file \
+                 again; check its permissions if that's not wanted",
+                cache_file.display(),
+                e
+            ),
+        ),
+    }
+}
+
+/// Checks that the filesystem holding `cache_dir` has room to save the cache, which is
+/// written next to `cache_file` before replacing it.
+pub fn check_disk_space(cache_dir: &Path, cache_file: &Path) -> Diagnosis {
+    let Some(space) = disk_space(cache_dir) else {
+        return Diagnosis::warning(
+            "Disk space",
+            format!("Cannot tell the free space for {}", cache_dir.display()),
+        );
+    };
+    let cache_size = fs::metadata(cache_file).map_or(0, |m| m.len());
+    let message = format!(
+        "{} free for the cache in {}",
+        Bytes(space.available),
+        cache_dir.display()
+    );
+    if space.available <= cache_size {
+        Diagnosis::problem(
+            "Disk space",
+            format!(
+                "{}, but saving it needs {}. Free some space, or move the cache with \
+                 --cache-dir",
+                message,
+                Bytes(cache_size)
+            ),
+        )
+    } else if space.available <= cache_size.saturating_mul(2) {
+        Diagnosis::warning(
+            "Disk space",
+            format!("{}, little more than the cache takes", message),
+        )
+    } else {
+        Diagnosis::ok("Disk space", message)
+    }
+}
+
+/// Measures how fast data is hashed in memory, without reading the disk.
+pub fn check_hashing() -> Diagnosis {
+    let sample = vec![0x5a; HASH_SAMPLE_SIZE];
+    let start = Instant::now();
+    let _ = ContentHash::of(&sample);
+    let speed = HASH_SAMPLE_SIZE as f64 / start.elapsed().as_secs_f64().max(f64::EPSILON);
+    let message = format!("{}/s in memory", Bytes(speed as u64));
+    if speed < SLOW_HASHING {
+        Diagnosis::warning(
+            "Hashing",
+            format!(
+                "{}, which is slow: is the CPU busy, or is this a debug build? Run `bench` \
+                 to measure a disk",
+                message
+            ),
+        )
+    } else {
+        Diagnosis::ok("Hashing", message)
+    }
+}
+
+/// Logs each diagnosis, problems as errors.
+///
+/// # Errors
+///
+/// Returns an error if any check found a problem, so the exit status tells too.
+pub fn print_diagnoses(diagnoses: &[Diagnosis]) -> Result<()> {
+    let mut problems = 0;
+    for diagnosis in diagnoses {
+        match &diagnosis.outcome {
+            Outcome::Ok(message) => info!("{}: {}", diagnosis.check, message),
+            Outcome::Warning(message) => warn!("{}: {}", diagnosis.check, message),
+            Outcome::Problem(message) => {
+                problems += 1;
+                error!("{}: {}", diagnosis.check, message);
+            }
+        }
+    }
+    if problems > 0 {
+        anyhow::bail!("{} of {} checks found problems", problems, diagnoses.len());
+    }
+    info!("All {} checks passed", diagnoses.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagnoses_unreadable_caches_and_configs() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache.json.zst");
+        assert!(matches!(check_cache(&cache_file).outcome, Outcome::Ok(_)));
+        fs::write(&cache_file, b"not a cache").unwrap();
+        assert!(matches!(
+            check_cache(&cache_file).outcome,
+            Outcome::Problem(_)
+        ));
+        // The unreadable cache is left for the next scan to move aside
+        assert!(cache_file.exists());
+
+        let error = anyhow::anyhow!("unknown field `thread`");
+        let Outcome::Problem(message) = check_config(None, Some(&error)).outcome else {
+            panic!("a config error is a problem");
+        };
+        assert!(message.starts_with("unknown field `thread`."));
+
+        let logs = dir.path().join("logs");
+        assert!(matches!(
+            check_writable("Log directory", &logs, "--log-file").outcome,
+            Outcome::Ok(_)
+        ));
+        assert_eq!(fs::read_dir(&logs).unwrap().count(), 0);
+    }
+}
@@ -1,58 +1,849 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use clap::ValueEnum;
 use colored::Colorize;
-use indicatif::{HumanBytes, HumanCount};
 use log::{info, warn};
+use serde::{Deserialize, Serialize};
 
 use crate::FileInfo;
+use crate::content_hash::{ContentHash, GroupId};
+use crate::reflink::ExtentIds;
+use crate::scan_root::ScanRoot;
+use crate::selection::{KeepPolicy, looks_like_copies};
+use crate::units::{Bytes, Count};
+use crate::utils::{format_timestamp, user_name};
 
-pub fn find_duplicates(files: Vec<FileInfo>) -> HashMap<String, Vec<FileInfo>> {
-    let mut hash_groups: HashMap<String, Vec<FileInfo>> = HashMap::new();
-    
-    info!("Finding duplicates...");
-    for file in files {
-        let hash = file.hash.clone();
-        hash_groups.entry(hash).or_insert_with(Vec::new).push(file);
+/// Files grouped by contents as they are found, so a scan knows which groups are
+/// duplicates before all files are hashed. Files are keyed by size as well as hash, so
+/// a group is only confirmed once both match.
+#[derive(Debug, Default)]
+pub struct HashGroups {
+    groups: HashMap<(u64, ContentHash), Vec<FileInfo>>,
+    files: usize,
+    duplicates: usize,
+}
+
+impl HashGroups {
+    /// Adds `file` to the group with its contents. Returns the group if the file just
+    /// confirmed it as a duplicate, i.e. it is the second member.
+    pub fn add(&mut self, file: FileInfo) -> Option<&[FileInfo]> {
+        self.files += 1;
+        let group = self.groups.entry((file.size, file.hash)).or_default();
+        group.push(file);
+        if group.len() != 2 {
+            return None;
+        }
+        self.duplicates += 1;
+        Some(group)
+    }
+
+    /// Returns the number of files added.
+    pub fn len(&self) -> usize {
+        self.files
+    }
+
+    /// Returns `true` if no files were added.
+    pub fn is_empty(&self) -> bool {
+        self.files == 0
+    }
+
+    /// Returns the number of groups with more than one file.
+    pub fn duplicate_groups(&self) -> usize {
+        self.duplicates
+    }
+
+    /// Returns all files added, group by group.
+    pub fn files(&self) -> impl Iterator<Item = &FileInfo> {
+        self.groups.values().flatten()
+    }
+
+    /// Keeps only the files for which `keep` returns `true`, returning how many were
+    /// removed.
+    pub fn retain(&mut self, mut keep: impl FnMut(&FileInfo) -> bool) -> usize {
+        let count = self.files;
+        self.groups.retain(|_, group| {
+            group.retain(&mut keep);
+            !group.is_empty()
+        });
+        self.files = self.groups.values().map(Vec::len).sum();
+        self.duplicates = self.groups.values().filter(|group| group.len() > 1).count();
+        count - self.files
+    }
+
+    /// Returns all files added, in no particular order.
+    pub fn into_files(self) -> Vec<FileInfo> {
+        self.groups.into_values().flatten().collect()
     }
-    
+
+    /// Returns the groups by hash, including files whose contents are unique.
+    pub fn into_groups(self) -> HashMap<ContentHash, Vec<FileInfo>> {
+        let mut hash_groups: HashMap<ContentHash, Vec<FileInfo>> = HashMap::new();
+        for ((_, hash), files) in self.groups {
+            hash_groups.entry(hash).or_default().extend(files);
+        }
+        hash_groups
+    }
+}
+
+impl Extend<FileInfo> for HashGroups {
+    fn extend<I: IntoIterator<Item = FileInfo>>(&mut self, files: I) {
+        for file in files {
+            self.add(file);
+        }
+    }
+}
+
+impl FromIterator<FileInfo> for HashGroups {
+    fn from_iter<I: IntoIterator<Item = FileInfo>>(files: I) -> Self {
+        let mut groups = Self::default();
+        groups.extend(files);
+        groups
+    }
+}
+
+/// Groups `files` by hash, including files whose contents are unique.
+pub fn group_by_hash(files: Vec<FileInfo>) -> HashMap<ContentHash, Vec<FileInfo>> {
+    files.into_iter().collect::<HashGroups>().into_groups()
+}
+
+/// Keeps the groups from [`group_by_hash`] that have more than one file.
+pub fn find_duplicates(
+    mut hash_groups: HashMap<ContentHash, Vec<FileInfo>>,
+) -> HashMap<ContentHash, Vec<FileInfo>> {
+    info!("Finding duplicates...");
     // Filter out groups with only one file (no duplicates)
     hash_groups.retain(|_, group| group.len() > 1);
-    
+
     hash_groups
 }
 
-pub fn print_results(duplicates: &HashMap<String, Vec<FileInfo>>, base_path: &PathBuf) {
-    if duplicates.is_empty() {
-        println!("{}", "No duplicate files found!".green());
+/// Keeps only the groups with members in both subtree `a` and subtree `b`, dropping
+/// groups whose copies are all within one of them (or outside both). Groups that span
+/// both are kept whole. `a` and `b` must be absolute, like the scanned paths.
+pub fn retain_between(duplicates: &mut HashMap<ContentHash, Vec<FileInfo>>, a: &Path, b: &Path) {
+    let count = duplicates.len();
+    duplicates.retain(|_, group| {
+        group.iter().any(|file| file.path.starts_with(a))
+            && group.iter().any(|file| file.path.starts_with(b))
+    });
+    info!(
+        "Ignoring {} duplicate groups that don't span both {} and {}",
+        Count((count - duplicates.len()) as u64),
+        a.display(),
+        b.display()
+    );
+}
+
+/// Keeps only the groups with at least one member inside one of `dirs`, dropping groups
+/// that `--dedupe-within` leaves nothing to act on, or outside the `--drill-into`
+/// directory. `dirs` must be absolute, like the scanned paths, and come from `option`.
+pub fn retain_within(
+    duplicates: &mut HashMap<ContentHash, Vec<FileInfo>>,
+    dirs: &[PathBuf],
+    option: &str,
+) {
+    let count = duplicates.len();
+    duplicates.retain(|_, group| {
+        group
+            .iter()
+            .any(|file| dirs.iter().any(|dir| file.path.starts_with(dir)))
+    });
+    info!(
+        "Ignoring {} duplicate groups with no copy inside the {} directories",
+        Count((count - duplicates.len()) as u64),
+        option
+    );
+}
+
+/// Keeps only the copies with another copy in the same directory, such as `IMG_001.jpg`
+/// and `IMG_001 (1).jpg`, for `--within-dir-only`, dropping groups left with one copy.
+pub fn retain_siblings(duplicates: &mut HashMap<ContentHash, Vec<FileInfo>>) {
+    let count = duplicates.len();
+    duplicates.retain(|_, group| {
+        let siblings: Vec<bool> = {
+            let mut per_dir: HashMap<&Path, usize> = HashMap::new();
+            for file in group.iter() {
+                *per_dir.entry(file.path.parent()).or_default() += 1;
+            }
+            group
+                .iter()
+                .map(|file| per_dir[file.path.parent()] > 1)
+                .collect()
+        };
+        let mut siblings = siblings.into_iter();
+        group.retain(|_| siblings.next().unwrap_or(false));
+        group.len() > 1
+    });
+    info!(
+        "Ignoring {} duplicate groups with no two copies in the same directory",
+        Count((count - duplicates.len()) as u64)
+    );
+}
+
+/// Keeps only the copies with another copy of the same name, and modification time as
+/// `match_mode` asks, dropping groups left with one copy. Does nothing if `match_mode`
+/// only compares contents.
+pub fn retain_matching(
+    duplicates: &mut HashMap<ContentHash, Vec<FileInfo>>,
+    match_mode: MatchMode,
+) {
+    if !match_mode.matches_name() {
         return;
     }
-    let total_duplicates = duplicates.values().map(|group| group.len() - 1).sum::<usize>();
-    let total_wasted_space: u64 = duplicates.values()
-        .map(|group| group[0].size * (group.len() - 1) as u64)
-        .sum();
-    
-    warn!("Found {} duplicate files wasting {} of space", 
-        HumanCount(total_duplicates.try_into().unwrap()), HumanBytes(total_wasted_space));
-    
-    // Sort duplicate groups by space savings (largest first)
-    let mut sorted_groups: Vec<_> = duplicates.into_iter().collect();
-    sorted_groups.sort_by(|a, b| {
-        let space_a = a.1[0].size * (a.1.len() - 1) as u64;
-        let space_b = b.1[0].size * (b.1.len() - 1) as u64;
-        space_b.cmp(&space_a) // Reverse order (largest first)
+    let count = duplicates.len();
+    duplicates.retain(|_, group| {
+        let mut matched = vec![false; group.len()];
+        for part in split_group(group, false, match_mode) {
+            if part.len() > 1 {
+                for i in part {
+                    matched[i] = true;
+                }
+            }
+        }
+        let mut matched = matched.into_iter();
+        group.retain(|_| matched.next().unwrap_or(false));
+        group.len() > 1
     });
-    
-    for (_hash, group) in sorted_groups {
-        warn!("Duplicate group ({}, {} files):", HumanBytes(group[0].size), group.len());
-        for file in group {
-            // Truncate the base path from the file path
-            let relative_path = if file.path.starts_with(base_path) {
-                file.path.strip_prefix(base_path).unwrap_or(&file.path)
+    info!(
+        "Ignoring {} duplicate groups with no two copies of the same name{}",
+        Count((count - duplicates.len()) as u64),
+        if match_mode.matches_modified() {
+            " and modification time"
+        } else {
+            ""
+        }
+    );
+}
+
+/// Returns the indices of the members of `group` that are copies of each other, in the
+/// order the parts first appear: with `within_dir_only`, the copies in each directory,
+/// which `--within-dir-only` deduplicates on their own, and with a `match_mode` comparing
+/// names, the copies sharing a name, and a modification time if it asks. Otherwise the
+/// whole group is one part.
+pub fn split_group(
+    group: &[FileInfo],
+    within_dir_only: bool,
+    match_mode: MatchMode,
+) -> Vec<Vec<usize>> {
+    type Key<'a> = (Option<&'a Path>, Option<&'a OsStr>, Option<SystemTime>);
+    let mut parts: Vec<(Key, Vec<usize>)> = Vec::new();
+    for (i, file) in group.iter().enumerate() {
+        let key = (
+            within_dir_only.then(|| file.path.parent()),
+            match_mode.matches_name().then(|| file.path.file_name()),
+            file.modified.filter(|_| match_mode.matches_modified()),
+        );
+        match parts.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, indices)) => indices.push(i),
+            None => parts.push((key, vec![i])),
+        }
+    }
+    parts.into_iter().map(|(_, indices)| indices).collect()
+}
+
+/// Keeps only the groups picked by one of `ids` (from `--group` and `--delete-group`),
+/// warning about IDs that pick none, such as those of groups since cleaned up.
+pub fn retain_groups(duplicates: &mut HashMap<ContentHash, Vec<FileInfo>>, ids: &[GroupId]) {
+    for id in ids {
+        if !duplicates.keys().any(|hash| id.matches(hash)) {
+            warn!("No duplicate group with ID {} was found", id);
+        }
+    }
+    duplicates.retain(|hash, _| ids.iter().any(|id| id.matches(hash)));
+    info!(
+        "Only showing the {} duplicate groups picked by ID",
+        Count(duplicates.len() as u64)
+    );
+}
+
+/// Returns the number of distinct copies of the data in a group.
+///
+/// Members that are hardlinks to the same (device, inode) share their data and count
+/// as a single copy. Members without a file ID are always counted separately.
+pub fn distinct_copies(group: &[FileInfo]) -> usize {
+    let mut seen = HashSet::new();
+    group
+        .iter()
+        .filter(|file| file.file_id.is_none_or(|id| seen.insert(id)))
+        .count()
+}
+
+/// How files are compared to find duplicates.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum MatchMode {
+    /// Files of the same size with the same contents, found by hashing them
+    #[default]
+    Content,
+    /// Files with the same contents and the same file name
+    #[value(name = "content+name")]
+    ContentName,
+    /// Files with the same contents, file name and modification time
+    #[value(name = "content+name+mtime")]
+    ContentNameMtime,
+    /// Files with the same name and size, without reading them. Quick over slow network
+    /// shares, but only a heuristic
+    NameSize,
+}
+
+impl MatchMode {
+    /// Returns `true` if copies must have the same file name.
+    pub fn matches_name(self) -> bool {
+        self != Self::Content
+    }
+
+    /// Returns `true` if copies must have the same modification time.
+    pub fn matches_modified(self) -> bool {
+        self == Self::ContentNameMtime
+    }
+}
+
+/// Order in which duplicate groups are displayed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    /// Most wasted space first
+    #[default]
+    Waste,
+    /// Largest files first
+    Size,
+    /// Most files first
+    Count,
+    /// Alphabetically by the first path in each group, for stable, diff-friendly output
+    Path,
+}
+
+/// Format in which duplicate results are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Human-readable log output
+    #[default]
+    Text,
+    /// Self-contained HTML page with a sortable table of groups
+    Html,
+    /// One JSON object per duplicate group per line, streamed as groups are written
+    Ndjson,
+    /// Paths of each duplicate group one per line, groups separated by a blank line, like fdupes
+    Fdupes,
+    /// JSON array in the format of rmlint's json output
+    Rmlint,
+    /// Short plain-text summary with the groups wasting the most space, e.g. to mail from cron
+    Brief,
+}
+
+/// Options controlling how duplicate results are computed and displayed.
+#[derive(Debug, Default, Clone)]
+pub struct ReportOptions {
+    /// Compute wasted space from the space allocated on disk rather than the logical
+    /// file size, so the estimate matches what deleting the duplicates would free.
+    pub use_allocated_size: bool,
+    /// Don't count copies that are also hardlinked from outside the group as wasted
+    /// space, since removing them frees nothing.
+    pub count_hardlinks_once: bool,
+    /// Count the waste of the copies in each directory separately, for
+    /// `--within-dir-only`, since copies in different directories are left alone.
+    pub within_dir_only: bool,
+    /// Files sharing their data through reflinks (from `--detect-reflinks`): copies
+    /// sharing it with another copy in their group don't count as wasted space, like
+    /// hardlinks.
+    pub shared_extents: ExtentIds,
+    /// Only display the `top` groups wasting the most space.
+    pub top: Option<usize>,
+    /// Only display groups wasting at least this many bytes.
+    pub min_group_waste: u64,
+    /// Only display groups with at least this many distinct copies.
+    pub min_copies: usize,
+    /// Order in which groups are displayed.
+    pub sort_by: SortBy,
+    /// What besides their contents files must share to count as copies (`--match`).
+    pub match_mode: MatchMode,
+}
+
+impl ReportOptions {
+    /// Returns `true` if a group passes the `min_group_waste` and `min_copies` filters.
+    pub fn shows(&self, group: &[FileInfo]) -> bool {
+        distinct_copies(group) >= self.min_copies
+            && wasted_space(group, self) >= self.min_group_waste
+    }
+}
+
+/// Returns the space that would be freed by keeping a single copy of the group's data.
+///
+/// With `use_allocated_size`, the allocated sizes of all distinct copies are summed and
+/// the largest one is assumed to be kept, giving a conservative estimate. Files without
+/// a known allocated size fall back to their logical size. With `count_hardlinks_once`,
+/// copies that are [linked outside](linked_outside) the group stay on disk anyway, so one
+/// of them is assumed to be kept and none of them count. Reflinks in
+/// `shared_extents` count once, as hardlinks do. With `within_dir_only`, one copy is
+/// kept in each directory, and with a `match_mode` comparing names, one of each name.
+pub fn wasted_space(group: &[FileInfo], options: &ReportOptions) -> u64 {
+    let parts = split_group(group, options.within_dir_only, options.match_mode);
+    if parts.len() > 1 {
+        return parts
+            .iter()
+            .map(|indices| {
+                let copies: Vec<FileInfo> = indices.iter().map(|&i| group[i].clone()).collect();
+                copies_waste(&copies, options)
+            })
+            .sum();
+    }
+    copies_waste(group, options)
+}
+
+/// Returns the space freed by keeping a single copy of `group`, see [`wasted_space`].
+fn copies_waste(group: &[FileInfo], options: &ReportOptions) -> u64 {
+    let mut seen = HashSet::new();
+    let mut shared = HashSet::new();
+    let mut linked = false;
+    let mut freeable = Vec::new();
+    for file in group
+        .iter()
+        .filter(|file| file.file_id.is_none_or(|id| seen.insert(id)))
+        .filter(|file| {
+            options.shared_extents.is_empty()
+                || options
+                    .shared_extents
+                    .get(&file.path.to_path_buf())
+                    .is_none_or(|id| shared.insert(*id))
+        })
+    {
+        if options.count_hardlinks_once && linked_outside(file, group) {
+            linked = true;
+        } else if options.use_allocated_size {
+            freeable.push(file.allocated_size.unwrap_or(file.size));
+        } else {
+            freeable.push(file.size);
+        }
+    }
+    let kept = if linked {
+        0
+    } else {
+        freeable.iter().max().copied().unwrap_or(0)
+    };
+    freeable.iter().sum::<u64>() - kept
+}
+
+/// Returns `true` if `file` has hardlinks that aren't members of `group`, such as links
+/// outside the scanned directory, which keep its data on disk whatever is done to it.
+pub fn linked_outside(file: &FileInfo, group: &[FileInfo]) -> bool {
+    let (Some(links), Some(id)) = (file.links, file.file_id) else {
+        return false;
+    };
+    let scanned = group
+        .iter()
+        .filter(|member| member.file_id == Some(id))
+        .count();
+    links > scanned as u64
+}
+
+/// Prints the duplicate groups as numbered blocks, one member per line with its
+/// modification time and directory in columns. The member `policy` would keep is
+/// highlighted.
+pub fn print_results(
+    duplicates: &HashMap<ContentHash, Vec<FileInfo>>,
+    root: &ScanRoot,
+    options: &ReportOptions,
+    policy: &KeepPolicy,
+) {
+    // Groups whose members are all hardlinks of one file are already deduplicated
+    let (linked_groups, duplicate_groups): (Vec<_>, Vec<_>) = duplicates
+        .iter()
+        .partition(|(_, group)| distinct_copies(group) == 1);
+
+    if !linked_groups.is_empty() {
+        let linked_files: usize = linked_groups.iter().map(|(_, g)| g.len()).sum();
+        info!(
+            "Found {} hardlinked files in {} groups that are already deduplicated",
+            Count(linked_files as u64),
+            Count(linked_groups.len() as u64)
+        );
+    }
+
+    if duplicate_groups.is_empty() {
+        info!("{}", "No duplicate files found!".green());
+        return;
+    }
+    let total_duplicates = duplicate_groups
+        .iter()
+        .map(|(_, group)| {
+            split_group(group, options.within_dir_only, options.match_mode)
+                .iter()
+                .map(|indices| {
+                    let copies: Vec<FileInfo> = indices.iter().map(|&i| group[i].clone()).collect();
+                    distinct_copies(&copies) - 1
+                })
+                .sum::<usize>()
+        })
+        .sum::<usize>();
+    let total_wasted_space: u64 = duplicate_groups
+        .iter()
+        .map(|(_, group)| wasted_space(group, options))
+        .sum();
+
+    warn!(
+        groups = duplicate_groups.len(),
+        files = total_duplicates,
+        bytes = total_wasted_space;
+        "Found {} duplicate files wasting {} of {}",
+        Count(total_duplicates.try_into().unwrap()),
+        Bytes(total_wasted_space),
+        if options.use_allocated_size {
+            "allocated disk space"
+        } else {
+            "space"
+        }
+    );
+
+    // --top always picks the groups wasting the most space, whatever the display order
+    let group_count = duplicate_groups.len();
+    let mut sorted_groups: Vec<(&ContentHash, &Vec<FileInfo>, u64)> = duplicate_groups
+        .into_iter()
+        .filter(|(_, group)| options.shows(group))
+        .map(|(hash, group)| (hash, group, wasted_space(group, options)))
+        .collect();
+    sorted_groups.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(b.0)));
+    if let Some(top) = options.top {
+        sorted_groups.truncate(top);
+    }
+
+    // Members are listed in path order, so output is stable across runs
+    let mut sorted_groups: Vec<(&ContentHash, Vec<&FileInfo>, u64)> = sorted_groups
+        .into_iter()
+        .map(|(hash, group, waste)| {
+            let mut members: Vec<&FileInfo> = group.iter().collect();
+            members.sort_by(|a, b| a.path.cmp(&b.path));
+            (hash, members, waste)
+        })
+        .collect();
+    match options.sort_by {
+        SortBy::Waste => {}
+        SortBy::Size => sorted_groups.sort_by_key(|g| std::cmp::Reverse(g.1[0].size)),
+        SortBy::Count => sorted_groups.sort_by_key(|g| std::cmp::Reverse(g.1.len())),
+        SortBy::Path => sorted_groups.sort_by(|a, b| a.1[0].path.cmp(&b.1[0].path)),
+    }
+    if sorted_groups.len() < group_count {
+        info!(
+            "Showing {} of {} duplicate groups",
+            Count(sorted_groups.len() as u64),
+            Count(group_count as u64)
+        );
+    }
+
+    // Sizes are right-aligned to the widest one, so the groups line up down the page
+    let size_width = sorted_groups
+        .iter()
+        .map(|(_, group, _)| Bytes(group[0].size).to_string().len())
+        .max()
+        .unwrap_or(0);
+    let index_width = sorted_groups.len().to_string().len() + 1;
+    let mut user_names: HashMap<u32, String> = HashMap::new();
+    for (index, (hash, group, waste)) in sorted_groups.into_iter().enumerate() {
+        // Members on different devices can't be hardlinked to each other, so say where each is
+        let devices: HashSet<u64> = group.iter().filter_map(|file| file.device()).collect();
+        let spans_devices = devices.len() > 1;
+        warn!(
+            "{} {} {}  {} files{}, wasting {}",
+            format!("{:<index_width$}", format!("#{}", index + 1)).bold(),
+            format!("[{}]", hash.group_id()).dimmed(),
+            format!("{:>size_width$}", Bytes(group[0].size).to_string()).bold(),
+            group.len(),
+            if spans_devices {
+                format!(" on {} devices", devices.len())
+            } else {
+                String::new()
+            },
+            Bytes(waste)
+        );
+
+        // Files inside archives or compressed are never acted on, so one on disk is kept
+        let (indices, on_disk): (Vec<usize>, Vec<FileInfo>) = group
+            .iter()
+            .enumerate()
+            .filter(|(_, file)| file.is_actionable())
+            .map(|(i, file)| (i, (*file).clone()))
+            .unzip();
+        // With --within-dir-only, a copy is kept in each directory, and with --match of
+        // each name
+        let parts = policy.split(&on_disk);
+        let mut keep: Vec<Option<usize>> = vec![None; group.len()];
+        for part in parts.iter().filter(|part| !part.is_empty()) {
+            let copies: Vec<FileInfo> = part.iter().map(|&i| on_disk[i].clone()).collect();
+            let kept = indices[part[policy.select(&copies, root.scan_path())]];
+            for &i in part {
+                keep[indices[i]] = Some(kept);
+            }
+        }
+        let first_kept = keep.iter().flatten().next().copied();
+        for kept in keep.iter_mut().filter(|kept| kept.is_none()) {
+            *kept = first_kept;
+        }
+        let names: Vec<&OsStr> = group.iter().map(|file| file.path.file_name()).collect();
+        let copy_names = looks_like_copies(&names);
+        // Show paths relative to the scanned directory, with the directories in a column
+        let relative_paths: Vec<PathBuf> = group
+            .iter()
+            .map(|file| root.display_file(&file.path))
+            .collect();
+        let dirs: Vec<String> = relative_paths
+            .iter()
+            .map(|path| match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => {
+                    format!("{}{}", dir.display(), std::path::MAIN_SEPARATOR)
+                }
+                _ => String::new(),
+            })
+            .collect();
+        let dir_width = dirs
+            .iter()
+            .map(|dir| dir.chars().count())
+            .max()
+            .unwrap_or(0);
+        // Owners are shown where known, which helps tell apart copies with the same date
+        for uid in group.iter().filter_map(|file| file.owner) {
+            user_names.entry(uid).or_insert_with(|| user_name(uid));
+        }
+        let owners: Vec<&str> = group
+            .iter()
+            .map(|file| {
+                file.owner
+                    .and_then(|uid| user_names.get(&uid))
+                    .map_or("", String::as_str)
+            })
+            .collect();
+        let owner_width = owners
+            .iter()
+            .map(|owner| owner.chars().count())
+            .max()
+            .unwrap_or(0);
+        let mut first_link: HashMap<(u64, u64), PathBuf> = HashMap::new();
+        for (i, (file, copy_name)) in group.iter().zip(copy_names).enumerate() {
+            let relative_path = &relative_paths[i];
+            let modified = file
+                .modified
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or_else(|| "-".to_string(), |age| format_timestamp(age.as_secs()));
+            let name = relative_path
+                .file_name()
+                .unwrap_or(relative_path.as_os_str())
+                .to_string_lossy();
+            let (marker, name) = if keep[i] == Some(i) {
+                ("keep".green().bold(), name.green().bold())
             } else {
-                &file.path
+                ("    ".normal(), name.normal())
             };
-            warn!("  {}", relative_path.display());
+            let mut notes = match file.device() {
+                Some(device) if spans_devices => format!(" [device {}]", device),
+                _ => String::new(),
+            };
+            if file.compressed {
+                notes.push_str(&format!(" (compressed, {})", Bytes(file.size)));
+            }
+            // Hint at copies made by file managers, see --prefer-original-name
+            if copy_name {
+                notes.push_str(&" (copy?)".dimmed().to_string());
+            }
+            if let Some(k) = keep[i]
+                && k != i
+                && policy.streams_differ(file, group[k])
+            {
+                notes.push_str(&" (different xattrs/streams)".yellow().to_string());
+            }
+            if let Some(id) = file.file_id {
+                match first_link.get(&id) {
+                    Some(first) => {
+                        notes.push_str(&format!(" (hardlink of {})", first.display()));
+                    }
+                    None => {
+                        first_link.insert(id, relative_path.clone());
+                    }
+                }
+            }
+            let owner = if owner_width > 0 {
+                format!("{:<owner_width$}  ", owners[i])
+            } else {
+                String::new()
+            };
+            warn!(
+                "  {} {:<19}  {}{}{}{}",
+                marker,
+                modified,
+                owner,
+                format!("{:<dir_width$}", dirs[i]).dimmed(),
+                name,
+                notes
+            );
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retains_groups_spanning_both_subtrees() {
+        let file = |path: &str| FileInfo::for_test(path, 1, ContentHash::of(b""));
+        let mut duplicates = HashMap::from([
+            (ContentHash::of(b"across"), vec![file("/a/1"), file("/b/1")]),
+            (
+                ContentHash::of(b"within"),
+                vec![file("/a/2"), file("/a/x/2")],
+            ),
+            (
+                ContentHash::of(b"outside"),
+                vec![file("/a/3"), file("/c/3")],
+            ),
+            (
+                ContentHash::of(b"prefix"),
+                vec![file("/a/4"), file("/bb/4"), file("/b2/4")],
+            ),
+        ]);
+        retain_between(&mut duplicates, Path::new("/a"), Path::new("/b"));
+        assert_eq!(
+            duplicates.keys().collect::<Vec<_>>(),
+            [&ContentHash::of(b"across")]
+        );
+    }
+
+    #[test]
+    fn confirms_groups_as_files_arrive() {
+        let file = |path: &str, data: &[u8]| FileInfo::with_contents(path, data);
+        let mut groups = HashGroups::default();
+        assert!(groups.add(file("/a", b"same")).is_none());
+        assert!(groups.add(file("/b", b"other")).is_none());
+        let confirmed = groups.add(file("/c", b"same")).unwrap();
+        assert_eq!(confirmed.len(), 2);
+        assert!(groups.add(file("/d", b"same")).is_none());
+        groups.add(file("/e", b""));
+        groups.add(file("/f", b""));
+        assert_eq!((groups.len(), groups.duplicate_groups()), (6, 2));
+
+        assert_eq!(groups.retain(|file| file.size > 0), 2);
+        assert_eq!((groups.len(), groups.duplicate_groups()), (4, 1));
+        let duplicates = find_duplicates(groups.into_groups());
+        assert_eq!(duplicates[&ContentHash::of(b"same")].len(), 3);
+        assert_eq!(duplicates.len(), 1);
+    }
+
+    #[test]
+    fn counts_copies_linked_outside_the_group_once() {
+        let file = |path: &str, inode: u64, links: u64| FileInfo {
+            file_id: Some((1, inode)),
+            links: Some(links),
+            ..FileInfo::for_test(path, 100, ContentHash::of(b"data"))
+        };
+        // 'a' and 'b' are linked to each other only, 'c' also from outside the scan
+        let group = vec![
+            file("/a", 1, 2),
+            file("/b", 1, 2),
+            file("/c", 2, 2),
+            file("/d", 3, 1),
+        ];
+        assert!(!linked_outside(&group[0], &group));
+        assert!(linked_outside(&group[2], &group));
+
+        let naive = ReportOptions::default();
+        assert_eq!(wasted_space(&group, &naive), 200);
+        let once = ReportOptions {
+            count_hardlinks_once: true,
+            ..Default::default()
+        };
+        assert_eq!(wasted_space(&group, &once), 200);
+        assert_eq!(wasted_space(&group[2..], &once), 100);
+        assert_eq!(wasted_space(&group[..2], &once), 0);
+        let both_linked = vec![file("/c", 2, 2), file("/e", 4, 3)];
+        assert_eq!(wasted_space(&both_linked, &naive), 100);
+        assert_eq!(wasted_space(&both_linked, &once), 0);
+    }
+
+    #[test]
+    fn keeps_copies_within_one_directory() {
+        let file = |path: &str| FileInfo::for_test(path, 100, ContentHash::of(b"data"));
+        let mut duplicates = HashMap::from([
+            (
+                ContentHash::of(b"siblings"),
+                vec![
+                    file("/a/IMG_001.jpg"),
+                    file("/a/IMG_001 (1).jpg"),
+                    file("/b/IMG_001.jpg"),
+                    file("/c/x"),
+                    file("/c/y"),
+                ],
+            ),
+            (ContentHash::of(b"apart"), vec![file("/a/z"), file("/b/z")]),
+        ]);
+        retain_siblings(&mut duplicates);
+        assert_eq!(duplicates.len(), 1);
+        let group = &duplicates[&ContentHash::of(b"siblings")];
+        assert_eq!(group.len(), 4);
+        assert_eq!(
+            split_group(group, true, MatchMode::Content),
+            [vec![0, 1], vec![2, 3]]
+        );
+
+        let options = ReportOptions {
+            within_dir_only: true,
+            ..Default::default()
+        };
+        assert_eq!(wasted_space(group, &options), 200);
+        assert_eq!(wasted_space(group, &ReportOptions::default()), 300);
+    }
+
+    #[test]
+    fn matches_names_and_modification_times_with_contents() {
+        let file = |path: &str, modified: u64| FileInfo {
+            modified: Some(UNIX_EPOCH + std::time::Duration::from_secs(modified)),
+            ..FileInfo::for_test(path, 100, ContentHash::of(b"data"))
+        };
+        let duplicates = HashMap::from([
+            (
+                ContentHash::of(b"data"),
+                vec![
+                    file("/a/report.pdf", 1),
+                    file("/b/report.pdf", 1),
+                    file("/c/report.pdf", 2),
+                    file("/c/report-final.pdf", 1),
+                ],
+            ),
+            (
+                ContentHash::of(b"renamed"),
+                vec![file("/a/x", 1), file("/b/y", 1)],
+            ),
+        ]);
+
+        let mut by_name = duplicates.clone();
+        retain_matching(&mut by_name, MatchMode::ContentName);
+        assert_eq!(by_name.len(), 1);
+        let group = &by_name[&ContentHash::of(b"data")];
+        assert_eq!(group.len(), 3);
+        let options = ReportOptions {
+            match_mode: MatchMode::ContentName,
+            ..Default::default()
+        };
+        assert_eq!(wasted_space(group, &options), 200);
+
+        let mut by_time = duplicates;
+        retain_matching(&mut by_time, MatchMode::ContentNameMtime);
+        let group = &by_time[&ContentHash::of(b"data")];
+        assert_eq!(
+            split_group(group, false, MatchMode::ContentNameMtime),
+            [vec![0, 1]]
+        );
+    }
+
+    #[test]
+    fn counts_reflinks_once() {
+        let file = |path: &str| FileInfo::for_test(path, 100, ContentHash::of(b"data"));
+        let group = vec![file("/a"), file("/b"), file("/c")];
+        let options = ReportOptions {
+            shared_extents: ExtentIds::from([(PathBuf::from("/b"), 7), (PathBuf::from("/c"), 7)]),
+            ..Default::default()
+        };
+        assert_eq!(wasted_space(&group, &ReportOptions::default()), 200);
+        assert_eq!(wasted_space(&group, &options), 100);
+        assert_eq!(wasted_space(&group[1..], &options), 0);
+    }
+}
@@ -0,0 +1,190 @@
+//! Errors of the library surface: the hash cache, the scanner and the hashing it does.
+//! Each failure has its own variant, so integrations can tell a corrupted cache apart
+//! from a file that can't be read or options that can't be used, and handle them
+//! without parsing messages. The binary wraps them in `anyhow` errors with more context.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Result of the fallible functions of [`crate::cache`] and [`crate::scanner`].
+pub type Result<T, E = CheckFileDupsError> = std::result::Result<T, E>;
+
+/// Why an operation of the library failed.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CheckFileDupsError {
+    /// Reading or writing `path` failed, e.g. because it is missing or access was denied.
+    Io { path: PathBuf, source: io::Error },
+    /// The hash cache in `path` is truncated or corrupted.
+    CacheCorrupted { path: PathBuf, reason: String },
+    /// The hash cache in `path` is in format `version`, written by a newer version of the
+    /// program, so it can't be read but shouldn't be discarded either.
+    CacheVersionUnsupported { path: PathBuf, version: u32 },
+    /// The hash cache in `path` is locked, by another process saving it or by a thread
+    /// that failed while holding it.
+    CacheLocked { path: PathBuf },
+    /// `path` was modified while it was being hashed, so its hash may match neither
+    /// version.
+    ChangedDuringScan { path: PathBuf },
+    /// No data was read from `path` for `timeout`, as happens on unresponsive network
+    /// shares.
+    ReadTimeout { path: PathBuf, timeout: Duration },
+    /// `path` is not a regular file, e.g. a FIFO or socket listed in a stale file list.
+    NotRegularFile { path: PathBuf, kind: &'static str },
+    /// Options that can't be used, such as a malformed value.
+    InvalidOptions(String),
+    /// The threads to scan on could not be started.
+    ThreadPool(rayon::ThreadPoolBuildError),
+}
+
+impl CheckFileDupsError {
+    /// Returns an [`CheckFileDupsError::Io`] error for `source`, met reading or writing
+    /// `path`.
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Self::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// Returns the file the error is about, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Io { path, .. }
+            | Self::CacheCorrupted { path, .. }
+            | Self::CacheVersionUnsupported { path, .. }
+            | Self::CacheLocked { path }
+            | Self::ChangedDuringScan { path }
+            | Self::ReadTimeout { path, .. }
+            | Self::NotRegularFile { path, .. } => Some(path),
+            Self::InvalidOptions(_) | Self::ThreadPool(_) => None,
+        }
+    }
+
+    /// Returns the IO error behind the failure, if any.
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the failure is a lack of permission to read or write a file.
+    pub fn is_permission_denied(&self) -> bool {
+        self.io_error()
+            .is_some_and(|e| e.kind() == io::ErrorKind::PermissionDenied)
+    }
+}
+
+impl fmt::Display for CheckFileDupsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, source } => {
+                write!(f, "Failed to access {}", path.display())?;
+                if f.alternate() {
+                    write!(f, ": {}", source)?;
+                }
+                Ok(())
+            }
+            Self::CacheCorrupted { path, reason } => {
+                write!(
+                    f,
+                    "{} is not a readable hash cache: {}",
+                    path.display(),
+                    reason
+                )
+            }
+            Self::CacheVersionUnsupported { path, version } => write!(
+                f,
+                "The hash cache {} is in format version {}, newer than supported version {}",
+                path.display(),
+                version,
+                crate::cache::CACHE_FORMAT_VERSION
+            ),
+            Self::CacheLocked { path } => write!(
+                f,
+                "The hash cache {} is locked by another process or thread",
+                path.display()
+            ),
+            Self::ChangedDuringScan { .. } => f.write_str("modified while it was being hashed"),
+            Self::ReadTimeout { timeout, .. } => {
+                write!(f, "no data read for {} seconds", timeout.as_secs_f64())
+            }
+            Self::NotRegularFile { kind, .. } => write!(f, "not a regular file ({})", kind),
+            Self::InvalidOptions(message) => f.write_str(message),
+            Self::ThreadPool(source) => {
+                f.write_str("Failed to start the scanning threads")?;
+                if f.alternate() {
+                    write!(f, ": {}", source)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// With `{:#}`, the message is followed by that of the error it was caused by, as
+/// `anyhow` shows the chain of causes.
+impl std::error::Error for CheckFileDupsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::ThreadPool(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<rayon::ThreadPoolBuildError> for CheckFileDupsError {
+    fn from(error: rayon::ThreadPoolBuildError) -> Self {
+        Self::ThreadPool(error)
+    }
+}
+
+/// Attaches the path an IO operation was on to its error.
+pub(crate) trait IoResultExt<T> {
+    /// Turns an IO error into a [`CheckFileDupsError::Io`] error for `path`.
+    fn at(self, path: &Path) -> Result<T>;
+}
+
+impl<T> IoResultExt<T> for io::Result<T> {
+    fn at(self, path: &Path) -> Result<T> {
+        self.map_err(|e| CheckFileDupsError::io(path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan_errors::ScanErrorKind;
+
+    #[test]
+    fn io_errors_keep_their_path_and_cause() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        let error = std::fs::read(&missing).at(&missing).unwrap_err();
+        assert!(matches!(&error, CheckFileDupsError::Io { path, .. } if *path == missing));
+        assert_eq!(
+            error.io_error().map(io::Error::kind),
+            Some(io::ErrorKind::NotFound)
+        );
+        assert!(!error.is_permission_denied());
+        let cause = error.io_error().unwrap().to_string();
+        assert_eq!(
+            format!("{:#}", error),
+            format!("Failed to access {}: {}", missing.display(), cause)
+        );
+        let error = anyhow::Error::from(error).context("reading the cache");
+        assert_eq!(
+            format!("{:#}", error),
+            format!(
+                "reading the cache: Failed to access {}: {}",
+                missing.display(),
+                cause
+            )
+        );
+        assert_eq!(ScanErrorKind::of(&error), ScanErrorKind::Vanished);
+    }
+}
@@ -0,0 +1,266 @@
+//! `--estimate`: hashes a random sample of the files that share their size with another
+//! file and extrapolates the duplicates of the whole tree, with confidence bounds, in a
+//! fraction of the time a full scan takes.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use log::{info, warn};
+use rayon::prelude::*;
+
+use crate::cache::HashCache;
+use crate::scan_errors::ScanErrors;
+use crate::scan_root::ScanRoot;
+use crate::scanner::{
+    ModifiedRange, NameFilter, RetryPolicy, ScanLimit, auto_walk_threads, calculate_file_hash,
+    file_id, walk_directory,
+};
+use crate::units::{Bytes, Count};
+use crate::utils::new_progress_bar;
+
+/// Number of standard errors either side of an estimate covering 95% of outcomes.
+const Z_95: f64 = 1.96;
+
+/// A total extrapolated from a sample, with its 95% confidence interval.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub value: f64,
+    pub low: f64,
+    pub high: f64,
+}
+
+/// The duplicates of a tree, extrapolated from a sample of its files.
+#[derive(Debug, Clone, Copy)]
+pub struct Estimate {
+    /// Files listed, counting hardlinks of one file once.
+    pub files: u64,
+    /// Files sharing their size with another file, the only ones that can be duplicates.
+    pub candidates: u64,
+    /// Groups of files sharing a size.
+    pub size_groups: u64,
+    /// Size groups whose files were hashed.
+    pub sampled_groups: u64,
+    /// Files hashed, and their total size.
+    pub sampled_files: u64,
+    pub sampled_bytes: u64,
+    /// Files that duplicate another one, as counted by a full scan.
+    pub duplicate_files: Bounds,
+    /// Space taken by all but one copy of each file.
+    pub wasted_bytes: Bounds,
+}
+
+/// Extrapolates the sum over `total` groups of a value observed in a simple random
+/// sample of them. The interval is the usual normal approximation, with the finite
+/// population correction, so a sample of every group gives the exact total. The lower
+/// bound is never below what the sample itself holds.
+fn extrapolate(values: &[f64], total: usize) -> Bounds {
+    let n = values.len() as f64;
+    let sum: f64 = values.iter().sum();
+    if values.is_empty() {
+        return Bounds {
+            value: 0.0,
+            low: 0.0,
+            high: 0.0,
+        };
+    }
+    let mean = sum / n;
+    let value = mean * total as f64;
+    let variance = if values.len() > 1 {
+        values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0)
+    } else {
+        0.0
+    };
+    let error = total as f64 * ((1.0 - n / total as f64) * variance / n).sqrt();
+    Bounds {
+        value,
+        low: (value - Z_95 * error).max(sum),
+        high: value + Z_95 * error,
+    }
+}
+
+/// Lists the files under `root.scan_path()` like a scan, hashes those of up to
+/// `sample_groups` randomly chosen groups of files sharing a size, and extrapolates the
+/// duplicates of the whole tree. Empty files and files smaller than `min_size` are left
+/// out, like files modified outside `modified` and those left out by `names`. Hashes are taken from and added to
+/// `cache` when `use_cache` is set, so a full scan afterwards reuses them.
+#[allow(clippy::too_many_arguments)]
+pub fn estimate(
+    root: &ScanRoot,
+    cache: &HashCache,
+    use_cache: bool,
+    skip_dirs: &[String],
+    names: &NameFilter,
+    min_size: u64,
+    modified: &ModifiedRange,
+    sample_groups: usize,
+) -> Result<Estimate> {
+    let mut errors = ScanErrors::default();
+    let (file_paths, _) = walk_directory(
+        root,
+        skip_dirs,
+        names,
+        min_size,
+        modified,
+        &ScanLimit::default(),
+        false,
+        false,
+        None,
+        auto_walk_threads(),
+        &mut errors,
+    )?;
+    let files: Vec<_> = file_paths
+        .into_par_iter()
+        .filter_map(|path| {
+            let metadata = fs::metadata(&path).ok()?;
+            let id = file_id(&metadata);
+            Some((path, metadata.len(), id))
+        })
+        .collect();
+
+    // Hardlinks of one file share its data, so only the first is counted
+    let mut seen = HashSet::new();
+    let mut by_size: BTreeMap<u64, Vec<PathBuf>> = BTreeMap::new();
+    let mut listed = 0;
+    for (path, size, id) in files {
+        if size == 0 || !id.is_none_or(|id| seen.insert(id)) {
+            continue;
+        }
+        listed += 1;
+        by_size.entry(size).or_default().push(path);
+    }
+    let groups: Vec<(u64, Vec<PathBuf>)> = by_size
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .collect();
+    let candidates = groups.iter().map(|(_, paths)| paths.len() as u64).sum();
+
+    let sample = fastrand::choose_multiple(&groups, sample_groups);
+    let sampled_files = sample.iter().map(|(_, paths)| paths.len() as u64).sum();
+    let sampled_bytes = sample
+        .iter()
+        .map(|(size, paths)| size * paths.len() as u64)
+        .sum();
+    info!(
+        "Hashing {} files ({}) from {} of {} groups of files sharing a size...",
+        Count(sampled_files),
+        Bytes(sampled_bytes),
+        Count(sample.len() as u64),
+        Count(groups.len() as u64)
+    );
+    let progress = new_progress_bar(sampled_bytes);
+    let retry = RetryPolicy::default();
+    // Duplicate files and wasted bytes of each sampled group
+    let observed: Vec<(f64, f64)> = sample
+        .par_iter()
+        .map(|(size, paths)| {
+            let mut copies: HashMap<_, u64> = HashMap::new();
+            for path in paths {
+                match calculate_file_hash(
+                    path, root, cache, use_cache, None, None, &retry, None, None,
+                ) {
+                    Ok(hash) => *copies.entry(hash).or_default() += 1,
+                    Err(e) => warn!("Failed to hash '{}': {}", path.display(), e),
+                }
+                progress.inc(*size);
+            }
+            let duplicates: u64 = copies.values().map(|count| count - 1).sum();
+            (duplicates as f64, (duplicates * size) as f64)
+        })
+        .collect();
+    progress.finish_and_clear();
+
+    let (duplicates, wasted): (Vec<f64>, Vec<f64>) = observed.into_iter().unzip();
+    Ok(Estimate {
+        files: listed,
+        candidates,
+        size_groups: groups.len() as u64,
+        sampled_groups: sample.len() as u64,
+        sampled_files,
+        sampled_bytes,
+        duplicate_files: extrapolate(&duplicates, groups.len()),
+        wasted_bytes: extrapolate(&wasted, groups.len()),
+    })
+}
+
+/// Prints `estimate` with its confidence intervals.
+pub fn print_estimate(estimate: &Estimate) {
+    info!(
+        "Listed {} files, {} of which share their size with another file ({} sizes)",
+        Count(estimate.files),
+        Count(estimate.candidates),
+        Count(estimate.size_groups)
+    );
+    info!(
+        "Hashed {} files ({}) from {} of those sizes",
+        Count(estimate.sampled_files),
+        Bytes(estimate.sampled_bytes),
+        Count(estimate.sampled_groups)
+    );
+    let files = estimate.duplicate_files;
+    let bytes = estimate.wasted_bytes;
+    warn!(
+        "Estimated {} duplicate files wasting {} of space",
+        Count(files.value.round() as u64),
+        Bytes(bytes.value.round() as u64)
+    );
+    warn!(
+        "95% confidence: {} to {} duplicate files, wasting {} to {}",
+        Count(files.low.round() as u64),
+        Count(files.high.round() as u64),
+        Bytes(bytes.low.round() as u64),
+        Bytes(bytes.high.round() as u64)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extrapolates_from_sampled_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, contents) in [
+            ("a1", "aaaa"),
+            ("a2", "aaaa"),
+            ("a3", "aaaa"),
+            ("b1", "bbbbbb"),
+            ("b2", "cccccc"),
+            ("unique", "u"),
+            ("empty1", ""),
+            ("empty2", ""),
+        ] {
+            fs::write(dir.path().join(name), contents).unwrap();
+        }
+        let root = ScanRoot::for_base(dir.path());
+        let cache = HashCache::load(dir.path().join("cache.json.zst"));
+        let estimate = estimate(
+            &root,
+            &cache,
+            false,
+            &[],
+            &NameFilter::default(),
+            0,
+            &ModifiedRange::default(),
+            10,
+        )
+        .unwrap();
+        assert_eq!(
+            (estimate.files, estimate.candidates, estimate.size_groups),
+            (6, 5, 2)
+        );
+        // Every group was hashed, so the estimate is exact
+        let exact = |value| Bounds {
+            value,
+            low: value,
+            high: value,
+        };
+        assert_eq!(estimate.duplicate_files, exact(2.0));
+        assert_eq!(estimate.wasted_bytes, exact(8.0));
+
+        let half = extrapolate(&[2.0, 0.0], 4);
+        assert_eq!(half.value, 4.0);
+        assert!(half.low == 2.0 && half.high > 4.0 + Z_95);
+    }
+}
@@ -0,0 +1,188 @@
+//! `--exec`: running a command of the user's for each duplicate group, e.g. to feed the
+//! groups into a review tool of their own. The command is a template run by the shell
+//! (`sh` on Unix, PowerShell on Windows), in which [`KEEPER`] is replaced by the copy
+//! that would be kept and [`DUPES`] by the duplicates that `--delete` would remove, each
+//! quoted as a word of its own, and [`GROUP_ID`] by the ID of the group.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::FileInfo;
+use crate::actions::{Action, Operation, plan_operations};
+use crate::content_hash::{ContentHash, GROUP_ID_LEN};
+use crate::selection::KeepPolicy;
+use crate::units::Count;
+
+/// Placeholder for the copy of a group that would be kept.
+pub const KEEPER: &str = "{keeper}";
+/// Placeholder for the other copies of a group, as separate words.
+pub const DUPES: &str = "{dupes...}";
+/// Placeholder for the ID of a group, as accepted by `--group`.
+pub const GROUP_ID: &str = "{id}";
+
+/// Returns the command line of `template` for a group, with its placeholders replaced by
+/// the `keeper`, the `dupes` and the group `id`, each path quoted with `quote`. Returns
+/// `None` if a path isn't valid UTF-8, since the shell can't be given it.
+pub fn expand(
+    template: &str,
+    id: &str,
+    keeper: &Path,
+    dupes: &[PathBuf],
+    quote: fn(&str) -> String,
+) -> Option<String> {
+    let keeper = quote(keeper.to_str()?);
+    let dupes = dupes
+        .iter()
+        .map(|path| path.to_str().map(quote))
+        .collect::<Option<Vec<String>>>()?
+        .join(" ");
+    Some(
+        template
+            .replace(KEEPER, &keeper)
+            .replace(DUPES, &dupes)
+            .replace(GROUP_ID, id),
+    )
+}
+
+/// Runs `command_line` with the shell, returning whether it succeeded.
+fn run_shell(command_line: &str) -> std::io::Result<bool> {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("powershell");
+        command.args(["-NoProfile", "-Command"]);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c");
+        command
+    };
+    Ok(command.arg(command_line).status()?.success())
+}
+
+/// Runs the command `template` once for each group of `duplicates`, in order of hash,
+/// keeping the copy chosen by `policy` as `--delete` would, under `scan_path`. Groups
+/// where `--delete` would remove nothing, e.g. since the other copies are protected or
+/// hardlinks of the kept one, are left out. A command that fails is logged, and the
+/// other groups are still run.
+///
+/// # Errors
+///
+/// Returns an error if any group consists only of protected files, as for `--delete`.
+pub fn run_for_groups(
+    duplicates: &HashMap<ContentHash, Vec<FileInfo>>,
+    scan_path: &Path,
+    template: &str,
+    policy: &KeepPolicy,
+) -> Result<()> {
+    let quote = if cfg!(windows) {
+        crate::script::powershell_quote
+    } else {
+        crate::script::sh_quote
+    };
+    // Operations come group by group, so each run of the same kept copy is a group
+    let mut groups: Vec<(String, PathBuf, Vec<PathBuf>)> = Vec::new();
+    for (operation, _) in plan_operations(duplicates, scan_path, &Action::Delete, policy)? {
+        let Operation::Delete {
+            path, kept, hash, ..
+        } = operation
+        else {
+            continue;
+        };
+        match groups.last_mut() {
+            Some((_, last_kept, dupes)) if *last_kept == kept => dupes.push(path),
+            _ => groups.push((hash, kept, vec![path])),
+        }
+    }
+
+    let mut failed = 0;
+    for (hash, keeper, dupes) in &groups {
+        let id = &hash[..hash.len().min(GROUP_ID_LEN)];
+        let Some(command_line) = expand(template, id, keeper, dupes, quote) else {
+            warn!(
+                "Not running the --exec command for group {}, since a path isn't valid UTF-8",
+                id
+            );
+            failed += 1;
+            continue;
+        };
+        match run_shell(&command_line) {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!("--exec command failed for group {}: {}", id, command_line);
+                failed += 1;
+            }
+            Err(e) => {
+                warn!("Failed to run the --exec command for group {}: {}", id, e);
+                failed += 1;
+            }
+        }
+    }
+    info!(
+        "Ran the --exec command for {} duplicate groups",
+        Count((groups.len() - failed) as u64)
+    );
+    if failed > 0 {
+        warn!(
+            "The --exec command failed for {} duplicate groups",
+            Count(failed as u64)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::sh_quote;
+
+    #[test]
+    fn expands_placeholders_into_quoted_paths() {
+        let dupes = [PathBuf::from("/b/it's.jpg"), PathBuf::from("/c/a b.jpg")];
+        assert_eq!(
+            expand(
+                "review --id {id} {keeper} {dupes...}",
+                "0123456789ab",
+                Path::new("/a/keep.jpg"),
+                &dupes,
+                sh_quote
+            )
+            .as_deref(),
+            Some(r"review --id 0123456789ab '/a/keep.jpg' '/b/it'\''s.jpg' '/c/a b.jpg'")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_the_command_for_each_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str, content: &[u8]| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            FileInfo::with_contents(path, content)
+        };
+        let duplicates = HashMap::from([
+            (
+                ContentHash::of(b"a"),
+                vec![file("a1", b"a"), file("a2", b"a"), file("a3", b"a")],
+            ),
+            (
+                ContentHash::of(b"b"),
+                vec![file("b1", b"b"), file("b2", b"b")],
+            ),
+        ]);
+        let log = dir.path().join("log");
+        let template = format!("echo {{keeper}} {{dupes...}} >> '{}'", log.display());
+        run_for_groups(&duplicates, dir.path(), &template, &KeepPolicy::default()).unwrap();
+
+        let mut lines: Vec<String> = std::fs::read_to_string(&log)
+            .unwrap()
+            .lines()
+            .map(|line| line.replace(&format!("{}/", dir.path().display()), ""))
+            .collect();
+        lines.sort();
+        assert_eq!(lines, ["a1 a2 a3", "b1 b2"]);
+    }
+}
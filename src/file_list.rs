@@ -0,0 +1,63 @@
+//! Reading the files to scan from a list given with `--files-from`, such as the output of
+//! `find -print0`, instead of walking the scanned directory.
+
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::paths::path_from_bytes;
+
+/// Splits `data` into paths: at NUL bytes if there are any, so names containing newlines
+/// survive, otherwise at line breaks. Empty entries are skipped.
+fn parse_file_list(data: &[u8]) -> Vec<PathBuf> {
+    let separator = if data.contains(&0) { 0 } else { b'\n' };
+    data.split(|&byte| byte == separator)
+        .map(|entry| {
+            if separator == b'\n' {
+                entry.strip_suffix(b"\r").unwrap_or(entry)
+            } else {
+                entry
+            }
+        })
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| path_from_bytes(entry.to_vec()))
+        .collect()
+}
+
+/// Reads the paths listed in `source`, or on standard input if it is `-`, made absolute
+/// against the current directory.
+pub fn read_file_list(source: &Path) -> Result<Vec<PathBuf>> {
+    let data = if source == Path::new("-") {
+        let mut data = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut data)
+            .context("Failed to read the file list from standard input")?;
+        data
+    } else {
+        fs::read(source)
+            .with_context(|| format!("Failed to read the file list {}", source.display()))?
+    };
+    parse_file_list(&data)
+        .into_iter()
+        .map(|path| Ok(std::path::absolute(&path)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_at_nul_bytes_or_line_breaks() {
+        assert_eq!(
+            parse_file_list(b"a/one\r\nb/two\n\nthree\n"),
+            [Path::new("a/one"), Path::new("b/two"), Path::new("three")]
+        );
+        assert_eq!(
+            parse_file_list(b"new\nline\0plain\0"),
+            [Path::new("new\nline"), Path::new("plain")]
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Broad kinds of files, used to break down wasted space in the summary and, with
+//! `--type`, to only scan files of some kinds. The kind is told from the first bytes of
+//! a file where they are distinctive, so files without an extension (such as photos
+//! exported from phones as `IMG_1234`) are still recognized, and from the extension
+//! otherwise.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use log::{debug, info};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::units::{Bytes, Count};
+
+/// Number of bytes read from the start of a file to recognize it, enough to reach the
+/// `ustar` signature of tar archives at offset 257.
+const SNIFF_LEN: usize = 512;
+
+/// Broad kinds of files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileType {
+    /// Videos, e.g. MP4, QuickTime, Matroska and AVI
+    Video,
+    /// Images, including HEIC and camera raw files
+    Image,
+    /// Audio, e.g. MP3, AAC, FLAC and WAV
+    Audio,
+    /// PDF, office and text documents
+    Document,
+    /// Compressed files and archives
+    Archive,
+    /// Anything else
+    Other,
+}
+
+impl FileType {
+    /// Detects the kind of a file from its extension.
+    pub fn from_path(path: &Path) -> Self {
+        let Some(ext) = path.extension().and_then(|ext| ext.to_str()) else {
+            return Self::Other;
+        };
+        match ext.to_ascii_lowercase().as_str() {
+            "3gp" | "avi" | "m2ts" | "m4v" | "mkv" | "mov" | "mp4" | "mpeg" | "mpg" | "mts"
+            | "webm" | "wmv" => Self::Video,
+            "arw" | "bmp" | "cr2" | "dng" | "gif" | "heic" | "heif" | "jpeg" | "jpg" | "nef"
+            | "orf" | "png" | "psd" | "rw2" | "svg" | "tif" | "tiff" | "webp" => Self::Image,
+            "aac" | "aif" | "aiff" | "flac" | "m4a" | "mp3" | "oga" | "ogg" | "opus" | "wav"
+            | "wma" => Self::Audio,
+            "csv" | "doc" | "docx" | "epub" | "md" | "odp" | "ods" | "odt" | "pdf" | "ppt"
+            | "pptx" | "rtf" | "txt" | "xls" | "xlsx" => Self::Document,
+            "7z" | "bz2" | "dmg" | "gz" | "iso" | "rar" | "tar" | "tgz" | "xz" | "zip" => {
+                Self::Archive
+            }
+            _ => Self::Other,
+        }
+    }
+
+    /// Recognizes the kind of a file from `header`, its first bytes, or returns `None` if
+    /// they aren't distinctive (as for plain text).
+    pub fn from_header(header: &[u8]) -> Option<Self> {
+        let at =
+            |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+        // Containers whose kind depends on a second signature
+        if at(0, b"RIFF") {
+            return match header.get(8..12)? {
+                b"WEBP" => Some(Self::Image),
+                b"AVI " => Some(Self::Video),
+                b"WAVE" => Some(Self::Audio),
+                _ => None,
+            };
+        }
+        if at(0, b"FORM") && (at(8, b"AIFF") || at(8, b"AIFC")) {
+            return Some(Self::Audio);
+        }
+        if at(4, b"ftyp") {
+            return match header.get(8..12)? {
+                b"heic" | b"heix" | b"heim" | b"heis" | b"mif1" | b"msf1" | b"avif" => {
+                    Some(Self::Image)
+                }
+                b"M4A " | b"M4B " | b"M4P " => Some(Self::Audio),
+                _ => Some(Self::Video),
+            };
+        }
+        if at(0, b"PK\x03\x04") {
+            // Office documents and EPUBs are zip files that start with a known member
+            let name = header.get(30..)?;
+            return if name.starts_with(b"[Content_Types].xml") || name.starts_with(b"mimetype") {
+                Some(Self::Document)
+            } else {
+                Some(Self::Archive)
+            };
+        }
+
+        const SIGNATURES: &[(&[u8], FileType)] = &[
+            (b"\xFF\xD8\xFF", FileType::Image),
+            (b"\x89PNG", FileType::Image),
+            (b"GIF8", FileType::Image),
+            (b"II*\0", FileType::Image),
+            (b"MM\0*", FileType::Image),
+            (b"IIRO", FileType::Image),
+            (b"IIU\0", FileType::Image),
+            (b"8BPS", FileType::Image),
+            (b"\x1A\x45\xDF\xA3", FileType::Video),
+            (b"\0\0\x01\xBA", FileType::Video),
+            (b"\x30\x26\xB2\x75\x8E\x66\xCF\x11", FileType::Video),
+            (b"ID3", FileType::Audio),
+            (b"fLaC", FileType::Audio),
+            (b"OggS", FileType::Audio),
+            (b"%PDF", FileType::Document),
+            (b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1", FileType::Document),
+            (b"{\\rtf", FileType::Document),
+            (b"PK\x05\x06", FileType::Archive),
+            (b"\x1F\x8B", FileType::Archive),
+            (b"BZh", FileType::Archive),
+            (b"\xFD7zXZ\0", FileType::Archive),
+            (b"7z\xBC\xAF\x27\x1C", FileType::Archive),
+            (b"Rar!\x1A\x07", FileType::Archive),
+            (b"\x28\xB5\x2F\xFD", FileType::Archive),
+        ];
+        if let Some((_, file_type)) = SIGNATURES.iter().find(|(magic, _)| at(0, magic)) {
+            return Some(*file_type);
+        }
+        if at(257, b"ustar") {
+            return Some(Self::Archive);
+        }
+        // MPEG transport streams have a sync byte every 188 bytes
+        if at(0, b"\x47") && at(188, b"\x47") {
+            return Some(Self::Video);
+        }
+        // MP3 and ADTS AAC frames without an ID3 tag start with an 11-bit frame sync
+        if let [0xFF, second, ..] = header
+            && second & 0xE0 == 0xE0
+        {
+            return Some(Self::Audio);
+        }
+        None
+    }
+
+    /// Detects the kind of the file at `path` from its first bytes, or from its extension
+    /// if they aren't distinctive.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read.
+    pub fn detect(path: &Path) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(SNIFF_LEN);
+        fs::File::open(path)?
+            .take(SNIFF_LEN as u64)
+            .read_to_end(&mut header)?;
+        Ok(Self::from_header(&header).unwrap_or_else(|| Self::from_path(path)))
+    }
+
+    /// Plural name shown in the summary, e.g. `videos`.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Video => "videos",
+            Self::Image => "images",
+            Self::Audio => "audio",
+            Self::Document => "documents",
+            Self::Archive => "archives",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// Keeps the files of `file_paths` whose kind is one of `types`, reading their first
+/// bytes in parallel, and returns their total size. Files that can't be read are kept,
+/// so hashing reports them.
+pub fn retain_types(file_paths: &mut Vec<PathBuf>, types: &[FileType]) -> u64 {
+    let count = file_paths.len();
+    let kept: Vec<(PathBuf, u64)> = std::mem::take(file_paths)
+        .into_par_iter()
+        .filter_map(|path| {
+            let wanted = match FileType::detect(&path) {
+                Ok(file_type) => types.contains(&file_type),
+                Err(e) => {
+                    debug!(
+                        "Failed to read '{}' to detect its type: {}",
+                        path.display(),
+                        e
+                    );
+                    true
+                }
+            };
+            let size = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+            wanted.then_some((path, size))
+        })
+        .collect();
+    let total_size = kept.iter().map(|(_, size)| size).sum();
+    *file_paths = kept.into_iter().map(|(path, _)| path).collect();
+    let labels: Vec<&str> = types.iter().map(|file_type| file_type.label()).collect();
+    info!(
+        "Kept {} of {} files that are {} ({})",
+        Count(file_paths.len() as u64),
+        Count(count as u64),
+        labels.join(" or "),
+        Bytes(total_size)
+    );
+    total_size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_files_without_extensions_by_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str, content: &[u8]| {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            path
+        };
+        let photo = file("IMG_1234", b"\xFF\xD8\xFF\xE1\0\0Exif");
+        let iphone = file("IMG_5678", b"\0\0\0\x18ftypheic\0\0\0\0mif1heic");
+        let clip = file("VID_0001", b"\0\0\0\x20ftypisom\0\0\x02\0isomiso2");
+        let notes = file("notes.txt", b"just text");
+        let unknown = file("README", b"just text");
+        let renamed = file("photo.txt", b"\x89PNG\r\n\x1A\n");
+
+        assert_eq!(FileType::detect(&photo).unwrap(), FileType::Image);
+        assert_eq!(FileType::detect(&iphone).unwrap(), FileType::Image);
+        assert_eq!(FileType::detect(&clip).unwrap(), FileType::Video);
+        assert_eq!(FileType::detect(&notes).unwrap(), FileType::Document);
+        assert_eq!(FileType::detect(&unknown).unwrap(), FileType::Other);
+        assert_eq!(FileType::detect(&renamed).unwrap(), FileType::Image);
+
+        let mut paths = vec![
+            photo.clone(),
+            iphone.clone(),
+            clip,
+            notes,
+            unknown,
+            renamed.clone(),
+        ];
+        retain_types(&mut paths, &[FileType::Image]);
+        assert_eq!(paths, [photo, iphone, renamed]);
+    }
+}
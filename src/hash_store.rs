@@ -0,0 +1,232 @@
+//! Storage of the hashes a scan reuses, behind the [`HashStore`] trait, so the scanner
+//! doesn't depend on how they are kept. [`HashCache`], saved as zstd-compressed JSON or
+//! in the binary format, is the backend runs use; [`MemoryStore`] keeps hashes for the
+//! lifetime of the process only, for tests and one-off library use. A [`LoadingCache`]
+//! is a `HashCache` still being loaded on another thread, waited for on first use. Other
+//! backends, such as a database or a store shared over the network, implement the same
+//! trait.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cache::{HashCache, LoadingCache, PruneStats};
+use crate::error::{IoResultExt, Result};
+use crate::scan_root::ScanRoot;
+
+/// Hashes of files keyed by their path relative to the base path of a [`ScanRoot`],
+/// valid while the file keeps its size and modification time.
+pub trait HashStore: Send + Sync {
+    /// Returns the hash recorded for `file_path` if the file hasn't changed since.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file's metadata cannot be read.
+    fn get_hash(&self, file_path: &Path, root: &ScanRoot) -> Result<Option<String>>;
+
+    /// Records `hash` for `file_path`, with its current size and modification time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file's metadata cannot be read.
+    fn set_hash(&self, file_path: &Path, root: &ScanRoot, hash: String) -> Result<()>;
+
+    /// Forgets the hash of `file_path`, returning `true` if one was recorded.
+    fn remove(&self, file_path: &Path, root: &ScanRoot) -> bool;
+
+    /// Forgets the hashes of files that no longer exist, only those last modified more
+    /// than `older_than` ago if given.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be accessed.
+    fn prune(&self, root: &ScanRoot, older_than: Option<Duration>) -> Result<PruneStats>;
+
+    /// Writes what changed to wherever the store keeps it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be written.
+    fn flush(&self) -> Result<()>;
+
+    /// Returns and forgets the files whose hashes were returned although their
+    /// modification time changed, to be hashed again. Stores that never return such
+    /// hashes have none.
+    fn take_suspects(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+}
+
+impl HashStore for HashCache {
+    fn get_hash(&self, file_path: &Path, root: &ScanRoot) -> Result<Option<String>> {
+        HashCache::get_hash(self, file_path, root)
+    }
+
+    fn set_hash(&self, file_path: &Path, root: &ScanRoot, hash: String) -> Result<()> {
+        HashCache::set_hash(self, file_path, root, hash)
+    }
+
+    fn remove(&self, file_path: &Path, root: &ScanRoot) -> bool {
+        HashCache::remove(self, file_path, root)
+    }
+
+    fn prune(&self, root: &ScanRoot, older_than: Option<Duration>) -> Result<PruneStats> {
+        HashCache::prune(self, root, older_than)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.save()
+    }
+
+    fn take_suspects(&self) -> Vec<PathBuf> {
+        HashCache::take_suspects(self)
+    }
+}
+
+impl HashStore for LoadingCache {
+    fn get_hash(&self, file_path: &Path, root: &ScanRoot) -> Result<Option<String>> {
+        self.wait().get_hash(file_path, root)
+    }
+
+    fn set_hash(&self, file_path: &Path, root: &ScanRoot, hash: String) -> Result<()> {
+        self.wait().set_hash(file_path, root, hash)
+    }
+
+    fn remove(&self, file_path: &Path, root: &ScanRoot) -> bool {
+        self.wait().remove(file_path, root)
+    }
+
+    fn prune(&self, root: &ScanRoot, older_than: Option<Duration>) -> Result<PruneStats> {
+        self.wait().prune(root, older_than)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.wait().save()
+    }
+
+    fn take_suspects(&self) -> Vec<PathBuf> {
+        self.wait().take_suspects()
+    }
+}
+
+/// Returns the size and modification time, in seconds since the epoch, of `file_path`.
+fn stamp(file_path: &Path) -> Result<(u64, u64)> {
+    let metadata = file_path.metadata().at(file_path)?;
+    let mtime = metadata
+        .modified()
+        .and_then(|modified| {
+            modified
+                .duration_since(UNIX_EPOCH)
+                .map_err(std::io::Error::other)
+        })
+        .at(file_path)?
+        .as_secs();
+    Ok((metadata.len(), mtime))
+}
+
+/// A [`HashStore`] in memory, lost when dropped. Flushing does nothing.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    /// Key -> (size, mtime, hash).
+    entries: Mutex<HashMap<String, (u64, u64, String)>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of hashes recorded.
+    pub fn len(&self) -> usize {
+        self.entries.lock().map_or(0, |entries| entries.len())
+    }
+
+    /// Returns `true` if no hash is recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl HashStore for MemoryStore {
+    fn get_hash(&self, file_path: &Path, root: &ScanRoot) -> Result<Option<String>> {
+        let current = stamp(file_path)?;
+        let Ok(entries) = self.entries.lock() else {
+            return Ok(None);
+        };
+        Ok(entries
+            .get(&root.key(file_path))
+            .filter(|(size, mtime, _)| (*size, *mtime) == current)
+            .map(|(_, _, hash)| hash.clone()))
+    }
+
+    fn set_hash(&self, file_path: &Path, root: &ScanRoot, hash: String) -> Result<()> {
+        let (size, mtime) = stamp(file_path)?;
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.insert(root.key(file_path), (size, mtime, hash));
+        }
+        Ok(())
+    }
+
+    fn remove(&self, file_path: &Path, root: &ScanRoot) -> bool {
+        self.entries
+            .lock()
+            .is_ok_and(|mut entries| entries.remove(&root.key(file_path)).is_some())
+    }
+
+    fn prune(&self, root: &ScanRoot, older_than: Option<Duration>) -> Result<PruneStats> {
+        let cutoff = older_than.map(|age| {
+            SystemTime::now()
+                .checked_sub(age)
+                .unwrap_or(UNIX_EPOCH)
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs())
+        });
+        let Ok(mut entries) = self.entries.lock() else {
+            return Ok(PruneStats::default());
+        };
+        let mut stats = PruneStats {
+            total_entries: entries.len(),
+            ..PruneStats::default()
+        };
+        entries.retain(|key, (size, mtime, _)| {
+            let keep = cutoff.is_some_and(|cutoff| *mtime >= cutoff) || root.resolve(key).exists();
+            if !keep {
+                stats.removed_entries += 1;
+                stats.removed_bytes += *size;
+            }
+            keep
+        });
+        Ok(stats)
+    }
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::{ScanOptions, scan_directory};
+    use std::fs;
+
+    #[test]
+    fn scans_reuse_hashes_kept_in_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), "same").unwrap();
+        fs::write(dir.path().join("b"), "same").unwrap();
+        let root = ScanRoot::for_base(dir.path());
+        let store = MemoryStore::new();
+
+        let (groups, _, stats) = scan_directory(&root, &store, ScanOptions::default()).unwrap();
+        assert_eq!((groups.duplicate_groups(), stats.hashed_files), (1, 2));
+        assert_eq!(store.len(), 2);
+        let (_, _, stats) = scan_directory(&root, &store, ScanOptions::default()).unwrap();
+        assert_eq!((stats.cached_files, stats.hashed_files), (2, 0));
+
+        fs::remove_file(dir.path().join("b")).unwrap();
+        let pruned = store.prune(&root, None).unwrap();
+        assert_eq!((pruned.removed_entries, store.len()), (1, 1));
+    }
+}
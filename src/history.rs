@@ -0,0 +1,253 @@
+//! Duplication history: a summary of each completed scan, appended next to the hash
+//! cache, and the trend shown by `check-file-dups history`, to tell whether cleaning up
+//! actually reduces duplication over the months.
+
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::report::DuplicateReport;
+use crate::units::{Bytes, Count};
+
+/// Characters of the sparkline, from the least to the most wasted space.
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Summary of one completed scan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// RFC 3339 timestamp of the scan.
+    pub date: String,
+    /// Directory that was scanned.
+    #[serde(with = "crate::paths::serde_path")]
+    pub scan_path: PathBuf,
+    /// Number of files scanned.
+    pub files: usize,
+    /// Number of duplicate groups.
+    pub groups: usize,
+    /// Number of redundant files (excluding one copy per group).
+    pub duplicates: usize,
+    /// Space used by redundant files, in bytes.
+    pub wasted_space: u64,
+}
+
+impl HistoryEntry {
+    /// Summarizes `report`, of a scan that went through `files` files.
+    pub fn from_report(report: &DuplicateReport, files: usize) -> Self {
+        Self {
+            date: report.generated_at.clone(),
+            scan_path: report.scan_path.clone(),
+            files,
+            groups: report.groups.len(),
+            duplicates: report.total_duplicates,
+            wasted_space: report.total_wasted_space,
+        }
+    }
+}
+
+/// Returns the path of the history file kept next to `cache_file`.
+pub fn history_file_for_cache(cache_file: &Path) -> PathBuf {
+    let mut name = cache_file.file_name().unwrap_or_default().to_os_string();
+    name.push(".history");
+    cache_file.with_file_name(name)
+}
+
+/// Appends `entry` to the history in `file`, one JSON object per line. A last line cut
+/// short, e.g. by a crash, is ended first, so the entry isn't glued to it.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened or written.
+pub fn append_entry(file: &Path, entry: &HistoryEntry) -> Result<()> {
+    let line = serde_json::to_string(entry)?;
+    append_line(file, &line)
+        .with_context(|| format!("Failed to append to the history {}", file.display()))
+}
+
+/// Appends `line` to `file`, after a line break if its last line wasn't ended.
+fn append_line(file: &Path, line: &str) -> std::io::Result<()> {
+    let mut out = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(file)?;
+    let mut text = format!("{}\n", line);
+    let len = out.metadata()?.len();
+    if len > 0 {
+        let mut last = [0];
+        out.seek(SeekFrom::Start(len - 1))?;
+        out.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            text.insert(0, '\n');
+        }
+    }
+    // In one write, so lines appended by concurrent scans don't interleave
+    out.write_all(text.as_bytes())
+}
+
+/// Appends `entry` to the history in `file`, warning instead of failing the scan.
+pub fn record_scan(file: &Path, entry: &HistoryEntry) {
+    if let Err(e) = append_entry(file, entry) {
+        warn!("Failed to record the scan in the history: {:#}", e);
+    }
+}
+
+/// Loads the history in `file`, oldest first, or none if it doesn't exist. Lines that
+/// cannot be parsed, e.g. one cut short by a crash, are skipped.
+///
+/// # Errors
+///
+/// Returns an error if the file exists but cannot be read.
+pub fn load_history(file: &Path) -> Result<Vec<HistoryEntry>> {
+    let reader = match fs::File::open(file) {
+        Ok(reader) => BufReader::new(reader),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to open {}", file.display()));
+        }
+    };
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line.with_context(|| format!("Failed to read {}", file.display()))?;
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(_) if line.trim().is_empty() => {}
+            Err(e) => warn!("Skipping a history entry that cannot be parsed: {}", e),
+        }
+    }
+    Ok(entries)
+}
+
+/// Keeps the entries of the scans of `path`, or of the directory of the last scan if
+/// none is given, since the trend across scans of different directories means nothing.
+/// The other directories recorded are logged, to pick one from.
+pub fn scans_of(entries: Vec<HistoryEntry>, path: Option<&Path>) -> Vec<HistoryEntry> {
+    let Some(path) = path
+        .map(Path::to_path_buf)
+        .or_else(|| entries.last().map(|entry| entry.scan_path.clone()))
+    else {
+        return entries;
+    };
+    let others: BTreeSet<&Path> = entries
+        .iter()
+        .map(|entry| entry.scan_path.as_path())
+        .filter(|scanned| *scanned != path)
+        .collect();
+    if !others.is_empty() {
+        let others: Vec<String> = others.iter().map(|p| p.display().to_string()).collect();
+        info!(
+            "Scans of other directories were recorded too, pass one to see its history: {}",
+            others.join(", ")
+        );
+    }
+    info!("Scans of {}", path.display());
+    entries
+        .into_iter()
+        .filter(|entry| entry.scan_path == path)
+        .collect()
+}
+
+/// Draws `values` as a sparkline, scaled from zero to the largest value.
+pub fn sparkline(values: &[u64]) -> String {
+    let most = values.iter().copied().max().unwrap_or(0);
+    values
+        .iter()
+        .map(|&value| {
+            let level = if most == 0 {
+                0
+            } else {
+                (value as u128 * (SPARKS.len() - 1) as u128 / most as u128) as usize
+            };
+            SPARKS[level]
+        })
+        .collect()
+}
+
+/// Prints the last `limit` entries of `entries` as a table, with a sparkline of the
+/// wasted space and the change since the first one shown.
+pub fn print_history(entries: &[HistoryEntry], limit: usize) {
+    let shown = &entries[entries.len().saturating_sub(limit)..];
+    let (Some(first), Some(last)) = (shown.first(), shown.last()) else {
+        info!("No scans recorded yet");
+        return;
+    };
+    info!(
+        "  {:<25}  {:>10}  {:>8}  {:>10}  {:>10}",
+        "Date", "Files", "Groups", "Duplicates", "Wasted"
+    );
+    for entry in shown {
+        info!(
+            "  {:<25}  {:>10}  {:>8}  {:>10}  {:>10}",
+            entry.date,
+            Count(entry.files as u64).to_string(),
+            Count(entry.groups as u64).to_string(),
+            Count(entry.duplicates as u64).to_string(),
+            Bytes(entry.wasted_space).to_string()
+        );
+    }
+    let wasted: Vec<u64> = shown.iter().map(|entry| entry.wasted_space).collect();
+    info!("Wasted space: {}", sparkline(&wasted));
+    let change = if last.wasted_space <= first.wasted_space {
+        format!("down {}", Bytes(first.wasted_space - last.wasted_space))
+    } else {
+        format!("up {}", Bytes(last.wasted_space - first.wasted_space))
+    };
+    info!(
+        "Wasted space is {} over {} scans since {}, with {} duplicates now against {} then",
+        change,
+        Count(shown.len() as u64),
+        first.date,
+        Count(last.duplicates as u64),
+        Count(first.duplicates as u64)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appends_scans_and_draws_the_trend() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = history_file_for_cache(&dir.path().join("cache"));
+        let scan = |date: &str, scan_path: &str, wasted_space: u64| HistoryEntry {
+            date: date.to_string(),
+            scan_path: PathBuf::from(scan_path),
+            files: 100,
+            groups: 3,
+            duplicates: 4,
+            wasted_space,
+        };
+        let entry = |date: &str, wasted_space: u64| scan(date, "/photos", wasted_space);
+        append_entry(&file, &entry("2026-08-01T10:00:00Z", 700)).unwrap();
+        append_entry(&file, &entry("2026-09-01T10:00:00Z", 350)).unwrap();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&file)
+            .unwrap()
+            .write_all(b"{\"date\":\"2026-09-15")
+            .unwrap();
+        append_entry(&file, &scan("2026-09-20T10:00:00Z", "/music", 50)).unwrap();
+        append_entry(&file, &entry("2026-10-01T10:00:00Z", 0)).unwrap();
+        let entries = load_history(&file).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(
+            scans_of(entries.clone(), None),
+            [
+                entry("2026-08-01T10:00:00Z", 700),
+                entry("2026-09-01T10:00:00Z", 350),
+                entry("2026-10-01T10:00:00Z", 0)
+            ]
+        );
+        assert_eq!(
+            scans_of(entries, Some(Path::new("/music"))),
+            [scan("2026-09-20T10:00:00Z", "/music", 50)]
+        );
+        assert_eq!(sparkline(&[700, 350, 0]), "█▄▁");
+        assert_eq!(sparkline(&[0, 0]), "▁▁");
+    }
+}
@@ -0,0 +1,385 @@
+use std::io::Write;
+use std::path::{Component, Path, PathBuf, Prefix};
+
+use anyhow::Result;
+
+use crate::FileInfo;
+use crate::duplicates::ReportOptions;
+use crate::report::DuplicateReport;
+use crate::scan_root::shown_path;
+use crate::units::{Bytes, Count};
+
+const STYLE: &str = r#"body { font-family: system-ui, sans-serif; margin: 2em; color: #222; }
+h1 { font-size: 1.5em; }
+dl.summary { display: grid; grid-template-columns: max-content auto; gap: 0.25em 1em; }
+dl.summary dt { font-weight: bold; }
+table { border-collapse: collapse; width: 100%; margin-top: 1.5em; }
+th, td { text-align: left; padding: 0.4em 0.6em; border-bottom: 1px solid #ddd; vertical-align: top; }
+th { cursor: pointer; user-select: none; background: #f4f4f4; }
+th.asc::after { content: " \25B2"; }
+th.desc::after { content: " \25BC"; }
+td.num { text-align: right; white-space: nowrap; }
+summary { cursor: pointer; }
+ul { margin: 0.4em 0 0 0; padding-left: 1.2em; }
+.hash { font-family: monospace; color: #888; font-size: 0.85em; }"#;
+
+const SCRIPT: &str = r#"document.querySelectorAll("th[data-col]").forEach(function (th) {
+  th.addEventListener("click", function () {
+    var col = Number(th.dataset.col);
+    var asc = !th.classList.contains("asc");
+    document.querySelectorAll("th").forEach(function (h) { h.classList.remove("asc", "desc"); });
+    th.classList.add(asc ? "asc" : "desc");
+    var tbody = document.querySelector("tbody");
+    var rows = Array.from(tbody.rows);
+    rows.sort(function (a, b) {
+      var x = a.cells[col].dataset.value, y = b.cells[col].dataset.value;
+      var cmp = th.dataset.type === "num" ? Number(x) - Number(y) : x.localeCompare(y);
+      return asc ? cmp : -cmp;
+    });
+    rows.forEach(function (row) { tbody.appendChild(row); });
+  });
+});"#;
+
+/// Escapes text for use in HTML element content and quoted attribute values.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Percent-encodes a path segment for use in a relative URL.
+pub(crate) fn encode_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Returns a relative URL from `from_dir` to `target`. Both paths should be absolute.
+///
+/// Falls back to a `file://` URL if the paths share no common root (e.g. different
+/// Windows drives).
+pub fn relative_link(from_dir: &Path, target: &Path) -> String {
+    let from: Vec<Component> = from_dir.components().collect();
+    let to: Vec<Component> = target.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let segment = |c: &Component| encode_segment(&c.as_os_str().to_string_lossy());
+    if common == 0 {
+        let path: Vec<String> = to
+            .iter()
+            .filter_map(|c| match c {
+                Component::Prefix(prefix) => match prefix.kind() {
+                    Prefix::Disk(drive) | Prefix::VerbatimDisk(drive) => {
+                        Some(format!("{}:", drive as char))
+                    }
+                    _ => None,
+                },
+                Component::Normal(_) => Some(segment(c)),
+                _ => None,
+            })
+            .collect();
+        return format!("file:///{}", path.join("/"));
+    }
+    let mut parts: Vec<String> = vec!["..".to_string(); from.len() - common];
+    parts.extend(to[common..].iter().map(segment));
+    parts.join("/")
+}
+
+/// Returns the directory a report written to `file` is saved in, for [`write_html`]: its
+/// parent, or the current directory for a bare file name.
+pub fn output_dir(file: &Path) -> &Path {
+    file.parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+/// Writes a self-contained HTML report to `writer`: summary statistics and a sortable
+/// table of duplicate groups, each with a collapsible list of its members.
+///
+/// Links to files are made relative to `output_dir`, the directory the report will be
+/// saved in, so they keep working when the report is opened from there. The `top`,
+/// `min_group_waste` and `min_copies` filters in `options` are applied to the table.
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_html(
+    report: &DuplicateReport,
+    options: &ReportOptions,
+    output_dir: &Path,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let absolute = |path: &Path| -> PathBuf {
+        path.canonicalize()
+            .or_else(|_| std::path::absolute(path))
+            .unwrap_or_else(|_| path.to_path_buf())
+    };
+    let output_dir = absolute(output_dir);
+    let scan_root = absolute(&report.scan_path);
+    let groups: Vec<_> = report
+        .groups
+        .iter()
+        .filter(|g| g.copies() >= options.min_copies && g.wasted_space() >= options.min_group_waste)
+        .take(options.top.unwrap_or(usize::MAX))
+        .collect();
+    let title = format!("Duplicate files in {}", report.scan_path.display());
+
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(
+        writer,
+        "<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">"
+    )?;
+    writeln!(writer, "<title>{}</title>", escape(&title))?;
+    writeln!(writer, "<style>\n{}\n</style>\n</head>\n<body>", STYLE)?;
+    writeln!(writer, "<h1>{}</h1>", escape(&title))?;
+    writeln!(writer, "<dl class=\"summary\">")?;
+    for (label, value) in [
+        ("Generated", report.generated_at.clone()),
+        (
+            "Duplicate groups",
+            Count(report.groups.len() as u64).to_string(),
+        ),
+        (
+            "Duplicate files",
+            Count(report.total_duplicates as u64).to_string(),
+        ),
+        ("Wasted space", Bytes(report.total_wasted_space).to_string()),
+    ] {
+        writeln!(writer, "<dt>{}</dt><dd>{}</dd>", label, escape(&value))?;
+    }
+    if groups.len() < report.groups.len() {
+        writeln!(
+            writer,
+            "<dt>Shown</dt><dd>{} of {} groups</dd>",
+            Count(groups.len() as u64),
+            Count(report.groups.len() as u64)
+        )?;
+    }
+    writeln!(writer, "</dl>")?;
+
+    writeln!(writer, "<table>\n<thead><tr>")?;
+    writeln!(
+        writer,
+        "<th data-col=\"0\" data-type=\"num\">File size</th>"
+    )?;
+    writeln!(writer, "<th data-col=\"1\" data-type=\"num\">Copies</th>")?;
+    writeln!(
+        writer,
+        "<th data-col=\"2\" data-type=\"num\" class=\"desc\">Wasted</th>"
+    )?;
+    writeln!(writer, "<th data-col=\"3\" data-type=\"text\">Files</th>")?;
+    writeln!(writer, "</tr></thead>\n<tbody>")?;
+    for group in groups {
+        let mut paths: Vec<&PathBuf> = group.paths.iter().collect();
+        paths.sort();
+        let display = |path: &Path| {
+            shown_path(&absolute(path), &scan_root)
+                .display()
+                .to_string()
+        };
+        let first = paths.first().map(|p| display(p)).unwrap_or_default();
+        writeln!(writer, "<tr>")?;
+        writeln!(
+            writer,
+            "<td class=\"num\" data-value=\"{}\">{}</td>",
+            group.size,
+            Bytes(group.size)
+        )?;
+        writeln!(
+            writer,
+            "<td class=\"num\" data-value=\"{0}\">{0}</td>",
+            group.copies()
+        )?;
+        writeln!(
+            writer,
+            "<td class=\"num\" data-value=\"{}\">{}</td>",
+            group.wasted_space(),
+            Bytes(group.wasted_space())
+        )?;
+        writeln!(writer, "<td data-value=\"{}\"><details>", escape(&first))?;
+        writeln!(
+            writer,
+            "<summary>{} <span class=\"hash\">({} files, {})</span></summary>\n<ul>",
+            escape(&first),
+            paths.len(),
+            escape(group.group_id())
+        )?;
+        for path in paths {
+            writeln!(
+                writer,
+                "<li><a href=\"{}\">{}</a></li>",
+                escape(&relative_link(&output_dir, &absolute(path))),
+                escape(&display(path))
+            )?;
+        }
+        writeln!(writer, "</ul>\n</details></td>\n</tr>")?;
+    }
+    writeln!(writer, "</tbody>\n</table>")?;
+    if !report.errors.is_empty() {
+        writeln!(
+            writer,
+            "<h2>Scan errors</h2>\n<p>These paths could not be scanned, so they were not checked for duplicates.</p>"
+        )?;
+        for (kind, errors) in report.errors.by_kind() {
+            if errors.is_empty() {
+                continue;
+            }
+            writeln!(
+                writer,
+                "<h3>{} ({})</h3>\n<ul>",
+                kind.label(),
+                Count(errors.len() as u64)
+            )?;
+            for error in errors {
+                let path = absolute(&error.path);
+                writeln!(
+                    writer,
+                    "<li>{} <span class=\"hash\">({})</span></li>",
+                    escape(&shown_path(&path, &scan_root).display().to_string()),
+                    escape(&error.error)
+                )?;
+            }
+            writeln!(writer, "</ul>")?;
+        }
+    }
+    writeln!(writer, "<script>\n{}\n</script>\n</body>\n</html>", SCRIPT)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes a self-contained HTML report of files without duplicates (see
+/// [`crate::unique`]) to `writer`: their count and total size, and a sortable table of
+/// the files. Links are made relative to `output_dir`, as in [`write_html`].
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_unique_html(
+    unique: &[FileInfo],
+    scan_path: &Path,
+    output_dir: &Path,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let absolute = |path: &Path| -> PathBuf {
+        path.canonicalize()
+            .or_else(|_| std::path::absolute(path))
+            .unwrap_or_else(|_| path.to_path_buf())
+    };
+    let output_dir = absolute(output_dir);
+    let scan_root = absolute(scan_path);
+    let title = format!("Files without copies in {}", scan_path.display());
+
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(
+        writer,
+        "<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">"
+    )?;
+    writeln!(writer, "<title>{}</title>", escape(&title))?;
+    writeln!(writer, "<style>\n{}\n</style>\n</head>\n<body>", STYLE)?;
+    writeln!(writer, "<h1>{}</h1>", escape(&title))?;
+    writeln!(writer, "<dl class=\"summary\">")?;
+    for (label, value) in [
+        ("Files", Count(unique.len() as u64).to_string()),
+        (
+            "Total size",
+            Bytes(unique.iter().map(|file| file.size).sum()).to_string(),
+        ),
+    ] {
+        writeln!(writer, "<dt>{}</dt><dd>{}</dd>", label, escape(&value))?;
+    }
+    writeln!(writer, "</dl>")?;
+
+    writeln!(writer, "<table>\n<thead><tr>")?;
+    writeln!(
+        writer,
+        "<th data-col=\"0\" data-type=\"num\">File size</th>"
+    )?;
+    writeln!(writer, "<th data-col=\"1\" data-type=\"text\">File</th>")?;
+    writeln!(writer, "</tr></thead>\n<tbody>")?;
+    for file in unique {
+        let path = absolute(&file.path.to_path_buf());
+        let display = shown_path(&path, &scan_root).display().to_string();
+        writeln!(writer, "<tr>")?;
+        writeln!(
+            writer,
+            "<td class=\"num\" data-value=\"{}\">{}</td>",
+            file.size,
+            Bytes(file.size)
+        )?;
+        writeln!(
+            writer,
+            "<td data-value=\"{0}\"><a href=\"{1}\">{0}</a></td>\n</tr>",
+            escape(&display),
+            escape(&relative_link(&output_dir, &path))
+        )?;
+    }
+    writeln!(writer, "</tbody>\n</table>")?;
+    writeln!(writer, "<script>\n{}\n</script>\n</body>\n</html>", SCRIPT)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn links_from_a_report_named_without_a_directory_are_relative() {
+        let scan_path = std::env::current_dir().unwrap().join("s");
+        let duplicates = std::collections::HashMap::from([(
+            crate::content_hash::ContentHash::of(b"same"),
+            vec![
+                FileInfo::with_contents(scan_path.join("1"), b"same"),
+                FileInfo::with_contents(scan_path.join("2"), b"same"),
+            ],
+        )]);
+        let options = ReportOptions::default();
+        let report = DuplicateReport::new(&duplicates, &scan_path, &options);
+        let output_dir = output_dir(Path::new("report.html"));
+        assert_eq!(output_dir, Path::new("."));
+        let mut html = Vec::new();
+        write_html(&report, &options, output_dir, &mut html).unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.contains("href=\"s/1\""), "{}", html);
+        assert!(!html.contains("file://"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_are_relative_and_encoded() {
+        assert_eq!(
+            relative_link(
+                Path::new("/home/me/reports"),
+                Path::new("/home/me/Photos/a b#1.jpg")
+            ),
+            "../Photos/a%20b%231.jpg"
+        );
+        assert_eq!(
+            relative_link(Path::new("/home/me"), Path::new("/home/me/x.jpg")),
+            "x.jpg"
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! `import`: copying files into a directory, such as photos off a camera card into a
+//! library, skipping those whose contents the directory already has. The directory is
+//! scanned with the hash cache, so only files new since the last scan are hashed, and
+//! files repeated within the source are copied once. Files are not copied over files at
+//! the same path with other contents.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use walkdir::WalkDir;
+
+use crate::actions::{ActionPermission, move_file};
+use crate::content_hash::ContentHash;
+use crate::merge_dirs::Conflict;
+use crate::scanner::hash_file;
+use crate::units::{Bytes, Count};
+
+/// A file of the source not copied, since its contents are already at `copy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Skipped {
+    pub path: PathBuf,
+    /// The file with the same contents, in the destination or earlier in the source.
+    pub copy: PathBuf,
+    pub size: u64,
+}
+
+/// What importing a directory would do, from [`plan_import`].
+#[derive(Debug, Default)]
+pub struct ImportPlan {
+    /// The directory imported from, canonicalized.
+    pub src: PathBuf,
+    /// The directory imported into, canonicalized.
+    pub dest: PathBuf,
+    /// Files to copy, each with where to and its size.
+    pub copies: Vec<(PathBuf, PathBuf, u64)>,
+    /// Files with contents the destination already has.
+    pub skipped: Vec<Skipped>,
+    /// Files with another file at their path in the destination, left out.
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Works out how to import the files of `src` into `dest`, given the files `dest`
+/// already has by hash, hashing the files of `src` without changing anything. Empty
+/// files are imported like any other, since they are not copies of each other.
+///
+/// # Errors
+///
+/// Returns an error if either directory can't be read, or if one is inside the other.
+pub fn plan_import(
+    src: &Path,
+    dest: &Path,
+    existing: &HashMap<ContentHash, PathBuf>,
+) -> Result<ImportPlan> {
+    let canonical = |dir: &Path| {
+        dir.canonicalize()
+            .with_context(|| format!("Failed to read directory {}", dir.display()))
+    };
+    let (src, dest) = (canonical(src)?, canonical(dest)?);
+    if src.starts_with(&dest) || dest.starts_with(&src) {
+        anyhow::bail!(
+            "Can't import {} into {}, since one contains the other",
+            src.display(),
+            dest.display()
+        );
+    }
+
+    let mut plan = ImportPlan {
+        src: src.clone(),
+        dest: dest.clone(),
+        ..ImportPlan::default()
+    };
+    let mut imported: HashMap<ContentHash, PathBuf> = HashMap::new();
+    for entry in WalkDir::new(&src).sort_by_file_name() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("Failed to read '{}': {}", src.display(), e);
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            if !entry.file_type().is_dir() {
+                debug!("Not importing '{}'", entry.path().display());
+            }
+            continue;
+        }
+        let path = entry.path().to_path_buf();
+        let size = entry.metadata().map_or(0, |metadata| metadata.len());
+        let hash = match hash_file(&path) {
+            Ok(hash) => hash,
+            Err(e) => {
+                warn!("Not importing '{}': {:#}", path.display(), e);
+                continue;
+            }
+        };
+        let copy = (size > 0)
+            .then(|| existing.get(&hash).or_else(|| imported.get(&hash)))
+            .flatten();
+        if let Some(copy) = copy {
+            plan.skipped.push(Skipped {
+                path,
+                copy: copy.clone(),
+                size,
+            });
+            continue;
+        }
+        let target = dest.join(path.strip_prefix(&src)?);
+        if fs::symlink_metadata(&target).is_ok() {
+            plan.conflicts.push(Conflict {
+                from: path,
+                into: target,
+            });
+            continue;
+        }
+        imported.insert(hash, path.clone());
+        plan.copies.push((path, target, size));
+    }
+    Ok(plan)
+}
+
+impl ImportPlan {
+    /// Logs what the import would do, each skipped duplicate and each conflict.
+    pub fn print(&self) {
+        for skipped in &self.skipped {
+            info!(
+                "Skipping '{}', already at '{}'",
+                skipped.path.display(),
+                skipped.copy.display()
+            );
+        }
+        for conflict in &self.conflicts {
+            warn!(
+                "Conflict: '{}' is taken by another file, not importing '{}'",
+                conflict.into.display(),
+                conflict.from.display()
+            );
+        }
+        info!(
+            "Plan: import {} into {}: copy {} files ({}), skip {} duplicates ({})",
+            self.src.display(),
+            self.dest.display(),
+            Count(self.copies.len() as u64),
+            Bytes(self.copies.iter().map(|(_, _, size)| size).sum()),
+            Count(self.skipped.len() as u64),
+            Bytes(self.skipped.iter().map(|skipped| skipped.size).sum())
+        );
+        if !self.conflicts.is_empty() {
+            warn!(
+                "{} files would replace other files at the same path, and are left out",
+                Count(self.conflicts.len() as u64)
+            );
+        }
+    }
+}
+
+/// Copies the files of `plan`, or moves them with `move_files`, returning how many were
+/// imported. Each copy is written under a temporary name and renamed into place, so an
+/// interrupted import leaves no partial files behind. Files are not copied over files
+/// that appeared since the plan was made.
+///
+/// # Errors
+///
+/// Returns an error if no file could be imported. Failures of individual files are
+/// logged and skipped.
+pub fn apply_import(
+    plan: &ImportPlan,
+    move_files: bool,
+    _permission: &ActionPermission,
+) -> Result<usize> {
+    let mut imported = 0;
+    for (from, to, _) in &plan.copies {
+        let result = if to.exists() {
+            Err(anyhow::anyhow!("it appeared since the plan was made"))
+        } else if move_files {
+            move_file(from, to).map_err(anyhow::Error::from)
+        } else {
+            copy_file(from, to)
+        };
+        match result {
+            Ok(()) => imported += 1,
+            Err(e) => warn!(
+                "Failed to import '{}' to '{}': {:#}",
+                from.display(),
+                to.display(),
+                e
+            ),
+        }
+    }
+    if imported == 0 && !plan.copies.is_empty() {
+        anyhow::bail!("Failed to import any of {} files", plan.copies.len());
+    }
+    info!(
+        "{} {} files into {}",
+        if move_files { "Moved" } else { "Copied" },
+        Count(imported as u64),
+        plan.dest.display()
+    );
+    Ok(imported)
+}
+
+/// Copies `from` to `to` through a temporary file next to `to`, creating its directory.
+fn copy_file(from: &Path, to: &Path) -> Result<()> {
+    let parent = to.parent().context("no parent directory")?;
+    fs::create_dir_all(parent)?;
+    let mut tmp = to.as_os_str().to_owned();
+    tmp.push(format!(".{}-import", env!("CARGO_PKG_NAME")));
+    let tmp = PathBuf::from(tmp);
+    fs::copy(from, &tmp)
+        .and_then(|_| fs::rename(&tmp, to))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_contents_the_destination_has() {
+        let dir = tempfile::tempdir().unwrap();
+        let (card, library) = (dir.path().join("card"), dir.path().join("library"));
+        for (path, content) in [
+            ("library/2024/a.jpg", "a"),
+            ("library/b.jpg", "taken"),
+            ("card/DCIM/a.jpg", "a"),
+            ("card/DCIM/b.jpg", "new b"),
+            ("card/DCIM/c.jpg", "c"),
+            ("card/DCIM/c-copy.jpg", "c"),
+            ("card/b.jpg", "b"),
+        ] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let library_a = library.join("2024/a.jpg").canonicalize().unwrap();
+        let existing = HashMap::from([
+            (ContentHash::of(b"a"), library_a.clone()),
+            (ContentHash::of(b"taken"), library.join("b.jpg")),
+        ]);
+
+        let plan = plan_import(&card, &library, &existing).unwrap();
+        let skipped: Vec<_> = plan.skipped.iter().map(|s| s.copy.clone()).collect();
+        assert_eq!(skipped, [library_a, plan.src.join("DCIM/c-copy.jpg")]);
+        assert_eq!(plan.conflicts.len(), 1);
+        assert_eq!(plan.conflicts[0].from, plan.src.join("b.jpg"));
+
+        let permission = ActionPermission::allow_changes();
+        assert_eq!(apply_import(&plan, false, &permission).unwrap(), 2);
+        assert_eq!(fs::read(library.join("DCIM/b.jpg")).unwrap(), b"new b");
+        assert_eq!(fs::read(library.join("DCIM/c-copy.jpg")).unwrap(), b"c");
+        assert!(!library.join("DCIM/c.jpg").exists());
+        assert!(card.join("DCIM/b.jpg").exists());
+        assert_eq!(fs::read(library.join("b.jpg")).unwrap(), b"taken");
+    }
+}
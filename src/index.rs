@@ -0,0 +1,166 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::cache::HashCache;
+use crate::scan_root::ScanRoot;
+
+/// A reverse index from content hash to the files with that content.
+///
+/// Built from the hash cache, so it covers every file hashed under the base path, not
+/// just the current scan. Entries are not checked against the filesystem; callers should
+/// confirm that a path still exists before reporting it.
+#[derive(Debug, Default, Clone)]
+pub struct HashIndex {
+    by_hash: HashMap<String, BTreeSet<PathBuf>>,
+    by_path: HashMap<PathBuf, String>,
+    /// Size of the files with each hash, where known.
+    sizes: HashMap<String, u64>,
+}
+
+impl HashIndex {
+    /// Creates an empty index.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds an index of every entry in `cache`, with cache keys resolved against `root`.
+    pub fn from_cache(cache: &HashCache, root: &ScanRoot) -> Self {
+        let mut index = Self::new();
+        for entry in cache.entries() {
+            index.set_size(&entry.hash, entry.size);
+            index.insert(root.resolve(&entry.path), entry.hash);
+        }
+        index
+    }
+
+    /// Records that `path` has content `hash`, replacing any previous hash for it.
+    pub fn insert(&mut self, path: PathBuf, hash: String) {
+        self.remove(&path);
+        self.by_hash
+            .entry(hash.clone())
+            .or_default()
+            .insert(path.clone());
+        self.by_path.insert(path, hash);
+    }
+
+    /// Removes `path` from the index, returning its hash if it was present.
+    pub fn remove(&mut self, path: &Path) -> Option<String> {
+        let hash = self.by_path.remove(path)?;
+        if let Some(paths) = self.by_hash.get_mut(&hash) {
+            paths.remove(path);
+            if paths.is_empty() {
+                self.by_hash.remove(&hash);
+                self.sizes.remove(&hash);
+            }
+        }
+        Some(hash)
+    }
+
+    /// Returns the hash recorded for `path`.
+    pub fn hash_of(&self, path: &Path) -> Option<&str> {
+        self.by_path.get(path).map(String::as_str)
+    }
+
+    /// Returns the files with content `hash`, sorted by path.
+    pub fn paths(&self, hash: &str) -> impl Iterator<Item = &Path> {
+        self.by_hash
+            .get(hash)
+            .into_iter()
+            .flatten()
+            .map(PathBuf::as_path)
+    }
+
+    /// Returns the files with content `hash` other than `path` that still exist on disk.
+    pub fn copies(&self, hash: &str, path: &Path) -> Vec<PathBuf> {
+        self.paths(hash)
+            .filter(|other| *other != path && other.is_file())
+            .map(Path::to_path_buf)
+            .collect()
+    }
+
+    /// Returns every hash shared by more than one file, with its files sorted by path.
+    pub fn duplicates(&self) -> impl Iterator<Item = (&str, Vec<&Path>)> {
+        self.by_hash
+            .iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .map(|(hash, paths)| (hash.as_str(), paths.iter().map(PathBuf::as_path).collect()))
+    }
+
+    /// Records the size of the files with content `hash`, for [`HashIndex::wasted_bytes`].
+    pub fn set_size(&mut self, hash: &str, size: u64) {
+        self.sizes.insert(hash.to_string(), size);
+    }
+
+    /// Returns the space taken by all but one file of each duplicate group, leaving out
+    /// groups whose size isn't known.
+    pub fn wasted_bytes(&self) -> u64 {
+        self.by_hash
+            .iter()
+            .filter_map(|(hash, paths)| Some(self.sizes.get(hash)? * (paths.len() as u64 - 1)))
+            .sum()
+    }
+
+    /// Returns the number of distinct hashes in the index.
+    pub fn hash_count(&self) -> usize {
+        self.by_hash.len()
+    }
+
+    /// Returns the number of files in the index.
+    pub fn len(&self) -> usize {
+        self.by_path.len()
+    }
+
+    /// Returns `true` if the index has no files.
+    pub fn is_empty(&self) -> bool {
+        self.by_path.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_replaces_previous_hash() {
+        let mut index = HashIndex::new();
+        index.insert(PathBuf::from("/d/a"), "h1".into());
+        index.insert(PathBuf::from("/d/b"), "h1".into());
+        index.insert(PathBuf::from("/d/a"), "h2".into());
+
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.hash_of(Path::new("/d/a")), Some("h2"));
+        assert_eq!(index.hash_count(), 2);
+        assert_eq!(index.duplicates().count(), 0);
+        assert_eq!(index.paths("h1").collect::<Vec<_>>(), [Path::new("/d/b")]);
+        assert_eq!(index.remove(Path::new("/d/b")), Some("h1".into()));
+        assert_eq!(index.paths("h1").count(), 0);
+        assert_eq!(index.remove(Path::new("/d/b")), None);
+
+        index.insert(PathBuf::from("/d/c"), "h2".into());
+        index.insert(PathBuf::from("/d/d"), "h2".into());
+        assert_eq!(index.wasted_bytes(), 0);
+        index.set_size("h2", 100);
+        assert_eq!(index.wasted_bytes(), 200);
+    }
+
+    #[test]
+    fn builds_from_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a"), b"x").unwrap();
+        std::fs::write(dir.path().join("b"), b"x").unwrap();
+        let root = ScanRoot::for_base(dir.path());
+        let cache = HashCache::load(dir.path().join("cache.json.zst"));
+        for name in ["a", "b"] {
+            let path = root.base().join(name);
+            cache.set_hash(&path, &root, "h".into()).unwrap();
+        }
+
+        let index = HashIndex::from_cache(&cache, &root);
+        assert_eq!(
+            index.paths("h").collect::<Vec<_>>(),
+            [root.base().join("a"), root.base().join("b")]
+        );
+        std::fs::remove_file(dir.path().join("b")).unwrap();
+        assert!(index.copies("h", &root.base().join("a")).is_empty());
+    }
+}
@@ -0,0 +1,418 @@
+//! Inventories of every scanned file with its size, modification time and hash, written
+//! with `--export-inventory` as a content manifest, e.g. to verify backups.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+use crate::FileInfo;
+use crate::scan_root::ScanRoot;
+use crate::units::Count;
+
+/// Formats of inventory files, chosen by the file's extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InventoryFormat {
+    /// Comma-separated values with a `path,size,mtime,hash` header row.
+    Csv,
+    /// One JSON object per line.
+    Ndjson,
+}
+
+impl InventoryFormat {
+    /// Returns NDJSON for `.ndjson`, `.jsonl` and `.json` files, and CSV otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        match path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase)
+            .as_deref()
+        {
+            Some("ndjson" | "jsonl" | "json") => Self::Ndjson,
+            _ => Self::Csv,
+        }
+    }
+}
+
+/// A file in an inventory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InventoryEntry {
+    /// Path relative to the scanned directory, so the inventory can be checked against a
+    /// copy of the tree elsewhere.
+    #[serde(with = "crate::paths::serde_path")]
+    pub path: PathBuf,
+    /// Size in bytes.
+    pub size: u64,
+    /// RFC 3339 modification time, if known.
+    pub mtime: Option<String>,
+    /// BLAKE3 hash of the contents.
+    pub hash: String,
+}
+
+impl InventoryEntry {
+    /// Describes `file`, with its path relative to `root`'s scanned directory.
+    pub fn new(file: &FileInfo, root: &ScanRoot) -> Self {
+        Self {
+            path: root.relative_file(&file.path),
+            size: file.size,
+            mtime: file.modified.map(format_mtime),
+            hash: file.hash.to_string(),
+        }
+    }
+}
+
+/// Formats a modification time as RFC 3339 in UTC.
+fn format_mtime(time: SystemTime) -> String {
+    OffsetDateTime::from(time)
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Writes `entries` to `writer` in `format`.
+pub fn write_inventory(
+    entries: &[InventoryEntry],
+    format: InventoryFormat,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    match format {
+        InventoryFormat::Csv => {
+            writeln!(writer, "path,size,mtime,hash")?;
+            for entry in entries {
+                writeln!(
+                    writer,
+                    "{},{},{},{}",
+                    csv_field(&entry.path.to_string_lossy()),
+                    entry.size,
+                    entry.mtime.as_deref().unwrap_or(""),
+                    entry.hash
+                )?;
+            }
+        }
+        InventoryFormat::Ndjson => {
+            for entry in entries {
+                serde_json::to_writer(&mut *writer, entry)?;
+                writeln!(writer)?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes an inventory of `files` to `path`, sorted by path, in the format given by its
+/// extension. Files inside archives are left out. Returns the number of files written.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be written.
+pub fn export_inventory<'a>(
+    files: impl IntoIterator<Item = &'a FileInfo>,
+    root: &ScanRoot,
+    path: &Path,
+) -> Result<usize> {
+    let mut entries: Vec<InventoryEntry> = files
+        .into_iter()
+        .filter(|file| !file.in_archive)
+        .map(|file| InventoryEntry::new(file, root))
+        .collect();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let file = fs::File::create(path)
+        .with_context(|| format!("Failed to create inventory {}", path.display()))?;
+    write_inventory(
+        &entries,
+        InventoryFormat::from_path(path),
+        &mut BufWriter::new(file),
+    )?;
+    Ok(entries.len())
+}
+
+/// Splits CSV `text` into records of fields, undoing quoting.
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut field)),
+            (false, '\r') => {}
+            (false, '\n') => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if quoted {
+        anyhow::bail!("Unterminated quoted field");
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Reads an inventory written by [`export_inventory`], in the format given by the
+/// extension of `path`.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or is not a valid inventory.
+pub fn read_inventory(path: &Path) -> Result<Vec<InventoryEntry>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read inventory {}", path.display()))?;
+    let invalid = || format!("Invalid inventory {}", path.display());
+    match InventoryFormat::from_path(path) {
+        InventoryFormat::Ndjson => text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).with_context(invalid))
+            .collect(),
+        InventoryFormat::Csv => {
+            let records = parse_csv(&text).with_context(invalid)?;
+            let Some((header, rows)) = records.split_first() else {
+                return Ok(Vec::new());
+            };
+            if header != &["path", "size", "mtime", "hash"] {
+                anyhow::bail!("{}: expected a path,size,mtime,hash header", invalid());
+            }
+            rows.iter()
+                .enumerate()
+                .map(|(i, row)| match row.as_slice() {
+                    [path, size, mtime, hash] => Ok(InventoryEntry {
+                        path: path.into(),
+                        size: size
+                            .parse()
+                            .with_context(|| format!("{}: row {}", invalid(), i + 2))?,
+                        mtime: (!mtime.is_empty()).then(|| mtime.clone()),
+                        hash: hash.clone(),
+                    }),
+                    _ => anyhow::bail!("{}: row {} does not have 4 fields", invalid(), i + 2),
+                })
+                .collect()
+        }
+    }
+}
+
+/// Differences between an inventory and the files found now.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Verification {
+    /// Number of files whose contents match the inventory.
+    pub verified: usize,
+    /// Files whose contents and modification time changed, as by an edit.
+    pub modified: Vec<PathBuf>,
+    /// Files whose contents changed while their size and modification time didn't, the
+    /// signature of silent corruption.
+    pub corrupted: Vec<PathBuf>,
+    /// Files in the inventory that no longer exist, or could not be read.
+    pub missing: Vec<PathBuf>,
+    /// Files that are not in the inventory.
+    pub added: Vec<PathBuf>,
+}
+
+impl Verification {
+    /// Returns the number of inventoried files that are modified, corrupted or missing.
+    pub fn problems(&self) -> usize {
+        self.modified.len() + self.corrupted.len() + self.missing.len()
+    }
+}
+
+/// Compares `entries` with `files`, just hashed under `root`'s scanned directory. Paths
+/// in the result are relative to it and sorted.
+pub fn verify<'a>(
+    entries: &[InventoryEntry],
+    files: impl IntoIterator<Item = &'a FileInfo>,
+    root: &ScanRoot,
+) -> Verification {
+    let mut current: HashMap<PathBuf, InventoryEntry> = files
+        .into_iter()
+        .filter(|file| !file.in_archive)
+        .map(|file| {
+            let entry = InventoryEntry::new(file, root);
+            (entry.path.clone(), entry)
+        })
+        .collect();
+    let mut result = Verification::default();
+    for entry in entries {
+        match current.remove(&entry.path) {
+            None => result.missing.push(entry.path.clone()),
+            Some(now) if now.hash == entry.hash => result.verified += 1,
+            Some(now) if now.size == entry.size && now.mtime == entry.mtime => {
+                result.corrupted.push(entry.path.clone())
+            }
+            Some(_) => result.modified.push(entry.path.clone()),
+        }
+    }
+    result.added = current.into_keys().collect();
+    for paths in [
+        &mut result.modified,
+        &mut result.corrupted,
+        &mut result.missing,
+        &mut result.added,
+    ] {
+        paths.sort();
+    }
+    result
+}
+
+/// Prints each difference found by [`verify`] and a summary.
+pub fn print_verification(result: &Verification) {
+    for (label, paths) in [
+        ("corrupted", &result.corrupted),
+        ("modified", &result.modified),
+        ("missing", &result.missing),
+        ("new", &result.added),
+    ] {
+        for path in paths {
+            println!("{}: {}", label, path.display());
+        }
+    }
+    let summary = format!(
+        "{} files verified, {} corrupted, {} modified, {} missing, {} new",
+        Count(result.verified as u64),
+        Count(result.corrupted.len() as u64),
+        Count(result.modified.len() as u64),
+        Count(result.missing.len() as u64),
+        Count(result.added.len() as u64)
+    );
+    if result.problems() > 0 {
+        warn!("{}", summary);
+    } else {
+        info!("{}", summary);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content_hash::ContentHash;
+
+    #[test]
+    fn writes_csv_and_ndjson() {
+        let entries = [
+            InventoryEntry {
+                path: "a, \"b\".txt".into(),
+                size: 3,
+                mtime: Some("2024-05-01T12:30:00Z".to_string()),
+                hash: "ab12".to_string(),
+            },
+            InventoryEntry {
+                path: "c/d.txt".into(),
+                size: 0,
+                mtime: None,
+                hash: "cd34".to_string(),
+            },
+        ];
+        let mut csv = Vec::new();
+        write_inventory(&entries, InventoryFormat::Csv, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "path,size,mtime,hash\n\"a, \"\"b\"\".txt\",3,2024-05-01T12:30:00Z,ab12\nc/d.txt,0,,cd34\n"
+        );
+
+        let mut ndjson = Vec::new();
+        write_inventory(&entries, InventoryFormat::Ndjson, &mut ndjson).unwrap();
+        let line = String::from_utf8(ndjson)
+            .unwrap()
+            .lines()
+            .nth(1)
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            line,
+            r#"{"path":"c/d.txt","size":0,"mtime":null,"hash":"cd34"}"#
+        );
+
+        assert_eq!(
+            InventoryFormat::from_path(Path::new("x.JSONL")),
+            InventoryFormat::Ndjson
+        );
+        assert_eq!(
+            InventoryFormat::from_path(Path::new("x.csv")),
+            InventoryFormat::Csv
+        );
+    }
+
+    #[test]
+    fn reads_back_and_verifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = ScanRoot::for_base(dir.path());
+        let entry = |path: &str, size, mtime: &str, hash: &str| InventoryEntry {
+            path: path.into(),
+            size,
+            mtime: Some(mtime.to_string()),
+            hash: hash.to_string(),
+        };
+        let entries = vec![
+            entry("a, \"b\".txt", 3, "2024-05-01T12:30:00Z", "aa"),
+            entry("edited", 3, "2024-05-01T12:30:00Z", "bb"),
+            entry("rotten", 3, "2024-05-01T12:30:00Z", "cc"),
+            entry("gone", 3, "2024-05-01T12:30:00Z", "dd"),
+        ];
+        for name in ["inventory.csv", "inventory.ndjson"] {
+            let path = dir.path().join(name);
+            export_entries(&entries, &path);
+            assert_eq!(read_inventory(&path).unwrap(), entries);
+        }
+
+        let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_714_566_600);
+        let file = |name: &str, hash: &[u8], modified| FileInfo {
+            modified: Some(modified),
+            ..FileInfo::for_test(root.base().join(name), 3, ContentHash::of(hash))
+        };
+        let mut files = vec![
+            file(
+                "edited",
+                b"new",
+                modified + std::time::Duration::from_secs(60),
+            ),
+            file("rotten", b"bad", modified),
+            file("extra", b"x", modified),
+        ];
+        let mut expected = entries.clone();
+        expected[0].hash = ContentHash::of(b"a").to_string();
+        files.push(file("a, \"b\".txt", b"a", modified));
+        let result = verify(&expected, &files, &root);
+        assert_eq!(
+            result,
+            Verification {
+                verified: 1,
+                modified: vec!["edited".into()],
+                corrupted: vec!["rotten".into()],
+                missing: vec!["gone".into()],
+                added: vec!["extra".into()],
+            }
+        );
+        assert_eq!(result.problems(), 3);
+    }
+
+    fn export_entries(entries: &[InventoryEntry], path: &Path) {
+        let mut writer = fs::File::create(path).unwrap();
+        write_inventory(entries, InventoryFormat::from_path(path), &mut writer).unwrap();
+    }
+}
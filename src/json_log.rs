@@ -0,0 +1,154 @@
+//! Writing the log file as JSON lines with `--log-format json`, so scheduled scans can be
+//! fed to log pipelines and alerting without parsing text.
+
+use std::io::Write;
+use std::sync::Mutex;
+
+use clap::ValueEnum;
+use log::kv::{self, Key, Value, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, json};
+use simplelog::{Config, SharedLogger};
+use time::format_description::well_known::Rfc3339;
+use time::{OffsetDateTime, UtcOffset};
+
+/// Formats of the log file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Lines like those printed to the console
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// A logger writing each record as one line of JSON: `timestamp` (RFC 3339), `level`,
+/// `target`, `event` (the message), and any fields attached to the record, such as
+/// `info!(path:% = path.display(), bytes = size; "...")`.
+pub struct JsonLogger<W: Write + Send> {
+    level: LevelFilter,
+    writer: Mutex<W>,
+    /// Local time offset, found at startup since it can't be safely looked up once other
+    /// threads are running.
+    offset: UtcOffset,
+}
+
+impl<W: Write + Send + 'static> JsonLogger<W> {
+    pub fn new(level: LevelFilter, writer: W) -> Box<Self> {
+        Box::new(Self {
+            level,
+            writer: Mutex::new(writer),
+            offset: UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC),
+        })
+    }
+}
+
+/// Collects the fields of a record into a JSON object, keeping numbers and booleans as
+/// such.
+struct Fields<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(n) = value.to_u64() {
+            json!(n)
+        } else if let Some(n) = value.to_i64() {
+            json!(n)
+        } else if let Some(n) = value.to_f64() {
+            json!(n)
+        } else if let Some(b) = value.to_bool() {
+            json!(b)
+        } else {
+            json!(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+/// Returns the JSON object for `record`, stamped with `timestamp`.
+fn to_json(record: &Record, timestamp: OffsetDateTime) -> serde_json::Value {
+    let mut object = Map::new();
+    object.insert(
+        "timestamp".to_string(),
+        json!(timestamp.format(&Rfc3339).unwrap_or_default()),
+    );
+    object.insert("level".to_string(), json!(record.level().as_str()));
+    object.insert("target".to_string(), json!(record.target()));
+    object.insert("event".to_string(), json!(record.args().to_string()));
+    // A field can't be malformed, so visiting never fails
+    let _ = record.key_values().visit(&mut Fields(&mut object));
+    serde_json::Value::Object(object)
+}
+
+impl<W: Write + Send> Log for JsonLogger<W> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = to_json(record, OffsetDateTime::now_utc().to_offset(self.offset));
+        let mut writer = self.writer.lock().unwrap();
+        let _ = writeln!(writer, "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = self.writer.lock().unwrap().flush();
+    }
+}
+
+impl<W: Write + Send + 'static> SharedLogger for JsonLogger<W> {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+    use time::macros::datetime;
+
+    use super::*;
+
+    #[test]
+    fn writes_fields_with_their_types() {
+        let fields: [(&str, Value); 3] = [
+            ("path", Value::from("a/b.jpg")),
+            ("bytes", Value::from(4096u64)),
+            ("duration", Value::from(1.5f64)),
+        ];
+        let json = to_json(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("check_file_dups::scanner")
+                .args(format_args!("Failed to read '{}'", "a/b.jpg"))
+                .key_values(&fields)
+                .build(),
+            datetime!(2024-05-01 12:30:00 UTC),
+        );
+        assert_eq!(
+            json,
+            json!({
+                "timestamp": "2024-05-01T12:30:00Z",
+                "level": "WARN",
+                "target": "check_file_dups::scanner",
+                "event": "Failed to read 'a/b.jpg'",
+                "path": "a/b.jpg",
+                "bytes": 4096,
+                "duration": 1.5,
+            })
+        );
+    }
+}
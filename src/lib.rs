@@ -1,17 +1,179 @@
-use std::path::PathBuf;
+use std::time::SystemTime;
 
+pub mod acknowledged;
+pub mod actions;
+pub mod archives;
+pub mod attributes;
+#[cfg(feature = "audio")]
+pub mod audio;
+pub mod bench;
+pub mod binary;
 pub mod cache;
+pub mod checksums;
+pub mod chunks;
 pub mod cli;
+pub mod compat;
+pub mod completions;
+pub mod config;
+pub mod content_hash;
+pub mod corpus;
+pub mod daemon;
+pub mod decompress;
+pub mod dir_index;
+pub mod disk_image;
+pub mod doctor;
 pub mod duplicates;
+pub mod error;
+pub mod estimate;
+pub mod exec;
+pub mod file_list;
+pub mod file_type;
+pub mod hash_store;
+pub mod history;
+pub mod html;
+pub mod import;
+pub mod index;
+pub mod inventory;
+pub mod json_log;
+pub mod manifest;
+pub mod merge_dirs;
+pub mod metadata;
+pub mod metrics;
+pub mod names;
+pub mod notify;
+pub mod originals;
+pub mod pager;
+pub mod paths;
+#[cfg(feature = "perceptual")]
+pub mod perceptual;
+pub mod plan;
+pub mod potential;
+pub mod progress;
+pub mod reflink;
+pub mod report;
+pub mod resume;
+pub mod reveal;
+pub mod scan_errors;
+pub mod scan_root;
 pub mod scanner;
+pub mod script;
+pub mod selection;
+pub mod shutdown;
+pub mod sizes;
+pub mod snapshot;
+pub mod spill;
+pub mod stats;
+pub mod store;
+pub mod streams;
+pub mod suggest;
+pub mod testdata;
+pub mod throttle;
+pub mod tombstones;
+pub mod top_dirs;
+pub mod trash;
+pub mod unique;
+pub mod units;
+pub mod utils;
+pub mod watch;
 
-pub use cache::HashCache;
-pub use cli::Cli;
-pub use duplicates::{find_duplicates, print_results};
-pub use scanner::{calculate_file_hash, scan_directory_with_cache};
+pub use cache::{
+    BINARY_CACHE_MAGIC, CACHE_FORMAT_VERSION, CacheEntry, CacheFileInfo, CacheFormat, CacheLimits,
+    CacheStats, CheckpointPolicy, HashCache, PruneStats,
+};
+pub use cli::{CacheCommand, Cli, Command, ConfigCommand, IndexCommand, ScanArgs};
+pub use content_hash::{ContentHash, GroupId};
+pub use duplicates::{
+    HashGroups, OutputFormat, ReportOptions, SortBy, distinct_copies, find_duplicates,
+    group_by_hash, print_results, retain_between, retain_groups, retain_siblings, retain_within,
+    wasted_space,
+};
+pub use error::CheckFileDupsError;
+pub use hash_store::{HashStore, MemoryStore};
+pub use index::HashIndex;
+pub use paths::{FilePath, PathInterner};
+pub use progress::{HashStats, print_hash_stats};
+pub use report::{
+    DuplicateGroup, DuplicateReport, GroupStatistics, ReportDiff, print_breakdown, print_diff,
+    print_report, write_brief, write_ndjson,
+};
+pub use scan_errors::{ScanError, ScanErrorKind, ScanErrors, print_scan_errors};
+pub use scan_root::ScanRoot;
+#[allow(deprecated)]
+pub use scanner::scan_directory_with_cache;
+pub use scanner::{
+    JUNK_NAMES, ModifiedRange, NameFilter, RetryPolicy, ScanLimit, ScanOptions, Threads,
+    allocated_size, calculate_file_hash, file_id, hash_file, hash_file_parallel, is_skipped,
+    scan_directory, set_read_options,
+};
 
+/// A scanned file. Scans can hold millions of these, so the path shares its directory
+/// with the other files there and the hash is stored as bytes.
+#[derive(Debug, Clone)]
 pub struct FileInfo {
-    pub path: PathBuf,
+    pub path: FilePath,
     pub size: u64,
-    pub hash: String,
+    pub hash: ContentHash,
+    /// (device, inode) identifying the underlying file on Unix, used to recognize hardlinks.
+    /// `None` on platforms without stable inode numbers.
+    pub file_id: Option<(u64, u64)>,
+    /// Number of hardlinks to the underlying file, including any outside the scan.
+    /// `None` on platforms where it isn't available.
+    pub links: Option<u64>,
+    /// Space actually allocated on disk for the file, which can be smaller than `size` for
+    /// sparse or compressed files and larger for small files. `None` if unavailable.
+    pub allocated_size: Option<u64>,
+    /// Modification time when the file was scanned, to notice files changed before they
+    /// are acted on. `None` if unavailable, and for files inside archives.
+    pub modified: Option<SystemTime>,
+    /// `true` for a file inside an archive, whose path has the form `archive!member`.
+    /// Such files are reported but never moved, deleted or hardlinked.
+    pub in_archive: bool,
+    /// `true` for a gzip or Zstandard file hashed by its decompressed contents, with
+    /// `--decompress-before-hash`. Such files are reported as copies of the uncompressed
+    /// files but never moved, deleted or hardlinked.
+    pub compressed: bool,
+    /// User ID of the owner of the file on Unix, shown beside its modification time when
+    /// choosing a copy to keep. `None` elsewhere, and for files inside archives.
+    pub owner: Option<u32>,
+}
+
+impl FileInfo {
+    /// Returns the ID of the device (filesystem) holding the file, if known. Files can
+    /// only be hardlinked to files on the same device.
+    pub fn device(&self) -> Option<u64> {
+        self.file_id.map(|(device, _)| device)
+    }
+
+    /// Returns `true` if the file is on disk as it was hashed, so actions can be applied
+    /// to it: it is neither inside an archive nor compressed.
+    pub fn is_actionable(&self) -> bool {
+        !self.in_archive && !self.compressed
+    }
+
+    /// Returns a file at `path` with `size` and `hash`, and nothing else known about it.
+    #[cfg(test)]
+    pub(crate) fn for_test(
+        path: impl AsRef<std::path::Path>,
+        size: u64,
+        hash: ContentHash,
+    ) -> Self {
+        Self {
+            path: path.as_ref().into(),
+            size,
+            hash,
+            file_id: None,
+            links: None,
+            allocated_size: None,
+            modified: None,
+            in_archive: false,
+            compressed: false,
+            owner: None,
+        }
+    }
+
+    /// Returns a file at `path` with `contents`, as [`FileInfo::for_test`] does.
+    #[cfg(test)]
+    pub(crate) fn with_contents(path: impl AsRef<std::path::Path>, contents: &[u8]) -> Self {
+        Self::for_test(path, contents.len() as u64, ContentHash::of(contents))
+    }
 }
@@ -1,34 +1,322 @@
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Context, Result};
+use clap::{ArgMatches, CommandFactory};
+use colored::Colorize;
+use globset::GlobMatcher;
 use indicatif::HumanDuration;
-use log::{error, info};
-use serde::{Deserialize, Serialize};
+use log::{error, info, warn};
 use simplelog::{
-    ColorChoice, CombinedLogger, ConfigBuilder, LevelFilter, TermLogger, TerminalMode, WriteLogger,
+    ColorChoice, CombinedLogger, ConfigBuilder, LevelFilter, SharedLogger, TermLogger,
+    TerminalMode, WriteLogger,
 };
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use time::macros::format_description;
 
-use check_file_dups::{Cli, HashCache, find_duplicates, print_results, scan_directory_with_cache};
+use check_file_dups::acknowledged::{AcknowledgeTarget, Acknowledged};
+use check_file_dups::actions::{
+    Action, ActionOptions, ActionPermission, Journal, apply_planned, default_journal_dir,
+    journal_file_name, plan_operations, undo,
+};
+use check_file_dups::archives::scan_archives;
+#[cfg(feature = "audio")]
+use check_file_dups::audio::{find_same_recordings, print_same_recordings};
+use check_file_dups::bench::{print_bench, run_bench};
+use check_file_dups::checksums::{export_cache, import_manifest};
+use check_file_dups::chunks::{find_partial_duplicates, print_partial_duplicates};
+use check_file_dups::compat::{
+    write_checksums, write_fdupes, write_print0, write_rmlint, write_rmlint_unique,
+};
+use check_file_dups::completions::{write_completions, write_man_page};
+use check_file_dups::config::Config;
+use check_file_dups::corpus::CorpusIndex;
+use check_file_dups::daemon;
+use check_file_dups::dir_index::DirIndex;
+use check_file_dups::disk_image::MountedImage;
+use check_file_dups::doctor::{
+    check_base_path, check_cache, check_config, check_disk_space, check_hashing, check_writable,
+    print_diagnoses,
+};
+use check_file_dups::duplicates::retain_matching;
+use check_file_dups::estimate::{estimate, print_estimate};
+use check_file_dups::exec::run_for_groups;
+use check_file_dups::file_list::read_file_list;
+use check_file_dups::history::{
+    HistoryEntry, history_file_for_cache, load_history, print_history, record_scan, scans_of,
+};
+use check_file_dups::html::{output_dir, write_html, write_unique_html};
+use check_file_dups::import::{apply_import, plan_import};
+use check_file_dups::inventory::{export_inventory, print_verification, read_inventory, verify};
+use check_file_dups::json_log::{JsonLogger, LogFormat};
+use check_file_dups::manifest::{Manifest, apply_manifest};
+use check_file_dups::merge_dirs::{apply_merge, plan_merge};
+use check_file_dups::metadata::{
+    Images, Mp3Audio, PayloadFormat, find_metadata_duplicates, print_metadata_duplicates,
+};
+use check_file_dups::metrics::WatchMetrics;
+use check_file_dups::names::{find_same_names, print_same_names};
+use check_file_dups::notify::{Notifier, ScanSummary, Smtp, Webhook, notify_all};
+use check_file_dups::originals::Originals;
+use check_file_dups::pager::Pager;
+#[cfg(feature = "perceptual")]
+use check_file_dups::perceptual::{find_similar, print_similar};
+use check_file_dups::plan::{confirm, print_plan};
+use check_file_dups::potential::{PotentialMatch, confirm_paths, find_potential, print_potential};
+use check_file_dups::progress::{ProgressFormat, set_progress_events};
+use check_file_dups::reflink::{ExtentIds, read_extent_ids};
+use check_file_dups::resume::ResumeState;
+use check_file_dups::reveal::reveal_files;
+use check_file_dups::scan_root::set_display_paths;
+use check_file_dups::script::write_script;
+use check_file_dups::selection::{KeepPolicy, path_glob};
+use check_file_dups::shutdown::{Shutdown, exit_on_interrupt};
+use check_file_dups::sizes::{print_size_summary, summarize_sizes};
+use check_file_dups::snapshot::snapshots_of;
+use check_file_dups::spill::find_duplicates_spilling;
+use check_file_dups::stats::{RunStats, print_stats};
+use check_file_dups::store::{ContentStore, archive, print_archive_stats};
+use check_file_dups::streams::read_stream_digests;
+use check_file_dups::suggest::{Suggester, print_suggestions};
+use check_file_dups::testdata::{TestTreeSpec, generate};
+use check_file_dups::throttle::{Throttle, lower_priority};
+use check_file_dups::tombstones::{Tombstones, print_reappeared, record_deletions};
+use check_file_dups::top_dirs::{GroupBy, print_by_top_dir, waste_by_top_dir};
+use check_file_dups::unique::{find_unique, print_unique, write_unique_ndjson, write_unique_paths};
+use check_file_dups::units::{Bytes, Count, NumberFormat, set_number_format};
+use check_file_dups::utils::{format_timestamp, set_progress_hidden};
+use check_file_dups::watch::watch;
+use check_file_dups::{
+    CacheCommand, Cli, Command, ConfigCommand, ContentHash, DuplicateReport, FileInfo, HashCache,
+    HashIndex, IndexCommand, OutputFormat, ReportOptions, ScanOptions, ScanRoot, find_duplicates,
+    hash_file, print_breakdown, print_diff, print_hash_stats, print_report, print_results,
+    print_scan_errors, retain_between, retain_groups, retain_siblings, retain_within,
+    scan_directory, set_read_options, write_brief, write_ndjson,
+};
+
+/// Writes generated shell integration to `name` in `dir`, or to stdout without a `dir`.
+fn write_generated(
+    dir: Option<&Path>,
+    name: &str,
+    write: impl FnOnce(&mut dyn Write) -> std::io::Result<()>,
+) -> Result<()> {
+    match dir {
+        Some(dir) => {
+            let path = dir.join(name);
+            fs::create_dir_all(dir)?;
+            let mut writer = BufWriter::new(
+                fs::File::create(&path)
+                    .with_context(|| format!("Failed to create {}", path.display()))?,
+            );
+            write(&mut writer)?;
+            writer.flush()?;
+            info!("Wrote {}", path.display());
+        }
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            write(&mut stdout)?;
+            stdout.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns `true` if the command writes machine-readable data to stdout, in which case
+/// console logging must go to stderr only.
+fn writes_data_to_stdout(cli: &Cli) -> bool {
+    cli.scan.emit_script.is_some()
+        || (cli.command.is_none()
+            && cli.scan.output_format != OutputFormat::Text
+            && cli.scan.output.is_none())
+        || matches!(
+            &cli.command,
+            Some(Command::Cache {
+                action: CacheCommand::List | CacheCommand::Export { output: None, .. },
+            }) | Some(Command::Completions { dir: None, .. } | Command::Man { dir: None })
+        )
+}
+
+/// Resolves the log file to use, creating its directory if needed. Returns `None` if
+/// file logging is disabled.
+///
+/// The default location is the platform data directory (e.g. `~/.local/share/check-file-dups` on Linux), falling
+/// back to the current directory.
+fn resolve_log_file(cli: &Cli) -> Result<Option<PathBuf>> {
+    let Some(log_file) = log_file_path(cli)? else {
+        return Ok(None);
+    };
+    if let Some(parent) = log_file.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(Some(log_file))
+}
+
+/// Returns the log file [`resolve_log_file`] uses, without creating anything.
+fn log_file_path(cli: &Cli) -> Result<Option<PathBuf>> {
+    if cli.no_log_file {
+        return Ok(None);
+    }
+    let file_name = format!("{}.log", env!("CARGO_PKG_NAME"));
+    Ok(Some(match cli.log_file.clone() {
+        Some(file) => file,
+        None => match dirs::data_local_dir() {
+            Some(dir) => dir.join(env!("CARGO_PKG_NAME")).join(file_name),
+            None => std::env::current_dir()?.join(file_name),
+        },
+    }))
+}
+
+/// Opens where `--progress json` events go: the file descriptor given with
+/// `--progress-fd`, or stderr. Returns `None` when progress is shown as bars.
+fn progress_events_output(cli: &Cli) -> Result<Option<Box<dyn Write + Send>>> {
+    if cli.progress != ProgressFormat::Json {
+        if cli.progress_fd.is_some() {
+            anyhow::bail!("--progress-fd only applies with --progress json");
+        }
+        return Ok(None);
+    }
+    let Some(fd) = cli.progress_fd else {
+        return Ok(Some(Box::new(std::io::stderr())));
+    };
+    if cfg!(not(unix)) {
+        anyhow::bail!("--progress-fd is only available on Unix");
+    }
+    // Opening /dev/fd/N duplicates the descriptor, which the wrapper keeps open
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .open(format!("/dev/fd/{}", fd))
+        .with_context(|| format!("Failed to open --progress-fd {}", fd))?;
+    Ok(Some(Box::new(file)))
+}
+
+/// Resolves the directory for the hash cache and the index, creating it if needed.
+///
+/// The directory is taken from `--cache-dir` (or the config file), then the platform
+/// default.
+fn resolve_cache_dir(cli: &Cli) -> Result<PathBuf> {
+    let cache_dir = cache_dir_path(cli)?;
+    fs::create_dir_all(&cache_dir)?;
+    Ok(cache_dir)
+}
+
+/// Returns the directory [`resolve_cache_dir`] uses, without creating it.
+fn cache_dir_path(cli: &Cli) -> Result<PathBuf> {
+    Ok(cli
+        .cache_dir
+        .clone()
+        .or_else(HashCache::default_dir)
+        .unwrap_or(std::env::current_dir()?))
+}
+
+/// Resolves the hash cache file to use in the directory from [`resolve_cache_dir`]. A
+/// cache left in the current directory by older versions is moved to the new location
+/// the first time it is used. It is only moved into the cache of the first base path
+/// scanned, since its keys would match the files of any other base path with the same
+/// relative paths.
+fn resolve_cache_file(cli: &Cli, root: &ScanRoot) -> Result<PathBuf> {
+    let cache_file = HashCache::file_for_base(&resolve_cache_dir(cli)?, root);
+
+    let legacy_file =
+        std::env::current_dir()?.join(format!("{}-cache.json.zst", env!("CARGO_PKG_NAME")));
+    if !cache_file.exists() && legacy_file.exists() {
+        info!(
+            "Migrating hash cache from {} to {}",
+            legacy_file.display(),
+            cache_file.display()
+        );
+        // Across filesystems, renaming fails
+        if fs::rename(&legacy_file, &cache_file).is_err() {
+            fs::copy(&legacy_file, &cache_file)?;
+            fs::remove_file(&legacy_file)?;
+        }
+    }
+    Ok(cache_file)
+}
 
-/// Configuration structure for storing base path and skip directories.
-#[derive(Serialize, Deserialize)]
-struct Config {
-    base_path: String,
-    #[serde(default)]
-    skip_dirs: Vec<String>,
+/// Finds and loads the config file, if any, returning it along with its path.
+fn load_config(cli: &Cli) -> Result<(Config, Option<PathBuf>)> {
+    match Config::find(cli.config.as_deref())? {
+        Some(file) => Ok((Config::load(&file, cli.profile.as_deref())?, Some(file))),
+        None if cli.profile.is_some() => {
+            anyhow::bail!("--profile needs a config file, but none was found")
+        }
+        None => Ok((Config::default(), None)),
+    }
+}
+
+/// Grants permission to change files for `what`, unless `--read-only` is given.
+fn permission_to(cli: &Cli, what: &str) -> Result<ActionPermission> {
+    if cli.read_only {
+        anyhow::bail!("Refusing to {}, since --read-only is set", what);
+    }
+    Ok(ActionPermission::allow_changes())
 }
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let start_time = std::time::Instant::now();
+    let (mut cli, matches) = Cli::from_matches(Cli::command().get_matches())?;
+    let start_time = Instant::now();
+
+    // Load the config first, since it can change where the log is written. `config init`
+    // ignores any existing config, so that a broken one can be replaced, and `doctor`
+    // reports a broken one rather than stopping at it.
+    let doctor = matches!(cli.command, Some(Command::Doctor));
+    let loaded = match &cli.command {
+        Some(Command::Config { .. }) => Ok((Config::default(), None)),
+        _ => load_config(&cli),
+    }
+    .and_then(|(config, file)| {
+        config.apply_to(&mut cli, &matches)?;
+        Ok((config, file))
+    });
+    let (config, config_file, config_error) = match loaded {
+        Ok((config, file)) => (config, file, None),
+        Err(e) if doctor => (Config::default(), None, Some(e)),
+        Err(e) => return Err(e),
+    };
+    if cli.scan.print0 {
+        match cli.scan.output_format {
+            OutputFormat::Text => cli.scan.output_format = OutputFormat::Fdupes,
+            OutputFormat::Fdupes => {}
+            _ => anyhow::bail!("--print0 only applies to the fdupes output format"),
+        }
+    }
 
-    // Initialize console and file logging
-    let log_file = std::env::current_dir()?.join(format!("{}.log", env!("CARGO_PKG_NAME")));
-    let log_level = LevelFilter::Info;
+    // Initialize console and file logging. `doctor` checks the log file rather than
+    // failing to open it.
+    let log_file = if doctor {
+        None
+    } else {
+        resolve_log_file(&cli)?
+    };
+    let (console_level, file_level) = if cli.quiet {
+        (LevelFilter::Warn, LevelFilter::Info)
+    } else if cli.verbose {
+        (LevelFilter::Debug, LevelFilter::Debug)
+    } else {
+        (LevelFilter::Info, LevelFilter::Info)
+    };
+    let progress_events = progress_events_output(&cli)?;
+    set_progress_hidden(cli.quiet || progress_events.is_some());
+    if let Some(out) = progress_events {
+        set_progress_events(out);
+    }
+    set_number_format(NumberFormat::from_env(cli.si));
+    set_display_paths(cli.display_paths.clone());
+    set_read_options(
+        cli.read_buffer,
+        cli.drop_page_cache,
+        cli.large_file_threshold,
+    );
+    // https://no-color.org: any non-empty value turns colors off
+    let no_color = cli.no_color || std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    if no_color {
+        colored::control::set_override(false);
+    }
     let log_config = ConfigBuilder::new()
         .set_time_format_custom(format_description!(
             "[year]-[month]-[day] [hour]:[minute]:[second].[subsecond digits:3]"
@@ -36,67 +324,922 @@ fn main() -> Result<()> {
         .set_time_offset_to_local()
         .unwrap_or_else(|builder| builder) // Fallback to UTC if local offset fails
         .build();
-    CombinedLogger::init(vec![
-        TermLogger::new(
-            log_level,
-            log_config.clone(),
-            TerminalMode::Mixed,
-            ColorChoice::Auto,
+    // Keep stdout clean for commands whose output is meant to be piped
+    let terminal_mode = if writes_data_to_stdout(&cli) {
+        TerminalMode::Stderr
+    } else {
+        TerminalMode::Mixed
+    };
+    let mut loggers: Vec<Box<dyn SharedLogger>> = vec![TermLogger::new(
+        console_level,
+        log_config.clone(),
+        terminal_mode,
+        if no_color {
+            ColorChoice::Never
+        } else {
+            ColorChoice::Auto
+        },
+    )];
+    if let Some(log_file) = &log_file {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_file)
+            .with_context(|| format!("Failed to open log file {}", log_file.display()))?;
+        loggers.push(match cli.log_format {
+            LogFormat::Text => WriteLogger::new(file_level, log_config, file),
+            LogFormat::Json => JsonLogger::new(file_level, file),
+        });
+    }
+    CombinedLogger::init(loggers)?;
+
+    if let Some(log_file) = &log_file {
+        info!("Logging to {}", log_file.display());
+    }
+    match &config_file {
+        Some(file) => info!(
+            "Loaded config from {}{}: base_path={}",
+            file.display(),
+            cli.profile
+                .as_ref()
+                .map(|name| format!(" (profile {})", name))
+                .unwrap_or_default(),
+            config.base_path
         ),
-        WriteLogger::new(
-            log_level,
-            log_config,
-            std::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&log_file)?,
+        None if config_error.is_none() => {
+            info!("No config file found, using default base path")
+        }
+        None => {}
+    }
+
+    if cli.low_priority {
+        match lower_priority() {
+            Ok(()) => info!("Running with low CPU and disk priority"),
+            Err(e) => warn!("Failed to lower the process priority: {}", e),
+        }
+    }
+
+    match &cli.command {
+        Some(Command::Cache { action }) => run_cache_command(action, &cli, &config),
+        Some(Command::Config {
+            action: ConfigCommand::Init { path, force },
+        }) => {
+            let path = match path.clone().or_else(Config::default_path) {
+                Some(path) => path,
+                None => anyhow::bail!("No config directory found, pass a path to write to"),
+            };
+            Config::write_template(&path, *force)?;
+            info!("Wrote config template to {}", path.display());
+            Ok(())
+        }
+        Some(Command::Report {
+            file,
+            sort_by,
+            top,
+            min_group_waste,
+            min_copies,
+            diff_against,
+            ..
+        }) => {
+            let file = match file {
+                Some(file) => file.clone(),
+                None => {
+                    let root = ScanRoot::for_base(Path::new(&config.base_path));
+                    let file =
+                        DuplicateReport::last_file_for_cache(&resolve_cache_file(&cli, &root)?);
+                    if !file.exists() {
+                        anyhow::bail!(
+                            "No results of an earlier scan of {} to show",
+                            root.base().display()
+                        );
+                    }
+                    file
+                }
+            };
+            let report = DuplicateReport::load(&file)?;
+            print_report(
+                &report,
+                &report.shown_groups(*sort_by, *min_copies, *min_group_waste),
+                *top,
+            );
+            if let Some(previous) = diff_against {
+                print_diff(&report.diff(&DuplicateReport::load(previous)?));
+            }
+            Ok(())
+        }
+        Some(Command::History { path, limit }) => {
+            let root = ScanRoot::for_base(Path::new(&config.base_path));
+            let entries = load_history(&history_file_for_cache(&resolve_cache_file(&cli, &root)?))?;
+            // Recorded canonical, as the scan root has it
+            let path = path
+                .as_ref()
+                .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()));
+            print_history(&scans_of(entries, path.as_deref()), *limit);
+            Ok(())
+        }
+        // Taken out of the command by Cli::from_matches
+        Some(Command::Scan(_)) => unreachable!(),
+        Some(Command::Undo { journal }) => {
+            let stats = undo(&Journal::load(journal)?, &permission_to(&cli, "undo")?);
+            if stats.failed > 0 {
+                anyhow::bail!("{} operations could not be undone", stats.failed);
+            }
+            Ok(())
+        }
+        Some(Command::Apply {
+            manifest,
+            journal,
+            use_trash,
+            metadata_policy,
+        }) => {
+            let journal = journal
+                .clone()
+                .unwrap_or_else(|| default_journal_dir().join(journal_file_name()));
+            let options = ActionOptions {
+                use_trash: *use_trash,
+                metadata: *metadata_policy,
+                ..ActionOptions::default()
+            };
+            let permission = permission_to(&cli, "apply a manifest")?;
+            let manifest = Manifest::load(manifest)?;
+            manifest.check_protected(
+                &KeepPolicy::default()
+                    .with_protected(protected_patterns(&cli, &config)?)
+                    .with_originals(Originals::load(originals_file(&cli))?.paths()),
+            )?;
+            apply_manifest(&manifest, &options, &journal, &permission)?;
+            Ok(())
+        }
+        Some(Command::MergeDirs {
+            into,
+            from,
+            journal,
+            use_trash,
+            dry_run,
+            confirm: ask,
+        }) => {
+            let permission = (!*dry_run)
+                .then(|| permission_to(&cli, "merge directories"))
+                .transpose()?;
+            let plan = plan_merge(into, from)?;
+            plan.print();
+            if *dry_run {
+                info!("Dry run, not changing any files");
+            } else if *ask && !confirm("Proceed?")? {
+                info!("Not changing any files");
+            } else if let Some(permission) = &permission {
+                let journal = journal
+                    .clone()
+                    .unwrap_or_else(|| default_journal_dir().join(journal_file_name()));
+                let options = ActionOptions {
+                    use_trash: *use_trash,
+                    ..ActionOptions::default()
+                };
+                apply_merge(plan, &options, &journal, permission)?;
+            }
+            Ok(())
+        }
+        Some(Command::Acknowledge { targets, note }) => {
+            let mut acknowledged = Acknowledged::load(acknowledged_file(&cli))?;
+            match AcknowledgeTarget::parse(targets, true)? {
+                None => {
+                    acknowledged.print();
+                    return Ok(());
+                }
+                Some(AcknowledgeTarget::Hash(hash)) => {
+                    if !acknowledged.acknowledge_hash(hash, note.clone()) {
+                        info!("Duplicate group {} is already acknowledged", hash);
+                    }
+                }
+                Some(AcknowledgeTarget::Paths(paths)) => {
+                    if paths.len() < 2 {
+                        anyhow::bail!("Give a group's hash, or at least two files to acknowledge");
+                    }
+                    if !acknowledged.acknowledge_paths(paths, note.clone()) {
+                        info!("These files are already acknowledged as copies");
+                    }
+                }
+            }
+            acknowledged.save()
+        }
+        Some(Command::Unacknowledge { targets }) => {
+            let mut acknowledged = Acknowledged::load(acknowledged_file(&cli))?;
+            let removed = match AcknowledgeTarget::parse(targets, false)? {
+                Some(AcknowledgeTarget::Hash(hash)) => {
+                    usize::from(acknowledged.unacknowledge_hash(&hash))
+                }
+                Some(AcknowledgeTarget::Paths(paths)) => acknowledged.unacknowledge_paths(&paths),
+                None => 0,
+            };
+            if removed == 0 {
+                anyhow::bail!("Nothing acknowledged matches the given hash or files");
+            }
+            info!("Removed {} acknowledgements", Count(removed as u64));
+            acknowledged.save()
+        }
+        Some(Command::RegisterOriginals { path }) => {
+            let mut originals = Originals::load(originals_file(&cli))?;
+            if let Some(path) = path {
+                let root = ScanRoot::new(path, Path::new(&config.base_path))
+                    .with_case_insensitive_paths(cli.case_insensitive_paths);
+                let (_, files) = scan_with_cache(&cli, &config, &root, cli.scan.min_size, true)?;
+                let registered_at = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)?
+                    .as_secs();
+                originals.register(root.scan_path(), &files, registered_at);
+                originals.save()?;
+            }
+            originals.print();
+            Ok(())
+        }
+        Some(Command::Lookup { files }) => run_lookup(&cli, &config, files),
+        Some(Command::Index {
+            action,
+            path,
+            index_file,
+        }) => {
+            let index_file = resolve_index_file(&cli, index_file.as_deref())?;
+            match (action, path) {
+                (Some(IndexCommand::Export { file }), _) => {
+                    CorpusIndex::load(index_file)?.export(file)?;
+                    Ok(())
+                }
+                (Some(IndexCommand::Import { file, name }), _) => {
+                    let name = match name {
+                        Some(name) => name.clone(),
+                        None => file
+                            .file_stem()
+                            .map(|stem| stem.to_string_lossy().into_owned())
+                            .context("Use --name to name the imported locations")?,
+                    };
+                    let mut index = CorpusIndex::load(index_file)?;
+                    index.import(file, &name)?;
+                    index.save()?;
+                    index.print_roots();
+                    Ok(())
+                }
+                (None, Some(path)) => run_index(&cli, &config, path, index_file),
+                (None, None) => unreachable!("clap requires a path without a subcommand"),
+            }
+        }
+        Some(Command::CheckAgainstIndex {
+            path,
+            index_file,
+            missing,
+        }) => run_check_against_index(&cli, &config, path, index_file.as_deref(), *missing),
+        Some(Command::Import {
+            src,
+            dest,
+            move_files,
+            dry_run,
+        }) => run_import(&cli, &config, src, dest, *move_files, *dry_run),
+        Some(Command::Watch { path, settle_ms }) => {
+            run_watch(&cli, &config, path, Duration::from_millis(*settle_ms), None)
+        }
+        Some(Command::Daemon {
+            path,
+            listen,
+            settle_ms,
+        }) => run_watch(
+            &cli,
+            &config,
+            path,
+            Duration::from_millis(*settle_ms),
+            Some(listen),
         ),
-    ])?;
+        Some(Command::Verify {
+            inventory,
+            path,
+            cached,
+        }) => {
+            let entries = read_inventory(inventory)?;
+            let root = ScanRoot::new(path, Path::new(&config.base_path))
+                .with_case_insensitive_paths(cli.case_insensitive_paths);
+            let (_, files) = scan_with_cache(&cli, &config, &root, 0, *cached)?;
+            let result = verify(&entries, &files, &root);
+            print_verification(&result);
+            if result.problems() > 0 {
+                anyhow::bail!(
+                    "{} of {} inventoried files failed verification",
+                    Count(result.problems() as u64),
+                    Count(entries.len() as u64)
+                );
+            }
+            Ok(())
+        }
+        Some(Command::Archive {
+            path,
+            store,
+            link,
+            manifest,
+        }) => {
+            let root = ScanRoot::new(path, Path::new(&config.base_path))
+                .with_case_insensitive_paths(cli.case_insensitive_paths);
+            let (_, files) = scan_with_cache(&cli, &config, &root, cli.scan.min_size, true)?;
+            let store = ContentStore::new(store);
+            let manifest = manifest
+                .clone()
+                .unwrap_or_else(|| store.manifest_path(&root, std::time::SystemTime::now()));
+            let stats = archive(&files, &root, &store, &manifest, *link)?;
+            print_archive_stats(&stats, &manifest);
+            Ok(())
+        }
+        Some(Command::Doctor) => {
+            let base_path = Path::new(&config.base_path);
+            let cache_dir = cache_dir_path(&cli)?;
+            let cache_file = HashCache::file_for_base(&cache_dir, &ScanRoot::for_base(base_path));
+            let mut diagnoses = vec![
+                check_config(config_file.as_deref(), config_error.as_ref()),
+                check_base_path(base_path),
+                check_cache(&cache_file),
+                check_writable("Cache directory", &cache_dir, "--cache-dir"),
+            ];
+            if let Some(log_file) = log_file_path(&cli)? {
+                let log_dir = log_file.parent().unwrap_or(Path::new("."));
+                diagnoses.push(check_writable("Log directory", log_dir, "--log-file"));
+            }
+            diagnoses.push(check_disk_space(&cache_dir, &cache_file));
+            diagnoses.push(check_hashing());
+            print_diagnoses(&diagnoses)
+        }
+        Some(Command::Bench {
+            path,
+            max_threads,
+            sample_size,
+        }) => {
+            let root = ScanRoot::new(path, Path::new(&config.base_path))
+                .with_case_insensitive_paths(cli.case_insensitive_paths);
+            let max_threads = max_threads
+                .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+            let results = run_bench(
+                &root,
+                &config.skip_dirs,
+                cli.scan.min_size,
+                max_threads,
+                *sample_size,
+            )?;
+            print_bench(&results);
+            Ok(())
+        }
+        Some(Command::GenTestdata {
+            dir,
+            files,
+            dup_ratio,
+            seed,
+            max_size,
+        }) => {
+            let tree = generate(
+                dir,
+                &TestTreeSpec {
+                    files: *files,
+                    dup_ratio: *dup_ratio,
+                    seed: *seed,
+                    max_size: *max_size,
+                },
+            )?;
+            info!(
+                "Wrote {} files ({}) to {}, with {} duplicate groups holding {} extra copies",
+                Count(tree.files.len() as u64),
+                Bytes(tree.bytes),
+                dir.display(),
+                Count(tree.groups.len() as u64),
+                Count(tree.groups.iter().map(|group| group.len() as u64 - 1).sum())
+            );
+            Ok(())
+        }
+        Some(Command::Completions { shell, dir }) => {
+            let mut command = Cli::command();
+            let name = shell.file_name(command.get_name());
+            write_generated(dir.as_deref(), &name, |writer| {
+                write_completions(&mut command, *shell, writer)
+            })
+        }
+        Some(Command::Man { dir }) => {
+            let mut command = Cli::command();
+            let name = format!("{}.1", command.get_name());
+            write_generated(dir.as_deref(), &name, |writer| {
+                write_man_page(&mut command, writer)
+            })
+        }
+        None if cli.scan.estimate => run_estimate(&cli, &config, start_time),
+        None if cli.scan.size_only => {
+            run_potential(&cli, &config, start_time, PotentialMatch::Size)
+        }
+        None if let Some(by) = cli.scan.potential_match()
+            && cli.scan.confirm_groups.is_empty() =>
+        {
+            run_potential(&cli, &config, start_time, by)
+        }
+        None => {
+            let notifiers = notifiers(&cli, &config)?;
+            let result = run_scan(&cli, &matches, &config, start_time, &notifiers);
+            if let Err(e) = &result {
+                notify_all(
+                    &notifiers,
+                    &ScanSummary::failed(&cli.scan.path, e, start_time.elapsed()),
+                );
+            }
+            result
+        }
+    }
+}
 
+/// Estimates the duplicates under `cli.scan.path` from a sample of its files, saving the hashes
+/// computed to the cache for the full scan.
+fn run_estimate(cli: &Cli, config: &Config, start_time: Instant) -> Result<()> {
+    let root = ScanRoot::with_paths(&cli.scan.scan_paths(), Path::new(&config.base_path))
+        .with_case_insensitive_paths(cli.case_insensitive_paths);
+    let cache = HashCache::load(resolve_cache_file(cli, &root)?)
+        .with_base(&root)
+        .with_limits(cli.scan.cache_limits())
+        .with_format(cli.scan.cache_format)
+        .with_compression(cli.scan.cache_compression())
+        .with_mtime_policy(cli.scan.mtime_policy())
+        .with_lock_wait(!cli.scan.no_cache_wait);
+    let estimate = estimate(
+        &root,
+        &cache,
+        !cli.scan.no_cache,
+        &config.skip_dirs,
+        &cli.name_filter(),
+        cli.scan.min_size,
+        &cli.scan.modified_range(),
+        cli.scan.estimate_groups,
+    )?;
+    if !cli.scan.no_cache
+        && let Err(e) = cache.save()
+    {
+        error!("Failed to save hash cache: {:#}", e);
+    }
+    print_estimate(&estimate);
     info!(
-        "Starting check-file-dups v{} with options: path={}, threads={:?}, no_cache={}, prune_cache={}",
-        env!("CARGO_PKG_VERSION"),
-        cli.path.display(),
-        cli.threads.unwrap(),
-        cli.no_cache,
-        cli.prune_cache
+        duration = start_time.elapsed().as_secs_f64();
+        "Program completed successfully in {}",
+        HumanDuration(start_time.elapsed())
     );
-    info!("Logging to {}", log_file.display());
+    Ok(())
+}
 
-    let config_file = std::env::current_dir()
-        .expect("Failed to get current directory")
-        .join(format!("{}.toml", env!("CARGO_PKG_NAME")));
+/// Reports the files under `cli.scan.path` sharing what `by` asks for as potential
+/// duplicates, without reading them.
+fn run_potential(
+    cli: &Cli,
+    config: &Config,
+    start_time: Instant,
+    by: PotentialMatch,
+) -> Result<()> {
+    if cli.scan.has_action() || cli.scan.exec.is_some() || cli.scan.write_manifest.is_some() {
+        anyhow::bail!(
+            "Potential duplicates found without reading the files can't be acted on: drop \
+             --size-only, --match name-size or --hash none, or pick the groups to hash and act \
+             on with --confirm-group"
+        );
+    }
+    let root = ScanRoot::with_paths(&cli.scan.scan_paths(), Path::new(&config.base_path))
+        .with_case_insensitive_paths(cli.case_insensitive_paths);
+    let groups = find_potential(
+        &root,
+        &config.skip_dirs,
+        &cli.name_filter(),
+        cli.scan.min_size,
+        &cli.scan.modified_range(),
+        by,
+    )?;
+    print_potential(&groups, &root, cli.scan.top, by);
+    info!(
+        duration = start_time.elapsed().as_secs_f64();
+        "Program completed successfully in {}",
+        HumanDuration(start_time.elapsed())
+    );
+    Ok(())
+}
 
-    let config = if let Ok(config_content) = fs::read_to_string(&config_file) {
-        if let Ok(config) = toml::from_str::<Config>(&config_content) {
-            info!("Loaded config: base_path={}", config.base_path);
-            config
+/// Returns the notifiers for `--notify-webhook` and the config file's `[smtp]` table.
+fn notifiers(cli: &Cli, config: &Config) -> Result<Vec<Box<dyn Notifier>>> {
+    let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+    if let Some(url) = &cli.scan.notify_webhook {
+        notifiers.push(Box::new(Webhook::new(url)?));
+    }
+    if let Some(smtp) = &config.smtp {
+        notifiers.push(Box::new(Smtp::new(smtp)?));
+    }
+    Ok(notifiers)
+}
+
+/// Resolves the directories given to `option` to absolute paths, checking that each
+/// exists inside the scanned directory.
+fn dirs_inside(root: &ScanRoot, option: &str, dirs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    dirs.iter()
+        .map(|dir| {
+            let dir = dir
+                .canonicalize()
+                .with_context(|| format!("{} {}", option, dir.display()))?;
+            if !dir.starts_with(root.scan_path()) {
+                anyhow::bail!(
+                    "{} {} is not inside the scanned directory {}",
+                    option,
+                    dir.display(),
+                    root.scan_path().display()
+                );
+            }
+            Ok(dir)
+        })
+        .collect()
+}
+
+/// Returns the `--protect` globs together with those of the config file's `protect` key.
+fn protected_patterns(cli: &Cli, config: &Config) -> Result<Vec<GlobMatcher>> {
+    let mut patterns = cli.protect.clone();
+    for pattern in &config.protect {
+        patterns.push(path_glob(pattern).map_err(anyhow::Error::msg)?);
+    }
+    Ok(patterns)
+}
+
+/// Hashes `files` and prints the copies of each found in the hash cache.
+fn run_lookup(cli: &Cli, config: &Config, files: &[PathBuf]) -> Result<()> {
+    let root = ScanRoot::for_base(Path::new(&config.base_path))
+        .with_case_insensitive_paths(cli.case_insensitive_paths);
+    let cache = HashCache::load(resolve_cache_file(cli, &root)?).with_base(&root);
+    let index = HashIndex::from_cache(&cache, &root);
+    for file in files {
+        let path = file
+            .canonicalize()
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        // Reuse the cached hash if the file is unchanged, without caching files from
+        // outside the base path
+        let hash = match cache.get_hash(&path, &root) {
+            Ok(Some(hash)) => hash,
+            _ => hash_file(&path)?.to_string(),
+        };
+        let copies = index.copies(&hash, &path);
+        if copies.is_empty() {
+            println!("{}: no copies found", file.display());
         } else {
-            info!("Failed to parse config file, using default base path");
-            Config {
-                base_path: ".".to_string(),
-                skip_dirs: Vec::new(),
+            println!(
+                "{}: {} {}",
+                file.display(),
+                copies.len(),
+                if copies.len() == 1 { "copy" } else { "copies" }
+            );
+            for copy in copies {
+                println!("  {}", copy.display());
             }
         }
-    } else {
-        info!("No config file found, using default base path");
-        Config {
-            base_path: ".".to_string(),
-            skip_dirs: Vec::new(),
+    }
+    Ok(())
+}
+
+/// Returns the scan options every scan takes from the command line and `config`: the
+/// directories and names to skip, the filesystems to cross, threads, `throttle` and
+/// retries.
+fn scan_options<'a>(cli: &Cli, config: &Config, throttle: &'a Throttle) -> ScanOptions<'a> {
+    ScanOptions::default()
+        .with_skip_dirs(config.skip_dirs.clone())
+        .with_names(cli.name_filter())
+        .with_one_file_system(cli.one_file_system)
+        .with_follow_junctions(cli.follow_junctions)
+        .with_threads(
+            cli.scan.walk_threads(),
+            cli.scan.io_threads(),
+            cli.scan.hash_threads(),
+        )
+        .with_throttle(throttle)
+        .with_retry(cli.retry_policy())
+}
+
+/// Scans `root.scan_path()` with the hash cache for its base path, without looking for
+/// duplicates, and returns the cache and the files found. Files smaller than `min_size`
+/// are skipped as in a scan. Without `use_cache`, every file is hashed again and the
+/// cache is left alone. The cache is saved afterwards; Ctrl+C exits, leaving the new hashes
+/// in the cache journal.
+fn scan_with_cache(
+    cli: &Cli,
+    config: &Config,
+    root: &ScanRoot,
+    min_size: u64,
+    use_cache: bool,
+) -> Result<(Arc<HashCache>, Vec<FileInfo>)> {
+    let mut cache = HashCache::load(resolve_cache_file(cli, root)?)
+        .with_base(root)
+        .with_limits(cli.scan.cache_limits())
+        .with_format(cli.scan.cache_format)
+        .with_compression(cli.scan.cache_compression())
+        .with_mtime_policy(cli.scan.mtime_policy())
+        .with_lock_wait(!cli.scan.no_cache_wait);
+    if let Some(policy) = cli.scan.cache_checkpoint {
+        cache = cache.with_checkpoint(policy);
+    }
+    let cache = Arc::new(cache);
+
+    // New hashes are in the cache journal, so nothing is lost by exiting on Ctrl+C
+    exit_on_interrupt()?;
+
+    let throttle = cli.throttle();
+    let options = scan_options(cli, config, &throttle)
+        .with_min_size(min_size)
+        .with_cache(use_cache);
+    let (groups, errors, _) = scan_directory(root, cache.as_ref(), options)?;
+    if use_cache && let Err(e) = cache.save() {
+        error!("Failed to save hash cache: {:#}", e);
+    }
+    print_scan_errors(&errors, root, cli.scan.skip_unreadable);
+    Ok((cache, groups.into_files()))
+}
+
+/// Returns the file of acknowledged duplicate groups, from `--acknowledged-file` or the
+/// default location.
+fn acknowledged_file(cli: &Cli) -> PathBuf {
+    cli.acknowledged_file
+        .clone()
+        .unwrap_or_else(Acknowledged::default_file)
+}
+
+/// Returns the file of registered originals, from `--originals-file` or the default
+/// location.
+fn originals_file(cli: &Cli) -> PathBuf {
+    cli.originals_file
+        .clone()
+        .unwrap_or_else(Originals::default_file)
+}
+
+/// Resolves the index file to use: `index_file` if given, otherwise the default one in
+/// the cache directory.
+fn resolve_index_file(cli: &Cli, index_file: Option<&Path>) -> Result<PathBuf> {
+    match index_file {
+        Some(file) => Ok(file.to_path_buf()),
+        None => Ok(CorpusIndex::file_in(&resolve_cache_dir(cli)?)),
+    }
+}
+
+/// Scans `path` and records its files in the index, replacing those recorded by an
+/// earlier run for the same location.
+fn run_index(cli: &Cli, config: &Config, path: &Path, index_file: PathBuf) -> Result<()> {
+    let mut index = CorpusIndex::load(index_file)?;
+    let root = ScanRoot::new(path, Path::new(&config.base_path))
+        .with_case_insensitive_paths(cli.case_insensitive_paths);
+    let (_, files) = scan_with_cache(cli, config, &root, cli.scan.min_size, true)?;
+    let indexed_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    index.replace(root.scan_path(), &files, indexed_at);
+    index.save()?;
+    index.print_roots();
+    Ok(())
+}
+
+/// Scans `path` and prints its files that already exist in the index, with their
+/// indexed copies, or with `missing` the files that don't.
+fn run_check_against_index(
+    cli: &Cli,
+    config: &Config,
+    path: &Path,
+    index_file: Option<&Path>,
+    missing: bool,
+) -> Result<()> {
+    let index = CorpusIndex::load(resolve_index_file(cli, index_file)?)?;
+    if index.is_empty() {
+        anyhow::bail!("The index is empty, build it first with the `index` command");
+    }
+    index.print_roots();
+    let root = ScanRoot::new(path, Path::new(&config.base_path))
+        .with_case_insensitive_paths(cli.case_insensitive_paths);
+    let (_, mut files) = scan_with_cache(cli, config, &root, cli.scan.min_size, true)?;
+    // Empty files match every other empty file, so they say nothing about the index
+    if cli.scan.ignore_empty {
+        files.retain(|file| file.size > 0);
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let (mut found, mut found_size) = (0u64, 0u64);
+    for file in &files {
+        // A checked directory inside an indexed location finds each file in the index
+        let copies: Vec<&PathBuf> = index
+            .paths(&file.hash.to_string())
+            .iter()
+            .filter(|copy| file.path != ***copy)
+            .collect();
+        if !copies.is_empty() {
+            found += 1;
+            found_size += file.size;
         }
-    };
+        let relative = root.display_file(&file.path);
+        let display = relative.display();
+        match (copies.split_first(), missing) {
+            (Some((first, [])), false) => {
+                println!("{}: in index as {}", display, first.display());
+            }
+            (Some((first, rest)), false) => println!(
+                "{}: in index as {} (and {} more)",
+                display,
+                first.display(),
+                rest.len()
+            ),
+            (None, true) => println!("{}", display),
+            _ => {}
+        }
+    }
+    let total_size: u64 = files.iter().map(|file| file.size).sum();
+    info!(
+        "{} of {} files ({} of {}) already exist in the index",
+        Count(found),
+        Count(files.len() as u64),
+        Bytes(found_size),
+        Bytes(total_size)
+    );
+    Ok(())
+}
+
+/// Scans `dest` with its hash cache, then imports the files of `src` whose contents it
+/// doesn't have yet.
+fn run_import(
+    cli: &Cli,
+    config: &Config,
+    src: &Path,
+    dest: &Path,
+    move_files: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let permission = (!dry_run)
+        .then(|| permission_to(cli, "import files"))
+        .transpose()?;
+    let root = ScanRoot::new(dest, Path::new(&config.base_path))
+        .with_case_insensitive_paths(cli.case_insensitive_paths);
+    let (_, files) = scan_with_cache(cli, config, &root, 0, true)?;
+    let existing: HashMap<ContentHash, PathBuf> = files
+        .into_iter()
+        .filter(|file| file.size > 0)
+        .map(|file| (file.hash, file.path.to_path_buf()))
+        .collect();
+    let plan = plan_import(src, dest, &existing)?;
+    plan.print();
+    match &permission {
+        Some(permission) => {
+            apply_import(&plan, move_files, permission)?;
+        }
+        None => info!("Dry run, not copying any files"),
+    }
+    Ok(())
+}
+
+/// Scans `path` to warm the cache, then watches it for new duplicates until interrupted.
+/// If `listen` is given, the index is also served over HTTP on that address.
+fn run_watch(
+    cli: &Cli,
+    config: &Config,
+    path: &Path,
+    settle: Duration,
+    listen: Option<&str>,
+) -> Result<()> {
+    // Bind first, so a busy port is reported before the initial scan
+    let server = listen.map(daemon::listen).transpose()?;
+    let root = ScanRoot::new(path, Path::new(&config.base_path))
+        .with_case_insensitive_paths(cli.case_insensitive_paths);
+    let metrics = Arc::new(WatchMetrics::default());
+    let scan_start = Instant::now();
+    let (cache, _) = scan_with_cache(cli, config, &root, 0, true)?;
+    metrics.set_initial_scan(scan_start.elapsed());
+    let index = Arc::new(Mutex::new(HashIndex::from_cache(&cache, &root)));
+    if let Some(server) = server {
+        let (index, cache, metrics) = (index.clone(), cache.clone(), metrics.clone());
+        thread::spawn(move || daemon::serve(server, index, cache, metrics));
+    }
+    watch(&root, &cache, &index, &config.skip_dirs, settle, &metrics)
+}
+
+/// Scans `cli.scan.path` for duplicates and reports them.
+fn run_scan(
+    cli: &Cli,
+    matches: &ArgMatches,
+    config: &Config,
+    start_time: Instant,
+    notifiers: &[Box<dyn Notifier>],
+) -> Result<()> {
+    info!(
+        "Starting check-file-dups v{} with options: path={}, walk_threads={}, io_threads={}, hash_threads={}, no_cache={}, prune_cache={}",
+        env!("CARGO_PKG_VERSION"),
+        cli.scan.path.display(),
+        cli.scan.walk_threads(),
+        cli.scan.io_threads(),
+        cli.scan.hash_threads(),
+        cli.scan.no_cache,
+        cli.scan.prune_cache
+    );
 
-    if cli.no_cache {
+    if cli.scan.perceptual && !cfg!(feature = "perceptual") {
+        anyhow::bail!("--perceptual requires a build with the perceptual feature enabled");
+    }
+    if cli.scan.audio && !cfg!(feature = "audio") {
+        anyhow::bail!("--audio requires a build with the audio feature enabled");
+    }
+    if cli.scan.snapshot && !cfg!(windows) {
+        anyhow::bail!("--snapshot is only available on Windows");
+    }
+    // Asked for before scanning, so --read-only refuses before a long scan, not after
+    let permission = cli
+        .scan
+        .changes_files()
+        .then(|| permission_to(cli, "change files"))
+        .transpose()?;
+    if let (Some(newer_than), Some(older_than)) = (cli.scan.newer_than, cli.scan.older_than)
+        && newer_than >= older_than
+    {
+        anyhow::bail!("--newer-than must be earlier than --older-than, or no file is scanned");
+    }
+    if cli.scan.random_sample && !cli.scan.scan_limit().is_set() {
+        anyhow::bail!("--random-sample requires --limit-files or --limit-bytes");
+    }
+    if cli.scan.no_cache {
         info!("Hash cache disabled - computing all hashes fresh");
     }
 
+    // Detached again when dropped at the end of the scan
+    let images = cli
+        .scan
+        .disk_image
+        .iter()
+        .map(|image| MountedImage::attach(image))
+        .collect::<Result<Vec<_>>>()?;
+    let mut scan_paths = cli.scan.scan_paths();
+    scan_paths.extend(
+        images
+            .iter()
+            .flat_map(|image| image.mount_points())
+            .cloned(),
+    );
+    let root = ScanRoot::with_paths(&scan_paths, Path::new(&config.base_path))
+        .with_case_insensitive_paths(cli.case_insensitive_paths);
+    let between = cli
+        .scan
+        .between
+        .as_deref()
+        .map(|dirs| dirs_inside(&root, "--between", dirs))
+        .transpose()?;
+    let dedupe_within = dirs_inside(&root, "--dedupe-within", &cli.scan.dedupe_within)?;
+    let drill_into = dirs_inside(&root, "--drill-into", cli.scan.drill_into.as_slice())?;
+    let mut file_list = cli
+        .scan
+        .files_from
+        .as_deref()
+        .map(read_file_list)
+        .transpose()?;
+    if !cli.scan.confirm_groups.is_empty() {
+        let Some(by) = cli
+            .scan
+            .potential_match()
+            .filter(|&by| by != PotentialMatch::Size)
+        else {
+            anyhow::bail!("--confirm-group requires --match name-size or --hash none");
+        };
+        let groups = find_potential(
+            &root,
+            &config.skip_dirs,
+            &cli.name_filter(),
+            cli.scan.min_size,
+            &cli.scan.modified_range(),
+            by,
+        )?;
+        let paths = confirm_paths(&groups, &cli.scan.confirm_groups)?;
+        info!(
+            "Confirming {} files of the groups with the same {} by hashing them",
+            Count(paths.len() as u64),
+            by.shared()
+        );
+        file_list = Some(paths);
+    }
+
     // Create a global cache instance for signal handling
-    let global_cache = Arc::new(HashCache::new());
+    let cache_file = resolve_cache_file(cli, &root)?;
+    // Loaded while the files are listed, and waited for once they are hashed
+    let cache_root = root.clone();
+    let limits = cli.scan.cache_limits();
+    let format = cli.scan.cache_format;
+    let compression = cli.scan.cache_compression();
+    let mtime_policy = cli.scan.mtime_policy();
+    let lock_wait = !cli.scan.no_cache_wait;
+    let checkpoint = cli.scan.cache_checkpoint.filter(|_| !cli.scan.no_cache);
+    let loading_cache = HashCache::load_in_background(cache_file.clone(), move |cache| {
+        let cache = cache
+            .with_base(&cache_root)
+            .with_limits(limits)
+            .with_format(format)
+            .with_compression(compression)
+            .with_mtime_policy(mtime_policy)
+            .with_lock_wait(lock_wait);
+        match checkpoint {
+            Some(policy) => cache.with_checkpoint(policy),
+            None => cache,
+        }
+    });
 
     // Prune cache if requested
-    if cli.prune_cache && !cli.no_cache {
-        let base_path = PathBuf::from(&config.base_path);
-        if let Err(e) = global_cache.prune(&base_path) {
+    if cli.scan.prune_cache && !cli.scan.no_cache {
+        let global_cache = loading_cache.wait();
+        let older_than = cli
+            .scan
+            .prune_older_than
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60));
+        if let Err(e) = global_cache.prune(&root, older_than) {
             error!("Failed to prune cache: {}", e);
         } else {
             // Save the pruned cache immediately
@@ -106,49 +1249,628 @@ fn main() -> Result<()> {
         }
     }
 
-    let cache_for_signal = global_cache.clone();
+    let resume = cli.scan.resume.then(|| {
+        Arc::new(ResumeState::load(
+            ResumeState::file_for_cache(&cache_file),
+            root.scan_path(),
+            &config.skip_dirs,
+            cli.scan.min_size,
+            cli.one_file_system,
+            cli.follow_junctions,
+        ))
+    });
 
-    // Set up signal handler for Ctrl+C and other unexpected exits
-    let running = Arc::new(AtomicBool::new(true)); // Not directly used for loop control yet
-    let running_for_signal = running.clone();
+    // The first Ctrl+C during the scan stops hashing new files, so the files hashed so
+    // far are reported and the cache is saved once at the end; any other exits at once.
+    // New hashes are in the cache journal either way, but the scan progress is saved here
+    let resume_for_signal = resume.clone();
+    let shutdown = Shutdown::install(move || {
+        if let Some(resume) = &resume_for_signal
+            && let Err(e) = resume.save()
+        {
+            eprintln!("Failed to save scan progress on exit: {}", e);
+        }
+    })?;
 
-    let no_cache_for_signal = cli.no_cache;
-    ctrlc::set_handler(move || {
-        if !no_cache_for_signal {
-            info!("Received interrupt signal, saving cache...");
-            if let Err(e) = cache_for_signal.save() {
-                eprintln!("Failed to save hash cache on exit: {}", e);
+    let dir_index = (cli.scan.incremental && !cli.scan.no_cache)
+        .then(|| DirIndex::load(DirIndex::file_for_cache(&cache_file)));
+    let snapshots = if cli.scan.snapshot {
+        snapshots_of(root.roots())?
+    } else {
+        Vec::new()
+    };
+
+    let throttle = cli.throttle();
+    let options = scan_options(cli, config, &throttle)
+        .with_min_size(cli.scan.min_size)
+        .with_modified(cli.scan.modified_range())
+        .with_file_types(cli.scan.file_types.clone())
+        .with_limit(cli.scan.scan_limit())
+        .with_file_list(file_list.as_deref())
+        .with_cache(!cli.scan.no_cache)
+        .with_decompress(cli.scan.decompress_before_hash)
+        .with_detect_reflinks(cli.scan.detect_reflinks)
+        .with_resume(resume.as_deref())
+        .with_dir_index(dir_index.as_ref())
+        .with_snapshots(&snapshots)
+        .with_running(shutdown.running());
+    let (mut groups, errors, hash_stats) = scan_directory(&root, &loading_cache, options)?;
+    let global_cache = Arc::clone(loading_cache.wait());
+    let cache_load_time = loading_cache.load_time();
+    // Deletes the snapshots
+    drop(snapshots);
+    if let Some(dir_index) = &dir_index
+        && let Err(e) = dir_index.save(root.roots())
+    {
+        error!("Failed to save directory index: {}", e);
+    }
+    shutdown.scan_finished();
+    let interrupted = shutdown.interrupted();
+    // Compresses the cache while the results are worked out and printed
+    let background_save = (cli.scan.background_cache_save && !cli.scan.no_cache)
+        .then(|| global_cache.save_in_background());
+    if let Some(resume) = &resume {
+        if interrupted {
+            if let Err(e) = resume.save() {
+                error!("Failed to save scan progress: {}", e);
             }
         } else {
-            info!("Received interrupt signal, exiting...");
+            resume.remove();
         }
-        running_for_signal.store(false, Ordering::SeqCst);
-        std::process::exit(130); // STATUS_CONTROL_C_EXIT
-    })?;
+    }
+    if interrupted {
+        warn!(
+            "{}",
+            format!(
+                "Scan interrupted: the results below are partial, covering the {} files hashed so far",
+                Count(groups.len() as u64)
+            )
+            .yellow()
+        );
+    }
+    if let Some(file) = &cli.scan.export_inventory {
+        if interrupted {
+            warn!("Not writing the inventory, since the scan was interrupted");
+        } else {
+            let written = export_inventory(groups.files(), &root, file)?;
+            info!(
+                "Wrote an inventory of {} files to {}",
+                Count(written as u64),
+                file.display()
+            );
+        }
+    }
+    if cli.scan.look_inside_archives {
+        let members = scan_archives(groups.files());
+        groups.extend(members);
+    }
+    let mut empty_files = 0;
+    if cli.scan.ignore_empty {
+        empty_files = groups.retain(|file| file.size > 0);
+    }
 
-    let files = scan_directory_with_cache(
-        &cli.path,
-        &global_cache,
-        &PathBuf::from(&config.base_path),
-        &config.skip_dirs,
-        cli.threads.unwrap(),
-        cli.no_cache,
-    )?;
+    let mut report_options = ReportOptions {
+        use_allocated_size: cli.scan.allocated_size,
+        count_hardlinks_once: cli.scan.count_hardlinks_once,
+        within_dir_only: cli.scan.within_dir_only,
+        shared_extents: ExtentIds::new(),
+        top: cli.scan.top,
+        min_group_waste: cli.scan.min_group_waste,
+        min_copies: cli.scan.min_copies,
+        sort_by: cli.scan.sort_by,
+        match_mode: cli.scan.match_mode,
+    };
+    let mut metadata_formats: Vec<&dyn PayloadFormat> = Vec::new();
+    if cli.scan.ignore_image_metadata {
+        metadata_formats.push(&Images);
+    }
+    if cli.scan.ignore_audio_tags {
+        metadata_formats.push(&Mp3Audio);
+    }
+    let metadata_duplicates = (!metadata_formats.is_empty())
+        .then(|| find_metadata_duplicates(groups.files(), &metadata_formats));
+    let tombstones = Tombstones::load(HashCache::tombstones_file(&cache_file))?;
+    let reappeared = tombstones.find_reappeared(groups.files());
+    let same_names = cli
+        .scan
+        .report_same_name
+        .then(|| find_same_names(groups.files(), cli.case_insensitive_paths));
+    let sizes = (cli.scan.report_largest.is_some() || cli.scan.size_histogram)
+        .then(|| summarize_sizes(groups.files(), cli.scan.report_largest.unwrap_or(0)));
+    let partial_duplicates = cli.scan.partial_duplicates.then(|| {
+        find_partial_duplicates(
+            groups.files(),
+            cli.scan.partial_min_size,
+            f64::from(cli.scan.partial_similarity) / 100.0,
+        )
+    });
+    #[cfg(feature = "perceptual")]
+    let similar = cli
+        .scan
+        .perceptual
+        .then(|| find_similar(groups.files(), cli.scan.perceptual_distance));
+    #[cfg(feature = "audio")]
+    let same_recordings = cli.scan.audio.then(|| {
+        find_same_recordings(groups.files(), f64::from(cli.scan.audio_similarity) / 100.0)
+    });
+    let group_start = Instant::now();
+    let scanned_files = groups.len();
+    let mut hash_groups = groups.into_groups();
+    let originals = Originals::load(originals_file(cli))?;
+    originals.add_to(&mut hash_groups);
+    let unique = cli.scan.report_unique.then(|| find_unique(&hash_groups));
+    let mut duplicates = match cli.scan.memory_budget {
+        Some(budget) => find_duplicates_spilling(hash_groups, budget, &std::env::temp_dir())?,
+        None => find_duplicates(hash_groups),
+    };
+    if let Some([a, b]) = between.as_deref() {
+        retain_between(&mut duplicates, a, b);
+    }
+    if !dedupe_within.is_empty() {
+        retain_within(&mut duplicates, &dedupe_within, "--dedupe-within");
+    }
+    if !drill_into.is_empty() {
+        retain_within(&mut duplicates, &drill_into, "--drill-into");
+    }
+    if cli.scan.within_dir_only {
+        retain_siblings(&mut duplicates);
+    }
+    retain_matching(&mut duplicates, cli.scan.match_mode);
+    Acknowledged::load(acknowledged_file(cli))?
+        .retain_unacknowledged(&mut duplicates, &cli.scan.ignore_group);
+    let picked_groups = [cli.scan.groups.as_slice(), &cli.scan.delete_group].concat();
+    if !picked_groups.is_empty() {
+        retain_groups(&mut duplicates, &picked_groups);
+    }
+    if cli.scan.detect_reflinks {
+        report_options.shared_extents = read_extent_ids(&duplicates);
+    }
+    let group_time = group_start.elapsed();
+    let suggester = if config.suggestion_signals.is_empty() {
+        Suggester::default()
+    } else {
+        Suggester::new(
+            config
+                .suggestion_signals
+                .iter()
+                .map(|signal| signal.parse())
+                .collect::<Result<_, _>>()
+                .map_err(anyhow::Error::msg)?,
+        )
+    };
+    // Also used to point out the copy of each group that would be kept
+    let policy = match cli.scan.keep_policy(matches) {
+        Some(policy) => policy,
+        None => KeepPolicy::new(
+            config
+                .prefer
+                .iter()
+                .map(|rule| rule.parse())
+                .collect::<Result<_, _>>()
+                .map_err(anyhow::Error::msg)?,
+        ),
+    }
+    .with_protected(protected_patterns(cli, config)?)
+    .with_priority_dirs(config.priority_dirs.clone())
+    .with_suggestions(cli.scan.accept_suggestions.then(|| suggester.clone()))
+    .with_originals(originals.paths())
+    .with_removable_within(dedupe_within)
+    .with_hardlinks_counted_once(cli.scan.count_hardlinks_once)
+    .with_within_dir_only(cli.scan.within_dir_only)
+    .with_match_mode(cli.scan.match_mode)
+    .with_streams(
+        cli.scan
+            .check_streams
+            .then(|| read_stream_digests(&duplicates)),
+    );
+    let report_start = Instant::now();
+    // Page the report, but not the output of actions, which may show progress bars
+    let pager = (cli.scan.output_format == OutputFormat::Text
+        && !cli.scan.no_pager
+        && !cli.scan.has_action())
+    .then(Pager::start)
+    .flatten();
+    if let Some(unique) = &unique {
+        write_unique(unique, cli, &root)?;
+    } else {
+        match cli.scan.output_format {
+            OutputFormat::Text => {
+                match cli.scan.group_by {
+                    Some(GroupBy::TopDir) => {
+                        let base = drill_into
+                            .first()
+                            .map_or(root.scan_path(), PathBuf::as_path);
+                        let folders =
+                            waste_by_top_dir(&duplicates, base, root.scan_path(), &policy);
+                        print_by_top_dir(&folders, base, cli.scan.top);
+                    }
+                    None => print_results(&duplicates, &root, &report_options, &policy),
+                }
+                print_breakdown(&DuplicateReport::new(
+                    &duplicates,
+                    root.scan_path(),
+                    &report_options,
+                ));
+            }
+            OutputFormat::Html => {
+                let report = DuplicateReport::new(&duplicates, root.scan_path(), &report_options)
+                    .with_errors(&errors);
+                match &cli.scan.output {
+                    Some(file) => {
+                        let output_dir = output_dir(file);
+                        let mut writer = BufWriter::new(fs::File::create(file)?);
+                        write_html(&report, &report_options, output_dir, &mut writer)?;
+                        info!("HTML report written to {}", file.display());
+                    }
+                    None => write_html(
+                        &report,
+                        &report_options,
+                        Path::new("."),
+                        &mut std::io::stdout().lock(),
+                    )?,
+                }
+            }
+            OutputFormat::Brief => {
+                let report = DuplicateReport::new(&duplicates, root.scan_path(), &report_options)
+                    .with_errors(&errors);
+                let mut writer: Box<dyn Write> = match &cli.scan.output {
+                    Some(file) => Box::new(BufWriter::new(fs::File::create(file)?)),
+                    None => Box::new(std::io::stdout().lock()),
+                };
+                write_brief(&report, scanned_files, report_options.top, &mut writer)?;
+            }
+            OutputFormat::Ndjson | OutputFormat::Fdupes | OutputFormat::Rmlint => {
+                let mut writer: Box<dyn Write> = match &cli.scan.output {
+                    Some(file) => Box::new(BufWriter::new(fs::File::create(file)?)),
+                    None => Box::new(std::io::stdout().lock()),
+                };
+                let written = match cli.scan.output_format {
+                    OutputFormat::Fdupes if cli.scan.print0 => write_print0(
+                        &duplicates,
+                        &report_options,
+                        &cli.scan.group_separator,
+                        &mut writer,
+                    )?,
+                    OutputFormat::Fdupes => {
+                        write_fdupes(&duplicates, &report_options, &mut writer)?
+                    }
+                    OutputFormat::Rmlint => {
+                        write_rmlint(&duplicates, &report_options, &mut writer)?
+                    }
+                    _ => write_ndjson(&duplicates, &report_options, &mut writer)?,
+                };
+                info!("Wrote {} duplicate groups", Count(written as u64));
+            }
+        }
+    }
+
+    if let Some(groups) = metadata_duplicates {
+        print_metadata_duplicates(&groups, &root);
+    }
+    if let Some(groups) = same_names {
+        print_same_names(&groups, &root);
+    }
+    print_reappeared(&reappeared, &root);
+    if cli.scan.suggest || cli.scan.accept_suggestions {
+        print_suggestions(&duplicates, &suggester, &root, cli.scan.top);
+    }
+    if let Some(sizes) = sizes {
+        print_size_summary(&sizes, cli.scan.size_histogram, &root);
+    }
+    if let Some(pairs) = partial_duplicates {
+        print_partial_duplicates(&pairs, &root);
+    }
+    #[cfg(feature = "perceptual")]
+    if let Some(similar) = similar {
+        print_similar(&similar, &root);
+    }
+    #[cfg(feature = "audio")]
+    if let Some(same_recordings) = same_recordings {
+        print_same_recordings(&same_recordings, &root);
+    }
+    print_hash_stats(&hash_stats, !cli.scan.no_cache);
+    print_scan_errors(&errors, &root, cli.scan.skip_unreadable);
+    if empty_files > 0 {
+        info!(
+            "Found {} empty files, not reported as duplicates (see --ignore-empty)",
+            Count(empty_files as u64)
+        );
+    }
+    if cli.scan.reveal {
+        reveal_files(duplicates.values().flatten());
+    }
+    let report_time = report_start.elapsed();
 
-    let duplicates = find_duplicates(files);
-    print_results(&duplicates, &cli.path);
+    if interrupted
+        && (cli.scan.save_report.is_some()
+            || cli.scan.diff_against.is_some()
+            || cli.scan.emit_checksums.is_some())
+    {
+        warn!("Not saving or comparing reports, since the scan was interrupted");
+    } else if !interrupted {
+        if let Some(file) = &cli.scan.emit_checksums {
+            let mut writer = BufWriter::new(
+                fs::File::create(file)
+                    .with_context(|| format!("Failed to create {}", file.display()))?,
+            );
+            let written = write_checksums(&duplicates, &report_options, &mut writer)?;
+            info!(
+                "Wrote the checksums of {} duplicate groups to {}",
+                Count(written as u64),
+                file.display()
+            );
+        }
+        let report = DuplicateReport::new(&duplicates, root.scan_path(), &report_options)
+            .with_errors(&errors);
+        // Kept for `report --last`, to show the results again another way without scanning
+        if let Err(e) = report.save_last(&DuplicateReport::last_file_for_cache(&cache_file)) {
+            warn!("Failed to save the results for `report --last`: {:#}", e);
+        }
+        record_scan(
+            &history_file_for_cache(&cache_file),
+            &HistoryEntry::from_report(&report, scanned_files),
+        );
+        if let Some(previous_file) = &cli.scan.diff_against {
+            let previous = DuplicateReport::load(previous_file)?;
+            print_diff(&report.diff(&previous));
+        }
+        if let Some(report_file) = &cli.scan.save_report {
+            report.save(report_file)?;
+        }
+    }
+
+    let action = match &cli.scan.move_to {
+        Some(dir) => Some(Action::MoveTo(dir.clone())),
+        None if cli.scan.delete || !cli.scan.delete_group.is_empty() => Some(Action::Delete),
+        None if cli.scan.hardlink => Some(Action::Hardlink),
+        None if cli.scan.reflink => Some(Action::Reflink),
+        None => None,
+    };
+    if interrupted && (action.is_some() || cli.scan.write_manifest.is_some()) {
+        warn!("Not changing any files, since the scan was interrupted");
+    } else if action.is_some() || cli.scan.write_manifest.is_some() {
+        let action = action.unwrap_or(Action::Delete);
+        if let Some(file) = &cli.scan.write_manifest {
+            let manifest = Manifest::plan(&duplicates, root.scan_path(), &action, &policy)?;
+            manifest.save(file)?;
+            info!(
+                "Wrote a manifest of {} duplicate groups to {}, carry it out with `apply`",
+                Count(manifest.groups.len() as u64),
+                file.display()
+            );
+        } else if let Some(format) = cli.scan.emit_script {
+            let operations = plan_operations(&duplicates, root.scan_path(), &action, &policy)?;
+            write_script(&operations, format, &mut std::io::stdout().lock())?;
+        } else {
+            let journal_path = cli.scan.journal.clone().unwrap_or_else(|| {
+                let dir = match &action {
+                    Action::MoveTo(dir) => dir.clone(),
+                    _ => default_journal_dir(),
+                };
+                dir.join(journal_file_name())
+            });
+            let operations = plan_operations(&duplicates, root.scan_path(), &action, &policy)?;
+            print_plan(&operations, &action, root.scan_path());
+            if cli.scan.dry_run {
+                info!("Dry run, not changing any files");
+            } else if cli.scan.confirm && !confirm("Proceed?")? {
+                info!("Not changing any files");
+            } else if let Some(permission) = &permission {
+                let journal = apply_planned(
+                    &duplicates,
+                    &action,
+                    operations,
+                    &ActionOptions {
+                        verify_hash: cli.scan.verify_hash,
+                        use_trash: cli.scan.use_trash,
+                        metadata: cli.scan.metadata_policy,
+                    },
+                    &journal_path,
+                    permission,
+                )?;
+                record_deletions(&HashCache::tombstones_file(&cache_file), &journal);
+            }
+        }
+    }
 
-    // Final cache save (only if caching is enabled)
-    if !cli.no_cache {
-        if let Err(e) = global_cache.save() {
-            error!("Failed to save hash cache on exit: {}", e);
+    if let Some(template) = &cli.scan.exec {
+        if interrupted {
+            warn!("Not running the --exec command, since the scan was interrupted");
+        } else {
+            run_for_groups(&duplicates, root.scan_path(), template, &policy)?;
         }
     }
 
+    // Final cache save (only if caching is enabled), or waiting for the background one
+    let cache_save_start = Instant::now();
+    let saved = match background_save {
+        Some(thread) => thread.join().map_or_else(
+            |_| Err(anyhow::anyhow!("the saving thread panicked")),
+            |saved| saved.map_err(Into::into),
+        ),
+        None if !cli.scan.no_cache => global_cache.save().map_err(Into::into),
+        None => Ok(()),
+    };
+    if let Err(e) = saved {
+        error!("Failed to save hash cache on exit: {:#}", e);
+    }
+    if let Some(format) = cli.scan.stats {
+        let stats = RunStats {
+            cache_load: cache_load_time,
+            hashes: hash_stats,
+            group: group_time,
+            report: report_time,
+            cache_save: cache_save_start.elapsed(),
+        };
+        print_stats(&stats, format);
+    }
+    drop(pager);
+    if !notifiers.is_empty() {
+        let report = DuplicateReport::new(&duplicates, root.scan_path(), &report_options);
+        notify_all(
+            notifiers,
+            &ScanSummary::from_report(&report, interrupted, start_time.elapsed()),
+        );
+    }
+
+    if interrupted {
+        warn!(
+            duration = start_time.elapsed().as_secs_f64();
+            "Scan interrupted after {}, results are partial",
+            HumanDuration(start_time.elapsed())
+        );
+        std::process::exit(130); // STATUS_CONTROL_C_EXIT
+    }
+    if cli.scan.fail_on_unreadable && !errors.permission_denied.is_empty() {
+        anyhow::bail!(
+            "Could not read {} paths, so the scan is incomplete (--fail-on-unreadable)",
+            Count(errors.permission_denied.len() as u64)
+        );
+    }
     info!(
+        duration = start_time.elapsed().as_secs_f64();
         "Program completed successfully in {}",
         HumanDuration(start_time.elapsed())
     );
 
     Ok(())
 }
+
+/// Writes the files without copies found for --report-unique in the chosen output format.
+fn write_unique(unique: &[FileInfo], cli: &Cli, root: &ScanRoot) -> Result<()> {
+    if cli.scan.output_format == OutputFormat::Text {
+        print_unique(unique, root);
+        return Ok(());
+    }
+    let (mut writer, output_dir): (Box<dyn Write>, &Path) = match &cli.scan.output {
+        Some(file) => (
+            Box::new(BufWriter::new(fs::File::create(file)?)),
+            output_dir(file),
+        ),
+        None => (Box::new(std::io::stdout().lock()), Path::new(".")),
+    };
+    let written = match cli.scan.output_format {
+        OutputFormat::Html => {
+            write_unique_html(unique, root.scan_path(), output_dir, &mut writer)?;
+            unique.len()
+        }
+        OutputFormat::Fdupes | OutputFormat::Brief => {
+            write_unique_paths(unique, cli.scan.print0, &mut writer)?
+        }
+        OutputFormat::Rmlint => write_rmlint_unique(unique, &mut writer)?,
+        _ => write_unique_ndjson(unique, &mut writer)?,
+    };
+    info!("Wrote {} files without copies", Count(written as u64));
+    Ok(())
+}
+
+/// Runs a `cache` subcommand against the cache for the configured base path.
+fn run_cache_command(action: &CacheCommand, cli: &Cli, config: &Config) -> Result<()> {
+    if let CacheCommand::Merge { inputs, output } = action {
+        return merge_caches(cli, inputs, output);
+    }
+    let root = ScanRoot::for_base(Path::new(&config.base_path))
+        .with_case_insensitive_paths(cli.case_insensitive_paths);
+    let cache = HashCache::load(resolve_cache_file(cli, &root)?)
+        .with_base(&root)
+        .with_limits(cli.scan.cache_limits())
+        .with_format(cli.scan.cache_format)
+        .with_compression(cli.scan.cache_compression())
+        .with_mtime_policy(cli.scan.mtime_policy())
+        .with_lock_wait(!cli.scan.no_cache_wait);
+
+    match action {
+        CacheCommand::Stats => {
+            let stats = cache.stats();
+            println!("Cache file:  {}", cache.cache_file.display());
+            println!("Entries:     {}", Count(stats.entries as u64));
+            println!("Total size:  {}", Bytes(stats.total_size));
+            if let Some((path, mtime)) = &stats.oldest {
+                println!("Oldest:      {} ({})", format_timestamp(*mtime), path);
+            }
+            if let Some((path, mtime)) = &stats.newest {
+                println!("Newest:      {} ({})", format_timestamp(*mtime), path);
+            }
+        }
+        CacheCommand::List => {
+            for entry in cache.entries() {
+                println!(
+                    "{}  {:>12}  {}  {}",
+                    entry.hash,
+                    entry.size,
+                    format_timestamp(entry.mtime),
+                    entry.path
+                );
+            }
+        }
+        CacheCommand::Get { path } => match cache.get_entry(path, &root) {
+            Some(entry) => {
+                let valid = cache.get_hash(path, &root).ok().flatten().is_some();
+                println!("Path:     {}", entry.path);
+                println!("Hash:     {}", entry.hash);
+                println!("Size:     {} ({})", Bytes(entry.size), entry.size);
+                println!("Modified: {}", format_timestamp(entry.mtime));
+                println!("Seen:     {}", format_timestamp(entry.last_seen));
+                if valid {
+                    println!("Status:   {}", "valid".green());
+                } else {
+                    println!(
+                        "Status:   {}",
+                        "stale (file changed or missing, will be rehashed)".yellow()
+                    );
+                }
+            }
+            None => println!("No cache entry for {}", root.key(path)),
+        },
+        CacheCommand::Remove { path } => {
+            if cache.remove(path, &root) {
+                cache.save()?;
+                println!("Removed cache entry for {}", root.key(path));
+            } else {
+                println!("No cache entry for {}", root.key(path));
+            }
+        }
+        CacheCommand::Export { format, output } => match output {
+            Some(output) => {
+                let mut writer = BufWriter::new(fs::File::create(output)?);
+                export_cache(&cache, &root, *format, &mut writer)?;
+            }
+            None => {
+                let mut writer = std::io::stdout().lock();
+                export_cache(&cache, &root, *format, &mut writer)?;
+            }
+        },
+        CacheCommand::Import { file } => {
+            if import_manifest(&cache, &root, file)? > 0 {
+                cache.save()?;
+            }
+        }
+        CacheCommand::Merge { .. } => unreachable!("handled above"),
+    }
+    Ok(())
+}
+
+/// Merges the cache files `inputs` into `output`, as `cache merge`.
+fn merge_caches(cli: &Cli, inputs: &[PathBuf], output: &Path) -> Result<()> {
+    let cache = HashCache::load(output.to_path_buf())
+        .with_limits(cli.scan.cache_limits())
+        .with_format(cli.scan.cache_format)
+        .with_compression(cli.scan.cache_compression())
+        .with_mtime_policy(cli.scan.mtime_policy())
+        .with_lock_wait(!cli.scan.no_cache_wait);
+    for input in inputs {
+        let merged = cache.merge_file(input)?;
+        info!(
+            "Merged {} entries from {}",
+            Count(merged as u64),
+            input.display()
+        );
+    }
+    cache.save()?;
+    println!(
+        "Wrote {} cache entries to {}",
+        Count(cache.len() as u64),
+        output.display()
+    );
+    Ok(())
+}
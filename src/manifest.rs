@@ -0,0 +1,428 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+use crate::FileInfo;
+use crate::actions::{
+    Action, ActionOptions, ActionPermission, Journal, Operation, absolute, actionable_groups,
+    execute_operations, plan_operations,
+};
+use crate::content_hash::ContentHash;
+use crate::scanner::hash_file;
+use crate::selection::KeepPolicy;
+use crate::units::Count;
+
+/// Version of the manifest format written by this build.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// What `apply` does with a file listed in a [`Manifest`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileAction {
+    /// Leave the file in place. The first kept file of a group is the copy deleted files
+    /// can be recreated from and hardlinks and reflinks point to.
+    Keep,
+    /// Delete the file.
+    Delete,
+    /// Replace the file with a hardlink to the kept copy.
+    Hardlink,
+    /// Replace the file with a reflink of the kept copy.
+    Reflink,
+    /// Move the file to the path given in `to`.
+    Move,
+    /// Leave the file in place, e.g. a hardlink of the kept copy.
+    Skip,
+}
+
+impl FileAction {
+    /// Returns `true` if the action changes the file.
+    fn changes_file(self) -> bool {
+        matches!(
+            self,
+            Self::Delete | Self::Hardlink | Self::Reflink | Self::Move
+        )
+    }
+}
+
+/// A member of a duplicate group in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    #[serde(with = "crate::paths::serde_path")]
+    pub path: PathBuf,
+    pub action: FileAction,
+    /// Destination of a file to move.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "crate::paths::serde_path::option"
+    )]
+    pub to: Option<PathBuf>,
+}
+
+/// A duplicate group in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestGroup {
+    /// Hash every file of the group must still have for `apply` to act on it.
+    pub hash: String,
+    pub size: u64,
+    pub files: Vec<ManifestFile>,
+}
+
+impl ManifestGroup {
+    /// Returns the first file marked to keep.
+    fn keeper(&self) -> Option<&ManifestFile> {
+        self.files
+            .iter()
+            .find(|file| file.action == FileAction::Keep)
+    }
+}
+
+/// Proposed changes to the duplicates found by a scan, written as JSON by
+/// `--write-manifest` so they can be reviewed and edited by hand before `apply` carries
+/// them out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Manifest format version.
+    pub version: u32,
+    /// RFC 3339 timestamp of when the manifest was written.
+    pub created_at: String,
+    /// Directory that was scanned.
+    #[serde(with = "crate::paths::serde_path")]
+    pub scan_path: PathBuf,
+    pub groups: Vec<ManifestGroup>,
+}
+
+impl Manifest {
+    /// Proposes applying `action` to every duplicate, marking the copy of each group
+    /// chosen by `policy` to keep. Members that [`plan_operations`] leaves alone, such as
+    /// hardlinks of the kept copy, are marked to skip, and protected files to keep.
+    /// Paths are made absolute.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if [`plan_operations`] does.
+    pub fn plan(
+        duplicates: &HashMap<ContentHash, Vec<FileInfo>>,
+        scan_path: &Path,
+        action: &Action,
+        policy: &KeepPolicy,
+    ) -> Result<Self> {
+        let operations = plan_operations(duplicates, scan_path, action, policy)?;
+        let planned: HashMap<&Path, &Operation> = operations
+            .iter()
+            .map(|(operation, _)| (operation.path(), operation))
+            .collect();
+
+        let mut groups = Vec::new();
+        for (hash, group) in actionable_groups(duplicates, policy) {
+            let keeper = &group[policy.select(&group, scan_path)];
+            let mut others: Vec<ManifestFile> = group
+                .iter()
+                .filter(|file| file.path != keeper.path)
+                .map(|file| {
+                    let path = absolute(&file.path.to_path_buf());
+                    let (action, to) = match planned.get(path.as_path()) {
+                        Some(Operation::Move { to, .. }) => (FileAction::Move, Some(to.clone())),
+                        Some(Operation::Hardlink { .. }) => (FileAction::Hardlink, None),
+                        Some(Operation::Reflink { .. }) => (FileAction::Reflink, None),
+                        Some(Operation::Delete { .. }) => (FileAction::Delete, None),
+                        None if policy.is_protected(&file.path.to_path_buf(), scan_path) => {
+                            (FileAction::Keep, None)
+                        }
+                        None => (FileAction::Skip, None),
+                    };
+                    ManifestFile { path, action, to }
+                })
+                .collect();
+            others.sort_by(|a, b| a.path.cmp(&b.path));
+
+            let mut files = vec![ManifestFile {
+                path: absolute(&keeper.path.to_path_buf()),
+                action: FileAction::Keep,
+                to: None,
+            }];
+            files.extend(others);
+            groups.push(ManifestGroup {
+                hash: hash.to_string(),
+                size: keeper.size,
+                files,
+            });
+        }
+
+        Ok(Self {
+            version: MANIFEST_VERSION,
+            created_at: OffsetDateTime::now_local()
+                .unwrap_or_else(|_| OffsetDateTime::now_utc())
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            scan_path: absolute(scan_path),
+            groups,
+        })
+    }
+
+    /// Writes the manifest to `path` as pretty-printed JSON.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if serialization fails or the file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_vec_pretty(self)?;
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write manifest to {}", path.display()))
+    }
+
+    /// Loads a manifest previously written by [`Manifest::save`], possibly edited since.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, is not a valid manifest, or fails
+    /// [`Manifest::validate`].
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read(path)
+            .with_context(|| format!("Failed to read manifest from {}", path.display()))?;
+        let manifest: Self = serde_json::from_slice(&content)
+            .with_context(|| format!("Failed to parse manifest {}", path.display()))?;
+        manifest
+            .validate()
+            .with_context(|| format!("Invalid manifest {}", path.display()))?;
+        Ok(manifest)
+    }
+
+    /// Checks that the manifest can be carried out: every group that changes files keeps
+    /// at least one, every file to move has a destination, and no path is listed twice.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first problem found.
+    pub fn validate(&self) -> Result<()> {
+        if self.version != MANIFEST_VERSION {
+            bail!(
+                "unsupported manifest version {} (expected {})",
+                self.version,
+                MANIFEST_VERSION
+            );
+        }
+        let mut seen = HashSet::new();
+        for group in &self.groups {
+            for file in &group.files {
+                if !seen.insert(&file.path) {
+                    bail!("'{}' is listed more than once", file.path.display());
+                }
+                if file.action == FileAction::Move && file.to.is_none() {
+                    bail!(
+                        "'{}' is marked to move, but has no destination in \"to\"",
+                        file.path.display()
+                    );
+                }
+            }
+            if group.keeper().is_none() && group.files.iter().any(|file| file.action.changes_file())
+            {
+                bail!("group {} changes files but keeps none", group.hash);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the manifest doesn't change any file protected by `policy`, matching
+    /// paths relative to the manifest's scanned directory, e.g. after hand edits.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first protected file that would be changed.
+    pub fn check_protected(&self, policy: &KeepPolicy) -> Result<()> {
+        for file in self.groups.iter().flat_map(|group| &group.files) {
+            if file.action.changes_file() && policy.is_protected(&file.path, &self.scan_path) {
+                bail!(
+                    "'{}' is protected, but is marked to {}",
+                    file.path.display(),
+                    format!("{:?}", file.action).to_lowercase()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns why `path` no longer has the contents hashed to `hash`, if it doesn't.
+fn changed(path: &Path, hash: &str) -> Option<String> {
+    match hash_file(path) {
+        Ok(current) if current.to_string() == hash => None,
+        Ok(_) => Some("its contents have changed".to_string()),
+        Err(e) => Some(format!("{:#}", e)),
+    }
+}
+
+/// Carries out `manifest`, recording every change in a journal written to
+/// `journal_path` that [`undo`](crate::actions::undo) can use to revert it.
+///
+/// Every file is hashed again first, and files whose contents no longer match the
+/// manifest are skipped, as are whole groups whose kept copy changed, so nothing is
+/// removed unless an identical copy remains. Files are not moved over existing files.
+/// Otherwise files are changed as described by `options`.
+///
+/// # Errors
+///
+/// Returns an error if the manifest is invalid or the journal cannot be written.
+/// Failures of individual file operations are logged and skipped.
+pub fn apply_manifest(
+    manifest: &Manifest,
+    options: &ActionOptions,
+    journal_path: &Path,
+    permission: &ActionPermission,
+) -> Result<Journal> {
+    manifest.validate()?;
+
+    let mut operations = Vec::new();
+    let mut skipped = 0;
+    for group in &manifest.groups {
+        let changes: Vec<&ManifestFile> = group
+            .files
+            .iter()
+            .filter(|file| file.action.changes_file())
+            .collect();
+        let Some(keeper) = group.keeper() else {
+            continue;
+        };
+        if changes.is_empty() {
+            continue;
+        }
+        if let Some(reason) = changed(&keeper.path, &group.hash) {
+            warn!(
+                "Skipping group {}: kept copy '{}' can't be verified: {}",
+                group.hash,
+                keeper.path.display(),
+                reason
+            );
+            skipped += changes.len();
+            continue;
+        }
+
+        for file in changes {
+            if let Some(reason) = changed(&file.path, &group.hash) {
+                warn!("Skipping '{}': {}", file.path.display(), reason);
+                skipped += 1;
+                continue;
+            }
+            let operation = match (file.action, &file.to) {
+                (FileAction::Move, Some(to)) if to.exists() => {
+                    warn!(
+                        "Not moving '{}': '{}' already exists",
+                        file.path.display(),
+                        to.display()
+                    );
+                    skipped += 1;
+                    continue;
+                }
+                (FileAction::Move, Some(to)) => Operation::Move {
+                    from: file.path.clone(),
+                    to: to.clone(),
+                    hash: group.hash.clone(),
+                },
+                (FileAction::Hardlink, _) => Operation::Hardlink {
+                    path: file.path.clone(),
+                    target: keeper.path.clone(),
+                    hash: group.hash.clone(),
+                    attributes: None,
+                },
+                (FileAction::Reflink, _) => Operation::Reflink {
+                    path: file.path.clone(),
+                    target: keeper.path.clone(),
+                    hash: group.hash.clone(),
+                    attributes: None,
+                },
+                _ => Operation::Delete {
+                    path: file.path.clone(),
+                    kept: keeper.path.clone(),
+                    hash: group.hash.clone(),
+                    trashed: None,
+                    attributes: None,
+                },
+            };
+            operations.push((operation, group.size));
+        }
+    }
+
+    if skipped > 0 {
+        warn!(
+            "Skipped {} files that no longer match the manifest",
+            Count(skipped as u64)
+        );
+    }
+    info!(
+        "Applying {} changes from the manifest",
+        Count(operations.len() as u64)
+    );
+    // Every file was hashed again above
+    execute_operations(operations, journal_path, options, &|_| None, permission)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selection::path_glob;
+
+    fn file(path: &Path, contents: &str) -> FileInfo {
+        fs::write(path, contents).unwrap();
+        FileInfo::for_test(path, contents.len() as u64, hash_file(path).unwrap())
+    }
+
+    #[test]
+    fn applies_edited_manifest_after_rechecking_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let group: Vec<FileInfo> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| file(&root.join(name), "same"))
+            .collect();
+        let duplicates = HashMap::from([(group[0].hash, group)]);
+
+        let mut manifest =
+            Manifest::plan(&duplicates, &root, &Action::Delete, &KeepPolicy::default()).unwrap();
+        let actions: Vec<FileAction> = manifest.groups[0]
+            .files
+            .iter()
+            .map(|file| file.action)
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                FileAction::Keep,
+                FileAction::Delete,
+                FileAction::Delete,
+                FileAction::Delete
+            ]
+        );
+
+        // Edit by hand: keep b as well, and change d after the manifest was written
+        manifest.groups[0].files[1].action = FileAction::Keep;
+        fs::write(root.join("d"), "different").unwrap();
+        let manifest_file = root.join("manifest.json");
+        manifest.save(&manifest_file).unwrap();
+        let journal = apply_manifest(
+            &Manifest::load(&manifest_file).unwrap(),
+            &ActionOptions::default(),
+            &root.join("j"),
+            &ActionPermission::allow_changes(),
+        )
+        .unwrap();
+
+        assert_eq!(journal.operations.len(), 1);
+        assert!(root.join("a").exists() && root.join("b").exists());
+        assert!(!root.join("c").exists());
+        assert!(root.join("d").exists());
+
+        manifest.groups[0].files[0].action = FileAction::Skip;
+        manifest.groups[0].files[1].action = FileAction::Delete;
+        assert!(manifest.validate().is_err());
+
+        let protect_b = KeepPolicy::default().with_protected(vec![path_glob("b").unwrap()]);
+        assert!(manifest.check_protected(&protect_b).is_err());
+        assert!(manifest.check_protected(&KeepPolicy::default()).is_ok());
+    }
+}
@@ -0,0 +1,268 @@
+//! `merge-dirs`: merging one directory into another, such as a backup folder into the
+//! folder it was copied from. Each file is compared with the file at the same path in
+//! the directory merged into: identical files are deleted, files missing there are moved
+//! there, and files that differ are reported and left in place. Both directories are
+//! canonicalized first, which gives extended-length paths on Windows, so deep trees
+//! merge fully. Every change is recorded in a journal for `undo`, like the actions.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use walkdir::WalkDir;
+
+use crate::actions::{ActionOptions, ActionPermission, Journal, Operation, execute_operations};
+use crate::scanner::hash_file;
+use crate::units::{Bytes, Count};
+
+/// A file in both directories with different contents, left in place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// The file in the directory being merged.
+    pub from: PathBuf,
+    /// The file at the same path in the directory merged into.
+    pub into: PathBuf,
+}
+
+/// What merging one directory into another would do, from [`plan_merge`].
+#[derive(Debug, Default)]
+pub struct MergePlan {
+    /// The directory merged into, canonicalized.
+    pub into: PathBuf,
+    /// The directory being merged, canonicalized.
+    pub from: PathBuf,
+    /// Deletions of identical files and moves of missing ones, each with the size of the
+    /// file, grouped by hash.
+    pub operations: Vec<(Operation, u64)>,
+    /// Files that differ from the file at the same path, left in place.
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Works out how to merge the directory `from` into `into`, hashing the files of `from`
+/// and those they are compared with, without changing anything.
+///
+/// # Errors
+///
+/// Returns an error if either directory can't be read, or if one is inside the other.
+pub fn plan_merge(into: &Path, from: &Path) -> Result<MergePlan> {
+    let canonical = |dir: &Path| {
+        dir.canonicalize()
+            .with_context(|| format!("Failed to read directory {}", dir.display()))
+    };
+    let (into, from) = (canonical(into)?, canonical(from)?);
+    if into.starts_with(&from) || from.starts_with(&into) {
+        anyhow::bail!(
+            "Can't merge {} into {}, since one contains the other",
+            from.display(),
+            into.display()
+        );
+    }
+
+    let mut plan = MergePlan {
+        into: into.clone(),
+        from: from.clone(),
+        ..MergePlan::default()
+    };
+    for entry in WalkDir::new(&from).sort_by_file_name() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("Failed to read '{}': {}", from.display(), e);
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            if !entry.file_type().is_dir() {
+                debug!("Leaving '{}' in place", entry.path().display());
+            }
+            continue;
+        }
+        let path = entry.path().to_path_buf();
+        let target = into.join(path.strip_prefix(&from)?);
+        let hash = match hash_file(&path) {
+            Ok(hash) => hash,
+            Err(e) => {
+                warn!("Leaving '{}' in place: {:#}", path.display(), e);
+                continue;
+            }
+        };
+        let size = entry.metadata().map_or(0, |metadata| metadata.len());
+        let operation = match fs::symlink_metadata(&target) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Operation::Move {
+                from: path,
+                to: target,
+                hash: hash.to_string(),
+            },
+            Ok(metadata)
+                if metadata.is_file()
+                    && metadata.len() == size
+                    && hash_file(&target).is_ok_and(|target_hash| target_hash == hash) =>
+            {
+                Operation::Delete {
+                    path,
+                    kept: target,
+                    hash: hash.to_string(),
+                    trashed: None,
+                    attributes: None,
+                }
+            }
+            _ => {
+                plan.conflicts.push(Conflict {
+                    from: path,
+                    into: target,
+                });
+                continue;
+            }
+        };
+        plan.operations.push((operation, size));
+    }
+    // Operations are carried out group by group
+    plan.operations
+        .sort_by(|a, b| (a.0.hash(), a.0.path()).cmp(&(b.0.hash(), b.0.path())));
+    Ok(plan)
+}
+
+impl MergePlan {
+    /// Logs what the merge would do, and each conflict.
+    pub fn print(&self) {
+        let (mut moved, mut moved_bytes, mut deleted, mut deleted_bytes) = (0, 0, 0, 0);
+        for (operation, size) in &self.operations {
+            if let Operation::Move { .. } = operation {
+                moved += 1;
+                moved_bytes += size;
+            } else {
+                deleted += 1;
+                deleted_bytes += size;
+            }
+        }
+        info!(
+            "Plan: merge {} into {}: move {} files ({}), delete {} identical files ({})",
+            self.from.display(),
+            self.into.display(),
+            Count(moved),
+            Bytes(moved_bytes),
+            Count(deleted),
+            Bytes(deleted_bytes)
+        );
+        for conflict in &self.conflicts {
+            warn!(
+                "Conflict: '{}' differs from '{}', leaving it in place",
+                conflict.from.display(),
+                conflict.into.display()
+            );
+        }
+        if !self.conflicts.is_empty() {
+            warn!(
+                "{} files differ from the files at the same path, and are left in place",
+                Count(self.conflicts.len() as u64)
+            );
+        }
+    }
+}
+
+/// Carries out `plan`, recording every change in a journal written to `journal_path`,
+/// then removes the directories of the merged directory left empty, and the directory
+/// itself if it is. Files are not moved over files that appeared since the plan was made.
+///
+/// # Errors
+///
+/// Returns an error if the journal cannot be written. Failures of individual file
+/// operations are logged and skipped.
+pub fn apply_merge(
+    plan: MergePlan,
+    options: &ActionOptions,
+    journal_path: &Path,
+    permission: &ActionPermission,
+) -> Result<Journal> {
+    let mut groups: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for (operation, _) in &plan.operations {
+        if let Operation::Move { to, hash, .. } = operation {
+            groups.entry(hash.clone()).or_default().push(to.clone());
+        }
+    }
+    let verify = |operation: &Operation| {
+        groups
+            .get(operation.hash())?
+            .iter()
+            .find(|to| to.exists())
+            .map(|to| format!("'{}' appeared since the plan was made", to.display()))
+    };
+    let journal = execute_operations(plan.operations, journal_path, options, &verify, permission)?;
+
+    // Deepest first, so parents are empty by the time they are reached
+    let dirs: Vec<PathBuf> = WalkDir::new(&plan.from)
+        .contents_first(true)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_dir())
+        .map(|entry| entry.into_path())
+        .collect();
+    let removed = dirs
+        .iter()
+        .filter(|dir| fs::remove_dir(dir).is_ok())
+        .count();
+    if removed > 0 {
+        info!(
+            "Removed {} empty directories from {}",
+            Count(removed as u64),
+            plan.from.display()
+        );
+    }
+    Ok(journal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::undo;
+
+    #[test]
+    fn merges_a_copy_and_undoes_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let (into, from) = (dir.path().join("photos"), dir.path().join("backup"));
+        for (path, content) in [
+            ("photos/same.jpg", "same"),
+            ("photos/album/changed.jpg", "edited"),
+            ("backup/same.jpg", "same"),
+            ("backup/album/changed.jpg", "original"),
+            ("backup/album/only-here.jpg", "only here"),
+            ("backup/old/same-too.jpg", "same"),
+        ] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        let plan = plan_merge(&into, &from).unwrap();
+        assert_eq!(plan.operations.len(), 3);
+        assert_eq!(
+            plan.conflicts,
+            [Conflict {
+                from: plan.from.join("album/changed.jpg"),
+                into: plan.into.join("album/changed.jpg"),
+            }]
+        );
+        let journal_path = dir.path().join("journal.json");
+        let permission = ActionPermission::allow_changes();
+        let journal =
+            apply_merge(plan, &ActionOptions::default(), &journal_path, &permission).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(into.join("album/only-here.jpg")).unwrap(),
+            "only here"
+        );
+        assert!(!from.join("same.jpg").exists());
+        assert!(into.join("old/same-too.jpg").exists());
+        assert!(!from.join("old").exists());
+        assert!(from.join("album/changed.jpg").exists());
+
+        assert_eq!(undo(&journal, &permission).failed, 0);
+        assert!(!into.join("album/only-here.jpg").exists());
+        assert_eq!(
+            fs::read_to_string(from.join("old/same-too.jpg")).unwrap(),
+            "same"
+        );
+    }
+}
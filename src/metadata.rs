@@ -0,0 +1,367 @@
+//! Hashing files without their metadata, so copies that only differ in tags are still
+//! found: `--ignore-image-metadata` for JPEG and PNG images, `--ignore-audio-tags` for
+//! MP3 files. Each format is a [`PayloadFormat`], which tells the regions of a file
+//! holding its contents from those holding metadata; more formats can be added the same
+//! way, each behind its own flag.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use log::{debug, info, warn};
+use rayon::prelude::*;
+
+use crate::FileInfo;
+use crate::content_hash::ContentHash;
+use crate::scan_root::ScanRoot;
+use crate::units::{Bytes, Count};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// PNG chunks holding metadata rather than pixels: text, EXIF and modification time.
+const PNG_METADATA_CHUNKS: &[&[u8; 4]] = &[b"tEXt", b"zTXt", b"iTXt", b"eXIf", b"tIME"];
+
+/// A file format whose contents can be hashed apart from its metadata.
+pub trait PayloadFormat: Sync {
+    /// Returns `true` if `path` has an extension of the format.
+    fn is_supported(&self, path: &Path) -> bool;
+
+    /// Feeds the contents of the file in `data` to `hasher`, leaving out its metadata.
+    /// Returns `false` if `data` isn't in the format after all.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `data` is malformed.
+    fn hash_payload(&self, data: &[u8], hasher: &mut blake3::Hasher) -> Result<bool>;
+}
+
+/// Returns `true` if the extension of `path` is one of `extensions`, ignoring case.
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// JPEG and PNG images, without EXIF, XMP, IPTC, comments and PNG text chunks.
+pub struct Images;
+
+impl PayloadFormat for Images {
+    fn is_supported(&self, path: &Path) -> bool {
+        has_extension(path, &["jpg", "jpeg", "png"])
+    }
+
+    fn hash_payload(&self, data: &[u8], hasher: &mut blake3::Hasher) -> Result<bool> {
+        if data.starts_with(&[0xFF, 0xD8]) {
+            hasher.update(b"jpeg");
+            hash_jpeg(data, hasher)?;
+        } else if data.starts_with(PNG_SIGNATURE) {
+            hasher.update(b"png");
+            hash_png(data, hasher)?;
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+/// MP3 files, without their ID3v2 tags at the start and ID3v1 tag at the end, so
+/// retagged copies of a track match.
+pub struct Mp3Audio;
+
+impl PayloadFormat for Mp3Audio {
+    fn is_supported(&self, path: &Path) -> bool {
+        has_extension(path, &["mp3"])
+    }
+
+    fn hash_payload(&self, data: &[u8], hasher: &mut blake3::Hasher) -> Result<bool> {
+        let (start, end) = mp3_audio_range(data)?;
+        // MPEG audio frames start with 11 set sync bits
+        if start + 2 > end || data[start] != 0xFF || data[start + 1] & 0xE0 != 0xE0 {
+            return Ok(false);
+        }
+        hasher.update(b"mp3");
+        hasher.update(&data[start..end]);
+        Ok(true)
+    }
+}
+
+/// Returns the range of `data` between the ID3v2 tags at the start and the ID3v1 tag
+/// (possibly with an enhanced `TAG+` block before it) at the end.
+fn mp3_audio_range(data: &[u8]) -> Result<(usize, usize)> {
+    let mut start = 0;
+    // Tags may be written one after another
+    while data[start..].starts_with(b"ID3") {
+        let header = data
+            .get(start..start + 10)
+            .ok_or_else(|| anyhow::anyhow!("truncated ID3v2 header at offset {}", start))?;
+        // Sizes are "synchsafe": 7 bits per byte
+        let size = header[6..10]
+            .iter()
+            .fold(0usize, |size, byte| size << 7 | usize::from(byte & 0x7F));
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        start += 10 + size + footer;
+        if start > data.len() {
+            anyhow::bail!("truncated ID3v2 tag");
+        }
+    }
+    let mut end = data.len();
+    if end >= start + 128 && data[end - 128..].starts_with(b"TAG") {
+        end -= 128;
+        if end >= start + 227 && data[end - 227..].starts_with(b"TAG+") {
+            end -= 227;
+        }
+    }
+    Ok((start, end))
+}
+
+/// Returns `true` if `path` has the extension of one of `formats`.
+pub fn is_supported(path: &Path, formats: &[&dyn PayloadFormat]) -> bool {
+    formats.iter().any(|format| format.is_supported(path))
+}
+
+/// Feeds the JPEG in `data` to `hasher`, leaving out the APP1 (EXIF, XMP), APP13 (IPTC)
+/// and comment segments. Everything from the first start-of-scan marker on is image
+/// data and is hashed whole.
+fn hash_jpeg(data: &[u8], hasher: &mut blake3::Hasher) -> Result<()> {
+    let mut pos = 2;
+    loop {
+        if pos + 4 > data.len() || data[pos] != 0xFF {
+            anyhow::bail!("malformed JPEG segment at offset {}", pos);
+        }
+        let marker = data[pos + 1];
+        if marker == 0xFF {
+            // Fill byte before a marker
+            pos += 1;
+            continue;
+        }
+        if marker == 0xDA {
+            hasher.update(&data[pos..]);
+            return Ok(());
+        }
+        let end = pos + 2 + usize::from(u16::from_be_bytes([data[pos + 2], data[pos + 3]]));
+        if end > data.len() {
+            anyhow::bail!("truncated JPEG segment at offset {}", pos);
+        }
+        if !matches!(marker, 0xE1 | 0xED | 0xFE) {
+            hasher.update(&data[pos..end]);
+        }
+        pos = end;
+    }
+}
+
+/// Feeds the PNG chunks in `data` to `hasher`, leaving out metadata chunks.
+fn hash_png(data: &[u8], hasher: &mut blake3::Hasher) -> Result<()> {
+    let mut pos = PNG_SIGNATURE.len();
+    while pos < data.len() {
+        if pos + 12 > data.len() {
+            anyhow::bail!("truncated PNG chunk at offset {}", pos);
+        }
+        let len = u32::from_be_bytes(data[pos..pos + 4].try_into()?) as usize;
+        let end = pos + 12 + len;
+        if end > data.len() {
+            anyhow::bail!("truncated PNG chunk at offset {}", pos);
+        }
+        let kind = &data[pos + 4..pos + 8];
+        if !PNG_METADATA_CHUNKS.iter().any(|chunk| kind == *chunk) {
+            // Type and data; the CRC follows from them
+            hasher.update(&data[pos + 4..end - 4]);
+        }
+        pos = end;
+    }
+    Ok(())
+}
+
+/// Returns the BLAKE3 hash of a file's contents without its metadata, so that two files
+/// differing only in metadata, such as EXIF or ID3 tags, get the same hash. The first of
+/// `formats` supporting the file's extension is used. Returns `None` if none does, or
+/// the file isn't in its format.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or is malformed.
+pub fn payload_hash(path: &Path, formats: &[&dyn PayloadFormat]) -> Result<Option<String>> {
+    let Some(format) = formats.iter().find(|format| format.is_supported(path)) else {
+        return Ok(None);
+    };
+    let data = fs::read(path)?;
+    let mut hasher = blake3::Hasher::new();
+    if !format.hash_payload(&data, &mut hasher)? {
+        return Ok(None);
+    }
+    Ok(Some(hasher.finalize().to_hex().to_string()))
+}
+
+/// Finds groups of files in one of `formats` whose contents are identical but whose
+/// metadata differs. Files with identical bytes are exact duplicates and are reported as
+/// such, so only groups with at least two different contents are returned. Members of
+/// each group are sorted by path.
+///
+/// Files that cannot be parsed are skipped.
+pub fn find_metadata_duplicates<'a>(
+    files: impl IntoIterator<Item = &'a FileInfo>,
+    formats: &[&dyn PayloadFormat],
+) -> Vec<Vec<FileInfo>> {
+    let supported: Vec<&FileInfo> = files
+        .into_iter()
+        .filter(|file| !file.in_archive && is_supported(&file.path.to_path_buf(), formats))
+        .collect();
+    info!(
+        "Hashing the contents of {} files without metadata...",
+        Count(supported.len() as u64)
+    );
+    let hashed: Vec<(&FileInfo, String)> = supported
+        .into_par_iter()
+        .filter_map(
+            |file| match payload_hash(&file.path.to_path_buf(), formats) {
+                Ok(hash) => hash.map(|hash| (file, hash)),
+                Err(e) => {
+                    debug!("Skipping '{}': {}", file.path.display(), e);
+                    None
+                }
+            },
+        )
+        .collect();
+
+    let mut by_payload: HashMap<String, Vec<&FileInfo>> = HashMap::new();
+    for (file, hash) in hashed {
+        by_payload.entry(hash).or_default().push(file);
+    }
+    let mut groups: Vec<Vec<FileInfo>> = by_payload
+        .into_values()
+        .filter(|group| {
+            let contents: HashSet<ContentHash> = group.iter().map(|file| file.hash).collect();
+            contents.len() > 1
+        })
+        .map(|group| {
+            let mut members: Vec<FileInfo> = group.into_iter().cloned().collect();
+            members.sort_by(|a, b| a.path.cmp(&b.path));
+            members
+        })
+        .collect();
+    groups.sort_by(|a, b| a[0].path.cmp(&b[0].path));
+    groups
+}
+
+/// Prints groups found by [`find_metadata_duplicates`], as a section separate from the
+/// exact duplicates.
+pub fn print_metadata_duplicates(groups: &[Vec<FileInfo>], root: &ScanRoot) {
+    if groups.is_empty() {
+        info!("No files differing only in metadata found");
+        return;
+    }
+    warn!(
+        "Found {} groups of files differing only in metadata",
+        Count(groups.len() as u64)
+    );
+    for group in groups {
+        warn!(
+            "Content-identical, metadata differs ({} files):",
+            group.len()
+        );
+        for file in group {
+            warn!(
+                "  {} ({})",
+                root.display_file(&file.path).display(),
+                Bytes(file.size)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jpeg(app1: &[u8], scan: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        // APP0 JFIF header, kept
+        data.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x05, b'J', b'F', b'X']);
+        data.extend_from_slice(&[0xFF, 0xE1]);
+        data.extend_from_slice(&(app1.len() as u16 + 2).to_be_bytes());
+        data.extend_from_slice(app1);
+        data.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02]);
+        data.extend_from_slice(scan);
+        data.extend_from_slice(&[0xFF, 0xD9]);
+        data
+    }
+
+    fn png(text: &[u8], pixels: &[u8]) -> Vec<u8> {
+        let mut data = PNG_SIGNATURE.to_vec();
+        for (kind, body) in [(b"tEXt", text), (b"IDAT", pixels), (b"IEND", &[][..])] {
+            data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            data.extend_from_slice(kind);
+            data.extend_from_slice(body);
+            data.extend_from_slice(&[0; 4]); // CRC, not checked
+        }
+        data
+    }
+
+    #[test]
+    fn ignores_jpeg_and_png_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, data: Vec<u8>| {
+            let path = dir.path().join(name);
+            fs::write(&path, data).unwrap();
+            payload_hash(&path, &[&Images]).unwrap().unwrap()
+        };
+
+        let a = write("a.jpg", jpeg(b"Exif\0\0camera A", b"pixels"));
+        let b = write("b.jpg", jpeg(b"Exif\0\0edited", b"pixels"));
+        let c = write("c.jpg", jpeg(b"Exif\0\0camera A", b"other pixels"));
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+
+        let d = write("d.png", png(b"Comment\0one", b"idat"));
+        let e = write("e.png", png(b"Comment\0two", b"idat"));
+        let f = write("f.png", png(b"Comment\0one", b"IDAT"));
+        assert_eq!(d, e);
+        assert_ne!(d, f);
+    }
+
+    #[test]
+    fn ignores_mp3_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let frames = [0xFF, 0xFB, 0x90, 0x64, 1, 2, 3, 4];
+        let mp3 = |id3v2: &[u8], id3v1: Option<&[u8]>| {
+            let mut data = b"ID3\x04\x00\x00\x00\x00\x00".to_vec();
+            data.push(id3v2.len() as u8);
+            data.extend_from_slice(id3v2);
+            data.extend_from_slice(&frames);
+            if let Some(title) = id3v1 {
+                let mut tag = b"TAG".to_vec();
+                tag.extend_from_slice(title);
+                tag.resize(128, 0);
+                data.extend_from_slice(&tag);
+            }
+            data
+        };
+        let write = |name: &str, data: Vec<u8>| {
+            let path = dir.path().join(name);
+            fs::write(&path, data).unwrap();
+            payload_hash(&path, &[&Images, &Mp3Audio]).unwrap()
+        };
+
+        let a = write("a.mp3", mp3(b"TIT2 Song", None));
+        let b = write("b.mp3", mp3(b"TIT2 Retagged song", Some(b"Retagged")));
+        assert!(a.is_some());
+        assert_eq!(a, b);
+        assert_eq!(write("c.mp3", frames.to_vec()), a);
+        assert_eq!(write("d.mp3", b"not audio".to_vec()), None);
+        // Without the format, MP3 files aren't looked at
+        let path = dir.path().join("a.mp3");
+        assert_eq!(payload_hash(&path, &[&Images]).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_unsupported_and_malformed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = dir.path().join("a.jpg");
+        fs::write(&text, b"not an image").unwrap();
+        assert!(payload_hash(&text, &[&Images]).unwrap().is_none());
+
+        let truncated = dir.path().join("b.jpg");
+        fs::write(&truncated, &jpeg(b"Exif", b"x")[..8]).unwrap();
+        assert!(payload_hash(&truncated, &[&Images]).is_err());
+    }
+}
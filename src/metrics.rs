@@ -0,0 +1,181 @@
+//! Counters kept while watching a directory, and the Prometheus text format served at
+//! `/metrics` by the `daemon` command, so monitoring can graph duplicate growth over time.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::index::HashIndex;
+
+/// Counters updated by the watcher. Gauges such as the number of duplicates are read
+/// from the index when the metrics are rendered.
+#[derive(Debug, Default)]
+pub struct WatchMetrics {
+    initial_scan_micros: AtomicU64,
+    files_hashed: AtomicU64,
+    hash_errors: AtomicU64,
+    hash_micros: AtomicU64,
+    new_duplicates: AtomicU64,
+}
+
+impl WatchMetrics {
+    /// Records how long the scan before watching took.
+    pub fn set_initial_scan(&self, elapsed: Duration) {
+        self.initial_scan_micros
+            .store(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Records a file hashed by the watcher, or that failed to hash, and how long it took.
+    pub fn file_hashed(&self, elapsed: Duration, ok: bool) {
+        if ok {
+            self.files_hashed.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.hash_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        self.hash_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Records a new file found to duplicate an existing one.
+    pub fn new_duplicate(&self) {
+        self.new_duplicates.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Appends one metric with its help and type lines.
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "# HELP check_file_dups_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE check_file_dups_{} {}", name, kind);
+    let _ = writeln!(out, "check_file_dups_{} {}", name, value);
+}
+
+/// Renders the state of `index`, the number of entries in the hash cache and the watcher's
+/// counters in the Prometheus text exposition format.
+pub fn render(index: &HashIndex, cache_entries: usize, metrics: &WatchMetrics) -> String {
+    let (groups, files) = index
+        .duplicates()
+        .fold((0, 0), |(groups, files), (_, paths)| {
+            (groups + 1, files + paths.len())
+        });
+    let micros = |counter: &AtomicU64| counter.load(Ordering::Relaxed) as f64 / 1e6;
+    let mut out = String::new();
+    metric(
+        &mut out,
+        "indexed_files",
+        "gauge",
+        "Files in the hash index.",
+        index.len(),
+    );
+    metric(
+        &mut out,
+        "distinct_hashes",
+        "gauge",
+        "Distinct contents in the hash index.",
+        index.hash_count(),
+    );
+    metric(
+        &mut out,
+        "duplicate_groups",
+        "gauge",
+        "Contents shared by more than one file.",
+        groups,
+    );
+    metric(
+        &mut out,
+        "duplicate_files",
+        "gauge",
+        "Files whose contents are shared with another file.",
+        files,
+    );
+    metric(
+        &mut out,
+        "wasted_bytes",
+        "gauge",
+        "Space taken by all but one file of each duplicate group.",
+        index.wasted_bytes(),
+    );
+    metric(
+        &mut out,
+        "cache_entries",
+        "gauge",
+        "Entries in the hash cache.",
+        cache_entries,
+    );
+    metric(
+        &mut out,
+        "initial_scan_seconds",
+        "gauge",
+        "Time taken by the scan before watching.",
+        micros(&metrics.initial_scan_micros),
+    );
+    metric(
+        &mut out,
+        "files_hashed_total",
+        "counter",
+        "Files hashed since watching started.",
+        metrics.files_hashed.load(Ordering::Relaxed),
+    );
+    metric(
+        &mut out,
+        "hash_errors_total",
+        "counter",
+        "Files that failed to hash since watching started.",
+        metrics.hash_errors.load(Ordering::Relaxed),
+    );
+    metric(
+        &mut out,
+        "hash_seconds_total",
+        "counter",
+        "Time spent hashing since watching started.",
+        micros(&metrics.hash_micros),
+    );
+    metric(
+        &mut out,
+        "new_duplicates_total",
+        "counter",
+        "New duplicates found since watching started.",
+        metrics.new_duplicates.load(Ordering::Relaxed),
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn renders_index_and_counters() {
+        let mut index = HashIndex::new();
+        index.insert(PathBuf::from("/d/a"), "ab12".into());
+        index.insert(PathBuf::from("/d/b"), "ab12".into());
+        index.insert(PathBuf::from("/d/c"), "cd34".into());
+        index.set_size("ab12", 1000);
+        let metrics = WatchMetrics::default();
+        metrics.set_initial_scan(Duration::from_millis(2500));
+        metrics.file_hashed(Duration::from_millis(10), true);
+        metrics.file_hashed(Duration::from_millis(5), false);
+        metrics.new_duplicate();
+
+        let text = render(&index, 7, &metrics);
+        let values: Vec<&str> = text.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(
+            values,
+            [
+                "check_file_dups_indexed_files 3",
+                "check_file_dups_distinct_hashes 2",
+                "check_file_dups_duplicate_groups 1",
+                "check_file_dups_duplicate_files 2",
+                "check_file_dups_wasted_bytes 1000",
+                "check_file_dups_cache_entries 7",
+                "check_file_dups_initial_scan_seconds 2.5",
+                "check_file_dups_files_hashed_total 1",
+                "check_file_dups_hash_errors_total 1",
+                "check_file_dups_hash_seconds_total 0.015",
+                "check_file_dups_new_duplicates_total 1",
+            ]
+        );
+        assert!(text.contains("# TYPE check_file_dups_wasted_bytes gauge\n"));
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::{HashMap, HashSet};
+
+use log::{info, warn};
+
+use crate::FileInfo;
+use crate::scan_root::ScanRoot;
+use crate::units::{Bytes, Count};
+
+/// Files sharing one file name while their contents differ.
+#[derive(Debug, Clone)]
+pub struct SameNameGroup {
+    pub name: String,
+    /// The files, sorted by contents and then by path, so identical copies are adjacent.
+    pub files: Vec<FileInfo>,
+    /// Number of different contents among the files.
+    pub versions: usize,
+}
+
+/// Finds files with exactly the same file name but different contents anywhere in
+/// `files`, a common sign of copies that diverged and need reconciling by hand rather
+/// than deduplicating. Names whose files all have identical contents are exact
+/// duplicates and are reported as such, so only names with at least two different
+/// contents are returned, in name order.
+///
+/// With `case_insensitive`, names differing only in case are the same name, which is
+/// then shown as the first file spells it.
+pub fn find_same_names<'a>(
+    files: impl IntoIterator<Item = &'a FileInfo>,
+    case_insensitive: bool,
+) -> Vec<SameNameGroup> {
+    let mut by_name: HashMap<String, Vec<&FileInfo>> = HashMap::new();
+    for file in files {
+        let name = file.path.file_name().to_string_lossy();
+        let name = if case_insensitive {
+            name.to_lowercase()
+        } else {
+            name.into_owned()
+        };
+        by_name.entry(name).or_default().push(file);
+    }
+    let mut groups: Vec<SameNameGroup> = by_name
+        .into_values()
+        .filter_map(|group| {
+            let versions = group
+                .iter()
+                .map(|file| file.hash)
+                .collect::<HashSet<_>>()
+                .len();
+            (versions > 1).then(|| {
+                let mut files: Vec<FileInfo> = group.into_iter().cloned().collect();
+                files.sort_by(|a, b| a.hash.cmp(&b.hash).then_with(|| a.path.cmp(&b.path)));
+                let name = files[0].path.file_name().to_string_lossy().into_owned();
+                SameNameGroup {
+                    name,
+                    files,
+                    versions,
+                }
+            })
+        })
+        .collect();
+    groups.sort_by(|a, b| a.name.cmp(&b.name));
+    groups
+}
+
+/// Prints groups found by [`find_same_names`], as a section separate from the
+/// duplicates. Files are numbered by version, so identical copies share a number.
+pub fn print_same_names(groups: &[SameNameGroup], root: &ScanRoot) {
+    if groups.is_empty() {
+        info!("No files sharing a name with different contents found");
+        return;
+    }
+    warn!(
+        "Found {} file names used for different contents",
+        Count(groups.len() as u64)
+    );
+    for group in groups {
+        warn!(
+            "'{}' has {} versions ({} files):",
+            group.name,
+            group.versions,
+            group.files.len()
+        );
+        let mut version = 0;
+        let mut previous_hash = None;
+        for file in &group.files {
+            if previous_hash != Some(&file.hash) {
+                version += 1;
+                previous_hash = Some(&file.hash);
+            }
+            warn!(
+                "  [{}] {} ({})",
+                version,
+                root.display_file(&file.path).display(),
+                Bytes(file.size)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content_hash::ContentHash;
+    use std::path::PathBuf;
+
+    #[test]
+    fn finds_names_with_different_contents() {
+        let file =
+            |path: &str, hash: &str| FileInfo::for_test(path, 1, ContentHash::of(hash.as_bytes()));
+        let files = vec![
+            file("/a/notes.txt", "2"),
+            file("/b/notes.txt", "1"),
+            file("/c/notes.txt", "2"),
+            file("/a/same.txt", "3"),
+            file("/b/same.txt", "3"),
+            file("/a/Notes.txt", "4"),
+        ];
+        let groups = find_same_names(&files, false);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].name, "notes.txt");
+        assert_eq!(groups[0].versions, 2);
+        let paths: Vec<PathBuf> = groups[0]
+            .files
+            .iter()
+            .map(|file| file.path.to_path_buf())
+            .collect();
+        assert_eq!(
+            paths,
+            ["/a/notes.txt", "/c/notes.txt", "/b/notes.txt"].map(PathBuf::from)
+        );
+
+        let groups = find_same_names(&files, true);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].versions, 3);
+        assert_eq!(groups[0].files.len(), 4);
+    }
+}
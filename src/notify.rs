@@ -0,0 +1,534 @@
+//! Notifications sent when an unattended scan finishes or fails, so scans run from cron
+//! or a scheduled task can be left alone until there is something to look at.
+//!
+//! Each way of sending one implements [`Notifier`]: [`Webhook`] posts the summary as JSON
+//! with `--notify-webhook`, and [`Smtp`] emails it with the config file's `[smtp]` table.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use indicatif::HumanDuration;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc2822;
+
+use crate::report::{DuplicateGroup, DuplicateReport};
+use crate::units::{Bytes, Count};
+
+/// Number of groups wasting the most space that are listed in a notification.
+const TOP_GROUPS: usize = 5;
+
+/// Longest wait for a webhook or mail server to connect or answer.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How a scan ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Completed,
+    /// Stopped with Ctrl+C; the results cover the files hashed until then.
+    Interrupted,
+    Failed,
+}
+
+/// What a notification says about a scan.
+#[derive(Clone)]
+pub struct ScanSummary {
+    pub status: Status,
+    /// Directory that was scanned.
+    pub scan_path: PathBuf,
+    /// Number of duplicate groups.
+    pub groups: usize,
+    /// Number of redundant files, excluding one copy per group.
+    pub duplicates: usize,
+    /// Space used by the redundant files, in bytes.
+    pub wasted_space: u64,
+    /// Groups wasting the most space, largest first.
+    pub top_groups: Vec<DuplicateGroup>,
+    /// Time from start to finish.
+    pub duration: Duration,
+    /// Why the scan failed.
+    pub error: Option<String>,
+}
+
+impl ScanSummary {
+    /// Summarizes the duplicates of a scan that ran to the end, or was `interrupted`.
+    pub fn from_report(report: &DuplicateReport, interrupted: bool, duration: Duration) -> Self {
+        Self {
+            status: if interrupted {
+                Status::Interrupted
+            } else {
+                Status::Completed
+            },
+            scan_path: report.scan_path.clone(),
+            groups: report.groups.len(),
+            duplicates: report.total_duplicates,
+            wasted_space: report.total_wasted_space,
+            top_groups: report.groups.iter().take(TOP_GROUPS).cloned().collect(),
+            duration,
+            error: None,
+        }
+    }
+
+    /// Summarizes a scan of `scan_path` that stopped with `error`.
+    pub fn failed(scan_path: &Path, error: &anyhow::Error, duration: Duration) -> Self {
+        Self {
+            status: Status::Failed,
+            scan_path: scan_path.to_path_buf(),
+            groups: 0,
+            duplicates: 0,
+            wasted_space: 0,
+            top_groups: Vec::new(),
+            duration,
+            error: Some(format!("{:#}", error)),
+        }
+    }
+
+    /// Returns a one-line summary, used as the subject of emails.
+    pub fn subject(&self) -> String {
+        match self.status {
+            Status::Failed => format!("check-file-dups failed on {}", self.scan_path.display()),
+            Status::Completed | Status::Interrupted => format!(
+                "check-file-dups{}: {} duplicate files wasting {} in {}",
+                if self.status == Status::Interrupted {
+                    " (interrupted)"
+                } else {
+                    ""
+                },
+                Count(self.duplicates as u64),
+                Bytes(self.wasted_space),
+                self.scan_path.display()
+            ),
+        }
+    }
+
+    /// Returns the summary as plain text, used as the body of emails.
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "{}\n\nScanned {} in {}.\n",
+            self.subject(),
+            self.scan_path.display(),
+            HumanDuration(self.duration)
+        );
+        if let Some(error) = &self.error {
+            text.push_str(&format!("\nError: {}\n", error));
+            return text;
+        }
+        text.push_str(&format!(
+            "Found {} duplicate groups holding {} redundant files, wasting {}.\n",
+            Count(self.groups as u64),
+            Count(self.duplicates as u64),
+            Bytes(self.wasted_space)
+        ));
+        if !self.top_groups.is_empty() {
+            text.push_str("\nGroups wasting the most space:\n");
+        }
+        for group in &self.top_groups {
+            text.push_str(&format!(
+                "\n{} files of {}, wasting {}:\n",
+                group.copies(),
+                Bytes(group.size),
+                Bytes(group.wasted_space())
+            ));
+            for path in &group.paths {
+                text.push_str(&format!("  {}\n", path.display()));
+            }
+        }
+        text
+    }
+
+    /// Returns the summary as JSON, with the duration in seconds.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "status": self.status,
+            "summary": self.subject(),
+            "scan_path": self.scan_path,
+            "duplicate_groups": self.groups,
+            "duplicate_files": self.duplicates,
+            "wasted_space": self.wasted_space,
+            "top_groups": self.top_groups.iter().map(|group| json!({
+                "size": group.size,
+                "wasted_space": group.wasted_space(),
+                "paths": group.paths,
+            })).collect::<Vec<_>>(),
+            "duration": self.duration.as_secs_f64(),
+            "error": self.error,
+        })
+    }
+}
+
+/// A way of sending a notification.
+pub trait Notifier {
+    /// Describes where notifications go, for the log.
+    fn describe(&self) -> String;
+
+    /// Sends `summary`.
+    fn send(&self, summary: &ScanSummary) -> Result<()>;
+}
+
+/// Sends `summary` with each of `notifiers`, logging failures rather than returning them
+/// so one broken notifier neither hides the others nor fails the scan.
+pub fn notify_all(notifiers: &[Box<dyn Notifier>], summary: &ScanSummary) {
+    for notifier in notifiers {
+        match notifier.send(summary) {
+            Ok(()) => info!("Sent notification to {}", notifier.describe()),
+            Err(e) => warn!(
+                "Failed to send notification to {}: {:#}",
+                notifier.describe(),
+                e
+            ),
+        }
+    }
+}
+
+/// Posts the summary as JSON to a URL. `http` URLs are posted directly; `https` URLs are
+/// posted with `curl`, which must be installed.
+pub struct Webhook {
+    url: String,
+}
+
+impl Webhook {
+    /// # Errors
+    ///
+    /// Returns an error if `url` is not an `http` or `https` URL.
+    pub fn new(url: &str) -> Result<Self> {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            anyhow::bail!("The notification webhook must be an http:// or https:// URL: {url}");
+        }
+        Ok(Self {
+            url: url.to_string(),
+        })
+    }
+}
+
+impl Notifier for Webhook {
+    fn describe(&self) -> String {
+        self.url.clone()
+    }
+
+    fn send(&self, summary: &ScanSummary) -> Result<()> {
+        let body = summary.to_json().to_string();
+        match self.url.strip_prefix("http://") {
+            Some(rest) => post_http(rest, body.as_bytes()),
+            None => post_with_curl(&self.url, body.as_bytes()),
+        }
+    }
+}
+
+/// Posts `body` to `rest`, an `http://` URL without the scheme, and checks that the server
+/// accepted it.
+fn post_http(rest: &str, body: &[u8]) -> Result<()> {
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let has_port = authority
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+    let address = if has_port {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    let mut stream = TcpStream::connect(&address)
+        .with_context(|| format!("Failed to connect to {}", address))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: check-file-dups/{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        authority,
+        env!("CARGO_PKG_VERSION"),
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+    let code = status
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .with_context(|| format!("Invalid response: {}", status.trim()))?;
+    if !(200..300).contains(&code) {
+        anyhow::bail!("The server answered {}", status.trim());
+    }
+    Ok(())
+}
+
+/// Posts `body` to `url` with `curl`, for URLs needing TLS.
+fn post_with_curl(url: &str, body: &[u8]) -> Result<()> {
+    let mut child = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--max-time"])
+        .arg(TIMEOUT.as_secs().to_string())
+        .args(["--header", "Content-Type: application/json"])
+        .args(["--data-binary", "@-", url])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("https:// webhooks are posted with curl, which could not be started")?;
+    child.stdin.take().unwrap().write_all(body)?;
+    let mut stderr = String::new();
+    child.stderr.take().unwrap().read_to_string(&mut stderr)?;
+    if !child.wait()?.success() {
+        anyhow::bail!("curl failed: {}", stderr.trim());
+    }
+    Ok(())
+}
+
+fn default_smtp_port() -> u16 {
+    25
+}
+
+/// The `[smtp]` table of the config file: a mail server to email summaries through.
+///
+/// Mail is sent without TLS or authentication, so this is meant for a relay on the same
+/// machine or a trusted network, such as a local Postfix or msmtpd forwarding to the
+/// real mail provider.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpConfig {
+    /// Host name or address of the mail server.
+    pub host: String,
+    /// Port of the mail server.
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    /// Sender address.
+    pub from: String,
+    /// Recipient addresses.
+    pub to: Vec<String>,
+}
+
+/// Emails the summary as plain text through an SMTP server.
+pub struct Smtp {
+    config: SmtpConfig,
+}
+
+impl Smtp {
+    /// # Errors
+    ///
+    /// Returns an error if no recipient is configured.
+    pub fn new(config: &SmtpConfig) -> Result<Self> {
+        if config.to.is_empty() {
+            anyhow::bail!("The [smtp] config needs at least one address in `to`");
+        }
+        Ok(Self {
+            config: config.clone(),
+        })
+    }
+}
+
+impl Notifier for Smtp {
+    fn describe(&self) -> String {
+        self.config.to.join(", ")
+    }
+
+    fn send(&self, summary: &ScanSummary) -> Result<()> {
+        let config = &self.config;
+        let stream = TcpStream::connect((config.host.as_str(), config.port))
+            .with_context(|| format!("Failed to connect to {}:{}", config.host, config.port))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut session = SmtpSession {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+
+        session.expect(220)?;
+        session.command("EHLO localhost", 250)?;
+        session.command(&format!("MAIL FROM:<{}>", config.from), 250)?;
+        for to in &config.to {
+            session.command(&format!("RCPT TO:<{}>", to), 250)?;
+        }
+        session.command("DATA", 354)?;
+        let date = OffsetDateTime::now_utc().format(&Rfc2822)?;
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+            config.from,
+            config.to.join(", "),
+            summary.subject(),
+            date
+        );
+        for line in summary.to_text().lines() {
+            // Lines starting with a dot are escaped, since a lone dot ends the message
+            if line.starts_with('.') {
+                message.push('.');
+            }
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        session.writer.write_all(message.as_bytes())?;
+        session.command(".", 250)?;
+        session.command("QUIT", 221)
+    }
+}
+
+/// A connection to an SMTP server.
+struct SmtpSession {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl SmtpSession {
+    /// Sends `command` and checks the server's reply.
+    fn command(&mut self, command: &str, code: u16) -> Result<()> {
+        write!(self.writer, "{}\r\n", command)?;
+        self.expect(code)
+            .with_context(|| format!("Mail server rejected {}", command))
+    }
+
+    /// Reads a reply, which can span lines like `250-...` up to `250 ...`, and checks
+    /// that it has `code`.
+    fn expect(&mut self, code: u16) -> Result<()> {
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                anyhow::bail!("The mail server closed the connection");
+            }
+            if line.get(..3).and_then(|c| c.parse::<u16>().ok()) != Some(code) {
+                anyhow::bail!("Unexpected reply: {}", line.trim());
+            }
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    fn summary() -> ScanSummary {
+        ScanSummary {
+            status: Status::Completed,
+            scan_path: "/photos".into(),
+            groups: 1,
+            duplicates: 1,
+            wasted_space: 2048,
+            top_groups: vec![DuplicateGroup {
+                hash: "abc".to_string(),
+                id: "abc".to_string(),
+                size: 2048,
+                paths: vec!["a.jpg".into(), "b.jpg".into()],
+                copies: 2,
+                wasted_space: Some(2048),
+                compressed: Vec::new(),
+            }],
+            duration: Duration::from_secs(3),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn posts_summary_to_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header == "\r\n" {
+                    break;
+                }
+                if let Some(value) = header.strip_prefix("Content-Length: ") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            (&stream)
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            (request_line, body)
+        });
+
+        Webhook::new(&url).unwrap().send(&summary()).unwrap();
+        let (request_line, body) = server.join().unwrap();
+        assert_eq!(request_line, "POST /hook HTTP/1.1\r\n");
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["status"], "completed");
+        assert_eq!(json["wasted_space"], 2048);
+        assert_eq!(json["top_groups"][0]["paths"][1], "b.jpg");
+
+        assert!(Webhook::new("ftp://example.com").is_err());
+    }
+
+    #[test]
+    fn emails_summary_over_smtp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut transcript = Vec::new();
+            writer.write_all(b"220 test\r\n").unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_end().to_string();
+                let reply: &[u8] = if in_data {
+                    if line == "." {
+                        in_data = false;
+                        b"250 queued\r\n"
+                    } else {
+                        b""
+                    }
+                } else if line.starts_with("EHLO") {
+                    b"250-test\r\n250 8BITMIME\r\n"
+                } else if line == "DATA" {
+                    in_data = true;
+                    b"354 go on\r\n"
+                } else if line == "QUIT" {
+                    b"221 bye\r\n"
+                } else {
+                    b"250 ok\r\n"
+                };
+                writer.write_all(reply).unwrap();
+                transcript.push(line);
+                if transcript.last().unwrap() == "QUIT" {
+                    break;
+                }
+            }
+            transcript
+        });
+
+        let config = SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            from: "nas@example.com".to_string(),
+            to: vec!["me@example.com".to_string()],
+        };
+        Smtp::new(&config).unwrap().send(&summary()).unwrap();
+        let transcript = server.join().unwrap();
+        assert_eq!(transcript[1], "MAIL FROM:<nas@example.com>");
+        assert_eq!(transcript[2], "RCPT TO:<me@example.com>");
+        assert!(transcript.contains(
+            &"Subject: check-file-dups: 1 duplicate files wasting 2.00 KiB in /photos".to_string()
+        ));
+        assert!(transcript.contains(&"  b.jpg".to_string()));
+        assert_eq!(transcript.last().unwrap(), "QUIT");
+    }
+}
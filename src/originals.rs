@@ -0,0 +1,166 @@
+//! `register-originals`: a registry of known good originals, such as a canonical photo
+//! library. The files of each registered directory are hashed and recorded like a
+//! location in an `index`. Later scans add the registered originals to the groups of
+//! files with their contents, so copies are reported as redundant even when the
+//! originals aren't scanned, and never pick an original for deletion or linking.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use log::info;
+
+use crate::FileInfo;
+use crate::content_hash::ContentHash;
+use crate::corpus::CorpusIndex;
+use crate::units::Count;
+
+/// The registered originals, by content hash.
+pub struct Originals {
+    index: CorpusIndex,
+}
+
+impl Originals {
+    /// Returns the default file: `originals.json.zst` in the platform data directory
+    /// (e.g. `~/.local/share/check-file-dups`), falling back to the current directory.
+    pub fn default_file() -> PathBuf {
+        dirs::data_local_dir()
+            .map(|dir| dir.join(env!("CARGO_PKG_NAME")))
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("originals.json.zst")
+    }
+
+    /// Loads the originals registered in `file`, or starts with none if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or parsed.
+    pub fn load(file: PathBuf) -> Result<Self> {
+        Ok(Self {
+            index: CorpusIndex::load(file)?,
+        })
+    }
+
+    /// Registers `files`, found by hashing the directory `root` at `registered_at` (a
+    /// Unix timestamp), as originals, replacing those registered for it before.
+    pub fn register(&mut self, root: &Path, files: &[FileInfo], registered_at: u64) {
+        self.index.replace(root, files, registered_at);
+    }
+
+    /// Writes the registered originals back to their file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.index.file().parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        self.index.save()
+    }
+
+    /// Logs the registered directories.
+    pub fn print(&self) {
+        self.index.print_roots();
+    }
+
+    /// Returns the paths of the registered originals, to protect them from actions.
+    pub fn paths(&self) -> HashSet<PathBuf> {
+        self.index
+            .entries()
+            .map(|entry| entry.path.clone())
+            .collect()
+    }
+
+    /// Adds the registered originals to the groups of `hash_groups` with their contents,
+    /// unless scanned already, returning the number of scanned files that are copies of
+    /// an original. The originals are taken as recorded, and checked again like any
+    /// other file before an action.
+    pub fn add_to(&self, hash_groups: &mut HashMap<ContentHash, Vec<FileInfo>>) -> usize {
+        if self.index.is_empty() {
+            return 0;
+        }
+        let mut copies = 0;
+        for (hash, group) in hash_groups.iter_mut() {
+            let originals = self.index.paths(&hash.to_string());
+            if originals.is_empty() {
+                continue;
+            }
+            let size = group[0].size;
+            let scanned = group.len();
+            copies += group
+                .iter()
+                .filter(|file| !originals.iter().any(|path| file.path == **path))
+                .count();
+            for path in originals {
+                if !group[..scanned].iter().any(|file| file.path == **path) {
+                    group.push(FileInfo {
+                        path: path.clone().into(),
+                        size,
+                        hash: *hash,
+                        file_id: None,
+                        links: None,
+                        allocated_size: None,
+                        modified: None,
+                        in_archive: false,
+                        compressed: false,
+                        owner: None,
+                    });
+                }
+            }
+        }
+        if copies > 0 {
+            info!(
+                "Found {} copies of registered originals",
+                Count(copies as u64)
+            );
+        }
+        copies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selection::KeepPolicy;
+
+    #[test]
+    fn adds_and_protects_registered_originals() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = dir.path().join("library");
+        let file_name = dir.path().join("originals.json.zst");
+        let mut originals = Originals::load(file_name.clone()).unwrap();
+        originals.register(
+            &library,
+            &[FileInfo::with_contents(library.join("photo.jpg"), b"photo")],
+            0,
+        );
+        originals.save().unwrap();
+
+        let originals = Originals::load(file_name).unwrap();
+        let copy = dir.path().join("downloads/photo (1).jpg");
+        let mut hash_groups = HashMap::from([
+            (
+                ContentHash::of(b"photo"),
+                vec![FileInfo::with_contents(&copy, b"photo")],
+            ),
+            (
+                ContentHash::of(b"other"),
+                vec![FileInfo::with_contents(dir.path().join("other"), b"other")],
+            ),
+        ]);
+        assert_eq!(originals.add_to(&mut hash_groups), 1);
+        let group = &hash_groups[&ContentHash::of(b"photo")];
+        assert_eq!(group.len(), 2);
+        assert_eq!(hash_groups[&ContentHash::of(b"other")].len(), 1);
+
+        // The original is kept, even though a rule prefers the copy
+        let policy = KeepPolicy::new(vec!["path:downloads/**".parse().unwrap()])
+            .with_originals(originals.paths());
+        assert_eq!(
+            group[policy.select(group, dir.path())].path.to_path_buf(),
+            library.join("photo.jpg")
+        );
+        assert!(policy.is_protected(&library.join("photo.jpg"), dir.path()));
+    }
+}
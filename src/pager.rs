@@ -0,0 +1,106 @@
+//! Paging long reports through `$PAGER` (or `less`) when writing to a terminal, so
+//! interactive users aren't scrolled past thousands of lines.
+
+use std::io::{self, Write};
+use std::process::{Child, ChildStdin};
+
+/// A running pager. While it lives, standard output, and standard error if it is a
+/// terminal, are written to the pager. Dropping it restores them and waits for the user
+/// to quit the pager.
+#[cfg_attr(not(unix), allow(dead_code))]
+pub struct Pager {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    /// Each redirected descriptor, with a copy of what it was before.
+    #[cfg(unix)]
+    saved: Vec<(i32, i32)>,
+}
+
+impl Pager {
+    /// Starts `$PAGER`, or `less` if it isn't set, if standard output is a terminal.
+    /// `less` is told to quit at once if everything fits on one screen, unless `$LESS`
+    /// says otherwise.
+    ///
+    /// Returns `None` if standard output isn't a terminal, `$PAGER` is empty or `cat`,
+    /// or the pager can't be started. Paging is only supported on Unix.
+    pub fn start() -> Option<Self> {
+        #[cfg(unix)]
+        {
+            use std::io::IsTerminal;
+            use std::os::fd::AsRawFd;
+            use std::process::{Command, Stdio};
+
+            use log::debug;
+
+            if !io::stdout().is_terminal() {
+                return None;
+            }
+            let command = std::env::var("PAGER").unwrap_or_else(|_| "less".to_string());
+            let mut words = command.split_whitespace();
+            let program = words.next()?;
+            if program == "cat" {
+                return None;
+            }
+            let mut pager = Command::new(program);
+            pager.args(words).stdin(Stdio::piped());
+            // Quit if the report fits on the screen, show colors, and leave the report on
+            // the screen afterwards
+            if std::env::var_os("LESS").is_none() {
+                pager.env("LESS", "FRX");
+            }
+            let mut child = match pager.spawn() {
+                Ok(child) => child,
+                Err(e) => {
+                    debug!("Failed to start pager '{}': {}", command, e);
+                    return None;
+                }
+            };
+            let stdin = child.stdin.take()?;
+
+            let _ = io::stdout().flush();
+            let _ = io::stderr().flush();
+            let mut fds = vec![libc::STDOUT_FILENO];
+            if io::stderr().is_terminal() {
+                fds.push(libc::STDERR_FILENO);
+            }
+            let mut saved = Vec::new();
+            for fd in fds {
+                // SAFETY: only the standard descriptors and the open pipe are used, and
+                // the copies are closed when the pager is dropped
+                unsafe {
+                    let copy = libc::dup(fd);
+                    if copy >= 0 && libc::dup2(stdin.as_raw_fd(), fd) >= 0 {
+                        saved.push((fd, copy));
+                    } else if copy >= 0 {
+                        libc::close(copy);
+                    }
+                }
+            }
+            Some(Self {
+                child,
+                stdin: Some(stdin),
+                saved,
+            })
+        }
+        #[cfg(not(unix))]
+        None
+    }
+}
+
+impl Drop for Pager {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+        #[cfg(unix)]
+        for &(fd, copy) in &self.saved {
+            // SAFETY: `copy` is the descriptor saved in `start`, closed only here
+            unsafe {
+                libc::dup2(copy, fd);
+                libc::close(copy);
+            }
+        }
+        // Closing the last write end of the pipe tells the pager the report is complete
+        drop(self.stdin.take());
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,428 @@
+//! Compact storage for the paths of scanned files. Scans of millions of files hold every
+//! path until the end, so each path is stored as its directory, shared by all the files in
+//! it, and its file name.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fmt;
+use std::iter;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// The path of a scanned file: its directory, shared with the other files there when
+/// created by a [`PathInterner`], and its file name. Compares and sorts like the full path.
+#[derive(Clone)]
+pub struct FilePath {
+    dir: Arc<Path>,
+    name: Box<OsStr>,
+}
+
+impl FilePath {
+    /// Returns the full path.
+    pub fn to_path_buf(&self) -> PathBuf {
+        self.dir.join(&*self.name)
+    }
+
+    /// Returns the directory of the file.
+    pub fn parent(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the file name, or the whole path for paths without one (such as `/`).
+    pub fn file_name(&self) -> &OsStr {
+        &self.name
+    }
+
+    /// Returns an object that displays the full path, like [`Path::display`].
+    pub fn display(&self) -> impl fmt::Display + '_ {
+        Display(self)
+    }
+
+    /// Returns `true` if the path starts with `base`, like [`Path::starts_with`].
+    pub fn starts_with(&self, base: &Path) -> bool {
+        self.components()
+            .take(base.components().count())
+            .eq(base.components())
+    }
+
+    /// Returns the components of the full path, like [`Path::components`].
+    pub fn components(&self) -> impl Iterator<Item = Component<'_>> {
+        self.dir
+            .components()
+            .chain(iter::once(Component::Normal(&self.name)))
+    }
+}
+
+/// Returns `path` as a string that keeps every byte of it: `%` and bytes that aren't
+/// valid UTF-8 (only possible on Unix, or for unpaired surrogates on Windows) are written
+/// as `%XX`. Other paths are returned unchanged apart from `%`. Reversed by
+/// [`unescape_path`].
+pub fn escape_path(path: &Path) -> String {
+    let mut escaped = String::new();
+    for chunk in path.as_os_str().as_encoded_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c == '%' {
+                escaped.push_str("%25");
+            } else {
+                escaped.push(c);
+            }
+        }
+        for byte in chunk.invalid() {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+/// Returns the path written by [`escape_path`]. A `%` not followed by two hex digits is
+/// kept as it is. On Windows, paths with unpaired surrogates come back with replacement
+/// characters.
+pub fn unescape_path(escaped: &str) -> PathBuf {
+    let bytes = escaped.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) if bytes[i] == b'%' => {
+                unescaped.push(byte);
+                i += 3;
+            }
+            _ => {
+                unescaped.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    path_from_bytes(unescaped)
+}
+
+/// Returns the path made of `bytes`, as [`path_bytes`] returns them.
+#[cfg(unix)]
+pub(crate) fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Serializes paths as JSON strings when they are valid UTF-8, and otherwise as
+/// `{"escaped": "..."}` holding the path written by [`escape_path`], so reports, manifests
+/// and journals keep every file name. Use with `#[serde(with = "crate::paths::serde_path")]`,
+/// or the `option`, `vec` and `map` submodules for `Option<PathBuf>`, `Vec<PathBuf>` and
+/// maps keyed by path.
+pub mod serde_path {
+    use std::path::{Path, PathBuf};
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{escape_path, unescape_path};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Encoded {
+        Plain(String),
+        Escaped { escaped: String },
+    }
+
+    impl Encoded {
+        fn new(path: &Path) -> Self {
+            match path.to_str() {
+                Some(path) => Encoded::Plain(path.to_string()),
+                None => Encoded::Escaped {
+                    escaped: escape_path(path),
+                },
+            }
+        }
+
+        fn into_path(self) -> PathBuf {
+            match self {
+                Encoded::Plain(path) => PathBuf::from(path),
+                Encoded::Escaped { escaped } => unescape_path(&escaped),
+            }
+        }
+    }
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        Encoded::new(path).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        Encoded::deserialize(deserializer).map(Encoded::into_path)
+    }
+
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            path: &Option<PathBuf>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            path.as_deref().map(Encoded::new).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<PathBuf>, D::Error> {
+            Ok(Option::<Encoded>::deserialize(deserializer)?.map(Encoded::into_path))
+        }
+    }
+
+    pub mod vec {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            paths: &[PathBuf],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(paths.iter().map(|path| Encoded::new(path)))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<PathBuf>, D::Error> {
+            Ok(Vec::<Encoded>::deserialize(deserializer)?
+                .into_iter()
+                .map(Encoded::into_path)
+                .collect())
+        }
+    }
+
+    /// For maps keyed by path, whose keys must be strings: every key is written by
+    /// [`escape_path`].
+    pub mod map {
+        use std::collections::HashMap;
+
+        use super::*;
+
+        pub fn serialize<S: Serializer, V: Serialize>(
+            map: &HashMap<PathBuf, V>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(map.iter().map(|(path, value)| (escape_path(path), value)))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>, V: Deserialize<'de>>(
+            deserializer: D,
+        ) -> Result<HashMap<PathBuf, V>, D::Error> {
+            Ok(HashMap::<String, V>::deserialize(deserializer)?
+                .into_iter()
+                .map(|(path, value)| (unescape_path(&path), value))
+                .collect())
+        }
+    }
+}
+
+/// Returns the bytes of `path`: on Unix the bytes the system uses, so names that aren't
+/// valid UTF-8 are kept unchanged, elsewhere its UTF-8 form.
+pub fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Cow::Borrowed(path.as_os_str().as_bytes())
+    }
+    #[cfg(not(unix))]
+    {
+        match path.to_string_lossy() {
+            Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+            Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+        }
+    }
+}
+
+struct Display<'a>(&'a FilePath);
+
+impl fmt::Display for Display<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0.to_path_buf().display(), f)
+    }
+}
+
+impl fmt::Debug for FilePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_path_buf(), f)
+    }
+}
+
+impl From<&Path> for FilePath {
+    fn from(path: &Path) -> Self {
+        match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) => Self {
+                dir: Arc::from(dir),
+                name: Box::from(name),
+            },
+            _ => Self {
+                dir: Arc::from(Path::new("")),
+                name: Box::from(path.as_os_str()),
+            },
+        }
+    }
+}
+
+impl From<PathBuf> for FilePath {
+    fn from(path: PathBuf) -> Self {
+        Self::from(path.as_path())
+    }
+}
+
+impl From<&str> for FilePath {
+    fn from(path: &str) -> Self {
+        Self::from(Path::new(path))
+    }
+}
+
+impl PartialEq for FilePath {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.dir == other.dir
+    }
+}
+
+impl Eq for FilePath {}
+
+impl PartialEq<Path> for FilePath {
+    fn eq(&self, other: &Path) -> bool {
+        self.components().eq(other.components())
+    }
+}
+
+impl Ord for FilePath {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Compared by component, like paths, since a file sorts before the contents of a
+        // sibling directory whose name is greater
+        if Arc::ptr_eq(&self.dir, &other.dir) {
+            return self.name.cmp(&other.name);
+        }
+        self.components().cmp(other.components())
+    }
+}
+
+impl PartialOrd for FilePath {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::hash::Hash for FilePath {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.dir.hash(state);
+        self.name.hash(state);
+    }
+}
+
+impl Serialize for FilePath {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_path::serialize(&self.to_path_buf(), serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FilePath {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        serde_path::deserialize(deserializer).map(Self::from)
+    }
+}
+
+/// Creates [`FilePath`]s that share one copy of each directory. Can be used from several
+/// threads at once.
+#[derive(Default)]
+pub struct PathInterner {
+    dirs: Mutex<HashSet<Arc<Path>>>,
+}
+
+impl PathInterner {
+    /// Returns `path` as a [`FilePath`], reusing the directory of an earlier path if it is
+    /// the same.
+    pub fn intern(&self, path: &Path) -> FilePath {
+        let mut file_path = FilePath::from(path);
+        let mut dirs = self.dirs.lock().unwrap();
+        match dirs.get(&file_path.dir) {
+            Some(dir) => file_path.dir = Arc::clone(dir),
+            None => {
+                dirs.insert(Arc::clone(&file_path.dir));
+            }
+        }
+        file_path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_directories_and_sorts_like_paths() {
+        let interner = PathInterner::default();
+        let a = interner.intern(Path::new("/data/a.txt"));
+        let b = interner.intern(Path::new("/data/b.txt"));
+        assert!(Arc::ptr_eq(&a.dir, &b.dir));
+        assert_eq!(b.to_path_buf(), Path::new("/data/b.txt"));
+        assert_eq!(
+            b.display().to_string(),
+            Path::new("/data/b.txt").display().to_string()
+        );
+
+        let mut paths: Vec<FilePath> = ["/data/x/y", "/data/b.txt", "/data/z", "/data/a.txt"]
+            .into_iter()
+            .map(FilePath::from)
+            .collect();
+        paths.push(a);
+        paths.sort();
+        let sorted: Vec<PathBuf> = paths.iter().map(FilePath::to_path_buf).collect();
+        assert_eq!(
+            sorted,
+            [
+                "/data/a.txt",
+                "/data/a.txt",
+                "/data/b.txt",
+                "/data/x/y",
+                "/data/z"
+            ]
+            .map(PathBuf::from)
+        );
+        assert!(sorted[3].starts_with("/data/x"));
+        assert!(paths[3].starts_with(Path::new("/data/x")));
+        assert!(!paths[3].starts_with(Path::new("/data/x/y/z")));
+    }
+
+    #[test]
+    fn escapes_paths_losslessly() {
+        #[derive(serde::Serialize, serde::Deserialize)]
+        struct Entry {
+            #[serde(with = "serde_path")]
+            path: PathBuf,
+        }
+
+        let percent = Path::new("100%/50%41.txt");
+        assert_eq!(escape_path(percent), "100%25/50%2541.txt");
+        assert_eq!(unescape_path(&escape_path(percent)), percent);
+        let json = serde_json::to_string(&Entry {
+            path: percent.to_path_buf(),
+        })
+        .unwrap();
+        assert_eq!(json, r#"{"path":"100%/50%41.txt"}"#);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let invalid = Path::new(OsStr::from_bytes(b"caf\xe9/%.txt"));
+            assert_eq!(escape_path(invalid), "caf%E9/%25.txt");
+            assert_eq!(unescape_path(&escape_path(invalid)), invalid);
+            let json = serde_json::to_string(&Entry {
+                path: invalid.to_path_buf(),
+            })
+            .unwrap();
+            assert_eq!(json, r#"{"path":{"escaped":"caf%E9/%25.txt"}}"#);
+            let entry: Entry = serde_json::from_str(&json).unwrap();
+            assert_eq!(entry.path, invalid);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Result;
+use image::imageops::FilterType;
+use log::{debug, info, warn};
+use rayon::prelude::*;
+
+use crate::FileInfo;
+use crate::content_hash::ContentHash;
+use crate::scan_root::ScanRoot;
+use crate::units::{Bytes, Count};
+use crate::utils::cluster;
+
+/// Extensions of the image formats that can be decoded for perceptual hashing.
+pub const IMAGE_EXTENSIONS: &[&str] = &["bmp", "gif", "jpeg", "jpg", "png", "tif", "tiff", "webp"];
+
+/// Returns `true` if `path` has the extension of a supported image format.
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Computes the 64-bit difference hash (dHash) of an image: the image is reduced to 9x8
+/// grayscale pixels, and each bit records whether a pixel is brighter than its right
+/// neighbour. Resizing, re-encoding and small edits change only a few bits.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or decoded.
+pub fn dhash(path: &Path) -> Result<u64> {
+    let pixels = image::open(path)?
+        .resize_exact(9, 8, FilterType::Triangle)
+        .into_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if pixels.get_pixel(x, y)[0] > pixels.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    Ok(hash)
+}
+
+/// Returns the number of bits that differ between two perceptual hashes.
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Finds clusters of visually similar images among `files`, comparing the perceptual hash
+/// of every image with the others. Only clusters with at least two different contents are
+/// returned; files with identical contents are exact duplicates and are reported as such.
+/// Members of each cluster are sorted by path.
+///
+/// Images that cannot be decoded are skipped.
+pub fn find_similar<'a>(
+    files: impl IntoIterator<Item = &'a FileInfo>,
+    max_distance: u32,
+) -> Vec<Vec<FileInfo>> {
+    // Decode each distinct content once
+    let mut by_hash: HashMap<ContentHash, Vec<&FileInfo>> = HashMap::new();
+    for file in files
+        .into_iter()
+        .filter(|file| !file.in_archive && is_image(&file.path.to_path_buf()))
+    {
+        by_hash.entry(file.hash).or_default().push(file);
+    }
+    let mut contents: Vec<Vec<&FileInfo>> = by_hash.into_values().collect();
+    contents.sort_by(|a, b| a[0].path.cmp(&b[0].path));
+    info!(
+        "Computing perceptual hashes of {} images...",
+        Count(contents.len() as u64)
+    );
+    let hashed: Vec<(Vec<&FileInfo>, u64)> = contents
+        .into_par_iter()
+        .filter_map(|members| match dhash(&members[0].path.to_path_buf()) {
+            Ok(hash) => Some((members, hash)),
+            Err(e) => {
+                debug!("Skipping '{}': {}", members[0].path.display(), e);
+                None
+            }
+        })
+        .collect();
+
+    let hashes: Vec<u64> = hashed.iter().map(|(_, hash)| *hash).collect();
+    let linked = |i: usize, j: usize| hamming_distance(hashes[i], hashes[j]) <= max_distance;
+    let mut clusters: Vec<Vec<FileInfo>> = cluster(hashes.len(), linked)
+        .into_iter()
+        .map(|indices| {
+            let mut members: Vec<FileInfo> = indices
+                .into_iter()
+                .flat_map(|i| hashed[i].0.iter().map(|file| (*file).clone()))
+                .collect();
+            members.sort_by(|a, b| a.path.cmp(&b.path));
+            members
+        })
+        .collect();
+    clusters.sort_by(|a, b| a[0].path.cmp(&b[0].path));
+    clusters
+}
+
+/// Prints clusters of similar images found by [`find_similar`], as a section separate
+/// from the exact duplicates.
+pub fn print_similar(clusters: &[Vec<FileInfo>], root: &ScanRoot) {
+    if clusters.is_empty() {
+        info!("No similar images found");
+        return;
+    }
+    warn!(
+        "Found {} clusters of similar images",
+        Count(clusters.len() as u64)
+    );
+    for cluster in clusters {
+        warn!("Similar images ({} files):", cluster.len());
+        for file in cluster {
+            warn!(
+                "  {} ({})",
+                root.display_file(&file.path).display(),
+                Bytes(file.size)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_image_extensions() {
+        assert!(is_image(Path::new("a/IMG_0001.JPG")));
+        assert!(is_image(Path::new("b.webp")));
+        assert!(!is_image(Path::new("c.mp4")));
+        assert!(!is_image(Path::new("jpg")));
+    }
+
+    #[test]
+    fn counts_differing_bits() {
+        assert_eq!(hamming_distance(0b0000, 0b0111), 3);
+        assert_eq!(hamming_distance(u64::MAX, 0), 64);
+    }
+
+    #[test]
+    fn resized_image_has_similar_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let gradient = image::RgbImage::from_fn(64, 48, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8])
+        });
+        gradient.save(dir.path().join("a.png")).unwrap();
+        image::imageops::resize(&gradient, 32, 24, FilterType::Triangle)
+            .save(dir.path().join("b.png"))
+            .unwrap();
+
+        let a = dhash(&dir.path().join("a.png")).unwrap();
+        let b = dhash(&dir.path().join("b.png")).unwrap();
+        assert!(hamming_distance(a, b) <= 4, "{:064b}\n{:064b}", a, b);
+    }
+}
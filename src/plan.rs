@@ -0,0 +1,316 @@
+//! The summary of what an action is about to do, logged before any file is changed:
+//! a tree of the directories it touches with the number of files and bytes in each, and
+//! the space each filesystem gains. With `--dry-run` nothing more happens, and with
+//! `--confirm` the action only goes ahead once confirmed.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::actions::{Action, Operation};
+use crate::units::{Bytes, Count};
+use crate::utils::disk_space;
+
+/// Most directories listed in the tree, so a plan touching thousands of directories
+/// still fits on a screen. Deeper directories are left out first.
+const MAX_TREE_LINES: usize = 40;
+
+/// Files and bytes an action touches in a directory and the directories below it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DirectoryTotals {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Space an action frees on one filesystem. Negative for the quarantine filesystem of
+/// `--move-to`, which files are moved onto.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilesystemChange {
+    /// Where the filesystem is mounted.
+    pub mount: PathBuf,
+    /// Bytes freed, or taken if negative.
+    pub freed: i128,
+    /// Bytes used before the action, if known.
+    pub used: Option<u64>,
+}
+
+/// Returns the totals of `operations` in each directory holding the files they act on
+/// and each directory above those up to `scan_path`, by path relative to it (the empty
+/// path for the scanned directory itself).
+pub fn directory_totals(
+    operations: &[(Operation, u64)],
+    scan_path: &Path,
+) -> BTreeMap<PathBuf, DirectoryTotals> {
+    let mut totals: BTreeMap<PathBuf, DirectoryTotals> = BTreeMap::new();
+    for (operation, size) in operations {
+        let dir = operation.path().parent().unwrap_or(Path::new(""));
+        let relative = dir.strip_prefix(scan_path).unwrap_or(dir);
+        for ancestor in relative.ancestors() {
+            let entry = totals.entry(ancestor.to_path_buf()).or_default();
+            entry.files += 1;
+            entry.bytes += size;
+        }
+    }
+    totals
+}
+
+/// Returns the lines of a tree of `totals`, as returned by [`directory_totals`], with
+/// the files and bytes of each directory. Directories deeper than needed to stay within
+/// [`MAX_TREE_LINES`] are left out.
+pub fn tree_lines(totals: &BTreeMap<PathBuf, DirectoryTotals>, verb: &str) -> Vec<String> {
+    let depth = |path: &Path| path.components().count();
+    let mut max_depth = totals.keys().map(|path| depth(path)).max().unwrap_or(0);
+    while max_depth > 1 && totals.keys().filter(|p| depth(p) <= max_depth).count() > MAX_TREE_LINES
+    {
+        max_depth -= 1;
+    }
+    let shown: Vec<(&PathBuf, &DirectoryTotals)> = totals
+        .iter()
+        .filter(|(path, _)| !path.as_os_str().is_empty() && depth(path) <= max_depth)
+        .collect();
+    let mut lines = Vec::new();
+    for (i, (path, dir_totals)) in shown.iter().enumerate() {
+        // Lines continue down from each ancestor followed by a sibling further down
+        let has_later_sibling = |dir: &Path| {
+            shown[i + 1..]
+                .iter()
+                .any(|(later, _)| later.parent() == dir.parent())
+        };
+        let mut prefix = String::new();
+        for ancestor_depth in 1..depth(path) {
+            let ancestor: PathBuf = path.components().take(ancestor_depth).collect();
+            prefix.push_str(if has_later_sibling(&ancestor) {
+                "│   "
+            } else {
+                "    "
+            });
+        }
+        let last = !has_later_sibling(path);
+        let name = path.file_name().map_or_else(
+            || path.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        lines.push(format!(
+            "{}{}{}{}  {} {} files ({})",
+            prefix,
+            if last { "└── " } else { "├── " },
+            name,
+            std::path::MAIN_SEPARATOR,
+            verb,
+            Count(dir_totals.files as u64),
+            Bytes(dir_totals.bytes)
+        ));
+    }
+    let hidden = totals.len() - 1 - shown.len();
+    if hidden > 0 {
+        lines.push(format!(
+            "... and {} deeper directories",
+            Count(hidden as u64)
+        ));
+    }
+    lines
+}
+
+/// Returns the space freed on each filesystem by `operations`, largest first. Deleted,
+/// hardlinked and reflinked files free their size, while files moved onto another
+/// filesystem take it there, and files moved within one filesystem free nothing.
+pub fn filesystem_changes(operations: &[(Operation, u64)]) -> Vec<FilesystemChange> {
+    let mut mounts: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut mount_of = |path: &Path| -> PathBuf {
+        let dir = existing_ancestor(path);
+        mounts
+            .entry(dir.clone())
+            .or_insert_with(|| mount_point(&dir))
+            .clone()
+    };
+    let mut freed: HashMap<PathBuf, i128> = HashMap::new();
+    for (operation, size) in operations {
+        let size = i128::from(*size);
+        let from = mount_of(operation.path());
+        if let Operation::Move { to, .. } = operation {
+            let to = mount_of(to);
+            if to == from {
+                freed.entry(from).or_default();
+                continue;
+            }
+            *freed.entry(to).or_default() -= size;
+        }
+        *freed.entry(from).or_default() += size;
+    }
+    let mut changes: Vec<FilesystemChange> = freed
+        .into_iter()
+        .map(|(mount, freed)| FilesystemChange {
+            used: disk_usage(&mount),
+            mount,
+            freed,
+        })
+        .collect();
+    changes.sort_by(|a, b| b.freed.cmp(&a.freed).then_with(|| a.mount.cmp(&b.mount)));
+    changes
+}
+
+/// Logs the plan of `action`, whose `operations` act on files under `scan_path`: the
+/// tree of directories it touches and the space each filesystem gains.
+pub fn print_plan(operations: &[(Operation, u64)], action: &Action, scan_path: &Path) {
+    let verb = match action {
+        Action::Delete => "delete",
+        Action::Hardlink => "hardlink",
+        Action::Reflink => "reflink",
+        Action::MoveTo(_) => "move",
+    };
+    if operations.is_empty() {
+        info!("Plan: nothing to {}", verb);
+        return;
+    }
+    let totals = directory_totals(operations, scan_path);
+    let all = totals.get(Path::new("")).copied().unwrap_or_default();
+    info!(
+        "Plan: {} {} files ({}) in {} directories of {}",
+        verb,
+        Count(all.files as u64),
+        Bytes(all.bytes),
+        Count(
+            operations
+                .iter()
+                .filter_map(|(operation, _)| operation.path().parent())
+                .collect::<std::collections::HashSet<_>>()
+                .len() as u64
+        ),
+        scan_path.display()
+    );
+    for line in tree_lines(&totals, verb) {
+        info!("  {}", line);
+    }
+    info!("Projected disk usage:");
+    for change in filesystem_changes(operations) {
+        let freed = u64::try_from(change.freed.unsigned_abs()).unwrap_or(u64::MAX);
+        let delta = match change.freed {
+            0 => "unchanged".to_string(),
+            d if d > 0 => format!("{} freed", Bytes(freed)),
+            _ => format!("{} more used", Bytes(freed)),
+        };
+        match change.used {
+            Some(used) => {
+                let after = u64::try_from((i128::from(used) - change.freed).max(0)).unwrap_or(0);
+                info!(
+                    "  {}: {} used -> {} ({})",
+                    change.mount.display(),
+                    Bytes(used),
+                    Bytes(after),
+                    delta
+                );
+            }
+            None => info!("  {}: {}", change.mount.display(), delta),
+        }
+    }
+}
+
+/// Asks on the terminal whether to go ahead with the plan, returning `true` only if the
+/// answer is yes. Without a terminal to ask on, the answer is no.
+///
+/// # Errors
+///
+/// Returns an error if the answer can't be read.
+pub fn confirm(question: &str) -> io::Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// Returns `path` or its nearest ancestor that exists, such as the quarantine directory
+/// above files not moved there yet.
+fn existing_ancestor(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(path)
+        .to_path_buf()
+}
+
+/// Returns the directory the filesystem holding `dir` is mounted on: the topmost
+/// ancestor on the same device on Unix, and the root of the path elsewhere.
+fn mount_point(dir: &Path) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let Ok(device) = fs::metadata(dir).map(|metadata| metadata.dev()) else {
+            return dir.to_path_buf();
+        };
+        dir.ancestors()
+            .take_while(|ancestor| {
+                fs::metadata(ancestor).is_ok_and(|metadata| metadata.dev() == device)
+            })
+            .last()
+            .unwrap_or(dir)
+            .to_path_buf()
+    }
+    #[cfg(not(unix))]
+    {
+        dir.ancestors().last().unwrap_or(dir).to_path_buf()
+    }
+}
+
+/// Returns the bytes used on the filesystem holding `path`, if known.
+fn disk_usage(path: &Path) -> Option<u64> {
+    disk_space(path).map(|space| space.total.saturating_sub(space.free))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delete(path: &str) -> (Operation, u64) {
+        (
+            Operation::Delete {
+                path: PathBuf::from(path),
+                kept: PathBuf::from("/scan/kept"),
+                hash: String::new(),
+                trashed: None,
+                attributes: None,
+            },
+            10,
+        )
+    }
+
+    #[test]
+    fn plans_are_summarized_by_directory() {
+        let operations = [
+            delete("/scan/photos/2023/a.jpg"),
+            delete("/scan/photos/2023/b.jpg"),
+            delete("/scan/photos/backup/a.jpg"),
+            delete("/scan/music/a.mp3"),
+        ];
+        let totals = directory_totals(&operations, Path::new("/scan"));
+        assert_eq!(
+            totals[Path::new("")],
+            DirectoryTotals {
+                files: 4,
+                bytes: 40
+            }
+        );
+        assert_eq!(totals[Path::new("photos")].files, 3);
+        assert_eq!(totals[Path::new("photos/2023")].bytes, 20);
+
+        let sep = std::path::MAIN_SEPARATOR;
+        let lines = tree_lines(&totals, "delete");
+        assert_eq!(
+            lines,
+            [
+                format!("├── music{sep}  delete 1 files (10 B)"),
+                format!("└── photos{sep}  delete 3 files (30 B)"),
+                format!("    ├── 2023{sep}  delete 2 files (20 B)"),
+                format!("    └── backup{sep}  delete 1 files (10 B)"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,403 @@
+//! `--size-only` and `--match name-size`: a first look at a new volume in the time it
+//! takes to list it. Files are grouped by size, or by name and size, without reading any
+//! of them, so the groups are only potential duplicates: files of the same size often
+//! differ, but files of different sizes never match, so a full scan only ever finds
+//! duplicates among the groups by size. Groups by name and size have IDs, so a second
+//! run with `--confirm-group` can hash only the files of the groups worth a closer look.
+//! `--hash none` also matches modification times, for media such as tape or optical
+//! discs where reading every file is impractical: files with the same name, size and
+//! modification time are very likely, though not certain, to be copies.
+
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use colored::Colorize;
+use log::{info, warn};
+use rayon::prelude::*;
+
+use crate::content_hash::{ContentHash, GroupId};
+use crate::scan_errors::ScanErrors;
+use crate::scan_root::ScanRoot;
+use crate::scanner::{
+    ModifiedRange, NameFilter, ScanLimit, auto_walk_threads, file_id, walk_directory,
+};
+use crate::units::{Bytes, Count};
+
+/// Whether a scan reads the files, for `--hash`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum HashMode {
+    /// Hash the contents of the files
+    #[default]
+    Content,
+    /// Read no file, matching files by name, size and modification time. Unverified, but
+    /// likely duplicates
+    None,
+}
+
+/// What files must share to be potential duplicates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PotentialMatch {
+    /// Their size, for `--size-only`.
+    Size,
+    /// Their name and size, for `--match name-size`.
+    NameSize,
+    /// Their name, size and modification time, for `--hash none`.
+    NameSizeMtime,
+}
+
+impl PotentialMatch {
+    fn by_name(self) -> bool {
+        self != Self::Size
+    }
+
+    /// Describes what the files of a group share.
+    pub fn shared(self) -> &'static str {
+        match self {
+            Self::Size => "size",
+            Self::NameSize => "name and size",
+            Self::NameSizeMtime => "name, size and modification time",
+        }
+    }
+}
+
+/// Files sharing a size, and a name and modification time when matched by them, which
+/// may or may not have the same contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PotentialGroup {
+    pub size: u64,
+    /// The file name shared by the files, when matched by name and size.
+    pub name: Option<OsString>,
+    /// The modification time shared by the files, when matched by it too.
+    pub modified: Option<SystemTime>,
+    /// The files, in path order.
+    pub paths: Vec<PathBuf>,
+}
+
+impl PotentialGroup {
+    /// Space the group would waste if all its files were the same.
+    pub fn potential_waste(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+
+    /// Returns the ID of a group matched by name and size, which comes from the name,
+    /// size and modification time matched, so it stays the same from run to run.
+    pub fn id(&self) -> Option<String> {
+        let name = self.name.as_ref()?;
+        let mut key = self.size.to_le_bytes().to_vec();
+        key.extend_from_slice(name.as_encoded_bytes());
+        if let Some(since) = self
+            .modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        {
+            key.extend_from_slice(&since.as_nanos().to_le_bytes());
+        }
+        Some(ContentHash::of(&key).group_id())
+    }
+}
+
+/// Lists the files under `root.scan_path()` like a scan and groups those sharing what
+/// `by` asks for, without reading them, largest potential waste first.
+/// Empty files and files smaller than `min_size` are left out, like files modified
+/// outside `modified` and those left out by `names`. Hardlinks of one file are counted
+/// once.
+pub fn find_potential(
+    root: &ScanRoot,
+    skip_dirs: &[String],
+    names: &NameFilter,
+    min_size: u64,
+    modified: &ModifiedRange,
+    by: PotentialMatch,
+) -> Result<Vec<PotentialGroup>> {
+    let mut errors = ScanErrors::default();
+    let (file_paths, _) = walk_directory(
+        root,
+        skip_dirs,
+        names,
+        min_size,
+        modified,
+        &ScanLimit::default(),
+        false,
+        false,
+        None,
+        auto_walk_threads(),
+        &mut errors,
+    )?;
+    let files: Vec<_> = file_paths
+        .into_par_iter()
+        .filter_map(|path| {
+            let metadata = fs::metadata(&path).ok()?;
+            let id = file_id(&metadata);
+            Some((path, metadata.len(), id, metadata.modified().ok()))
+        })
+        .collect();
+
+    // Hardlinks of one file share its data, so only the first is counted
+    let mut seen = HashSet::new();
+    let mut by_key: BTreeMap<(u64, Option<OsString>, Option<SystemTime>), Vec<PathBuf>> =
+        BTreeMap::new();
+    for (path, size, id, modified) in files {
+        if size > 0 && id.is_none_or(|id| seen.insert(id)) {
+            let name = by
+                .by_name()
+                .then(|| path.file_name().unwrap_or_default().to_os_string());
+            let modified = modified.filter(|_| by == PotentialMatch::NameSizeMtime);
+            by_key.entry((size, name, modified)).or_default().push(path);
+        }
+    }
+    let mut groups: Vec<PotentialGroup> = by_key
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|((size, name, modified), paths)| PotentialGroup {
+            size,
+            name,
+            modified,
+            paths,
+        })
+        .collect();
+    groups.sort_by_key(|group| std::cmp::Reverse(group.potential_waste()));
+    Ok(groups)
+}
+
+/// Returns the files of the `groups` with one of the `ids`, for `--confirm-group`.
+///
+/// # Errors
+///
+/// Returns an error if an ID matches none of the groups.
+pub fn confirm_paths(groups: &[PotentialGroup], ids: &[GroupId]) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for id in ids {
+        let matched: Vec<&PotentialGroup> = groups
+            .iter()
+            .filter(|group| {
+                group
+                    .id()
+                    .is_some_and(|group_id| group_id.starts_with(&id.to_string()))
+            })
+            .collect();
+        if matched.is_empty() {
+            anyhow::bail!("No group of potential duplicates has the ID {}", id);
+        }
+        paths.extend(matched.into_iter().flat_map(|group| group.paths.clone()));
+    }
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+/// Prints `groups` as potential duplicates, only the first `top` if given, with paths
+/// relative to the scanned directory. `by` says what they were matched by.
+pub fn print_potential(
+    groups: &[PotentialGroup],
+    root: &ScanRoot,
+    top: Option<usize>,
+    by: PotentialMatch,
+) {
+    if groups.is_empty() {
+        info!(
+            "{}",
+            format!(
+                "No files share their {}, so there are no duplicates!",
+                by.shared()
+            )
+            .green()
+        );
+        return;
+    }
+    let files: u64 = groups
+        .iter()
+        .map(|group| group.paths.len() as u64 - 1)
+        .sum();
+    let waste: u64 = groups.iter().map(PotentialGroup::potential_waste).sum();
+    warn!(
+        "Found {} potential duplicate files, which would waste up to {} of space",
+        Count(files),
+        Bytes(waste)
+    );
+    let shown = &groups[..top.unwrap_or(groups.len()).min(groups.len())];
+    if shown.len() < groups.len() {
+        info!(
+            "Showing {} of {} potential duplicate groups",
+            Count(shown.len() as u64),
+            Count(groups.len() as u64)
+        );
+    }
+    let size_width = shown
+        .iter()
+        .map(|group| Bytes(group.size).to_string().len())
+        .max()
+        .unwrap_or(0);
+    let index_width = shown.len().to_string().len() + 1;
+    for (index, group) in shown.iter().enumerate() {
+        let id = group
+            .id()
+            .map_or_else(String::new, |id| format!("[{}] ", id).dimmed().to_string());
+        warn!(
+            "{} {}{}  {} files of the same {}, potentially wasting {}",
+            format!("{:<index_width$}", format!("#{}", index + 1)).bold(),
+            id,
+            format!("{:>size_width$}", Bytes(group.size).to_string()).bold(),
+            group.paths.len(),
+            by.shared(),
+            Bytes(group.potential_waste())
+        );
+        for path in &group.paths {
+            warn!("       {}", root.display(path).display());
+        }
+    }
+    match by {
+        PotentialMatch::NameSizeMtime => warn!(
+            "{}",
+            "These are unverified but likely duplicates, matched by name, size and modification time without reading them: run again with --confirm-group ID to hash the files of a group"
+                .yellow()
+        ),
+        PotentialMatch::NameSize => warn!(
+            "{}",
+            "These are potential duplicates, matched by name and size only: run again with --confirm-group ID to hash the files of a group"
+                .yellow()
+        ),
+        PotentialMatch::Size => warn!(
+            "{}",
+            "These are potential duplicates, grouped by size only: run without --size-only to compare their contents"
+                .yellow()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_files_by_size_without_reading_them() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, contents) in [
+            ("a1", "aaaa"),
+            ("a2", "bbbb"),
+            ("b1", "bbbbbb"),
+            ("b2", "bbbbbb"),
+            ("b3", "cccccc"),
+            ("unique", "u"),
+            ("empty1", ""),
+            ("empty2", ""),
+        ] {
+            fs::write(dir.path().join(name), contents).unwrap();
+        }
+        let root = ScanRoot::for_base(dir.path());
+        let groups = find_potential(
+            &root,
+            &[],
+            &NameFilter::default(),
+            0,
+            &ModifiedRange::default(),
+            PotentialMatch::Size,
+        )
+        .unwrap();
+        let base = root.scan_path();
+        assert_eq!(
+            groups,
+            [
+                PotentialGroup {
+                    size: 6,
+                    name: None,
+                    modified: None,
+                    paths: vec![base.join("b1"), base.join("b2"), base.join("b3")],
+                },
+                PotentialGroup {
+                    size: 4,
+                    name: None,
+                    modified: None,
+                    paths: vec![base.join("a1"), base.join("a2")],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_names_and_sizes_and_confirms_groups_by_id() {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, contents) in [
+            ("a/photo.jpg", "same"),
+            ("b/photo.jpg", "diff"),
+            ("b/other.jpg", "same"),
+            ("a/notes.txt", "notes"),
+            ("b/notes.txt", "notes"),
+        ] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let root = ScanRoot::for_base(dir.path());
+        let groups = find_potential(
+            &root,
+            &[],
+            &NameFilter::default(),
+            0,
+            &ModifiedRange::default(),
+            PotentialMatch::NameSize,
+        )
+        .unwrap();
+        assert_eq!(groups.len(), 2);
+        let photos = groups
+            .iter()
+            .find(|group| group.name.as_deref() == Some("photo.jpg".as_ref()))
+            .unwrap();
+        let base = root.scan_path();
+        let id: GroupId = photos.id().unwrap().parse().unwrap();
+        assert_eq!(
+            confirm_paths(&groups, &[id]).unwrap(),
+            [base.join("a/photo.jpg"), base.join("b/photo.jpg")]
+        );
+        assert!(confirm_paths(&groups, &["000000000000".parse().unwrap()]).is_err());
+    }
+
+    #[test]
+    fn matches_modification_times_with_hash_none() {
+        let dir = tempfile::tempdir().unwrap();
+        let taken = UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+        for (path, modified) in [
+            ("tape1/scan.tif", taken),
+            ("tape2/scan.tif", taken),
+            ("tape3/scan.tif", taken + std::time::Duration::from_secs(60)),
+        ] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "pixels").unwrap();
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+        let root = ScanRoot::for_base(dir.path());
+        let find = |by| {
+            find_potential(
+                &root,
+                &[],
+                &NameFilter::default(),
+                0,
+                &ModifiedRange::default(),
+                by,
+            )
+            .unwrap()
+        };
+        let groups = find(PotentialMatch::NameSizeMtime);
+        let base = root.scan_path();
+        assert_eq!(
+            groups,
+            [PotentialGroup {
+                size: 6,
+                name: Some("scan.tif".into()),
+                modified: Some(taken),
+                paths: vec![base.join("tape1/scan.tif"), base.join("tape2/scan.tif")],
+            }]
+        );
+        // The modification time is part of the ID, so groups by name and size differ
+        assert_ne!(groups[0].id(), find(PotentialMatch::NameSize)[0].id());
+    }
+}
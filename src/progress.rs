@@ -0,0 +1,410 @@
+use std::cell::Cell;
+use std::fmt;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use indicatif::{ProgressBar, ProgressState, ProgressStyle};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::units::{Bytes, Count};
+use crate::utils::new_progress_bar;
+
+/// How often the bar is redrawn from the counters.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often `--progress json` writes an event while a phase runs.
+const EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How progress is shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressFormat {
+    /// Spinners and progress bars on the terminal
+    #[default]
+    Bar,
+    /// One JSON object per line with the phase, files and bytes done and the rate, for
+    /// programs wrapping this one
+    Json,
+}
+
+/// Whether progress events are written, checked before building one.
+static EVENTS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Where progress events go, set once at startup.
+struct EventSink {
+    out: Box<dyn Write + Send>,
+    /// When the last event still in a phase was written.
+    last: Option<Instant>,
+}
+
+static EVENTS: Mutex<Option<EventSink>> = Mutex::new(None);
+
+/// Writes progress events to `out` from now on, for `--progress json`.
+pub fn set_progress_events(out: Box<dyn Write + Send>) {
+    *EVENTS.lock().unwrap() = Some(EventSink { out, last: None });
+    EVENTS_ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Writes a progress event of `phase` with `fields`, as one line of JSON like
+/// `{"event":"progress","phase":"hash","files_done":10,...}`. Events are written at most
+/// every [`EVENT_INTERVAL`], except the last one of a phase, marked `done`, so they
+/// can be written from the hot path. Nothing is built unless events are enabled.
+pub fn progress_event(phase: &str, done: bool, fields: impl FnOnce() -> Value) {
+    if !EVENTS_ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut events = EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(EventSink { out, last }) = events.as_mut() else {
+        return;
+    };
+    if !done && last.is_some_and(|last| last.elapsed() < EVENT_INTERVAL) {
+        return;
+    }
+    *last = (!done).then(Instant::now);
+    let mut event = json!({"event": "progress", "phase": phase, "done": done});
+    if let (Some(event), Value::Object(fields)) = (event.as_object_mut(), fields()) {
+        event.extend(fields);
+    }
+    // A wrapper that went away shouldn't stop the scan
+    let _ = writeln!(out, "{}", event).and_then(|()| out.flush());
+}
+
+/// Counters of a scan, updated by the hashing threads and read by the reporter thread.
+#[derive(Default)]
+struct Counters {
+    bytes: AtomicU64,
+    files: AtomicU64,
+    cache_hits: AtomicU64,
+    cached_bytes: AtomicU64,
+    hashed_files: AtomicU64,
+    hashed_bytes: AtomicU64,
+    duplicate_groups: AtomicU64,
+}
+
+impl Counters {
+    /// Writes the counters as a `hash` progress event, `elapsed` into the phase.
+    fn emit(&self, total_bytes: u64, elapsed: Duration, done: bool) {
+        progress_event("hash", done, || {
+            let bytes = self.bytes.load(Ordering::Relaxed);
+            json!({
+                "files_done": self.files.load(Ordering::Relaxed),
+                "bytes_done": bytes,
+                "bytes_total": total_bytes,
+                "bytes_per_sec": (bytes as f64 / elapsed.as_secs_f64().max(0.001)) as u64,
+                "cache_hits": self.cache_hits.load(Ordering::Relaxed),
+                "duplicate_groups": self.duplicate_groups.load(Ordering::Relaxed),
+            })
+        });
+    }
+
+    /// Draws the counters on `bar`.
+    fn show(&self, bar: &ProgressBar) {
+        let files = self.files.load(Ordering::Relaxed);
+        bar.set_position(self.bytes.load(Ordering::Relaxed));
+        bar.set_message(format!(
+            "{} files, {:.0}% cached, {} duplicate groups",
+            Count(files),
+            self.cache_hits.load(Ordering::Relaxed) as f64 * 100.0 / files.max(1) as f64,
+            Count(self.duplicate_groups.load(Ordering::Relaxed))
+        ));
+    }
+}
+
+/// Progress of hashing the files found by a scan, shown as a bar over their total size
+/// with throughput, ETA, and how many hashes came from the cache. Bytes are counted as
+/// they are read, so the bar moves smoothly through large files, and the duplicate groups
+/// confirmed so far are counted. Shared by all threads of a scan.
+///
+/// Hashing threads only update counters. The bar is drawn from them by a reporter thread
+/// every [`REFRESH_INTERVAL`], and once more by [`ScanProgress::finish`], so it ends on
+/// the final counts.
+pub struct ScanProgress {
+    bar: ProgressBar,
+    counters: Arc<Counters>,
+    /// Stops the reporter thread when sent to or dropped.
+    reporter: Mutex<Option<(Sender<()>, JoinHandle<()>)>>,
+}
+
+impl ScanProgress {
+    /// Creates a progress bar over `total_bytes` to hash, and starts drawing it.
+    pub fn new(total_bytes: u64) -> Self {
+        let bar = new_progress_bar(total_bytes);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {percent}% {size}/{total_size} ({size_per_sec}, ETA {eta}) {msg}")
+                .unwrap()
+                .with_key("size", |state: &ProgressState, w: &mut dyn fmt::Write| {
+                    write!(w, "{}", Bytes(state.pos())).unwrap()
+                })
+                .with_key("total_size", |state: &ProgressState, w: &mut dyn fmt::Write| {
+                    write!(w, "{}", Bytes(state.len().unwrap_or(0))).unwrap()
+                })
+                .with_key("size_per_sec", |state: &ProgressState, w: &mut dyn fmt::Write| {
+                    write!(w, "{}/s", Bytes(state.per_sec() as u64)).unwrap()
+                })
+                .progress_chars("#>-"),
+        );
+        let counters = Arc::new(Counters::default());
+        let (stop, stopped) = mpsc::channel();
+        let reporter = thread::spawn({
+            let bar = bar.clone();
+            let counters = Arc::clone(&counters);
+            move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(REFRESH_INTERVAL) {
+                    counters.show(&bar);
+                    counters.emit(total_bytes, bar.elapsed(), false);
+                }
+            }
+        });
+        Self {
+            bar,
+            counters,
+            reporter: Mutex::new(Some((stop, reporter))),
+        }
+    }
+
+    /// Counts a duplicate group confirmed while the scan is still running.
+    pub fn duplicate_found(&self) {
+        self.counters
+            .duplicate_groups
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Starts tracking the progress of a single file.
+    pub fn file(&self) -> FileProgress<'_> {
+        FileProgress {
+            counters: &self.counters,
+            read: Cell::new(0),
+            cached: Cell::new(false),
+        }
+    }
+
+    /// Returns where the hashes of the files done so far came from. Files that couldn't
+    /// be read are left out.
+    pub fn stats(&self) -> HashStats {
+        let counters = &self.counters;
+        HashStats {
+            cached_files: counters.cache_hits.load(Ordering::Relaxed),
+            cached_bytes: counters.cached_bytes.load(Ordering::Relaxed),
+            hashed_files: counters.hashed_files.load(Ordering::Relaxed),
+            hashed_bytes: counters.hashed_bytes.load(Ordering::Relaxed),
+            ..HashStats::default()
+        }
+    }
+
+    /// Stops the reporter thread, if still running.
+    fn stop_reporter(&self) {
+        let reporter = self
+            .reporter
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some((stop, reporter)) = reporter {
+            drop(stop);
+            let _ = reporter.join();
+        }
+    }
+
+    /// Completes the bar once all files are done, showing the final counts.
+    pub fn finish(&self) {
+        self.stop_reporter();
+        self.counters.show(&self.bar);
+        self.counters
+            .emit(self.bar.length().unwrap_or(0), self.bar.elapsed(), true);
+        self.bar.finish_with_message(format!(
+            "Scan complete! {} files, {} duplicate groups",
+            Count(self.counters.files.load(Ordering::Relaxed)),
+            Count(self.counters.duplicate_groups.load(Ordering::Relaxed))
+        ));
+    }
+}
+
+impl Drop for ScanProgress {
+    fn drop(&mut self) {
+        self.stop_reporter();
+    }
+}
+
+/// Progress of a single file of a scan, see [`ScanProgress::file`].
+pub struct FileProgress<'a> {
+    counters: &'a Counters,
+    /// Bytes of the file counted so far.
+    read: Cell<u64>,
+    cached: Cell<bool>,
+}
+
+impl FileProgress<'_> {
+    /// Accounts for `bytes` just read from the file.
+    pub fn read(&self, bytes: u64) {
+        self.read.set(self.read.get() + bytes);
+        self.counters.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Takes back the bytes read so far, before the file is read again.
+    pub fn restart(&self) {
+        self.counters
+            .bytes
+            .fetch_sub(self.read.replace(0), Ordering::Relaxed);
+    }
+
+    /// Records that the hash came from the cache, without reading the file.
+    pub fn cache_hit(&self) {
+        self.cached.set(true);
+    }
+
+    /// Marks the file done, moving the bar past all of its `size` bytes. Only files whose
+    /// hash was `found` count towards the [`HashStats`].
+    pub fn finish(self, size: u64, found: bool) {
+        let counters = self.counters;
+        counters
+            .bytes
+            .fetch_add(size.saturating_sub(self.read.get()), Ordering::Relaxed);
+        counters.files.fetch_add(1, Ordering::Relaxed);
+        if found && self.cached.get() {
+            counters.cache_hits.fetch_add(1, Ordering::Relaxed);
+            counters.cached_bytes.fetch_add(size, Ordering::Relaxed);
+        } else if found {
+            counters.hashed_files.fetch_add(1, Ordering::Relaxed);
+            counters.hashed_bytes.fetch_add(size, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Where the hashes of a scan came from, to show whether the cache helps, and how long
+/// listing and hashing the files took.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HashStats {
+    /// Files whose hashes came from the cache (or saved scan progress).
+    pub cached_files: u64,
+    /// Total size of those files, i.e. the bytes the cache saved reading.
+    pub cached_bytes: u64,
+    /// Files read and hashed.
+    pub hashed_files: u64,
+    /// Total size of the files read and hashed.
+    pub hashed_bytes: u64,
+    /// Time taken to list the files to hash.
+    pub walk_time: Duration,
+    /// Time taken to hash the files, or take their hashes from the cache.
+    pub hash_time: Duration,
+}
+
+impl HashStats {
+    /// Share of the files whose hashes came from the cache, in percent.
+    pub fn hit_rate(&self) -> f64 {
+        let files = self.cached_files + self.hashed_files;
+        if files == 0 {
+            0.0
+        } else {
+            self.cached_files as f64 * 100.0 / files as f64
+        }
+    }
+}
+
+/// Logs how many hashes came from the cache and how much reading it saved.
+pub fn print_hash_stats(stats: &HashStats, use_cache: bool) {
+    if !use_cache {
+        info!(
+            files = stats.hashed_files,
+            bytes = stats.hashed_bytes,
+            duration = stats.hash_time.as_secs_f64();
+            "Hashed {} files ({}) without the cache",
+            Count(stats.hashed_files),
+            Bytes(stats.hashed_bytes)
+        );
+        return;
+    }
+    info!(
+        files = stats.hashed_files,
+        bytes = stats.hashed_bytes,
+        cached_files = stats.cached_files,
+        cached_bytes = stats.cached_bytes,
+        duration = stats.hash_time.as_secs_f64();
+        "Hashed {} files ({}), took {} hashes from the cache ({:.1}% hit rate), saving {} of reading",
+        Count(stats.hashed_files),
+        Bytes(stats.hashed_bytes),
+        Count(stats.cached_files),
+        stats.hit_rate(),
+        Bytes(stats.cached_bytes)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_bytes_once_per_file() {
+        let progress = ScanProgress::new(100);
+        let file = progress.file();
+        file.read(30);
+        file.restart();
+        file.read(40);
+        file.finish(60, true);
+        let file = progress.file();
+        file.cache_hit();
+        file.finish(40, true);
+        progress.file().finish(0, false);
+        progress.finish();
+
+        assert_eq!(progress.bar.position(), 100);
+        assert!(progress.bar.message().starts_with("Scan complete! 3 files"));
+        assert_eq!(
+            progress.stats(),
+            HashStats {
+                cached_files: 1,
+                cached_bytes: 40,
+                hashed_files: 1,
+                hashed_bytes: 60,
+                ..HashStats::default()
+            }
+        );
+        assert_eq!(progress.stats().hit_rate(), 50.0);
+    }
+
+    /// Collects the events written, shared with the test.
+    #[derive(Clone, Default)]
+    struct Events(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Events {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_json_progress_events() {
+        let events = Events::default();
+        set_progress_events(Box::new(events.clone()));
+        let progress = ScanProgress::new(1234);
+        progress.file().finish(1000, true);
+        progress.file().finish(234, true);
+        progress.duplicate_found();
+        progress.finish();
+
+        // Other tests running at the same time may write events too
+        let written = String::from_utf8(events.0.lock().unwrap().clone()).unwrap();
+        let done: Vec<Value> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .filter(|event: &Value| event["phase"] == "hash" && event["done"] == true)
+            .collect();
+        assert!(done.iter().any(|event| {
+            event["event"] == "progress"
+                && event["files_done"] == 2
+                && event["bytes_done"] == 1234
+                && event["bytes_total"] == 1234
+                && event["duplicate_groups"] == 1
+        }));
+    }
+}
@@ -0,0 +1,258 @@
+//! Reflinks (clones): files sharing their data blocks on copy-on-write filesystems such
+//! as btrfs, XFS and APFS, until either is modified. Supported on Linux (`FICLONE`) and
+//! macOS (`clonefile`). On Linux, files that already share their data are recognized
+//! from their extents (`FIEMAP`), so `--detect-reflinks` can leave them out of the
+//! wasted space and skip reading them.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use rayon::prelude::*;
+
+use crate::FileInfo;
+use crate::content_hash::ContentHash;
+use crate::units::Count;
+
+/// Identifies the data blocks of a file shared with other files, by path. Files with
+/// the same ID are reflinks of each other. Files sharing nothing are missing.
+pub type ExtentIds = HashMap<PathBuf, u64>;
+
+/// Creates `dest`, which must not exist, as a reflink of `source`.
+///
+/// # Errors
+///
+/// Returns an error if the filesystem doesn't support reflinks, the files are on
+/// different filesystems, or `dest` can't be created.
+pub fn clone_file(source: &Path, dest: &Path) -> io::Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::os::fd::AsRawFd;
+
+        let source = fs::File::open(source)?;
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dest)?;
+        // SAFETY: both are open file descriptors, kept alive for the call
+        if unsafe { libc::ioctl(file.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) } != 0 {
+            let error = io::Error::last_os_error();
+            drop(file);
+            let _ = fs::remove_file(dest);
+            return Err(error);
+        }
+        Ok(())
+    }
+    #[cfg(target_os = "macos")]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let c_path = |path: &Path| {
+            CString::new(path.as_os_str().as_bytes())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+        };
+        let (source, dest) = (c_path(source)?, c_path(dest)?);
+        // SAFETY: both are NUL-terminated strings that outlive the call
+        if unsafe { libc::clonefile(source.as_ptr(), dest.as_ptr(), 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+    {
+        let _ = (source, dest);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "reflinks are not supported on this platform",
+        ))
+    }
+}
+
+/// Checks whether files in `dir` can be reflinked to `source`, by reflinking it to a
+/// temporary file there.
+///
+/// # Errors
+///
+/// Returns the error of the attempt if reflinking is not possible.
+pub fn probe(source: &Path, dir: &Path) -> io::Result<()> {
+    let probe = dir.join(format!(".{}-reflink-probe.tmp", env!("CARGO_PKG_NAME")));
+    clone_file(source, &probe)?;
+    fs::remove_file(&probe)
+}
+
+/// Returns an ID of where the data of the file at `path` is stored on disk, if any of it
+/// is shared with other files: two files with the same ID and size are reflinks of each
+/// other. Returns `None` for files sharing nothing, and on filesystems and platforms
+/// where this can't be found out.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be opened or its extents can't be read.
+pub fn extent_id(path: &Path) -> io::Result<Option<u64>> {
+    #[cfg(target_os = "linux")]
+    {
+        fiemap::extent_id(path)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        Ok(None)
+    }
+}
+
+/// Finds the members of `duplicates` on disk that share their data with other files, and
+/// logs how many there are.
+pub fn read_extent_ids(duplicates: &HashMap<ContentHash, Vec<FileInfo>>) -> ExtentIds {
+    let paths: Vec<PathBuf> = duplicates
+        .values()
+        .flatten()
+        .filter(|file| !file.in_archive)
+        .map(|file| file.path.to_path_buf())
+        .collect();
+    let ids: ExtentIds = paths
+        .into_par_iter()
+        .filter_map(|path| match extent_id(&path) {
+            Ok(id) => Some((path, id?)),
+            Err(e) => {
+                warn!("Failed to read the extents of '{}': {}", path.display(), e);
+                None
+            }
+        })
+        .collect();
+    if !ids.is_empty() {
+        info!(
+            "Found {} duplicates sharing their data through reflinks, which free no space",
+            Count(ids.len() as u64)
+        );
+    }
+    ids
+}
+
+#[cfg(target_os = "linux")]
+mod fiemap {
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::AsRawFd;
+    use std::path::Path;
+
+    /// `_IOWR('f', 11, struct fiemap)`
+    const FS_IOC_FIEMAP: libc::c_ulong = 0xC020_660B;
+    const FIEMAP_FLAG_SYNC: u32 = 0x1;
+    const FIEMAP_EXTENT_LAST: u32 = 0x1;
+    const FIEMAP_EXTENT_SHARED: u32 = 0x2000;
+    /// Extents read per call.
+    const EXTENTS: usize = 64;
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct Extent {
+        logical: u64,
+        physical: u64,
+        length: u64,
+        reserved64: [u64; 2],
+        flags: u32,
+        reserved: [u32; 3],
+    }
+
+    #[repr(C)]
+    struct Fiemap {
+        start: u64,
+        length: u64,
+        flags: u32,
+        mapped_extents: u32,
+        extent_count: u32,
+        reserved: u32,
+        extents: [Extent; EXTENTS],
+    }
+
+    pub fn extent_id(path: &Path) -> io::Result<Option<u64>> {
+        let file = File::open(path)?;
+        let mut hasher = blake3::Hasher::new();
+        let mut shared = false;
+        let mut start = 0;
+        loop {
+            let mut map = Fiemap {
+                start,
+                length: u64::MAX - start,
+                flags: FIEMAP_FLAG_SYNC,
+                mapped_extents: 0,
+                extent_count: EXTENTS as u32,
+                reserved: 0,
+                extents: [Extent::default(); EXTENTS],
+            };
+            // SAFETY: `map` is a `struct fiemap` with room for `extent_count` extents
+            if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_FIEMAP as _, &mut map) } != 0 {
+                let error = io::Error::last_os_error();
+                return match error.raw_os_error() {
+                    Some(libc::EOPNOTSUPP | libc::ENOTTY | libc::EINVAL) => Ok(None),
+                    _ => Err(error),
+                };
+            }
+            let extents = &map.extents[..map.mapped_extents as usize];
+            for extent in extents {
+                shared |= extent.flags & FIEMAP_EXTENT_SHARED != 0;
+                hasher.update(&extent.logical.to_le_bytes());
+                hasher.update(&extent.physical.to_le_bytes());
+                hasher.update(&extent.length.to_le_bytes());
+            }
+            match extents.last() {
+                Some(last) if last.flags & FIEMAP_EXTENT_LAST == 0 => {
+                    start = last.logical + last.length;
+                }
+                _ => break,
+            }
+        }
+        if !shared {
+            return Ok(None);
+        }
+        let hash = hasher.finalize();
+        Ok(Some(u64::from_le_bytes(
+            hash.as_bytes()[..8].try_into().unwrap(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_or_leaves_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        fs::write(&source, "data").unwrap();
+        let dest = dir.path().join("dest");
+        match clone_file(&source, &dest) {
+            Ok(()) => {
+                assert_eq!(fs::read_to_string(&dest).unwrap(), "data");
+                assert!(probe(&source, dir.path()).is_ok());
+            }
+            Err(_) => {
+                assert!(!dest.exists());
+                assert!(probe(&source, dir.path()).is_err());
+            }
+        }
+        let probe_file = format!(".{}-reflink-probe.tmp", env!("CARGO_PKG_NAME"));
+        assert!(!dir.path().join(probe_file).exists());
+    }
+
+    #[test]
+    fn recognizes_reflinks_by_their_extents() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        fs::write(&source, vec![7; 64 * 1024]).unwrap();
+        // Written rather than copied, since copies can be reflinks themselves
+        let copy = dir.path().join("copy");
+        fs::write(&copy, vec![7; 64 * 1024]).unwrap();
+        assert_eq!(extent_id(&copy).unwrap(), None);
+        let clone = dir.path().join("clone");
+        if clone_file(&source, &clone).is_ok() {
+            let id = extent_id(&source).unwrap();
+            assert!(id.is_some());
+            assert_eq!(extent_id(&clone).unwrap(), id);
+        }
+    }
+}
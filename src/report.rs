@@ -0,0 +1,880 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+use crate::FileInfo;
+use crate::content_hash::{ContentHash, GROUP_ID_LEN};
+use crate::duplicates::{ReportOptions, SortBy, distinct_copies, wasted_space};
+use crate::file_type::FileType;
+use crate::scan_errors::ScanErrors;
+use crate::scan_root::{exported_path, shown_path};
+use crate::units::{Bytes, Count};
+
+/// First bytes of a Zstandard frame, which tell the results of the last scan apart from
+/// reports saved as plain JSON.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// A single group of files sharing identical content.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    /// BLAKE3 hash shared by all members of the group.
+    pub hash: String,
+    /// ID of the group, the start of its hash, to pick it with `--group` in later runs.
+    /// Empty in reports written before this field existed.
+    #[serde(default)]
+    pub id: String,
+    /// Size of each member in bytes.
+    pub size: u64,
+    /// Paths of all members of the group.
+    #[serde(with = "crate::paths::serde_path::vec")]
+    pub paths: Vec<PathBuf>,
+    /// Number of distinct copies of the data; members that are hardlinks of each other
+    /// count once. Reports written before this field existed default to `paths.len()`.
+    #[serde(default)]
+    pub copies: usize,
+    /// Space reclaimable by removing the redundant copies, in bytes. Reports written
+    /// before this field existed default to `size * (copies - 1)`.
+    #[serde(default)]
+    pub wasted_space: Option<u64>,
+    /// Members that are gzip or Zstandard files whose decompressed contents match, with
+    /// `--decompress-before-hash`, rather than identical files.
+    #[serde(
+        default,
+        with = "crate::paths::serde_path::vec",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub compressed: Vec<PathBuf>,
+}
+
+impl DuplicateGroup {
+    /// Builds a group from the members sharing `hash`, with paths in sorted order.
+    pub fn from_files(hash: &ContentHash, files: &[FileInfo], options: &ReportOptions) -> Self {
+        let mut paths: Vec<PathBuf> = files.iter().map(|f| f.path.to_path_buf()).collect();
+        paths.sort();
+        let mut compressed: Vec<PathBuf> = files
+            .iter()
+            .filter(|f| f.compressed)
+            .map(|f| f.path.to_path_buf())
+            .collect();
+        compressed.sort();
+        Self {
+            hash: hash.to_string(),
+            id: hash.group_id(),
+            size: files[0].size,
+            paths,
+            copies: distinct_copies(files),
+            wasted_space: Some(wasted_space(files, options)),
+            compressed,
+        }
+    }
+
+    /// ID of the group, also for reports written before it was recorded.
+    pub fn group_id(&self) -> &str {
+        if self.id.is_empty() {
+            &self.hash[..self.hash.len().min(GROUP_ID_LEN)]
+        } else {
+            &self.id
+        }
+    }
+
+    /// Number of distinct copies of the data in this group.
+    pub fn copies(&self) -> usize {
+        if self.copies == 0 {
+            self.paths.len()
+        } else {
+            self.copies
+        }
+    }
+
+    /// Space that would be reclaimed by keeping a single copy of this group.
+    pub fn wasted_space(&self) -> u64 {
+        self.wasted_space
+            .unwrap_or_else(|| self.size * (self.copies() as u64).saturating_sub(1))
+    }
+}
+
+/// A serializable snapshot of the duplicates found by a single run.
+///
+/// Reports are written as pretty-printed JSON so that they can be inspected by hand,
+/// and can be compared against each other with [`DuplicateReport::diff`].
+#[derive(Serialize, Deserialize)]
+pub struct DuplicateReport {
+    /// Version of check-file-dups that produced the report.
+    pub version: String,
+    /// RFC 3339 timestamp of when the report was generated.
+    pub generated_at: String,
+    /// Directory that was scanned.
+    #[serde(with = "crate::paths::serde_path")]
+    pub scan_path: PathBuf,
+    /// Number of redundant files (excluding one copy per group).
+    pub total_duplicates: usize,
+    /// Total space used by redundant files, in bytes.
+    pub total_wasted_space: u64,
+    /// Duplicate groups, sorted by wasted space (largest first). Groups whose members are
+    /// all hardlinks of a single file are omitted.
+    pub groups: Vec<DuplicateGroup>,
+    /// How the copies are spread over the groups. Reports written before this field
+    /// existed default to all zeros.
+    #[serde(default)]
+    pub statistics: GroupStatistics,
+    /// Files and directories that could not be scanned, and so were not checked for
+    /// duplicates. Reports written before this field existed default to none.
+    #[serde(default, skip_serializing_if = "ScanErrors::is_empty")]
+    pub errors: ScanErrors,
+}
+
+/// How many copies duplicate groups have, which tells a few heavily replicated files
+/// apart from many pairs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupStatistics {
+    /// Number of groups with exactly two copies.
+    pub two_copies: usize,
+    /// Number of groups with exactly three copies.
+    pub three_copies: usize,
+    /// Number of groups with four or more copies.
+    pub four_or_more_copies: usize,
+    /// Number of copies in the largest group.
+    pub largest_group: usize,
+    /// Median space wasted by a group, in bytes.
+    pub median_wasted_space: u64,
+}
+
+impl GroupStatistics {
+    /// Computes the statistics of `groups`.
+    pub fn of(groups: &[DuplicateGroup]) -> Self {
+        let mut statistics = Self::default();
+        for group in groups {
+            match group.copies() {
+                0..=2 => statistics.two_copies += 1,
+                3 => statistics.three_copies += 1,
+                _ => statistics.four_or_more_copies += 1,
+            }
+            statistics.largest_group = statistics.largest_group.max(group.copies());
+        }
+        let mut wasted: Vec<u64> = groups.iter().map(|group| group.wasted_space()).collect();
+        wasted.sort_unstable();
+        let middle = wasted.len() / 2;
+        statistics.median_wasted_space = match wasted.len() {
+            0 => 0,
+            len if len % 2 == 1 => wasted[middle],
+            _ => wasted[middle - 1].midpoint(wasted[middle]),
+        };
+        statistics
+    }
+}
+
+/// Wasted space attributed to one file extension or [`FileType`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WasteShare {
+    /// Extension (e.g. `.mp4`, or `(none)`) or file type label.
+    pub label: String,
+    /// Number of redundant files.
+    pub duplicates: usize,
+    /// Space used by the redundant files, in bytes.
+    pub wasted_space: u64,
+}
+
+/// Differences between two duplicate reports.
+pub struct ReportDiff {
+    /// Groups present in the current report but not in the previous one.
+    pub new_groups: Vec<DuplicateGroup>,
+    /// Groups present in the previous report but no longer duplicated.
+    pub resolved_groups: Vec<DuplicateGroup>,
+    /// Groups present in both reports whose member count changed: (previous, current).
+    pub changed_groups: Vec<(DuplicateGroup, DuplicateGroup)>,
+    /// Total wasted space of the previous report.
+    pub previous_wasted_space: u64,
+    /// Total wasted space of the current report.
+    pub current_wasted_space: u64,
+}
+
+impl DuplicateReport {
+    /// Builds a report from the duplicate groups returned by `find_duplicates`.
+    pub fn new(
+        duplicates: &HashMap<ContentHash, Vec<FileInfo>>,
+        scan_path: &Path,
+        options: &ReportOptions,
+    ) -> Self {
+        let mut groups: Vec<DuplicateGroup> = duplicates
+            .iter()
+            .filter(|(_, files)| distinct_copies(files) > 1)
+            .map(|(hash, files)| DuplicateGroup::from_files(hash, files, options))
+            .collect();
+        groups.sort_by(|a, b| {
+            b.wasted_space()
+                .cmp(&a.wasted_space())
+                .then_with(|| a.hash.cmp(&b.hash))
+        });
+
+        let generated_at = OffsetDateTime::now_local()
+            .unwrap_or_else(|_| OffsetDateTime::now_utc())
+            .format(&Rfc3339)
+            .unwrap_or_default();
+
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            generated_at,
+            scan_path: scan_path.to_path_buf(),
+            total_duplicates: groups.iter().map(|g| g.copies() - 1).sum(),
+            total_wasted_space: groups.iter().map(|g| g.wasted_space()).sum(),
+            statistics: GroupStatistics::of(&groups),
+            groups,
+            errors: ScanErrors::default(),
+        }
+    }
+
+    /// Adds the errors met during the scan to the report.
+    pub fn with_errors(mut self, errors: &ScanErrors) -> Self {
+        self.errors = errors.clone();
+        self
+    }
+
+    /// Sums wasted space over groups sharing the same `key`, largest first. Groups are
+    /// keyed by their first path, since copies nearly always share an extension.
+    fn waste_by(&self, key: impl Fn(&Path) -> String) -> Vec<WasteShare> {
+        let mut shares: HashMap<String, WasteShare> = HashMap::new();
+        for group in &self.groups {
+            let label = key(&group.paths[0]);
+            let share = shares.entry(label.clone()).or_insert(WasteShare {
+                label,
+                duplicates: 0,
+                wasted_space: 0,
+            });
+            share.duplicates += group.copies() - 1;
+            share.wasted_space += group.wasted_space();
+        }
+        let mut shares: Vec<WasteShare> = shares.into_values().collect();
+        shares.sort_by(|a, b| {
+            b.wasted_space
+                .cmp(&a.wasted_space)
+                .then_with(|| a.label.cmp(&b.label))
+        });
+        shares
+    }
+
+    /// Breaks down wasted space by lowercased file extension, largest first.
+    pub fn waste_by_extension(&self) -> Vec<WasteShare> {
+        self.waste_by(|path| match path.extension() {
+            Some(ext) => format!(".{}", ext.to_string_lossy().to_lowercase()),
+            None => "(none)".to_string(),
+        })
+    }
+
+    /// Breaks down wasted space by [`FileType`], largest first.
+    pub fn waste_by_type(&self) -> Vec<WasteShare> {
+        self.waste_by(|path| FileType::from_path(path).label().to_string())
+    }
+
+    /// Writes the report to `path` as pretty-printed JSON.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if serialization fails or the file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_vec_pretty(self)?;
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write report to {}", path.display()))?;
+        info!(
+            "Saved report with {} duplicate groups to {}",
+            Count(self.groups.len() as u64),
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Returns the path of the results of the last scan, kept next to `cache_file` for
+    /// `report --last`.
+    pub fn last_file_for_cache(cache_file: &Path) -> PathBuf {
+        let mut name = cache_file.file_name().unwrap_or_default().to_os_string();
+        name.push(".last-report");
+        cache_file.with_file_name(name)
+    }
+
+    /// Writes the report to `path` as compressed JSON, as the results of the last scan.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if serialization fails or the file cannot be written.
+    pub fn save_last(&self, path: &Path) -> Result<()> {
+        let compressed = zstd::encode_all(&serde_json::to_vec(self)?[..], 3)?;
+        fs::write(path, compressed)
+            .with_context(|| format!("Failed to write report to {}", path.display()))?;
+        debug!(
+            "Saved the results of the scan with {} duplicate groups to {}",
+            Count(self.groups.len() as u64),
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Loads a report previously written by [`DuplicateReport::save`] or, compressed, by
+    /// [`DuplicateReport::save_last`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is not a valid report.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut content = fs::read(path)
+            .with_context(|| format!("Failed to read report from {}", path.display()))?;
+        if content.starts_with(&ZSTD_MAGIC) {
+            content = zstd::decode_all(&content[..])
+                .with_context(|| format!("Failed to decompress report {}", path.display()))?;
+        }
+        serde_json::from_slice(&content)
+            .with_context(|| format!("Failed to parse report {}", path.display()))
+    }
+
+    /// Returns the groups with at least `min_copies` distinct copies wasting at least
+    /// `min_group_waste` bytes, in `sort_by` order, to show a report again another way.
+    pub fn shown_groups(
+        &self,
+        sort_by: SortBy,
+        min_copies: usize,
+        min_group_waste: u64,
+    ) -> Vec<&DuplicateGroup> {
+        let mut groups: Vec<&DuplicateGroup> = self
+            .groups
+            .iter()
+            .filter(|group| group.copies() >= min_copies && group.wasted_space() >= min_group_waste)
+            .collect();
+        // Groups are saved most wasted space first, and the sort is stable
+        match sort_by {
+            SortBy::Waste => {}
+            SortBy::Size => groups.sort_by_key(|group| std::cmp::Reverse(group.size)),
+            SortBy::Count => groups.sort_by_key(|group| std::cmp::Reverse(group.paths.len())),
+            SortBy::Path => groups.sort_by(|a, b| a.paths.first().cmp(&b.paths.first())),
+        }
+        groups
+    }
+
+    /// Compares this (current) report against a `previous` one.
+    ///
+    /// Groups are matched by content hash, so a group is considered the same across
+    /// runs even if its members were renamed or moved.
+    pub fn diff(&self, previous: &DuplicateReport) -> ReportDiff {
+        let previous_groups: HashMap<&str, &DuplicateGroup> = previous
+            .groups
+            .iter()
+            .map(|g| (g.hash.as_str(), g))
+            .collect();
+        let current_groups: HashMap<&str, &DuplicateGroup> =
+            self.groups.iter().map(|g| (g.hash.as_str(), g)).collect();
+
+        let mut new_groups = Vec::new();
+        let mut changed_groups = Vec::new();
+        for group in &self.groups {
+            match previous_groups.get(group.hash.as_str()) {
+                None => new_groups.push(group.clone()),
+                Some(prev) if prev.copies() != group.copies() => {
+                    changed_groups.push(((*prev).clone(), group.clone()));
+                }
+                Some(_) => {}
+            }
+        }
+        let resolved_groups = previous
+            .groups
+            .iter()
+            .filter(|g| !current_groups.contains_key(g.hash.as_str()))
+            .cloned()
+            .collect();
+
+        ReportDiff {
+            new_groups,
+            resolved_groups,
+            changed_groups,
+            previous_wasted_space: previous.total_wasted_space,
+            current_wasted_space: self.total_wasted_space,
+        }
+    }
+}
+
+/// Streams duplicate groups to `writer` as newline-delimited JSON, one
+/// [`DuplicateGroup`] object per line, without building a full report in memory.
+///
+/// Each line is written as soon as its group is serialized, so output can be piped into
+/// tools like `jq` while it is produced. Groups are written in no particular order,
+/// unless `options.top` is set, in which case only the groups wasting the most space
+/// are written, largest first. The `min_group_waste` and `min_copies` filters apply.
+/// Returns the number of groups written.
+///
+/// # Errors
+///
+/// Returns an error if serialization or writing fails.
+pub fn write_ndjson(
+    duplicates: &HashMap<ContentHash, Vec<FileInfo>>,
+    options: &ReportOptions,
+    writer: &mut dyn Write,
+) -> Result<usize> {
+    let mut groups: Box<dyn Iterator<Item = (&ContentHash, &Vec<FileInfo>)>> = Box::new(
+        duplicates
+            .iter()
+            .filter(|(_, files)| distinct_copies(files) > 1 && options.shows(files)),
+    );
+    if let Some(top) = options.top {
+        let mut largest: Vec<_> = groups
+            .map(|(hash, files)| (wasted_space(files, options), hash, files))
+            .collect();
+        largest.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        largest.truncate(top);
+        groups = Box::new(largest.into_iter().map(|(_, hash, files)| (hash, files)));
+    }
+
+    let mut written = 0;
+    for (hash, files) in groups {
+        let mut group = DuplicateGroup::from_files(hash, files, options);
+        for path in group.paths.iter_mut().chain(&mut group.compressed) {
+            *path = exported_path(path).into_owned();
+        }
+        serde_json::to_writer(&mut *writer, &group)?;
+        writeln!(writer)?;
+        written += 1;
+    }
+    writer.flush()?;
+    Ok(written)
+}
+
+/// Number of groups listed by [`write_brief`], unless `--top` says otherwise.
+const BRIEF_GROUPS: usize = 10;
+
+/// Writes a short plain-text summary of `report` for `--output-format brief`: the number
+/// of files scanned, the duplicates and the space they waste, and one line for each of
+/// the `top` (by default [`BRIEF_GROUPS`]) groups wasting the most. Made for mailing
+/// from cron jobs, so it has no colors or log prefixes. Returns the number of groups
+/// listed.
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_brief(
+    report: &DuplicateReport,
+    scanned_files: usize,
+    top: Option<usize>,
+    writer: &mut dyn Write,
+) -> Result<usize> {
+    writeln!(
+        writer,
+        "Duplicate files in {} ({})",
+        report.scan_path.display(),
+        report.generated_at
+    )?;
+    writeln!(writer, "Scanned {} files", Count(scanned_files as u64))?;
+    if report.groups.is_empty() {
+        writeln!(writer, "No duplicate files found")?;
+        writer.flush()?;
+        return Ok(0);
+    }
+    writeln!(
+        writer,
+        "Found {} duplicate files in {} groups, wasting {}",
+        Count(report.total_duplicates as u64),
+        Count(report.groups.len() as u64),
+        Bytes(report.total_wasted_space)
+    )?;
+    if !report.errors.is_empty() {
+        writeln!(
+            writer,
+            "{} files or directories could not be scanned",
+            Count(report.errors.len() as u64)
+        )?;
+    }
+    let groups = &report.groups[..report.groups.len().min(top.unwrap_or(BRIEF_GROUPS))];
+    writeln!(writer)?;
+    writeln!(writer, "Groups wasting the most space:")?;
+    let width = groups.len().to_string().len() + 1;
+    for (index, group) in groups.iter().enumerate() {
+        let first = group.paths.first().map_or_else(PathBuf::new, |path| {
+            shown_path(path, &report.scan_path).into_owned()
+        });
+        writeln!(
+            writer,
+            "{:>width$} [{}] {} wasted, {} copies of {}: {}{}",
+            format!("{}.", index + 1),
+            group.group_id(),
+            Bytes(group.wasted_space()),
+            group.copies(),
+            Bytes(group.size),
+            first.display(),
+            match group.paths.len() {
+                0 | 1 => String::new(),
+                n => format!(" and {} more", n - 1),
+            }
+        )?;
+    }
+    if groups.len() < report.groups.len() {
+        writeln!(
+            writer,
+            "... and {} more groups",
+            Count((report.groups.len() - groups.len()) as u64)
+        )?;
+    }
+    writer.flush()?;
+    Ok(groups.len())
+}
+
+/// Number of extensions listed by [`print_breakdown`]; the rest are summed up.
+const BREAKDOWN_EXTENSIONS: usize = 10;
+
+fn print_shares(heading: &str, shares: &[WasteShare], total: u64) {
+    info!("{}", heading);
+    for share in shares {
+        info!(
+            "  {:<12} {:>12} {:>6.1}%  {} files",
+            share.label,
+            Bytes(share.wasted_space).to_string(),
+            share.wasted_space as f64 * 100.0 / total as f64,
+            Count(share.duplicates as u64)
+        );
+    }
+}
+
+/// Logs how many copies the groups have, then tables of wasted space by file type and
+/// by extension, so it is clear at a glance which kinds of files waste the most. Only
+/// the largest extensions are listed individually. Nothing is logged if no space is
+/// wasted.
+pub fn print_breakdown(report: &DuplicateReport) {
+    if report.total_wasted_space == 0 {
+        return;
+    }
+    let statistics = &report.statistics;
+    info!(
+        "Duplicate groups: {} with 2 copies, {} with 3, {} with 4 or more; the largest has {} copies, and the median group wastes {}",
+        Count(statistics.two_copies as u64),
+        Count(statistics.three_copies as u64),
+        Count(statistics.four_or_more_copies as u64),
+        Count(statistics.largest_group as u64),
+        Bytes(statistics.median_wasted_space)
+    );
+    print_shares(
+        "Wasted space by type:",
+        &report.waste_by_type(),
+        report.total_wasted_space,
+    );
+    let mut extensions = report.waste_by_extension();
+    if extensions.len() > BREAKDOWN_EXTENSIONS {
+        let rest = extensions.split_off(BREAKDOWN_EXTENSIONS - 1);
+        extensions.push(WasteShare {
+            label: format!("({} more)", rest.len()),
+            duplicates: rest.iter().map(|share| share.duplicates).sum(),
+            wasted_space: rest.iter().map(|share| share.wasted_space).sum(),
+        });
+    }
+    print_shares(
+        "Wasted space by extension:",
+        &extensions,
+        report.total_wasted_space,
+    );
+}
+
+/// Logs a report saved earlier, for `report`: the totals, the `groups` picked by
+/// [`DuplicateReport::shown_groups`], only the first `top` if given, with paths relative
+/// to the scanned directory, and the breakdown by file type and extension.
+pub fn print_report(report: &DuplicateReport, groups: &[&DuplicateGroup], top: Option<usize>) {
+    info!(
+        "Report of {} from {}, by check-file-dups {}",
+        report.scan_path.display(),
+        report.generated_at,
+        report.version
+    );
+    if report.groups.is_empty() {
+        info!("{}", "No duplicate files found!".green());
+        return;
+    }
+    warn!(
+        "Found {} duplicate files wasting {} of space",
+        Count(report.total_duplicates as u64),
+        Bytes(report.total_wasted_space)
+    );
+    let shown = &groups[..top.unwrap_or(groups.len()).min(groups.len())];
+    if shown.len() < report.groups.len() {
+        info!(
+            "Showing {} of {} duplicate groups",
+            Count(shown.len() as u64),
+            Count(report.groups.len() as u64)
+        );
+    }
+    let size_width = shown
+        .iter()
+        .map(|group| Bytes(group.size).to_string().len())
+        .max()
+        .unwrap_or(0);
+    let index_width = shown.len().to_string().len() + 1;
+    for (index, group) in shown.iter().enumerate() {
+        warn!(
+            "{} {} {}  {} files, wasting {}",
+            format!("{:<index_width$}", format!("#{}", index + 1)).bold(),
+            format!("[{}]", group.group_id()).dimmed(),
+            format!("{:>size_width$}", Bytes(group.size).to_string()).bold(),
+            group.paths.len(),
+            Bytes(group.wasted_space())
+        );
+        for path in &group.paths {
+            warn!("    {}", shown_path(path, &report.scan_path).display());
+        }
+    }
+    if !report.errors.is_empty() {
+        warn!(
+            "{} files or directories could not be scanned",
+            Count(report.errors.len() as u64)
+        );
+    }
+    print_breakdown(report);
+}
+
+/// Logs a summary of the differences between two reports.
+pub fn print_diff(diff: &ReportDiff) {
+    if diff.new_groups.is_empty()
+        && diff.resolved_groups.is_empty()
+        && diff.changed_groups.is_empty()
+    {
+        info!("{}", "No changes since the previous report".green());
+        return;
+    }
+
+    let (sign, delta) = if diff.current_wasted_space >= diff.previous_wasted_space {
+        ("+", diff.current_wasted_space - diff.previous_wasted_space)
+    } else {
+        ("-", diff.previous_wasted_space - diff.current_wasted_space)
+    };
+    info!(
+        "Compared to previous report: {} new, {} resolved, {} changed groups; wasted space {} -> {} ({}{})",
+        Count(diff.new_groups.len() as u64),
+        Count(diff.resolved_groups.len() as u64),
+        Count(diff.changed_groups.len() as u64),
+        Bytes(diff.previous_wasted_space),
+        Bytes(diff.current_wasted_space),
+        sign,
+        Bytes(delta)
+    );
+
+    for group in &diff.new_groups {
+        warn!(
+            "New duplicate group ({}, {} files):",
+            Bytes(group.size),
+            group.paths.len()
+        );
+        for path in &group.paths {
+            warn!("  {}", path.display());
+        }
+    }
+    for (previous, current) in &diff.changed_groups {
+        warn!(
+            "Changed duplicate group ({}, {} -> {} files):",
+            Bytes(current.size),
+            previous.paths.len(),
+            current.paths.len()
+        );
+        for path in &current.paths {
+            warn!("  {}", path.display());
+        }
+    }
+    for group in &diff.resolved_groups {
+        info!(
+            "Resolved duplicate group ({}, {} files):",
+            Bytes(group.size),
+            group.paths.len()
+        );
+        for path in &group.paths {
+            info!("  {}", path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, size: u64, hash: &str) -> FileInfo {
+        FileInfo::for_test(path, size, ContentHash::of(hash.as_bytes()))
+    }
+
+    #[test]
+    fn brief_lists_the_groups_wasting_the_most() {
+        let mut duplicates = HashMap::new();
+        duplicates.insert(
+            ContentHash::of(b"a"),
+            vec![file("/scan/x/1", 10, "a"), file("/scan/x/2", 10, "a")],
+        );
+        duplicates.insert(
+            ContentHash::of(b"b"),
+            vec![
+                file("/scan/y/1", 100, "b"),
+                file("/scan/y/2", 100, "b"),
+                file("/scan/y/3", 100, "b"),
+            ],
+        );
+        let report =
+            DuplicateReport::new(&duplicates, Path::new("/scan"), &ReportOptions::default());
+        let mut out = Vec::new();
+        assert_eq!(write_brief(&report, 7, Some(1), &mut out).unwrap(), 1);
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[1], "Scanned 7 files");
+        assert_eq!(
+            lines[2],
+            "Found 3 duplicate files in 2 groups, wasting 210 B"
+        );
+        assert_eq!(
+            lines[5],
+            format!(
+                "1. [{}] 200 B wasted, 3 copies of 100 B: y/1 and 2 more",
+                ContentHash::of(b"b").group_id()
+            )
+        );
+        assert_eq!(lines[6], "... and 1 more groups");
+    }
+
+    #[test]
+    fn shows_the_last_results_again_another_way() {
+        let mut duplicates = HashMap::new();
+        duplicates.insert(
+            ContentHash::of(b"a"),
+            vec![
+                file("/scan/a/1", 10, "a"),
+                file("/scan/a/2", 10, "a"),
+                file("/scan/a/3", 10, "a"),
+            ],
+        );
+        duplicates.insert(
+            ContentHash::of(b"b"),
+            vec![file("/scan/b/1", 100, "b"), file("/scan/b/2", 100, "b")],
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let last = DuplicateReport::last_file_for_cache(&dir.path().join("cache.json.zst"));
+        DuplicateReport::new(&duplicates, Path::new("/scan"), &ReportOptions::default())
+            .save_last(&last)
+            .unwrap();
+
+        let report = DuplicateReport::load(&last).unwrap();
+        let paths = |groups: Vec<&DuplicateGroup>| -> Vec<PathBuf> {
+            groups.iter().map(|group| group.paths[0].clone()).collect()
+        };
+        assert_eq!(
+            paths(report.shown_groups(SortBy::Waste, 2, 0)),
+            [PathBuf::from("/scan/b/1"), PathBuf::from("/scan/a/1")]
+        );
+        assert_eq!(
+            paths(report.shown_groups(SortBy::Count, 2, 0)),
+            [PathBuf::from("/scan/a/1"), PathBuf::from("/scan/b/1")]
+        );
+        assert!(report.shown_groups(SortBy::Waste, 4, 0).is_empty());
+        assert_eq!(report.shown_groups(SortBy::Waste, 2, 50).len(), 1);
+    }
+
+    #[test]
+    fn ndjson_writes_one_group_per_line() {
+        let mut duplicates = HashMap::new();
+        duplicates.insert(
+            ContentHash::of(b"a"),
+            vec![file("x/2", 10, "a"), file("x/1", 10, "a")],
+        );
+        duplicates.insert(
+            ContentHash::of(b"b"),
+            vec![
+                file("y/1", 100, "b"),
+                file("y/2", 100, "b"),
+                file("y/3", 100, "b"),
+            ],
+        );
+
+        let options = ReportOptions {
+            top: Some(1),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        assert_eq!(write_ndjson(&duplicates, &options, &mut out).unwrap(), 1);
+        let lines: Vec<&str> = std::str::from_utf8(&out).unwrap().lines().collect();
+        assert_eq!(lines.len(), 1);
+        let group: DuplicateGroup = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(group.hash, ContentHash::of(b"b").to_string());
+        assert_eq!(group.wasted_space(), 200);
+
+        let mut out = Vec::new();
+        write_ndjson(&duplicates, &ReportOptions::default(), &mut out).unwrap();
+        let mut groups: Vec<DuplicateGroup> = std::str::from_utf8(&out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        groups.sort_by(|a, b| a.paths.cmp(&b.paths));
+        assert_eq!(
+            groups[0].paths,
+            [PathBuf::from("x/1"), PathBuf::from("x/2")]
+        );
+        assert_eq!(groups.len(), 2);
+    }
+
+    #[test]
+    fn breaks_down_waste_by_extension_and_type() {
+        let mut duplicates = HashMap::new();
+        duplicates.insert(
+            ContentHash::of(b"a"),
+            vec![file("a/clip.MP4", 900, "a"), file("b/clip.mp4", 900, "a")],
+        );
+        duplicates.insert(
+            ContentHash::of(b"b"),
+            vec![file("a/x.mov", 50, "b"), file("b/x.mov", 50, "b")],
+        );
+        duplicates.insert(
+            ContentHash::of(b"c"),
+            vec![
+                file("a/notes", 10, "c"),
+                file("b/notes", 10, "c"),
+                file("c/notes", 10, "c"),
+            ],
+        );
+        let report = DuplicateReport::new(&duplicates, Path::new("."), &ReportOptions::default());
+
+        let share = |label: &str, duplicates, wasted_space| WasteShare {
+            label: label.into(),
+            duplicates,
+            wasted_space,
+        };
+        assert_eq!(
+            report.waste_by_extension(),
+            [
+                share(".mp4", 1, 900),
+                share(".mov", 1, 50),
+                share("(none)", 2, 20)
+            ]
+        );
+        assert_eq!(
+            report.waste_by_type(),
+            [share("videos", 2, 950), share("other", 2, 20)]
+        );
+    }
+
+    #[test]
+    fn counts_groups_by_copies() {
+        let group = |hash: &str, size, copies: usize| {
+            let files = (0..copies)
+                .map(|i| file(&format!("{hash}/{i}"), size, hash))
+                .collect();
+            (ContentHash::of(hash.as_bytes()), files)
+        };
+        let duplicates = HashMap::from([
+            group("a", 100, 2),
+            group("b", 10, 2),
+            group("c", 20, 3),
+            group("d", 1, 6),
+        ]);
+        let report = DuplicateReport::new(&duplicates, Path::new("."), &ReportOptions::default());
+        assert_eq!(
+            report.statistics,
+            GroupStatistics {
+                two_copies: 2,
+                three_copies: 1,
+                four_or_more_copies: 1,
+                largest_group: 6,
+                median_wasted_space: 25,
+            }
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::shutdown::hold_exit;
+use crate::units::Count;
+
+/// What is saved of an interrupted scan.
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    /// Directory being scanned, and the options that decide which files it contains.
+    #[serde(with = "crate::paths::serde_path")]
+    scan_path: PathBuf,
+    skip_dirs: Vec<String>,
+    min_size: u64,
+    one_file_system: bool,
+    follow_junctions: bool,
+    /// Every file found by the walk, hashed or not.
+    #[serde(with = "crate::paths::serde_path::vec")]
+    files: Vec<PathBuf>,
+    /// Files hashed so far: path -> (mtime, size, hash).
+    #[serde(with = "crate::paths::serde_path::map")]
+    hashed: HashMap<PathBuf, (u64, u64, String)>,
+}
+
+/// Progress of a scan run with `--resume`, saved when the scan is interrupted so the next
+/// run with the same directory and options can skip the walk and the files already
+/// hashed. Hashes are kept here as well as in the cache, so resuming also works with
+/// `--no-cache`.
+pub struct ResumeState {
+    file: PathBuf,
+    state: Mutex<State>,
+    resumed: bool,
+}
+
+fn mtime(metadata: &fs::Metadata) -> Option<u64> {
+    let modified = metadata.modified().ok()?;
+    Some(
+        modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?
+            .as_secs(),
+    )
+}
+
+impl ResumeState {
+    /// Returns the path of the resume file kept next to `cache_file`.
+    pub fn file_for_cache(cache_file: &Path) -> PathBuf {
+        let mut name = cache_file.file_name().unwrap_or_default().to_os_string();
+        name.push(".resume");
+        cache_file.with_file_name(name)
+    }
+
+    /// Loads the progress saved in `file` by an interrupted scan of `scan_path` with the
+    /// same options. Starts afresh if there is none, or if it was saved by a scan of
+    /// another directory or with other options.
+    pub fn load(
+        file: PathBuf,
+        scan_path: &Path,
+        skip_dirs: &[String],
+        min_size: u64,
+        one_file_system: bool,
+        follow_junctions: bool,
+    ) -> Self {
+        let saved = fs::read(&file)
+            .ok()
+            .and_then(|compressed| zstd::decode_all(&compressed[..]).ok())
+            .and_then(|json| serde_json::from_slice::<State>(&json).ok());
+        let (state, resumed) = match saved {
+            Some(state)
+                if state.scan_path == scan_path
+                    && state.skip_dirs == skip_dirs
+                    && state.min_size == min_size
+                    && state.one_file_system == one_file_system
+                    && state.follow_junctions == follow_junctions =>
+            {
+                info!(
+                    "Resuming interrupted scan: {} of {} files already hashed",
+                    Count(state.hashed.len() as u64),
+                    Count(state.files.len() as u64)
+                );
+                (state, true)
+            }
+            Some(_) => {
+                info!("Saved scan progress is for other options, starting afresh");
+                (State::default(), false)
+            }
+            None => (State::default(), false),
+        };
+        Self {
+            file,
+            state: Mutex::new(State {
+                scan_path: scan_path.to_path_buf(),
+                skip_dirs: skip_dirs.to_vec(),
+                min_size,
+                one_file_system,
+                follow_junctions,
+                ..state
+            }),
+            resumed,
+        }
+    }
+
+    /// Returns the files found by the interrupted scan, or `None` if nothing was resumed
+    /// and the directory needs to be walked.
+    pub fn files(&self) -> Option<Vec<PathBuf>> {
+        self.resumed
+            .then(|| self.state.lock().unwrap().files.clone())
+    }
+
+    /// Records the files found by walking the directory.
+    pub fn set_files(&self, files: &[PathBuf]) {
+        self.state.lock().unwrap().files = files.to_vec();
+    }
+
+    /// Returns the hash recorded for `path`, if it hasn't changed since.
+    pub fn get_hash(&self, path: &Path, metadata: &fs::Metadata) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        let (hashed_mtime, hashed_size, hash) = state.hashed.get(path)?;
+        (Some(*hashed_mtime) == mtime(metadata) && *hashed_size == metadata.len())
+            .then(|| hash.clone())
+    }
+
+    /// Records the hash of `path`.
+    pub fn record(&self, path: &Path, metadata: &fs::Metadata, hash: &str) {
+        if let Some(mtime) = mtime(metadata) {
+            self.state.lock().unwrap().hashed.insert(
+                path.to_path_buf(),
+                (mtime, metadata.len(), hash.to_string()),
+            );
+        }
+    }
+
+    /// Saves the progress so far, so a later run can resume it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if serialization or writing fails.
+    pub fn save(&self) -> Result<()> {
+        let _exit = hold_exit();
+        let state = self.state.lock().unwrap();
+        let compressed = zstd::encode_all(&serde_json::to_vec(&*state)?[..], 3)?;
+        fs::write(&self.file, compressed)?;
+        info!(
+            "Saved scan progress to {} ({} of {} files hashed), continue with --resume",
+            self.file.display(),
+            Count(state.hashed.len() as u64),
+            Count(state.files.len() as u64)
+        );
+        Ok(())
+    }
+
+    /// Deletes the saved progress once the scan has completed.
+    pub fn remove(&self) {
+        match fs::remove_file(&self.file) {
+            Ok(()) => debug!("Removed {}", self.file.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to remove {}: {}", self.file.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resumes_only_matching_scans() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("cache.json.zst.resume");
+        let a = dir.path().join("a");
+        fs::write(&a, "a").unwrap();
+        let metadata = a.metadata().unwrap();
+
+        let state = ResumeState::load(file.clone(), dir.path(), &[], 0, false, false);
+        assert!(state.files().is_none());
+        state.set_files(std::slice::from_ref(&a));
+        state.record(&a, &metadata, "hash-a");
+        state.save().unwrap();
+
+        let state = ResumeState::load(file.clone(), dir.path(), &[], 0, false, false);
+        assert_eq!(state.files(), Some(vec![a.clone()]));
+        assert_eq!(state.get_hash(&a, &metadata).as_deref(), Some("hash-a"));
+
+        let other = ResumeState::load(file.clone(), dir.path(), &["skip".into()], 0, false, false);
+        assert!(other.files().is_none());
+        assert!(other.get_hash(&a, &metadata).is_none());
+
+        state.remove();
+        assert!(!file.exists());
+    }
+}
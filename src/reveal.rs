@@ -0,0 +1,146 @@
+//! `--reveal`: opening the platform file manager on the members of picked duplicate
+//! groups, with each file selected, to look at the copies before deciding which to keep.
+//! Explorer and Finder select files themselves; on Linux and other Unix systems the file
+//! manager is asked over D-Bus, falling back to opening the containing directory with
+//! `xdg-open`.
+
+use std::io;
+use std::path::{Component, Path};
+use std::process::Command;
+
+use log::{info, warn};
+
+use crate::FileInfo;
+use crate::html::encode_segment;
+
+/// Most files revealed at once, since each can open a window.
+pub const MAX_REVEALED: usize = 20;
+
+/// Returns the `file://` URL of the absolute `path`.
+fn file_url(path: &Path) -> String {
+    let segments: Vec<String> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(encode_segment(&name.to_string_lossy())),
+            Component::Prefix(prefix) => Some(prefix.as_os_str().to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect();
+    format!("file:///{}", segments.join("/"))
+}
+
+/// Returns the argument asking Explorer to select `path`. Explorer parses its command
+/// line itself and takes a path with spaces only if it is quoted after the comma, not
+/// if the whole argument is quoted as `Command::arg` would.
+fn explorer_select(path: &Path) -> String {
+    format!("/select,\"{}\"", path.display())
+}
+
+/// Returns the commands that show `path` in the file manager, in the order to try them.
+pub fn reveal_commands(path: &Path) -> Vec<Command> {
+    if cfg!(windows) {
+        let mut explorer = Command::new("explorer.exe");
+        #[cfg(windows)]
+        std::os::windows::process::CommandExt::raw_arg(&mut explorer, explorer_select(path));
+        #[cfg(not(windows))]
+        explorer.arg(explorer_select(path));
+        vec![explorer]
+    } else if cfg!(target_os = "macos") {
+        let mut open = Command::new("open");
+        open.arg("-R").arg(path);
+        vec![open]
+    } else {
+        let mut dbus = Command::new("dbus-send");
+        dbus.args([
+            "--session",
+            "--print-reply",
+            "--dest=org.freedesktop.FileManager1",
+            "--type=method_call",
+            "/org/freedesktop/FileManager1",
+            "org.freedesktop.FileManager1.ShowItems",
+        ])
+        .arg(format!("array:string:{}", file_url(path)))
+        .arg("string:");
+        let mut xdg_open = Command::new("xdg-open");
+        xdg_open.arg(path.parent().unwrap_or(path));
+        vec![dbus, xdg_open]
+    }
+}
+
+/// Shows `path` in the file manager, trying each of [`reveal_commands`] until one
+/// succeeds.
+///
+/// # Errors
+///
+/// Returns the error of the last command tried if none succeeded.
+pub fn reveal(path: &Path) -> io::Result<()> {
+    let mut error = io::Error::other("no file manager found");
+    for mut command in reveal_commands(path) {
+        match command.output() {
+            // Explorer exits with 1 even when it shows the file
+            Ok(output) if output.status.success() || cfg!(windows) => return Ok(()),
+            Ok(output) => {
+                error = io::Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string())
+            }
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+/// Shows each of `files` in the file manager, up to [`MAX_REVEALED`] of them, logging
+/// those that can't be shown.
+pub fn reveal_files<'a>(files: impl IntoIterator<Item = &'a FileInfo>) {
+    let files: Vec<&FileInfo> = files
+        .into_iter()
+        .filter(|file| file.is_actionable())
+        .collect();
+    if files.len() > MAX_REVEALED {
+        warn!(
+            "Only showing the first {} of {} files, pick fewer groups with --group",
+            MAX_REVEALED,
+            files.len()
+        );
+    }
+    for file in files.iter().take(MAX_REVEALED) {
+        let path = file.path.to_path_buf();
+        match reveal(&path) {
+            Ok(()) => info!("Showing {} in the file manager", path.display()),
+            Err(e) => warn!(
+                "Failed to show {} in the file manager: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reveals_the_file_itself_where_possible() {
+        let path = std::env::temp_dir().join("a dir").join("photo #1.jpg");
+        let commands = reveal_commands(&path);
+        let args: Vec<String> = commands[0]
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let last = args.iter().rev().find(|arg| arg.contains("photo")).unwrap();
+        if cfg!(unix) && !cfg!(target_os = "macos") {
+            assert!(last.ends_with("/a%20dir/photo%20%231.jpg"), "{}", last);
+            assert!(last.starts_with("array:string:file:///"), "{}", last);
+            let fallback: Vec<_> = commands[1].get_args().collect();
+            assert_eq!(fallback, [path.parent().unwrap().as_os_str()]);
+        } else if cfg!(windows) {
+            assert_eq!(*last, explorer_select(&path));
+        } else {
+            assert!(last.ends_with("photo #1.jpg"), "{}", last);
+        }
+        assert_eq!(
+            explorer_select(Path::new(r"C:\Users\me\a dir\photo #1.jpg")),
+            r#"/select,"C:\Users\me\a dir\photo #1.jpg""#
+        );
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use colored::Colorize;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::error::CheckFileDupsError;
+use crate::scan_root::ScanRoot;
+use crate::units::Count;
+
+/// Why a file or directory could not be scanned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanErrorKind {
+    PermissionDenied,
+    PathTooLong,
+    /// The file or directory was deleted or moved while the scan was running.
+    Vanished,
+    /// The file was modified while it was hashed, so its hash may match neither version.
+    Changed,
+    /// Another program has the file open without letting others read it, as Outlook does
+    /// with its PST files. Only happens on Windows.
+    Locked,
+    /// Any other error, such as a failing disk, a dropped network connection or a read
+    /// that timed out.
+    Io,
+}
+
+impl ScanErrorKind {
+    /// Classifies `error` by the first IO error in its chain of causes.
+    pub fn of(error: &anyhow::Error) -> Self {
+        if let Some(error) = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<CheckFileDupsError>())
+        {
+            return Self::of_error(error);
+        }
+        Self::of_io(
+            error
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<io::Error>()),
+        )
+    }
+
+    /// Classifies an error of the library.
+    pub fn of_error(error: &CheckFileDupsError) -> Self {
+        match error {
+            CheckFileDupsError::ChangedDuringScan { .. } => Self::Changed,
+            error => Self::of_io(error.io_error()),
+        }
+    }
+
+    /// Classifies an IO error, or any other error without one.
+    fn of_io(io_error: Option<&io::Error>) -> Self {
+        if io_error.is_some_and(is_locked) {
+            return Self::Locked;
+        }
+        match io_error.map(io::Error::kind) {
+            Some(io::ErrorKind::PermissionDenied) => Self::PermissionDenied,
+            Some(io::ErrorKind::InvalidFilename) => Self::PathTooLong,
+            Some(io::ErrorKind::NotFound) => Self::Vanished,
+            _ => Self::Io,
+        }
+    }
+
+    /// Heading shown in the error summary.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::PermissionDenied => "Permission denied",
+            Self::PathTooLong => "Path too long",
+            Self::Vanished => "Vanished during scan",
+            Self::Changed => "Changed during scan",
+            Self::Locked => "Locked by another program",
+            Self::Io => "IO error",
+        }
+    }
+}
+
+/// Returns `true` if `error` is a sharing or lock violation, from opening a file another
+/// program has locked.
+fn is_locked(error: &io::Error) -> bool {
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::Foundation::{ERROR_LOCK_VIOLATION, ERROR_SHARING_VIOLATION};
+        matches!(
+            error.raw_os_error().map(|code| code as u32),
+            Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)
+        )
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = error;
+        false
+    }
+}
+
+/// A file or directory that could not be scanned, so it may have duplicates that were
+/// not found.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanError {
+    #[serde(with = "crate::paths::serde_path")]
+    pub path: PathBuf,
+    /// Why scanning the path failed.
+    pub error: String,
+}
+
+/// Errors met during a scan, grouped by [`ScanErrorKind`], so a run can tell "no
+/// duplicates" apart from "most of the tree couldn't be read".
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanErrors {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permission_denied: Vec<ScanError>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub path_too_long: Vec<ScanError>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vanished: Vec<ScanError>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changed: Vec<ScanError>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub locked: Vec<ScanError>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub io: Vec<ScanError>,
+}
+
+impl ScanErrors {
+    /// Records that scanning `path` failed with `error`.
+    pub fn add(&mut self, path: &Path, error: &anyhow::Error) {
+        let errors = match ScanErrorKind::of(error) {
+            ScanErrorKind::PermissionDenied => &mut self.permission_denied,
+            ScanErrorKind::PathTooLong => &mut self.path_too_long,
+            ScanErrorKind::Vanished => &mut self.vanished,
+            ScanErrorKind::Changed => &mut self.changed,
+            ScanErrorKind::Locked => &mut self.locked,
+            ScanErrorKind::Io => &mut self.io,
+        };
+        errors.push(ScanError {
+            path: path.to_path_buf(),
+            error: format!("{:#}", error),
+        });
+    }
+
+    /// Adds the errors of `other`.
+    pub fn merge(&mut self, other: ScanErrors) {
+        self.permission_denied.extend(other.permission_denied);
+        self.path_too_long.extend(other.path_too_long);
+        self.vanished.extend(other.vanished);
+        self.changed.extend(other.changed);
+        self.locked.extend(other.locked);
+        self.io.extend(other.io);
+    }
+
+    /// Returns the errors of each kind, in a fixed order.
+    pub fn by_kind(&self) -> [(ScanErrorKind, &[ScanError]); 6] {
+        [
+            (ScanErrorKind::PermissionDenied, &self.permission_denied),
+            (ScanErrorKind::PathTooLong, &self.path_too_long),
+            (ScanErrorKind::Vanished, &self.vanished),
+            (ScanErrorKind::Changed, &self.changed),
+            (ScanErrorKind::Locked, &self.locked),
+            (ScanErrorKind::Io, &self.io),
+        ]
+    }
+
+    /// Total number of errors.
+    pub fn len(&self) -> usize {
+        self.by_kind().iter().map(|(_, errors)| errors.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the paths that couldn't be read for lack of permission, each with the
+    /// errors it stands for, in path order. Several denied entries of one directory are
+    /// summed up as that directory, since a whole subtree is usually unreadable at once,
+    /// such as the home directories of other users.
+    pub fn denied_subtrees(&self) -> Vec<(&Path, Vec<&ScanError>)> {
+        let mut by_parent: BTreeMap<&Path, Vec<&ScanError>> = BTreeMap::new();
+        for error in &self.permission_denied {
+            let parent = error.path.parent().unwrap_or(&error.path);
+            by_parent.entry(parent).or_default().push(error);
+        }
+        let mut subtrees: Vec<(&Path, Vec<&ScanError>)> = by_parent
+            .into_iter()
+            .map(|(parent, errors)| match errors[..] {
+                [error] => (error.path.as_path(), errors),
+                _ => (parent, errors),
+            })
+            .collect();
+        subtrees.sort_by_key(|(path, _)| *path);
+        subtrees
+    }
+
+    /// Sorts the errors of each kind by path, so output is stable across runs.
+    pub fn sort(&mut self) {
+        for errors in [
+            &mut self.permission_denied,
+            &mut self.path_too_long,
+            &mut self.vanished,
+            &mut self.changed,
+            &mut self.locked,
+            &mut self.io,
+        ] {
+            errors.sort_by(|a, b| a.path.cmp(&b.path));
+        }
+    }
+}
+
+/// Number of paths listed for each kind of error by [`print_scan_errors`].
+const LISTED_PER_KIND: usize = 10;
+
+/// Logs a summary of the errors met during the scan, by kind, with the first few paths
+/// of each. Paths denied are listed by unreadable subtree (see
+/// [`ScanErrors::denied_subtrees`]), or with `skip_unreadable` only counted, since they
+/// are expected. Nothing is logged if there were no errors.
+pub fn print_scan_errors(errors: &ScanErrors, root: &ScanRoot, skip_unreadable: bool) {
+    let denied = errors.permission_denied.len();
+    if skip_unreadable && denied > 0 {
+        info!(
+            "Skipped {} unreadable paths (--skip-unreadable)",
+            Count(denied as u64)
+        );
+    }
+    let count = if skip_unreadable {
+        errors.len() - denied
+    } else {
+        errors.len()
+    };
+    if count == 0 {
+        return;
+    }
+    warn!(
+        "{}",
+        format!(
+            "Could not scan {} paths, so the results may be incomplete:",
+            Count(count as u64)
+        )
+        .yellow()
+    );
+    let subtrees = errors.denied_subtrees();
+    for (kind, errors) in errors.by_kind() {
+        if errors.is_empty() {
+            continue;
+        }
+        if kind == ScanErrorKind::PermissionDenied {
+            if !skip_unreadable {
+                print_denied(&subtrees, root);
+            }
+            continue;
+        }
+        warn!("  {}: {}", kind.label(), Count(errors.len() as u64));
+        for error in errors.iter().take(LISTED_PER_KIND) {
+            warn!(
+                "    {}: {}",
+                root.display(&error.path).display(),
+                error.error
+            );
+        }
+        if errors.len() > LISTED_PER_KIND {
+            warn!(
+                "    ({} more, see --save-report)",
+                Count((errors.len() - LISTED_PER_KIND) as u64)
+            );
+        }
+        if kind == ScanErrorKind::Locked {
+            warn!("    (run as administrator with --snapshot to read them from a volume snapshot)");
+        }
+    }
+}
+
+/// Logs the paths denied, one line per unreadable subtree.
+fn print_denied(subtrees: &[(&Path, Vec<&ScanError>)], root: &ScanRoot) {
+    let denied: usize = subtrees.iter().map(|(_, errors)| errors.len()).sum();
+    warn!(
+        "  {}: {} in {} subtrees",
+        ScanErrorKind::PermissionDenied.label(),
+        Count(denied as u64),
+        Count(subtrees.len() as u64)
+    );
+    for (path, errors) in subtrees.iter().take(LISTED_PER_KIND) {
+        match errors[..] {
+            [error] => warn!("    {}: {}", root.display(path).display(), error.error),
+            _ => warn!(
+                "    {}: {} entries unreadable",
+                root.display(path).display(),
+                Count(errors.len() as u64)
+            ),
+        }
+    }
+    if subtrees.len() > LISTED_PER_KIND {
+        warn!(
+            "    ({} more, see --save-report)",
+            Count((subtrees.len() - LISTED_PER_KIND) as u64)
+        );
+    }
+    warn!(
+        "    ({} to read them, or pass --skip-unreadable if they are expected)",
+        if cfg!(windows) {
+            "run as administrator"
+        } else {
+            "run as root"
+        }
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_errors_by_kind() {
+        let mut errors = ScanErrors::default();
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        errors.add(Path::new("b"), &denied.into());
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        errors.add(
+            Path::new("a"),
+            &anyhow::Error::from(missing).context("reading a"),
+        );
+        errors.add(
+            Path::new("c"),
+            &anyhow::anyhow!("no data read for 30 seconds"),
+        );
+        errors.add(
+            Path::new("a"),
+            &io::Error::from(io::ErrorKind::PermissionDenied).into(),
+        );
+        errors.add(
+            Path::new("d"),
+            &CheckFileDupsError::ChangedDuringScan { path: "d".into() }.into(),
+        );
+        errors.sort();
+
+        assert_eq!(errors.len(), 5);
+        assert_eq!(
+            errors.changed[0].error,
+            "modified while it was being hashed"
+        );
+        assert_eq!(errors.permission_denied[0].path, Path::new("a"));
+        assert!(errors.vanished[0].error.starts_with("reading a: "));
+        assert_eq!(errors.io.len(), 1);
+        assert!(errors.path_too_long.is_empty());
+
+        let json = serde_json::to_string(&errors).unwrap();
+        assert!(!json.contains("path_too_long"));
+        assert_eq!(serde_json::from_str::<ScanErrors>(&json).unwrap(), errors);
+    }
+
+    #[test]
+    fn sums_up_denied_entries_by_directory() {
+        let mut errors = ScanErrors::default();
+        for path in [
+            "home/ann",
+            "home/bob",
+            "home/cy",
+            "srv/private",
+            "tmp/x/locked",
+        ] {
+            errors.add(
+                Path::new(path),
+                &io::Error::from(io::ErrorKind::PermissionDenied).into(),
+            );
+        }
+        let subtrees: Vec<(&Path, usize)> = errors
+            .denied_subtrees()
+            .into_iter()
+            .map(|(path, errors)| (path, errors.len()))
+            .collect();
+        assert_eq!(
+            subtrees,
+            [
+                (Path::new("home"), 3),
+                (Path::new("srv/private"), 1),
+                (Path::new("tmp/x/locked"), 1),
+            ]
+        );
+    }
+}
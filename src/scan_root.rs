@@ -0,0 +1,488 @@
+use std::borrow::Cow;
+use std::path::{Component, Path, PathBuf};
+use std::sync::RwLock;
+
+use log::warn;
+
+use crate::paths::{FilePath, escape_path, unescape_path};
+
+/// The directories a scan is relative to: the base path that cache keys are derived from,
+/// and the directory being scanned.
+///
+/// Both are canonicalized once, when the `ScanRoot` is created, so that cache keys do not
+/// depend on the current directory or on how the paths were spelled on the command line.
+/// Files under the base path are keyed by their path relative to it, with `/` separators.
+/// Files outside the base path (when the scan path is not inside it) are keyed by their
+/// absolute path instead, which always starts with a root (`/` or a drive letter) and so
+/// can never collide with a relative key.
+///
+/// A scan can cover several directories. Directories given more than once, or inside
+/// another one, are dropped so each file is scanned once, and the scanned directory is
+/// then the deepest directory containing all of them.
+///
+/// On case-insensitive filesystems, paths differing only in case name the same file. With
+/// [`ScanRoot::with_case_insensitive_paths`], such paths overlap and share a cache key.
+#[derive(Debug, Clone)]
+pub struct ScanRoot {
+    base: PathBuf,
+    scan: PathBuf,
+    /// The canonical directories to walk, none of them inside another.
+    roots: Vec<PathBuf>,
+    scan_inside_base: bool,
+    case_insensitive: bool,
+}
+
+/// Canonicalizes `path`, falling back to an absolute path if it does not exist.
+/// An empty path means the current directory.
+fn canonical(path: &Path) -> PathBuf {
+    let path = if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        path
+    };
+    path.canonicalize()
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+impl ScanRoot {
+    /// Creates a root for scanning `scan_path` with cache keys relative to `base_path`.
+    ///
+    /// Logs a warning if the scan path lies outside the base path, since its files are
+    /// then cached under absolute keys that only match scans of the same location.
+    pub fn new(scan_path: &Path, base_path: &Path) -> Self {
+        Self::with_paths(&[scan_path.to_path_buf()], base_path)
+    }
+
+    /// Creates a root for scanning all of `scan_paths` with cache keys relative to
+    /// `base_path`, which must not be empty.
+    ///
+    /// Logs a warning for each scan path that repeats another one or lies inside it, since
+    /// it is then skipped, and for each scan path outside the base path.
+    pub fn with_paths(scan_paths: &[PathBuf], base_path: &Path) -> Self {
+        let base = canonical(base_path);
+        let paths: Vec<(&Path, PathBuf)> = scan_paths
+            .iter()
+            .map(|path| (path.as_path(), canonical(path)))
+            .collect();
+        let roots = outermost(&paths, false);
+        for root in &roots {
+            if !root.starts_with(&base) {
+                warn!(
+                    "Scan path {} is outside the base path {}, so its cache entries are keyed by absolute path",
+                    root.display(),
+                    base.display()
+                );
+            }
+        }
+        let scan = common_ancestor(&roots);
+        Self {
+            scan_inside_base: scan.starts_with(&base),
+            base,
+            scan,
+            roots,
+            case_insensitive: false,
+        }
+    }
+
+    /// Treats paths differing only in case as the same, as case-insensitive filesystems
+    /// do: scan paths that differ from another one only in case are dropped, and cache
+    /// keys are lowercased.
+    pub fn with_case_insensitive_paths(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = case_insensitive;
+        if case_insensitive {
+            let paths: Vec<(&Path, PathBuf)> = self
+                .roots
+                .iter()
+                .map(|root| (root.as_path(), root.clone()))
+                .collect();
+            let roots = outermost(&paths, true);
+            self.scan = common_ancestor(&roots);
+            self.scan_inside_base = strip_prefix(&self.scan, &self.base, true).is_some();
+            self.roots = roots;
+        }
+        self
+    }
+
+    /// Creates a root for cache operations that don't scan, such as the `cache` subcommands.
+    pub fn for_base(base_path: &Path) -> Self {
+        let base = canonical(base_path);
+        Self {
+            scan: base.clone(),
+            roots: vec![base.clone()],
+            base,
+            scan_inside_base: true,
+            case_insensitive: false,
+        }
+    }
+
+    /// The canonical base path.
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    /// The canonical directory being scanned, or the deepest directory containing all the
+    /// scanned directories.
+    pub fn scan_path(&self) -> &Path {
+        &self.scan
+    }
+
+    /// The canonical directories being scanned.
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Returns the cache key for `file`.
+    ///
+    /// Paths found by walking [`ScanRoot::scan_path`] are keyed without touching the
+    /// filesystem. Other paths, such as relative paths given on the command line, are
+    /// canonicalized first if they exist.
+    pub fn key(&self, file: &Path) -> String {
+        let key = |path: &Path| {
+            let key = to_key(path);
+            if self.case_insensitive {
+                key.to_lowercase()
+            } else {
+                key
+            }
+        };
+        if is_normalized(file) {
+            if let Some(relative) = strip_prefix(file, &self.base, self.case_insensitive) {
+                return key(&relative);
+            }
+            if !self.scan_inside_base
+                && strip_prefix(file, &self.scan, self.case_insensitive).is_some()
+            {
+                return key(file);
+            }
+        }
+        let file = canonical(file);
+        match strip_prefix(&file, &self.base, self.case_insensitive) {
+            Some(relative) => key(&relative),
+            None => key(&file),
+        }
+    }
+
+    /// Returns the path of the file a cache key refers to.
+    pub fn resolve(&self, key: &str) -> PathBuf {
+        // Joining an absolute key replaces the base entirely
+        self.base.join(unescape_path(key))
+    }
+
+    /// Returns `file` relative to the scanned directory, or as it is if outside it.
+    pub fn relative<'a>(&self, file: &'a Path) -> &'a Path {
+        file.strip_prefix(&self.scan).unwrap_or(file)
+    }
+
+    /// Returns the scanned `file` relative to the scanned directory, as in inventories.
+    pub fn relative_file(&self, file: &FilePath) -> PathBuf {
+        self.relative(&file.to_path_buf()).to_path_buf()
+    }
+
+    /// Returns `file` as shown to people, by default relative to the scanned directory
+    /// (see [`set_display_paths`]).
+    pub fn display<'a>(&self, file: &'a Path) -> Cow<'a, Path> {
+        display_paths().show(file, &self.scan)
+    }
+
+    /// Returns the scanned `file` as shown to people, like [`ScanRoot::display`].
+    pub fn display_file(&self, file: &FilePath) -> PathBuf {
+        self.display(&file.to_path_buf()).into_owned()
+    }
+}
+
+/// How the paths of files are written in reports, with `--display-paths`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum DisplayPaths {
+    /// Relative to the scanned directory in output for people, and absolute in output
+    /// for programs, such as JSON, fdupes and rmlint output, and scripts.
+    #[default]
+    Auto,
+    /// Absolute everywhere.
+    Absolute,
+    /// Relative to this directory everywhere, through `..` for files outside it, so
+    /// the results of scans of different directories line up.
+    RelativeTo(PathBuf),
+}
+
+impl std::str::FromStr for DisplayPaths {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "absolute" => Ok(Self::Absolute),
+            _ => match s.strip_prefix("relative-to=") {
+                Some(dir) if !dir.is_empty() => Ok(Self::RelativeTo(canonical(Path::new(dir)))),
+                _ => Err(format!(
+                    "invalid display paths '{}' (expected auto, absolute or relative-to=DIR)",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl DisplayPaths {
+    /// Returns `path` as shown to people, when `scan_path` was scanned.
+    pub fn show<'a>(&self, path: &'a Path, scan_path: &Path) -> Cow<'a, Path> {
+        match self {
+            Self::Auto => Cow::Borrowed(path.strip_prefix(scan_path).unwrap_or(path)),
+            _ => self.export(path),
+        }
+    }
+
+    /// Returns `path` as written for programs.
+    pub fn export<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        match self {
+            Self::Auto | Self::Absolute => Cow::Borrowed(path),
+            Self::RelativeTo(dir) => Cow::Owned(relative_path(path, dir)),
+        }
+    }
+}
+
+/// The mode used by [`ScanRoot::display`] and [`exported_path`], set once at startup.
+static DISPLAY_PATHS: RwLock<DisplayPaths> = RwLock::new(DisplayPaths::Auto);
+
+/// Writes the paths of files in all reports afterwards as `mode` says.
+pub fn set_display_paths(mode: DisplayPaths) {
+    *DISPLAY_PATHS.write().unwrap() = mode;
+}
+
+/// Returns the mode set with [`set_display_paths`].
+pub fn display_paths() -> DisplayPaths {
+    DISPLAY_PATHS.read().unwrap().clone()
+}
+
+/// Returns `path` as shown to people in a report of `scan_path`, like
+/// [`ScanRoot::display`].
+pub fn shown_path<'a>(path: &'a Path, scan_path: &Path) -> Cow<'a, Path> {
+    display_paths().show(path, scan_path)
+}
+
+/// Returns `path` as written in output for programs: absolute, unless
+/// `--display-paths relative-to=DIR` was given.
+pub fn exported_path(path: &Path) -> Cow<'_, Path> {
+    display_paths().export(path)
+}
+
+/// Returns the path leading from `dir` to `path`, both absolute, going up with `..` as
+/// far as needed. Paths on another drive are returned as they are.
+fn relative_path(path: &Path, dir: &Path) -> PathBuf {
+    let mut path_components = path.components().peekable();
+    let mut dir_components = dir.components().peekable();
+    if path_components.peek() != dir_components.peek() {
+        return path.to_path_buf();
+    }
+    while let (Some(a), Some(b)) = (path_components.peek(), dir_components.peek())
+        && a == b
+    {
+        path_components.next();
+        dir_components.next();
+    }
+    let relative: PathBuf = dir_components
+        .map(|_| Component::ParentDir)
+        .chain(path_components)
+        .collect();
+    if relative.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        relative
+    }
+}
+
+/// Returns the canonical paths of `paths`, given as (path as given, canonical path),
+/// leaving out those that repeat an earlier one or lie inside another one, with a
+/// warning. Paths are compared ignoring case with `case_insensitive`.
+fn outermost(paths: &[(&Path, PathBuf)], case_insensitive: bool) -> Vec<PathBuf> {
+    let inside = |path: &Path, dir: &Path| strip_prefix(path, dir, case_insensitive).is_some();
+    let mut roots: Vec<PathBuf> = Vec::new();
+    for (path, canonical) in paths {
+        if let Some(outer) = roots.iter().find(|root| inside(canonical, root)) {
+            warn!(
+                "Skipping scan path {}: scanning {} covers it already",
+                path.display(),
+                outer.display()
+            );
+            continue;
+        }
+        roots.retain(|root| {
+            let covered = inside(root, canonical);
+            if covered {
+                warn!(
+                    "Skipping scan path {}: scanning {} covers it already",
+                    root.display(),
+                    canonical.display()
+                );
+            }
+            !covered
+        });
+        roots.push(canonical.clone());
+    }
+    roots
+}
+
+/// Returns `path` relative to `prefix`, like [`Path::strip_prefix`], comparing
+/// components ignoring case with `case_insensitive`.
+fn strip_prefix(path: &Path, prefix: &Path, case_insensitive: bool) -> Option<PathBuf> {
+    if !case_insensitive {
+        return path.strip_prefix(prefix).ok().map(Path::to_path_buf);
+    }
+    let mut components = path.components();
+    for expected in prefix.components() {
+        let component = components.next()?;
+        let same = component.as_os_str() == expected.as_os_str()
+            || component.as_os_str().to_string_lossy().to_lowercase()
+                == expected.as_os_str().to_string_lossy().to_lowercase();
+        if !same {
+            return None;
+        }
+    }
+    Some(components.as_path().to_path_buf())
+}
+
+/// Returns the deepest directory containing all of `paths`.
+fn common_ancestor(paths: &[PathBuf]) -> PathBuf {
+    let Some((first, rest)) = paths.split_first() else {
+        return PathBuf::new();
+    };
+    let mut ancestor = first.clone();
+    for path in rest {
+        while !path.starts_with(&ancestor) && ancestor.pop() {}
+    }
+    ancestor
+}
+
+/// Returns true if `path` has no `.` or `..` components that would need resolving.
+fn is_normalized(path: &Path) -> bool {
+    !path
+        .components()
+        .any(|c| matches!(c, Component::CurDir | Component::ParentDir))
+}
+
+/// Converts a path to a cache key, using `/` as the separator on every platform. Names
+/// that aren't valid UTF-8 are escaped rather than mangled (see [`escape_path`]), so they
+/// can't collide.
+fn to_key(path: &Path) -> String {
+    escape_path(path).replace('\\', "/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn keys_are_relative_to_base_regardless_of_spelling() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base");
+        fs::create_dir_all(base.join("photos/2024")).unwrap();
+        fs::write(base.join("photos/2024/a.jpg"), b"a").unwrap();
+
+        let root = ScanRoot::new(&base.join("photos"), &base.join("photos/.."));
+        let file = root.scan_path().join("2024/a.jpg");
+        assert_eq!(root.key(&file), "photos/2024/a.jpg");
+        assert_eq!(root.relative(&file), Path::new("2024/a.jpg"));
+        assert_eq!(
+            root.key(&base.join("photos/2024/../2024/a.jpg")),
+            "photos/2024/a.jpg"
+        );
+        assert_eq!(
+            root.resolve("photos/2024/a.jpg"),
+            root.base().join("photos/2024/a.jpg")
+        );
+    }
+
+    #[test]
+    fn files_outside_base_get_absolute_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base");
+        let other = dir.path().join("other");
+        fs::create_dir_all(&base).unwrap();
+        fs::create_dir_all(&other).unwrap();
+
+        let root = ScanRoot::new(&other, &base);
+        let file = root.scan_path().join("a.jpg");
+        let key = root.key(&file);
+        assert!(Path::new(&key).is_absolute(), "{}", key);
+        assert_eq!(root.resolve(&key), file);
+    }
+
+    #[test]
+    fn overlapping_scan_paths_are_scanned_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(base.join("data/photos")).unwrap();
+        fs::create_dir_all(base.join("music")).unwrap();
+
+        let root = ScanRoot::with_paths(
+            &[
+                base.join("data/photos"),
+                base.join("music"),
+                base.join("data"),
+                base.join("music/."),
+            ],
+            &base,
+        );
+        assert_eq!(root.roots(), [base.join("music"), base.join("data")]);
+        assert_eq!(root.scan_path(), base);
+        assert_eq!(
+            root.relative(&base.join("data/a.jpg")),
+            Path::new("data/a.jpg")
+        );
+    }
+
+    #[test]
+    fn case_insensitive_paths_share_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        let upper = base.join("Photos");
+        let lower = base.join("photos");
+        fs::create_dir_all(&upper).unwrap();
+        fs::create_dir_all(&lower).unwrap();
+
+        let paths = [upper.clone(), lower.clone()];
+        let root = ScanRoot::with_paths(&paths, &base);
+        assert_eq!(root.roots().len(), 2);
+        assert_ne!(
+            root.key(&upper.join("IMG.JPG")),
+            root.key(&lower.join("img.jpg"))
+        );
+
+        let root = ScanRoot::with_paths(&paths, &base).with_case_insensitive_paths(true);
+        assert_eq!(root.roots(), std::slice::from_ref(&upper));
+        assert_eq!(root.key(&upper.join("IMG.JPG")), "photos/img.jpg");
+        assert_eq!(root.key(&lower.join("img.jpg")), "photos/img.jpg");
+        let spelled = PathBuf::from(base.to_string_lossy().to_uppercase()).join("Photos/a");
+        assert_eq!(root.key(&spelled), "photos/a");
+    }
+
+    #[test]
+    fn empty_base_means_current_directory() {
+        let root = ScanRoot::for_base(Path::new(""));
+        assert_eq!(
+            root.base(),
+            std::env::current_dir().unwrap().canonicalize().unwrap()
+        );
+    }
+
+    #[test]
+    fn displays_paths_relative_to_a_chosen_directory() {
+        let scan = Path::new("/data/photos");
+        let file = Path::new("/data/photos/2024/a.jpg");
+        assert_eq!(DisplayPaths::Auto.show(file, scan), Path::new("2024/a.jpg"));
+        assert_eq!(DisplayPaths::Auto.export(file), file);
+        assert_eq!(DisplayPaths::Absolute.show(file, scan), file);
+
+        let backup = DisplayPaths::RelativeTo(PathBuf::from("/data/backup"));
+        assert_eq!(backup.show(file, scan), Path::new("../photos/2024/a.jpg"));
+        assert_eq!(backup.export(file), Path::new("../photos/2024/a.jpg"));
+        assert_eq!(backup.export(Path::new("/data/backup/x")), Path::new("x"));
+        assert_eq!(backup.export(Path::new("/data/backup")), Path::new("."));
+        assert_eq!(
+            "absolute".parse::<DisplayPaths>(),
+            Ok(DisplayPaths::Absolute)
+        );
+        assert!("relative-to=".parse::<DisplayPaths>().is_err());
+    }
+}
@@ -1,212 +1,2373 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs;
 use std::io::Read;
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::Result;
 use blake3;
-use indicatif::{HumanBytes, HumanCount, ProgressBar, ProgressStyle};
-use log::{error, info, warn};
+use indicatif::ProgressBar;
+use log::{debug, error, info, warn};
+use rayon::ThreadPool;
 use rayon::prelude::*;
-use walkdir::WalkDir;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::json;
 
 use crate::FileInfo;
-use crate::cache::HashCache;
+use crate::content_hash::ContentHash;
+use crate::decompress::{Compression, hash_decompressed};
+use crate::dir_index::{DirIndex, ListedEntry};
+use crate::duplicates::HashGroups;
+use crate::error::{CheckFileDupsError, IoResultExt, Result};
+use crate::file_type::{FileType, retain_types};
+use crate::hash_store::HashStore;
+use crate::paths::PathInterner;
+use crate::progress::{FileProgress, HashStats, ScanProgress, progress_event};
+use crate::reflink::extent_id;
+use crate::resume::ResumeState;
+use crate::scan_errors::{ScanErrorKind, ScanErrors};
+use crate::scan_root::ScanRoot;
+use crate::snapshot::{Snapshot, find_in};
+use crate::throttle::Throttle;
+use crate::units::{Bytes, Count};
+use crate::utils::new_spinner;
 
-pub fn calculate_file_hash(
-    file_path: &PathBuf,
-    base_path: &PathBuf,
-    cache: &HashCache,
+/// Number of threads used for one stage of a scan, or `auto` to choose one at startup.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Threads {
+    /// Choose the number of threads from the number of CPU cores and, for reading files,
+    /// a short benchmark of the disk.
+    #[default]
+    Auto,
+    /// Use exactly this many threads.
+    Count(usize),
+}
+
+impl std::str::FromStr for Threads {
+    type Err = CheckFileDupsError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Threads::Auto);
+        }
+        match s.parse::<usize>() {
+            Ok(0) | Err(_) => Err(CheckFileDupsError::InvalidOptions(format!(
+                "invalid thread count '{}', expected a positive number or 'auto'",
+                s
+            ))),
+            Ok(count) => Ok(Threads::Count(count)),
+        }
+    }
+}
+
+impl fmt::Display for Threads {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Threads::Auto => write!(f, "auto"),
+            Threads::Count(count) => write!(f, "{}", count),
+        }
+    }
+}
+
+/// Accepts a number (`threads = 4`) or a string (`threads = "auto"`) in the config file.
+impl<'de> Deserialize<'de> for Threads {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Count(usize),
+            Name(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Count(count) => count.to_string().parse(),
+            Raw::Name(name) => name.parse(),
+        }
+        .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Threads {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            Threads::Auto => serializer.serialize_str("auto"),
+            Threads::Count(count) => serializer.serialize_u64(*count as u64),
+        }
+    }
+}
+
+/// Pause before the first retry of a file that could not be read. Each further retry
+/// waits one such delay longer, giving a network share time to recover.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Smallest buffer files are read with when it is sized by the file, so small files are
+/// read in one go.
+const MIN_READ_BUFFER: usize = 64 * 1024;
+
+/// Largest buffer files are read with when it is sized by the file, which keeps reads
+/// long enough for fast disks without holding much memory per reading thread.
+const MAX_READ_BUFFER: usize = 4 * 1024 * 1024;
+
+/// Files are read in chunks at least this long once they reach the large file threshold,
+/// so each chunk is long enough to split between all cores.
+const LARGE_FILE_CHUNK: usize = 16 * 1024 * 1024;
+
+/// Size from which files are hashed on several threads at once by default.
+pub const DEFAULT_LARGE_FILE_THRESHOLD: u64 = 1024 * 1024 * 1024;
+
+/// Size from which a file is hashed on several threads at once, set once at startup by
+/// `--large-file-threshold`.
+static LARGE_FILE_THRESHOLD: AtomicU64 = AtomicU64::new(DEFAULT_LARGE_FILE_THRESHOLD);
+
+/// Size of the buffer files are read with, set once at startup by `--read-buffer`, or 0
+/// to size it by each file.
+static READ_BUFFER: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether to drop files from the page cache once read, set once at startup by
+/// `--drop-page-cache`.
+static DROP_PAGE_CACHE: AtomicBool = AtomicBool::new(false);
+
+/// Sets how files are read for hashing: with a buffer of `buffer` bytes, or one sized by
+/// each file if `None`, and, with `drop_page_cache`, telling the kernel (on Linux) that
+/// their data won't be needed again once read, so a scan doesn't evict what other
+/// programs have cached. Files of at least `large_file_threshold` bytes are hashed on
+/// several threads at once.
+pub fn set_read_options(buffer: Option<u64>, drop_page_cache: bool, large_file_threshold: u64) {
+    let buffer = buffer.map_or(0, |size| usize::try_from(size).unwrap_or(usize::MAX).max(1));
+    READ_BUFFER.store(buffer, Ordering::Relaxed);
+    DROP_PAGE_CACHE.store(drop_page_cache, Ordering::Relaxed);
+    LARGE_FILE_THRESHOLD.store(large_file_threshold, Ordering::Relaxed);
+}
+
+/// Returns the size of the buffer to read a file of `file_size` bytes with: the size set
+/// by [`set_read_options`], or the file size rounded up to a power of two, between
+/// [`MIN_READ_BUFFER`] and [`MAX_READ_BUFFER`].
+pub fn read_buffer_size(file_size: u64) -> usize {
+    match READ_BUFFER.load(Ordering::Relaxed) {
+        0 => adaptive_read_buffer(file_size),
+        size => size,
+    }
+}
+
+fn adaptive_read_buffer(file_size: u64) -> usize {
+    // One more byte, so the read reaching the end of the file also sees it ends
+    let wanted = file_size.saturating_add(1).min(MAX_READ_BUFFER as u64) as usize;
+    wanted
+        .next_power_of_two()
+        .clamp(MIN_READ_BUFFER, MAX_READ_BUFFER)
+}
+
+/// Tells the kernel that `file` is about to be read from start to end, so it reads ahead
+/// further. Only done on Linux and Android.
+fn advise_sequential(file: &fs::File) {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::os::fd::AsRawFd;
+        // SAFETY: the descriptor is open for as long as `file` is borrowed. The hint is
+        // only advice, so failures are ignored
+        unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_SEQUENTIAL) };
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let _ = file;
+}
+
+/// Tells the kernel that the data of `file`, just read, won't be needed again, if
+/// [`set_read_options`] asked for it. Only done on Linux and Android.
+fn advise_done(file: &fs::File) {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if DROP_PAGE_CACHE.load(Ordering::Relaxed) {
+        use std::os::fd::AsRawFd;
+        // SAFETY: as in `advise_sequential`
+        unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let _ = file;
+}
+
+/// How to handle files that can't be read, e.g. on flaky SMB or NFS shares.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetryPolicy {
+    /// Give up reading a file when no data arrives for this long. `None` waits forever.
+    pub read_timeout: Option<Duration>,
+    /// Number of times to retry a file whose read failed or timed out. Missing files and
+    /// files without read permission are not retried.
+    pub retries: u32,
+}
+
+/// Names of the files and directories operating systems leave behind to hold metadata,
+/// such as thumbnails, folder settings and search indexes. Matched case-insensitively.
+pub const JUNK_NAMES: &[&str] = &[
+    ".DS_Store",
+    ".Spotlight-V100",
+    ".fseventsd",
+    "Thumbs.db",
+    "ehthumbs.db",
+    "desktop.ini",
+];
+
+/// Files and directories left out of a scan by their name or attributes, besides
+/// `skip_dirs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NameFilter {
+    /// Leave out hidden files and directories: those whose name starts with a dot, and
+    /// on Windows also those with the hidden attribute.
+    pub skip_hidden: bool,
+    /// Leave out the operating system metadata in [`JUNK_NAMES`].
+    pub skip_junk: bool,
+}
+
+impl Default for NameFilter {
+    fn default() -> Self {
+        Self {
+            skip_hidden: false,
+            skip_junk: true,
+        }
+    }
+}
+
+impl NameFilter {
+    /// Returns `true` if `path` is operating system metadata left out by the filter.
+    pub fn is_junk(&self, path: &Path) -> bool {
+        self.skip_junk
+            && path.file_name().is_some_and(|name| {
+                JUNK_NAMES
+                    .iter()
+                    .any(|junk| name.eq_ignore_ascii_case(junk))
+            })
+    }
+
+    /// Returns `true` if the directory entry `entry` is hidden and left out by the filter.
+    pub fn is_hidden(&self, entry: &fs::DirEntry) -> bool {
+        self.skip_hidden && is_hidden(entry)
+    }
+}
+
+/// Returns `true` if the directory entry `entry` is hidden: its name starts with a dot,
+/// or on Windows, it has the hidden attribute.
+fn is_hidden(entry: &fs::DirEntry) -> bool {
+    if entry.file_name().as_encoded_bytes().starts_with(b".") {
+        return true;
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        use windows_sys::Win32::Storage::FileSystem::FILE_ATTRIBUTE_HIDDEN;
+        // Read along with the directory, so this doesn't cost another call
+        entry
+            .metadata()
+            .is_ok_and(|metadata| metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+    }
+    #[cfg(not(windows))]
+    false
+}
+
+/// Limits on the modification time of the files to scan. Files whose modification time
+/// can't be read are left out once either limit is set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModifiedRange {
+    /// Only scan files modified at or after this time.
+    pub newer_than: Option<SystemTime>,
+    /// Only scan files modified before this time.
+    pub older_than: Option<SystemTime>,
+}
+
+impl ModifiedRange {
+    /// Returns `true` if a file modified at `modified` is within the limits.
+    pub fn contains(&self, modified: Option<SystemTime>) -> bool {
+        if self.newer_than.is_none() && self.older_than.is_none() {
+            return true;
+        }
+        modified.is_some_and(|modified| {
+            self.newer_than.is_none_or(|limit| modified >= limit)
+                && self.older_than.is_none_or(|limit| modified < limit)
+        })
+    }
+}
+
+/// Limits on the number of files a scan collects, for a quick look at part of a large
+/// tree before scanning all of it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanLimit {
+    /// Collect at most this many files.
+    pub files: Option<u64>,
+    /// Collect files adding up to at most this many bytes.
+    pub bytes: Option<u64>,
+    /// Pick the files at random from the whole tree, rather than stopping the walk once
+    /// a limit is reached.
+    pub random: bool,
+}
+
+impl ScanLimit {
+    /// Returns `true` if either limit is set.
+    pub fn is_set(&self) -> bool {
+        self.files.is_some() || self.bytes.is_some()
+    }
+
+    /// Returns `true` if `files` files adding up to `bytes` are within the limits.
+    fn allows(&self, files: u64, bytes: u64) -> bool {
+        self.files.is_none_or(|limit| files <= limit)
+            && self.bytes.is_none_or(|limit| bytes <= limit)
+    }
+}
+
+/// Kinds of directory entries other than regular files and directories. They are never
+/// hashed, since reading a FIFO or a device can block forever or never end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SpecialFile {
+    Fifo,
+    Socket,
+    BlockDevice,
+    CharDevice,
+    Other,
+}
+
+impl SpecialFile {
+    /// Classifies an entry that is neither a regular file nor a directory.
+    pub fn of(file_type: &fs::FileType) -> Self {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            if file_type.is_fifo() {
+                return Self::Fifo;
+            } else if file_type.is_socket() {
+                return Self::Socket;
+            } else if file_type.is_block_device() {
+                return Self::BlockDevice;
+            } else if file_type.is_char_device() {
+                return Self::CharDevice;
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = file_type;
+        Self::Other
+    }
+
+    /// Name used in log messages.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Fifo => "named pipes",
+            Self::Socket => "sockets",
+            Self::BlockDevice => "block devices",
+            Self::CharDevice => "character devices",
+            Self::Other => "other special files",
+        }
+    }
+}
+
+/// Number of files read in each half of the benchmark run by [`auto_io_threads`].
+const BENCHMARK_FILES: usize = 16;
+
+/// Only the start of each benchmark file is read, so large files don't slow down startup.
+const BENCHMARK_BYTES_PER_FILE: u64 = 4 * 1024 * 1024;
+
+/// Reading in parallel must be at least this much faster than sequential reading for
+/// `auto` to read files in parallel.
+const PARALLEL_SPEEDUP: f64 = 1.5;
+
+/// Reads the start of each of `paths` on `threads` threads, returning the throughput in
+/// bytes per second.
+fn read_throughput(paths: &[&PathBuf], threads: usize) -> Result<f64> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?;
+    let start = Instant::now();
+    let bytes: u64 = pool.install(|| {
+        paths
+            .par_iter()
+            .map(|path| {
+                fs::File::open(path)
+                    .and_then(|file| {
+                        std::io::copy(
+                            &mut file.take(BENCHMARK_BYTES_PER_FILE),
+                            &mut std::io::sink(),
+                        )
+                    })
+                    .unwrap_or(0)
+            })
+            .sum()
+    });
+    Ok(bytes as f64 / start.elapsed().as_secs_f64().max(1e-6))
+}
+
+/// Chooses how many files to read at once by timing sequential against parallel reads
+/// of a sample of the files that need hashing. SSDs read many files at once faster than
+/// one at a time, while on HDDs parallel reads cause seeking and are slower, so a single
+/// thread is used unless parallel reads are clearly faster.
+///
+/// Files already in the cache are not read by the scan and are left out of the sample.
+/// If there are too few files to hash for a meaningful benchmark, all cores are used.
+fn auto_io_threads(
+    file_paths: &[PathBuf],
+    root: &ScanRoot,
+    cache: &dyn HashStore,
     use_cache: bool,
-) -> Result<String> {
-    // Check cache first if enabled
-    if use_cache {
-        if let Some(cached_hash) = cache.get_hash(file_path, base_path)? {
-            return Ok(cached_hash);
+    cores: usize,
+) -> usize {
+    if cores == 1 {
+        return 1;
+    }
+    // Spread the sample over the tree rather than taking one directory
+    let step = (file_paths.len() / (BENCHMARK_FILES * 16)).max(1);
+    let sample: Vec<&PathBuf> = file_paths
+        .iter()
+        .step_by(step)
+        .filter(|path| !use_cache || matches!(cache.get_hash(path, root), Ok(None)))
+        .take(BENCHMARK_FILES * 2)
+        .collect();
+    if sample.len() < 4 {
+        debug!(
+            "Too few files to hash for a disk benchmark, reading {} files at once",
+            cores
+        );
+        return cores;
+    }
+    let (first, second) = sample.split_at(sample.len() / 2);
+    let (sequential, parallel) = match (read_throughput(first, 1), read_throughput(second, cores)) {
+        (Ok(sequential), Ok(parallel)) => (sequential, parallel),
+        (Err(e), _) | (_, Err(e)) => {
+            warn!("Disk benchmark failed, reading one file at a time: {}", e);
+            return 1;
+        }
+    };
+    debug!(
+        "Disk benchmark: {}/s sequential, {}/s with {} threads",
+        Bytes(sequential as u64),
+        Bytes(parallel as u64),
+        cores
+    );
+    if parallel >= sequential * PARALLEL_SPEEDUP {
+        cores
+    } else {
+        1
+    }
+}
+
+/// Returns the (device, inode) pair identifying the file behind `metadata`.
+///
+/// Paths sharing the same pair are hardlinks to the same data. Returns `None` on
+/// platforms without stable inode numbers.
+pub fn file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some((metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Returns the user ID of the owner of a file, on Unix.
+pub fn owner(metadata: &fs::Metadata) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some(metadata.uid())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Returns the number of hardlinks to a file, if the platform reports it.
+pub fn links(metadata: &fs::Metadata) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some(metadata.nlink())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Returns `path` in the extended-length form `\\?\C:\...` (or `\\?\UNC\server\...`
+/// for network paths), which Windows APIs accept beyond the 260 character `MAX_PATH`
+/// limit. Relative and already extended paths are returned unchanged.
+pub fn extended_length_path(path: &str) -> String {
+    if path.starts_with(r"\\?\") || path.starts_with(r"\\.\") {
+        path.to_string()
+    } else if let Some(unc) = path.strip_prefix(r"\\").or_else(|| path.strip_prefix("//")) {
+        format!(r"\\?\UNC\{}", unc.replace('/', r"\"))
+    } else if path.as_bytes().get(1) == Some(&b':')
+        && matches!(path.as_bytes().get(2), Some(b'\\' | b'/'))
+    {
+        format!(r"\\?\{}", path.replace('/', r"\"))
+    } else {
+        path.to_string()
+    }
+}
+
+/// Returns `path` as a NUL-terminated UTF-16 string for Windows APIs, in extended-length
+/// form so that long paths work.
+#[cfg(windows)]
+fn wide_path(path: &Path) -> Vec<u16> {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    extended_length_path(&absolute.to_string_lossy())
+        .encode_utf16()
+        .chain(Some(0))
+        .collect()
+}
+
+/// Returns `true` if `path` is a directory junction (a mount point reparse point), as
+/// opposed to a symbolic link. Always `false` on other platforms than Windows.
+pub fn is_junction(path: &Path) -> bool {
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+        use windows_sys::Win32::Storage::FileSystem::{
+            FILE_ATTRIBUTE_REPARSE_POINT, FindClose, FindFirstFileW, WIN32_FIND_DATAW,
+        };
+        const IO_REPARSE_TAG_MOUNT_POINT: u32 = 0xA000_0003;
+
+        let wide = wide_path(path);
+        // SAFETY: WIN32_FIND_DATAW is plain data, for which all zeroes is valid
+        let mut data: WIN32_FIND_DATAW = unsafe { std::mem::zeroed() };
+        // SAFETY: `wide` is a NUL-terminated UTF-16 string and `data` a valid out pointer
+        let handle = unsafe { FindFirstFileW(wide.as_ptr(), &mut data) };
+        if handle == INVALID_HANDLE_VALUE {
+            return false;
+        }
+        // SAFETY: `handle` was returned by FindFirstFileW
+        unsafe { FindClose(handle) };
+        // For reparse points, dwReserved0 holds the reparse tag
+        data.dwFileAttributes & FILE_ATTRIBUTE_REPARSE_POINT != 0
+            && data.dwReserved0 == IO_REPARSE_TAG_MOUNT_POINT
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = path;
+        false
+    }
+}
+
+/// Returns the space allocated on disk for a file, in bytes.
+///
+/// Uses `st_blocks` on Unix and `GetCompressedFileSizeW` on Windows, which account for
+/// sparse and compressed files. Returns `None` if the allocated size cannot be determined.
+pub fn allocated_size(path: &Path, metadata: &fs::Metadata) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let _ = path;
+        // st_blocks is always in 512-byte units, regardless of the filesystem block size
+        Some(metadata.blocks() * 512)
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::Storage::FileSystem::{GetCompressedFileSizeW, INVALID_FILE_SIZE};
+
+        let _ = metadata;
+        let wide = wide_path(path);
+        let mut high: u32 = 0;
+        // SAFETY: `wide` is a NUL-terminated UTF-16 string and `high` is a valid out pointer
+        let low = unsafe { GetCompressedFileSizeW(wide.as_ptr(), &mut high) };
+        if low == INVALID_FILE_SIZE && std::io::Error::last_os_error().raw_os_error() != Some(0) {
+            return None;
         }
+        Some(((high as u64) << 32) | low as u64)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (path, metadata);
+        None
     }
+}
+
+/// Computes the BLAKE3 hash of a file's contents, bypassing the cache.
+pub fn hash_file(file_path: &Path) -> Result<ContentHash> {
+    hash_contents(file_path, None, u64::MAX, None, None, None)
+}
 
-    let mut file = fs::File::open(file_path)?;
+/// Computes the BLAKE3 hash of a file like [`hash_file`], spreading the hashing of each
+/// chunk over the threads of `pool`. This speeds up large files when reading is faster
+/// than a single core can hash.
+pub fn hash_file_parallel(file_path: &Path, pool: &ThreadPool) -> Result<ContentHash> {
+    hash_contents(file_path, Some(pool), 0, None, None, None)
+}
+
+/// Returns the hash of the contents of `file_path`. Files of at least
+/// `large_file_threshold` bytes are read in long chunks, each hashed on the threads of
+/// `pool`, or without one on the threads of the pool the caller runs on, so idle
+/// reading threads help hash them. Smaller files are hashed on the calling thread.
+fn hash_contents(
+    file_path: &Path,
+    pool: Option<&ThreadPool>,
+    large_file_threshold: u64,
+    throttle: Option<&Throttle>,
+    read_timeout: Option<Duration>,
+    progress: Option<&FileProgress>,
+) -> Result<ContentHash> {
     let mut hasher = blake3::Hasher::new();
-    let mut buffer = [0; 8192];
+    let mut update = |chunk: &[u8], large: bool| {
+        if let Some(throttle) = throttle {
+            throttle.consume(chunk.len() as u64);
+        }
+        if let Some(progress) = progress {
+            progress.read(chunk.len() as u64);
+        }
+        match pool {
+            Some(pool) if large => pool.install(|| hasher.update_rayon(chunk)),
+            None if large => hasher.update_rayon(chunk),
+            _ => hasher.update(chunk),
+        };
+    };
+    let chunk_size = |file_size: u64| {
+        let size = read_buffer_size(file_size);
+        // Long chunks give the threads enough work to split between them
+        if file_size >= large_file_threshold {
+            let file_size = usize::try_from(file_size).unwrap_or(usize::MAX);
+            size.max(LARGE_FILE_CHUNK.min(file_size.max(MIN_READ_BUFFER)))
+        } else {
+            size
+        }
+    };
+
+    match read_timeout {
+        Some(timeout) => {
+            let file_size = fs::metadata(file_path).map_or(0, |metadata| metadata.len());
+            let large = file_size >= large_file_threshold;
+            read_with_timeout(file_path, chunk_size(file_size), timeout, &mut |chunk| {
+                update(chunk, large)
+            })?;
+        }
+        None => {
+            let mut file = fs::File::open(file_path).at(file_path)?;
+            advise_sequential(&file);
+            let file_size = file.metadata().map_or(0, |metadata| metadata.len());
+            let large = file_size >= large_file_threshold;
+            let mut buffer = vec![0; chunk_size(file_size)];
+            loop {
+                let bytes_read = file.read(&mut buffer).at(file_path)?;
+                if bytes_read == 0 {
+                    break;
+                }
+                update(&buffer[..bytes_read], large);
+            }
+            advise_done(&file);
+        }
+    }
+    if let Some(throttle) = throttle {
+        throttle.finish_file();
+    }
+
+    Ok(hasher.finalize().into())
+}
+
+/// Reads `file_path` in chunks of `chunk_size` bytes on a separate thread, passing them
+/// to `update`, and fails if no data arrives for `timeout`. A read hanging on an
+/// unresponsive network share can't be cancelled, so the reading thread is left behind
+/// and exits once the read returns.
+fn read_with_timeout(
+    file_path: &Path,
+    chunk_size: usize,
+    timeout: Duration,
+    update: &mut dyn FnMut(&[u8]),
+) -> Result<()> {
+    let (sender, receiver) = mpsc::sync_channel::<std::io::Result<Vec<u8>>>(2);
+    let path = file_path.to_path_buf();
+    thread::spawn(move || {
+        let mut file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                let _ = sender.send(Err(e));
+                return;
+            }
+        };
+        advise_sequential(&file);
+        loop {
+            let mut buffer = vec![0; chunk_size];
+            match file.read(&mut buffer) {
+                // Dropping the sender tells the receiver that the whole file was read
+                Ok(0) => {
+                    advise_done(&file);
+                    return;
+                }
+                Ok(bytes_read) => {
+                    buffer.truncate(bytes_read);
+                    if sender.send(Ok(buffer)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    return;
+                }
+            }
+        }
+    });
 
     loop {
-        let bytes_read = file.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
+        match receiver.recv_timeout(timeout) {
+            Ok(chunk) => update(&chunk.at(file_path)?),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                return Err(CheckFileDupsError::ReadTimeout {
+                    path: file_path.to_path_buf(),
+                    timeout,
+                });
+            }
         }
-        hasher.update(&buffer[..bytes_read]);
     }
+}
+
+/// Returns `file_paths` with their metadata, largest first, as the queue of files to
+/// hash. Each idle thread takes the largest file left, so a huge file is started early
+/// rather than left to hash alone on one thread after all the others are done. Files whose
+/// metadata can't be read come last, to be reported as errors.
+fn largest_first(file_paths: &[PathBuf]) -> Vec<(&PathBuf, std::io::Result<fs::Metadata>)> {
+    let mut queue: Vec<_> = file_paths
+        .par_iter()
+        .map(|path| (path, path.metadata()))
+        .collect();
+    queue.sort_by_key(|(_, metadata)| {
+        std::cmp::Reverse(metadata.as_ref().map_or(0, |metadata| metadata.len()))
+    });
+    queue
+}
 
-    let hash = hasher.finalize().to_hex().to_string();
+/// Checks that `path` still has the size and modification time in `before`, returning
+/// [`CheckFileDupsError::ChangedDuringScan`] if not, or the IO error if it can no longer
+/// be read.
+fn check_unchanged(path: &Path, before: &fs::Metadata) -> Result<()> {
+    let after = path.metadata().at(path)?;
+    if after.len() != before.len() || after.modified().ok() != before.modified().ok() {
+        return Err(CheckFileDupsError::ChangedDuringScan {
+            path: path.to_path_buf(),
+        });
+    }
+    Ok(())
+}
+
+/// Returns `true` if reading a file failed in a way that may go away on its own, such as
+/// a timeout or a dropped network connection, rather than because the file is missing or
+/// not accessible.
+fn is_transient(error: &CheckFileDupsError) -> bool {
+    ScanErrorKind::of_error(error) == ScanErrorKind::Io
+}
+
+/// Name of the marker file that excludes the directory holding it, and everything
+/// below, from scans, for opting out a project without editing the config file.
+pub const IGNORE_MARKER: &str = ".check-file-dups-ignore";
+
+/// Returns `true` if `path` lies in one of `skip_dirs`. Only components below the scanned
+/// directory are matched, so `skip_dirs` never excludes the whole scan.
+pub fn is_skipped(root: &ScanRoot, path: &Path, skip_dirs: &[String]) -> bool {
+    skip_dirs.iter().any(|skip_dir| {
+        root.relative(path)
+            .components()
+            .any(|component| component.as_os_str().to_string_lossy() == *skip_dir)
+    })
+}
+
+/// Returns the hash of `file_path`, from the cache if the file hasn't changed since it
+/// was last hashed. Files are hashed on `hash_pool` if given, otherwise on the calling
+/// thread, and read no faster than `throttle` allows. Reads that stall or fail are
+/// handled as `retry` says. Bytes read and cache hits are reported to `progress`.
+///
+/// With `compression`, the decompressed contents are hashed instead. Those hashes
+/// bypass the cache, which holds the hashes of files as they are on disk.
+#[allow(clippy::too_many_arguments)]
+pub fn calculate_file_hash(
+    file_path: &Path,
+    root: &ScanRoot,
+    cache: &dyn HashStore,
+    use_cache: bool,
+    hash_pool: Option<&ThreadPool>,
+    throttle: Option<&Throttle>,
+    retry: &RetryPolicy,
+    progress: Option<&FileProgress>,
+    compression: Option<Compression>,
+) -> Result<ContentHash> {
+    let use_cache = use_cache && compression.is_none();
+    // Check cache first if enabled
+    if use_cache {
+        if let Some(cached_hash) = cache.get_hash(file_path, root)?
+            && let Ok(cached_hash) = cached_hash.parse()
+        {
+            debug!("Cache hit: {}", file_path.display());
+            if let Some(progress) = progress {
+                progress.cache_hit();
+            }
+            return Ok(cached_hash);
+        }
+        debug!("Cache miss: {}", file_path.display());
+    }
+
+    let mut attempt = 0;
+    let hash = loop {
+        let result = match compression {
+            Some(compression) => {
+                let hash = hash_decompressed(file_path, compression, |read| {
+                    if let Some(throttle) = throttle {
+                        throttle.consume(read as u64);
+                    }
+                    if let Some(progress) = progress {
+                        progress.read(read as u64);
+                    }
+                });
+                if let Some(throttle) = throttle {
+                    throttle.finish_file();
+                }
+                hash
+            }
+            None => hash_contents(
+                file_path,
+                hash_pool,
+                LARGE_FILE_THRESHOLD.load(Ordering::Relaxed),
+                throttle,
+                retry.read_timeout,
+                progress,
+            ),
+        };
+        match result {
+            Ok(hash) => break hash,
+            Err(e) if attempt < retry.retries && is_transient(&e) => {
+                attempt += 1;
+                warn!(
+                    "Failed to read '{}': {:#}, retrying ({} of {})",
+                    file_path.display(),
+                    e,
+                    attempt,
+                    retry.retries
+                );
+                if let Some(progress) = progress {
+                    progress.restart();
+                }
+                thread::sleep(RETRY_DELAY * attempt);
+            }
+            Err(e) => return Err(e),
+        }
+    };
 
     // Cache the hash if caching is enabled
     if use_cache {
-        cache.set_hash(file_path, base_path, hash.clone())?;
+        cache.set_hash(file_path, root, hash.to_string())?;
     }
 
     Ok(hash)
 }
 
-pub fn scan_directory_with_cache(
-    path: &PathBuf,
-    cache: &HashCache,
-    base_path: &PathBuf,
-    skip_dirs: &[String],
-    num_threads: usize,
-    no_cache: bool,
-) -> Result<Vec<FileInfo>> {
-    let mut files = Vec::new();
-
-    // First pass: count files and directories, calculate total size
-    let mut total_files = 0;
-    let mut total_dirs = 0;
-    let mut total_size = 0u64;
-
-    info!("Scanning {}", path.display());
-
-    // Add a progress bar for the directory scan
-    let pb = ProgressBar::new_spinner();
-    pb.set_message("Scanning files and directories...");
-    pb.enable_steady_tick(std::time::Duration::from_millis(100));
-
-    let mut file_paths = Vec::new();
-    let mut skipped_dirs = std::collections::HashSet::new();
-
-    for entry in WalkDir::new(path)
-        .follow_links(true)
-        .into_iter()
-        .filter_entry(|e| {
-            // Check if this entry should be skipped
-            let path = e.path();
-            let should_skip = skip_dirs.iter().any(|skip_dir| {
-                path.components()
-                    .any(|component| component.as_os_str().to_string_lossy() == *skip_dir)
-            });
+/// Returns the number of threads to list directories on with [`Threads::Auto`]: twice the
+/// number of cores, since listing a directory mostly waits for the filesystem, especially
+/// on network shares.
+pub(crate) fn auto_walk_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get()) * 2
+}
 
-            // If it's a directory and should be skipped, log it once
-            if should_skip && path.is_dir() {
-                if skipped_dirs.insert(path.to_path_buf()) {
-                    warn!("Skipping directory: {}", path.display());
+/// Returns an identifier of the directory at `path`, used to detect symlink loops and
+/// mount points while walking: the (device, inode) pair on Unix, and the volume serial
+/// number and file index on Windows. Returns `None` if it can't be determined.
+fn directory_id(path: &Path, metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        let _ = path;
+        file_id(metadata)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        use std::os::windows::io::AsRawHandle;
+        use windows_sys::Win32::Storage::FileSystem::{
+            BY_HANDLE_FILE_INFORMATION, FILE_FLAG_BACKUP_SEMANTICS, GetFileInformationByHandle,
+        };
+
+        let _ = metadata;
+        // Directories can only be opened with backup semantics, and no access is needed
+        // to query them
+        let dir = fs::OpenOptions::new()
+            .access_mode(0)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+            .open(path)
+            .ok()?;
+        // SAFETY: BY_HANDLE_FILE_INFORMATION is plain data, for which all zeroes is valid
+        let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+        // SAFETY: `dir` is an open handle and `info` a valid out pointer
+        if unsafe { GetFileInformationByHandle(dir.as_raw_handle(), &mut info) } == 0 {
+            return None;
+        }
+        Some((
+            u64::from(info.dwVolumeSerialNumber),
+            (u64::from(info.nFileIndexHigh) << 32) | u64::from(info.nFileIndexLow),
+        ))
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (path, metadata);
+        None
+    }
+}
+
+/// What a walk found so far. Each directory is read into its own copy, which is then
+/// merged, so the threads of a walk rarely wait for each other.
+#[derive(Default)]
+struct WalkResult {
+    file_paths: Vec<PathBuf>,
+    total_size: u64,
+    total_dirs: u64,
+    outside_range: u64,
+    /// Operating system metadata left out, see [`NameFilter::is_junk`].
+    junk: u64,
+    special_files: BTreeMap<SpecialFile, u64>,
+    errors: ScanErrors,
+}
+
+impl WalkResult {
+    fn merge(&mut self, other: WalkResult) {
+        self.file_paths.extend(other.file_paths);
+        self.total_size += other.total_size;
+        self.total_dirs += other.total_dirs;
+        self.outside_range += other.outside_range;
+        self.junk += other.junk;
+        for (kind, count) in other.special_files {
+            *self.special_files.entry(kind).or_insert(0) += count;
+        }
+        self.errors.merge(other.errors);
+    }
+}
+
+/// A walk of one directory tree, reading its directories in parallel.
+struct Walker<'a> {
+    root: &'a ScanRoot,
+    skip_dirs: &'a [String],
+    names: &'a NameFilter,
+    min_size: u64,
+    modified: &'a ModifiedRange,
+    /// Whether to stay on the device of the scanned directory each walk started from.
+    one_file_system: bool,
+    follow_junctions: bool,
+    /// Listings of directories unchanged since the last scan, and where to record them.
+    dir_index: Option<&'a DirIndex>,
+    /// Limits at which the walk stops, unless files are sampled at random afterwards.
+    limit: &'a ScanLimit,
+    files_taken: AtomicU64,
+    bytes_taken: AtomicU64,
+    /// Set once a limit is reached, so no more directories are read.
+    full: AtomicBool,
+    result: Mutex<WalkResult>,
+    /// Directories, files and bytes found so far, shown on the spinner.
+    found_dirs: AtomicU64,
+    found_files: AtomicU64,
+    found_bytes: AtomicU64,
+    spinner: ProgressBar,
+}
+
+impl Walker<'_> {
+    /// Counts a file of `size` bytes towards the limits, returning `false` and stopping
+    /// the walk if it doesn't fit.
+    fn take(&self, size: u64) -> bool {
+        if self.limit.random || !self.limit.is_set() {
+            return true;
+        }
+        let files = self.files_taken.fetch_add(1, Ordering::Relaxed) + 1;
+        let bytes = self.bytes_taken.fetch_add(size, Ordering::Relaxed) + size;
+        let fits = self.limit.allows(files, bytes);
+        if !fits {
+            self.full.store(true, Ordering::Relaxed);
+        }
+        fits
+    }
+
+    /// Returns the entries of the directory `dir`, from the directory index if it hasn't
+    /// changed since it was indexed, or `None` if it can't be read. Entries that can't be
+    /// read are added to `errors`, and the directory isn't indexed then.
+    fn list(&self, dir: &Path, errors: &mut ScanErrors) -> Option<Vec<ListedEntry>> {
+        // Read before the entries, so changes made while reading them show next time
+        let metadata = self.dir_index.and_then(|_| fs::metadata(dir).ok());
+        if let (Some(dir_index), Some(metadata)) = (self.dir_index, &metadata)
+            && let Some(entries) = dir_index.get(dir, metadata)
+        {
+            return Some(entries);
+        }
+        let read = match fs::read_dir(dir) {
+            Ok(read) => read,
+            Err(e) => {
+                // Unreadable subtrees are summed up at the end, rather than logged one by one
+                if e.kind() == std::io::ErrorKind::PermissionDenied {
+                    debug!("Failed to read directory '{}': {}", dir.display(), e);
+                } else {
+                    warn!("Failed to read directory '{}': {}", dir.display(), e);
                 }
+                errors.add(dir, &e.into());
+                return None;
             }
+        };
+        let mut entries = Vec::new();
+        let mut complete = true;
+        for entry in read {
+            match entry {
+                Ok(entry) => entries.push(ListedEntry {
+                    name: entry.file_name().into(),
+                    symlink: entry.file_type().is_ok_and(|t| t.is_symlink()),
+                    hidden: is_hidden(&entry),
+                }),
+                Err(e) => {
+                    warn!(
+                        "Failed to read directory entry in '{}': {}",
+                        dir.display(),
+                        e
+                    );
+                    errors.add(dir, &e.into());
+                    complete = false;
+                }
+            }
+        }
+        if let (Some(dir_index), Some(metadata)) = (self.dir_index, &metadata)
+            && complete
+        {
+            dir_index.record(dir, metadata, entries.clone());
+        }
+        Some(entries)
+    }
 
-            !should_skip
-        })
-    {
-        pb.tick();
-        match entry {
-            Ok(entry) => {
-                let path = entry.path();
+    /// Reads the directory `dir` and spawns a task on `scope` for each of its
+    /// subdirectories, so idle threads steal directories from busy ones. `ancestors`
+    /// identifies `dir` and the directories above it up to the scanned directory, to detect
+    /// symlink loops and other filesystems.
+    fn walk<'s>(&'s self, scope: &rayon::Scope<'s>, dir: PathBuf, ancestors: Vec<(u64, u64)>) {
+        if self.full.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut result = WalkResult::default();
+        let Some(entries) = self.list(&dir, &mut result.errors) else {
+            self.result.lock().unwrap().merge(result);
+            return;
+        };
 
+        for entry in entries {
+            let path = dir.join(&entry.name);
+            let is_symlink = entry.symlink;
+            if !self.follow_junctions && is_symlink && is_junction(&path) {
+                debug!("Skipping junction: {}", path.display());
+                continue;
+            }
+            if is_skipped(self.root, &path, self.skip_dirs) {
                 if path.is_dir() {
-                    total_dirs += 1;
-                } else if path.is_file() {
-                    if let Ok(metadata) = path.metadata() {
-                        let size = metadata.len();
-                        total_files += 1;
-                        total_size += size;
-                        file_paths.push(path.to_path_buf());
-                    }
+                    warn!("Skipping directory: {}", path.display());
+                }
+                continue;
+            }
+            if self.names.is_junk(&path) {
+                debug!("Skipping operating system metadata: {}", path.display());
+                result.junk += 1;
+                continue;
+            }
+            if self.names.skip_hidden && entry.hidden {
+                debug!("Skipping hidden: {}", path.display());
+                continue;
+            }
+            // Symbolic links are followed
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                // Broken symlinks hide no data, so they don't make the results incomplete
+                Err(e) if is_symlink && e.kind() == std::io::ErrorKind::NotFound => {
+                    debug!("Skipping broken symlink: {}", path.display());
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Failed to read metadata for '{}': {}", path.display(), e);
+                    result.errors.add(&path, &e.into());
+                    continue;
+                }
+            };
+
+            if metadata.is_dir() {
+                let id = directory_id(&path, &metadata);
+                if let (Some((device, _)), Some((dir_device, _))) = (ancestors.first(), id)
+                    && self.one_file_system
+                    && dir_device != *device
+                {
+                    debug!("Skipping other filesystem: {}", path.display());
+                    continue;
+                }
+                if id.is_some_and(|id| ancestors.contains(&id)) {
+                    warn!(
+                        "Skipping '{}': it links to a directory containing it",
+                        path.display()
+                    );
+                    continue;
+                }
+                if path.join(IGNORE_MARKER).exists() {
+                    info!(
+                        "Skipping directory marked with {}: {}",
+                        IGNORE_MARKER,
+                        path.display()
+                    );
+                    continue;
+                }
+                result.total_dirs += 1;
+                let mut ancestors = ancestors.clone();
+                ancestors.extend(id);
+                scope.spawn(move |scope| self.walk(scope, path, ancestors));
+            } else if metadata.is_file() {
+                let size = metadata.len();
+                if size > 0 && size < self.min_size {
+                    continue;
                 }
+                if !self.modified.contains(metadata.modified().ok()) {
+                    result.outside_range += 1;
+                    continue;
+                }
+                if !self.take(size) {
+                    break;
+                }
+                result.total_size += size;
+                result.file_paths.push(path);
+            } else {
+                let kind = SpecialFile::of(&metadata.file_type());
+                debug!("Skipping {}: {}", kind.name(), path.display());
+                *result.special_files.entry(kind).or_insert(0) += 1;
             }
+        }
+        self.show_found(&result);
+        self.result.lock().unwrap().merge(result);
+    }
+
+    /// Adds what a directory held to the counts on the spinner, so a slow walk, e.g. of
+    /// a network share, shows how far it got.
+    fn show_found(&self, result: &WalkResult) {
+        let dirs = self.found_dirs.fetch_add(1, Ordering::Relaxed) + 1;
+        let files = self
+            .found_files
+            .fetch_add(result.file_paths.len() as u64, Ordering::Relaxed)
+            + result.file_paths.len() as u64;
+        let bytes = self
+            .found_bytes
+            .fetch_add(result.total_size, Ordering::Relaxed)
+            + result.total_size;
+        self.spinner.set_message(format!(
+            "Scanning files and directories: {} directories, {} files ({})",
+            Count(dirs),
+            Count(files),
+            Bytes(bytes)
+        ));
+        progress_event(
+            "walk",
+            false,
+            || json!({"dirs_done": dirs, "files_done": files, "bytes_done": bytes}),
+        );
+    }
+}
+
+/// Walks the directories of `root` on `threads` threads, returning the files to hash,
+/// sorted by path, and their total size. Directories are read in parallel, which mostly
+/// helps on network filesystems, where each read waits for the server. Skipped
+/// directories are logged once, files and directories are left out as `names` says, and
+/// non-empty files smaller than `min_size` or modified
+/// outside `modified` are left out. With `one_file_system`, directories on other devices
+/// than the scanned directory they are in (mount points) are not descended into.
+/// Directory junctions are only followed with `follow_junctions`, since they often point
+/// back into the tree (e.g. the legacy `Application Data` junctions in Windows user
+/// profiles) and would be scanned twice. With `dir_index`, directories unchanged since
+/// they were last indexed are listed from it, and the others are recorded. Once `limit` is reached the walk stops, or with
+/// `limit.random` files are picked at random from the whole tree up to the limit. Entries
+/// that can't be read are added to `errors`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn walk_directory(
+    root: &ScanRoot,
+    skip_dirs: &[String],
+    names: &NameFilter,
+    min_size: u64,
+    modified: &ModifiedRange,
+    limit: &ScanLimit,
+    one_file_system: bool,
+    follow_junctions: bool,
+    dir_index: Option<&DirIndex>,
+    threads: usize,
+    errors: &mut ScanErrors,
+) -> Result<(Vec<PathBuf>, u64)> {
+    let path = root.scan_path();
+    let mut starts = Vec::new();
+    for dir in root.roots() {
+        info!("Scanning {}", dir.display());
+        match fs::metadata(dir) {
+            Ok(metadata) => starts.push((dir, directory_id(dir, &metadata))),
             Err(e) => {
-                warn!("Failed to read directory entry: {}", e);
+                warn!("Failed to read metadata for '{}': {}", dir.display(), e);
+                errors.add(dir, &e.into());
             }
         }
     }
-    pb.finish_and_clear();
+    if starts.is_empty() {
+        return Ok((Vec::new(), 0));
+    }
+    let walker = Walker {
+        root,
+        skip_dirs,
+        names,
+        min_size,
+        modified,
+        one_file_system,
+        follow_junctions,
+        dir_index,
+        limit,
+        files_taken: AtomicU64::new(0),
+        bytes_taken: AtomicU64::new(0),
+        full: AtomicBool::new(false),
+        result: Mutex::new(WalkResult {
+            total_dirs: starts.len() as u64,
+            ..WalkResult::default()
+        }),
+        found_dirs: AtomicU64::new(0),
+        found_files: AtomicU64::new(0),
+        found_bytes: AtomicU64::new(0),
+        spinner: new_spinner("Scanning files and directories..."),
+    };
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?
+        .scope(|scope| {
+            for (dir, id) in starts {
+                let walker = &walker;
+                scope.spawn(move |scope| walker.walk(scope, dir.clone(), id.into_iter().collect()));
+            }
+        });
+    walker.spinner.finish_and_clear();
+    progress_event("walk", true, || {
+        json!({
+            "dirs_done": walker.found_dirs.load(Ordering::Relaxed),
+            "files_done": walker.found_files.load(Ordering::Relaxed),
+            "bytes_done": walker.found_bytes.load(Ordering::Relaxed),
+        })
+    });
 
+    let full = walker.full.load(Ordering::Relaxed);
+    let mut result = walker.result.into_inner().unwrap();
+    if full {
+        warn!(
+            "Stopped listing files at the --limit-files/--limit-bytes limit, so the results only cover part of {}",
+            path.display()
+        );
+    }
+    if limit.random && limit.is_set() {
+        let found = result.file_paths.len();
+        (result.file_paths, result.total_size) = sample(result.file_paths, limit);
+        warn!(
+            "Sampled {} of {} files at random, so the results only cover part of {}",
+            Count(result.file_paths.len() as u64),
+            Count(found as u64),
+            path.display()
+        );
+    }
+    // Directories finish in any order, so sort for reproducible results
+    result.file_paths.sort();
+    errors.merge(result.errors);
     info!(
         "Found {} files and {} directories ({})",
-        HumanCount(total_files),
-        HumanCount(total_dirs),
-        HumanBytes(total_size)
+        Count(result.file_paths.len() as u64),
+        Count(result.total_dirs),
+        Bytes(result.total_size)
     );
-
-    let progress_bar = {
-        let pb = ProgressBar::new(total_size as u64);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {percent}% {msg} ETA: {eta} ({binary_bytes_per_sec})")
-                .unwrap()
-                .progress_chars("#>-"),
+    if result.outside_range > 0 {
+        info!(
+            "Skipped {} files modified outside the --newer-than/--older-than range",
+            Count(result.outside_range)
         );
-        Some(pb)
-    };
+    }
+    if result.junk > 0 {
+        info!(
+            "Skipped {} operating system metadata files such as .DS_Store and Thumbs.db (see --include-junk)",
+            Count(result.junk)
+        );
+    }
+    if !result.special_files.is_empty() {
+        let counts: Vec<String> = result
+            .special_files
+            .iter()
+            .map(|(kind, count)| format!("{} {}", Count(*count), kind.name()))
+            .collect();
+        info!(
+            "Skipped {} special files that can't be hashed: {}",
+            Count(result.special_files.values().sum()),
+            counts.join(", ")
+        );
+    }
 
-    // Set up parallel processing
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(num_threads)
-        .build_global()
-        .unwrap();
+    Ok((result.file_paths, result.total_size))
+}
 
-    let progress_bar = progress_bar.as_ref();
-    let files_processed = Arc::new(AtomicUsize::new(0));
-    let total_size_processed = Arc::new(AtomicU64::new(0));
-    let last_update = Arc::new(std::sync::Mutex::new(std::time::Instant::now()));
+/// Returns files of `file_paths` picked at random until `limit` is reached, with their
+/// total size. Files that can no longer be read are left out.
+fn sample(mut file_paths: Vec<PathBuf>, limit: &ScanLimit) -> (Vec<PathBuf>, u64) {
+    fastrand::shuffle(&mut file_paths);
+    let mut picked = Vec::new();
+    let mut total_size = 0;
+    for path in file_paths {
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+        if !limit.allows(picked.len() as u64 + 1, total_size + metadata.len()) {
+            break;
+        }
+        total_size += metadata.len();
+        picked.push(path);
+    }
+    (picked, total_size)
+}
 
-    // Process files in parallel
-    info!("Scanning files...");
-    let results: Vec<Result<FileInfo>> = file_paths
-        .par_iter()
-        .map(|path| {
-            let metadata = match path.metadata() {
-                Ok(metadata) => metadata,
-                Err(e) => {
-                    error!("Failed to read metadata for '{}': {}", path.display(), e);
-                    return Err(anyhow::anyhow!("Failed to read metadata"));
-                }
-            };
+/// Returns the regular files of `file_paths` to scan, each once and sorted by path, and
+/// their total size, leaving out non-empty files smaller than `min_size` and files
+/// modified outside `modified` like a walk does. Paths that can't be read are kept, so hashing reports them.
+fn listed_files(
+    file_paths: &[PathBuf],
+    min_size: u64,
+    modified: &ModifiedRange,
+) -> (Vec<PathBuf>, u64) {
+    let mut files = file_paths.to_vec();
+    files.sort();
+    files.dedup();
+    let mut total_size = 0;
+    files.retain(|path| match path.metadata() {
+        Ok(metadata) if !metadata.is_file() => {
+            debug!("Skipping listed non-file: {}", path.display());
+            false
+        }
+        Ok(metadata) => {
             let size = metadata.len();
+            let wanted =
+                (size == 0 || size >= min_size) && modified.contains(metadata.modified().ok());
+            if wanted {
+                total_size += size;
+            }
+            wanted
+        }
+        Err(_) => true,
+    });
+    info!(
+        "Read {} files to scan from the file list ({})",
+        Count(files.len() as u64),
+        Bytes(total_size)
+    );
+    (files, total_size)
+}
 
-            let hash = match calculate_file_hash(path, &base_path, &cache, !no_cache) {
-                Ok(hash) => hash,
-                Err(e) => {
-                    error!("Failed to calculate hash for '{}': {}", path.display(), e);
-                    return Err(e);
-                }
+/// What a scan looks at and how: the filters deciding which files are hashed, the
+/// threads and throttle used to read them, and how the cache and resume state are used.
+/// Options start out as for a plain scan of everything with the cache, and are set with
+/// the `with_` methods, so new options can be added without changing every caller.
+pub struct ScanOptions<'a> {
+    skip_dirs: Vec<String>,
+    names: NameFilter,
+    min_size: u64,
+    modified: ModifiedRange,
+    file_types: Vec<FileType>,
+    limit: ScanLimit,
+    file_list: Option<&'a [PathBuf]>,
+    one_file_system: bool,
+    follow_junctions: bool,
+    walk_threads: Threads,
+    io_threads: Threads,
+    hash_threads: Threads,
+    throttle: Option<&'a Throttle>,
+    retry: RetryPolicy,
+    use_cache: bool,
+    decompress: bool,
+    detect_reflinks: bool,
+    resume: Option<&'a ResumeState>,
+    dir_index: Option<&'a DirIndex>,
+    snapshots: &'a [Snapshot],
+    running: Option<&'a AtomicBool>,
+}
+
+impl Default for ScanOptions<'_> {
+    fn default() -> Self {
+        Self {
+            skip_dirs: Vec::new(),
+            names: NameFilter::default(),
+            min_size: 0,
+            modified: ModifiedRange::default(),
+            file_types: Vec::new(),
+            limit: ScanLimit::default(),
+            file_list: None,
+            one_file_system: false,
+            follow_junctions: false,
+            walk_threads: Threads::Auto,
+            io_threads: Threads::Auto,
+            hash_threads: Threads::Auto,
+            throttle: None,
+            retry: RetryPolicy::default(),
+            use_cache: true,
+            decompress: false,
+            detect_reflinks: false,
+            resume: None,
+            dir_index: None,
+            snapshots: &[],
+            running: None,
+        }
+    }
+}
+
+impl<'a> ScanOptions<'a> {
+    /// Skips the directories matching `skip_dirs` (see [`is_skipped`]).
+    pub fn with_skip_dirs(mut self, skip_dirs: Vec<String>) -> Self {
+        self.skip_dirs = skip_dirs;
+        self
+    }
+
+    /// Leaves out the files and directories `names` filters out.
+    pub fn with_names(mut self, names: NameFilter) -> Self {
+        self.names = names;
+        self
+    }
+
+    /// Skips non-empty files smaller than `min_size` bytes without hashing them.
+    pub fn with_min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Skips files modified outside `modified`.
+    pub fn with_modified(mut self, modified: ModifiedRange) -> Self {
+        self.modified = modified;
+        self
+    }
+
+    /// Only scans files of `file_types`, or of every kind if empty.
+    pub fn with_file_types(mut self, file_types: Vec<FileType>) -> Self {
+        self.file_types = file_types;
+        self
+    }
+
+    /// Scans at most the files `limit` allows.
+    pub fn with_limit(mut self, limit: ScanLimit) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Scans the files in `file_list`, if given, instead of walking the directory.
+    pub fn with_file_list(mut self, file_list: Option<&'a [PathBuf]>) -> Self {
+        self.file_list = file_list;
+        self
+    }
+
+    /// Skips other filesystems mounted inside the scanned directory.
+    pub fn with_one_file_system(mut self, enabled: bool) -> Self {
+        self.one_file_system = enabled;
+        self
+    }
+
+    /// Follows directory junctions even with [`Self::with_one_file_system`].
+    pub fn with_follow_junctions(mut self, enabled: bool) -> Self {
+        self.follow_junctions = enabled;
+        self
+    }
+
+    /// Lists directories on `walk` threads, reads `io` files at once and hashes each
+    /// file on `hash` threads.
+    pub fn with_threads(mut self, walk: Threads, io: Threads, hash: Threads) -> Self {
+        self.walk_threads = walk;
+        self.io_threads = io;
+        self.hash_threads = hash;
+        self
+    }
+
+    /// Reads the files that need hashing no faster than `throttle` allows.
+    pub fn with_throttle(mut self, throttle: &'a Throttle) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    /// Retries files that can't be read as `retry` says.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Takes hashes from the cache and adds new ones to it, unless `use_cache` is false.
+    pub fn with_cache(mut self, use_cache: bool) -> Self {
+        self.use_cache = use_cache;
+        self
+    }
+
+    /// Hashes gzip and Zstandard files by their decompressed contents.
+    pub fn with_decompress(mut self, enabled: bool) -> Self {
+        self.decompress = enabled;
+        self
+    }
+
+    /// Takes the hash of files sharing their data with a file of the same size already
+    /// hashed, found by [`extent_id`], from that file instead of reading them.
+    pub fn with_detect_reflinks(mut self, enabled: bool) -> Self {
+        self.detect_reflinks = enabled;
+        self
+    }
+
+    /// Reuses the files listed and hashed by an interrupted scan from `resume`, if given,
+    /// and records progress in it.
+    pub fn with_resume(mut self, resume: Option<&'a ResumeState>) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Lists directories unchanged since the last scan from `dir_index`, if given, and
+    /// records the others in it (see [`crate::dir_index`]).
+    pub fn with_dir_index(mut self, dir_index: Option<&'a DirIndex>) -> Self {
+        self.dir_index = dir_index;
+        self
+    }
+
+    /// Reads files locked by other programs from the one of `snapshots` of their volume.
+    pub fn with_snapshots(mut self, snapshots: &'a [Snapshot]) -> Self {
+        self.snapshots = snapshots;
+        self
+    }
+
+    /// Stops hashing new files once `running` is cleared (e.g. on Ctrl+C).
+    pub fn with_running(mut self, running: &'a AtomicBool) -> Self {
+        self.running = Some(running);
+        self
+    }
+}
+
+/// Scans `root.scan_path()` recursively and hashes every file, using the cache for files
+/// that haven't changed since they were last hashed, with the filters and settings of
+/// `options`.
+///
+/// With [`Threads::Auto`], directories are listed on twice as many threads as there are
+/// cores, the number of IO threads is chosen by a short disk benchmark, and the cores not
+/// used for reading are used for hashing. Files that can't be read, even after
+/// retrying, are returned separately with the other errors met during the scan, and with
+/// counts of the hashes taken from the cache.
+///
+/// Once the flag set by [`ScanOptions::with_running`] is cleared, no new files are
+/// hashed; files being hashed are finished and the files hashed so far are returned.
+pub fn scan_directory(
+    root: &ScanRoot,
+    cache: &dyn HashStore,
+    options: ScanOptions<'_>,
+) -> Result<(HashGroups, ScanErrors, HashStats)> {
+    let ScanOptions {
+        skip_dirs,
+        names,
+        min_size,
+        modified,
+        file_types,
+        limit,
+        file_list,
+        one_file_system,
+        follow_junctions,
+        walk_threads,
+        io_threads,
+        hash_threads,
+        throttle,
+        retry,
+        use_cache,
+        decompress,
+        detect_reflinks,
+        resume,
+        dir_index,
+        snapshots,
+        running,
+    } = options;
+    let mut groups = HashGroups::default();
+    let mut errors = ScanErrors::default();
+
+    // First pass: list the files to hash, unless an interrupted scan listed them already
+    let walk_start = Instant::now();
+    let (mut file_paths, mut total_size) = match (file_list, resume.and_then(ResumeState::files)) {
+        (Some(file_list), _) => {
+            let (file_paths, total_size) = listed_files(file_list, min_size, &modified);
+            if let Some(resume) = resume {
+                resume.set_files(&file_paths);
+            }
+            (file_paths, total_size)
+        }
+        (None, Some(file_paths)) => {
+            // The limits may be relative to now, so they are checked again
+            let mut total_size = 0;
+            let file_paths: Vec<PathBuf> = file_paths
+                .into_iter()
+                .filter(|path| match path.metadata() {
+                    Ok(metadata) if modified.contains(metadata.modified().ok()) => {
+                        total_size += metadata.len();
+                        true
+                    }
+                    Ok(_) => false,
+                    Err(_) => true,
+                })
+                .collect();
+            (file_paths, total_size)
+        }
+        (None, None) => {
+            let walk_threads = match walk_threads {
+                Threads::Count(count) => count,
+                Threads::Auto => auto_walk_threads(),
             };
+            let (file_paths, total_size) = walk_directory(
+                root,
+                &skip_dirs,
+                &names,
+                min_size,
+                &modified,
+                &limit,
+                one_file_system,
+                follow_junctions,
+                dir_index,
+                walk_threads,
+                &mut errors,
+            )?;
+            if let Some(resume) = resume {
+                resume.set_files(&file_paths);
+            }
+            (file_paths, total_size)
+        }
+    };
+
+    if !file_types.is_empty() {
+        total_size = retain_types(&mut file_paths, &file_types);
+    }
+    let walk_time = walk_start.elapsed();
 
-            // Update progress
-            let processed = files_processed.fetch_add(1, Ordering::Relaxed) + 1;
-            let size_processed = total_size_processed.fetch_add(size, Ordering::Relaxed) + size;
+    // Set up parallel processing
+    let hash_start = Instant::now();
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let io_threads = match io_threads {
+        Threads::Count(count) => count,
+        Threads::Auto => auto_io_threads(&file_paths, root, cache, use_cache, cores),
+    };
+    let hash_threads = match hash_threads {
+        Threads::Count(count) => count,
+        Threads::Auto => (cores / io_threads).max(1),
+    };
+    info!(
+        "Using {} IO threads and {} hashing threads",
+        io_threads, hash_threads
+    );
+    // Pools are owned by this scan rather than global, so scans can run repeatedly
+    let io_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(io_threads)
+        .build()?;
+    let hash_pool = if hash_threads > 1 {
+        Some(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(hash_threads)
+                .build()?,
+        )
+    } else {
+        None
+    };
 
-            if let Some(pb) = progress_bar {
-                let mut last_update_guard = last_update.lock().unwrap();
-                if last_update_guard.elapsed().as_millis() > 200 {
-                    pb.set_position(size_processed as u64);
-                    pb.set_message(format!(
-                        "Scanned {} files ({})",
-                        HumanCount(processed.try_into().unwrap()),
-                        HumanBytes(size_processed)
-                    ));
-                    *last_update_guard = std::time::Instant::now();
+    let progress = ScanProgress::new(total_size);
+    let paths = PathInterner::default();
+    // Hashes by size and extent ID, for `detect_reflinks`
+    let clones: Mutex<HashMap<(u64, u64), ContentHash>> = Mutex::new(HashMap::new());
+
+    // Process files in parallel, grouping the results on this thread as they arrive
+    info!("Scanning files...");
+    let queue = io_pool.install(|| largest_first(&file_paths));
+    let (sender, receiver) = mpsc::channel::<Result<FileInfo, (&PathBuf, CheckFileDupsError)>>();
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            io_pool.install(|| {
+                queue
+                    .into_iter()
+                    .par_bridge()
+                    .filter(|_| running.is_none_or(|running| running.load(Ordering::Relaxed)))
+                    .map(|(path, metadata)| {
+                        let file_progress = progress.file();
+                        let metadata = match metadata {
+                            Ok(metadata) => metadata,
+                            Err(e) => {
+                                error!(path:% = path.display(); "Failed to read metadata for '{}': {}", path.display(), e);
+                                file_progress.finish(0, false);
+                                return Err((path, CheckFileDupsError::io(path, e)));
+                            }
+                        };
+                        let size = metadata.len();
+                        // A resumed file list can be stale, so check again that the file can be read
+                        if !metadata.is_file() {
+                            let kind = SpecialFile::of(&metadata.file_type());
+                            warn!("Skipping {}: {}", kind.name(), path.display());
+                            file_progress.finish(size, false);
+                            return Err((
+                                path,
+                                CheckFileDupsError::NotRegularFile {
+                                    path: path.clone(),
+                                    kind: kind.name(),
+                                },
+                            ));
+                        }
+
+                        let compression = if decompress {
+                            Compression::of(path)
+                        } else {
+                            None
+                        };
+                        let resumed = resume
+                            .and_then(|resume| resume.get_hash(path, &metadata))
+                            .and_then(|hash| hash.parse().ok());
+                        // Reflinks of a file already hashed share its data, so they
+                        // aren't read again
+                        let clone = if detect_reflinks && compression.is_none() && resumed.is_none() {
+                            extent_id(path).ok().flatten().map(|id| (size, id))
+                        } else {
+                            None
+                        };
+                        let cloned = clone.and_then(|key| clones.lock().unwrap().get(&key).copied());
+                        let result = match (resumed, cloned) {
+                            (Some(hash), _) => {
+                                file_progress.cache_hit();
+                                Ok(hash)
+                            }
+                            (None, Some(hash)) => {
+                                debug!("Reflink of a file already hashed: {}", path.display());
+                                file_progress.cache_hit();
+                                if use_cache {
+                                    cache.set_hash(path, root, hash.to_string()).map(|()| hash)
+                                } else {
+                                    Ok(hash)
+                                }
+                            }
+                            (None, None) => calculate_file_hash(
+                                path,
+                                root,
+                                cache,
+                                use_cache,
+                                hash_pool.as_ref(),
+                                throttle,
+                                &retry,
+                                Some(&file_progress),
+                                compression,
+                            )
+                            .or_else(|e| match find_in(snapshots, path) {
+                                Some(shadow) if ScanErrorKind::of_error(&e) == ScanErrorKind::Locked => {
+                                    debug!("Reading locked file from the snapshot: {}", path.display());
+                                    let hash = hash_contents(
+                                        &shadow,
+                                        hash_pool.as_ref(),
+                                        LARGE_FILE_THRESHOLD.load(Ordering::Relaxed),
+                                        throttle,
+                                        retry.read_timeout,
+                                        Some(&file_progress),
+                                    )?;
+                                    if use_cache {
+                                        cache.set_hash(path, root, hash.to_string())?;
+                                    }
+                                    Ok(hash)
+                                }
+                                _ => Err(e),
+                            }),
+                        };
+                        file_progress.finish(size, result.is_ok());
+                        if let (Some(key), Ok(hash)) = (clone, &result) {
+                            clones.lock().unwrap().insert(key, *hash);
+                        }
+                        let hash = match result {
+                            Ok(hash) => hash,
+                            Err(e) => {
+                                error!(path:% = path.display(); "Failed to calculate hash for '{}': {:#}", path.display(), e);
+                                return Err((path, e));
+                            }
+                        };
+                        // A file written to while it was read may match neither version,
+                        // so it is left out instead of being grouped by a stale hash
+                        if let Err(e) = check_unchanged(path, &metadata) {
+                            warn!("Leaving out '{}': {:#}", path.display(), e);
+                            if use_cache {
+                                cache.remove(path, root);
+                            }
+                            return Err((path, e));
+                        }
+                        if let Some(resume) = resume {
+                            resume.record(path, &metadata, &hash.to_string());
+                        }
+
+                        Ok(FileInfo {
+                            path: paths.intern(path),
+                            size,
+                            hash,
+                            file_id: file_id(&metadata),
+                            links: links(&metadata),
+                            allocated_size: allocated_size(path, &metadata),
+                            modified: metadata.modified().ok(),
+                            in_archive: false,
+                            compressed: compression.is_some(),
+                            owner: owner(&metadata),
+                        })
+                    })
+                    .for_each_with(sender, |sender, result| {
+                        // The receiver lives until all results are sent
+                        let _ = sender.send(result);
+                    });
+            });
+        });
+        for result in receiver {
+            match result {
+                Ok(file_info) => {
+                    if let Some(group) = groups.add(file_info) {
+                        progress.duplicate_found();
+                        debug!(
+                            "Found duplicates: '{}' and '{}'",
+                            group[0].path.display(),
+                            group[1].path.display()
+                        );
+                    }
                 }
+                Err((path, e)) => errors.add(path, &e.into()),
             }
+        }
+    });
+    errors.sort();
 
-            Ok(FileInfo {
-                path: path.clone(),
-                size,
-                hash,
-            })
-        })
-        .collect();
+    // Files whose cached hash was trusted despite a changed modification time are hashed
+    // again last, so an interrupted scan still has results for them
+    let suspects = if use_cache {
+        cache.take_suspects()
+    } else {
+        Vec::new()
+    };
+    if !suspects.is_empty() && running.is_none_or(|running| running.load(Ordering::Relaxed)) {
+        info!(
+            "Hashing {} files again whose modification time changed but size didn't",
+            Count(suspects.len() as u64)
+        );
+        let rehashed: HashMap<PathBuf, ContentHash> = io_pool.install(|| {
+            suspects
+                .par_iter()
+                .filter(|_| running.is_none_or(|running| running.load(Ordering::Relaxed)))
+                .filter_map(|path| {
+                    let hash = hash_contents(
+                        path,
+                        hash_pool.as_ref(),
+                        LARGE_FILE_THRESHOLD.load(Ordering::Relaxed),
+                        throttle,
+                        retry.read_timeout,
+                        None,
+                    )
+                    .and_then(|hash| cache.set_hash(path, root, hash.to_string()).map(|()| hash));
+                    match hash {
+                        Ok(hash) => Some((path.clone(), hash)),
+                        Err(e) => {
+                            warn!("Failed to hash '{}' again: {:#}", path.display(), e);
+                            None
+                        }
+                    }
+                })
+                .collect()
+        });
+        let mut changed = Vec::new();
+        groups.retain(|file| match rehashed.get(&file.path.to_path_buf()) {
+            Some(hash) if *hash != file.hash => {
+                changed.push(FileInfo {
+                    hash: *hash,
+                    ..file.clone()
+                });
+                false
+            }
+            _ => true,
+        });
+        if !changed.is_empty() {
+            warn!(
+                "{} files had changed although their size hadn't, and were regrouped",
+                Count(changed.len() as u64)
+            );
+        }
+        groups.extend(changed);
+    }
 
-    // Collect successful results
-    for result in results {
-        match result {
-            Ok(file_info) => files.push(file_info),
-            Err(e) => {
-                error!("Error processing file: {}", e);
+    progress.finish();
+
+    let stats = HashStats {
+        walk_time,
+        hash_time: hash_start.elapsed(),
+        ..progress.stats()
+    };
+    Ok((groups, errors, stats))
+}
+
+/// Scans `root.scan_path()` as [`scan_directory`] does, with the options given one by
+/// one.
+#[deprecated(since = "0.1.0", note = "use `scan_directory` with `ScanOptions`")]
+#[allow(clippy::too_many_arguments)]
+pub fn scan_directory_with_cache(
+    root: &ScanRoot,
+    cache: &dyn HashStore,
+    skip_dirs: &[String],
+    names: &NameFilter,
+    min_size: u64,
+    modified: &ModifiedRange,
+    file_types: &[FileType],
+    limit: &ScanLimit,
+    file_list: Option<&[PathBuf]>,
+    one_file_system: bool,
+    follow_junctions: bool,
+    walk_threads: Threads,
+    io_threads: Threads,
+    hash_threads: Threads,
+    throttle: &Throttle,
+    retry: &RetryPolicy,
+    no_cache: bool,
+    decompress: bool,
+    resume: Option<&ResumeState>,
+    running: &AtomicBool,
+) -> Result<(HashGroups, ScanErrors, HashStats)> {
+    let options = ScanOptions::default()
+        .with_skip_dirs(skip_dirs.to_vec())
+        .with_names(*names)
+        .with_min_size(min_size)
+        .with_modified(*modified)
+        .with_file_types(file_types.to_vec())
+        .with_limit(*limit)
+        .with_file_list(file_list)
+        .with_one_file_system(one_file_system)
+        .with_follow_junctions(follow_junctions)
+        .with_threads(walk_threads, io_threads, hash_threads)
+        .with_throttle(throttle)
+        .with_retry(*retry)
+        .with_cache(!no_cache)
+        .with_decompress(decompress)
+        .with_resume(resume)
+        .with_running(running);
+    scan_directory(root, cache, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{HashCache, MtimePolicy};
+
+    #[test]
+    fn queues_the_largest_files_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file_paths = Vec::new();
+        for (name, size) in [("a", 10), ("b", 1000), ("c", 100), ("d", 1000)] {
+            let path = dir.path().join(name);
+            fs::write(&path, vec![0; size]).unwrap();
+            file_paths.push(path);
+        }
+        file_paths.push(dir.path().join("missing"));
+        let queue = largest_first(&file_paths);
+        let order: Vec<&str> = queue
+            .iter()
+            .map(|(path, _)| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(order, ["b", "d", "c", "a", "missing"]);
+        assert!(queue[4].1.is_err());
+    }
+
+    #[test]
+    fn parses_thread_counts() {
+        assert_eq!("auto".parse().ok(), Some(Threads::Auto));
+        assert_eq!("4".parse().ok(), Some(Threads::Count(4)));
+        assert!(matches!(
+            "0".parse::<Threads>(),
+            Err(CheckFileDupsError::InvalidOptions(_))
+        ));
+        assert!("many".parse::<Threads>().is_err());
+    }
+
+    #[test]
+    fn sizes_read_buffers_by_file() {
+        assert_eq!(adaptive_read_buffer(0), MIN_READ_BUFFER);
+        assert_eq!(adaptive_read_buffer(100 * 1024), 128 * 1024);
+        // A file filling a buffer needs one more byte to see its end in the same read
+        assert_eq!(adaptive_read_buffer(128 * 1024), 256 * 1024);
+        assert_eq!(adaptive_read_buffer(1 << 40), MAX_READ_BUFFER);
+    }
+
+    #[test]
+    fn extends_windows_paths() {
+        assert_eq!(
+            extended_length_path(r"C:\Photos\a.jpg"),
+            r"\\?\C:\Photos\a.jpg"
+        );
+        assert_eq!(
+            extended_length_path("C:/Photos/a.jpg"),
+            r"\\?\C:\Photos\a.jpg"
+        );
+        assert_eq!(
+            extended_length_path(r"\\nas\share\a.jpg"),
+            r"\\?\UNC\nas\share\a.jpg"
+        );
+        assert_eq!(extended_length_path(r"\\?\C:\a"), r"\\?\C:\a");
+        assert_eq!(extended_length_path(r"Photos\a.jpg"), r"Photos\a.jpg");
+    }
+
+    #[test]
+    fn notices_files_changed_while_hashed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("growing.log");
+        fs::write(&path, b"first").unwrap();
+        let before = path.metadata().unwrap();
+        assert!(check_unchanged(&path, &before).is_ok());
+
+        fs::write(&path, b"first and more").unwrap();
+        let changed = check_unchanged(&path, &before).unwrap_err();
+        assert_eq!(ScanErrorKind::of_error(&changed), ScanErrorKind::Changed);
+        fs::remove_file(&path).unwrap();
+        let vanished = check_unchanged(&path, &before).unwrap_err();
+        assert_eq!(ScanErrorKind::of_error(&vanished), ScanErrorKind::Vanished);
+    }
+
+    #[test]
+    fn parallel_hash_matches_sequential() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large");
+        let data: Vec<u8> = (0..3_000_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, data).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        assert_eq!(
+            hash_file_parallel(&path, &pool).unwrap(),
+            hash_file(&path).unwrap()
+        );
+        // Above the threshold, without a pool of its own, on the pool it runs on
+        let large = pool.install(|| hash_contents(&path, None, 1_000_000, None, None, None));
+        assert_eq!(large.unwrap(), hash_file(&path).unwrap());
+    }
+
+    #[test]
+    fn scans_repeatedly_in_one_process() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), "same").unwrap();
+        fs::write(dir.path().join("b"), "same").unwrap();
+        let root = ScanRoot::for_base(dir.path());
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = HashCache::load(cache_dir.path().join("cache.json.zst"));
+        for threads in [2, 3] {
+            let options = ScanOptions::default().with_threads(
+                Threads::Count(threads),
+                Threads::Count(threads),
+                Threads::Count(1),
+            );
+            let (groups, errors, stats) = scan_directory(&root, &cache, options).unwrap();
+            assert_eq!((groups.len(), groups.duplicate_groups()), (2, 1));
+            assert!(errors.is_empty());
+            assert_eq!(stats.cached_files + stats.hashed_files, 2);
+        }
+    }
+
+    #[test]
+    fn hashes_suspect_cache_entries_again_last() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = ScanRoot::for_base(dir.path());
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = HashCache::load(cache_dir.path().join("cache.json.zst")).with_mtime_policy(
+            MtimePolicy {
+                tolerance: Duration::from_secs(2),
+                rehash_suspect: true,
+            },
+        );
+        let now = SystemTime::now();
+        let write = |name: &str, content: &str, age: u64| {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(now - Duration::from_secs(age))
+                .unwrap();
+        };
+        write("a", "same", 7200);
+        write("b", "same", 7200);
+        write("c", "same", 7200);
+        scan_directory(&root, &cache, ScanOptions::default()).unwrap();
+
+        // Shifted by a second: within the tolerance. Shifted by an hour, with and without
+        // changed contents: trusted, then hashed again
+        write("a", "same", 7201);
+        write("b", "same", 3600);
+        write("c", "diff", 3600);
+        let (groups, _, stats) = scan_directory(&root, &cache, ScanOptions::default()).unwrap();
+        assert_eq!(stats.cached_files, 3);
+        let c = groups
+            .files()
+            .find(|file| file.path == *dir.path().join("c"))
+            .unwrap();
+        assert_eq!(c.hash, ContentHash::of(b"diff"));
+        assert_eq!((groups.len(), groups.duplicate_groups()), (3, 1));
+        assert!(cache.take_suspects().is_empty());
+        let (_, _, stats) = scan_directory(&root, &cache, ScanOptions::default()).unwrap();
+        assert_eq!(stats.cached_files, 3);
+        assert!(cache.take_suspects().is_empty());
+    }
+
+    #[test]
+    fn skips_files_modified_outside_the_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = SystemTime::now();
+        for (name, age) in [("old", 3600), ("new", 0)] {
+            let path = dir.path().join(name);
+            fs::write(&path, name).unwrap();
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(now - Duration::from_secs(age))
+                .unwrap();
+        }
+        let walk = |modified: ModifiedRange| {
+            walk_directory(
+                &ScanRoot::for_base(dir.path()),
+                &[],
+                &NameFilter::default(),
+                0,
+                &modified,
+                &ScanLimit::default(),
+                false,
+                false,
+                None,
+                2,
+                &mut ScanErrors::default(),
+            )
+            .unwrap()
+            .0
+        };
+        let cutoff = Some(now - Duration::from_secs(60));
+        assert_eq!(
+            walk(ModifiedRange {
+                newer_than: cutoff,
+                older_than: None,
+            }),
+            vec![dir.path().join("new")]
+        );
+        assert_eq!(
+            walk(ModifiedRange {
+                newer_than: None,
+                older_than: cutoff,
+            }),
+            vec![dir.path().join("old")]
+        );
+    }
+
+    #[test]
+    fn stops_at_the_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut all = Vec::new();
+        for name in 0..10 {
+            let path = dir.path().join(name.to_string());
+            fs::write(&path, "0123456789").unwrap();
+            all.push(path);
+        }
+        let walk = |limit: ScanLimit| {
+            walk_directory(
+                &ScanRoot::for_base(dir.path()),
+                &[],
+                &NameFilter::default(),
+                0,
+                &ModifiedRange::default(),
+                &limit,
+                false,
+                false,
+                None,
+                1,
+                &mut ScanErrors::default(),
+            )
+            .unwrap()
+        };
+        let (files, total_size) = walk(ScanLimit {
+            files: Some(3),
+            ..ScanLimit::default()
+        });
+        assert_eq!((files.len(), total_size), (3, 30));
+        let (files, total_size) = walk(ScanLimit {
+            bytes: Some(25),
+            ..ScanLimit::default()
+        });
+        assert_eq!((files.len(), total_size), (2, 20));
+        let (files, total_size) = walk(ScanLimit {
+            files: Some(4),
+            bytes: None,
+            random: true,
+        });
+        assert_eq!((files.len(), total_size), (4, 40));
+        assert!(files.is_sorted() && files.iter().all(|file| all.contains(file)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walks_directories_in_parallel() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut expected = Vec::new();
+        for sub in ["a", "a/b", "a/b/c", "d", "skip"] {
+            fs::create_dir(dir.path().join(sub)).unwrap();
+            let file = dir.path().join(sub).join("file");
+            fs::write(&file, sub).unwrap();
+            if sub != "skip" {
+                expected.push(file);
             }
         }
+        std::os::unix::fs::symlink(dir.path().join("a"), dir.path().join("a/b/loop")).unwrap();
+
+        let mut errors = ScanErrors::default();
+        let (files, total_size) = walk_directory(
+            &ScanRoot::for_base(dir.path()),
+            &["skip".to_string()],
+            &NameFilter::default(),
+            0,
+            &ModifiedRange::default(),
+            &ScanLimit::default(),
+            false,
+            false,
+            None,
+            4,
+            &mut errors,
+        )
+        .unwrap();
+        expected.sort();
+        assert_eq!(files, expected);
+        assert_eq!(total_size, 10);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn skips_hidden_files_and_junk() {
+        let dir = tempfile::tempdir().unwrap();
+        for sub in [".git", "photos", "photos/.Spotlight-V100"] {
+            fs::create_dir(dir.path().join(sub)).unwrap();
+        }
+        for file in [
+            ".git/config",
+            ".hidden",
+            "photos/a.jpg",
+            "photos/.DS_Store",
+            "photos/THUMBS.DB",
+            "photos/.Spotlight-V100/store.db",
+        ] {
+            fs::write(dir.path().join(file), file).unwrap();
+        }
+        let walk = |names: NameFilter| {
+            let (files, _) = walk_directory(
+                &ScanRoot::for_base(dir.path()),
+                &[],
+                &names,
+                0,
+                &ModifiedRange::default(),
+                &ScanLimit::default(),
+                false,
+                false,
+                None,
+                2,
+                &mut ScanErrors::default(),
+            )
+            .unwrap();
+            files
+                .iter()
+                .map(|file| file.strip_prefix(dir.path()).unwrap().to_path_buf())
+                .collect::<Vec<_>>()
+        };
+        let path = PathBuf::from;
+
+        assert_eq!(
+            walk(NameFilter::default()),
+            [path(".git/config"), path(".hidden"), path("photos/a.jpg")]
+        );
+        let hidden = NameFilter {
+            skip_hidden: true,
+            ..NameFilter::default()
+        };
+        assert_eq!(walk(hidden), [path("photos/a.jpg")]);
+        let everything = NameFilter {
+            skip_hidden: false,
+            skip_junk: false,
+        };
+        assert_eq!(walk(everything).len(), 6);
+    }
+
+    #[test]
+    fn skips_directories_with_an_ignore_marker() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(IGNORE_MARKER), "").unwrap();
+        for sub in ["project", "project/build", "photos"] {
+            fs::create_dir(dir.path().join(sub)).unwrap();
+        }
+        fs::write(dir.path().join("project").join(IGNORE_MARKER), "").unwrap();
+        for file in ["project/a", "project/build/b", "photos/c"] {
+            fs::write(dir.path().join(file), file).unwrap();
+        }
+        let (files, _) = walk_directory(
+            &ScanRoot::for_base(dir.path()),
+            &[],
+            &NameFilter::default(),
+            0,
+            &ModifiedRange::default(),
+            &ScanLimit::default(),
+            false,
+            false,
+            None,
+            2,
+            &mut ScanErrors::default(),
+        )
+        .unwrap();
+        // The scanned directory itself is never excluded
+        assert_eq!(
+            files,
+            [dir.path().join(IGNORE_MARKER), dir.path().join("photos/c")]
+        );
     }
 
-    if let Some(pb) = progress_bar {
-        pb.finish_with_message("Scan complete!");
+    #[cfg(unix)]
+    #[test]
+    fn skips_special_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), "a").unwrap();
+        let fifo = std::ffi::CString::new(dir.path().join("fifo").to_str().unwrap()).unwrap();
+        // SAFETY: the path is a valid NUL-terminated string
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+        let _socket = std::os::unix::net::UnixListener::bind(dir.path().join("socket")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("missing"), dir.path().join("link")).unwrap();
+
+        let mut errors = ScanErrors::default();
+        let (files, _) = walk_directory(
+            &ScanRoot::for_base(dir.path()),
+            &[],
+            &NameFilter::default(),
+            0,
+            &ModifiedRange::default(),
+            &ScanLimit::default(),
+            false,
+            false,
+            None,
+            2,
+            &mut errors,
+        )
+        .unwrap();
+        assert_eq!(files, vec![dir.path().join("a")]);
+        assert!(errors.is_empty());
     }
 
-    Ok(files)
+    #[cfg(unix)]
+    #[test]
+    fn times_out_stalled_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fifo");
+        let fifo = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+        // SAFETY: the path is a valid NUL-terminated string
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+
+        // Opening a FIFO blocks until there is a writer, like a hanging network read
+        let result = hash_contents(
+            &path,
+            None,
+            u64::MAX,
+            None,
+            Some(Duration::from_millis(100)),
+            None,
+        );
+        assert!(result.is_err());
+        assert!(is_transient(&result.unwrap_err()));
+        // Let the reading thread exit
+        drop(fs::OpenOptions::new().write(true).open(&path).unwrap());
+
+        let missing = hash_contents(
+            &dir.path().join("missing"),
+            None,
+            u64::MAX,
+            None,
+            None,
+            None,
+        );
+        assert!(!is_transient(&missing.unwrap_err()));
+    }
 }
@@ -0,0 +1,250 @@
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use log::{info, warn};
+
+use crate::actions::Operation;
+use crate::scan_root::{DisplayPaths, display_paths, exported_path};
+use crate::units::{Bytes, Count};
+
+/// Shells supported by `--emit-script`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ScriptFormat {
+    /// POSIX shell script using rm, ln and mv
+    Sh,
+    /// PowerShell script using Remove-Item, New-Item and Move-Item
+    Powershell,
+}
+
+/// Quotes `s` as a single POSIX shell word.
+///
+/// Everything inside single quotes is literal, so the only character needing care is
+/// the single quote itself, which is written as `'\''`.
+pub fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Quotes `s` as a PowerShell single-quoted string.
+///
+/// PowerShell treats the typographic quotes U+2018 to U+201B like `'`, so those are
+/// doubled as well.
+pub fn powershell_quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('\'');
+    for c in s.chars() {
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}') {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// Makes `s` safe to place after `#` on a single comment line.
+fn comment(s: &str) -> String {
+    s.replace(['\n', '\r'], "?")
+}
+
+/// Returns the shell commands implementing `operation`, or `None` if a path is not
+/// valid UTF-8 and so cannot be written to the script.
+fn commands(operation: &Operation, format: ScriptFormat) -> Option<Vec<String>> {
+    let quote = match format {
+        ScriptFormat::Sh => sh_quote,
+        ScriptFormat::Powershell => powershell_quote,
+    };
+    let q = |path: &Path| exported_path(path).to_str().map(quote);
+    let commands = match (operation, format) {
+        (Operation::Delete { path, .. }, ScriptFormat::Sh) => vec![format!("rm -- {}", q(path)?)],
+        (Operation::Delete { path, .. }, ScriptFormat::Powershell) => {
+            vec![format!("Remove-Item -LiteralPath {}", q(path)?)]
+        }
+        (Operation::Hardlink { path, target, .. }, ScriptFormat::Sh) => {
+            vec![format!("ln -f -- {} {}", q(target)?, q(path)?)]
+        }
+        (Operation::Hardlink { path, target, .. }, ScriptFormat::Powershell) => vec![format!(
+            "New-Item -ItemType HardLink -Force -Path {} -Target {} | Out-Null",
+            q(path)?,
+            q(target)?
+        )],
+        (Operation::Reflink { path, target, .. }, ScriptFormat::Sh) => {
+            vec![format!(
+                "cp --reflink=always -- {} {}",
+                q(target)?,
+                q(path)?
+            )]
+        }
+        (Operation::Reflink { path, .. }, ScriptFormat::Powershell) => vec![format!(
+            "Write-Warning {}",
+            powershell_quote(&format!(
+                "Not reflinking {}: reflinks are not supported on Windows",
+                exported_path(path).to_str()?
+            ))
+        )],
+        (Operation::Move { from, to, .. }, ScriptFormat::Sh) => {
+            let parent = to.parent().unwrap_or(to);
+            vec![
+                format!("mkdir -p -- {}", q(parent)?),
+                format!("mv -n -- {} {}", q(from)?, q(to)?),
+            ]
+        }
+        (Operation::Move { from, to, .. }, ScriptFormat::Powershell) => {
+            let parent = to.parent().unwrap_or(to);
+            vec![
+                format!(
+                    "New-Item -ItemType Directory -Force -Path {} | Out-Null",
+                    q(parent)?
+                ),
+                format!(
+                    "Move-Item -LiteralPath {} -Destination {}",
+                    q(from)?,
+                    q(to)?
+                ),
+            ]
+        }
+    };
+    Some(commands)
+}
+
+/// Writes a script performing `operations` (as returned by
+/// [`plan_operations`](crate::actions::plan_operations)) to `writer`, so it can be
+/// reviewed and run by hand. Returns the number of operations written.
+///
+/// # Errors
+///
+/// Returns an error if writing fails. Operations on paths that are not valid UTF-8 are
+/// logged and left out of the script.
+pub fn write_script(
+    operations: &[(Operation, u64)],
+    format: ScriptFormat,
+    writer: &mut dyn Write,
+) -> Result<usize> {
+    let freed: u64 = operations.iter().map(|(_, size)| size).sum();
+    let header = format!(
+        "Generated by {} {}: {} operations, freeing {}. Review before running.",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        operations.len(),
+        Bytes(freed)
+    );
+    match format {
+        ScriptFormat::Sh => writeln!(writer, "#!/bin/sh\n# {}\nset -eu", header)?,
+        ScriptFormat::Powershell => {
+            writeln!(writer, "# {}\n$ErrorActionPreference = 'Stop'", header)?
+        }
+    }
+    // Paths relative to a directory only work from there
+    if let DisplayPaths::RelativeTo(dir) = display_paths()
+        && let Some(dir) = dir.to_str()
+    {
+        match format {
+            ScriptFormat::Sh => writeln!(writer, "cd -- {}", sh_quote(dir))?,
+            ScriptFormat::Powershell => writeln!(
+                writer,
+                "Set-Location -LiteralPath {}",
+                powershell_quote(dir)
+            )?,
+        }
+    }
+
+    let mut written = 0;
+    let mut last_hash = None;
+    for (operation, _) in operations {
+        let Some(commands) = commands(operation, format) else {
+            warn!(
+                "Skipping '{}': path is not valid UTF-8",
+                operation.path().display()
+            );
+            continue;
+        };
+        let hash = operation.hash();
+        if last_hash != Some(hash) {
+            writeln!(writer)?;
+            match operation {
+                Operation::Delete { kept, .. }
+                | Operation::Hardlink { target: kept, .. }
+                | Operation::Reflink { target: kept, .. } => writeln!(
+                    writer,
+                    "# {} (keeping {})",
+                    hash,
+                    comment(&exported_path(kept).to_string_lossy())
+                )?,
+                Operation::Move { .. } => writeln!(writer, "# {}", hash)?,
+            }
+            last_hash = Some(hash);
+        }
+        for command in commands {
+            writeln!(writer, "{}", command)?;
+        }
+        written += 1;
+    }
+    writer.flush()?;
+    info!("Wrote a script of {} operations", Count(written as u64));
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn sh_quote_escapes_single_quotes() {
+        assert_eq!(sh_quote("plain"), "'plain'");
+        assert_eq!(sh_quote("it's"), r"'it'\''s'");
+        assert_eq!(sh_quote("$HOME `x` \"y\"\n"), "'$HOME `x` \"y\"\n'");
+    }
+
+    #[test]
+    fn powershell_quote_doubles_all_single_quote_forms() {
+        assert_eq!(powershell_quote("plain"), "'plain'");
+        assert_eq!(powershell_quote("it's"), "'it''s'");
+        assert_eq!(powershell_quote("a\u{2019}b"), "'a\u{2019}\u{2019}b'");
+        assert_eq!(powershell_quote("$env:X `n"), "'$env:X `n'");
+    }
+
+    #[test]
+    fn writes_commands_grouped_by_hash() {
+        let operations = vec![
+            (
+                Operation::Delete {
+                    path: PathBuf::from("/d/it's"),
+                    kept: PathBuf::from("/d/a"),
+                    hash: "h1".into(),
+                    trashed: None,
+                    attributes: None,
+                },
+                3,
+            ),
+            (
+                Operation::Move {
+                    from: PathBuf::from("/d/b"),
+                    to: PathBuf::from("/q/b"),
+                    hash: "h2".into(),
+                },
+                4,
+            ),
+        ];
+        let mut out = Vec::new();
+        assert_eq!(
+            write_script(&operations, ScriptFormat::Sh, &mut out).unwrap(),
+            2
+        );
+        let script = String::from_utf8(out).unwrap();
+        let body: Vec<&str> = script.lines().skip(3).collect();
+        assert_eq!(
+            body,
+            [
+                "",
+                "# h1 (keeping /d/a)",
+                r"rm -- '/d/it'\''s'",
+                "",
+                "# h2",
+                "mkdir -p -- '/q'",
+                "mv -n -- '/d/b' '/q/b'",
+            ]
+        );
+    }
+}
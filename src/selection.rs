@@ -0,0 +1,680 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use globset::{GlobBuilder, GlobMatcher};
+
+use crate::FileInfo;
+use crate::duplicates::{MatchMode, linked_outside, split_group};
+use crate::streams::{StreamDigests, streams_match};
+use crate::suggest::Suggester;
+
+/// A rule for choosing which member of a duplicate group to keep.
+#[derive(Debug, Clone)]
+pub enum Rule {
+    /// Prefer files whose path, relative to the scanned directory, matches a glob pattern.
+    PreferPath(GlobMatcher),
+    /// Prefer the most recently modified file.
+    PreferNewest,
+    /// Prefer the file with the fewest path components, i.e. closest to the scan root.
+    PreferShallowest,
+    /// Prefer files whose names don't look like copies (see [`looks_like_copies`]).
+    PreferOriginalName,
+}
+
+/// Words for "copy" that file managers add to the names of copied files, in lowercase.
+const COPY_WORDS: &[&str] = &[
+    "copy",
+    "kopie",
+    "copie",
+    "copia",
+    "cópia",
+    "kopia",
+    "kopio",
+    "kópia",
+    "копия",
+];
+
+/// Copy markers added without a separator by file managers in East Asian languages.
+const CJK_COPY_SUFFIXES: &[&str] = &["のコピー", "コピー", "副本", "복사본"];
+
+/// Prefixes older versions of Windows put before the names of copied files, in lowercase.
+const COPY_PREFIXES: &[&str] = &[
+    "copy of ",
+    "kopie von ",
+    "copie de ",
+    "copia de ",
+    "copia di ",
+    "kopie van ",
+];
+
+/// Returns `s` without `suffix`, compared case-insensitively.
+fn strip_suffix_ignore_case<'a>(s: &'a str, suffix: &str) -> Option<&'a str> {
+    let i = s.len().checked_sub(suffix.len())?;
+    (s.is_char_boundary(i) && s[i..].to_lowercase() == suffix).then(|| &s[..i])
+}
+
+/// Returns `s` without `prefix`, compared case-insensitively.
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    let i = prefix.len();
+    (s.is_char_boundary(i) && s[..i].to_lowercase() == prefix).then(|| &s[i..])
+}
+
+/// If the file name `name` looks like that of a copy, returns the name of the original
+/// and whether that is certain: copy words (`file - Copy.docx`, `Copy of file.docx`,
+/// `file copy 2.docx`) and counters (`IMG_001 (1).jpg`) are, while a short number after
+/// `_`, `-` or a space (`photo_2.jpg`) may also be part of the original name.
+fn strip_copy_marker(name: &str) -> Option<(String, bool)> {
+    let (stem, extension) = match name.rfind('.') {
+        Some(i) if i > 0 => name.split_at(i),
+        _ => (name, ""),
+    };
+    for prefix in COPY_PREFIXES {
+        if let Some(rest) = strip_prefix_ignore_case(stem, prefix)
+            && !rest.is_empty()
+        {
+            return Some((format!("{}{}", rest, extension), true));
+        }
+    }
+
+    // Counters added by browsers and file managers: "name (2)"
+    let mut rest = stem;
+    let mut counted = false;
+    if let Some(inner) = stem.strip_suffix(')')
+        && let Some(open) = inner.rfind('(')
+        && open > 0
+        && inner[open + 1..].chars().all(|c| c.is_ascii_digit())
+        && inner.len() > open + 1
+    {
+        rest = inner[..open].trim_end();
+        counted = true;
+    }
+    for suffix in CJK_COPY_SUFFIXES {
+        if let Some(original) = strip_suffix_ignore_case(rest, suffix) {
+            let original = original.trim_end_matches([' ', '-', '_']);
+            if !original.is_empty() {
+                return Some((format!("{}{}", original, extension), true));
+            }
+        }
+    }
+    // macOS numbers further copies after the word: "name copy 2"
+    let unnumbered = match rest.trim_end_matches(|c: char| c.is_ascii_digit()) {
+        numbered if numbered.len() < rest.len() && numbered.ends_with(' ') => numbered.trim_end(),
+        _ => rest,
+    };
+    for word in COPY_WORDS {
+        if let Some(before) = strip_suffix_ignore_case(unnumbered, word)
+            && before.ends_with([' ', '-', '_'])
+        {
+            let original = before.trim_end_matches([' ', '-', '_']);
+            if !original.is_empty() {
+                return Some((format!("{}{}", original, extension), true));
+            }
+        }
+    }
+    if counted {
+        return Some((format!("{}{}", rest, extension), true));
+    }
+
+    // A short number after a separator, as in "photo_2"
+    let numbered = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+    if (1..=2).contains(&(stem.len() - numbered.len()))
+        && let Some(original) = numbered.strip_suffix(['_', '-', ' '])
+        && !original.is_empty()
+    {
+        return Some((format!("{}{}", original, extension), false));
+    }
+    None
+}
+
+/// Returns, for each of `names` (the file names of the members of a duplicate group),
+/// whether it looks like that of a copy made by a file manager or browser. Names with a copy word
+/// or a `(N)` counter always do; names ending in a short number, like `photo_2.jpg`,
+/// only if another member has the name without it.
+pub fn looks_like_copies(names: &[&OsStr]) -> Vec<bool> {
+    let names: Vec<String> = names
+        .iter()
+        .map(|name| name.to_string_lossy().into_owned())
+        .collect();
+    let all_names: HashSet<&str> = names.iter().map(String::as_str).collect();
+    names
+        .iter()
+        .map(|name| match strip_copy_marker(name) {
+            Some((_, true)) => true,
+            Some((original, false)) => all_names.contains(original.as_str()),
+            None => false,
+        })
+        .collect()
+}
+
+/// Parses a glob matched against paths relative to the scanned directory. `*` and `?`
+/// do not match `/`, while `**` matches any number of directories.
+pub fn path_glob(pattern: &str) -> Result<GlobMatcher, String> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map(|glob| glob.compile_matcher())
+        .map_err(|e| format!("invalid path pattern '{}': {}", pattern, e))
+}
+
+/// Returns `path` relative to `scan_path`, without any `.` or `..` components.
+fn relative(path: &Path, scan_path: &Path) -> PathBuf {
+    path.strip_prefix(scan_path)
+        .unwrap_or(path)
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect()
+}
+
+impl Rule {
+    /// Parses a `--prefer-path` glob (see [`path_glob`]).
+    pub fn prefer_path(pattern: &str) -> Result<Self, String> {
+        path_glob(pattern).map(Rule::PreferPath)
+    }
+
+    /// Compares two candidates, ordering the preferred one first.
+    fn compare(&self, a: &Candidate, b: &Candidate) -> Ordering {
+        match self {
+            Rule::PreferPath(matcher) => matcher
+                .is_match(&b.relative)
+                .cmp(&matcher.is_match(&a.relative)),
+            Rule::PreferNewest => b.modified.cmp(&a.modified),
+            Rule::PreferShallowest => a.depth.cmp(&b.depth),
+            Rule::PreferOriginalName => a.copy_name.cmp(&b.copy_name),
+        }
+    }
+}
+
+/// Parses a rule in the form used by the config file: `path:GLOB`, `newest`,
+/// `shallowest` or `original-name`.
+impl std::str::FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(pattern) = s.strip_prefix("path:") {
+            return Rule::prefer_path(pattern);
+        }
+        match s {
+            "newest" => Ok(Rule::PreferNewest),
+            "shallowest" => Ok(Rule::PreferShallowest),
+            "original-name" => Ok(Rule::PreferOriginalName),
+            _ => Err(format!(
+                "unknown rule '{}' (expected path:GLOB, newest, shallowest or original-name)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::PreferPath(matcher) => write!(f, "path:{}", matcher.glob()),
+            Rule::PreferNewest => write!(f, "newest"),
+            Rule::PreferShallowest => write!(f, "shallowest"),
+            Rule::PreferOriginalName => write!(f, "original-name"),
+        }
+    }
+}
+
+/// The attributes of a group member that rules are evaluated against.
+struct Candidate {
+    relative: PathBuf,
+    depth: usize,
+    modified: Option<SystemTime>,
+    copy_name: bool,
+}
+
+impl Candidate {
+    fn new(file: &FileInfo, scan_path: &Path, copy_name: bool) -> Self {
+        let path = file.path.to_path_buf();
+        let relative = relative(&path, scan_path);
+        Self {
+            copy_name,
+            depth: relative.components().count(),
+            relative,
+            modified: path.metadata().and_then(|m| m.modified()).ok(),
+        }
+    }
+}
+
+/// An ordered list of rules deciding which member of each duplicate group is kept.
+///
+/// Rules are evaluated in order: the first rule that prefers one file over another
+/// decides, and later rules only break ties. If every rule ties, the file with the
+/// lexicographically smallest path is kept, so repeated runs make the same choice.
+///
+/// Protected files come before any rule: one of them is always the copy kept, and
+/// actions leave the others alone too. Next come the directories set by
+/// [`KeepPolicy::with_priority_dirs`], in order, then the copies suggested by
+/// [`KeepPolicy::with_suggestions`]. Files outside the directories set by
+/// [`KeepPolicy::with_removable_within`] count as protected, like the registered
+/// originals set by [`KeepPolicy::with_originals`]. With
+/// [`KeepPolicy::with_hardlinks_counted_once`], copies linked from outside their group
+/// are kept ahead of any rule as well, since their data stays on disk anyway. With
+/// [`KeepPolicy::with_streams`], copies whose extra streams differ from the kept copy
+/// are left alone. With [`KeepPolicy::with_within_dir_only`], the copies in each
+/// directory are chosen from separately, like those of each name with
+/// [`KeepPolicy::with_match_mode`].
+#[derive(Debug, Clone, Default)]
+pub struct KeepPolicy {
+    rules: Vec<Rule>,
+    protected: Vec<GlobMatcher>,
+    originals: HashSet<PathBuf>,
+    removable_within: Vec<PathBuf>,
+    priority_dirs: Vec<PathBuf>,
+    suggester: Option<Suggester>,
+    count_hardlinks_once: bool,
+    within_dir_only: bool,
+    match_mode: MatchMode,
+    streams: Option<StreamDigests>,
+}
+
+impl KeepPolicy {
+    /// Creates a policy evaluating `rules` in order.
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Self {
+            rules,
+            protected: Vec::new(),
+            originals: HashSet::new(),
+            removable_within: Vec::new(),
+            priority_dirs: Vec::new(),
+            suggester: None,
+            count_hardlinks_once: false,
+            within_dir_only: false,
+            match_mode: MatchMode::Content,
+            streams: None,
+        }
+    }
+
+    /// Protects the files whose path relative to the scanned directory matches any of
+    /// `patterns` (see [`path_glob`]).
+    pub fn with_protected(mut self, patterns: Vec<GlobMatcher>) -> Self {
+        self.protected = patterns;
+        self
+    }
+
+    /// Protects the registered originals at `paths` (see
+    /// [`crate::originals::Originals`]).
+    pub fn with_originals(mut self, paths: HashSet<PathBuf>) -> Self {
+        self.originals = paths;
+        self
+    }
+
+    /// Keeps the copy in the first of `dirs` that any copy is in, ahead of the rules, such
+    /// as `["Originals", "Archive", "Downloads"]`. Each is matched against the
+    /// directories a file is in, relative to the scanned directory: `Originals` matches
+    /// a directory of that name at any depth, `Photos/Originals` that pair of directories.
+    pub fn with_priority_dirs(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.priority_dirs = dirs;
+        self
+    }
+
+    /// Keeps the copy `suggester` suggests, ahead of the rules but after the priority
+    /// directories, for `--accept-suggestions`.
+    pub fn with_suggestions(mut self, suggester: Option<Suggester>) -> Self {
+        self.suggester = suggester;
+        self
+    }
+
+    /// Returns the position in the priority directories of the first one `relative`, a
+    /// path relative to the scanned directory, is in, or their number if it is in none.
+    fn priority(&self, relative: &Path) -> usize {
+        let dirs: Vec<Component> = relative
+            .parent()
+            .map_or(Vec::new(), |dir| dir.components().collect());
+        self.priority_dirs
+            .iter()
+            .position(|priority| {
+                let priority: Vec<Component> = priority.components().collect();
+                !priority.is_empty() && dirs.windows(priority.len()).any(|w| w == priority)
+            })
+            .unwrap_or(self.priority_dirs.len())
+    }
+
+    /// Protects every file that isn't inside one of `dirs`, which must be absolute like
+    /// the scanned paths. An empty list leaves all files removable.
+    pub fn with_removable_within(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.removable_within = dirs;
+        self
+    }
+
+    /// Prefers keeping, and otherwise leaves alone, copies with hardlinks outside their
+    /// group (see [`linked_outside`]), since acting on them frees no space.
+    pub fn with_hardlinks_counted_once(mut self, enabled: bool) -> Self {
+        self.count_hardlinks_once = enabled;
+        self
+    }
+
+    /// Keeps a copy in each directory of a group, acting only on the copies sharing a
+    /// directory with the kept one, for `--within-dir-only`.
+    pub fn with_within_dir_only(mut self, enabled: bool) -> Self {
+        self.within_dir_only = enabled;
+        self
+    }
+
+    /// Keeps a copy of each name in a group, and each modification time too, as
+    /// `match_mode` asks, for `--match content+name` and `content+name+mtime`.
+    pub fn with_match_mode(mut self, match_mode: MatchMode) -> Self {
+        self.match_mode = match_mode;
+        self
+    }
+
+    /// Returns the indices of the members of `group` from which one copy each is kept,
+    /// see [`split_group`].
+    pub fn split(&self, group: &[FileInfo]) -> Vec<Vec<usize>> {
+        split_group(group, self.within_dir_only, self.match_mode)
+    }
+
+    /// Leaves alone the copies whose extended attributes or alternate data streams, as
+    /// read into `digests`, differ from those of the kept copy, since replacing them
+    /// would lose what is in their streams.
+    pub fn with_streams(mut self, digests: Option<StreamDigests>) -> Self {
+        self.streams = digests;
+        self
+    }
+
+    /// Returns `true` if streams are checked and `file` has other extra streams than
+    /// `kept`, or they couldn't be read.
+    pub fn streams_differ(&self, file: &FileInfo, kept: &FileInfo) -> bool {
+        self.streams
+            .as_ref()
+            .is_some_and(|digests| !streams_match(digests, file, kept))
+    }
+
+    /// Returns `true` if actions should leave `file`, a member of `group`, alone because
+    /// its data stays on disk through hardlinks outside the group.
+    pub fn keeps_linked(&self, file: &FileInfo, group: &[FileInfo]) -> bool {
+        self.count_hardlinks_once && linked_outside(file, group)
+    }
+
+    /// Returns the rules in evaluation order.
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Returns `true` if the file at `path` is protected, matching paths relative to
+    /// `scan_path`.
+    pub fn is_protected(&self, path: &Path, scan_path: &Path) -> bool {
+        if !self.removable_within.is_empty()
+            && !self
+                .removable_within
+                .iter()
+                .any(|dir| path.starts_with(dir))
+        {
+            return true;
+        }
+        if self.originals.contains(path) {
+            return true;
+        }
+        if self.protected.is_empty() {
+            return false;
+        }
+        let relative = relative(path, scan_path);
+        self.protected
+            .iter()
+            .any(|matcher| matcher.is_match(&relative))
+    }
+
+    /// Returns the index of the group member to keep.
+    ///
+    /// Paths are matched relative to `scan_path`. Returns 0 for an empty group.
+    pub fn select(&self, group: &[FileInfo], scan_path: &Path) -> usize {
+        let names: Vec<&OsStr> = group.iter().map(|file| file.path.file_name()).collect();
+        let candidates: Vec<Candidate> = group
+            .iter()
+            .zip(looks_like_copies(&names))
+            .map(|(file, copy_name)| Candidate::new(file, scan_path, copy_name))
+            .collect();
+        let protected: Vec<bool> = group
+            .iter()
+            .map(|file| {
+                self.is_protected(&file.path.to_path_buf(), scan_path)
+                    || self.keeps_linked(file, group)
+            })
+            .collect();
+        let priorities: Vec<usize> = candidates
+            .iter()
+            .map(|candidate| self.priority(&candidate.relative))
+            .collect();
+        let points: Vec<usize> = match &self.suggester {
+            Some(suggester) => suggester
+                .score(group, scan_path)
+                .into_iter()
+                .map(|(points, _)| points)
+                .collect(),
+            None => vec![0; group.len()],
+        };
+        (0..group.len())
+            .min_by(|&a, &b| {
+                protected[b]
+                    .cmp(&protected[a])
+                    .then_with(|| priorities[a].cmp(&priorities[b]))
+                    .then_with(|| points[b].cmp(&points[a]))
+                    .then_with(|| {
+                        self.rules
+                            .iter()
+                            .map(|rule| rule.compare(&candidates[a], &candidates[b]))
+                            .find(|ordering| ordering.is_ne())
+                            .unwrap_or(Ordering::Equal)
+                    })
+                    .then_with(|| group[a].path.cmp(&group[b].path))
+            })
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content_hash::ContentHash;
+    use std::fs;
+    use std::time::Duration;
+
+    fn write_file(dir: &Path, name: &str, age: Duration) -> FileInfo {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, b"same").unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+        FileInfo::for_test(path, 4, ContentHash::of(b"same"))
+    }
+
+    fn kept(policy: &KeepPolicy, group: &[FileInfo], root: &Path) -> PathBuf {
+        group[policy.select(group, root)]
+            .path
+            .to_path_buf()
+            .strip_prefix(root)
+            .unwrap()
+            .to_path_buf()
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rules: Vec<Rule> = ["path:Photos/**", " newest", "shallowest", "original-name"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let names: Vec<String> = rules.iter().map(Rule::to_string).collect();
+        assert_eq!(
+            names,
+            ["path:Photos/**", "newest", "shallowest", "original-name"]
+        );
+
+        assert!("oldest".parse::<Rule>().is_err());
+        assert!("path:[".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn path_glob_does_not_cross_separators_with_single_star() {
+        let rule = Rule::prefer_path("Photos/*.jpg").unwrap();
+        let Rule::PreferPath(matcher) = rule else {
+            unreachable!()
+        };
+        assert!(matcher.is_match("Photos/a.jpg"));
+        assert!(!matcher.is_match("Photos/2024/a.jpg"));
+    }
+
+    #[test]
+    fn without_rules_keeps_smallest_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let group = vec![
+            write_file(root, "b/file", Duration::ZERO),
+            write_file(root, "a/deep/file", Duration::ZERO),
+        ];
+        assert_eq!(
+            kept(&KeepPolicy::default(), &group, root),
+            Path::new("a/deep/file")
+        );
+    }
+
+    #[test]
+    fn first_deciding_rule_wins() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let group = vec![
+            write_file(root, "Backup/old", Duration::from_secs(3600)),
+            write_file(root, "Photos/2024/old", Duration::from_secs(7200)),
+            write_file(root, "new", Duration::ZERO),
+        ];
+
+        let newest_first = KeepPolicy::new(vec![
+            Rule::PreferNewest,
+            Rule::prefer_path("Photos/**").unwrap(),
+        ]);
+        assert_eq!(kept(&newest_first, &group, root), Path::new("new"));
+
+        let path_first = KeepPolicy::new(vec![
+            Rule::prefer_path("Photos/**").unwrap(),
+            Rule::PreferNewest,
+        ]);
+        assert_eq!(
+            kept(&path_first, &group, root),
+            Path::new("Photos/2024/old")
+        );
+    }
+
+    #[test]
+    fn later_rules_break_ties() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let group = vec![
+            write_file(root, "Photos/a/b/deep", Duration::ZERO),
+            write_file(root, "Photos/z/shallow", Duration::ZERO),
+            write_file(root, "top", Duration::ZERO),
+        ];
+
+        let policy = KeepPolicy::new(vec![
+            Rule::prefer_path("Photos/**").unwrap(),
+            Rule::PreferShallowest,
+        ]);
+        assert_eq!(kept(&policy, &group, root), Path::new("Photos/z/shallow"));
+
+        // Both Photos files tie on every rule, so the smallest path decides
+        let policy = KeepPolicy::new(vec![Rule::prefer_path("Photos/**").unwrap()]);
+        assert_eq!(kept(&policy, &group, root), Path::new("Photos/a/b/deep"));
+    }
+
+    #[test]
+    fn protected_files_are_kept_before_any_rule() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let group = vec![
+            write_file(root, "Originals/2020/old", Duration::from_secs(3600)),
+            write_file(root, "new", Duration::ZERO),
+        ];
+        let policy = KeepPolicy::new(vec![Rule::PreferNewest])
+            .with_protected(vec![path_glob("Originals/**").unwrap()]);
+        assert_eq!(kept(&policy, &group, root), Path::new("Originals/2020/old"));
+        assert!(policy.is_protected(&group[0].path.to_path_buf(), root));
+        assert!(!policy.is_protected(&group[1].path.to_path_buf(), root));
+    }
+
+    #[test]
+    fn files_outside_removable_dirs_are_references() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let group = vec![
+            write_file(root, "Archive/old", Duration::from_secs(3600)),
+            write_file(root, "Inbox/new", Duration::ZERO),
+        ];
+        let policy = KeepPolicy::new(vec![Rule::PreferNewest])
+            .with_removable_within(vec![root.join("Inbox")]);
+        assert_eq!(kept(&policy, &group, root), Path::new("Archive/old"));
+        assert!(policy.is_protected(&group[0].path.to_path_buf(), root));
+        assert!(!policy.is_protected(&group[1].path.to_path_buf(), root));
+    }
+
+    #[test]
+    fn keeps_the_copy_in_the_highest_priority_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let group = vec![
+            write_file(root, "Downloads/a", Duration::ZERO),
+            write_file(root, "2024/Archive/a", Duration::from_secs(3600)),
+            write_file(root, "Originals/x/a", Duration::from_secs(7200)),
+        ];
+        let priorities = ["Originals", "Archive", "Downloads"]
+            .map(PathBuf::from)
+            .to_vec();
+        let policy = KeepPolicy::new(vec![Rule::PreferNewest]).with_priority_dirs(priorities);
+        assert_eq!(kept(&policy, &group, root), Path::new("Originals/x/a"));
+        assert_eq!(
+            kept(&policy, &group[..2], root),
+            Path::new("2024/Archive/a")
+        );
+        // Copies in none of the directories fall back to the rules
+        let policy = policy.with_priority_dirs(vec![PathBuf::from("Elsewhere")]);
+        assert_eq!(kept(&policy, &group, root), Path::new("Downloads/a"));
+    }
+
+    #[test]
+    fn recognizes_copy_names() {
+        let copies = |names: &[&str]| {
+            let names: Vec<&OsStr> = names.iter().map(OsStr::new).collect();
+            looks_like_copies(&names)
+        };
+        assert_eq!(
+            copies(&[
+                "IMG_001 (1).jpg",
+                "file - Copy.docx",
+                "file - Copy (2).docx",
+                "Copy of file.docx",
+                "file copy 2.txt",
+                "Bericht - Kopie.pdf",
+                "写真 - コピー.jpg",
+                "IMG_001.jpg",
+                "photocopy.txt",
+                "notes (draft).txt",
+            ]),
+            [
+                true, true, true, true, true, true, true, false, false, false
+            ]
+        );
+        // A trailing number only counts when the name without it is in the group too
+        assert_eq!(copies(&["photo_2.jpg", "photo.jpg"]), [true, false]);
+        assert_eq!(copies(&["IMG_12.jpg", "DSC_12.jpg"]), [false, false]);
+    }
+
+    #[test]
+    fn prefers_original_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let group = vec![
+            write_file(root, "a/report - Copy.pdf", Duration::ZERO),
+            write_file(root, "b/report.pdf", Duration::ZERO),
+        ];
+        let policy = KeepPolicy::new(vec![Rule::PreferOriginalName]);
+        assert_eq!(kept(&policy, &group, root), Path::new("b/report.pdf"));
+    }
+
+    #[test]
+    fn empty_group_selects_zero() {
+        assert_eq!(KeepPolicy::default().select(&[], Path::new(".")), 0);
+    }
+}
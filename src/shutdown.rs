@@ -0,0 +1,126 @@
+//! Shutting down on Ctrl+C. The signal handler never saves the hash cache itself, which
+//! could race with the scan writing to it: the first interrupt of a scan asks it to
+//! stop, so the files being hashed are finished, the results so far are reported, and
+//! the cache is saved once on the main thread as usual. Any other interrupt exits at
+//! once, losing nothing, since every new hash is already in the cache journal, which is
+//! replayed on the next load.
+//!
+//! Saves hold [`hold_exit`], and exits on an interrupt wait for it, so they are never cut
+//! off halfway with the cache lock held.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use anyhow::Result;
+use log::{info, warn};
+
+/// Held while saving, see [`hold_exit`].
+static SAVING: Mutex<()> = Mutex::new(());
+
+/// Holds off exiting on Ctrl+C until the returned guard is dropped, for writing files
+/// that must not be left half written, such as the hash cache.
+pub fn hold_exit() -> MutexGuard<'static, ()> {
+    SAVING.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Exits with the status of an interrupted program, once any save in progress has
+/// finished.
+fn exit_interrupted() -> ! {
+    let _saving = hold_exit();
+    std::process::exit(130); // STATUS_CONTROL_C_EXIT
+}
+
+/// What to do on Ctrl+C during a scan, see the module documentation.
+#[derive(Clone)]
+pub struct Shutdown {
+    /// Cleared by the first interrupt of the scan, to stop hashing new files.
+    running: Arc<AtomicBool>,
+    /// Set while the scan runs, when an interrupt stops it rather than exiting.
+    scanning: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    fn new() -> Self {
+        Self {
+            running: Arc::new(AtomicBool::new(true)),
+            scanning: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Installs the Ctrl+C handler of a scan. The first interrupt while scanning stops
+    /// the scan; any other one calls `on_exit`, e.g. to save the scan progress, and exits.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a Ctrl+C handler was already installed.
+    pub fn install(on_exit: impl Fn() + Send + 'static) -> Result<Self> {
+        let shutdown = Self::new();
+        let handler = shutdown.clone();
+        ctrlc::set_handler(move || {
+            if handler.interrupt() {
+                warn!(
+                    "Received interrupt signal, finishing the files being hashed (press Ctrl+C again to exit immediately)..."
+                );
+                return;
+            }
+            info!("Received interrupt signal, exiting...");
+            on_exit();
+            exit_interrupted();
+        })?;
+        Ok(shutdown)
+    }
+
+    /// Handles an interrupt, returning `true` if it stopped the scan and `false` if the
+    /// program should exit.
+    fn interrupt(&self) -> bool {
+        self.scanning.load(Ordering::SeqCst) && self.running.swap(false, Ordering::SeqCst)
+    }
+
+    /// Flag the scan keeps hashing new files while set.
+    pub fn running(&self) -> &AtomicBool {
+        &self.running
+    }
+
+    /// Marks the scan done, after which interrupts exit at once.
+    pub fn scan_finished(&self) {
+        self.scanning.store(false, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if the scan was stopped by an interrupt.
+    pub fn interrupted(&self) -> bool {
+        !self.running.load(Ordering::SeqCst)
+    }
+}
+
+/// Installs a Ctrl+C handler that exits at once, for commands that can't stop halfway.
+///
+/// # Errors
+///
+/// Returns an error if a Ctrl+C handler was already installed.
+pub fn exit_on_interrupt() -> Result<()> {
+    ctrlc::set_handler(|| {
+        info!("Received interrupt signal, exiting...");
+        exit_interrupted();
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_first_interrupt_of_a_scan_stops_it() {
+        let shutdown = Shutdown::new();
+        assert!(!shutdown.interrupted());
+        assert!(shutdown.interrupt());
+        assert!(shutdown.interrupted());
+        assert!(!shutdown.running().load(Ordering::SeqCst));
+        assert!(!shutdown.interrupt());
+
+        let shutdown = Shutdown::new();
+        shutdown.scan_finished();
+        assert!(!shutdown.interrupt());
+        assert!(!shutdown.interrupted());
+    }
+}
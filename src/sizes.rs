@@ -0,0 +1,158 @@
+//! `--report-largest` and `--size-histogram`: basic disk usage from the files a scan
+//! already looked at, the largest files and how the files are spread over sizes, so a
+//! separate `du`-style run isn't needed to see where the space goes.
+
+use log::info;
+
+use crate::FileInfo;
+use crate::scan_root::ScanRoot;
+use crate::units::{Bytes, Count};
+
+/// Upper bounds of the histogram buckets, each 16 times the one before, with their
+/// labels. Files from the last bound up fall in one more bucket.
+const BUCKETS: [(u64, &str); 8] = [
+    (1, "0 B"),
+    (1 << 10, "< 1 KiB"),
+    (16 << 10, "< 16 KiB"),
+    (256 << 10, "< 256 KiB"),
+    (4 << 20, "< 4 MiB"),
+    (64 << 20, "< 64 MiB"),
+    (1 << 30, "< 1 GiB"),
+    (16 << 30, "< 16 GiB"),
+];
+
+/// Width of the longest histogram bar, in characters.
+const BAR_WIDTH: usize = 40;
+
+/// Files of one size range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeBucket {
+    pub label: &'static str,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// How the scanned files are spread over sizes, and the largest of them.
+#[derive(Debug, Clone)]
+pub struct SizeSummary {
+    /// Buckets from the smallest sizes up.
+    pub histogram: Vec<SizeBucket>,
+    /// The largest files, largest first, ties by path.
+    pub largest: Vec<FileInfo>,
+}
+
+/// Counts `files` by size range, and keeps the `largest` largest of them.
+pub fn summarize_sizes<'a>(
+    files: impl IntoIterator<Item = &'a FileInfo>,
+    largest: usize,
+) -> SizeSummary {
+    let mut histogram: Vec<SizeBucket> = BUCKETS
+        .iter()
+        .map(|&(_, label)| label)
+        .chain(["16 GiB or more"])
+        .map(|label| SizeBucket {
+            label,
+            files: 0,
+            bytes: 0,
+        })
+        .collect();
+    let mut all = Vec::new();
+    for file in files {
+        let bucket = BUCKETS
+            .iter()
+            .position(|&(bound, _)| file.size < bound)
+            .unwrap_or(BUCKETS.len());
+        histogram[bucket].files += 1;
+        histogram[bucket].bytes += file.size;
+        if largest > 0 {
+            all.push(file);
+        }
+    }
+    let by_size = |a: &&FileInfo, b: &&FileInfo| b.size.cmp(&a.size).then(a.path.cmp(&b.path));
+    if all.len() > largest && largest > 0 {
+        all.select_nth_unstable_by(largest - 1, by_size);
+        all.truncate(largest);
+    }
+    all.sort_unstable_by(by_size);
+    SizeSummary {
+        histogram,
+        largest: all.into_iter().cloned().collect(),
+    }
+}
+
+/// Logs the largest files, if any were kept, then with `histogram` the files and space
+/// in each size range, with a bar for the share of files.
+pub fn print_size_summary(summary: &SizeSummary, histogram: bool, root: &ScanRoot) {
+    if !summary.largest.is_empty() {
+        info!("Largest {} files:", Count(summary.largest.len() as u64));
+        for file in &summary.largest {
+            info!(
+                "  {:>10}  {}",
+                Bytes(file.size).to_string(),
+                root.display_file(&file.path).display()
+            );
+        }
+    }
+    if !histogram {
+        return;
+    }
+    let most = summary
+        .histogram
+        .iter()
+        .map(|bucket| bucket.files)
+        .max()
+        .unwrap_or(0);
+    info!("Files by size:");
+    for bucket in &summary.histogram {
+        let bar = if most == 0 {
+            0
+        } else {
+            (bucket.files as f64 / most as f64 * BAR_WIDTH as f64).ceil() as usize
+        };
+        info!(
+            "  {:>14}  {:>10} files  {:>10}  {}",
+            bucket.label,
+            Count(bucket.files).to_string(),
+            Bytes(bucket.bytes).to_string(),
+            "#".repeat(bar)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content_hash::ContentHash;
+    use std::path::Path;
+
+    #[test]
+    fn counts_files_by_size_and_keeps_the_largest() {
+        let file = |name: &str, size: u64| {
+            FileInfo::for_test(
+                Path::new("/scan").join(name).as_path(),
+                size,
+                ContentHash::of(name.as_bytes()),
+            )
+        };
+        let files = [
+            file("empty", 0),
+            file("note.txt", 900),
+            file("photo.jpg", 3 << 20),
+            file("other.jpg", 3 << 20),
+            file("movie.mkv", 20 << 30),
+        ];
+        let summary = summarize_sizes(&files, 2);
+        let counts: Vec<u64> = summary.histogram.iter().map(|b| b.files).collect();
+        assert_eq!(counts, [1, 1, 0, 0, 2, 0, 0, 0, 1]);
+        assert_eq!(summary.histogram[4].bytes, 6 << 20);
+        let largest: Vec<_> = summary
+            .largest
+            .iter()
+            .map(|f| f.path.to_path_buf())
+            .collect();
+        assert_eq!(
+            largest,
+            [Path::new("/scan/movie.mkv"), Path::new("/scan/other.jpg")]
+        );
+    }
+}
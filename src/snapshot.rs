@@ -0,0 +1,174 @@
+//! `--snapshot`: reading files that other programs keep locked, such as Outlook's PST
+//! files, from a Volume Shadow Copy on Windows. A snapshot is taken of each volume
+//! scanned before the scan, files that fail to open with a sharing violation are read
+//! from it instead, and it is deleted again when the scan is done. Taking snapshots
+//! needs an administrator, and is done with PowerShell, like attaching disk images.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+
+/// A shadow copy of a volume, deleted when dropped.
+pub struct Snapshot {
+    id: String,
+    /// The drive the snapshot is of, such as `C:\`.
+    volume: String,
+    /// The device of the snapshot, such as
+    /// `\\?\GLOBALROOT\Device\HarddiskVolumeShadowCopy3`.
+    device: String,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of the volume holding `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `path` isn't on a drive, or the snapshot can't be taken, e.g.
+    /// since this doesn't run as an administrator or on Windows.
+    pub fn create(path: &Path) -> Result<Self> {
+        let volume = volume_of(&path.to_string_lossy()).with_context(|| {
+            format!(
+                "Can't take a snapshot of {}: it isn't on a drive",
+                path.display()
+            )
+        })?;
+        let snapshot = create_snapshot(&volume)
+            .with_context(|| format!("Failed to take a snapshot of {}", volume))?;
+        info!(
+            "Took a snapshot of {} to read locked files from: {}",
+            snapshot.volume, snapshot.device
+        );
+        Ok(snapshot)
+    }
+
+    /// Returns the path of `path` in the snapshot, if it is on the snapshot's volume.
+    pub fn path_of(&self, path: &Path) -> Option<PathBuf> {
+        let path = path.to_string_lossy();
+        if volume_of(&path)? != self.volume {
+            return None;
+        }
+        snapshot_path(&self.device, &path).map(PathBuf::from)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        match delete_snapshot(self) {
+            Ok(()) => info!("Deleted the snapshot of {}", self.volume),
+            Err(e) => warn!(
+                "Failed to delete the snapshot of {} ({}): {:#}",
+                self.volume, self.id, e
+            ),
+        }
+    }
+}
+
+/// Takes a snapshot of each volume holding one of `paths`.
+///
+/// # Errors
+///
+/// Returns an error if a snapshot can't be taken. Those taken already are deleted.
+pub fn snapshots_of(paths: &[PathBuf]) -> Result<Vec<Snapshot>> {
+    let mut snapshots: Vec<Snapshot> = Vec::new();
+    for path in paths {
+        if find_in(&snapshots, path).is_none() {
+            snapshots.push(Snapshot::create(path)?);
+        }
+    }
+    Ok(snapshots)
+}
+
+/// Returns the path of the file at `path` in the one of `snapshots` of its volume.
+pub fn find_in(snapshots: &[Snapshot], path: &Path) -> Option<PathBuf> {
+    snapshots.iter().find_map(|snapshot| snapshot.path_of(path))
+}
+
+/// Strips the extended-length prefix `\\?\` of canonical paths from `path`.
+fn strip_verbatim(path: &str) -> &str {
+    path.strip_prefix(r"\\?\").unwrap_or(path)
+}
+
+/// Returns the drive of `path`, such as `C:\`, in upper case.
+fn volume_of(path: &str) -> Option<String> {
+    let path = strip_verbatim(path);
+    let letter = path.chars().next().filter(char::is_ascii_alphabetic)?;
+    path[1..]
+        .starts_with(r":\")
+        .then(|| format!(r"{}:\", letter.to_ascii_uppercase()))
+}
+
+/// Returns `path` with its drive replaced by the snapshot `device`.
+fn snapshot_path(device: &str, path: &str) -> Option<String> {
+    let rest = strip_verbatim(path).get(3..)?;
+    Some(format!(r"{}\{}", device.trim_end_matches('\\'), rest))
+}
+
+#[cfg(windows)]
+fn create_snapshot(volume: &str) -> Result<Snapshot> {
+    let script = format!(
+        "$result = Invoke-CimMethod -ClassName Win32_ShadowCopy -MethodName Create \
+         -Arguments @{{Volume='{}'; Context='ClientAccessible'}}; \
+         if ($result.ReturnValue -ne 0) {{ throw \"Win32_ShadowCopy.Create returned $($result.ReturnValue)\" }}; \
+         $copy = Get-CimInstance Win32_ShadowCopy -Filter \"ID='$($result.ShadowID)'\"; \
+         $copy.ID; $copy.DeviceObject",
+        volume
+    );
+    let output = crate::disk_image::run("powershell", &["-NoProfile", "-Command", &script])?;
+    let mut lines = output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty());
+    match (lines.next(), lines.next()) {
+        (Some(id), Some(device)) => Ok(Snapshot {
+            id: id.to_string(),
+            volume: volume.to_string(),
+            device: device.to_string(),
+        }),
+        _ => anyhow::bail!("PowerShell didn't return the snapshot: {}", output.trim()),
+    }
+}
+
+#[cfg(windows)]
+fn delete_snapshot(snapshot: &Snapshot) -> Result<()> {
+    let script = format!(
+        "Get-CimInstance Win32_ShadowCopy -Filter \"ID='{}'\" | Remove-CimInstance",
+        snapshot.id.replace('\'', "")
+    );
+    crate::disk_image::run("powershell", &["-NoProfile", "-Command", &script])?;
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn create_snapshot(_volume: &str) -> Result<Snapshot> {
+    anyhow::bail!("volume snapshots are only available on Windows")
+}
+
+#[cfg(not(windows))]
+fn delete_snapshot(_snapshot: &Snapshot) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_paths_into_the_snapshot() {
+        let snapshot = Snapshot {
+            id: "{00000000-0000-0000-0000-000000000000}".to_string(),
+            volume: r"C:\".to_string(),
+            device: r"\\?\GLOBALROOT\Device\HarddiskVolumeShadowCopy3".to_string(),
+        };
+        assert_eq!(
+            snapshot.path_of(Path::new(r"\\?\c:\Users\me\Outlook.pst")),
+            Some(PathBuf::from(
+                r"\\?\GLOBALROOT\Device\HarddiskVolumeShadowCopy3\Users\me\Outlook.pst"
+            ))
+        );
+        assert_eq!(snapshot.path_of(Path::new(r"D:\Photos\a.jpg")), None);
+        assert_eq!(volume_of("/home/me"), None);
+        // Nothing to delete, since the snapshot wasn't taken
+        std::mem::forget(snapshot);
+    }
+}
@@ -0,0 +1,275 @@
+//! `--memory-budget`: finding duplicate groups in bounded memory. Files are buffered up
+//! to the budget, then sorted by hash and written to a temporary run file, and the runs
+//! are merged in hash order at the end, so that only the files of duplicate groups are
+//! held at once rather than a map of every file scanned. Slower than grouping in memory,
+//! for machines where tens of millions of files don't fit.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use log::{debug, info};
+
+use crate::FileInfo;
+use crate::binary::{BinaryReader, put_str, put_u32, put_u64};
+use crate::content_hash::ContentHash;
+use crate::paths::{escape_path, unescape_path};
+use crate::units::{Bytes, Count};
+
+/// Memory a buffered file takes besides its path, an estimate good enough to size runs.
+const FILE_OVERHEAD: u64 = std::mem::size_of::<FileInfo>() as u64 + 32;
+
+/// Temporary run files, removed when dropped.
+struct RunFiles {
+    dir: PathBuf,
+    runs: Vec<PathBuf>,
+}
+
+impl RunFiles {
+    fn new(temp_dir: &Path) -> Self {
+        Self {
+            dir: temp_dir.join(format!(
+                "{}-spill-{}",
+                env!("CARGO_PKG_NAME"),
+                std::process::id()
+            )),
+            runs: Vec::new(),
+        }
+    }
+
+    /// Sorts `files` by hash and writes them to a new run file.
+    fn write(&mut self, files: &mut Vec<FileInfo>) -> Result<()> {
+        files.sort_unstable_by_key(|file| file.hash);
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("run-{}", self.runs.len()));
+        let mut out = BufWriter::new(
+            fs::File::create(&path)
+                .with_context(|| format!("Failed to create {}", path.display()))?,
+        );
+        let mut record = Vec::new();
+        for file in files.drain(..) {
+            record.clear();
+            encode(&file, &mut record);
+            out.write_all(&(record.len() as u32).to_le_bytes())?;
+            out.write_all(&record)?;
+        }
+        out.flush()?;
+        debug!("Spilled files to {}", path.display());
+        self.runs.push(path);
+        Ok(())
+    }
+}
+
+impl Drop for RunFiles {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Keeps the groups of `hash_groups` that have more than one file, like
+/// [`crate::find_duplicates`], but spills files to run files in `temp_dir` whenever more
+/// than `budget` bytes of them are buffered. Each group of `hash_groups` is freed once
+/// buffered, so memory is bounded by the groups not yet looked at plus the budget.
+///
+/// # Errors
+///
+/// Returns an error if a run file cannot be written or read back.
+pub fn find_duplicates_spilling(
+    hash_groups: HashMap<ContentHash, Vec<FileInfo>>,
+    budget: u64,
+    temp_dir: &Path,
+) -> Result<HashMap<ContentHash, Vec<FileInfo>>> {
+    info!(
+        "Finding duplicates within a memory budget of {}...",
+        Bytes(budget)
+    );
+    let mut runs = RunFiles::new(temp_dir);
+    let mut buffer = Vec::new();
+    let mut buffered = 0;
+    for file in hash_groups.into_values().flatten() {
+        buffered += FILE_OVERHEAD + file.path.to_path_buf().as_os_str().len() as u64;
+        buffer.push(file);
+        if buffered >= budget {
+            runs.write(&mut buffer)?;
+            buffered = 0;
+        }
+    }
+    if runs.runs.is_empty() {
+        let mut duplicates: HashMap<ContentHash, Vec<FileInfo>> = HashMap::new();
+        for file in buffer {
+            duplicates.entry(file.hash).or_default().push(file);
+        }
+        duplicates.retain(|_, group| group.len() > 1);
+        return Ok(duplicates);
+    }
+    if !buffer.is_empty() {
+        runs.write(&mut buffer)?;
+    }
+    drop(buffer);
+    info!(
+        "Merging {} spilled runs of files",
+        Count(runs.runs.len() as u64)
+    );
+    merge(&runs.runs)
+}
+
+/// Merges run files sorted by hash, keeping the groups with more than one file.
+fn merge(runs: &[PathBuf]) -> Result<HashMap<ContentHash, Vec<FileInfo>>> {
+    let mut readers = runs
+        .iter()
+        .map(|path| Ok(BufReader::new(fs::File::open(path)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut heads = BinaryHeap::new();
+    let mut pending: Vec<Option<FileInfo>> = Vec::with_capacity(readers.len());
+    for (i, reader) in readers.iter_mut().enumerate() {
+        let file = read_record(reader)?;
+        if let Some(file) = &file {
+            heads.push(Reverse((file.hash, i)));
+        }
+        pending.push(file);
+    }
+
+    let mut duplicates = HashMap::new();
+    let mut group: Vec<FileInfo> = Vec::new();
+    while let Some(Reverse((_, i))) = heads.pop() {
+        let Some(file) = pending[i].take() else {
+            continue;
+        };
+        if group.first().is_some_and(|first| first.hash != file.hash) {
+            keep_group(&mut duplicates, std::mem::take(&mut group));
+        }
+        group.push(file);
+        pending[i] = read_record(&mut readers[i])?;
+        if let Some(next) = &pending[i] {
+            heads.push(Reverse((next.hash, i)));
+        }
+    }
+    keep_group(&mut duplicates, group);
+    Ok(duplicates)
+}
+
+fn keep_group(duplicates: &mut HashMap<ContentHash, Vec<FileInfo>>, group: Vec<FileInfo>) {
+    if group.len() > 1 {
+        duplicates.insert(group[0].hash, group);
+    }
+}
+
+/// Reads the next file from a run, or `None` at its end.
+fn read_record(reader: &mut impl Read) -> Result<Option<FileInfo>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let mut record = vec![0; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut record)?;
+    decode(&record).map(Some)
+}
+
+fn put_option(buffer: &mut Vec<u8>, value: Option<u64>) {
+    buffer.push(u8::from(value.is_some()));
+    put_u64(buffer, value.unwrap_or_default());
+}
+
+fn encode(file: &FileInfo, buffer: &mut Vec<u8>) {
+    buffer.extend_from_slice(file.hash.as_bytes());
+    put_u64(buffer, file.size);
+    put_str(buffer, &escape_path(&file.path.to_path_buf()));
+    let (device, inode) = file.file_id.unzip();
+    put_option(buffer, device);
+    put_option(buffer, inode);
+    put_option(buffer, file.links);
+    put_option(buffer, file.allocated_size);
+    let modified = file
+        .modified
+        .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok());
+    put_option(buffer, modified.map(|since| since.as_secs()));
+    put_option(buffer, file.owner.map(u64::from));
+    put_u32(buffer, modified.map_or(0, |since| since.subsec_nanos()));
+    buffer.push(u8::from(file.in_archive) | u8::from(file.compressed) << 1);
+}
+
+fn decode(record: &[u8]) -> Result<FileInfo> {
+    let mut reader = BinaryReader::new(record, "spilled file record");
+    let hash: [u8; 32] = reader.bytes(32)?.try_into()?;
+    let size = reader.u64()?;
+    let path = unescape_path(&reader.string()?);
+    let mut option = || -> Result<Option<u64>> {
+        let present = reader.u8()? != 0;
+        let value = reader.u64()?;
+        Ok(present.then_some(value))
+    };
+    let file_id = option()?.zip(option()?);
+    let links = option()?;
+    let allocated_size = option()?;
+    let modified_secs = option()?;
+    let owner = option()?.map(|owner| owner as u32);
+    let nanos = reader.u32()?;
+    let flags = reader.u8()?;
+    Ok(FileInfo {
+        path: path.as_path().into(),
+        size,
+        hash: blake3::Hash::from_bytes(hash).into(),
+        file_id,
+        links,
+        allocated_size,
+        modified: modified_secs.map(|secs| SystemTime::UNIX_EPOCH + Duration::new(secs, nanos)),
+        in_archive: flags & 1 != 0,
+        compressed: flags & 2 != 0,
+        owner,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::group_by_hash;
+
+    #[test]
+    fn finds_the_same_groups_when_spilling() {
+        let modified = Some(SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 5));
+        let file = |name: &str, content: &[u8]| FileInfo {
+            file_id: Some((1, name.len() as u64)),
+            links: Some(1),
+            modified,
+            compressed: name.ends_with(".gz"),
+            owner: Some(1000),
+            ..FileInfo::with_contents(Path::new("/scan").join(name), content)
+        };
+        let files = vec![
+            file("a", b"one"),
+            file("b", b"two"),
+            file("c", b"one"),
+            file("d.gz", b"two"),
+            file("e", b"three"),
+            file("f", b"one"),
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        // A budget this small spills every file to a run of its own
+        let duplicates =
+            find_duplicates_spilling(group_by_hash(files.clone()), 1, dir.path()).unwrap();
+        let in_memory =
+            find_duplicates_spilling(group_by_hash(files), 1 << 30, dir.path()).unwrap();
+        assert_eq!(duplicates.len(), 2);
+        for (hash, group) in &in_memory {
+            let mut paths: Vec<_> = group.iter().map(|file| file.path.to_path_buf()).collect();
+            let mut spilled: Vec<_> = duplicates[hash]
+                .iter()
+                .map(|file| file.path.to_path_buf())
+                .collect();
+            paths.sort();
+            spilled.sort();
+            assert_eq!(paths, spilled);
+        }
+        let d = &duplicates[&ContentHash::of(b"two")];
+        let d = d.iter().find(|file| file.compressed).unwrap();
+        assert_eq!((d.file_id, d.owner), (Some((1, 4)), Some(1000)));
+        assert_eq!(d.modified, modified);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
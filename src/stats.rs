@@ -0,0 +1,122 @@
+//! Timings and throughput of a scan, printed with `--stats` to track performance across
+//! versions on real datasets.
+
+use std::time::Duration;
+
+use clap::ValueEnum;
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::progress::HashStats;
+use crate::units::{Bytes, Count};
+
+/// Formats supported by `--stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsFormat {
+    /// Log lines at the end of the run
+    #[default]
+    Text,
+    /// One JSON object on standard error, for scripts collecting results over time
+    Json,
+}
+
+/// How long each phase of a scan took, and how much it read.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RunStats {
+    /// Loading the hash cache.
+    pub cache_load: Duration,
+    /// Hashes, and the time taken to list and hash the files.
+    pub hashes: HashStats,
+    /// Grouping the files by contents and finding the duplicates.
+    pub group: Duration,
+    /// Writing the results.
+    pub report: Duration,
+    /// Saving the hash cache.
+    pub cache_save: Duration,
+}
+
+impl RunStats {
+    /// Returns the files done per second of hashing, including those from the cache.
+    pub fn files_per_sec(&self) -> f64 {
+        (self.hashes.cached_files + self.hashes.hashed_files) as f64
+            / self.hashes.hash_time.as_secs_f64().max(1e-6)
+    }
+
+    /// Returns the bytes read per second of hashing.
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.hashes.hashed_bytes as f64 / self.hashes.hash_time.as_secs_f64().max(1e-6)
+    }
+
+    /// Returns the stats as JSON, with times in seconds.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "phases": {
+                "cache_load": self.cache_load.as_secs_f64(),
+                "walk": self.hashes.walk_time.as_secs_f64(),
+                "hash": self.hashes.hash_time.as_secs_f64(),
+                "group": self.group.as_secs_f64(),
+                "report": self.report.as_secs_f64(),
+                "cache_save": self.cache_save.as_secs_f64(),
+            },
+            "files": self.hashes.cached_files + self.hashes.hashed_files,
+            "cached_files": self.hashes.cached_files,
+            "bytes_read": self.hashes.hashed_bytes,
+            "files_per_sec": self.files_per_sec(),
+            "bytes_per_sec": self.bytes_per_sec(),
+        })
+    }
+}
+
+/// Prints `stats` in `format`.
+pub fn print_stats(stats: &RunStats, format: StatsFormat) {
+    match format {
+        StatsFormat::Json => eprintln!("{}", stats.to_json()),
+        StatsFormat::Text => {
+            info!(
+                "Phase timings: cache load {:.2}s, walk {:.2}s, hash {:.2}s, group {:.2}s, report {:.2}s, cache save {:.2}s",
+                stats.cache_load.as_secs_f64(),
+                stats.hashes.walk_time.as_secs_f64(),
+                stats.hashes.hash_time.as_secs_f64(),
+                stats.group.as_secs_f64(),
+                stats.report.as_secs_f64(),
+                stats.cache_save.as_secs_f64()
+            );
+            info!(
+                "Read {} while hashing: {}/s, {} files/s",
+                Bytes(stats.hashes.hashed_bytes),
+                Bytes(stats.bytes_per_sec() as u64),
+                Count(stats.files_per_sec() as u64)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_throughput_of_the_hash_phase() {
+        let stats = RunStats {
+            hashes: HashStats {
+                cached_files: 30,
+                cached_bytes: 3000,
+                hashed_files: 10,
+                hashed_bytes: 1000,
+                walk_time: Duration::from_millis(100),
+                hash_time: Duration::from_secs(2),
+            },
+            group: Duration::from_millis(5),
+            ..RunStats::default()
+        };
+        assert_eq!(stats.files_per_sec(), 20.0);
+        assert_eq!(stats.bytes_per_sec(), 500.0);
+        let json = stats.to_json();
+        assert_eq!(json["phases"]["walk"], 0.1);
+        assert_eq!(json["phases"]["group"], 0.005);
+        assert_eq!(json["bytes_read"], 1000);
+    }
+}
@@ -0,0 +1,219 @@
+//! `archive`: a content-addressed store of the scanned files, as a poor man's
+//! deduplicating backup. Each distinct content is stored once, named by its hash under
+//! `objects/` (e.g. `objects/3f/3f9a...`), copied or, with `--link`, hardlinked. A
+//! manifest in the inventory format maps each archived path to its hash, and so to its
+//! store entry, to put the tree back together. Contents stored by earlier runs are
+//! reused, so archiving a tree again only adds what changed.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+
+use crate::FileInfo;
+use crate::content_hash::ContentHash;
+use crate::inventory::{InventoryEntry, InventoryFormat, write_inventory};
+use crate::scan_root::ScanRoot;
+use crate::scanner::hash_file;
+use crate::units::{Bytes, Count};
+
+/// What archiving a tree did.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ArchiveStats {
+    /// Files in the manifest.
+    pub files: usize,
+    /// Contents added to the store.
+    pub stored: usize,
+    /// Size of the contents added, in bytes.
+    pub stored_bytes: u64,
+    /// Contents added as hardlinks rather than copies.
+    pub linked: usize,
+    /// Contents already in the store, from other copies or earlier runs.
+    pub reused: usize,
+}
+
+/// A directory of contents named by their hashes.
+pub struct ContentStore {
+    dir: PathBuf,
+}
+
+impl ContentStore {
+    /// Opens the store in `dir`, which is created when something is stored.
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// Returns the path of the store entry for contents with `hash`, fanned out over
+    /// subdirectories by its first two hex digits.
+    pub fn object_path(&self, hash: &ContentHash) -> PathBuf {
+        let hex = hash.to_string();
+        self.dir.join("objects").join(&hex[..2]).join(hex)
+    }
+
+    /// Adds the contents of `file` to the store, unless they are there already. With
+    /// `link`, the entry is a hardlink of the file where possible, which takes no space
+    /// but changes along with the file. Copies are checked against the file's hash, and
+    /// renamed into place once complete, so an interrupted run leaves no partial entry.
+    /// Returns whether the contents were added, and whether by linking.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be copied, or its copy doesn't match its hash,
+    /// e.g. since it changed after being scanned.
+    pub fn add(&self, file: &FileInfo, link: bool) -> Result<Option<bool>> {
+        let object = self.object_path(&file.hash);
+        if object.exists() {
+            return Ok(None);
+        }
+        let path = file.path.to_path_buf();
+        let dir = object.parent().unwrap_or(&self.dir);
+        fs::create_dir_all(dir)?;
+        if link {
+            match fs::hard_link(&path, &object) {
+                Ok(()) => return Ok(Some(true)),
+                Err(e) => debug!("Copying {} instead of linking it: {}", path.display(), e),
+            }
+        }
+        let mut tmp = object.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        fs::copy(&path, &tmp)
+            .with_context(|| format!("Failed to copy {} to the store", path.display()))?;
+        let hash = hash_file(&tmp)?;
+        if hash != file.hash {
+            let _ = fs::remove_file(&tmp);
+            anyhow::bail!(
+                "{} changed since it was scanned, so it wasn't archived",
+                path.display()
+            );
+        }
+        fs::rename(&tmp, &object)?;
+        Ok(Some(false))
+    }
+
+    /// Returns the path for the manifest of archiving `root` at `time`, under
+    /// `manifests/` and named after the scanned directory and the time.
+    pub fn manifest_path(&self, root: &ScanRoot, time: SystemTime) -> PathBuf {
+        let name = root
+            .scan_path()
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "root".to_string());
+        let secs = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        self.dir
+            .join("manifests")
+            .join(format!("{}-{}.csv", name, secs))
+    }
+}
+
+/// Adds the contents of `files`, scanned under `root`, to `store`, and writes a
+/// manifest of their paths and hashes to `manifest`. Files inside archives and
+/// compressed files matched by their decompressed contents aren't archived. Files that
+/// fail to archive are skipped with a warning and left out of the manifest.
+///
+/// # Errors
+///
+/// Returns an error if the manifest cannot be written.
+pub fn archive<'a>(
+    files: impl IntoIterator<Item = &'a FileInfo>,
+    root: &ScanRoot,
+    store: &ContentStore,
+    manifest: &Path,
+    link: bool,
+) -> Result<ArchiveStats> {
+    let mut files: Vec<&FileInfo> = files
+        .into_iter()
+        .filter(|file| !file.in_archive && !file.compressed)
+        .collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut stats = ArchiveStats::default();
+    let mut entries = Vec::with_capacity(files.len());
+    for file in files {
+        match store.add(file, link) {
+            Ok(Some(linked)) => {
+                stats.stored += 1;
+                stats.stored_bytes += file.size;
+                stats.linked += usize::from(linked);
+            }
+            Ok(None) => stats.reused += 1,
+            Err(e) => {
+                warn!("{:#}", e);
+                continue;
+            }
+        }
+        entries.push(InventoryEntry::new(file, root));
+    }
+    stats.files = entries.len();
+    if let Some(dir) = manifest.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let out = fs::File::create(manifest)
+        .with_context(|| format!("Failed to create manifest {}", manifest.display()))?;
+    write_inventory(
+        &entries,
+        InventoryFormat::from_path(manifest),
+        &mut std::io::BufWriter::new(out),
+    )?;
+    Ok(stats)
+}
+
+/// Logs what archiving did, and where the manifest is.
+pub fn print_archive_stats(stats: &ArchiveStats, manifest: &Path) {
+    info!(
+        "Archived {} files: stored {} new contents ({}, {} as hardlinks), reused {} already stored",
+        Count(stats.files as u64),
+        Count(stats.stored as u64),
+        Bytes(stats.stored_bytes),
+        Count(stats.linked as u64),
+        Count(stats.reused as u64)
+    );
+    info!("Wrote the manifest to {}", manifest.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::read_inventory;
+
+    #[test]
+    fn stores_each_content_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let tree = dir.path().join("tree");
+        fs::create_dir_all(tree.join("sub")).unwrap();
+        let root = ScanRoot::for_base(&tree);
+        let file = |name: &str, content: &[u8]| {
+            let path = root.scan_path().join(name);
+            fs::write(&path, content).unwrap();
+            FileInfo::with_contents(path, content)
+        };
+        let files = [
+            file("a", b"same"),
+            file("sub/b", b"same"),
+            file("c", b"other"),
+        ];
+        let store = ContentStore::new(&dir.path().join("store"));
+        let manifest = dir.path().join("manifest.csv");
+        let stats = archive(&files, &root, &store, &manifest, false).unwrap();
+        assert_eq!((stats.files, stats.stored, stats.reused), (3, 2, 1));
+        assert_eq!(
+            fs::read(store.object_path(&ContentHash::of(b"same"))).unwrap(),
+            b"same"
+        );
+        let entries = read_inventory(&manifest).unwrap();
+        assert_eq!(entries[2].path, Path::new("sub").join("b"));
+        assert_eq!(entries[2].hash, ContentHash::of(b"same").to_string());
+
+        // Archiving again reuses what's stored, and changed files are skipped
+        let changed = file("e", b"scanned");
+        fs::write(root.scan_path().join("e"), "changed").unwrap();
+        let files = [files[0].clone(), file("d", b"new"), changed];
+        let stats = archive(&files, &root, &store, &manifest, false).unwrap();
+        assert_eq!((stats.files, stats.stored, stats.reused), (2, 1, 1));
+    }
+}
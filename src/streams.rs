@@ -0,0 +1,216 @@
+//! `--check-streams`: content hidden next to a file's data, in extended attributes on
+//! Linux and macOS (where resource forks live) or alternate data streams on Windows.
+//! Two files with the same data can still differ there, so duplicates whose streams
+//! differ from the kept copy are pointed out and never deleted or replaced.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use rayon::prelude::*;
+
+use crate::FileInfo;
+use crate::content_hash::ContentHash;
+use crate::units::Count;
+
+/// Streams recording where a file came from or when it was last opened, rather than
+/// anything in it, which copies differ in all the time.
+const IGNORED_STREAMS: [&str; 4] = [
+    "com.apple.quarantine",
+    "com.apple.lastuseddate#PS",
+    "security.selinux",
+    "Zone.Identifier",
+];
+
+/// The digest of the extra streams of each file, by path: `None` for a file without any.
+/// Files whose streams couldn't be read are missing, and match no other file.
+pub type StreamDigests = HashMap<PathBuf, Option<ContentHash>>;
+
+/// Reads the extended attributes or alternate data streams of the file at `path`, by
+/// name, leaving out [`IGNORED_STREAMS`].
+///
+/// # Errors
+///
+/// Returns an error if the streams can't be listed or read.
+pub fn read_streams(path: &Path) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    let mut streams = platform_streams(path)?;
+    streams.retain(|name, _| !IGNORED_STREAMS.contains(&name.as_str()));
+    Ok(streams)
+}
+
+/// Returns a digest of the extra streams of the file at `path`, or `None` if it has none.
+///
+/// # Errors
+///
+/// Returns an error if the streams can't be read.
+pub fn streams_digest(path: &Path) -> io::Result<Option<ContentHash>> {
+    let streams = read_streams(path)?;
+    if streams.is_empty() {
+        return Ok(None);
+    }
+    let mut hasher = blake3::Hasher::new();
+    for (name, value) in &streams {
+        // Lengths first, so different splits into names and values can't collide
+        hasher.update(&(name.len() as u64).to_le_bytes());
+        hasher.update(name.as_bytes());
+        hasher.update(&(value.len() as u64).to_le_bytes());
+        hasher.update(value);
+    }
+    Ok(Some(hasher.finalize().into()))
+}
+
+/// Reads the extra streams of every member of `duplicates` on disk, and logs how many
+/// groups have members whose streams differ.
+pub fn read_stream_digests(duplicates: &HashMap<ContentHash, Vec<FileInfo>>) -> StreamDigests {
+    let paths: Vec<PathBuf> = duplicates
+        .values()
+        .flatten()
+        .filter(|file| !file.in_archive)
+        .map(|file| file.path.to_path_buf())
+        .collect();
+    let digests: StreamDigests = paths
+        .into_par_iter()
+        .filter_map(|path| match streams_digest(&path) {
+            Ok(digest) => Some((path, digest)),
+            Err(e) => {
+                warn!(
+                    "Failed to read the extended attributes of '{}': {}",
+                    path.display(),
+                    e
+                );
+                None
+            }
+        })
+        .collect();
+    let mixed = duplicates
+        .values()
+        .filter(|group| {
+            let mut on_disk = group.iter().filter(|file| !file.in_archive);
+            on_disk
+                .next()
+                .is_some_and(|first| on_disk.any(|file| !streams_match(&digests, first, file)))
+        })
+        .count();
+    if mixed > 0 {
+        info!(
+            "{} duplicate groups have copies whose extended attributes or alternate data streams differ, which are left alone",
+            Count(mixed as u64)
+        );
+    }
+    digests
+}
+
+/// Returns `true` if `a` and `b` are known to have the same extra streams.
+pub fn streams_match(digests: &StreamDigests, a: &FileInfo, b: &FileInfo) -> bool {
+    match (
+        digests.get(&a.path.to_path_buf()),
+        digests.get(&b.path.to_path_buf()),
+    ) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(not(windows))]
+fn platform_streams(path: &Path) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    crate::attributes::xattrs::read_raw(path)
+}
+
+#[cfg(windows)]
+fn platform_streams(path: &Path) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Foundation::{ERROR_HANDLE_EOF, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::Storage::FileSystem::{
+        FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard,
+        WIN32_FIND_STREAM_DATA,
+    };
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut data = WIN32_FIND_STREAM_DATA::default();
+    // SAFETY: `wide` is NUL-terminated and `data` is a valid out pointer
+    let handle = unsafe {
+        FindFirstStreamW(
+            wide.as_ptr(),
+            FindStreamInfoStandard,
+            (&raw mut data).cast(),
+            0,
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        let error = io::Error::last_os_error();
+        return match error.raw_os_error() {
+            // No streams at all, as for files on FAT volumes
+            Some(code) if code == ERROR_HANDLE_EOF as i32 => Ok(BTreeMap::new()),
+            _ => Err(error),
+        };
+    }
+    let mut names = Vec::new();
+    loop {
+        let length = data
+            .cStreamName
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(data.cStreamName.len());
+        names.push(String::from_utf16_lossy(&data.cStreamName[..length]));
+        // SAFETY: `handle` is an open stream search and `data` a valid out pointer
+        if unsafe { FindNextStreamW(handle, (&raw mut data).cast()) } == 0 {
+            break;
+        }
+    }
+    // SAFETY: `handle` is an open stream search, closed once
+    unsafe { FindClose(handle) };
+
+    let mut streams = BTreeMap::new();
+    // Names have the form `:name:$DATA`, and `::$DATA` is the file's own data
+    for name in names {
+        let name = name.strip_suffix(":$DATA").unwrap_or(&name);
+        if name == ":" {
+            continue;
+        }
+        let mut stream_path = path.as_os_str().to_owned();
+        stream_path.push(name);
+        let value = std::fs::read(&stream_path)?;
+        streams.insert(name.trim_start_matches(':').to_string(), value);
+    }
+    Ok(streams)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn tells_copies_with_other_streams_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str, xattrs: &[(&str, &str)]| {
+            let path = dir.path().join(name);
+            fs::write(&path, b"same").unwrap();
+            let xattrs = xattrs
+                .iter()
+                .map(|(name, hex)| (name.to_string(), hex.to_string()))
+                .collect();
+            crate::attributes::xattrs::write(&path, &xattrs).unwrap();
+            FileInfo::for_test(path, 4, ContentHash::of(b"same"))
+        };
+        let plain = file("plain", &[]);
+        let tagged = file("tagged", &[("user.tag", "01")]);
+        let also_tagged = file("also_tagged", &[("user.tag", "01")]);
+        let retagged = file("retagged", &[("user.tag", "02")]);
+        let downloaded = file("downloaded", &[("com.apple.quarantine", "01")]);
+        if read_streams(&tagged.path.to_path_buf()).unwrap().is_empty() {
+            // Extended attributes aren't supported here
+            return;
+        }
+
+        let group = vec![plain, tagged, also_tagged, retagged, downloaded];
+        let duplicates = HashMap::from([(ContentHash::of(b"same"), group.clone())]);
+        let digests = read_stream_digests(&duplicates);
+        assert_eq!(digests[&group[0].path.to_path_buf()], None);
+        assert!(streams_match(&digests, &group[1], &group[2]));
+        assert!(!streams_match(&digests, &group[1], &group[3]));
+        assert!(!streams_match(&digests, &group[0], &group[1]));
+        assert!(streams_match(&digests, &group[0], &group[4]));
+    }
+}
@@ -0,0 +1,267 @@
+//! `--suggest` and `--accept-suggestions`: proposing which copy of each duplicate group
+//! to keep from a few signals, and saying why. Each signal that tells the copies apart
+//! gives a point to the copies it favours: the copies at the shallowest depth, those not
+//! in a directory named like a backup or a copy, and the ones modified first, which are
+//! likely the originals. The copy with the most points is suggested.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Component, Path};
+use std::time::SystemTime;
+
+use log::info;
+
+use crate::FileInfo;
+use crate::content_hash::ContentHash;
+use crate::scan_root::ScanRoot;
+use crate::units::Count;
+
+/// Words in the names of directories holding backups or copies, in lowercase.
+const BACKUP_DIR_WORDS: &[&str] = &["backup", "copy", "copies", "bak", "old"];
+
+/// A signal telling which copy of a group is likely the one to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// Favours the copies closest to the scanned directory.
+    Shallower,
+    /// Favours the copies not in a directory named like a backup or a copy.
+    NotBackup,
+    /// Favours the copies modified first.
+    Older,
+}
+
+impl Signal {
+    /// Every signal, the default set.
+    pub const ALL: [Signal; 3] = [Signal::Shallower, Signal::NotBackup, Signal::Older];
+}
+
+/// Parses a signal in the form used by the config file: `shallower`, `not-backup` or
+/// `older`.
+impl std::str::FromStr for Signal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "shallower" => Ok(Signal::Shallower),
+            "not-backup" => Ok(Signal::NotBackup),
+            "older" => Ok(Signal::Older),
+            s => Err(format!(
+                "unknown suggestion signal '{}' (expected shallower, not-backup or older)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Signal::Shallower => "shallower",
+            Signal::NotBackup => "not-backup",
+            Signal::Older => "older",
+        })
+    }
+}
+
+/// The copy suggested to keep, with the reasons for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    /// Index of the copy in the group.
+    pub index: usize,
+    /// Why, one reason per signal favouring the copy.
+    pub reasons: Vec<String>,
+}
+
+/// Returns `true` if a directory `relative` is in, relative to the scanned directory, is
+/// named like a backup or a copy, such as `Backup 2019` or `old`.
+fn in_backup_dir(relative: &Path) -> bool {
+    relative.parent().is_some_and(|dir| {
+        dir.components().any(|component| {
+            let Component::Normal(name) = component else {
+                return false;
+            };
+            let name = name.to_string_lossy().to_lowercase();
+            name.split(|c: char| !c.is_alphanumeric())
+                .any(|word| BACKUP_DIR_WORDS.contains(&word))
+        })
+    })
+}
+
+/// Suggests the copy of each group to keep from a set of signals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggester {
+    signals: Vec<Signal>,
+}
+
+impl Default for Suggester {
+    fn default() -> Self {
+        Self::new(Signal::ALL.to_vec())
+    }
+}
+
+impl Suggester {
+    /// Creates a suggester weighing `signals`, each worth a point.
+    pub fn new(signals: Vec<Signal>) -> Self {
+        Self { signals }
+    }
+
+    /// Returns the points of each member of `group`, and for each the reasons, matching
+    /// paths relative to `scan_path`. Signals that don't tell the copies apart give none.
+    pub fn score(&self, group: &[FileInfo], scan_path: &Path) -> Vec<(usize, Vec<String>)> {
+        let relative: Vec<_> = group
+            .iter()
+            .map(|file| {
+                let path = file.path.to_path_buf();
+                path.strip_prefix(scan_path).unwrap_or(&path).to_path_buf()
+            })
+            .collect();
+        let mut scores: Vec<(usize, Vec<String>)> = vec![(0, Vec::new()); group.len()];
+        for signal in &self.signals {
+            let favoured: Vec<bool> = match signal {
+                Signal::Shallower => {
+                    let depths: Vec<usize> = relative
+                        .iter()
+                        .map(|path| path.components().count())
+                        .collect();
+                    let shallowest = depths.iter().min().copied().unwrap_or(0);
+                    depths.iter().map(|&depth| depth == shallowest).collect()
+                }
+                Signal::NotBackup => relative.iter().map(|path| !in_backup_dir(path)).collect(),
+                Signal::Older => {
+                    let modified: Vec<Option<SystemTime>> = group
+                        .iter()
+                        .map(|file| {
+                            file.modified.or_else(|| {
+                                file.path.to_path_buf().metadata().ok()?.modified().ok()
+                            })
+                        })
+                        .collect();
+                    let oldest = modified.iter().flatten().min().copied();
+                    modified
+                        .iter()
+                        .map(|&time| time.is_some() && time == oldest)
+                        .collect()
+                }
+            };
+            if favoured.iter().all(|&f| f) || !favoured.iter().any(|&f| f) {
+                continue;
+            }
+            let reason = match signal {
+                Signal::Shallower => "closest to the scanned directory",
+                Signal::NotBackup => "not in a backup or copy directory",
+                Signal::Older => "modified first",
+            };
+            for (score, _) in scores.iter_mut().zip(&favoured).filter(|(_, f)| **f) {
+                score.0 += 1;
+                score.1.push(reason.to_string());
+            }
+        }
+        scores
+    }
+
+    /// Suggests the member of `group` to keep: the one with the most points, the smallest
+    /// path of those tied, or `None` if no signal tells the copies apart.
+    pub fn suggest(&self, group: &[FileInfo], scan_path: &Path) -> Option<Suggestion> {
+        let scores = self.score(group, scan_path);
+        let index = (0..group.len()).min_by(|&a, &b| {
+            scores[b]
+                .0
+                .cmp(&scores[a].0)
+                .then_with(|| group[a].path.cmp(&group[b].path))
+        })?;
+        let (points, reasons) = scores.into_iter().nth(index)?;
+        (points > 0).then_some(Suggestion { index, reasons })
+    }
+}
+
+/// Logs the copy suggested to keep for each group, the groups wasting most first, only
+/// the first `top` if given, with the reasons.
+pub fn print_suggestions(
+    duplicates: &HashMap<ContentHash, Vec<FileInfo>>,
+    suggester: &Suggester,
+    root: &ScanRoot,
+    top: Option<usize>,
+) {
+    let mut groups: Vec<(&ContentHash, &Vec<FileInfo>)> = duplicates.iter().collect();
+    groups.sort_by(|a, b| {
+        let waste = |group: &[FileInfo]| group[0].size * (group.len() as u64 - 1);
+        waste(b.1).cmp(&waste(a.1)).then_with(|| a.0.cmp(b.0))
+    });
+    groups.truncate(top.unwrap_or(groups.len()));
+    let suggestions: Vec<_> = groups
+        .into_iter()
+        .map(|(hash, group)| {
+            let actionable: Vec<FileInfo> = group
+                .iter()
+                .filter(|file| file.is_actionable())
+                .cloned()
+                .collect();
+            let suggestion = suggester.suggest(&actionable, root.scan_path());
+            (hash, actionable, suggestion)
+        })
+        .collect();
+    let suggested = suggestions.iter().filter(|(_, _, s)| s.is_some()).count();
+    info!(
+        "Suggested copies to keep for {} of {} groups:",
+        Count(suggested as u64),
+        Count(suggestions.len() as u64)
+    );
+    for (hash, group, suggestion) in &suggestions {
+        match suggestion {
+            Some(Suggestion { index, reasons }) => info!(
+                "  [{}] keep {}: {}",
+                hash.group_id(),
+                root.display_file(&group[*index].path).display(),
+                reasons.join(", ")
+            ),
+            None => info!(
+                "  [{}] no suggestion, the copies look alike",
+                hash.group_id()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn suggests_the_shallow_older_copy_outside_backups() {
+        let epoch = SystemTime::UNIX_EPOCH;
+        let file = |path: &str, age: u64| FileInfo {
+            modified: Some(epoch + Duration::from_secs(1_000_000 - age)),
+            ..FileInfo::for_test(
+                Path::new("/scan").join(path).as_path(),
+                4,
+                ContentHash::of(b"same"),
+            )
+        };
+        let group = [
+            file("Backup 2019/Photos/img.jpg", 100),
+            file("Photos/img.jpg", 50),
+            file("Photos/Sorted/img.jpg", 10),
+        ];
+        let suggestion = Suggester::default()
+            .suggest(&group, Path::new("/scan"))
+            .unwrap();
+        assert_eq!(suggestion.index, 1);
+        assert_eq!(
+            suggestion.reasons,
+            [
+                "closest to the scanned directory",
+                "not in a backup or copy directory"
+            ]
+        );
+
+        let older = Suggester::new(vec![Signal::Older]);
+        assert_eq!(older.suggest(&group, Path::new("/scan")).unwrap().index, 0);
+        let alike = [file("a/img.jpg", 1), file("b/img.jpg", 1)];
+        assert_eq!(
+            Suggester::default().suggest(&alike, Path::new("/scan")),
+            None
+        );
+        assert!("newer".parse::<Signal>().is_err());
+    }
+}
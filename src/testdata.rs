@@ -0,0 +1,177 @@
+//! Reproducible trees of files with a known duplicate structure, for end-to-end tests,
+//! demos and benchmarks on realistic trees. `gen-testdata` writes one from the command
+//! line.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+/// What to generate with [`generate`].
+#[derive(Debug, Clone, Copy)]
+pub struct TestTreeSpec {
+    /// Number of files to write.
+    pub files: usize,
+    /// Share of the files that are extra copies of another file's contents, from 0 up to
+    /// but not including 1.
+    pub dup_ratio: f64,
+    /// Seed of the random choices; the same spec always gives the same tree.
+    pub seed: u64,
+    /// Largest file size in bytes. Sizes are spread over powers of two up to this.
+    pub max_size: u64,
+}
+
+impl Default for TestTreeSpec {
+    fn default() -> Self {
+        Self {
+            files: 1000,
+            dup_ratio: 0.3,
+            seed: 0,
+            max_size: 1024 * 1024,
+        }
+    }
+}
+
+/// A tree written by [`generate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestTree {
+    /// Paths of all files written, relative to the tree's directory, sorted.
+    pub files: Vec<PathBuf>,
+    /// Total size of the files.
+    pub bytes: u64,
+    /// Groups of files with the same contents, relative to the tree's directory. Files
+    /// are sorted within groups and groups by their first file.
+    pub groups: Vec<Vec<PathBuf>>,
+}
+
+/// SplitMix64, a small generator that is plenty for test data and keeps trees the same
+/// across versions of any random number crate.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number below `n`, which must not be 0.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+/// Writes a tree of `spec.files` files to `dir`, which must be empty or not exist yet,
+/// and returns its structure.
+///
+/// Files are spread over two levels of directories. Each file has its own contents,
+/// except `spec.dup_ratio` of them, which are copies of a randomly chosen earlier file.
+/// Contents start with a distinct number, so files that aren't copies never match.
+///
+/// # Errors
+///
+/// Returns an error if `dup_ratio` is out of range, `dir` is not empty, or a file can't
+/// be written.
+pub fn generate(dir: &Path, spec: &TestTreeSpec) -> Result<TestTree> {
+    if !(0.0..1.0).contains(&spec.dup_ratio) {
+        anyhow::bail!("The duplicate ratio must be at least 0 and less than 1");
+    }
+    if dir.exists() && fs::read_dir(dir)?.next().is_some() {
+        anyhow::bail!("{} is not empty", dir.display());
+    }
+    fs::create_dir_all(dir)?;
+
+    let mut rng = Rng(spec.seed);
+    // At least one file keeps its own contents for the copies to be copies of
+    let copies =
+        ((spec.files as f64 * spec.dup_ratio).round() as usize).min(spec.files.saturating_sub(1));
+    let contents = spec.files - copies;
+    let dirs = (spec.files as u64 / 64).max(1);
+    let max_bits = u64::BITS - spec.max_size.max(8).leading_zeros();
+
+    // Each file names the content it holds; copies pick one of the contents at random
+    let mut assignment: Vec<usize> = (0..contents).collect();
+    for _ in 0..copies {
+        assignment.push(rng.below(contents as u64) as usize);
+    }
+    let mut sizes = Vec::with_capacity(contents);
+    for _ in 0..contents {
+        let bits = rng.below(u64::from(max_bits)) as u32;
+        sizes.push((rng.below(1 << bits) + 8).min(spec.max_size.max(8)));
+    }
+
+    let mut files = Vec::with_capacity(spec.files);
+    let mut groups: Vec<Vec<PathBuf>> = vec![Vec::new(); contents];
+    let mut bytes = 0;
+    for (index, content) in assignment.into_iter().enumerate() {
+        let path = PathBuf::from(format!("d{:02}", rng.below(dirs)))
+            .join(format!("d{:02}", rng.below(4)))
+            .join(format!("f{:06}.dat", index));
+        fs::create_dir_all(dir.join(path.parent().unwrap()))?;
+        fs::write(
+            dir.join(&path),
+            content_bytes(spec.seed, content, sizes[content]),
+        )?;
+        bytes += sizes[content];
+        groups[content].push(path.clone());
+        files.push(path);
+    }
+
+    files.sort();
+    groups.retain(|group| group.len() > 1);
+    for group in &mut groups {
+        group.sort();
+    }
+    groups.sort();
+    Ok(TestTree {
+        files,
+        bytes,
+        groups,
+    })
+}
+
+/// Returns the `size` bytes of content number `content`: the number, then bytes from a
+/// generator seeded with it.
+fn content_bytes(seed: u64, content: usize, size: u64) -> Vec<u8> {
+    let mut rng = Rng(seed ^ (content as u64).wrapping_mul(0x2545_f491_4f6c_dd1d));
+    let mut data = (content as u64).to_le_bytes().to_vec();
+    while (data.len() as u64) < size {
+        data.extend_from_slice(&rng.next().to_le_bytes());
+    }
+    data.truncate(size as usize);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_the_same_tree_for_a_seed() {
+        let spec = TestTreeSpec {
+            files: 200,
+            dup_ratio: 0.25,
+            seed: 42,
+            max_size: 4096,
+        };
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        let tree = generate(first.path(), &spec).unwrap();
+        assert_eq!(generate(second.path(), &spec).unwrap(), tree);
+        assert_eq!(tree.files.len(), 200);
+        let extra: usize = tree.groups.iter().map(|group| group.len() - 1).sum();
+        assert_eq!(extra, 50);
+
+        let group = &tree.groups[0];
+        let read = |dir: &Path, path: &PathBuf| fs::read(dir.join(path)).unwrap();
+        assert_eq!(read(first.path(), &group[0]), read(first.path(), &group[1]));
+        assert_eq!(
+            read(first.path(), &group[0]),
+            read(second.path(), &group[0])
+        );
+
+        assert!(generate(first.path(), &spec).is_err());
+    }
+}
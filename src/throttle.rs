@@ -0,0 +1,146 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+/// Bytes read since `start`, for rate limiting.
+struct Budget {
+    start: Instant,
+    bytes: u64,
+}
+
+/// Limits how fast files are read, so a background scan leaves disk bandwidth for other
+/// programs. Shared by all threads of a scan, so the limit applies to their total.
+pub struct Throttle {
+    max_bytes_per_sec: Option<u64>,
+    file_delay: Duration,
+    budget: Mutex<Budget>,
+}
+
+impl Throttle {
+    /// Creates a throttle reading at most `max_bytes_per_sec` bytes per second (if given)
+    /// and pausing for `file_delay` after each file.
+    pub fn new(max_bytes_per_sec: Option<u64>, file_delay: Duration) -> Self {
+        Self {
+            max_bytes_per_sec: max_bytes_per_sec.filter(|&rate| rate > 0),
+            file_delay,
+            budget: Mutex::new(Budget {
+                start: Instant::now(),
+                bytes: 0,
+            }),
+        }
+    }
+
+    /// Creates a throttle that never waits.
+    pub fn unlimited() -> Self {
+        Self::new(None, Duration::ZERO)
+    }
+
+    /// Returns `true` if the throttle never waits.
+    pub fn is_unlimited(&self) -> bool {
+        self.max_bytes_per_sec.is_none() && self.file_delay.is_zero()
+    }
+
+    /// Accounts for `bytes` just read, sleeping as long as needed to stay under the rate
+    /// limit. Unused budget from idle periods (e.g. while files come from the cache) is
+    /// capped at one second, so reading resumes without a burst.
+    pub fn consume(&self, bytes: u64) {
+        let Some(rate) = self.max_bytes_per_sec else {
+            return;
+        };
+        let wait = {
+            let mut budget = self.budget.lock().unwrap();
+            let elapsed = budget.start.elapsed();
+            let due = Duration::from_secs_f64(budget.bytes as f64 / rate as f64);
+            if elapsed > due + Duration::from_secs(1) {
+                budget.start = Instant::now() - Duration::from_secs(1);
+                budget.bytes = 0;
+            }
+            budget.bytes += bytes;
+            Duration::from_secs_f64(budget.bytes as f64 / rate as f64)
+                .saturating_sub(budget.start.elapsed())
+        };
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Pauses for the configured delay after a file has been read.
+    pub fn finish_file(&self) {
+        if !self.file_delay.is_zero() {
+            thread::sleep(self.file_delay);
+        }
+    }
+}
+
+/// Lowers the CPU and disk priority of the current process, so a scan yields to other
+/// programs. Threads started afterwards inherit the lower priority, so call this before
+/// scanning.
+///
+/// On Unix the process is niced to 19, and on Linux its IO class is set to idle. On
+/// Windows the process enters background mode, which lowers CPU, IO and memory
+/// priority.
+///
+/// # Errors
+///
+/// Returns an error if the priority cannot be changed.
+pub fn lower_priority() -> Result<()> {
+    #[cfg(unix)]
+    {
+        // SAFETY: setpriority has no memory safety requirements
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        #[cfg(target_os = "linux")]
+        {
+            const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+            const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+            const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+            // SAFETY: ioprio_set takes plain integers
+            let result = unsafe {
+                libc::syscall(
+                    libc::SYS_ioprio_set,
+                    IOPRIO_WHO_PROCESS,
+                    0,
+                    IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+                )
+            };
+            if result != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+        Ok(())
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::System::Threading::{
+            GetCurrentProcess, PROCESS_MODE_BACKGROUND_BEGIN, SetPriorityClass,
+        };
+        // SAFETY: the pseudo handle of the current process is always valid
+        if unsafe { SetPriorityClass(GetCurrentProcess(), PROCESS_MODE_BACKGROUND_BEGIN) } == 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        anyhow::bail!("lowering the priority is not supported on this platform")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_read_rate() {
+        let throttle = Throttle::new(Some(1_000_000), Duration::ZERO);
+        let start = Instant::now();
+        for _ in 0..3 {
+            throttle.consume(100_000);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(250));
+        assert!(Throttle::unlimited().is_unlimited());
+    }
+}
@@ -0,0 +1,242 @@
+//! Tombstones: the contents of duplicates deleted by earlier runs, recorded next to the
+//! hash cache. A later scan that finds one of these contents again at a path other than
+//! the copy kept warns about it, so re-downloads and restored backups of what was cleaned
+//! up don't pile up unnoticed.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::FileInfo;
+use crate::actions::{Journal, Operation};
+use crate::content_hash::ContentHash;
+use crate::scan_root::ScanRoot;
+use crate::units::{Bytes, Count};
+
+/// Version of the tombstones file format.
+const TOMBSTONES_VERSION: u32 = 1;
+
+/// Contents deleted as duplicates.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub hash: ContentHash,
+    /// Copies deleted, across all runs.
+    #[serde(with = "crate::paths::serde_path::vec")]
+    pub deleted: Vec<PathBuf>,
+    /// Copies kept instead, which are expected to still have the contents.
+    #[serde(with = "crate::paths::serde_path::vec")]
+    pub kept: Vec<PathBuf>,
+    /// RFC 3339 timestamp of the last run deleting a copy.
+    pub deleted_at: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TombstonesFile {
+    version: u32,
+    tombstones: Vec<Tombstone>,
+}
+
+/// A scanned file with contents deleted as a duplicate before.
+#[derive(Debug, Clone)]
+pub struct Reappeared<'a> {
+    pub file: FileInfo,
+    pub tombstone: &'a Tombstone,
+}
+
+/// The tombstones recorded for one hash cache.
+pub struct Tombstones {
+    file: PathBuf,
+    tombstones: HashMap<ContentHash, Tombstone>,
+}
+
+impl Tombstones {
+    /// Loads the tombstones in `file`, or starts with none if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or parsed.
+    pub fn load(file: PathBuf) -> Result<Self> {
+        let tombstones = match fs::read(&file) {
+            Ok(data) => serde_json::from_slice::<TombstonesFile>(&data)
+                .with_context(|| format!("Failed to parse tombstones {}", file.display()))?
+                .tombstones
+                .into_iter()
+                .map(|tombstone| (tombstone.hash, tombstone))
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { file, tombstones })
+    }
+
+    /// Returns the number of contents with tombstones.
+    pub fn len(&self) -> usize {
+        self.tombstones.len()
+    }
+
+    /// Returns `true` if nothing was recorded as deleted.
+    pub fn is_empty(&self) -> bool {
+        self.tombstones.is_empty()
+    }
+
+    /// Returns the tombstone for contents with `hash`, if they were deleted before.
+    pub fn get(&self, hash: &ContentHash) -> Option<&Tombstone> {
+        self.tombstones.get(hash)
+    }
+
+    /// Records the duplicates deleted in `journal`, returning how many were recorded.
+    /// Files moved, hardlinked or reflinked keep their contents at hand, so they get no
+    /// tombstones.
+    pub fn record(&mut self, journal: &Journal) -> usize {
+        let mut recorded = 0;
+        for operation in &journal.operations {
+            let Operation::Delete {
+                path, kept, hash, ..
+            } = operation
+            else {
+                continue;
+            };
+            let Ok(hash) = hash.parse::<ContentHash>() else {
+                continue;
+            };
+            let tombstone = self.tombstones.entry(hash).or_insert_with(|| Tombstone {
+                hash,
+                deleted: Vec::new(),
+                kept: Vec::new(),
+                deleted_at: String::new(),
+            });
+            if !tombstone.deleted.contains(path) {
+                tombstone.deleted.push(path.clone());
+            }
+            if !tombstone.kept.contains(kept) {
+                tombstone.kept.push(kept.clone());
+            }
+            tombstone.deleted_at.clone_from(&journal.created_at);
+            recorded += 1;
+        }
+        recorded
+    }
+
+    /// Writes the tombstones back to their file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self) -> Result<()> {
+        let mut tombstones: Vec<Tombstone> = self.tombstones.values().cloned().collect();
+        tombstones.sort_by_key(|tombstone| tombstone.hash);
+        let data = serde_json::to_vec(&TombstonesFile {
+            version: TOMBSTONES_VERSION,
+            tombstones,
+        })?;
+        fs::write(&self.file, data)
+            .with_context(|| format!("Failed to write tombstones {}", self.file.display()))
+    }
+
+    /// Returns the files of `files` with contents deleted before, other than the copies
+    /// kept then, sorted by path.
+    pub fn find_reappeared<'a, 'b>(
+        &'a self,
+        files: impl IntoIterator<Item = &'b FileInfo>,
+    ) -> Vec<Reappeared<'a>> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        let mut reappeared: Vec<Reappeared> = files
+            .into_iter()
+            .filter_map(|file| {
+                let tombstone = self.get(&file.hash)?;
+                let path = file.path.to_path_buf();
+                (!tombstone.kept.contains(&path)).then(|| Reappeared {
+                    file: file.clone(),
+                    tombstone,
+                })
+            })
+            .collect();
+        reappeared.sort_by(|a, b| a.file.path.cmp(&b.file.path));
+        reappeared
+    }
+}
+
+/// Logs the files found again with contents deleted before, each with the copy kept then.
+pub fn print_reappeared(reappeared: &[Reappeared], root: &ScanRoot) {
+    if reappeared.is_empty() {
+        return;
+    }
+    warn!(
+        "Found {} files with contents deleted as duplicates before:",
+        Count(reappeared.len() as u64)
+    );
+    for Reappeared { file, tombstone } in reappeared {
+        warn!(
+            "  {} ({}), deleted {}",
+            root.display_file(&file.path).display(),
+            Bytes(file.size),
+            tombstone.deleted_at
+        );
+        if let Some(kept) = tombstone.kept.first() {
+            warn!("    kept: {}", root.display(kept).display());
+        }
+    }
+    info!("Delete them again, or keep them by deleting the tombstones file");
+}
+
+/// Loads the tombstones in `file`, records the deletions of `journal` and saves them,
+/// logging rather than failing, since the files are deleted either way.
+pub fn record_deletions(file: &Path, journal: &Journal) {
+    let recorded = Tombstones::load(file.to_path_buf()).and_then(|mut tombstones| {
+        let recorded = tombstones.record(journal);
+        if recorded > 0 {
+            tombstones.save()?;
+        }
+        Ok(recorded)
+    });
+    match recorded {
+        Ok(0) => {}
+        Ok(recorded) => info!(
+            "Recorded {} deleted duplicates in {}",
+            Count(recorded as u64),
+            file.display()
+        ),
+        Err(e) => warn!("Failed to record deleted duplicates: {:#}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warns_about_deleted_contents_found_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let hash = ContentHash::of(b"photo");
+        let mut journal = Journal::new();
+        journal.operations.push(Operation::Delete {
+            path: PathBuf::from("/photos/copy.jpg"),
+            kept: PathBuf::from("/photos/original.jpg"),
+            hash: hash.to_string(),
+            trashed: None,
+            attributes: None,
+        });
+        let file = dir.path().join("cache.tombstones");
+        record_deletions(&file, &journal);
+
+        let tombstones = Tombstones::load(file).unwrap();
+        assert_eq!(tombstones.len(), 1);
+        let scanned =
+            |path: &str, content: &[u8]| FileInfo::with_contents(Path::new(path), content);
+        let files = [
+            scanned("/photos/original.jpg", b"photo"),
+            scanned("/downloads/photo.jpg", b"photo"),
+            scanned("/downloads/other.jpg", b"other"),
+        ];
+        let reappeared = tombstones.find_reappeared(&files);
+        assert_eq!(reappeared.len(), 1);
+        assert_eq!(reappeared[0].file.path, *Path::new("/downloads/photo.jpg"));
+        assert_eq!(reappeared[0].tombstone.deleted_at, journal.created_at);
+    }
+}
@@ -0,0 +1,208 @@
+//! `--group-by top-dir`: a summary of the duplicates by top-level folder of the scanned
+//! directory, to tell where on a large volume cleaning up frees the most space. Each
+//! redundant copy counts towards the folder it is in, taking the copy of its group that
+//! would be kept as the original, so the folders add up to what `--delete` would free.
+//! `--drill-into` narrows the report to one folder, and summarizes its subfolders.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+use clap::ValueEnum;
+use colored::Colorize;
+use log::{info, warn};
+
+use crate::FileInfo;
+use crate::content_hash::ContentHash;
+use crate::selection::KeepPolicy;
+use crate::units::{Bytes, Count};
+
+/// How the duplicate groups are summarized, instead of being listed one by one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum GroupBy {
+    /// Duplicates and wasted space per top-level folder of the scanned directory
+    TopDir,
+}
+
+/// The redundant copies in one folder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FolderWaste {
+    /// The folder relative to the directory summarized, empty for the files directly in it.
+    pub folder: PathBuf,
+    /// Number of duplicate groups with a redundant copy in the folder.
+    pub groups: usize,
+    /// Number of redundant copies in the folder.
+    pub duplicates: usize,
+    /// Space used by the redundant copies, in bytes.
+    pub wasted_space: u64,
+}
+
+/// Returns the top-level folder of `path` below `base`, if it is inside `base`, or an
+/// empty path for a file directly in it.
+fn top_dir(path: &Path, base: &Path) -> Option<PathBuf> {
+    let relative = path.strip_prefix(base).ok()?;
+    let mut components = relative.components();
+    let first = components.next()?;
+    Some(match (first, components.next()) {
+        (Component::Normal(name), Some(_)) => PathBuf::from(name),
+        _ => PathBuf::new(),
+    })
+}
+
+/// Sums the redundant copies of `duplicates` by the top-level folder of `base` they are
+/// in, most wasted space first. The copy of each group that `policy` keeps, matching
+/// paths relative to `scan_path`, and its hardlinks aren't redundant. Copies outside
+/// `base` are left out.
+pub fn waste_by_top_dir(
+    duplicates: &HashMap<ContentHash, Vec<FileInfo>>,
+    base: &Path,
+    scan_path: &Path,
+    policy: &KeepPolicy,
+) -> Vec<FolderWaste> {
+    let mut folders: HashMap<PathBuf, FolderWaste> = HashMap::new();
+    for group in duplicates.values() {
+        let kept = &group[policy.select(group, scan_path)];
+        let mut touched = HashSet::new();
+        for file in group {
+            if std::ptr::eq(file, kept) || file.file_id.is_some() && file.file_id == kept.file_id {
+                continue;
+            }
+            let Some(folder) = top_dir(&file.path.to_path_buf(), base) else {
+                continue;
+            };
+            let waste = folders
+                .entry(folder.clone())
+                .or_insert_with(|| FolderWaste {
+                    folder: folder.clone(),
+                    groups: 0,
+                    duplicates: 0,
+                    wasted_space: 0,
+                });
+            waste.duplicates += 1;
+            waste.wasted_space += file.size;
+            if touched.insert(folder) {
+                waste.groups += 1;
+            }
+        }
+    }
+    let mut folders: Vec<FolderWaste> = folders.into_values().collect();
+    folders.sort_by(|a, b| {
+        b.wasted_space
+            .cmp(&a.wasted_space)
+            .then_with(|| a.folder.cmp(&b.folder))
+    });
+    folders
+}
+
+/// Logs a table of `folders` of `base`, only the first `top` if given, with the command
+/// to drill into the first.
+pub fn print_by_top_dir(folders: &[FolderWaste], base: &Path, top: Option<usize>) {
+    if folders.is_empty() {
+        info!("{}", "No duplicate files found!".green());
+        return;
+    }
+    let total: u64 = folders.iter().map(|folder| folder.wasted_space).sum();
+    let duplicates: usize = folders.iter().map(|folder| folder.duplicates).sum();
+    warn!(
+        "Found {} duplicate files wasting {} of space in {} folders of {}",
+        Count(duplicates as u64),
+        Bytes(total),
+        Count(folders.len() as u64),
+        base.display()
+    );
+    let shown = &folders[..top.unwrap_or(folders.len()).min(folders.len())];
+    let labels: Vec<String> = shown
+        .iter()
+        .map(|folder| {
+            if folder.folder.as_os_str().is_empty() {
+                "(files in the folder itself)".to_string()
+            } else {
+                format!("{}{}", folder.folder.display(), std::path::MAIN_SEPARATOR)
+            }
+        })
+        .collect();
+    let label_width = labels
+        .iter()
+        .map(|label| label.chars().count())
+        .max()
+        .unwrap_or(0);
+    for (folder, label) in shown.iter().zip(&labels) {
+        warn!(
+            "  {:<label_width$} {:>12} {:>6.1}%  {} files in {} groups",
+            label.bold(),
+            Bytes(folder.wasted_space).to_string(),
+            folder.wasted_space as f64 * 100.0 / total as f64,
+            Count(folder.duplicates as u64),
+            Count(folder.groups as u64)
+        );
+    }
+    if shown.len() < folders.len() {
+        info!(
+            "... and {} more folders",
+            Count((folders.len() - shown.len()) as u64)
+        );
+    }
+    if let Some(first) = folders
+        .iter()
+        .find(|folder| !folder.folder.as_os_str().is_empty())
+    {
+        info!(
+            "Drill into a folder with --drill-into, e.g. --drill-into '{}'",
+            base.join(&first.folder).display()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_redundant_copies_by_top_level_folder() {
+        let base = Path::new("/volume");
+        let group = |content: &[u8], paths: &[&str]| {
+            let files = paths
+                .iter()
+                .map(|path| FileInfo::with_contents(base.join(path), content))
+                .collect();
+            (ContentHash::of(content), files)
+        };
+        let duplicates = HashMap::from([
+            group(
+                b"movie",
+                &[
+                    "Archive/movie.mkv",
+                    "Backup/a/movie.mkv",
+                    "Backup/b/movie.mkv",
+                ],
+            ),
+            group(b"doc", &["Archive/doc.txt", "Backup/doc.txt", "doc.txt"]),
+        ]);
+        let policy = KeepPolicy::new(vec!["path:Archive/**".parse().unwrap()]);
+        let folders = waste_by_top_dir(&duplicates, base, base, &policy);
+        assert_eq!(
+            folders,
+            [
+                FolderWaste {
+                    folder: PathBuf::from("Backup"),
+                    groups: 2,
+                    duplicates: 3,
+                    wasted_space: 13,
+                },
+                FolderWaste {
+                    folder: PathBuf::new(),
+                    groups: 1,
+                    duplicates: 1,
+                    wasted_space: 3,
+                },
+            ]
+        );
+
+        // Drilling into a folder summarizes its subfolders
+        let folders = waste_by_top_dir(&duplicates, &base.join("Backup"), base, &policy);
+        let labels: Vec<&Path> = folders
+            .iter()
+            .map(|folder| folder.folder.as_path())
+            .collect();
+        assert_eq!(labels, [Path::new("a"), Path::new("b"), Path::new("")]);
+    }
+}
@@ -0,0 +1,322 @@
+//! Moving files to the platform trash, so deleted duplicates can be recovered: the
+//! Freedesktop.org trash on Linux and other Unix systems, `~/.Trash` on macOS, and the
+//! Recycle Bin on Windows.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::actions::ActionPermission;
+
+/// Moves `path` to the trash. Returns where the file now is, if known, so that
+/// [`restore`] can put it back; `None` on Windows, where the Recycle Bin decides.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be moved to the trash, e.g. because it is on a
+/// volume without one.
+pub fn trash(path: &Path, _permission: &ActionPermission) -> io::Result<Option<PathBuf>> {
+    imp::trash(&std::path::absolute(path)?)
+}
+
+/// Moves a file that [`trash`] moved to `trashed` back to `path`.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be moved back.
+pub fn restore(trashed: &Path, path: &Path, _permission: &ActionPermission) -> io::Result<()> {
+    imp::restore(trashed, path)
+}
+
+/// Returns `name`, or `name` with a counter appended for `attempt`s after the first, to
+/// find a free name in a trash directory.
+#[cfg(unix)]
+fn numbered(name: &std::ffi::OsStr, attempt: u32) -> std::ffi::OsString {
+    let mut name = name.to_os_string();
+    if attempt > 1 {
+        name.push(format!(".{}", attempt));
+    }
+    name
+}
+
+/// The Freedesktop.org Trash specification: files are moved to `files/` of a trash
+/// directory, next to a `.trashinfo` file in `info/` recording where they came from.
+/// Files on another filesystem than the home trash go to the trash at the top of their
+/// own filesystem.
+#[cfg(all(unix, not(target_os = "macos")))]
+mod imp {
+    use std::fs::{self, DirBuilder, OpenOptions};
+    use std::io::{self, Write};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+    use std::path::{Path, PathBuf};
+
+    use time::OffsetDateTime;
+    use time::macros::format_description;
+
+    use super::numbered;
+    use crate::actions::move_file;
+
+    /// Percent-encodes `path` as required for the `Path` key of a `.trashinfo` file.
+    fn encode(path: &Path) -> String {
+        let mut encoded = String::new();
+        for &byte in path.as_os_str().as_bytes() {
+            if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+                encoded.push(byte as char);
+            } else {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        }
+        encoded
+    }
+
+    /// Returns the topmost directory of the filesystem holding `path`.
+    fn mount_root(path: &Path, device: u64) -> PathBuf {
+        let mut root = path;
+        while let Some(parent) = root.parent()
+            && fs::metadata(parent).is_ok_and(|metadata| metadata.dev() == device)
+        {
+            root = parent;
+        }
+        root.to_path_buf()
+    }
+
+    /// Returns the trash directory for a file on `device` (`home_trash` if it is on the
+    /// same filesystem), and the directory the paths recorded in it are relative to
+    /// (`None` for absolute paths).
+    fn trash_dir(
+        path: &Path,
+        device: u64,
+        home_trash: &Path,
+    ) -> io::Result<(PathBuf, Option<PathBuf>)> {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(home_trash)?;
+        if fs::metadata(home_trash)?.dev() == device {
+            return Ok((home_trash.to_path_buf(), None));
+        }
+
+        let top = mount_root(path, device);
+        // SAFETY: getuid has no preconditions and can't fail
+        let uid = unsafe { libc::getuid() };
+        // An administrator-created `.Trash` must be a real, sticky directory to be used
+        let shared = top.join(".Trash");
+        if fs::symlink_metadata(&shared)
+            .is_ok_and(|metadata| metadata.is_dir() && metadata.permissions().mode() & 0o1000 != 0)
+        {
+            let dir = shared.join(uid.to_string());
+            if DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(&dir)
+                .is_ok()
+            {
+                return Ok((dir, Some(top)));
+            }
+        }
+        let dir = top.join(format!(".Trash-{}", uid));
+        DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+        Ok((dir, Some(top)))
+    }
+
+    pub fn trash(path: &Path) -> io::Result<Option<PathBuf>> {
+        let home_trash = dirs::data_dir()
+            .ok_or_else(|| io::Error::other("no home directory"))?
+            .join("Trash");
+        trash_in(path, &home_trash)
+    }
+
+    /// Moves `path` to `home_trash`, or to the trash of its own filesystem.
+    pub(super) fn trash_in(path: &Path, home_trash: &Path) -> io::Result<Option<PathBuf>> {
+        let device = fs::symlink_metadata(path)?.dev();
+        let (dir, top) = trash_dir(path, device, home_trash)?;
+        let (files, info) = (dir.join("files"), dir.join("info"));
+        for sub in [&files, &info] {
+            DirBuilder::new().recursive(true).mode(0o700).create(sub)?;
+        }
+
+        let name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+        let recorded = match &top {
+            Some(top) => path.strip_prefix(top).unwrap_or(path),
+            None => path,
+        };
+        let deleted_at = OffsetDateTime::now_local()
+            .unwrap_or_else(|_| OffsetDateTime::now_utc())
+            .format(format_description!(
+                "[year]-[month]-[day]T[hour]:[minute]:[second]"
+            ))
+            .unwrap_or_default();
+
+        // The info file is created first and exclusively, which reserves the name
+        for attempt in 1.. {
+            let name = numbered(name, attempt);
+            let mut info_name = name.clone();
+            info_name.push(".trashinfo");
+            let info_file = info.join(info_name);
+            let mut file = match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&info_file)
+            {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            };
+            let trashed = files.join(&name);
+            let moved = write!(
+                file,
+                "[Trash Info]\nPath={}\nDeletionDate={}\n",
+                encode(recorded),
+                deleted_at
+            )
+            .and_then(|()| fs::rename(path, &trashed));
+            return match moved {
+                Ok(()) => Ok(Some(trashed)),
+                Err(e) => {
+                    let _ = fs::remove_file(&info_file);
+                    Err(e)
+                }
+            };
+        }
+        unreachable!("ran out of trash file names")
+    }
+
+    pub fn restore(trashed: &Path, path: &Path) -> io::Result<()> {
+        move_file(trashed, path)?;
+        // `trashed` is in `files/` of the trash directory, its info file in `info/`
+        if let (Some(dir), Some(name)) =
+            (trashed.parent().and_then(Path::parent), trashed.file_name())
+        {
+            let mut info_name = name.to_os_string();
+            info_name.push(".trashinfo");
+            let _ = fs::remove_file(dir.join("info").join(info_name));
+        }
+        Ok(())
+    }
+}
+
+/// `~/.Trash`, where Finder keeps the files of the home volume that are in the trash.
+#[cfg(target_os = "macos")]
+mod imp {
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    use super::numbered;
+    use crate::actions::move_file;
+
+    pub fn trash(path: &Path) -> io::Result<Option<PathBuf>> {
+        let dir = dirs::home_dir()
+            .ok_or_else(|| io::Error::other("no home directory"))?
+            .join(".Trash");
+        fs::create_dir_all(&dir)?;
+        let name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+        let trashed = (1..)
+            .map(|attempt| dir.join(numbered(name, attempt)))
+            .find(|trashed| fs::symlink_metadata(trashed).is_err())
+            .expect("ran out of trash file names");
+        match fs::rename(path, &trashed) {
+            Ok(()) => Ok(Some(trashed)),
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                Err(io::Error::other("file is on another volume than the trash"))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn restore(trashed: &Path, path: &Path) -> io::Result<()> {
+        move_file(trashed, path)
+    }
+}
+
+/// The Recycle Bin, through the shell's file operations.
+#[cfg(windows)]
+mod imp {
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    use windows_sys::Win32::UI::Shell::{
+        FO_DELETE, FOF_ALLOWUNDO, FOF_NOCONFIRMATION, FOF_NOERRORUI, FOF_SILENT, SHFILEOPSTRUCTW,
+        SHFileOperationW,
+    };
+
+    pub fn trash(path: &Path) -> io::Result<Option<PathBuf>> {
+        // The list of files must end with two NULs. The shell doesn't accept `\\?\` paths
+        let from: Vec<u16> = path
+            .as_os_str()
+            .to_string_lossy()
+            .encode_utf16()
+            .chain([0, 0])
+            .collect();
+        let mut operation = SHFILEOPSTRUCTW {
+            wFunc: FO_DELETE,
+            pFrom: from.as_ptr(),
+            fFlags: (FOF_ALLOWUNDO | FOF_NOCONFIRMATION | FOF_NOERRORUI | FOF_SILENT) as u16,
+            ..Default::default()
+        };
+        // SAFETY: `from` is a double NUL-terminated UTF-16 string that outlives the call
+        let result = unsafe { SHFileOperationW(&mut operation) };
+        if result != 0 || operation.fAnyOperationsAborted != 0 {
+            return Err(io::Error::other(format!(
+                "moving to the Recycle Bin failed with code {:#x}",
+                result
+            )));
+        }
+        Ok(None)
+    }
+
+    pub fn restore(_trashed: &Path, _path: &Path) -> io::Result<()> {
+        Err(io::Error::other("restore the file from the Recycle Bin"))
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    pub fn trash(_path: &Path) -> io::Result<Option<PathBuf>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub fn restore(_trashed: &Path, _path: &Path) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(all(test, unix, not(target_os = "macos")))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn trashes_and_restores_with_trash_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a file");
+        fs::write(&path, "data").unwrap();
+        // Keep the test out of the real home trash
+        let trashed = imp::trash_in(&path, &dir.path().join("Trash"))
+            .unwrap()
+            .unwrap();
+        assert!(trashed.starts_with(dir.path().join("Trash/files")));
+        assert!(!path.exists());
+        assert_eq!(fs::read_to_string(&trashed).unwrap(), "data");
+        let info = trashed
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .join("info/a file.trashinfo");
+        let contents = fs::read_to_string(&info).unwrap();
+        assert!(contents.starts_with("[Trash Info]\nPath="));
+        assert!(contents.contains("a%20file\nDeletionDate="));
+
+        restore(&trashed, &path, &ActionPermission::allow_changes()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "data");
+        assert!(!info.exists());
+    }
+}
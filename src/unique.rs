@@ -0,0 +1,142 @@
+//! Files with no duplicate anywhere in the scanned tree, e.g. to confirm before wiping a
+//! drive that nothing on it is the only copy.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::Result;
+use colored::Colorize;
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::FileInfo;
+use crate::content_hash::ContentHash;
+use crate::duplicates::distinct_copies;
+use crate::paths::{FilePath, path_bytes};
+use crate::scan_root::ScanRoot;
+use crate::units::{Bytes, Count};
+
+/// A file whose contents exist nowhere else, as written by [`write_unique_ndjson`].
+#[derive(Serialize)]
+struct UniqueFile<'a> {
+    path: &'a FilePath,
+    size: u64,
+    hash: &'a ContentHash,
+}
+
+/// Returns the files of `hash_groups` (from
+/// [`group_by_hash`](crate::duplicates::group_by_hash)) whose contents have no other
+/// copy, sorted by path. Hardlinks of one file share its only copy, so they are all
+/// returned.
+pub fn find_unique(hash_groups: &HashMap<ContentHash, Vec<FileInfo>>) -> Vec<FileInfo> {
+    let mut unique: Vec<FileInfo> = hash_groups
+        .values()
+        .filter(|group| distinct_copies(group) == 1)
+        .flatten()
+        .cloned()
+        .collect();
+    unique.sort_by(|a, b| a.path.cmp(&b.path));
+    unique
+}
+
+/// Prints the files found by [`find_unique`] with their sizes.
+pub fn print_unique(unique: &[FileInfo], root: &ScanRoot) {
+    if unique.is_empty() {
+        info!("{}", "Every file has a copy elsewhere!".green());
+        return;
+    }
+    warn!(
+        "Found {} files ({}) with no copy elsewhere:",
+        Count(unique.len() as u64),
+        Bytes(unique.iter().map(|file| file.size).sum())
+    );
+    for file in unique {
+        warn!(
+            "  {} ({})",
+            root.display_file(&file.path).display(),
+            Bytes(file.size)
+        );
+    }
+}
+
+/// Writes the files found by [`find_unique`] to `writer` as newline-delimited JSON, one
+/// object with the path, size and hash of a file per line. Returns the number of files
+/// written.
+///
+/// # Errors
+///
+/// Returns an error if serialization or writing fails.
+pub fn write_unique_ndjson(unique: &[FileInfo], writer: &mut dyn Write) -> Result<usize> {
+    for file in unique {
+        serde_json::to_writer(
+            &mut *writer,
+            &UniqueFile {
+                path: &file.path,
+                size: file.size,
+                hash: &file.hash,
+            },
+        )?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(unique.len())
+}
+
+/// Writes the paths of the files found by [`find_unique`] to `writer`, one per line, like
+/// `fdupes` lists files, or with `print0` each followed by a NUL byte. Returns the number
+/// of files written.
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_unique_paths(
+    unique: &[FileInfo],
+    print0: bool,
+    writer: &mut dyn Write,
+) -> Result<usize> {
+    for file in unique {
+        if print0 {
+            writer.write_all(&path_bytes(&file.path.to_path_buf()))?;
+            writer.write_all(b"\0")?;
+        } else {
+            writeln!(writer, "{}", file.path.display())?;
+        }
+    }
+    writer.flush()?;
+    Ok(unique.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::duplicates::group_by_hash;
+    use std::path::PathBuf;
+
+    #[test]
+    fn finds_files_without_copies() {
+        let file = |path: &str, hash: &str, file_id: Option<(u64, u64)>| FileInfo {
+            file_id,
+            ..FileInfo::for_test(path, 1, ContentHash::of(hash.as_bytes()))
+        };
+        let groups = group_by_hash(vec![
+            file("b", "only", None),
+            file("c", "copied", None),
+            file("d", "copied", None),
+            file("link1", "linked", Some((1, 7))),
+            file("a", "linked", Some((1, 7))),
+        ]);
+        let unique = find_unique(&groups);
+        let paths: Vec<PathBuf> = unique.iter().map(|file| file.path.to_path_buf()).collect();
+        assert_eq!(paths, ["a", "b", "link1"].map(PathBuf::from));
+
+        let mut output = Vec::new();
+        write_unique_ndjson(&unique[1..2], &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "{{\"path\":\"b\",\"size\":1,\"hash\":\"{}\"}}\n",
+                ContentHash::of(b"only")
+            )
+        );
+    }
+}
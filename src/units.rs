@@ -0,0 +1,159 @@
+//! Sizes and counts formatted for people, the same way in progress bars, logs and
+//! reports. Sizes are in binary units (KiB, MiB) by default, or in decimal units (kB,
+//! MB) with `--si`. Numbers are grouped by thousands and use the decimal separator of the
+//! locale, taken from `LC_ALL`, `LC_NUMERIC` or `LANG` at startup. Output for other
+//! programs, such as JSON and CSV, keeps plain numbers.
+
+use std::fmt;
+use std::sync::RwLock;
+
+/// How numbers and sizes are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NumberFormat {
+    /// Separator between groups of thousands.
+    pub thousands: char,
+    /// Separator before the fractional digits.
+    pub decimal: char,
+    /// Whether sizes use decimal units (kB = 1000 bytes) rather than binary units
+    /// (KiB = 1024 bytes).
+    pub si: bool,
+}
+
+impl NumberFormat {
+    /// English separators and binary units, used until [`set_number_format`] is called.
+    pub const DEFAULT: Self = Self {
+        thousands: ',',
+        decimal: '.',
+        si: false,
+    };
+
+    /// Returns the format for `locale`, a locale name such as `de_DE.UTF-8`. Unknown
+    /// languages and the `C` locale get English separators.
+    pub fn from_locale(locale: &str, si: bool) -> Self {
+        let language = locale
+            .split(['_', '-', '.', '@'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let (thousands, decimal) = match language.as_str() {
+            "de" | "nl" | "es" | "it" | "pt" | "da" | "id" | "tr" | "el" | "ro" | "hr" | "sl"
+            | "sr" => ('.', ','),
+            "fr" | "ru" | "uk" | "sv" | "fi" | "nb" | "nn" | "no" | "cs" | "sk" | "pl" | "hu"
+            | "bg" | "et" | "lv" | "lt" => (' ', ','),
+            _ => (',', '.'),
+        };
+        Self {
+            thousands,
+            decimal,
+            si,
+        }
+    }
+
+    /// Returns the format for the locale of the environment: the first of `LC_ALL`,
+    /// `LC_NUMERIC` and `LANG` that is set.
+    pub fn from_env(si: bool) -> Self {
+        let locale = ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        Self::from_locale(&locale, si)
+    }
+
+    /// Formats `n` with its digits grouped by thousands.
+    pub fn count(&self, n: u64) -> String {
+        let digits = n.to_string();
+        let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                grouped.push(self.thousands);
+            }
+            grouped.push(digit);
+        }
+        grouped
+    }
+
+    /// Formats `bytes` in the largest unit it makes at least one of, with two decimals,
+    /// or in bytes below one kilobyte.
+    pub fn bytes(&self, bytes: u64) -> String {
+        let (base, units) = if self.si {
+            (1000.0, ["kB", "MB", "GB", "TB", "PB", "EB"])
+        } else {
+            (1024.0, ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"])
+        };
+        let mut value = bytes as f64;
+        if value < base {
+            return format!("{} B", bytes);
+        }
+        let mut unit = units[0];
+        value /= base;
+        for next in &units[1..] {
+            if value < base {
+                break;
+            }
+            value /= base;
+            unit = next;
+        }
+        let number = format!("{:.2}", value).replace('.', &self.decimal.to_string());
+        format!("{} {}", number, unit)
+    }
+}
+
+/// The format used by [`Bytes`] and [`Count`], set once at startup.
+static FORMAT: RwLock<NumberFormat> = RwLock::new(NumberFormat::DEFAULT);
+
+/// Formats all sizes and counts written afterwards with `format`.
+pub fn set_number_format(format: NumberFormat) {
+    *FORMAT.write().unwrap() = format;
+}
+
+/// Returns the format set with [`set_number_format`].
+pub fn number_format() -> NumberFormat {
+    *FORMAT.read().unwrap()
+}
+
+/// A size in bytes, displayed like `1.50 MiB`.
+#[derive(Clone, Copy, Debug)]
+pub struct Bytes(pub u64);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&number_format().bytes(self.0))
+    }
+}
+
+/// A number of things, displayed like `1,234,567`.
+#[derive(Clone, Copy, Debug)]
+pub struct Count(pub u64);
+
+impl fmt::Display for Count {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&number_format().count(self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_numbers_and_sizes_for_the_locale() {
+        let english = NumberFormat::from_locale("en_US.UTF-8", false);
+        assert_eq!(english, NumberFormat::DEFAULT);
+        assert_eq!(english.count(1_234_567), "1,234,567");
+        assert_eq!(english.count(999), "999");
+        assert_eq!(english.bytes(1000), "1000 B");
+        assert_eq!(english.bytes(1536), "1.50 KiB");
+        assert_eq!(english.bytes(3 << 30), "3.00 GiB");
+
+        let german = NumberFormat::from_locale("de_DE.UTF-8", true);
+        assert_eq!(german.count(1_234_567), "1.234.567");
+        assert_eq!(german.bytes(1000), "1,00 kB");
+        assert_eq!(german.bytes(1_500_000), "1,50 MB");
+
+        let french = NumberFormat::from_locale("fr_FR", false);
+        assert_eq!(french.count(12_345), "12 345");
+        assert_eq!(french.bytes(1536), "1,50 KiB");
+        assert_eq!(NumberFormat::from_locale("C", false), NumberFormat::DEFAULT);
+    }
+}
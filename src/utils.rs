@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+use indicatif::{ProgressBar, ProgressDrawTarget};
+use time::macros::format_description;
+use time::{Date, OffsetDateTime, PrimitiveDateTime, UtcOffset};
+
+/// Whether progress bars and spinners are hidden, set once at startup by `--quiet`.
+static PROGRESS_HIDDEN: AtomicBool = AtomicBool::new(false);
+
+/// Hides (or shows again) all progress bars and spinners created afterwards.
+pub fn set_progress_hidden(hidden: bool) {
+    PROGRESS_HIDDEN.store(hidden, Ordering::Relaxed);
+}
+
+/// Creates a progress bar of length `len`, hidden if [`set_progress_hidden`] was called.
+pub fn new_progress_bar(len: u64) -> ProgressBar {
+    if PROGRESS_HIDDEN.load(Ordering::Relaxed) {
+        ProgressBar::with_draw_target(Some(len), ProgressDrawTarget::hidden())
+    } else {
+        ProgressBar::new(len)
+    }
+}
+
+/// Creates a spinner showing `message`, ticking in the background until finished.
+/// Hidden if [`set_progress_hidden`] was called.
+pub fn new_spinner(message: &'static str) -> ProgressBar {
+    let spinner = if PROGRESS_HIDDEN.load(Ordering::Relaxed) {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new_spinner()
+    };
+    spinner.set_message(message);
+    spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+    spinner
+}
+
+/// Formats a Unix timestamp (in seconds) as a local `YYYY-MM-DD HH:MM:SS` date and time.
+///
+/// Falls back to UTC if the local offset cannot be determined, and to the raw number
+/// if the timestamp is out of range.
+pub fn format_timestamp(secs: u64) -> String {
+    let Ok(utc) = OffsetDateTime::from_unix_timestamp(secs as i64) else {
+        return secs.to_string();
+    };
+    let datetime = time::UtcOffset::current_local_offset()
+        .map(|offset| utc.to_offset(offset))
+        .unwrap_or(utc);
+    datetime
+        .format(format_description!(
+            "[year]-[month]-[day] [hour]:[minute]:[second]"
+        ))
+        .unwrap_or_else(|_| secs.to_string())
+}
+
+/// Returns the name of the user with ID `uid`, or the ID itself if it has no name, e.g.
+/// for files extracted from an archive made on another machine.
+pub fn user_name(uid: u32) -> String {
+    #[cfg(unix)]
+    {
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut found = std::ptr::null_mut();
+        let mut buffer = vec![0 as libc::c_char; 1024];
+        // SAFETY: `buffer` outlives the call and `found` points into `passwd` or is null
+        let status = unsafe {
+            libc::getpwuid_r(
+                uid,
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            )
+        };
+        if status == 0 && !found.is_null() {
+            // SAFETY: a found entry has a NUL-terminated name in `buffer`
+            let name = unsafe { std::ffi::CStr::from_ptr(passwd.pw_name) };
+            return name.to_string_lossy().into_owned();
+        }
+    }
+    uid.to_string()
+}
+
+/// Parses a size such as `1500`, `10K`, `1.5GiB` or `100MB` into a number of bytes.
+///
+/// Suffixes are case-insensitive. `K`, `M`, `G` and `T` (optionally followed by `iB`)
+/// are binary multiples of 1024, matching how sizes are displayed, while `KB`, `MB`,
+/// `GB` and `TB` are decimal multiples of 1000. A trailing `B` alone means bytes.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", s))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        _ => return Err(format!("unknown size unit in '{}'", s)),
+    };
+    Ok((number * multiplier as f64).round() as u64)
+}
+
+/// Parses a duration in seconds, minutes, hours, days or weeks, such as `2s`, `90m`,
+/// `1h` or `2w`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let unit_secs = match s.char_indices().last() {
+        Some((i, 's')) => Some((i, 1)),
+        Some((i, 'm')) => Some((i, 60)),
+        Some((i, 'h')) => Some((i, 60 * 60)),
+        Some((i, 'd')) => Some((i, 24 * 60 * 60)),
+        Some((i, 'w')) => Some((i, 7 * 24 * 60 * 60)),
+        _ => None,
+    };
+    let Some((i, unit)) = unit_secs else {
+        return Err(format!(
+            "invalid duration '{}' (expected e.g. 2s, 30m or 1h)",
+            s
+        ));
+    };
+    let value: u64 = s[..i]
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", s))?;
+    value
+        .checked_mul(unit)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("duration '{}' is too large", s))
+}
+
+/// Parses a point in time: a local date (`2024-01-31`, meaning its start), a local date
+/// and time (`2024-01-31T18:30:00`, or with a space instead of the `T`), or an age
+/// counted back from now in seconds, minutes, hours, days or weeks (`90s`, `30m`, `12h`,
+/// `30d`, `2w`).
+pub fn parse_time(s: &str) -> Result<SystemTime, String> {
+    let s = s.trim();
+    if let Ok(age) = parse_duration(s) {
+        return SystemTime::now()
+            .checked_sub(age)
+            .ok_or_else(|| format!("age '{}' is too large", s));
+    }
+
+    let datetime = PrimitiveDateTime::parse(
+        s,
+        format_description!("[year]-[month]-[day]T[hour]:[minute]:[second]"),
+    )
+    .or_else(|_| {
+        PrimitiveDateTime::parse(
+            s,
+            format_description!("[year]-[month]-[day] [hour]:[minute]:[second]"),
+        )
+    })
+    .or_else(|_| Date::parse(s, format_description!("[year]-[month]-[day]")).map(Date::midnight))
+    .map_err(|_| {
+        format!(
+            "invalid time '{}' (expected e.g. 2024-01-31, 2024-01-31T18:30:00 or 30d)",
+            s
+        )
+    })?;
+    let offset = UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC);
+    Ok(datetime.assume_offset(offset).into())
+}
+
+fn find_root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Space on a filesystem, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskSpace {
+    pub total: u64,
+    pub free: u64,
+    /// Free space this user can write to, less than `free` where some is reserved.
+    pub available: u64,
+}
+
+/// Returns the space on the filesystem holding `path`, if known.
+pub fn disk_space(path: &Path) -> Option<DiskSpace> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+        let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+        // SAFETY: `path` is NUL-terminated and `stats` a valid out pointer
+        if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+            return None;
+        }
+        // The field types differ between platforms, 32 bits wide on some
+        #[allow(clippy::useless_conversion)]
+        let (block, blocks, free, available) = (
+            u64::from(stats.f_frsize),
+            u64::from(stats.f_blocks),
+            u64::from(stats.f_bfree),
+            u64::from(stats.f_bavail),
+        );
+        Some(DiskSpace {
+            total: blocks * block,
+            free: free * block,
+            available: available * block,
+        })
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let (mut available, mut total, mut free) = (0, 0, 0);
+        // SAFETY: `wide` is NUL-terminated and the out pointers are valid
+        if unsafe { GetDiskFreeSpaceExW(wide.as_ptr(), &mut available, &mut total, &mut free) } == 0
+        {
+            return None;
+        }
+        Some(DiskSpace {
+            total,
+            free,
+            available,
+        })
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Groups the items `0..len` into clusters of items connected by a chain of pairs for
+/// which `linked` returns `true`. Every pair is compared, so this is quadratic in `len`.
+/// Returns the sorted indices of each cluster with two or more items, ordered by their
+/// first index.
+pub fn cluster(len: usize, linked: impl Fn(usize, usize) -> bool) -> Vec<Vec<usize>> {
+    let mut parents: Vec<usize> = (0..len).collect();
+    for i in 0..len {
+        for j in i + 1..len {
+            if linked(i, j) {
+                let (a, b) = (find_root(&mut parents, i), find_root(&mut parents, j));
+                parents[a.max(b)] = a.min(b);
+            }
+        }
+    }
+    let mut clusters: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..len {
+        let root = find_root(&mut parents, i);
+        clusters.entry(root).or_default().push(i);
+    }
+    let mut clusters: Vec<Vec<usize>> = clusters
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .collect();
+    clusters.sort();
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn names_users_by_their_id() {
+        assert_eq!(user_name(0), "root");
+        assert_eq!(user_name(u32::MAX - 1), (u32::MAX - 1).to_string());
+    }
+
+    #[test]
+    fn parses_sizes_with_units() {
+        assert_eq!(parse_size("1500"), Ok(1500));
+        assert_eq!(parse_size("10k"), Ok(10 * 1024));
+        assert_eq!(parse_size("1.5GiB"), Ok(1536 * 1024 * 1024));
+        assert_eq!(parse_size("100MB"), Ok(100_000_000));
+        assert_eq!(parse_size(" 2 M "), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_size("7B"), Ok(7));
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("10 parsecs").is_err());
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert!(parse_duration("5").is_err());
+        assert!(parse_duration("xh").is_err());
+    }
+
+    #[test]
+    fn parses_dates_and_ages() {
+        let day_ago = SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+        let parsed = parse_time("1d").unwrap();
+        assert!(parsed >= day_ago && parsed < day_ago + Duration::from_secs(60));
+        assert!(parse_time("2w").unwrap() < parse_time("13d").unwrap());
+
+        let date = parse_time("2024-01-31").unwrap();
+        let datetime = parse_time("2024-01-31T18:30:00").unwrap();
+        assert_eq!(
+            datetime.duration_since(date).unwrap(),
+            Duration::from_secs(18 * 60 * 60 + 30 * 60)
+        );
+        assert_eq!(parse_time("2024-01-31 18:30:00"), Ok(datetime));
+
+        assert!(parse_time("yesterday").is_err());
+        assert!(parse_time("2024-13-01").is_err());
+        assert!(parse_time("d").is_err());
+    }
+
+    #[test]
+    fn clusters_by_chained_links() {
+        let values: [u32; 6] = [0, 2, 4, 10, 11, 20];
+        let clusters = cluster(values.len(), |i, j| values[i].abs_diff(values[j]) <= 2);
+        assert_eq!(clusters, vec![vec![0, 1, 2], vec![3, 4]]);
+        assert!(cluster(values.len(), |_, _| false).is_empty());
+    }
+}
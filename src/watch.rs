@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, mpsc};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use log::{debug, error, info, warn};
+use notify::event::{AccessKind, AccessMode};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use walkdir::WalkDir;
+
+use crate::cache::HashCache;
+use crate::index::HashIndex;
+use crate::metrics::WatchMetrics;
+use crate::scan_root::ScanRoot;
+use crate::scanner::{RetryPolicy, calculate_file_hash, is_skipped};
+use crate::units::{Bytes, Count};
+
+/// Returns `true` if `event` may mean a file's contents or location changed.
+fn is_relevant(event: &Event) -> bool {
+    matches!(
+        event.kind,
+        EventKind::Create(_)
+            | EventKind::Modify(_)
+            | EventKind::Remove(_)
+            | EventKind::Access(AccessKind::Close(AccessMode::Write))
+    )
+}
+
+/// Hashes `path`, of `size` bytes, and updates `index`, returning the other existing files with the same
+/// content. Returns an empty list if the file's content is unchanged since it was last
+/// indexed.
+fn check_file(
+    path: &Path,
+    size: u64,
+    root: &ScanRoot,
+    cache: &HashCache,
+    index: &Mutex<HashIndex>,
+) -> Result<Vec<PathBuf>> {
+    // Hash before locking, so lookups aren't blocked while a large file is read
+    let hash = calculate_file_hash(
+        path,
+        root,
+        cache,
+        true,
+        None,
+        None,
+        &RetryPolicy::default(),
+        None,
+        None,
+    )?
+    .to_string();
+    let mut index = index.lock().unwrap();
+    if index.hash_of(path) == Some(hash.as_str()) {
+        return Ok(Vec::new());
+    }
+    let copies = index.copies(&hash, path);
+    index.set_size(&hash, size);
+    index.insert(path.to_path_buf(), hash);
+    Ok(copies)
+}
+
+/// Watches `root.scan_path()` for new and modified files, hashing each one once it has
+/// stopped changing for `settle`, and logs every file whose content already exists in
+/// `index`. Newly hashed files are added to `cache` and `index` as they are found, so a
+/// file copied twice into the watched directory is reported the second time.
+///
+/// Empty files are ignored, since files are usually created empty and written afterwards.
+/// Runs until the watcher stops delivering events. Hashing and the duplicates found are
+/// counted in `metrics`.
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be watched.
+pub fn watch(
+    root: &ScanRoot,
+    cache: &HashCache,
+    index: &Mutex<HashIndex>,
+    skip_dirs: &[String],
+    settle: Duration,
+    metrics: &WatchMetrics,
+) -> Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher
+        .watch(root.scan_path(), RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch {}", root.scan_path().display()))?;
+    info!(
+        "Watching {} for new duplicates ({} files indexed), press Ctrl+C to stop",
+        root.scan_path().display(),
+        Count(index.lock().unwrap().len() as u64)
+    );
+
+    // Files with pending changes, and when each last changed
+    let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+    loop {
+        match receiver.recv_timeout(settle) {
+            Ok(Ok(event)) if is_relevant(&event) => {
+                for path in event.paths {
+                    if is_skipped(root, &path, skip_dirs) {
+                        continue;
+                    }
+                    if path.is_dir() {
+                        // A directory moved into the watched tree reports no events
+                        // for the files inside it
+                        for entry in WalkDir::new(&path).into_iter().flatten() {
+                            if entry.file_type().is_file() {
+                                pending.insert(entry.into_path(), Instant::now());
+                            }
+                        }
+                    } else if path.exists() {
+                        pending.insert(path, Instant::now());
+                    } else if index.lock().unwrap().remove(&path).is_some() {
+                        debug!("Removed from index: {}", path.display());
+                    }
+                }
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => warn!("Watch error: {}", e),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+
+        let settled: Vec<PathBuf> = pending
+            .iter()
+            .filter(|(_, changed)| changed.elapsed() >= settle)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in settled {
+            pending.remove(&path);
+            let size = match path.metadata() {
+                Ok(metadata) if metadata.is_file() && metadata.len() > 0 => metadata.len(),
+                _ => continue,
+            };
+            let start = Instant::now();
+            let result = check_file(&path, size, root, cache, index);
+            metrics.file_hashed(start.elapsed(), result.is_ok());
+            match result {
+                Ok(copies) if !copies.is_empty() => {
+                    metrics.new_duplicate();
+                    warn!(
+                        "New duplicate ({}): {}",
+                        Bytes(size),
+                        root.display(&path).display()
+                    );
+                    for copy in copies {
+                        warn!("  same as {}", root.display(&copy).display());
+                    }
+                }
+                Ok(_) => debug!("Indexed {}", root.display(&path).display()),
+                Err(e) => error!("Failed to calculate hash for '{}': {}", path.display(), e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn reports_existing_copies_once() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), b"same").unwrap();
+        fs::write(dir.path().join("b"), b"same").unwrap();
+        let root = ScanRoot::for_base(dir.path());
+        let cache = HashCache::load(dir.path().join("cache.json.zst"));
+        let index = Mutex::new(HashIndex::new());
+        let a = root.base().join("a");
+        let b = root.base().join("b");
+
+        assert!(check_file(&a, 4, &root, &cache, &index).unwrap().is_empty());
+        assert_eq!(
+            check_file(&b, 4, &root, &cache, &index).unwrap(),
+            [a.as_path()]
+        );
+        // Unchanged content is not reported again
+        assert!(check_file(&b, 4, &root, &cache, &index).unwrap().is_empty());
+
+        fs::remove_file(&a).unwrap();
+        fs::write(dir.path().join("c"), b"same").unwrap();
+        let c = root.base().join("c");
+        assert_eq!(
+            check_file(&c, 4, &root, &cache, &index).unwrap(),
+            [b.as_path()]
+        );
+    }
+}